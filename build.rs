@@ -0,0 +1,75 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::PathBuf;
+
+/// The directory with the licenses that are embedded for the `licenses` subcommand
+/// (`licenses` in the crate's directory by default)
+const LICENSES_DIR_VAR: &str = "EMOJI_BUILDER_LICENSES_DIR";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    embed_licenses();
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Writes `licenses.rs` to `OUT_DIR`, which includes the licenses directory.
+/// `include_dir!` fails if the directory doesn't exist (e.g. if the crate is used as a
+/// dependency), so an empty directory is used instead in that case.
+fn embed_licenses() {
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-env-changed={}", LICENSES_DIR_VAR);
+
+    let licenses = match std::env::var_os(LICENSES_DIR_VAR) {
+        Some(dir) => crate_dir.join(dir),
+        None => crate_dir.join("licenses")
+    };
+    let licenses = if licenses.is_dir() {
+        println!("cargo:rerun-if-changed={}", licenses.to_string_lossy());
+        licenses
+    } else {
+        println!("cargo:warning=The licenses directory {:?} doesn't exist, no licenses will be embedded", licenses);
+        let empty = out_dir.join("no_licenses");
+        std::fs::create_dir_all(&empty).expect("Couldn't create an empty licenses directory");
+        empty
+    };
+
+    let source = format!(
+        "/// The licenses of emoji_builder and its dependencies (see `build.rs`)\n\
+        const LICENSES: include_dir::Dir = include_dir!({:?});\n",
+        licenses.to_string_lossy()
+    );
+    std::fs::write(out_dir.join("licenses.rs"), source).expect("Couldn't write licenses.rs");
+}
+
+/// Generates the C header for the `ffi` module as `emoji_builder.h` in `OUT_DIR`
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+
+    cbindgen::Builder::new()
+        .with_src(crate_dir.join("src").join("ffi.rs"))
+        .with_language(cbindgen::Language::C)
+        .with_include_guard("EMOJI_BUILDER_H")
+        .with_documentation(true)
+        .generate()
+        .expect("Couldn't generate the C header")
+        .write_to_file(out_dir.join("emoji_builder.h"));
+}
@@ -0,0 +1,357 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The licenses of the artwork (not of emoji_builder itself, see the `licenses` subcommand).
+//!
+//! A pack declares the license of its images in its configuration (`license`, `license_url` and
+//! `copyright`). Single images may have a different license, which is declared in a sidecar file
+//! next to them (like in the [REUSE specification](https://reuse.software/spec/)), e.g.
+//! `emoji_u1f914.svg.license`:
+//! ```text
+//! SPDX-FileCopyrightText: 2021 Jane Doe
+//! SPDX-License-Identifier: CC-BY-4.0
+//! ```
+//! The licenses end up in the font (see [crate::licensing]) and the emojis whose license requires
+//! an attribution are listed in [ATTRIBUTION_FILE].
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::configs::Settings;
+use crate::emoji::Emoji;
+
+/// The extension of the sidecar files, which is appended to the file name of the image
+pub const SIDECAR_EXTENSION: &str = "license";
+/// The file next to the font that lists the emojis that need an attribution
+pub const ATTRIBUTION_FILE: &str = "ATTRIBUTION.txt";
+
+const COPYRIGHT_TAG: &str = "SPDX-FileCopyrightText:";
+const LICENSE_TAG: &str = "SPDX-License-Identifier:";
+
+/// A license, identified by its [SPDX identifier](https://spdx.org/licenses/)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct License {
+    /// The SPDX identifier (or expression), e.g. `CC-BY-4.0`
+    pub id: String,
+    /// Where the license can be found, if it has been declared explicitly
+    pub url: Option<String>,
+}
+
+impl License {
+    /// A license with its identifier and (if it's declared) its URL
+    pub fn new(id: &str, url: Option<&str>) -> License {
+        License {
+            id: id.trim().to_owned(),
+            url: url.map(|url| url.trim().to_owned())
+        }
+    }
+
+    /// The declared URL or the one of a well-known license
+    /// # Examples
+    /// ```
+    /// use emoji_builder::attribution::License;
+    ///
+    /// let license = License::new("CC-BY-SA-4.0", None);
+    /// assert_eq!(license.url().as_deref(), Some("https://creativecommons.org/licenses/by-sa/4.0/"));
+    /// assert_eq!(License::new("LicenseRef-Custom", None).url(), None);
+    /// ```
+    pub fn url(&self) -> Option<String> {
+        self.url.clone().or_else(|| Self::known_url(&self.id))
+    }
+
+    /// Whether the license requires to give credit to the authors (i.e. the Creative Commons
+    /// licenses with the BY element)
+    pub fn requires_attribution(&self) -> bool {
+        self.id.to_uppercase().starts_with("CC-BY")
+    }
+
+    fn known_url(id: &str) -> Option<String> {
+        let id = id.to_uppercase();
+        if let Some(elements) = id.strip_prefix("CC-BY") {
+            // e.g. -NC-SA-4.0, ported versions (like CC-BY-3.0-DE) are left out
+            let mut elements: Vec<String> = elements.split('-')
+                .filter(|element| !element.is_empty())
+                .map(|element| element.to_lowercase())
+                .collect();
+            let version = elements.pop()?;
+            if !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return None;
+            }
+            return Some(format!("https://creativecommons.org/licenses/{}/{}/",
+                                std::iter::once(String::from("by")).chain(elements).join("-"),
+                                version));
+        }
+        match id.as_str() {
+            "CC0-1.0" => Some("https://creativecommons.org/publicdomain/zero/1.0/"),
+            "APACHE-2.0" => Some("https://www.apache.org/licenses/LICENSE-2.0"),
+            "OFL-1.1" => Some("https://scripts.sil.org/OFL"),
+            "MIT" => Some("https://opensource.org/licenses/MIT"),
+            _ => None
+        }.map(String::from)
+    }
+}
+
+impl Display for License {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.url() {
+            Some(url) => write!(f, "{} ({})", self.id, url),
+            None => write!(f, "{}", self.id)
+        }
+    }
+}
+
+/// The license and the copyright holders of an emoji
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credit {
+    pub license: License,
+    /// The copyright notices, e.g. `2021 Jane Doe`
+    pub copyright: Vec<String>,
+    /// Where this has been declared (e.g. the sidecar file), for error messages
+    pub source: String,
+}
+
+impl Credit {
+    /// The license of a pack (or of the emojis in general) from the `license`, `license_url` and
+    /// `copyright` settings
+    pub fn from_settings(settings: &Settings) -> Option<Credit> {
+        settings.get("license").map(|id| Credit {
+            license: License::new(id, settings.get("license_url")),
+            copyright: settings.get_list("copyright").map(<[String]>::to_vec).unwrap_or_default(),
+            source: settings.source("license").map(ToString::to_string).unwrap_or_default()
+        })
+    }
+
+    /// Parses a sidecar file with `SPDX-FileCopyrightText` and `SPDX-License-Identifier` lines.
+    /// Other lines are ignored.
+    pub fn from_sidecar<P: AsRef<Path>>(path: P) -> Result<Credit, AttributionError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let mut license = None;
+        let mut copyright = vec![];
+        for line in content.lines().map(str::trim) {
+            if let Some(holder) = line.strip_prefix(COPYRIGHT_TAG) {
+                copyright.push(holder.trim().to_owned());
+            } else if let Some(id) = line.strip_prefix(LICENSE_TAG) {
+                if license.replace(License::new(id, None)).is_some() {
+                    return Err(AttributionError::InvalidSidecar(path.to_path_buf(), String::from("more than one license identifier")));
+                }
+            }
+        }
+        match license {
+            Some(license) => Ok(Credit {
+                license,
+                copyright,
+                source: path.to_string_lossy().into_owned()
+            }),
+            None => Err(AttributionError::InvalidSidecar(path.to_path_buf(), format!("no {} line", LICENSE_TAG)))
+        }
+    }
+
+    /// The sidecar file of an image (which doesn't need to exist)
+    pub fn sidecar_path(image: &Path) -> PathBuf {
+        let mut name = image.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(SIDECAR_EXTENSION);
+        image.with_file_name(name)
+    }
+}
+
+/// The licenses of the emojis in one or more packs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attribution {
+    /// The licenses by their identifier with the source of their URL
+    licenses: BTreeMap<String, (License, String)>,
+    /// The emojis' names (as in error messages) and their credits
+    credits: BTreeMap<Vec<u32>, (String, Credit)>,
+}
+
+impl Attribution {
+    /// Collects the licenses of a pack's emojis.
+    /// Emojis with a sidecar file get its license, the other ones get the pack's license
+    /// (`default`, if it has one).
+    pub fn for_pack<'e, I: IntoIterator<Item=&'e Emoji>>(emojis: I, default: Option<&Credit>) -> Result<Attribution, AttributionError> {
+        let mut attribution = Attribution::default();
+        for emoji in emojis {
+            let sidecar = emoji.svg_path.as_deref()
+                .map(Credit::sidecar_path)
+                .filter(|sidecar| sidecar.is_file());
+            let credit = match sidecar {
+                Some(sidecar) => Credit::from_sidecar(sidecar)?,
+                None => match default {
+                    Some(default) => default.clone(),
+                    None => continue
+                }
+            };
+            attribution.insert(emoji.sequence.clone(), emoji.to_string(), credit)?;
+        }
+        Ok(attribution)
+    }
+
+    /// Adds the emojis and licenses of another pack.
+    /// The same emoji with another license or the same license with another URL are conflicts.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::attribution::{Attribution, AttributionError, Credit, License};
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// let credit = |id: &str| Credit { license: License::new(id, None), copyright: vec![], source: String::from(id) };
+    /// let emojis = vec![Emoji::from(vec![0x1f914])];
+    /// let mut attribution = Attribution::for_pack(&emojis, Some(&credit("CC-BY-4.0"))).unwrap();
+    ///
+    /// let other = Attribution::for_pack(&emojis, Some(&credit("Apache-2.0"))).unwrap();
+    /// match attribution.merge(other) {
+    ///     Err(AttributionError::ConflictingLicenses { .. }) => (),
+    ///     result => panic!("Expected a conflict, got {:?}", result)
+    /// }
+    /// ```
+    pub fn merge(&mut self, other: Attribution) -> Result<(), AttributionError> {
+        for (sequence, (name, credit)) in other.credits {
+            self.insert(sequence, name, credit)?;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, sequence: Vec<u32>, name: String, credit: Credit) -> Result<(), AttributionError> {
+        self.declare(&credit.license, &credit.source)?;
+        match self.credits.get(&sequence) {
+            // The same emoji may be in multiple packs as long as they agree on its license
+            Some((_, existing)) if existing.license.id == credit.license.id => Ok(()),
+            Some((_, existing)) => Err(AttributionError::ConflictingLicenses {
+                emoji: name,
+                licenses: (existing.license.id.clone(), credit.license.id),
+                sources: (existing.source.clone(), credit.source)
+            }),
+            None => {
+                self.credits.insert(sequence, (name, credit));
+                Ok(())
+            }
+        }
+    }
+
+    fn declare(&mut self, license: &License, source: &str) -> Result<(), AttributionError> {
+        if let Some((existing, existing_source)) = self.licenses.get(&license.id) {
+            match (&existing.url, &license.url) {
+                (Some(existing_url), Some(url)) if existing_url != url => return Err(AttributionError::ConflictingUrls {
+                    license: license.id.clone(),
+                    urls: (existing_url.clone(), url.clone()),
+                    sources: (existing_source.clone(), source.to_owned())
+                }),
+                (Some(_), _) => return Ok(()),
+                (None, _) => ()
+            }
+        }
+        self.licenses.insert(license.id.clone(), (license.clone(), source.to_owned()));
+        Ok(())
+    }
+
+    /// Whether no emoji has a license
+    pub fn is_empty(&self) -> bool {
+        self.credits.is_empty()
+    }
+
+    /// The license of an emoji
+    pub fn get(&self, sequence: &[u32]) -> Option<&Credit> {
+        self.credits.get(sequence).map(|(_, credit)| credit)
+    }
+
+    /// The licenses, starting with the one that most emojis have
+    pub fn licenses(&self) -> Vec<&License> {
+        self.licenses.values()
+            .map(|(license, _)| license)
+            .sorted_by_key(|license| std::cmp::Reverse(self.credits.values()
+                .filter(|(_, credit)| credit.license.id == license.id)
+                .count()))
+            .collect()
+    }
+
+    /// The content of [ATTRIBUTION_FILE]: The licenses and the emojis that need an attribution
+    pub fn attribution_text(&self) -> String {
+        let mut text = String::from("Licenses\n========\n");
+        for license in self.licenses() {
+            text.push_str(&format!("{}\n", license));
+        }
+        let attributions = self.credits.iter()
+            .filter(|(_, (_, credit))| credit.license.requires_attribution())
+            .map(|(sequence, (name, credit))| format!("{} ({}): {}, {}",
+                                                      name,
+                                                      sequence.iter().map(|codepoint| format!("U+{:04X}", codepoint)).join(" "),
+                                                      if credit.copyright.is_empty() {
+                                                          String::from("no copyright notice")
+                                                      } else {
+                                                          credit.copyright.iter().map(|holder| format!("© {}", holder)).join("; ")
+                                                      },
+                                                      credit.license.id))
+            .collect_vec();
+        if !attributions.is_empty() {
+            text.push_str("\nAttributions\n============\n");
+            for attribution in attributions {
+                text.push_str(&attribution);
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    /// Writes [Attribution::attribution_text] to a file
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.attribution_text())
+    }
+}
+
+/// An error that occurs while collecting the licenses
+#[derive(Debug)]
+pub enum AttributionError {
+    /// Wrapper for [std::io::Error]
+    IoError(std::io::Error),
+    /// A sidecar file that doesn't declare exactly one license
+    InvalidSidecar(PathBuf, String),
+    /// The same license has been declared with different URLs
+    ConflictingUrls {
+        license: String,
+        urls: (String, String),
+        /// Where the URLs have been declared
+        sources: (String, String),
+    },
+    /// The same emoji has been declared with different licenses
+    ConflictingLicenses {
+        emoji: String,
+        licenses: (String, String),
+        /// Where the licenses have been declared
+        sources: (String, String),
+    },
+}
+
+impl From<std::io::Error> for AttributionError {
+    fn from(error: std::io::Error) -> Self {
+        AttributionError::IoError(error)
+    }
+}
+
+impl Display for AttributionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributionError::IoError(error) => write!(f, "{}", error),
+            AttributionError::InvalidSidecar(path, message) => write!(f, "Invalid license file {:?}: {}", path, message),
+            AttributionError::ConflictingUrls { license, urls, sources } =>
+                write!(f, "{} has different URLs: {} (from {}) and {} (from {})", license, urls.0, sources.0, urls.1, sources.1),
+            AttributionError::ConflictingLicenses { emoji, licenses, sources } =>
+                write!(f, "{} has different licenses: {} (from {}) and {} (from {})", emoji, licenses.0, sources.0, licenses.1, sources.1),
+        }
+    }
+}
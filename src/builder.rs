@@ -16,13 +16,22 @@
 //! This is the main module for the actual emoji processing.
 
 use std::collections::HashMap;
-use std::fmt::Debug;
-use std::path::PathBuf;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use clap::{App, ArgMatches};
+use itertools::Itertools;
+use rayon::prelude::*;
 
 use crate::builder::ResetError::IoError;
+use crate::attribution::Attribution;
+use crate::configs::SettingSource;
+use crate::diagnostics::DiagnosticsSink;
 use crate::emoji::Emoji;
+use crate::emoji_tables::EmojiTable;
+use crate::licensing::BundledFile;
 
 /// Represents (if [core::result::Result::Ok]) a prepared emoji and possibly derived, prepared emojis
 /// (The latter one isn't used yet)
@@ -86,6 +95,34 @@ pub trait EmojiBuilder: Send + Sync {
         }
     }
 
+    /// Checks upfront whether the builder can handle an emoji.
+    ///
+    /// Unsupported emojis are skipped (and reported) before `prepare` is called. Emojis with
+    /// degraded support are still prepared, but they are reported as well.
+    /// The default implementation supports all emojis.
+    fn supports_emoji(&self, _emoji: &Emoji) -> SupportLevel {
+        SupportLevel::Full
+    }
+
+    /// Lets the builder report its problems (e.g. emojis that couldn't be rendered) in addition
+    /// to logging them.
+    /// This is called before any emoji is prepared. The default implementation ignores it.
+    fn set_diagnostics(&mut self, _diagnostics: DiagnosticsSink) {}
+
+    /// Lets the builder take the information it needs from the emoji table (e.g. which
+    /// codepoints have variation sequences), if one has been loaded.
+    /// This is called before any emoji is prepared. The default implementation ignores it.
+    fn set_table(&mut self, _table: &EmojiTable) {}
+
+    /// Lets the builder embed the licenses of the emojis (e.g. in the font's metadata).
+    /// This is called before any emoji is prepared. The default implementation ignores it.
+    fn set_attribution(&mut self, _attribution: &Attribution) {}
+
+    /// Tells the builder where the output path passed to [EmojiBuilder::build] comes from, so it
+    /// only changes the name of a default output (e.g. for a variant of the font).
+    /// This is called before any emoji is prepared. The default implementation ignores it.
+    fn set_output_source(&mut self, _source: &SettingSource) {}
+
     /// Preprocess a single emoji which will be later used to create the emoji set.
     ///
     /// This function needs to be thread-safe as the preparation might be done in parallel/concurrently.
@@ -93,6 +130,17 @@ pub trait EmojiBuilder: Send + Sync {
     /// `undo` or `reset` have been called.
     fn prepare(&self, emoji: &Emoji) -> PreparationResult<Self::PreparedEmoji, Self::Err>;
 
+    /// Preprocesses multiple emojis at once.
+    ///
+    /// Builders can override this to set up state that is shared by all emojis only once
+    /// (e.g. loading fonts). The result has to be the same as calling `prepare` for each emoji,
+    /// which is what the default implementation does (in parallel).
+    fn prepare_batch<'e>(&self, emojis: &[&'e Emoji]) -> HashMap<&'e Emoji, PreparationResult<Self::PreparedEmoji, Self::Err>> {
+        emojis.par_iter()
+            .map(|emoji| (*emoji, self.prepare(emoji)))
+            .collect()
+    }
+
     /// Builds the emoji set with the given emojis and sends the output to the specified file.
     ///
     /// Calling this function has to be performed _after_ calling `prepare` for all `Emoji`s in
@@ -103,6 +151,25 @@ pub trait EmojiBuilder: Send + Sync {
         output_file: PathBuf,
     ) -> Result<(), Self::Err>;
 
+    /// Builds the emoji set like `build`, but sends the output to `writer` (e.g. stdout)
+    /// instead of a file.
+    ///
+    /// The default implementation builds into a temporary directory and copies the output file
+    /// to `writer`, so any other files that the builder writes next to it are lost.
+    fn build_to_writer(
+        &mut self,
+        emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>,
+        writer: &mut dyn Write,
+    ) -> Result<(), Self::Err>
+        where Self::Err: From<std::io::Error> {
+        let output_dir = tempfile::tempdir()?;
+        let output_file = output_dir.path().join("output");
+        self.build(emojis, output_file.clone())?;
+        std::io::copy(&mut File::open(output_file)?, writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
     /// Does the exact opposite to `prepare`, i.e. it assumes that the emoji
     /// has already been prepared and it undoes that operation (e.g. by deleting the file).
     /// It is the responsibility of the controlling code to ensure that the emoji has already been
@@ -133,6 +200,131 @@ pub trait EmojiBuilder: Send + Sync {
     fn log_modules() -> Vec<String> {
         vec![String::from(module_path!())]
     }
+
+    /// The files that this builder bundles into its output or runs (e.g. scripts and templates),
+    /// for the licenses inventory (see [crate::licensing])
+    fn bundled_files() -> Vec<BundledFile> {
+        vec![]
+    }
+
+    /// The name of the file that the builder writes for an emoji (if it writes one per emoji)
+    fn output_file_name(_emoji: &Emoji) -> Option<String> {
+        None
+    }
+
+    /// Whether the output of an emoji in the build directory is up to date (e.g. for `--info`).
+    /// `matches` are the builder's arguments (like in [EmojiBuilder::new]), which may choose the
+    /// part of the build directory that's used. The build directory isn't changed.
+    /// The default implementation doesn't cache anything.
+    fn cache_status(_build_path: &Path, _matches: Option<&ArgMatches>, _emoji: &Emoji) -> CacheStatus {
+        CacheStatus::NoCache
+    }
+}
+
+/// How well an [EmojiBuilder] can handle an emoji (see [EmojiBuilder::supports_emoji])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SupportLevel {
+    /// The emoji can be built without any issues
+    Full,
+    /// The emoji can be built, but not entirely (e.g. without its animation)
+    Degraded(String),
+    /// The emoji can't be built at all and will be skipped
+    Unsupported(String),
+}
+
+/// Whether the output of an emoji in the build directory is up to date
+/// (see [EmojiBuilder::cache_status])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// There's nothing cached in the build directory
+    NoCache,
+    /// The emoji hasn't been built yet
+    NotBuilt,
+    UpToDate,
+    /// The source of the emoji has changed since it has been built
+    Changed,
+    /// The emoji has been built, but its source can't be read
+    Unreadable,
+}
+
+impl Display for CacheStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CacheStatus::NoCache => "nothing cached in the build directory",
+            CacheStatus::NotBuilt => "not built yet",
+            CacheStatus::UpToDate => "up to date",
+            CacheStatus::Changed => "changed since the last build (or tweaked)",
+            CacheStatus::Unreadable => "built, but the source can't be read",
+        })
+    }
+}
+
+/// The emojis that aren't (fully) supported by a builder, together with the reasons
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupportReport<'e> {
+    pub degraded: Vec<(&'e Emoji, String)>,
+    pub unsupported: Vec<(&'e Emoji, String)>,
+}
+
+impl<'e> SupportReport<'e> {
+    /// Asks the builder about every emoji and returns the ones that can be built
+    /// (including the degraded ones) and the report
+    pub fn check<B: EmojiBuilder + ?Sized>(builder: &B, emojis: &[&'e Emoji]) -> (Vec<&'e Emoji>, SupportReport<'e>) {
+        let mut report = SupportReport::default();
+        let mut supported = Vec::with_capacity(emojis.len());
+        for emoji in emojis {
+            match builder.supports_emoji(emoji) {
+                SupportLevel::Full => supported.push(*emoji),
+                SupportLevel::Degraded(reason) => {
+                    supported.push(*emoji);
+                    report.degraded.push((*emoji, reason));
+                }
+                SupportLevel::Unsupported(reason) => report.unsupported.push((*emoji, reason))
+            }
+        }
+        (supported, report)
+    }
+
+    /// Whether all emojis are fully supported
+    pub fn is_empty(&self) -> bool {
+        self.degraded.is_empty() && self.unsupported.is_empty()
+    }
+
+    /// Groups the emojis by their reason (in the order of their first occurrence)
+    fn by_reason<'r>(emojis: &'r [(&'e Emoji, String)]) -> Vec<(&'r str, Vec<&'e Emoji>)> {
+        let mut groups: Vec<(&str, Vec<&Emoji>)> = vec![];
+        for (emoji, reason) in emojis {
+            match groups.iter_mut().find(|(other, _)| *other == reason.as_str()) {
+                Some((_, group)) => group.push(*emoji),
+                None => groups.push((reason.as_str(), vec![*emoji]))
+            }
+        }
+        groups
+    }
+}
+
+impl Display for SupportReport<'_> {
+    /// Lists the emojis for each reason, e.g.
+    /// ```text
+    /// Skipped 2 unsupported emoji(s):
+    ///   no SVG file: 🤔 [1F914], 🦨 [1F9A8]
+    /// ```
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sections = [
+            ("Skipped", "unsupported", &self.unsupported),
+            ("Built", "degraded", &self.degraded),
+        ];
+        for (verb, kind, emojis) in sections.iter() {
+            if emojis.is_empty() {
+                continue;
+            }
+            writeln!(f, "{} {} {} emoji(s):", verb, emojis.len(), kind)?;
+            for (reason, emojis) in Self::by_reason(emojis) {
+                writeln!(f, "  {}: {}", reason, emojis.iter().join(", "))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// An error wrapper that can additionally output IO errors
@@ -142,10 +142,11 @@ def get_font_cmap(font):
   return font['cmap'].tables[0].cmap
 
 
-def add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf):
+def add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf, add_vmtx=False):
   """Add hmtx and GlyphOrder data for all sequences in seqs, and ensures there's
   a cmap entry for each single-codepoint sequence.  Seqs not in seq_to_advance
-  will get a zero advance."""
+  will get a zero advance.  If the font has a vmtx table (or add_vmtx is set,
+  which creates it if needed), all sequences get the vertical advance vadvance."""
 
   # We allow the template cmap to omit mappings for single-codepoint glyphs
   # defined in the template's GlyphOrder table.  Similarly, the hmtx table can
@@ -164,7 +165,11 @@ def add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf):
 
   cmap = get_font_cmap(font)
   hmtx = font['hmtx'].metrics
-  vmtx = font['vmtx'].metrics
+  if add_vmtx and 'vmtx' not in font:
+    # The template declares vertical metrics (vhea), but none of its glyphs has them
+    font['vmtx'] = vmtx_table = newTable("vmtx")
+    vmtx_table.metrics = {name: [vadvance, 0] for name in font.getGlyphOrder()}
+  vmtx = font['vmtx'].metrics if 'vmtx' in font else None
 
   # Add glyf table so empty glyphs will be added to ensure compatibility
   # with systems requiring a glyf table, like Windows 10.
@@ -195,7 +200,8 @@ def add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf):
       cmap[seq[0]] = name
     advance = seq_to_advance.get(seq, 0)
     hmtx[name] = [advance, 0]
-    vmtx[name] = [vadvance, 0]
+    if vmtx is not None:
+      vmtx[name] = [vadvance, 0]
     if name not in reverseGlyphMap:
       font.glyphOrder.append(name)
       updatedGlyphOrder=True
@@ -357,10 +363,18 @@ def add_cmap_format_4(font):
 
   font['cmap'].tables.append(newtable)
 
-def update_font_data(font, seq_to_advance, vadvance, aliases, add_cmap4, add_glyf):
-  """Update the font's cmap, hmtx, GSUB, and GlyphOrder tables."""
+def get_vertical_metrics(font, lineheight):
+  """Return the vertical advance of the emoji glyphs and whether the font has
+  vertical metrics (vhea).  All of them get the same advance: the height of the
+  image cell, which is scaled to the line height just like the horizontal
+  advances are.  Their tops are at the vertical origin (a top side bearing of
+  0), like in the vertical bitmap metrics that emoji_builder.py writes."""
+  return lineheight, 'vhea' in font
+
+def update_font_data(font, seq_to_advance, vadvance, aliases, add_cmap4, add_glyf, add_vmtx=False):
+  """Update the font's cmap, hmtx, vmtx, GSUB, and GlyphOrder tables."""
   seqs = get_all_seqs(font, seq_to_advance)
-  add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf)
+  add_glyph_data(font, seqs, seq_to_advance, vadvance, add_glyf, add_vmtx)
   add_aliases_to_cmap(font, aliases)
   add_ligature_sequences(font, seqs, aliases)
   if add_cmap4:
@@ -402,9 +416,9 @@ def update_ttx(in_file, out_file, image_dirs, prefix, ext, aliases_file, add_cma
   map_fn = get_png_file_to_advance_mapper(lineheight)
   seq_to_advance = remap_values(seq_to_file, map_fn)
 
-  vadvance = font['vhea'].advanceHeightMax if 'vhea' in font else lineheight
+  vadvance, add_vmtx = get_vertical_metrics(font, lineheight)
 
-  update_font_data(font, seq_to_advance, vadvance, aliases, add_cmap4, add_glyf)
+  update_font_data(font, seq_to_advance, vadvance, aliases, add_cmap4, add_glyf, add_vmtx)
 
   font.saveXML(out_file)
 
@@ -0,0 +1,265 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Exports the prepared PNG files as loose assets for Android (e.g. for filemojicompat-style
+//! integrations) instead of a font:
+//! - `emoji/<sequence>.png` for each emoji, e.g. `emoji/1f3f3_200d_1f308.png`
+//! - `metadata.json` with the fully-qualified sequence, the name and the first Android API level
+//!   that supports the emoji (`sdkAdded`)
+//! - `checksums.sha256` with the SHA256 hash of each image (in the format of `sha256sum`)
+//!
+//! Just like in the font's cmap, the file names don't contain `U+FE0F`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use itertools::Itertools;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::emoji::Emoji;
+use crate::emoji_tables::{EmojiTable, EmojiTableKey, EmojiVersion};
+
+/// The directory of the images inside of the export directory
+const IMAGE_DIR: &str = "emoji";
+const METADATA_FILE: &str = "metadata.json";
+const CHECKSUM_FILE: &str = "checksums.sha256";
+
+/// The first Android API level that supports an Emoji version (or an older one)
+const SDK_LEVELS: &[((u32, u32), u32)] = &[
+    ((2, 0), 23),
+    ((3, 0), 24),
+    ((4, 0), 25),
+    ((5, 0), 26),
+    ((11, 0), 28),
+    ((12, 0), 29),
+    ((13, 0), 30),
+    ((13, 1), 31),
+    ((14, 0), 33),
+    ((15, 0), 34),
+    ((15, 1), 35),
+    ((16, 0), 36),
+];
+
+/// What the export needs from the emoji table, by the sequences without `U+FE0F`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssetTable(HashMap<EmojiTableKey, AssetInfo>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AssetInfo {
+    /// The fully-qualified sequence (see [EmojiTable::status])
+    sequence: EmojiTableKey,
+    name: Option<String>,
+    version: Option<EmojiVersion>,
+}
+
+/// An entry of `metadata.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetMetadata {
+    /// The image, relative to the export directory
+    pub file: String,
+    /// The fully-qualified sequence in lowercase hex, separated by underscores
+    pub sequence: String,
+    pub name: Option<String>,
+    pub sdk_added: Option<u32>,
+}
+
+impl AssetTable {
+    /// Takes the fully-qualified sequences, names and versions from the table.
+    /// The table usually has entries with and without `U+FE0F`; the one that's `fully-qualified`
+    /// in `emoji-test.txt` is used. Without a status, the one with more of them is used, as the
+    /// table adds the spelling without them itself.
+    pub fn from_table(table: &EmojiTable) -> AssetTable {
+        let mut assets: HashMap<EmojiTableKey, ((bool, usize, EmojiTableKey), AssetInfo)> = HashMap::new();
+        for (key, entry) in table.iter() {
+            let info = AssetInfo {
+                sequence: key.clone(),
+                name: entry.name().map(|name| name.as_str().to_string()),
+                version: table.version(key),
+            };
+            let qualified = (table.status(key) == Some("fully-qualified"), key.len(), key.clone());
+            match assets.get(&without_fe0f(key)) {
+                Some((existing, _)) if *existing >= qualified => (),
+                _ => { assets.insert(without_fe0f(key), (qualified, info)); }
+            }
+        }
+        AssetTable(assets.into_iter()
+            .map(|(key, (_, info))| (key, info))
+            .collect())
+    }
+
+    fn get(&self, sequence: &[u32]) -> Option<&AssetInfo> {
+        self.0.get(&without_fe0f(sequence))
+    }
+}
+
+fn without_fe0f(sequence: &[u32]) -> EmojiTableKey {
+    sequence.iter()
+        .copied()
+        .filter(|codepoint| *codepoint != 0xfe0f)
+        .collect()
+}
+
+fn join_sequence(sequence: &[u32]) -> String {
+    sequence.iter().map(|codepoint| format!("{:x}", codepoint)).join("_")
+}
+
+/// The name of an emoji's image (e.g. `1f3f3_200d_1f308.png`), without `U+FE0F` like in the
+/// font's cmap
+pub fn asset_file_name(sequence: &[u32]) -> String {
+    format!("{}.png", join_sequence(&without_fe0f(sequence)))
+}
+
+/// The first Android API level that supports the Emoji version.
+/// Versions that are newer than the known ones don't have one.
+pub fn sdk_added(version: (u32, u32)) -> Option<u32> {
+    SDK_LEVELS.iter()
+        .find(|(supported, _)| version <= *supported)
+        .map(|(_, level)| *level)
+}
+
+/// Copies the images to `<dir>/emoji` and writes `metadata.json` and `checksums.sha256`.
+/// If multiple emojis end up with the same file name (i.e. they only differ by `U+FE0F`), only
+/// the one that comes first is exported.
+/// Without a table, the sequences are taken as they are and there are no API levels.
+pub fn export(dir: &Path, emojis: &[(&Emoji, &Path)], table: Option<&AssetTable>) -> std::io::Result<Vec<AssetMetadata>> {
+    let image_dir = dir.join(IMAGE_DIR);
+    create_dir_all(&image_dir)?;
+
+    let mut files = BTreeMap::new();
+    for (emoji, path) in emojis.iter().sorted_by(|(a, _), (b, _)| a.sequence.cmp(&b.sequence)) {
+        let file_name = asset_file_name(&emoji.sequence);
+        if files.contains_key(&file_name) {
+            warn!("{} has the same asset file name as another emoji ({}), skipping it", emoji, file_name);
+            continue;
+        }
+        let info = table.and_then(|table| table.get(&emoji.sequence));
+        let sequence = info.map(|info| info.sequence.as_slice()).unwrap_or(&emoji.sequence);
+        let metadata = AssetMetadata {
+            file: format!("{}/{}", IMAGE_DIR, file_name),
+            sequence: join_sequence(sequence),
+            name: emoji.name.clone().or_else(|| info.and_then(|info| info.name.clone())),
+            sdk_added: info.and_then(|info| info.version)
+                .and_then(|version| sdk_added(version.as_tuple())),
+        };
+        let content = std::fs::read(path)?;
+        std::fs::write(image_dir.join(&file_name), &content)?;
+        files.insert(file_name, (metadata, hex::encode(Sha256::digest(&content))));
+    }
+
+    let mut checksums = BufWriter::new(File::create(dir.join(CHECKSUM_FILE))?);
+    for (metadata, hash) in files.values() {
+        writeln!(checksums, "{}  {}", hash, metadata.file)?;
+    }
+    checksums.flush()?;
+
+    let metadata = files.into_iter()
+        .map(|(_, (metadata, _))| metadata)
+        .collect_vec();
+    let file = BufWriter::new(File::create(dir.join(METADATA_FILE))?);
+    serde_json::to_writer_pretty(file, &metadata)?;
+    Ok(metadata)
+}
+
+#[test]
+fn test_export() {
+    use crate::emoji::EmojiKind;
+    use crate::emoji_tables::EmojiTableEntry;
+
+    let source = tempfile::tempdir().unwrap();
+    let png = |name: &str, content: &str| {
+        let path = source.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let thinking = Emoji::from(vec![0x1f914]);
+    let germany = Emoji::from_flag("DE", None).unwrap();
+    let mut rainbow_flag = Emoji::from(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    rainbow_flag.name = Some(String::from("rainbow flag"));
+    // Differs only by U+FE0F
+    let rainbow_flag_unqualified = Emoji::from(vec![0x1f3f3, 0x200d, 0x1f308]);
+    let (thinking_png, germany_png, rainbow_png, unqualified_png) =
+        (png("a.png", "thinking"), png("b.png", "germany"), png("c.png", "rainbow"), png("d.png", "unqualified"));
+
+    let mut emoji_table = EmojiTable::new();
+    emoji_table.expand("1F914 ; Emoji # E1.0 [1] (🤔) thinking face\n\
+                        1F1E9 1F1EA ; RGI_Emoji_Flag_Sequence ; flag: Germany # E2.0 [1] (🇩🇪) flag: Germany\n\
+                        1F3F3 FE0F 200D 1F308 ; RGI_Emoji_ZWJ_Sequence ; rainbow flag # E4.0 [1] (🏳️‍🌈) rainbow flag\n".as_bytes()).unwrap();
+    emoji_table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking face"));
+    let table = AssetTable::from_table(&emoji_table);
+
+    let dir = tempfile::tempdir().unwrap();
+    let metadata = export(dir.path(), &[
+        (&rainbow_flag_unqualified, unqualified_png.as_path()),
+        (&thinking, thinking_png.as_path()),
+        (&germany, germany_png.as_path()),
+        (&rainbow_flag, rainbow_png.as_path()),
+    ], Some(&table)).unwrap();
+
+    assert_eq!(metadata, vec![
+        AssetMetadata {
+            file: String::from("emoji/1f1e9_1f1ea.png"),
+            sequence: String::from("1f1e9_1f1ea"),
+            name: None,
+            sdk_added: Some(23),
+        },
+        AssetMetadata {
+            file: String::from("emoji/1f3f3_200d_1f308.png"),
+            sequence: String::from("1f3f3_fe0f_200d_1f308"),
+            name: None,
+            sdk_added: Some(25),
+        },
+        AssetMetadata {
+            file: String::from("emoji/1f914.png"),
+            sequence: String::from("1f914"),
+            name: Some(String::from("thinking face")),
+            sdk_added: Some(23),
+        },
+    ]);
+    // The unqualified sequence comes first, the other one is skipped
+    assert_eq!(std::fs::read_to_string(dir.path().join("emoji/1f3f3_200d_1f308.png")).unwrap(), "unqualified");
+    assert_eq!(std::fs::read_dir(dir.path().join("emoji")).unwrap().count(), 3);
+
+    let written: serde_json::Value = serde_json::from_reader(File::open(dir.path().join(METADATA_FILE)).unwrap()).unwrap();
+    assert_eq!(written[2]["sdkAdded"], 23);
+    assert_eq!(written[2]["name"], "thinking face");
+
+    let checksums = std::fs::read_to_string(dir.path().join(CHECKSUM_FILE)).unwrap();
+    assert_eq!(checksums.lines().collect_vec(), vec![
+        format!("{}  emoji/1f1e9_1f1ea.png", hex::encode(Sha256::digest(b"germany"))),
+        format!("{}  emoji/1f3f3_200d_1f308.png", hex::encode(Sha256::digest(b"unqualified"))),
+        format!("{}  emoji/1f914.png", hex::encode(Sha256::digest(b"thinking"))),
+    ]);
+
+    // The status in emoji-test.txt decides which sequence is the fully-qualified one
+    let mut test_table = EmojiTable::new();
+    test_table.expand_descriptions_from_test_data("263A FE0F ; fully-qualified # ☺️ E0.6 smiling face\n\
+                                                   263A ; unqualified # ☺ E0.6 smiling face\n".as_bytes()).unwrap();
+    let table = AssetTable::from_table(&test_table);
+    assert_eq!(table.get(&[0x263a]).unwrap().sequence, vec![0x263a, 0xfe0f]);
+}
+
+#[test]
+fn test_sdk_added() {
+    assert_eq!(sdk_added((1, 0)), Some(23));
+    assert_eq!(sdk_added((5, 0)), Some(26));
+    assert_eq!(sdk_added((12, 1)), Some(30));
+    assert_eq!(sdk_added((13, 1)), Some(31));
+    assert_eq!(sdk_added((99, 0)), None);
+    assert_eq!(asset_file_name(&[0x2764, 0xfe0f]), "2764.png");
+}
@@ -0,0 +1,449 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The layout of the build directory, i.e. where the rendered images, their hashes and the
+//! intermediate font files are stored.
+//!
+//! The directory contains a `layout_version` file, so older build directories can be migrated
+//! to the current layout instead of silently being treated as empty.
+//!
+//! Builds of different packs (or branches of a pack) may use their own cache namespace
+//! (see [BuildDir::with_namespace]), i.e. their own hashes (`hashes.<key>.csv`) and images
+//! (`png/<key>/`), so they don't replace each other's images.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename, File};
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::builders::blobmoji::Blobmoji;
+use crate::emoji::Emoji;
+
+/// The version of the layout that is created by [BuildDir::open]
+pub const LAYOUT_VERSION: u32 = 1;
+
+const LAYOUT_VERSION_FILE: &str = "layout_version";
+const HASHES: &str = "hashes.csv";
+const MANIFEST: &str = "manifest.csv";
+const GLYPH_MAP: &str = "glyph_map.json";
+const STAGES: &str = "stages.json";
+/// The outputs of the completed stages of the fonts
+const STAGES_DIR: &str = "stages";
+const TMPL_TTX_TMPL: &str = "font.tmpl.ttx.tmpl";
+const TMPL_TTX: &str = "font.tmpl.ttx";
+const TMPL_TTF: &str = "font.tmpl.ttf";
+const TTF: &str = "font.ttf";
+const TTF_WITH_PUA: &str = "font.ttf-with-pua";
+const TTF_WITH_PUA_VARSE1: &str = "font.ttf-with-pua-varse1";
+const TTF_WITH_LICENSE: &str = "font.ttf-with-license";
+const PNG_DIR: &str = "png";
+/// The working directories of the single fonts
+const WORK_DIR: &str = "work";
+/// Where undone PNG files are kept until they are prepared again
+const UNDO_DIR: &str = "undo";
+
+/// When each cache namespace has been used (see [BuildDir::with_namespace])
+const NAMESPACES: &str = "namespaces.json";
+
+/// The name of the hashes file before the first layout version
+const V0_HASHES: &str = "hash.csv";
+
+/// The migration from each layout version (i.e. the index) to the next one
+const MIGRATIONS: [fn(&BuildDir) -> std::io::Result<()>; LAYOUT_VERSION as usize] = [
+    migrate_v0
+];
+
+/// The build directory of the [Blobmoji] builder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildDir {
+    path: PathBuf,
+    /// The cache namespace of the hashes and the images (if there is one)
+    namespace: Option<String>,
+}
+
+impl BuildDir {
+    /// Uses a directory as it is, i.e. it's neither created nor migrated
+    pub fn at<P: AsRef<Path>>(path: P) -> BuildDir {
+        BuildDir {
+            path: path.as_ref().to_path_buf(),
+            namespace: None,
+        }
+    }
+
+    /// Creates the directory with the current layout or migrates it to the current layout
+    /// (if it's from an older version).
+    /// Directories from a newer version are rejected.
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<BuildDir> {
+        let build_dir = BuildDir::at(path);
+        create_dir_all(&build_dir.path)?;
+
+        let version = build_dir.layout_version()?;
+        if version > LAYOUT_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "The build directory {:?} has the layout version {}, but only versions up to {} are supported",
+                build_dir.path, version, LAYOUT_VERSION
+            )));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            info!("Migrating the build directory from layout version {} to {}", from, from + 1);
+            migration(&build_dir)?;
+            std::fs::write(build_dir.path.join(LAYOUT_VERSION_FILE), (from + 1).to_string())?;
+        }
+
+        create_dir_all(build_dir.png_dir())?;
+        Ok(build_dir)
+    }
+
+    /// The layout version of the directory.
+    /// Directories without a version are from before the versioning (i.e. version 0).
+    pub fn layout_version(&self) -> std::io::Result<u32> {
+        match std::fs::read_to_string(self.path.join(LAYOUT_VERSION_FILE)) {
+            Ok(version) => version.trim().parse().map_err(|_| Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid layout version: {}", version.trim())
+            )),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// The directory itself
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a key can be used as a cache namespace, i.e. as a part of file names.
+    /// It may only contain ASCII letters, digits, `-`, `_` and `.` (but not at its start).
+    pub fn is_valid_namespace(key: &str) -> bool {
+        !key.is_empty()
+            && !key.starts_with('.')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    }
+
+    /// Like [BuildDir::with_namespace], but the namespace is neither created nor is its use
+    /// recorded (e.g. to only look at its hashes)
+    pub fn in_namespace(self, key: &str) -> BuildDir {
+        BuildDir {
+            namespace: Some(key.to_string()),
+            ..self
+        }
+    }
+
+    /// Uses the hashes and the images of a cache namespace (e.g. the fingerprint of a pack),
+    /// while everything else is shared with the other namespaces.
+    /// The use is recorded, so the least recently used namespaces can be removed
+    /// (see [BuildDir::remove_old_namespaces]).
+    /// # Errors
+    /// If the key isn't a valid namespace (see [BuildDir::is_valid_namespace]) or if the
+    /// namespace can't be created
+    pub fn with_namespace(self, key: &str) -> std::io::Result<BuildDir> {
+        if !Self::is_valid_namespace(key) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid cache namespace: {:?}", key)));
+        }
+        let build_dir = self.in_namespace(key);
+        create_dir_all(build_dir.png_dir())?;
+
+        let mut namespaces = build_dir.read_namespaces();
+        let last_use = namespaces.values().max().copied().unwrap_or_default() + 1;
+        namespaces.insert(key.to_string(), last_use);
+        serde_json::to_writer_pretty(File::create(build_dir.path.join(NAMESPACES))?, &namespaces)?;
+        Ok(build_dir)
+    }
+
+    /// The cache namespace that is used (if there is one)
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// The cache namespaces with the (relative) time of their last use
+    fn read_namespaces(&self) -> BTreeMap<String, u64> {
+        File::open(self.path.join(NAMESPACES))
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    /// Removes the hashes and the images of the least recently used cache namespaces, so only
+    /// `max` of them are kept. The current namespace is always kept.
+    /// Returns the removed namespaces.
+    pub fn remove_old_namespaces(&self, max: usize) -> std::io::Result<Vec<String>> {
+        let mut namespaces = self.read_namespaces();
+        let removed = namespaces.iter()
+            .filter(|(key, _)| Some(key.as_str()) != self.namespace())
+            .sorted_by_key(|(_, last_use)| std::cmp::Reverse(**last_use))
+            .skip(max.saturating_sub(self.namespace.iter().count()))
+            .map(|(key, _)| key.clone())
+            .collect_vec();
+        for key in &removed {
+            info!("Removing the cache namespace {}", key);
+            let namespace = BuildDir {
+                path: self.path.clone(),
+                namespace: Some(key.clone()),
+            };
+            for result in &[remove_file(namespace.hashes()), remove_dir_all(namespace.png_dir())] {
+                match result {
+                    Err(err) if err.kind() != ErrorKind::NotFound => warn!("Couldn't remove the cache namespace {}: {:?}", key, err),
+                    _ => ()
+                }
+            }
+            namespaces.remove(key);
+        }
+        if !removed.is_empty() {
+            serde_json::to_writer_pretty(File::create(self.path.join(NAMESPACES))?, &namespaces)?;
+        }
+        Ok(removed)
+    }
+
+    /// The file that contains the hashes of the rendered emojis
+    pub fn hashes(&self) -> PathBuf {
+        match &self.namespace {
+            Some(namespace) => self.path.join(format!("hashes.{}.csv", namespace)),
+            None => self.path.join(HASHES)
+        }
+    }
+
+    /// The file that lists the PNG file of each sequence (with `--content-addressed-pngs`)
+    pub fn manifest(&self) -> PathBuf {
+        self.path.join(MANIFEST)
+    }
+
+    /// The glyph names of the emojis in the (normal) font
+    pub fn glyph_map(&self) -> PathBuf {
+        self.path.join(GLYPH_MAP)
+    }
+
+    /// The checkpoints of the stages of the fonts (see [StageStore](crate::builders::blobmoji::stages::StageStore))
+    pub fn stages(&self) -> PathBuf {
+        self.path.join(STAGES)
+    }
+
+    /// The outputs of the completed stages
+    pub fn stages_dir(&self) -> PathBuf {
+        self.path.join(STAGES_DIR)
+    }
+
+    /// The directory with the rendered images
+    pub fn png_dir(&self) -> PathBuf {
+        match &self.namespace {
+            Some(namespace) => self.path.join(PNG_DIR).join(namespace),
+            None => self.path.join(PNG_DIR)
+        }
+    }
+
+    /// The rendered image of an emoji
+    pub fn png(&self, emoji: &Emoji) -> PathBuf {
+        self.png_dir().join(Blobmoji::generate_filename(emoji))
+    }
+
+    /// The rendered image of an emoji with the hash of its source in the file name
+    /// (see [Blobmoji::generate_content_addressed_filename])
+    pub fn content_addressed_png(&self, emoji: &Emoji, hash: &[u8]) -> PathBuf {
+        self.png_dir().join(Blobmoji::generate_content_addressed_filename(emoji, hash))
+    }
+
+    /// The directory in which undone images are kept
+    pub fn undo_dir(&self) -> PathBuf {
+        self.path.join(UNDO_DIR)
+    }
+
+    /// Where the image of an emoji with the given source hash is kept after it has been undone
+    pub fn undo_png(&self, emoji: &Emoji, hash: &[u8]) -> PathBuf {
+        let filename = Blobmoji::generate_filename(emoji);
+        let hash = hash.iter()
+            .map(|byte| format!("{:02x}", byte))
+            .join("");
+        self.undo_dir()
+            .join(format!("{}-{}.png", filename.trim_end_matches(".png"), hash))
+    }
+
+    /// The template for the TTX template
+    pub fn ttx_tmpl_tmpl(&self) -> PathBuf {
+        self.path.join(TMPL_TTX_TMPL)
+    }
+
+    /// Creates a new working directory (`work/<target>-<nonce>`) for building a single font.
+    /// Each build gets its own directory, so multiple fonts can be built from the same build
+    /// directory at the same time.
+    pub fn work_dir(&self, target: &str) -> std::io::Result<WorkDir> {
+        let work = self.path.join(WORK_DIR);
+        create_dir_all(&work)?;
+        let path = tempfile::Builder::new()
+            .prefix(&format!("{}-", target))
+            .tempdir_in(&work)?
+            .into_path();
+        Ok(WorkDir { path })
+    }
+}
+
+/// The directory with the intermediate files of a single font (see [BuildDir::work_dir])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkDir {
+    path: PathBuf
+}
+
+impl WorkDir {
+    /// The directory itself
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The TTX template with the glyphs of the emojis
+    pub fn ttx_tmpl(&self) -> PathBuf {
+        self.path.join(TMPL_TTX)
+    }
+
+    /// The glyph names that add_glyphs.py has assigned
+    pub fn glyph_map(&self) -> PathBuf {
+        self.path.join(GLYPH_MAP)
+    }
+
+    /// The images of the font under their usual names (only used with content-addressed images,
+    /// as emoji_builder.py finds the images by their names)
+    pub fn png_dir(&self) -> PathBuf {
+        self.path.join(PNG_DIR)
+    }
+
+    /// The compiled TTX template
+    pub fn tmpl_ttf(&self) -> PathBuf {
+        self.path.join(TMPL_TTF)
+    }
+
+    /// The font with the bitmaps
+    pub fn ttf(&self) -> PathBuf {
+        self.path.join(TTF)
+    }
+
+    /// The font with the Private Use Area mappings
+    pub fn ttf_with_pua(&self) -> PathBuf {
+        self.path.join(TTF_WITH_PUA)
+    }
+
+    /// The font with the Private Use Area mappings and the variation sequences
+    pub fn ttf_with_pua_varse1(&self) -> PathBuf {
+        self.path.join(TTF_WITH_PUA_VARSE1)
+    }
+
+    /// The font with the license in its name table
+    pub fn ttf_with_license(&self) -> PathBuf {
+        self.path.join(TTF_WITH_LICENSE)
+    }
+
+    /// Removes the directory with all of its files
+    pub fn remove(self) -> std::io::Result<()> {
+        remove_dir_all(&self.path)
+    }
+}
+
+/// Before the `png` directory, the images were stored directly in the build directory and the
+/// hashes were in `hash.csv`
+fn migrate_v0(build_dir: &BuildDir) -> std::io::Result<()> {
+    create_dir_all(build_dir.png_dir())?;
+    let pngs = read_dir(&build_dir.path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| path.file_name()
+            .map(|name| name.to_string_lossy())
+            .map(|name| name.starts_with("emoji_u") && name.ends_with(".png"))
+            .unwrap_or(false))
+        .collect_vec();
+    for path in pngs {
+        let target = build_dir.png_dir().join(path.file_name().unwrap());
+        // Images in the PNG directory are newer
+        if !target.exists() {
+            debug!("Moving {:?} to {:?}", path, target);
+            rename(path, target)?;
+        }
+    }
+
+    let old_hashes = build_dir.path.join(V0_HASHES);
+    if old_hashes.is_file() && !build_dir.hashes().exists() {
+        info!("Renaming {:?} to {:?}", old_hashes, build_dir.hashes());
+        rename(old_hashes, build_dir.hashes())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_migrate_v0() {
+    let dir = tempfile::tempdir().unwrap();
+    let thinking = Emoji::from(vec![0x1f914]);
+    let waving = Emoji::from(vec![0x1f44b]);
+    std::fs::write(dir.path().join(Blobmoji::generate_filename(&thinking)), b"old").unwrap();
+    std::fs::write(dir.path().join(Blobmoji::generate_filename(&waving)), b"old").unwrap();
+    std::fs::write(dir.path().join(V0_HASHES), b"1f914,abc").unwrap();
+    std::fs::write(dir.path().join("notes.png"), b"png").unwrap();
+    // A newer image that has already been in the PNG directory
+    std::fs::create_dir(dir.path().join(PNG_DIR)).unwrap();
+    std::fs::write(dir.path().join(PNG_DIR).join(Blobmoji::generate_filename(&waving)), b"new").unwrap();
+    assert_eq!(BuildDir::at(dir.path()).layout_version().unwrap(), 0);
+
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    assert_eq!(build_dir.layout_version().unwrap(), LAYOUT_VERSION);
+    assert_eq!(std::fs::read(build_dir.png(&thinking)).unwrap(), b"old");
+    assert_eq!(std::fs::read(build_dir.png(&waving)).unwrap(), b"new");
+    assert_eq!(std::fs::read(build_dir.hashes()).unwrap(), b"1f914,abc");
+    assert!(!dir.path().join(V0_HASHES).exists());
+    assert!(!dir.path().join(Blobmoji::generate_filename(&thinking)).exists());
+    // Other files are not touched
+    assert!(dir.path().join("notes.png").exists());
+
+    // Opening it again doesn't change anything
+    assert_eq!(BuildDir::open(dir.path()).unwrap(), build_dir);
+    assert_eq!(std::fs::read_dir(build_dir.png_dir()).unwrap().count(), 2);
+
+    // Newer layouts are rejected
+    std::fs::write(dir.path().join(LAYOUT_VERSION_FILE), (LAYOUT_VERSION + 1).to_string()).unwrap();
+    assert_eq!(BuildDir::open(dir.path()).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_namespaces() {
+    let dir = tempfile::tempdir().unwrap();
+    let thinking = Emoji::from(vec![0x1f914]);
+    let open = |key: &str| BuildDir::open(dir.path()).unwrap().with_namespace(key).unwrap();
+
+    let main = open("main");
+    assert_eq!(main.namespace(), Some("main"));
+    assert_eq!(main.hashes(), dir.path().join("hashes.main.csv"));
+    assert_eq!(main.png(&thinking), dir.path().join(PNG_DIR).join("main").join(Blobmoji::generate_filename(&thinking)));
+    std::fs::write(main.png(&thinking), b"main").unwrap();
+    std::fs::write(main.hashes(), b"1f914,abc").unwrap();
+
+    let feature = open("feature-1.2");
+    std::fs::write(feature.png(&thinking), b"feature").unwrap();
+    assert_eq!(std::fs::read(open("main").png(&thinking)).unwrap(), b"main");
+    // Everything else is shared
+    assert_eq!(main.ttx_tmpl_tmpl(), feature.ttx_tmpl_tmpl());
+    assert_eq!(main.stages(), feature.stages());
+
+    assert!(BuildDir::open(dir.path()).unwrap().with_namespace("../main").is_err());
+    assert!(!BuildDir::is_valid_namespace(""));
+    assert!(!BuildDir::is_valid_namespace(".hidden"));
+
+    // main has been used last, so the other namespaces are removed first
+    let other = open("other");
+    let main = open("main");
+    assert!(main.remove_old_namespaces(3).unwrap().is_empty());
+    assert_eq!(main.remove_old_namespaces(2).unwrap(), vec![String::from("feature-1.2")]);
+    assert!(!feature.png_dir().exists());
+    assert!(other.png_dir().exists());
+    // The current namespace is never removed
+    assert_eq!(main.remove_old_namespaces(0).unwrap(), vec![String::from("other")]);
+    assert!(main.hashes().exists());
+    assert_eq!(std::fs::read(main.png(&thinking)).unwrap(), b"main");
+}
@@ -0,0 +1,256 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Contact sheets of the rendered emojis: all PNG files of a directory in one large grid on a
+//! background color, e.g. to spot transparency issues on light and dark backgrounds.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::builders::blobmoji::image_utils;
+use crate::builders::blobmoji::image_utils::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// The space between the cells (and around the sheet)
+const MARGIN: u32 = 4;
+/// The space for the label below an emoji (including one pixel above and below the text)
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT + 2;
+
+/// Where the emojis are put on a sheet. The emojis are placed row by row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+}
+
+impl Grid {
+    /// A grid for `count` cells with at most `columns` columns
+    pub fn new(count: usize, columns: u32, cell_width: u32, cell_height: u32) -> Grid {
+        // An empty sheet only consists of its margin
+        if count == 0 {
+            return Grid { columns: 0, rows: 0, cell_width, cell_height };
+        }
+        let columns = columns.max(1).min(count as u32);
+        let rows = (count as u32).div_ceil(columns);
+        Grid { columns, rows, cell_width, cell_height }
+    }
+
+    /// The width and height of the whole sheet
+    pub fn size(&self) -> (u32, u32) {
+        (
+            MARGIN + self.columns * (self.cell_width + MARGIN),
+            MARGIN + self.rows * (self.cell_height + MARGIN)
+        )
+    }
+
+    /// The top left corner of a cell
+    pub fn cell(&self, index: usize) -> (u32, u32) {
+        let column = index as u32 % self.columns;
+        let row = index as u32 / self.columns;
+        (
+            MARGIN + column * (self.cell_width + MARGIN),
+            MARGIN + row * (self.cell_height + MARGIN)
+        )
+    }
+}
+
+/// A single emoji on a sheet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// The sequence from the file name (e.g. `1f914` for `emoji_u1f914.png`)
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    /// RGBA with straight alpha
+    pub pixels: Vec<u8>,
+}
+
+impl Cell {
+    /// Reads all PNG files of a directory, sorted by their names
+    pub fn from_dir(dir: &Path) -> std::io::Result<Vec<Cell>> {
+        let mut paths: Vec<PathBuf> = dir.read_dir()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|extension| extension == "png").unwrap_or(false))
+            .collect();
+        paths.sort();
+        paths.iter()
+            .map(|path| {
+                let (width, height, pixels) = image_utils::read_rgba(path)?;
+                let label = path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .trim_start_matches("emoji_u")
+                    .to_string();
+                Ok(Cell { label, width, height, pixels })
+            })
+            .collect()
+    }
+}
+
+/// A sheet with all cells on one background color
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContactSheet {
+    pub grid: Grid,
+    pub width: u32,
+    pub height: u32,
+    /// RGBA with straight alpha
+    pub pixels: Vec<u8>,
+}
+
+impl ContactSheet {
+    /// Puts the cells onto the background color (see [image_utils::compose_on_background]).
+    /// The cells have the size of the largest emoji and smaller ones are centered in them.
+    /// Labels are cut off at the edge of their cell.
+    pub fn draw(cells: &[Cell], columns: u32, background: [u8; 4], labels: bool) -> ContactSheet {
+        let image_width = cells.iter().map(|cell| cell.width).max().unwrap_or(0);
+        let image_height = cells.iter().map(|cell| cell.height).max().unwrap_or(0);
+        let cell_height = if labels { image_height + LABEL_HEIGHT } else { image_height };
+        let grid = Grid::new(cells.len(), columns, image_width, cell_height);
+        let (width, height) = grid.size();
+
+        let mut pixels = background.repeat(width as usize * height as usize);
+        for (index, cell) in cells.iter().enumerate() {
+            let (left, top) = grid.cell(index);
+            let placed = image_utils::place(&cell.pixels, cell.width, cell.height, image_width, image_height, 0, 0);
+            let composed = image_utils::compose_on_background(&placed, image_width, image_height, background);
+            for (y, row) in composed.chunks_exact(4 * image_width as usize).enumerate() {
+                let start = ((top as usize + y) * width as usize + left as usize) * 4;
+                pixels[start..start + row.len()].copy_from_slice(row);
+            }
+
+            if labels {
+                let max_chars = (image_width + 1) / (GLYPH_WIDTH + 1);
+                let label: String = cell.label.chars().take(max_chars as usize).collect();
+                let label_top = top + image_height + 1;
+                image_utils::draw_text(&mut pixels, width, height, left, label_top, &label, label_color(background));
+            }
+        }
+        ContactSheet { grid, width, height, pixels }
+    }
+
+    /// Saves the sheet as a PNG file
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(std::io::Error::other)
+    }
+}
+
+/// Parses `RRGGBB` or `RRGGBBAA` (optionally with a `#`, which would start a comment in most
+/// shells)
+pub fn parse_background(color: &str) -> Option<[u8; 4]> {
+    image_utils::parse_color(&format!("#{}", color.trim_start_matches('#')))
+}
+
+/// Black or white, whichever is better readable on the background
+fn label_color(background: [u8; 4]) -> [u8; 4] {
+    let luminance = 299 * background[0] as u32 + 587 * background[1] as u32 + 114 * background[2] as u32;
+    if background[3] < 128 || luminance > 128 * 1000 {
+        [0, 0, 0, 255]
+    } else {
+        [255, 255, 255, 255]
+    }
+}
+
+/// Writes one contact sheet of the PNG files in `png_dir` per background color to `output_dir`
+/// (as `contact_sheet_<RRGGBBAA>.png`) and returns the paths of the sheets
+pub fn write_contact_sheets(
+    png_dir: &Path,
+    output_dir: &Path,
+    backgrounds: &[[u8; 4]],
+    columns: u32,
+    labels: bool
+) -> std::io::Result<Vec<PathBuf>> {
+    let cells = Cell::from_dir(png_dir)?;
+    info!("Drawing {} emojis on {} contact sheet(s)", cells.len(), backgrounds.len());
+    std::fs::create_dir_all(output_dir)?;
+    backgrounds.iter()
+        .map(|background| {
+            let name = format!("contact_sheet_{}.png", background.iter().map(|channel| format!("{:02x}", channel)).join(""));
+            let path = output_dir.join(name);
+            ContactSheet::draw(&cells, columns, *background, labels).write(&path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+
+#[test]
+fn test_grid() {
+    let grid = Grid::new(10, 4, 136, 128);
+    assert_eq!((grid.columns, grid.rows), (4, 3));
+    assert_eq!(grid.size(), (4 + 4 * 140, 4 + 3 * 132));
+    assert_eq!(grid.cell(0), (4, 4));
+    assert_eq!(grid.cell(3), (4 + 3 * 140, 4));
+    assert_eq!(grid.cell(4), (4, 4 + 132));
+    assert_eq!(grid.cell(9), (4 + 140, 4 + 2 * 132));
+
+    // Fewer emojis than columns
+    let grid = Grid::new(2, 4, 10, 10);
+    assert_eq!((grid.columns, grid.rows), (2, 1));
+    assert_eq!(Grid::new(0, 4, 10, 10).size(), (4, 4));
+}
+
+#[test]
+fn test_parse_background() {
+    assert_eq!(parse_background("ff8800"), Some([255, 136, 0, 255]));
+    assert_eq!(parse_background("#00000080"), Some([0, 0, 0, 128]));
+    assert_eq!(parse_background("white"), None);
+}
+
+#[test]
+fn test_contact_sheet() {
+    let red = Cell { label: "1f534".to_string(), width: 2, height: 2, pixels: [255, 0, 0, 128].repeat(4) };
+    // Smaller images are centered in their cell
+    let dot = Cell { label: "2b24".to_string(), width: 1, height: 1, pixels: vec![0, 0, 255, 255] };
+    let cells = vec![red, dot.clone(), dot];
+
+    let sheet = ContactSheet::draw(&cells, 2, [0, 0, 0, 255], false);
+    assert_eq!(sheet.grid, Grid { columns: 2, rows: 2, cell_width: 2, cell_height: 2 });
+    assert_eq!((sheet.width, sheet.height), (4 + 2 * 6, 4 + 2 * 6));
+    assert_eq!(sheet.pixels.len(), 4 * sheet.width as usize * sheet.height as usize);
+    let pixel = |x: u32, y: u32| {
+        let start = (y * sheet.width + x) as usize * 4;
+        sheet.pixels[start..start + 4].to_vec()
+    };
+    assert_eq!(pixel(0, 0), vec![0, 0, 0, 255]);
+    assert_eq!(pixel(4, 4), vec![128, 0, 0, 255]);
+    assert_eq!(pixel(5, 5), vec![128, 0, 0, 255]);
+    // The dot is at the top left of the center, just like with enlarge_to
+    assert_eq!(pixel(10, 4), vec![0, 0, 255, 255]);
+    assert_eq!(pixel(11, 5), vec![0, 0, 0, 255]);
+    assert_eq!(pixel(4, 10), vec![0, 0, 255, 255]);
+
+    // The labels get their own space below the images (and are cut off at the edge of the cell)
+    let wide = Cell { label: "1f914".to_string(), width: 8, height: 1, pixels: vec![0; 4 * 8] };
+    let labelled = ContactSheet::draw(&[wide], 1, [255, 255, 255, 255], true);
+    assert_eq!(labelled.grid.cell_height, 1 + LABEL_HEIGHT);
+    assert_eq!((labelled.width, labelled.height), (16, 9 + LABEL_HEIGHT));
+    let black = |x: u32, y: u32| labelled.pixels[(y * labelled.width + x) as usize * 4] == 0;
+    // The top of the "1" and the "f"
+    assert!(black(5, 6));
+    assert!(black(8, 6));
+    // The space for the third character is empty
+    assert!(!(12..16).any(|x| (6..11).any(|y| black(x, y))));
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Finds emojis whose rendered PNGs are byte-identical (e.g. sequences with and without
+//! `FE0F` or emojis that are drawn the same way).
+//! These are added as aliases to the font, so they share a single glyph and the CBDT table
+//! only contains their bitmap once.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// The emojis that can use the glyph of another emoji with the same PNG
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Duplicates {
+    /// Maps a sequence to the sequence whose glyph it uses instead of its own
+    pub aliases: HashMap<Vec<u32>, Vec<u32>>,
+    /// The size of the PNG files that don't need to be embedded anymore
+    pub saved_bytes: u64,
+}
+
+impl Duplicates {
+    /// Compares the PNG files of the sequences (which don't contain `FE0F`).
+    ///
+    /// The shortest (and then lowest) sequence of a group with identical PNGs keeps its glyph.
+    /// Some sequences always keep their own glyph:
+    /// - the ones in `excluded` (i.e. those that are already aliases),
+    /// - single codepoints that are part of longer sequences, as the ligatures are based on their
+    ///   glyphs,
+    /// - longer sequences with a codepoint that wouldn't be in the font otherwise.
+    ///
+    /// Files that can't be read are skipped with a warning.
+    pub fn find<P: AsRef<Path>>(seq_to_file: &[(Vec<u32>, P)], excluded: &HashSet<Vec<u32>>) -> Duplicates {
+        let components: HashSet<u32> = seq_to_file.iter()
+            .filter(|(sequence, _)| sequence.len() > 1)
+            .flat_map(|(sequence, _)| sequence.iter().copied())
+            .collect();
+
+        let mut by_hash: HashMap<Vec<u8>, Vec<(&Vec<u32>, u64)>> = HashMap::new();
+        for (sequence, file) in seq_to_file {
+            if excluded.contains(sequence) {
+                continue;
+            }
+            match std::fs::read(file.as_ref()) {
+                Ok(png) => by_hash.entry(Sha256::digest(&png).to_vec())
+                    .or_default()
+                    .push((sequence, png.len() as u64)),
+                Err(err) => warn!("Couldn't read {:?} to check for duplicates: {:?}", file.as_ref(), err)
+            };
+        }
+
+        let mut sizes = HashMap::new();
+        let mut aliases = HashMap::new();
+        for same_png in by_hash.values_mut().filter(|same_png| same_png.len() > 1) {
+            same_png.sort_by_key(|(sequence, _)| (sequence.len(), *sequence));
+            let (target, _) = same_png[0];
+            for (sequence, size) in &same_png[1..] {
+                if sequence.len() == 1 && components.contains(&sequence[0]) {
+                    continue;
+                }
+                aliases.insert((*sequence).clone(), target.clone());
+                sizes.insert(*sequence, *size);
+            }
+        }
+
+        // All codepoints of a ligature need a glyph, so they have to remain in the font
+        let mut remaining: HashSet<u32> = seq_to_file.iter()
+            .filter(|(sequence, _)| !aliases.contains_key(sequence))
+            .flat_map(|(sequence, _)| sequence.iter().copied())
+            .collect();
+        let uncovered: Vec<Vec<u32>> = aliases.keys()
+            .filter(|sequence| sequence.len() > 1)
+            .filter(|sequence| sequence.iter().any(|codepoint| !remaining.contains(codepoint)))
+            .cloned()
+            .collect();
+        for sequence in uncovered {
+            remaining.extend(sequence.iter().copied());
+            aliases.remove(&sequence);
+        }
+
+        let saved_bytes = aliases.keys()
+            .filter_map(|sequence| sizes.get(sequence))
+            .sum();
+        Duplicates {
+            aliases,
+            saved_bytes,
+        }
+    }
+
+    /// Whether there are no duplicates
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
+
+#[test]
+fn test_find() {
+    let dir = tempfile::tempdir().unwrap();
+    let png = |name: &str, content: &[u8]| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let seq_to_file = vec![
+        (vec![0x1f914], png("emoji_u1f914.png", b"thinking")),
+        (vec![0x1f9a8], png("emoji_u1f9a8.png", b"thinking")),
+        (vec![0x1f600], png("emoji_u1f600.png", b"thinking")),
+        (vec![0x1f3f3], png("emoji_u1f3f3.png", b"flag")),
+        (vec![0x1f308], png("emoji_u1f308.png", b"flag")),
+        (vec![0x1f3f3, 0x200d, 0x1f308], png("emoji_u1f3f3_200d_1f308.png", b"flag")),
+        (vec![0x1f3f4, 0x200d, 0x2620], png("emoji_u1f3f4_200d_2620.png", b"flag")),
+        (vec![0x1f469, 0x200d, 0x1f52c], png("emoji_u1f469_200d_1f52c.png", b"scientist")),
+    ];
+    let excluded = vec![vec![0x1f600]].into_iter().collect();
+
+    let duplicates = Duplicates::find(&seq_to_file, &excluded);
+    let expected: HashMap<Vec<u32>, Vec<u32>> = vec![
+        (vec![0x1f9a8], vec![0x1f914]),
+        (vec![0x1f3f3, 0x200d, 0x1f308], vec![0x1f308]),
+    ].into_iter().collect();
+    // 1F3F3 is part of a ligature and the pirate flag would be the only sequence with 1F3F4
+    assert_eq!(duplicates.aliases, expected);
+    assert_eq!(duplicates.saved_bytes, 12);
+
+    let duplicates = Duplicates::find(&seq_to_file[..1], &HashSet::new());
+    assert!(duplicates.is_empty());
+    assert_eq!(duplicates.saved_bytes, 0);
+}
@@ -36,7 +36,21 @@ pub enum BlobmojiError {
     CsvError(csv::Error),
     // Unfortunately, PyErr requires additional stuff to be actually helpful
     /// Wrapper for an error that occured in Python code
-    PythonError(String)
+    PythonError(String),
+    /// Wrapper for [crate::configs::ConfigError]
+    ConfigError(crate::configs::ConfigError),
+    /// The optimized PNG file is larger than the size budget allows (size, budget)
+    SizeBudgetExceeded(u64, u64),
+    /// Prepared emojis whose PNG files are not in the build directory (anymore)
+    MissingPngs(Vec<std::path::PathBuf>),
+    /// Codepoints that should have an emoji variation sequence, but the font's cmap doesn't have one
+    MissingVariationSequences(Vec<u32>),
+    /// Glyphs of emojis without vertical metrics, although the font has them (`vhea`)
+    MissingVerticalMetrics(Vec<String>),
+    /// Font families that an emoji's text asks for, but which aren't available (with `--require-fonts`)
+    MissingFonts(Vec<String>),
+    /// The subset (`--subset`) doesn't contain any of the prepared emojis
+    EmptySubset
 }
 
 impl From<()> for BlobmojiError {
@@ -56,3 +70,9 @@ impl From<csv::Error> for BlobmojiError {
         BlobmojiError::CsvError(error)
     }
 }
+
+impl From<crate::configs::ConfigError> for BlobmojiError {
+    fn from(error: crate::configs::ConfigError) -> Self {
+        BlobmojiError::ConfigError(error)
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Lets flags share the artwork of another flag (e.g. the flag of Norway for Svalbard and Jan Mayen
+//! and for Bouvet Island), so it's only rendered and embedded once.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::emoji::Emoji;
+
+/// Maps flags to the flags (or other sequences) that use the same glyph.
+/// They are given as a JSON object like this one:
+/// ```json
+/// {
+///   "NO": ["SJ", "BV"],
+///   "FR": ["MF", "1f1f5-1f1f2"]
+/// }
+/// ```
+/// Flags are given by their ISO 3166-1/2 codes, just like the files in the flags directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagAliases(BTreeMap<String, Vec<String>>);
+
+impl FlagAliases {
+    /// Loads the aliases from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<FlagAliases> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads the aliases from a JSON object that maps flags to lists of flags or sequences
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<FlagAliases> {
+        Ok(FlagAliases(serde_json::from_reader(reader)?))
+    }
+
+    /// Parses a flag (e.g. `DE` or `DE-NW`) or a codepoint sequence (without `FE0F`)
+    fn sequence(flag: &str) -> Option<Vec<u32>> {
+        Emoji::from_flag(flag, None)
+            .or_else(|_| Emoji::from_sequence(flag, None))
+            .ok()
+            .map(|emoji| emoji.sequence.into_iter()
+                .filter(|codepoint| *codepoint != 0xfe0f)
+                .collect())
+    }
+
+    /// Maps the sequences of the aliases to the sequences of their flags.
+    ///
+    /// `available` contains the sequences (without `FE0F`) that have an image.
+    /// Aliases are skipped (with a warning) if their flag doesn't have an image, if they have an
+    /// image themselves or if one of their codepoints wouldn't be in the font otherwise.
+    pub fn resolve(&self, available: &HashSet<Vec<u32>>) -> HashMap<Vec<u32>, Vec<u32>> {
+        let codepoints: HashSet<u32> = available.iter()
+            .flat_map(|sequence| sequence.iter().copied())
+            .collect();
+        let mut aliases = HashMap::new();
+        for (flag, flag_aliases) in &self.0 {
+            let target = match Self::sequence(flag) {
+                Some(target) if available.contains(&target) => target,
+                Some(_) => {
+                    warn!("The flag {} has no image, so it can't be used for {:?}", flag, flag_aliases);
+                    continue;
+                },
+                None => {
+                    warn!("Invalid flag: {}", flag);
+                    continue;
+                }
+            };
+            for alias in flag_aliases {
+                match Self::sequence(alias) {
+                    Some(sequence) if available.contains(&sequence) =>
+                        warn!("{} has its own image and is not used as an alias of {}", alias, flag),
+                    Some(sequence) if !sequence.iter().all(|codepoint| codepoints.contains(codepoint)) =>
+                        warn!("Not all codepoints of {} are in the font, so it can't be an alias of {}", alias, flag),
+                    Some(sequence) => {
+                        info!("{} is an alias of the {} flag", alias.to_uppercase(), flag.to_uppercase());
+                        aliases.insert(sequence, target.clone());
+                    },
+                    None => warn!("Invalid alias of {}: {}", flag, alias)
+                }
+            }
+        }
+        aliases
+    }
+
+    /// Whether there are no aliases
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[test]
+fn test_resolve() {
+    let json = r#"{
+        "DE": ["AT", "et", "1f1f9-1f1e9", "DK"],
+        "AT": ["DE"],
+        "NO": ["SJ"],
+        "XYZ-": ["DE"]
+    }"#;
+    let flag_aliases = FlagAliases::from_reader(json.as_bytes()).unwrap();
+    let flag = |code: &str| Emoji::from_flag(code, None).unwrap().sequence;
+    // The regional indicators and the German flag
+    let available: HashSet<Vec<u32>> = vec![
+        vec![0x1f1e6], vec![0x1f1e9], vec![0x1f1ea], vec![0x1f1f9],
+        flag("DE"),
+    ].into_iter().collect();
+
+    let aliases = flag_aliases.resolve(&available);
+    let expected: HashMap<Vec<u32>, Vec<u32>> = vec![
+        (flag("AT"), flag("DE")),
+        (flag("ET"), flag("DE")),
+        (flag("TD"), flag("DE")),
+    ].into_iter().collect();
+    // There's no K, N, S or J and the flag of Austria has no image
+    assert_eq!(aliases, expected);
+
+    assert!(FlagAliases::from_reader(r#"{"DE": "AT"}"#.as_bytes()).is_err());
+    assert!(FlagAliases::default().resolve(&available).is_empty());
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+
+//! Reads the emoji sequences that an existing font supports, e.g. to compare a build against
+//! an official release of Noto Color Emoji.
+//!
+//! Single codepoints come from the cmap and sequences from the GSUB ligatures. The legacy PUA
+//! codepoints that `map_pua_emoji.py` adds for flags and keycaps are mapped back to their
+//! sequences.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use itertools::Itertools;
+use pyo3::{PyResult, Python};
+use pyo3::prelude::PyModule;
+
+use crate::builders::blobmoji::gsub;
+use crate::builders::blobmoji::noto_emoji_utils::ADD_EMOJI_GSUB_PY;
+
+const READ_FONT_PY: &str = r#"
+from fontTools.ttLib import TTFont
+import add_emoji_gsub
+
+def pua_sequences():
+    sequences = dict(add_emoji_gsub.EMOJI_KEYCAPS)
+    sequences.update(add_emoji_gsub.EMOJI_FLAGS)
+    return {pua: list(sequence) for pua, sequence in sequences.items()}
+
+def read(path):
+    font = TTFont(path)
+    cmap = font.getBestCmap() or {}
+    ligatures = []
+    if 'GSUB' in font:
+        for lookup in font['GSUB'].table.LookupList.Lookup:
+            for subtable in lookup.SubTable:
+                # Extension lookups (type 7) wrap the actual subtable
+                subtable = getattr(subtable, 'ExtSubTable', subtable)
+                for first, same_first in getattr(subtable, 'ligatures', {}).items():
+                    for ligature in same_first:
+                        ligatures.append(([first] + list(ligature.Component), ligature.LigGlyph))
+    return (cmap, ligatures)
+"#;
+
+/// The components and the resulting glyph of a ligature
+type Ligature = (Vec<String>, String);
+
+/// Reads all sequences from a font file (without `U+FE0F`)
+pub fn read_sequences(font: &Path) -> PyResult<HashSet<Vec<u32>>> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    PyModule::from_code(
+        py,
+        ADD_EMOJI_GSUB_PY,
+        "add_emoji_gsub.py",
+        "add_emoji_gsub"
+    )?;
+    let read_font = PyModule::from_code(py, READ_FONT_PY, "read_font.py", "read_font")?;
+
+    let pua: HashMap<u32, Vec<u32>> = read_font.call0("pua_sequences")?.extract()?;
+    let (cmap, ligatures): (HashMap<u32, String>, Vec<Ligature>) = read_font
+        .call1("read", (font.to_string_lossy().into_owned(),))?
+        .extract()?;
+
+    Ok(sequences(&cmap, &ligatures, &pua))
+}
+
+/// Reconstructs the sequences from the cmap and the ligatures (given as the components and the
+/// resulting glyph).
+///
+/// ZWJ sequences usually have a second, right-to-left ligature for the same glyph.
+/// If the glyph's name follows the naming scheme of `add_glyphs.py`, only the ligature that
+/// matches the name is used.
+fn sequences(
+    cmap: &HashMap<u32, String>,
+    ligatures: &[Ligature],
+    pua: &HashMap<u32, Vec<u32>>
+) -> HashSet<Vec<u32>> {
+    let without_fe0f = |sequence: &[u32]| sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect_vec();
+
+    // The PUA codepoints are only aliases for ligatures, so they are not used for the components
+    let codepoints: HashMap<&String, u32> = cmap.iter()
+        .filter(|(codepoint, _)| !pua.contains_key(codepoint))
+        .map(|(codepoint, glyph)| (glyph, *codepoint))
+        .collect();
+
+    let mut sequences: HashSet<Vec<u32>> = cmap.keys()
+        .map(|codepoint| pua.get(codepoint).cloned().unwrap_or_else(|| vec![*codepoint]))
+        .map(|sequence| without_fe0f(&sequence))
+        .filter(|sequence| !sequence.is_empty())
+        .collect();
+
+    let mut by_glyph: HashMap<&String, Vec<Vec<u32>>> = HashMap::new();
+    for (components, glyph) in ligatures {
+        let sequence: Option<Vec<u32>> = components.iter()
+            .map(|component| codepoints.get(component).copied())
+            .collect();
+        match sequence {
+            Some(sequence) => by_glyph.entry(glyph).or_default().push(sequence),
+            None => warn!("Not all components of {} are in the cmap", glyph)
+        }
+    }
+
+    for (glyph, candidates) in by_glyph {
+        let named = candidates.iter()
+            .find(|candidate| gsub::glyph_name(candidate) == *glyph);
+        match named {
+            Some(named) => {
+                sequences.insert(without_fe0f(named));
+            }
+            None => sequences.extend(candidates.iter().map(|candidate| without_fe0f(candidate)))
+        }
+    }
+
+    sequences
+}
+
+#[test]
+fn test_sequences() {
+    let cmap: HashMap<u32, String> = vec![
+        (0x1f468, "u1F468"),
+        (0x200d, "uni200D"),
+        (0x1f469, "u1F469"),
+        (0x1f1e9, "u1F1E9"),
+        (0x1f1ea, "u1F1EA"),
+        (0xfe0f, "uniFE0F"),
+        (0xfe4e8, "u1F1E9_1F1EA"),
+    ].into_iter()
+        .map(|(codepoint, glyph)| (codepoint, String::from(glyph)))
+        .collect();
+    let ligature = |components: &[&str], glyph: &str| (
+        components.iter().map(|component| String::from(*component)).collect_vec(),
+        String::from(glyph)
+    );
+    let ligatures = vec![
+        ligature(&["u1F468", "uni200D", "u1F469"], "u1F468_200D_1F469"),
+        // The right-to-left variant
+        ligature(&["u1F469", "uni200D", "u1F468"], "u1F468_200D_1F469"),
+        ligature(&["u1F1E9", "u1F1EA"], "u1F1E9_1F1EA"),
+        ligature(&["u1F1E9", "missing"], "broken"),
+    ];
+    let pua: HashMap<u32, Vec<u32>> = vec![(0xfe4e8, vec![0x1f1e9, 0x1f1ea])].into_iter().collect();
+
+    let sequences = sequences(&cmap, &ligatures, &pua);
+    let expected: HashSet<Vec<u32>> = vec![
+        vec![0x1f468],
+        vec![0x200d],
+        vec![0x1f469],
+        vec![0x1f1e9],
+        vec![0x1f1ea],
+        vec![0x1f468, 0x200d, 0x1f469],
+        vec![0x1f1e9, 0x1f1ea],
+    ].into_iter().collect();
+    assert_eq!(sequences, expected);
+}
+
+#[test]
+fn test_read_sequences() {
+    const BUILD_TEST_FONT: &str = r#"
+from fontTools.fontBuilder import FontBuilder
+from fontTools.pens.ttGlyphPen import TTGlyphPen
+
+def build(path):
+    glyphs = [".notdef", "u1F468", "uni200D", "u1F469", "u1F1E9", "u1F1EA", "u1F468_200D_1F469", "u1F1E9_1F1EA"]
+    fb = FontBuilder(1024, isTTF=True)
+    fb.setupGlyphOrder(glyphs)
+    fb.setupCharacterMap({0x1F468: "u1F468", 0x200D: "uni200D", 0x1F469: "u1F469",
+                          0x1F1E9: "u1F1E9", 0x1F1EA: "u1F1EA", 0xFE4E8: "u1F1E9_1F1EA"})
+    fb.setupGlyf({glyph: TTGlyphPen(None).glyph() for glyph in glyphs})
+    fb.setupHorizontalMetrics({glyph: (1024, 0) for glyph in glyphs})
+    fb.setupHorizontalHeader(ascent=800, descent=-200)
+    fb.addOpenTypeFeatures("""
+        feature ccmp {
+            sub u1F468 uni200D u1F469 by u1F468_200D_1F469;
+            sub u1F469 uni200D u1F468 by u1F468_200D_1F469;
+            sub u1F1E9 u1F1EA by u1F1E9_1F1EA;
+        } ccmp;
+    """)
+    fb.setupNameTable({"familyName": "Test", "styleName": "Regular"})
+    fb.setupOS2()
+    fb.setupPost()
+    fb.save(path)
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let ttf = dir.path().join("test.ttf");
+    {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let test_font = PyModule::from_code(py, BUILD_TEST_FONT, "test_font.py", "test_font").unwrap();
+        test_font.call1("build", (ttf.to_string_lossy().into_owned(),)).unwrap();
+    }
+
+    let sequences = read_sequences(&ttf).unwrap();
+    assert_eq!(sequences.len(), 7);
+    assert!(sequences.contains(&vec![0x1f468, 0x200d, 0x1f469]));
+    assert!(sequences.contains(&vec![0x1f1e9, 0x1f1ea]));
+    assert!(!sequences.contains(&vec![0x1f469, 0x200d, 0x1f468]));
+    assert!(!sequences.contains(&vec![0xfe4e8]));
+}
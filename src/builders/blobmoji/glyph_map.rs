@@ -0,0 +1,101 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The glyph names that the build has assigned to the emojis, so that tools like `pyftsubset`
+//! can subset the font without guessing them.
+//! They are read from the font after `add_glyphs.py`, so they include the aliases (and the
+//! deduplicated emojis), which use the glyph of another emoji.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+/// The file name of the glyph map next to a font
+const SUFFIX: &str = "glyph_map.json";
+
+/// The glyph order of a font and the glyph of each sequence, as JSON:
+/// ```json
+/// {
+///   "glyph_order": [".notdef", "u1F3F3", "u1F3F3_200D_1F308", ...],
+///   "glyphs": {
+///     "1f3f3 fe0f 200d 1f308": "u1F3F3_200D_1F308",
+///     ...
+///   }
+/// }
+/// ```
+/// The sequences are written like in the manifest (see [GlyphMap::sequence_key]).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlyphMap {
+    /// All glyphs of the font in their order
+    pub glyph_order: Vec<String>,
+    /// The name of each sequence's glyph
+    pub glyphs: BTreeMap<String, String>,
+}
+
+impl GlyphMap {
+    /// The codepoints in lowercase hex, separated by spaces
+    pub fn sequence_key(sequence: &[u32]) -> String {
+        sequence.iter().map(|codepoint| format!("{:x}", codepoint)).join(" ")
+    }
+
+    /// The name of the glyph of a sequence
+    pub fn get(&self, sequence: &[u32]) -> Option<&str> {
+        self.glyphs.get(&Self::sequence_key(sequence)).map(String::as_str)
+    }
+
+    /// Sets the glyph of a sequence
+    pub fn insert(&mut self, sequence: &[u32], glyph: String) {
+        self.glyphs.insert(Self::sequence_key(sequence), glyph);
+    }
+
+    /// The glyph map that belongs to a font (e.g. `Blobmoji.glyph_map.json` for `Blobmoji.ttf`)
+    pub fn next_to(font: &Path) -> PathBuf {
+        let mut name = font.file_stem().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(SUFFIX);
+        font.with_file_name(name)
+    }
+
+    /// Loads a glyph map from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<GlyphMap> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Writes the glyph map as JSON
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        Ok(serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?)
+    }
+}
+
+#[test]
+fn test_glyph_map_file() {
+    let mut glyph_map = GlyphMap {
+        glyph_order: vec![String::from(".notdef"), String::from("u1F914")],
+        glyphs: BTreeMap::new(),
+    };
+    glyph_map.insert(&[0x1f914], String::from("u1F914"));
+    assert_eq!(glyph_map.get(&[0x1f914]), Some("u1F914"));
+    assert_eq!(GlyphMap::sequence_key(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]), "1f3f3 fe0f 200d 1f308");
+    assert_eq!(GlyphMap::next_to(Path::new("fonts/Blobmoji.ttf")), Path::new("fonts/Blobmoji.glyph_map.json"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(SUFFIX);
+    glyph_map.write(&path).unwrap();
+    assert_eq!(GlyphMap::from_file(&path).unwrap(), glyph_map);
+}
@@ -0,0 +1,241 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+
+//! Generates the GSUB ligature substitutions for emoji sequences (e.g. ZWJ sequences).
+//! This mirrors `add_ligature_sequences` from `add_glyphs.py`, but it makes sure that every
+//! sequence gets a ligature, even if the template already contains a GSUB table.
+
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use pyo3::{PyAny, PyResult, Python};
+use pyo3::prelude::PyModule;
+use pyo3::types::{PyDict, PyList};
+
+const ZWJ: u32 = 0x200d;
+
+/// A ligature substitution, i.e. the glyphs of the sequence's codepoints are replaced by `glyph`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ligature {
+    pub sequence: Vec<u32>,
+    pub glyph: String,
+}
+
+/// The glyph name that `add_glyphs.py` uses for a sequence (see `seq_name` there)
+pub fn glyph_name(sequence: &[u32]) -> String {
+    if sequence.len() == 1 {
+        let codepoint = sequence[0];
+        if codepoint > 0xffff {
+            format!("u{:04X}", codepoint)
+        } else {
+            format!("uni{:04X}", codepoint)
+        }
+    } else {
+        format!("u{}", sequence.iter()
+            .map(|codepoint| format!("{:04X}", codepoint))
+            .join("_"))
+    }
+}
+
+/// ZWJ sequences also get a reversed variant for right-to-left text.
+/// Skin tone modifiers stay behind their base character though (see `get_rtl_seq`).
+fn rtl_sequence(sequence: &[u32]) -> Option<Vec<u32>> {
+    if !sequence.contains(&ZWJ) {
+        return None;
+    }
+    let is_fitzpatrick = |codepoint: u32| (0x1f3fb..=0x1f3ff).contains(&codepoint);
+    let mut reversed = sequence.to_vec();
+    reversed.reverse();
+    for i in 1..reversed.len() {
+        if is_fitzpatrick(reversed[i - 1]) {
+            reversed.swap(i, i - 1);
+        }
+    }
+    Some(reversed)
+}
+
+/// Computes the ligatures for all sequences with more than one codepoint (including the
+/// right-to-left variants of ZWJ sequences).
+///
+/// They are ordered by their first codepoint and longer sequences come first, so that
+/// sequences with a common prefix don't shadow each other.
+pub fn ligatures<I: IntoIterator<Item=Vec<u32>>>(sequences: I) -> Vec<Ligature> {
+    let mut ligatures: BTreeMap<Vec<u32>, String> = BTreeMap::new();
+    for sequence in sequences.into_iter().filter(|sequence| sequence.len() > 1) {
+        let glyph = glyph_name(&sequence);
+        if let Some(rtl) = rtl_sequence(&sequence) {
+            ligatures.entry(rtl).or_insert_with(|| glyph.clone());
+        }
+        ligatures.insert(sequence, glyph);
+    }
+    ligatures.into_iter()
+        .map(|(sequence, glyph)| Ligature { sequence, glyph })
+        .sorted_by_key(|ligature| (
+            ligature.sequence[0],
+            std::cmp::Reverse(ligature.sequence.len()),
+            ligature.sequence.clone()
+        ))
+        .collect()
+}
+
+/// Adds the ligatures to the first lookup of the font's GSUB table (which is created if it
+/// doesn't exist yet). Ligatures that already exist are left untouched.
+/// # Returns
+/// The number of ligatures that have been added
+pub fn add_ligatures(py: Python, add_glyphs_module: &PyModule, font: &PyAny, ligatures: &[Ligature]) -> PyResult<usize> {
+    let ot_tables = PyModule::import(py, "fontTools.ttLib.tables.otTables")?;
+
+    let cmap: HashMap<u32, String> = font.get_item("cmap")?
+        .getattr("tables")?
+        .get_item(0)?
+        .getattr("cmap")?
+        .extract()?;
+
+    // This also makes sure that the lookup is a properly initialized ligature substitution
+    let lookup = add_glyphs_module.call1("get_gsub_ligature_lookup", (font,))?;
+    let existing: &PyDict = lookup.getattr("SubTable")?
+        .get_item(0)?
+        .getattr("ligatures")?
+        .downcast()?;
+
+    let mut added = 0;
+    for ligature in ligatures {
+        let glyphs: Option<Vec<&String>> = ligature.sequence.iter()
+            .map(|codepoint| cmap.get(codepoint))
+            .collect();
+        let glyphs = match glyphs {
+            Some(glyphs) => glyphs,
+            None => {
+                warn!("Not all codepoints of {} are in the cmap", ligature.glyph);
+                continue;
+            }
+        };
+        let first = glyphs[0];
+        let components: Vec<String> = glyphs[1..].iter().map(|glyph| (*glyph).clone()).collect();
+
+        let same_ligatures = match existing.get_item(first) {
+            Some(same_first) => {
+                let same_first: &PyList = same_first.downcast()?;
+                let mut exists = false;
+                for other in same_first.iter() {
+                    let other_components: Vec<String> = other.getattr("Component")?.extract()?;
+                    exists |= other_components == components;
+                }
+                if exists {
+                    continue;
+                }
+                same_first
+            },
+            None => {
+                let same_first = PyList::empty(py);
+                existing.set_item(first, same_first)?;
+                same_first
+            }
+        };
+
+        let lig = ot_tables.call0("Ligature")?;
+        lig.setattr("CompCount", ligature.sequence.len())?;
+        lig.setattr("Component", components)?;
+        lig.setattr("LigGlyph", &ligature.glyph)?;
+        same_ligatures.append(lig)?;
+        added += 1;
+    }
+
+    // Longer sequences need to come first as they would be shadowed otherwise
+    let by_length = py.eval("lambda ligature: -ligature.CompCount", None, None)?;
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("key", by_length)?;
+    for (_, same_first) in existing.iter() {
+        same_first.call_method("sort", (), Some(kwargs))?;
+    }
+
+    Ok(added)
+}
+
+#[test]
+fn test_glyph_names() {
+    assert_eq!(glyph_name(&[0x23]), "uni0023");
+    assert_eq!(glyph_name(&[0x1f914]), "u1F914");
+    assert_eq!(glyph_name(&[0x1f3f3, 0x200d, 0x1f308]), "u1F3F3_200D_1F308");
+}
+
+#[test]
+fn test_ligatures() {
+    let sequences = vec![
+        vec![0x1f914],
+        vec![0x1f469, 0x200d, 0x1f52c],
+        vec![0x1f469, 0x1f3fd, 0x200d, 0x1f52c],
+        vec![0x1f1e9, 0x1f1ea],
+    ];
+    let ligatures = ligatures(sequences);
+
+    assert_eq!(ligatures, vec![
+        Ligature { sequence: vec![0x1f1e9, 0x1f1ea], glyph: String::from("u1F1E9_1F1EA") },
+        Ligature { sequence: vec![0x1f469, 0x1f3fd, 0x200d, 0x1f52c], glyph: String::from("u1F469_1F3FD_200D_1F52C") },
+        Ligature { sequence: vec![0x1f469, 0x200d, 0x1f52c], glyph: String::from("u1F469_200D_1F52C") },
+        // The RTL variants keep the skin tone modifier behind the base
+        Ligature { sequence: vec![0x1f52c, 0x200d, 0x1f469, 0x1f3fd], glyph: String::from("u1F469_1F3FD_200D_1F52C") },
+        Ligature { sequence: vec![0x1f52c, 0x200d, 0x1f469], glyph: String::from("u1F469_200D_1F52C") },
+    ]);
+}
+
+/// Builds a font with the ligatures and checks that a shaper turns the sequences into single glyphs
+#[test]
+fn test_shape_zwj_sequences() {
+    use crate::builder::EmojiBuilder;
+    use crate::builders::blobmoji::{Blobmoji, BuildDir, TMPL_TTX_TMPL_CONTENT};
+    use crate::builders::blobmoji::noto_emoji_utils::{add_glyphs, GlyphOptions};
+    use crate::builders::blobmoji::ttx_compiler;
+    use crate::emoji::Emoji;
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    let scientist = vec![0x1f469, ZWJ, 0x1f52c];
+    let family = vec![0x1f468, ZWJ, 0x1f469, ZWJ, 0x1f467];
+    // The components need to be in the cmap
+    let emojis: Vec<Emoji> = vec![scientist.clone(), family.clone()].into_iter()
+        .chain([0x1f469, 0x1f52c, 0x1f468, 0x1f467, ZWJ].iter().map(|codepoint| vec![*codepoint]))
+        .map(Emoji::from)
+        .collect();
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .map(|emoji| {
+            let path = build_dir.png(emoji);
+            tiny_skia::Pixmap::new(136, 128).unwrap().save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+
+    let work_dir = build_dir.work_dir("test").unwrap();
+    let options = GlyphOptions { add_cmap4_and_glyf: true, ..GlyphOptions::default() };
+    let glyph_map = add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &options).unwrap();
+    ttx_compiler::compile(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+
+    let font = std::fs::read(work_dir.tmpl_ttf()).unwrap();
+    let face = rustybuzz::Face::from_slice(&font, 0).unwrap();
+    for sequence in &[scientist, family] {
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(&sequence.iter().map(|codepoint| std::char::from_u32(*codepoint).unwrap()).collect::<String>());
+        let glyphs = rustybuzz::shape(&face, &[], buffer);
+        let expected = glyph_map.glyph_order.iter()
+            .position(|glyph| *glyph == glyph_name(sequence))
+            .unwrap() as u32;
+        let ids: Vec<u32> = glyphs.glyph_infos().iter().map(|info| info.codepoint).collect();
+        assert_eq!(ids, vec![expected], "{} isn't a single glyph", glyph_name(sequence));
+    }
+}
@@ -0,0 +1,120 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+//! Writes the hashes of the prepared emojis to the build directory while the build is still
+//! running, so emojis that have been rendered before an interruption aren't rendered again.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::changes::FileHashes;
+use crate::emoji::Emoji;
+
+/// Records the hashes as soon as the emojis are prepared and writes them in the background
+/// after every [HashFlush::EVERY] updates or [HashFlush::INTERVAL] (whichever comes first)
+pub struct HashFlush {
+    hashes: Arc<FileHashes>,
+    path: PathBuf,
+    every: usize,
+    interval: Duration,
+    /// The number of updates since the last write and its time
+    pending: Mutex<(usize, Instant)>,
+    /// The writes that are still running
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl HashFlush {
+    /// The number of updates after which the hashes are written
+    pub const EVERY: usize = 50;
+    /// The time after which new hashes are written
+    pub const INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Writes the (shared) hashes to the file
+    pub fn new(hashes: Arc<FileHashes>, path: PathBuf) -> HashFlush {
+        HashFlush::with_schedule(hashes, path, HashFlush::EVERY, HashFlush::INTERVAL)
+    }
+
+    /// Like [HashFlush::new], but with other limits
+    pub fn with_schedule(hashes: Arc<FileHashes>, path: PathBuf, every: usize, interval: Duration) -> HashFlush {
+        HashFlush {
+            hashes,
+            path,
+            every,
+            interval,
+            pending: Mutex::new((0, Instant::now())),
+            running: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records the hash of a prepared emoji and starts writing the hashes if it's due
+    pub fn record(&self, emoji: &Emoji, hash: &[u8]) {
+        self.hashes.update_concurrent(emoji, hash);
+        let mut pending = self.pending.lock().unwrap();
+        pending.0 += 1;
+        if pending.0 < self.every && pending.1.elapsed() < self.interval {
+            return;
+        }
+        *pending = (0, Instant::now());
+
+        let hashes = Arc::clone(&self.hashes);
+        let path = self.path.clone();
+        self.running.lock().unwrap().push(std::thread::spawn(move || {
+            if let Err(err) = hashes.write_to_path(path) {
+                warn!("Couldn't write the hashes of the prepared emojis: {:?}", err);
+            }
+        }));
+    }
+
+    /// Waits for the writes in the background and writes all hashes
+    pub fn flush(&self) -> Result<(), csv::Error> {
+        for write in self.running.lock().unwrap().drain(..) {
+            // A panic is already reported by the thread itself
+            let _ = write.join();
+        }
+        *self.pending.lock().unwrap() = (0, Instant::now());
+        self.hashes.write_to_path(self.path.clone())
+    }
+}
+
+#[test]
+fn test_hash_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hashes.csv");
+    let hashes = Arc::new(FileHashes::new());
+    let flush = HashFlush::with_schedule(Arc::clone(&hashes), path.clone(), 10, Duration::from_secs(3600));
+    let emojis = (0..95u32).map(|index| Emoji::from(vec![0xf0000 + index])).collect::<Vec<_>>();
+
+    rayon::scope(|scope| {
+        for chunk in emojis.chunks(19) {
+            let flush = &flush;
+            scope.spawn(move |_| for emoji in chunk {
+                flush.record(emoji, &FileHashes::hash_bytes(&emoji.sequence[0].to_le_bytes(), &[]));
+            });
+        }
+    });
+    // Every 10 updates, the hashes have been written in the background
+    assert_eq!(flush.running.lock().unwrap().len(), 9);
+
+    flush.flush().unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(csv.lines().count(), emojis.len());
+    let read = FileHashes::from_path(&path).unwrap();
+    for emoji in &emojis {
+        assert_eq!(read.get(emoji), hashes.get(emoji));
+    }
+}
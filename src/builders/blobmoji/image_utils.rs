@@ -19,13 +19,13 @@
 use png::EncodingError;
 use png::ColorType::RGBA;
 use png::BitDepth::Eight;
-use crate::builders::blobmoji::{CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT, Blobmoji, PNG_DIR};
+use crate::builders::blobmoji::{CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT};
+use crate::builders::blobmoji::render_policy::Anchor;
 use oxipng::{PngResult, optimize_from_memory};
 use oxipng::internal_tests::Headers::Safe;
-use std::path::{Path, PathBuf};
-use crate::emoji::Emoji;
+use std::path::Path;
 use std::fs::File;
-use std::io::Write;
+use std::io::{ErrorKind, Read, Write};
 
 pub fn pixels_to_png(img: &[u8]) -> Result<Vec<u8>, EncodingError> {
     // According to this post, PNG files have a header of 8 bytes: https://stackoverflow.com/questions/10423942/what-is-the-header-size-of-png-jpg-jpeg-bmp-gif-and-other-common-graphics-for
@@ -56,92 +56,102 @@ pub fn optimize_png(img: &[u8]) -> PngResult<Vec<u8>> {
 }
 
 
+/// The PNG signature, followed by the length and type of the `IHDR` chunk (which has to come first)
+const PNG_HEADER: &[u8; 16] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+
+/// Reads the width and height of a PNG file from its header without decoding the image
+pub fn png_dimensions(path: &Path) -> std::io::Result<(u32, u32)> {
+    let mut header = [0u8; 24];
+    File::open(path)?.read_exact(&mut header)?;
+    if &header[..16] != PNG_HEADER {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Not a PNG file"));
+    }
+    let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+    let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+    Ok((width, height))
+}
+
+
 /// Saves the already encoded PNG file
-pub fn write_png(build_path: &Path, emoji: &Emoji, image: Vec<u8>) -> std::io::Result<()> {
-    let filename = Blobmoji::generate_filename(&emoji);
-    let path = build_path
-        .join(PNG_DIR)
-        .join(&PathBuf::from(filename));
+pub fn write_png(path: &Path, image: Vec<u8>) -> std::io::Result<()> {
     let mut file = File::create(path)?;
     file.write_all(&image)
 }
 
 
-/// Adds a transparent area around an image and puts it in the center
-/// If a delta value is odd, the image will be positioned 1 pixel left of the center.
-fn enlarge_by(
+/// The transparent columns and lines that are added on each side of an image (see [pad])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Padding {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl Padding {
+    /// Puts the image in the center of an area that's `d_width` wider and `d_height` higher.
+    /// If a delta value is odd, the image will be positioned 1 pixel left of (or above) the center.
+    pub fn centered(d_width: u32, d_height: u32) -> Padding {
+        Padding {
+            left: d_width / 2,
+            right: d_width - d_width / 2,
+            top: d_height / 2,
+            bottom: d_height - d_height / 2,
+        }
+    }
+
+    /// Only adds lines above and below the image
+    pub fn vertical(top: u32, bottom: u32) -> Padding {
+        Padding {
+            top,
+            bottom,
+            ..Default::default()
+        }
+    }
+}
+
+
+/// Adds a transparent area around an image:
+///
+/// ```text
+/// |     top     |
+/// |-------------|
+/// |    |    |   |
+/// |left|cont|rgt|
+/// |    |    |   |
+/// |-------------|
+/// |   bottom    |
+/// ```
+///
+/// The result is `src_width + left + right` pixels wide and `src_height + top + bottom` pixels high.
+pub fn pad(
     content: &[u8],
     src_width: u32,
     src_height: u32,
-    d_width: u32,
-    d_height: u32,
+    padding: Padding,
 ) -> Vec<u8> {
-    // The padding will be added as follows:
-    //
-    // |  pad_vert   |  pad_vert = padding vertical = d_height/2
-    // |-------------|
-    // |  |      |   |
-    // |ph| cont |ph |  ph = padding horizontal = d_width/2
-    // |  |      |   |
-    // |-------------|
-    // |  pad_vert   |
-    // |             |
-
-
-    // If the delta value is odd, we need to have the left/top padding one pixel smaller.
-    // The approach here is to add the shorter padding and add a one pixel padding later.
-    // If d % 2 = 1, round it down by 1,
-    // If d % 2 = 0, don't round
-    // That's the same as subtracting d % 2
-    let d_width_rounded = d_width - (d_width % 2);
-    let d_height_rounded = d_height - (d_height % 2);
-
-    // This is what we eventually want to have
-    let target_width = src_width + d_width;
-    let target_height = src_height + d_height;
-
-    // The smaller padding side's lengths. As we assume that every pixel consists of 4 subpixels
-    // (RGBA), we'll need to multiply by 4 here.
-    let pad_horizontal = d_width_rounded * 4;
-    let pad_vertical = d_height_rounded * target_width * 4;
-
-    // Prepare the actual padding data
-    let pad_horizontal = vec![0; pad_horizontal as usize / 2];
-    let pad_vertical = vec![0; pad_vertical as usize / 2];
-
-    // This is the target image
-    let mut image = Vec::with_capacity((target_width * target_height * 4) as usize);
-
-    // Add the top padding (the shorter one)
-    image.extend_from_slice(&pad_vertical);
-    for line in 0..src_height as usize {
-        // Add the left padding
-        image.extend_from_slice(&pad_horizontal);
-        // Add the image's line
-        let start = line * src_width as usize * 4;
-        let end = (line + 1) * src_width as usize * 4;
-        image.extend_from_slice(&content[start..end]);
-        // Add the right padding
-        image.extend_from_slice(&pad_horizontal);
-        // If necessary, add an extra pixel at the right side
-        if d_width % 2 != 0 {
-            image.extend_from_slice(&Blobmoji::EMPTY_PIXEL);
-        }
-    }
-    // Add the bottom padding
-    image.extend_from_slice(&pad_vertical);
+    let target_width = (src_width + padding.left + padding.right) as usize;
+    let target_height = (src_height + padding.top + padding.bottom) as usize;
+    // As every pixel consists of 4 subpixels (RGBA), we'll need to multiply by 4 here.
+    let line_len = src_width as usize * 4;
+    let left = vec![0; padding.left as usize * 4];
+    let right = vec![0; padding.right as usize * 4];
 
-    // If necessary, add an extra line at the bottom.
-    if d_height % 2 != 0 {
-        image.extend_from_slice(&vec![0; target_width as usize * 4]);
+    let mut image = Vec::with_capacity(target_width * target_height * 4);
+    image.resize(padding.top as usize * target_width * 4, 0);
+    for line in content.chunks_exact(line_len.max(1)).take(src_height as usize) {
+        image.extend_from_slice(&left);
+        image.extend_from_slice(line);
+        image.extend_from_slice(&right);
     }
-
-    assert_eq!(image.len(), 4 * (target_width as usize * target_height as usize));
+    image.resize(target_width * target_height * 4, 0);
 
     image
 }
 
 
+/// Adds a transparent area around an image, so it has the target size, and puts it in the center
+/// (see [Padding::centered])
 pub fn enlarge_to(
     content: &[u8],
     src_width: u32,
@@ -156,9 +166,811 @@ pub fn enlarge_to(
     // is used to prevent overflows.
     let d_width = target_width.saturating_sub(src_width);
     let d_height = target_height.saturating_sub(src_height);
-    let enlarged = enlarge_by(content, src_width, src_height, d_width, d_height);
+    let enlarged = pad(content, src_width, src_height, Padding::centered(d_width, d_height));
 
     assert_eq!(enlarged.len(), 4 * target_width as usize * target_height as usize);
 
     enlarged
 }
+
+
+/// How the colors of an image are averaged when it's scaled down (see [downscale])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// The colors are converted to linear light first, so antialiased edges keep their brightness
+    #[default]
+    Linear,
+    /// The sRGB values are averaged directly, which is faster, but darkens edges and thin lines
+    Fast,
+}
+
+lazy_static! {
+    /// The linear value of each 8 bit sRGB value
+    static ref SRGB_TO_LINEAR: Vec<f32> = (0..256)
+        .map(|value| {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+}
+
+/// Converts a linear value (0 to 1) back to sRGB (0 to 1)
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The source pixels that cover each target pixel along one axis and how much of them is covered
+/// (i.e. the weights of a box filter, which add up to 1)
+fn box_weights(src_len: u32, target_len: u32) -> Vec<Vec<(usize, f32)>> {
+    let ratio = src_len as f64 / target_len as f64;
+    (0..target_len)
+        .map(|target| {
+            let start = target as f64 * ratio;
+            let end = (target + 1) as f64 * ratio;
+            (start.floor() as usize..(end.ceil() as usize).min(src_len as usize))
+                .map(|src| {
+                    let covered = end.min(src as f64 + 1.0) - start.max(src as f64);
+                    (src, (covered / ratio) as f32)
+                })
+                .filter(|(_, weight)| *weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+/// Scales a (premultiplied) image down to the target size with a box filter, i.e. each target
+/// pixel is the average of the source pixels it covers.
+///
+/// With [ScaleMode::Linear], the colors are averaged in linear light and weighted by their alpha,
+/// so a half-covered white edge on a transparent background stays white instead of turning grey.
+pub fn downscale(
+    content: &[u8],
+    src_width: u32,
+    src_height: u32,
+    target_width: u32,
+    target_height: u32,
+    mode: ScaleMode,
+) -> Vec<u8> {
+    assert!(target_width <= src_width && target_height <= src_height);
+    assert_eq!(content.len(), 4 * src_width as usize * src_height as usize);
+    if target_width == 0 || target_height == 0 {
+        return vec![];
+    }
+
+    // Premultiplied channels between 0 and 1 (in linear light for ScaleMode::Linear)
+    let pixels: Vec<[f32; 4]> = content.chunks_exact(4)
+        .map(|pixel| {
+            let alpha = pixel[3] as f32 / 255.0;
+            match mode {
+                ScaleMode::Fast => [pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0, alpha],
+                ScaleMode::Linear if pixel[3] == 0 => [0.0; 4],
+                ScaleMode::Linear => {
+                    let linear = |channel: u8| {
+                        let straight = (channel as u32 * 255 + pixel[3] as u32 / 2) / pixel[3] as u32;
+                        SRGB_TO_LINEAR[straight.min(255) as usize] * alpha
+                    };
+                    [linear(pixel[0]), linear(pixel[1]), linear(pixel[2]), alpha]
+                }
+            }
+        })
+        .collect();
+
+    let columns = box_weights(src_width, target_width);
+    let lines = box_weights(src_height, target_height);
+    let to_u8 = |value: f32| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    let mut image = Vec::with_capacity(4 * target_width as usize * target_height as usize);
+    for line in &lines {
+        for column in &columns {
+            let mut sum = [0f32; 4];
+            for (y, line_weight) in line {
+                for (x, column_weight) in column {
+                    let pixel = &pixels[y * src_width as usize + x];
+                    for (sum, channel) in sum.iter_mut().zip(pixel.iter()) {
+                        *sum += channel * line_weight * column_weight;
+                    }
+                }
+            }
+            let alpha = to_u8(sum[3]);
+            match mode {
+                ScaleMode::Fast => image.extend_from_slice(&[to_u8(sum[0]), to_u8(sum[1]), to_u8(sum[2]), alpha]),
+                _ if alpha == 0 => image.extend_from_slice(&[0; 4]),
+                ScaleMode::Linear => {
+                    // Back to straight sRGB and premultiplied again with the rounded alpha
+                    let premultiplied = |channel: f32| {
+                        let srgb = linear_to_srgb((channel / sum[3]).min(1.0));
+                        to_u8(srgb * alpha as f32 / 255.0)
+                    };
+                    image.extend_from_slice(&[premultiplied(sum[0]), premultiplied(sum[1]), premultiplied(sum[2]), alpha]);
+                }
+            }
+        }
+    }
+    image
+}
+
+
+/// Scales an image down (keeping its aspect ratio) if it's larger than the target size.
+/// Returns the pixels and their width and height.
+pub fn fit_into(
+    content: Vec<u8>,
+    src_width: u32,
+    src_height: u32,
+    target_width: u32,
+    target_height: u32,
+    mode: ScaleMode,
+) -> (Vec<u8>, u32, u32) {
+    if src_width <= target_width && src_height <= target_height {
+        return (content, src_width, src_height);
+    }
+    let factor = (target_width as f64 / src_width as f64).min(target_height as f64 / src_height as f64);
+    let width = ((src_width as f64 * factor).round() as u32).max(1).min(target_width);
+    let height = ((src_height as f64 * factor).round() as u32).max(1).min(target_height);
+    (downscale(&content, src_width, src_height, width, height, mode), width, height)
+}
+
+
+/// Puts an image on a transparent canvas of the given size. It is centered (just like in
+/// [enlarge_to]) and then moved by the offset (positive values move it to the right/bottom).
+/// Unlike [enlarge_to], the image may be larger than the canvas, anything outside of it is cut off.
+pub fn place(
+    content: &[u8],
+    src_width: u32,
+    src_height: u32,
+    target_width: u32,
+    target_height: u32,
+    offset_x: i32,
+    offset_y: i32,
+) -> Vec<u8> {
+    let left = (target_width as i64 - src_width as i64).div_euclid(2) + offset_x as i64;
+    let top = (target_height as i64 - src_height as i64).div_euclid(2) + offset_y as i64;
+
+    let mut image = vec![0; target_width as usize * target_height as usize * 4];
+
+    // The horizontal range of the canvas that is covered by the image
+    let start_x = left.max(0);
+    let end_x = (left + src_width as i64).min(target_width as i64);
+    if start_x >= end_x {
+        return image;
+    }
+
+    for y in 0..target_height as i64 {
+        let src_y = y - top;
+        if src_y < 0 || src_y >= src_height as i64 {
+            continue;
+        }
+        let src_start = ((src_y * src_width as i64 + (start_x - left)) * 4) as usize;
+        let src_end = ((src_y * src_width as i64 + (end_x - left)) * 4) as usize;
+        let target_start = ((y * target_width as i64 + start_x) * 4) as usize;
+        let target_end = ((y * target_width as i64 + end_x) * 4) as usize;
+        image[target_start..target_end].copy_from_slice(&content[src_start..src_end]);
+    }
+
+    image
+}
+
+
+/// Puts an image on a transparent canvas of the given size. It is centered horizontally and
+/// placed vertically according to the anchor (see [Anchor]).
+/// For [Anchor::Top] and [Anchor::Bottom], fully transparent lines at the edges are not counted,
+/// so the trough of a waved flag (which leaves some empty lines below it) touches the margin.
+/// If the visible part fits into the canvas, it's never moved out of it by the margin.
+pub fn anchor_to(
+    content: &[u8],
+    src_width: u32,
+    src_height: u32,
+    target_width: u32,
+    target_height: u32,
+    anchor: Anchor,
+    margin: u32,
+) -> Vec<u8> {
+    let line_is_empty = |line: &&[u8]| line.chunks_exact(4).all(|pixel| pixel[3] == 0);
+    let lines = content.chunks_exact(src_width.max(1) as usize * 4);
+    // The range of lines that are (partially) visible
+    let first = lines.clone().take_while(line_is_empty).count() as i64;
+    let end = src_height as i64 - lines.rev().take_while(line_is_empty).count() as i64;
+    let end = end.max(first);
+
+    let top = match anchor {
+        Anchor::Center => return enlarge_to(content, src_width, src_height, target_width, target_height),
+        Anchor::Top => margin as i64 - first,
+        Anchor::Bottom => target_height as i64 - margin as i64 - end
+    };
+    let top = if end - first <= target_height as i64 {
+        top.max(-first).min(target_height as i64 - end)
+    } else {
+        top
+    };
+
+    let centered_top = (target_height as i64 - src_height as i64).div_euclid(2);
+    place(content, src_width, src_height, target_width, target_height, 0, (top - centered_top) as i32)
+}
+
+
+/// Composites the (premultiplied) image over a background color (which is not premultiplied)
+pub fn fill_background(image: &mut [u8], background: [u8; 4]) {
+    let alpha = background[3] as u32;
+    let premultiplied = [
+        (background[0] as u32 * alpha + 127) / 255,
+        (background[1] as u32 * alpha + 127) / 255,
+        (background[2] as u32 * alpha + 127) / 255,
+        alpha
+    ];
+    for pixel in image.chunks_exact_mut(4) {
+        let transparency = 255 - pixel[3] as u32;
+        for (channel, background) in pixel.iter_mut().zip(premultiplied.iter()) {
+            *channel = (*channel as u32 + (background * transparency + 127) / 255).min(255) as u8;
+        }
+    }
+}
+
+
+/// Composites an image over a background color, both with straight (i.e. not premultiplied)
+/// alpha, as it's stored in the PNG files. The colors are premultiplied for compositing and the
+/// result is converted back, so half-transparent pixels over half-transparent backgrounds get
+/// the right color.
+pub fn compose_on_background(rgba: &[u8], width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+    assert_eq!(rgba.len(), 4 * width as usize * height as usize);
+    let background_alpha = color[3] as u32;
+    let mut composed = Vec::with_capacity(rgba.len());
+    for pixel in rgba.chunks_exact(4) {
+        let alpha = pixel[3] as u32;
+        let transparency = 255 - alpha;
+        // Both the alpha and the (premultiplied) colors are scaled by 255 * 255 here
+        let composed_alpha = alpha * 255 + background_alpha * transparency;
+        for channel in 0..3 {
+            let premultiplied = pixel[channel] as u32 * alpha * 255
+                + color[channel] as u32 * background_alpha * transparency;
+            // Fully transparent pixels have no color
+            let straight = (premultiplied + composed_alpha / 2).checked_div(composed_alpha).unwrap_or(0);
+            composed.push(straight as u8);
+        }
+        composed.push(((composed_alpha + 127) / 255) as u8);
+    }
+    composed
+}
+
+
+/// Parses `#RRGGBB` or `#RRGGBBAA`
+pub fn parse_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#')?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+    Some([channel(0)?, channel(1)?, channel(2)?, alpha])
+}
+
+
+/// Decodes a PNG file (in any color type and bit depth, e.g. after `oxipng` reduced it) to
+/// 8 bit RGBA with straight alpha.
+/// Returns the width, the height and the pixels.
+pub fn read_rgba(path: &Path) -> std::io::Result<(u32, u32, Vec<u8>)> {
+    let invalid = |err: png::DecodingError| std::io::Error::new(ErrorKind::InvalidData, err);
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(invalid)?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).map_err(invalid)?;
+
+    let rgba = match info.color_type {
+        png::ColorType::RGBA => pixels,
+        png::ColorType::RGB => pixels.chunks_exact(3)
+            .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2)
+            .flat_map(|pixel| vec![pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter()
+            .flat_map(|gray| vec![*gray, *gray, *gray, 255])
+            .collect(),
+        // Palettes are expanded by the decoder
+        png::ColorType::Indexed => return Err(std::io::Error::new(ErrorKind::InvalidData, "Unexpected palette"))
+    };
+    Ok((info.width, info.height, rgba))
+}
+
+
+/// The color of the border around the character box in debug images
+pub const CHARACTER_BOX_COLOR: [u8; 4] = [255, 0, 0, 255];
+/// The color of the border around the render box in debug images
+pub const RENDER_BOX_COLOR: [u8; 4] = [0, 255, 0, 255];
+/// The color of the label in debug images
+pub const LABEL_COLOR: [u8; 4] = [0, 0, 255, 255];
+
+/// The width of a character of the label font (without the space between the characters)
+pub const GLYPH_WIDTH: u32 = 3;
+/// The height of a character of the label font
+pub const GLYPH_HEIGHT: u32 = 5;
+
+/// A tiny 3x5 pixel font for the characters that are needed for sequences (`0-9a-f_`).
+/// Each row is stored in the three lowest bits, the leftmost pixel being the highest one.
+fn glyph(character: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+    let glyph = match character.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => return None
+    };
+    Some(glyph)
+}
+
+fn set_pixel(image: &mut [u8], width: u32, height: u32, x: u32, y: u32, color: [u8; 4]) {
+    if x < width && y < height {
+        let start = (y as usize * width as usize + x as usize) * 4;
+        image[start..start + 4].copy_from_slice(&color);
+    }
+}
+
+/// Draws a 1px border along the inside of the given rectangle
+fn draw_border(image: &mut [u8], width: u32, height: u32, left: u32, box_width: u32, color: [u8; 4]) {
+    if box_width == 0 || height == 0 {
+        return;
+    }
+    let right = left + box_width - 1;
+    for x in left..=right {
+        set_pixel(image, width, height, x, 0, color);
+        set_pixel(image, width, height, x, height - 1, color);
+    }
+    for y in 0..height {
+        set_pixel(image, width, height, left, y, color);
+        set_pixel(image, width, height, right, y, color);
+    }
+}
+
+/// Writes the text at the given position. Characters that are not part of the font are left
+/// blank.
+pub fn draw_text(image: &mut [u8], width: u32, height: u32, left: u32, top: u32, text: &str, color: [u8; 4]) {
+    for (index, character) in text.chars().enumerate() {
+        if let Some(glyph) = glyph(character) {
+            let glyph_left = left + index as u32 * (GLYPH_WIDTH + 1);
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        set_pixel(image, width, height, glyph_left + column, top + row as u32, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Annotates a padded emoji for debugging: It gets a border at the edge of the character box
+/// (i.e. the whole image), another one at the edge of the (horizontally centered) render box and
+/// the label in its top left corner.
+/// The label is wrapped at spaces if it doesn't fit into one line.
+pub fn annotate(image: &mut [u8], width: u32, height: u32, render_width: u32, label: &str) {
+    assert_eq!(image.len(), 4 * width as usize * height as usize);
+    let render_left = width.saturating_sub(render_width) / 2;
+
+    // As both boxes have the same height, the character box is drawn last to keep it complete
+    draw_border(image, width, height, render_left, render_width.min(width), RENDER_BOX_COLOR);
+    draw_border(image, width, height, 0, width, CHARACTER_BOX_COLOR);
+
+    // Leave one pixel of space to the border
+    let text_left = render_left + 2;
+    let max_chars = (render_width.saturating_sub(4) + 1) as usize / (GLYPH_WIDTH + 1) as usize;
+    let mut lines: Vec<String> = vec![];
+    for word in label.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= max_chars => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string())
+        }
+    }
+    for (index, line) in lines.iter().enumerate() {
+        let top = 2 + index as u32 * (GLYPH_HEIGHT + 1);
+        draw_text(image, width, height, text_left, top, line, LABEL_COLOR);
+    }
+}
+
+
+/// A deterministic image for the padding tests (every subpixel is different from its neighbours)
+#[cfg(test)]
+fn padding_fixture(width: u32, height: u32) -> Vec<u8> {
+    (0..width as usize * height as usize * 4).map(|i| ((i * 37 + 11) % 256) as u8).collect()
+}
+
+
+#[test]
+fn test_pad() {
+    use itertools::iproduct;
+
+    for src_width in 1..=5 {
+        for src_height in 1..=4 {
+            let content = padding_fixture(src_width, src_height);
+            for (left, right, top, bottom) in iproduct!(0..=3, 0..=3, 0..=2, 0..=3) {
+                let padding = Padding { left, right, top, bottom };
+                let padded = pad(&content, src_width, src_height, padding);
+                let width = (src_width + left + right) as usize;
+                let height = (src_height + top + bottom) as usize;
+                assert_eq!(padded.len(), width * height * 4, "{}x{} with {:?}", src_width, src_height, padding);
+                for (y, x) in iproduct!(0..height, 0..width) {
+                    let target = &padded[(y * width + x) * 4..(y * width + x + 1) * 4];
+                    let src_x = x as i64 - left as i64;
+                    let src_y = y as i64 - top as i64;
+                    if (0..src_width as i64).contains(&src_x) && (0..src_height as i64).contains(&src_y) {
+                        let source = ((src_y * src_width as i64 + src_x) * 4) as usize;
+                        assert_eq!(target, &content[source..source + 4], "({}, {}) of {:?}", x, y, padding);
+                    } else {
+                        assert_eq!(target, &[0; 4], "({}, {}) of {:?}", x, y, padding);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+#[test]
+fn test_centered_padding() {
+    use itertools::iproduct;
+
+    // Odd deltas put the image 1 pixel left of/above the center
+    for (d_width, d_height) in iproduct!(0..=5, 0..=5) {
+        let padding = Padding::centered(d_width, d_height);
+        assert_eq!(padding.left + padding.right, d_width);
+        assert_eq!(padding.top + padding.bottom, d_height);
+        assert_eq!(padding.left, d_width / 2);
+        assert_eq!(padding.top, d_height / 2);
+    }
+    assert_eq!(Padding::vertical(2, 4), Padding { left: 0, right: 0, top: 2, bottom: 4 });
+}
+
+
+/// The hashes of the padded fixtures from the implementations before [pad] (the centering
+/// `enlarge_by` and the one of the waveflag), so the rendered images stay the same
+#[test]
+fn test_padding_unchanged() {
+    use sha2::{Digest, Sha256};
+    let hash = |image: &[u8]| hex::encode(Sha256::digest(image));
+
+    let centered = [
+        ((5, 3, 8, 8), "63b1f97067a69d6b1fe605fecc3cd5455191e4ff9c12734072728567265ed494"),
+        ((4, 4, 7, 9), "d6df149859ba089c778b3ad7a727b36a35d7ba332f850c9783a827d6312c7c87"),
+        ((128, 96, 136, 128), "a8592a8d87c432a8c6373fe04bc038b420ada1e615855edcae96a1bb96622f22"),
+        ((1, 1, 2, 3), "7da1eb2ca4b413b9da3af382d1693328828750b541ff1c3c96ed2606d9bb6198"),
+    ];
+    for ((src_width, src_height, target_width, target_height), expected) in centered.iter() {
+        let enlarged = enlarge_to(&padding_fixture(*src_width, *src_height), *src_width, *src_height, *target_width, *target_height);
+        assert_eq!(&hash(&enlarged), expected);
+    }
+
+    // The waveflag adds `added_lines` lines above and `added_lines + 2` below the flag
+    let waved = [
+        ((6, 4, 3), "170776f8c01481996a879c2594668b7ea5467a8bb4c62fa19e75c746bef98c7e"),
+        ((128, 96, 10), "75a90416689ff610e284b105e85c3e686c12aa49160d14f879a89abd625b9bad"),
+    ];
+    for ((width, height, added_lines), expected) in waved.iter() {
+        let padded = pad(&padding_fixture(*width, *height), *width, *height, Padding::vertical(*added_lines, added_lines + 2));
+        assert_eq!(&hash(&padded), expected);
+    }
+}
+
+
+/// A checkerboard of `cell`x`cell` squares that starts with `on` in the top left corner
+#[cfg(test)]
+fn checkerboard(width: u32, height: u32, cell: u32, on: [u8; 4], off: [u8; 4]) -> Vec<u8> {
+    use itertools::iproduct;
+    iproduct!(0..height, 0..width)
+        .flat_map(|(y, x)| (if (x / cell + y / cell).is_multiple_of(2) { on } else { off }).to_vec())
+        .collect()
+}
+
+
+#[test]
+fn test_box_weights() {
+    for (src_len, target_len) in [(4, 2), (6, 4), (136, 128), (7, 3), (5, 5)].iter() {
+        let weights = box_weights(*src_len, *target_len);
+        assert_eq!(weights.len(), *target_len as usize);
+        for (target, weights) in weights.iter().enumerate() {
+            let sum: f32 = weights.iter().map(|(_, weight)| weight).sum();
+            assert!((sum - 1.0).abs() < 1e-5, "{} of {} -> {}: {}", target, src_len, target_len, sum);
+        }
+    }
+    assert_eq!(box_weights(4, 2), vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 0.5), (3, 0.5)]]);
+}
+
+
+/// The references have been calculated independently with the sRGB formulas (in double precision)
+#[test]
+fn test_downscale() {
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const TRANSPARENT: [u8; 4] = [0; 4];
+    let pixels = |image: &[u8]| image.chunks_exact(4).map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]]).collect::<Vec<_>>();
+
+    // Black and white average to the middle grey of linear light, not to 50% sRGB
+    let fine = checkerboard(4, 4, 1, WHITE, BLACK);
+    assert_eq!(pixels(&downscale(&fine, 4, 4, 2, 2, ScaleMode::Linear)), vec![[188, 188, 188, 255]; 4]);
+    assert_eq!(pixels(&downscale(&fine, 4, 4, 2, 2, ScaleMode::Fast)), vec![[128, 128, 128, 255]; 4]);
+
+    // Transparent pixels don't darken the color, only the alpha
+    let transparent = checkerboard(4, 4, 1, WHITE, TRANSPARENT);
+    assert_eq!(pixels(&downscale(&transparent, 4, 4, 2, 2, ScaleMode::Linear)), vec![[128, 128, 128, 128]; 4]);
+    assert_eq!(pixels(&downscale(&transparent, 4, 4, 2, 2, ScaleMode::Fast)), vec![[128, 128, 128, 128]; 4]);
+
+    // 6 -> 4 pixels, so the corners are covered by a single cell and the edges by both
+    let coarse = checkerboard(6, 6, 2, WHITE, BLACK);
+    let linear = pixels(&downscale(&coarse, 6, 6, 4, 4, ScaleMode::Linear));
+    let fast = pixels(&downscale(&coarse, 6, 6, 4, 4, ScaleMode::Fast));
+    for (x, y, expected_linear, expected_fast) in [(0, 0, 255, 255), (1, 0, 156, 85), (2, 0, 156, 85), (1, 1, 197, 142), (0, 3, 255, 255)].iter() {
+        let index = y * 4 + x;
+        assert_eq!(linear[index], [*expected_linear, *expected_linear, *expected_linear, 255], "({}, {})", x, y);
+        assert_eq!(fast[index], [*expected_fast, *expected_fast, *expected_fast, 255], "({}, {})", x, y);
+    }
+
+    let coarse = checkerboard(6, 6, 2, WHITE, TRANSPARENT);
+    let linear = pixels(&downscale(&coarse, 6, 6, 4, 4, ScaleMode::Linear));
+    assert_eq!(&linear[..4], &[[255; 4], [85; 4], [85; 4], [255; 4]]);
+    assert_eq!(linear[5], [142; 4]);
+
+    // Fully transparent areas stay fully transparent
+    assert_eq!(downscale(&[0; 64], 4, 4, 2, 2, ScaleMode::Linear), vec![0; 16]);
+    // The same size doesn't change anything
+    let unchanged = padding_fixture(3, 2)
+        .chunks_exact(4)
+        .flat_map(|pixel| vec![pixel[0].min(pixel[3]), pixel[1].min(pixel[3]), pixel[2].min(pixel[3]), pixel[3]])
+        .collect::<Vec<_>>();
+    assert_eq!(downscale(&unchanged, 3, 2, 3, 2, ScaleMode::Fast), unchanged);
+}
+
+
+#[test]
+fn test_fit_into() {
+    let image = vec![255; 4 * 272 * 64];
+    let (fitted, width, height) = fit_into(image.clone(), 272, 64, 136, 128, ScaleMode::Linear);
+    assert_eq!((width, height), (136, 32));
+    assert_eq!(fitted, vec![255; 4 * 136 * 32]);
+
+    let (fitted, width, height) = fit_into(vec![255; 4 * 100 * 160], 100, 160, 136, 128, ScaleMode::Fast);
+    assert_eq!((width, height), (80, 128));
+    assert_eq!(fitted.len(), 4 * 80 * 128);
+
+    // Images that already fit aren't touched
+    assert_eq!(fit_into(image, 136, 128, 136, 128, ScaleMode::Linear).1, 136);
+}
+
+
+#[test]
+fn test_place() {
+    // A 2x2 image with a different color in each pixel
+    let content: Vec<u8> = (1..=4u8)
+        .flat_map(|pixel| vec![pixel, pixel, pixel, 255])
+        .collect();
+    let pixel = |image: &[u8], width: usize, x: usize, y: usize| image[(y * width + x) * 4];
+
+    // Without an offset, it's the same as enlarge_to
+    assert_eq!(place(&content, 2, 2, 5, 4, 0, 0), enlarge_to(&content, 2, 2, 5, 4));
+
+    let moved = place(&content, 2, 2, 4, 4, 1, -1);
+    assert_eq!(moved.len(), 4 * 4 * 4);
+    assert_eq!(pixel(&moved, 4, 2, 0), 1);
+    assert_eq!(pixel(&moved, 4, 3, 0), 2);
+    assert_eq!(pixel(&moved, 4, 2, 1), 3);
+    assert_eq!(pixel(&moved, 4, 3, 1), 4);
+    assert_eq!(moved.iter().filter(|subpixel| **subpixel != 0).count(), 4 * 4);
+
+    // Parts outside of the canvas are cut off
+    let cropped = place(&content, 2, 2, 2, 2, -1, 1);
+    assert_eq!(cropped, vec![
+        0, 0, 0, 0, 0, 0, 0, 0,
+        2, 2, 2, 255, 0, 0, 0, 0,
+    ]);
+
+    // Larger images are cropped around their center
+    assert_eq!(place(&content, 2, 2, 1, 1, 0, 0), vec![4, 4, 4, 255]);
+    assert!(place(&content, 2, 2, 2, 2, 5, 0).iter().all(|subpixel| *subpixel == 0));
+}
+
+
+#[test]
+fn test_anchor_to() {
+    // A flag-sized image (4x2) with a transparent line below it, like the one after waving
+    let mut content: Vec<u8> = (1..=8u8)
+        .flat_map(|pixel| vec![pixel, pixel, pixel, 255])
+        .collect();
+    content.extend_from_slice(&[0; 4 * 4]);
+    let pixel = |image: &[u8], x: usize, y: usize| image[(y * 6 + x) * 4];
+    let visible_lines = |image: &[u8]| (0..8)
+        .filter(|y| (0..6).any(|x| image[(y * 6 + x) * 4 + 3] != 0))
+        .collect::<Vec<_>>();
+
+    let center = anchor_to(&content, 4, 3, 6, 8, Anchor::Center, 2);
+    assert_eq!(center, enlarge_to(&content, 4, 3, 6, 8));
+    assert_eq!(visible_lines(&center), vec![2, 3]);
+
+    // The empty line doesn't count, so the lowest visible line is right above the margin
+    let bottom = anchor_to(&content, 4, 3, 6, 8, Anchor::Bottom, 2);
+    assert_eq!(bottom.len(), 6 * 8 * 4);
+    assert_eq!(visible_lines(&bottom), vec![4, 5]);
+    assert_eq!(pixel(&bottom, 1, 4), 1);
+    assert_eq!(pixel(&bottom, 4, 5), 8);
+    assert_eq!(visible_lines(&anchor_to(&content, 4, 3, 6, 8, Anchor::Bottom, 0)), vec![6, 7]);
+
+    let top = anchor_to(&content, 4, 3, 6, 8, Anchor::Top, 1);
+    assert_eq!(visible_lines(&top), vec![1, 2]);
+    assert_eq!(pixel(&top, 1, 1), 1);
+
+    // A margin that's too large doesn't move the image out of the canvas
+    assert_eq!(visible_lines(&anchor_to(&content, 4, 3, 6, 8, Anchor::Bottom, 20)), vec![0, 1]);
+    assert_eq!(visible_lines(&anchor_to(&content, 4, 3, 6, 8, Anchor::Top, 20)), vec![6, 7]);
+}
+
+
+#[test]
+fn test_fill_background() {
+    let mut image = vec![
+        // Transparent
+        0, 0, 0, 0,
+        // Opaque
+        10, 20, 30, 255,
+        // Half-transparent (premultiplied) white
+        128, 128, 128, 128,
+    ];
+    fill_background(&mut image, [0, 0, 255, 255]);
+    assert_eq!(image, vec![
+        0, 0, 255, 255,
+        10, 20, 30, 255,
+        128, 128, 255, 255,
+    ]);
+
+    let mut image = vec![0, 0, 0, 0];
+    fill_background(&mut image, [255, 0, 0, 128]);
+    assert_eq!(image, vec![128, 0, 0, 128]);
+}
+
+
+#[test]
+fn test_compose_on_background() {
+    let image = vec![
+        // Transparent
+        0, 0, 0, 0,
+        // Opaque
+        10, 20, 30, 255,
+        // Half-transparent red
+        255, 0, 0, 128,
+    ];
+    assert_eq!(compose_on_background(&image, 3, 1, [255, 255, 255, 255]), vec![
+        255, 255, 255, 255,
+        10, 20, 30, 255,
+        255, 127, 127, 255,
+    ]);
+    assert_eq!(compose_on_background(&image, 3, 1, [0, 0, 0, 255]), vec![
+        0, 0, 0, 255,
+        10, 20, 30, 255,
+        128, 0, 0, 255,
+    ]);
+
+    // The color of a transparent background doesn't matter
+    assert_eq!(compose_on_background(&image, 3, 1, [0, 255, 0, 0]), image);
+
+    // With straight alpha on both sides, blending the colors by the image's alpha would give
+    // (127, 0, 128) here, which is too red
+    assert_eq!(compose_on_background(&[0, 0, 255, 128], 1, 1, [255, 0, 0, 128]), vec![85, 0, 170, 192]);
+}
+
+
+#[test]
+fn test_read_rgba() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("emoji_u1f914.png");
+
+    let pixels: Vec<u8> = vec![255, 0, 0, 128, 0, 0, 255, 255];
+    let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 1);
+    encoder.set_color(RGBA);
+    encoder.set_depth(Eight);
+    encoder.write_header().unwrap().write_image_data(&pixels).unwrap();
+    assert_eq!(read_rgba(&path).unwrap(), (2, 1, pixels.clone()));
+
+    // oxipng might turn it into an image with a palette
+    let optimized = optimize_png(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::write(&path, optimized).unwrap();
+    assert_eq!(read_rgba(&path).unwrap(), (2, 1, pixels));
+
+    let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 1, 1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(Eight);
+    encoder.write_header().unwrap().write_image_data(&[64]).unwrap();
+    assert_eq!(read_rgba(&path).unwrap(), (1, 1, vec![64, 64, 64, 255]));
+}
+
+
+#[test]
+fn test_annotate() {
+    let (width, height, render_width) = (12, 10, 8);
+    let mut image = vec![0; 4 * width as usize * height as usize];
+    annotate(&mut image, width, height, render_width, "");
+    let pixel = |image: &[u8], x: u32, y: u32| {
+        let start = (y * width + x) as usize * 4;
+        [image[start], image[start + 1], image[start + 2], image[start + 3]]
+    };
+
+    // The character box
+    for x in 0..width {
+        assert_eq!(pixel(&image, x, 0), CHARACTER_BOX_COLOR);
+        assert_eq!(pixel(&image, x, height - 1), CHARACTER_BOX_COLOR);
+    }
+    for y in 0..height {
+        assert_eq!(pixel(&image, 0, y), CHARACTER_BOX_COLOR);
+        assert_eq!(pixel(&image, width - 1, y), CHARACTER_BOX_COLOR);
+    }
+    // The render box (centered, from x = 2 to x = 9)
+    for y in 1..height - 1 {
+        assert_eq!(pixel(&image, 2, y), RENDER_BOX_COLOR);
+        assert_eq!(pixel(&image, 9, y), RENDER_BOX_COLOR);
+    }
+    // The rest stays untouched
+    assert_eq!(pixel(&image, 1, 1), [0, 0, 0, 0]);
+    assert_eq!(pixel(&image, 5, 5), [0, 0, 0, 0]);
+    assert_eq!(pixel(&image, 10, 8), [0, 0, 0, 0]);
+}
+
+#[test]
+fn test_annotate_label() {
+    let (width, height) = (10, 20);
+    let mut image = vec![0; 4 * width as usize * height as usize];
+    // Only one of the two words fits into a line
+    annotate(&mut image, width, height, width, "1 _");
+    let pixel = |x: u32, y: u32| {
+        let start = (y * width + x) as usize * 4;
+        [image[start], image[start + 1], image[start + 2], image[start + 3]]
+    };
+
+    // The "1" in the first line
+    assert_eq!(pixel(2, 2), [0, 0, 0, 0]);
+    assert_eq!(pixel(3, 2), LABEL_COLOR);
+    assert_eq!(pixel(2, 3), LABEL_COLOR);
+    assert_eq!(pixel(2, 6), LABEL_COLOR);
+    assert_eq!(pixel(4, 6), LABEL_COLOR);
+    // The "_" at the bottom of the second line
+    assert_eq!(pixel(2, 8), [0, 0, 0, 0]);
+    assert_eq!(pixel(2, 12), LABEL_COLOR);
+    assert_eq!(pixel(4, 12), LABEL_COLOR);
+    assert_eq!(pixel(5, 12), [0, 0, 0, 0]);
+}
+
+#[test]
+fn test_png_dimensions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("emoji_u1f914.png");
+
+    std::fs::write(&path, pixels_to_png(&vec![0; (CHARACTER_WIDTH * RENDER_AND_CHARACTER_HEIGHT * 4) as usize]).unwrap()).unwrap();
+    assert_eq!(png_dimensions(&path).unwrap(), (CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT));
+
+    tiny_skia::Pixmap::new(72, 64).unwrap().save_png(&path).unwrap();
+    assert_eq!(png_dimensions(&path).unwrap(), (72, 64));
+
+    std::fs::write(&path, b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap();
+    assert_eq!(png_dimensions(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Truncated files
+    std::fs::write(&path, &PNG_HEADER[..]).unwrap();
+    assert_eq!(png_dimensions(&path).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert!(png_dimensions(&dir.path().join("missing.png")).is_err());
+}
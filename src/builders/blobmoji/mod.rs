@@ -27,74 +27,227 @@
 
 // Microsoft, Windows are trademarks of the Microsoft group of companies.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::fs::{copy, create_dir_all, File, remove_file, rename};
 use std::io::Write;
 use std::path::{PathBuf, Path};
-use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use clap::{App, Arg, ArgMatches, SubCommand};
 use itertools::Itertools;
-use pyo3::Python;
-use sha2::{Digest, Sha256};
-use sha2::digest::generic_array::GenericArray;
-use usvg::FitTo;
+use rayon::prelude::*;
 use tiny_skia::Pixmap;
 
-use crate::builder::{EmojiBuilder, PreparationResult};
-use crate::changes::{CheckError, FileHashes};
+use crate::attribution::{Attribution, ATTRIBUTION_FILE};
+use crate::licensing::{font_license, BundledFile};
+use crate::builder::{CacheStatus, EmojiBuilder, PreparationResult, SupportLevel};
+use crate::changes::{CheckError, FileHashes, HashAlgorithm, SourceHash};
+use crate::diagnostics::{Category, Diagnostic, DiagnosticsSink};
+use crate::configs::{ConfigError, SettingSource, Settings};
 use crate::emoji::Emoji;
+use crate::emoji_tables::EmojiTable;
 use crate::emoji_processor::EmojiProcessor;
 use crate::emoji_processors::reduce_colors::ReduceColors;
+use crate::short_ids::ShortIds;
 use crate::builders::blobmoji::error::BlobmojiError;
+use crate::builders::blobmoji::subset::Subset;
+use crate::builders::blobmoji::tweaks::{Tweak, Tweaks};
+use crate::builders::blobmoji::render_policy::{RenderParams, RenderPolicy};
+use crate::builders::blobmoji::size_budget::{LARGEST_GLYPHS, SizeBudget, SizeReport};
+use crate::builders::blobmoji::noto_emoji_utils::{CbdtMetrics, FontMetrics, FontSteps, GlyphOptions, PreparedEmojis, PythonSteps};
+use crate::builders::blobmoji::prebuilt_ttx::TtxMismatch;
+use crate::builders::blobmoji::flag_aliases::FlagAliases;
+use crate::builders::blobmoji::glyph_map::GlyphMap;
+use crate::builders::blobmoji::android_assets::AssetTable;
+use crate::builders::blobmoji::render_check::{RenderCheck, RenderStats};
+use crate::builders::blobmoji::quantize::{Quantization, QuantizationStats, Quantizer};
+use crate::builders::blobmoji::rendering::RenderBox;
+use crate::builders::blobmoji::ttx_compiler::TtxCompiler;
+use crate::builders::blobmoji::waveflag::WaveMask;
+use crate::builders::blobmoji::stages::{Fingerprint, Stage, StageStore};
+use crate::builders::blobmoji::image_utils::ScaleMode;
+use crate::builders::blobmoji::subprocess_steps::PythonMode;
+use crate::builders::blobmoji::hash_flush::HashFlush;
+pub use crate::builders::blobmoji::build_dir::{BuildDir, WorkDir};
 
 mod waveflag;
 /// The error type that can occur for the [Blobmoji] builder
 pub mod error;
 mod image_utils;
 mod noto_emoji_utils;
+mod subset;
+mod gsub;
+mod tweaks;
+mod render_policy;
+mod render_check;
+mod size_budget;
+mod prebuilt_ttx;
+mod dedup;
+mod hash_flush;
+mod flag_aliases;
+mod build_dir;
+mod rendering;
+mod ttx_compiler;
+mod glyph_map;
+mod quantize;
+mod stages;
+mod subprocess_steps;
+pub mod font_sequences;
+pub mod contact_sheet;
+pub mod android_assets;
 
 #[allow(dead_code)]
 /// Represents the configuration for the `Blobmoji` builder
 pub struct Blobmoji {
-    build_path: PathBuf,
-    hashes: FileHashes,
+    build_dir: BuildDir,
+    /// The hashes of the sources, shared with [Blobmoji::hash_flush]
+    hashes: Arc<FileHashes>,
+    /// Writes the hashes while the emojis are prepared
+    hash_flush: HashFlush,
     aliases: Option<PathBuf>,
+    /// Flags that use the glyph of another flag
+    flag_aliases: FlagAliases,
     render_only: bool,
     default_font: String,
     fontdb: usvg::fontdb::Database,
+    /// Fail the preparation of emojis whose text would be rendered with a fallback font
+    require_fonts: bool,
     waveflag: bool,
+    /// The offsets of the waved flags' columns (instead of the sine)
+    waveflag_mask: Option<WaveMask>,
+    /// How renders that are larger than the character are scaled down
+    scale_mode: ScaleMode,
     reduce_colors: Option<Box<ReduceColors>>,
-    build_win: bool
+    build_win: bool,
+    subset: Option<Subset>,
+    /// Whether the output path is the default one (and may get a `_subset` suffix)
+    default_output: bool,
+    tweaks: Option<Tweaks>,
+    /// How the different kinds of emojis are rendered
+    render_policy: RenderPolicy,
+    /// When a render is reported as fully transparent or black
+    render_check: RenderCheck,
+    size_budget: SizeBudget,
+    /// Reduce the colors of the images to a palette before they're optimized
+    quantizer: Option<Quantizer>,
+    quantization: QuantizationStats,
+    woff2: bool,
+    prune_stale: bool,
+    cbdt_metrics: CbdtMetrics,
+    /// Overrides of the template's ascent, descent and line gap
+    metrics: FontMetrics,
+    /// The number of images that add_glyphs.py reads at once (all of them if it's `None`)
+    chunk_size: Option<usize>,
+    keep_outlines: bool,
+    /// How the TTX file of the template is compiled to a font
+    ttx_compiler: TtxCompiler,
+    /// Where to additionally store annotated copies of the rendered emojis
+    debug_pngs: Option<PathBuf>,
+    /// A TTX file that is used instead of the output of `add_glyphs.py`
+    prebuilt_ttx: Option<PathBuf>,
+    /// Don't remove the working directories of the fonts after they have been built
+    keep_intermediate: bool,
+    /// Name the PNG files by the hash of their source and keep the older ones
+    /// (see [Blobmoji::generate_content_addressed_filename])
+    content_addressed_pngs: bool,
+    /// Also write the glyph map next to the fonts (see [GlyphMap])
+    glyph_map: bool,
+    /// Where to export the PNG files as Android assets (see [android_assets])
+    export_assets: Option<PathBuf>,
+    /// The metadata of the emoji table for the Android assets
+    asset_table: Option<AssetTable>,
+    /// The (Python) steps that build the fonts
+    font_steps: Box<dyn FontSteps>,
+    /// The codepoints with emoji variation sequences according to the emoji table (if there is one)
+    variation_bases: Option<HashSet<u32>>,
+    /// The short IDs of the emoji table (if there is one) for the manifest
+    short_ids: Option<Arc<ShortIds>>,
+    /// The licenses of the emojis for the name table and the attribution file
+    attribution: Attribution,
+    /// How often the render options (and thus the font database) have been set up
+    render_options_built: AtomicUsize,
+    /// How often an emoji has actually been rendered (i.e. not taken from the cache)
+    renders: AtomicUsize,
+    /// The checkpoints of the font's stages, so they aren't run again if their input is unchanged
+    stages: StageStore,
+    diagnostics: DiagnosticsSink
 }
 
 const WAVE_FACTOR: f32 = 0.1;
+/// The number of emojis that are prepared in one go by a thread in `prepare_batch`
+const PREPARE_CHUNK_SIZE: usize = 16;
+/// From this number of emojis on, building them in chunks (`--chunk-size`) is suggested
+const LARGE_SET_EMOJIS: usize = 10_000;
 
-const HASHES: &str = "hashes.csv";
-const TMPL_TTX_TMPL: &str = "font.tmpl.ttx.tmpl";
-const TMPL_TTX: &str = "font.tmpl.ttx";
-const TMPL_TTF: &str = "font.tmpl.ttf";
-const TTF: &str = "font.ttf";
-const TTF_WITH_PUA: &str = "font.ttf-with-pua";
-const TTF_WITH_PUA_VARSE1: &str = "font.ttf-with-pua-varse1";
-const PNG_DIR: &str = "png";
+/// The maximum length of a file name on most filesystems (e.g. ext4 and NTFS)
+const MAX_FILE_NAME_LENGTH: usize = 255;
+/// The number of bytes of the source's hash in content-addressed file names
+const CONTENT_ADDRESS_BYTES: usize = 4;
 
 const TMPL_TTX_TMPL_CONTENT: &[u8] = include_bytes!("noto-emoji/NotoColorEmoji.tmpl.ttx.tmpl");
 
+/// Where [Blobmoji::build_font_to] writes a font to
+enum FontOutput<'w> {
+    /// A file, next to which the WOFF2 file and the glyph map are written as well
+    File(PathBuf),
+    /// Only the font itself is written (see [EmojiBuilder::build_to_writer])
+    Writer(&'w mut dyn Write),
+}
+
+impl Display for FontOutput<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontOutput::File(path) => write!(f, "{:?}", path),
+            FontOutput::Writer(_) => write!(f, "the output stream")
+        }
+    }
+}
+
 impl EmojiBuilder for Blobmoji {
     type Err = BlobmojiError;
     /// An emoji that's "prepared" here is (currently) a path (to the saved PNG file)
     /// and a hash that represents the source SVG
     type PreparedEmoji = (
         PathBuf,
-        Result<GenericArray<u8, <Sha256 as Digest>::OutputSize>, CheckError>
+        Result<SourceHash, CheckError>
     );
 
     fn new(
         build_path: PathBuf,
         matches: Option<ArgMatches>,
     ) -> Result<Box<Self>, Self::Err> {
-        let hash_path = build_path.join(String::from(HASHES));
-        let hashes = FileHashes::from_path(hash_path.as_path());
+        let settings = matches.as_ref().map(|matches| {
+            let mut settings = Settings::new();
+            settings.load_matches(matches, &[
+                "aliases", "render_only", "default_font", "waveflag", "waveflag_mask", "ttx_tmpl", "win10", "subset",
+                "tweaks", "render_policy", "max_png_bytes", "enforce_size_budget", "quantize_colors", "quantize_dither", "quantize_min_psnr",
+                "woff2", "prune_stale", "cbdt_metrics", "keep_glyph_outlines", "debug_pngs", "prebuilt_ttx",
+                "flag_aliases", "keep_intermediate", "ttx_compiler", "content_addressed_pngs", "glyph_map",
+                "export_assets", "transparent_threshold", "black_threshold", "ascent", "descent", "line_gap", "require_fonts",
+                "hash_algo", "from_stage", "fast_downscaling", "python_mode", "chunk_size", "cache_key", "max_cache_namespaces"
+            ]);
+            settings.load_env(std::env::vars());
+            settings
+        });
+
+        let build_dir = BuildDir::open(build_path)?;
+        // Each cache key (e.g. the fingerprint of a pack) has its own hashes and images
+        let build_dir = match settings.as_ref().and_then(|settings| settings.get("cache_key")) {
+            Some(cache_key) if !BuildDir::is_valid_namespace(cache_key) =>
+                return Err(ConfigError::InvalidValue(String::from("cache_key"), cache_key.to_string()).into()),
+            Some(cache_key) => {
+                info!("Using the cache namespace {}", cache_key);
+                build_dir.with_namespace(cache_key)?
+            },
+            None => build_dir
+        };
+        if let Some(max) = settings.as_ref().and_then(|settings| settings.get("max_cache_namespaces")) {
+            let max = max.parse::<usize>()
+                .map_err(|_| ConfigError::InvalidValue(String::from("max_cache_namespaces"), max.to_string()))?;
+            build_dir.remove_old_namespaces(max)?;
+        }
+        let hashes = FileHashes::from_path(build_dir.hashes());
         let hashes = match hashes {
             Ok(hashes) => hashes,
             Err(error) => {
@@ -109,7 +262,7 @@ impl EmojiBuilder for Blobmoji {
             }
         };
 
-        let ttx_tmpl_path = build_path.join(TMPL_TTX_TMPL);
+        let ttx_tmpl_path = build_dir.ttx_tmpl_tmpl();
 
         if !&ttx_tmpl_path.exists() {
             info!("Creating new TTX template");
@@ -119,30 +272,35 @@ impl EmojiBuilder for Blobmoji {
             info!("Using existing TTX template");
         }
 
-        // Create the PNG directory if it doesn't exist
-        let png_dir = build_path.join(PNG_DIR);
-        if !png_dir.exists() {
-            create_dir_all(png_dir)?;
-        };
-
         let mut fontdb = usvg::fontdb::Database::new();
         fontdb.load_system_fonts();
 
 
         // Collect CLI arguments
-        if let Some(matches) = &matches {
-            let aliases = match matches.value_of("aliases") {
-                None => None,
-                Some(aliases) => PathBuf::from_str(aliases).ok()
+        if let (Some(matches), Some(settings)) = (&matches, &settings) {
+            let aliases = settings.get_path("aliases")?;
+            let flag_aliases = match settings.get_path("flag_aliases")? {
+                Some(flag_aliases) => FlagAliases::from_file(flag_aliases)?,
+                None => FlagAliases::default()
             };
 
-            let render_only = matches.is_present("render_only");
+            let render_only = settings.get_bool("render_only")?;
 
-            let default_font = String::from(matches.value_of("default_font").unwrap_or("cursive"));
+            let default_font = String::from(settings.get("default_font").unwrap_or("cursive"));
+            let require_fonts = settings.get_bool("require_fonts")?;
 
             let additional_fonts = matches.values_of_os("additional_fonts");
 
-            let waveflag = matches.is_present("waveflag");
+            let waveflag = settings.get_bool("waveflag")?;
+            let waveflag_mask = match settings.get_path("waveflag_mask")? {
+                Some(waveflag_mask) => Some(WaveMask::from_file(&waveflag_mask)?),
+                None => None
+            };
+            let scale_mode = if settings.get_bool("fast_downscaling")? {
+                ScaleMode::Fast
+            } else {
+                ScaleMode::Linear
+            };
 
             let reduce_colors = {
                 let args = ReduceColors::cli_arguments(&Self::sub_command().p.global_args);
@@ -171,9 +329,9 @@ impl EmojiBuilder for Blobmoji {
             };
 
             // Copy the predefined TTX_TMPL file to the destination
-            match matches.value_of("ttx_tmpl") {
+            match settings.get_path("ttx_tmpl")? {
                 // TODO: Don't unwrap
-                Some(ttx_tmpl) => std::fs::copy(PathBuf::from(ttx_tmpl), &ttx_tmpl_path).unwrap(),
+                Some(ttx_tmpl) => std::fs::copy(ttx_tmpl, &ttx_tmpl_path).unwrap(),
                 None => 0
             };
 
@@ -201,114 +359,320 @@ impl EmojiBuilder for Blobmoji {
             }?;
 
             // Check whether we want to build a Windows-compatible font as well
-            let build_win = matches.is_present("win10");
+            let build_win = settings.get_bool("win10")?;
+
+            // Only build a font with a certain subset of the emojis
+            let subset = match settings.get("subset") {
+                Some(subset) => Some(Subset::parse(subset)?),
+                None => None
+            };
+
+            // Manual adjustments for single emojis
+            let tweaks = match settings.get_path("tweaks")? {
+                Some(path) => {
+                    let tweaks = Tweaks::from_file(&path)?;
+                    info!("Loaded {} tweak(s) from {:?}", tweaks.len(), path);
+                    Some(tweaks)
+                },
+                None => None
+            };
+
+            // Custom rendering parameters for kinds of emojis
+            let render_policy = match settings.get_path("render_policy")? {
+                Some(render_policy) => RenderPolicy::from_file(render_policy)?,
+                None => RenderPolicy::default()
+            };
+
+            // Warn about (or reject) emojis with large PNG files
+            let size_budget = SizeBudget {
+                max_bytes: match settings.get("max_png_bytes") {
+                    Some(max_bytes) => Some(max_bytes.parse().map_err(|_| ConfigError::InvalidValue(
+                        String::from("max_png_bytes"),
+                        max_bytes.to_string()
+                    ))?),
+                    None => None
+                },
+                enforce: settings.get_bool("enforce_size_budget")?
+            };
+
+            // Lossy compression with a palette
+            let quantize_colors: u16 = match settings.get("quantize_colors") {
+                Some(colors) => colors.parse().ok()
+                    .filter(|colors| *colors <= 256)
+                    .ok_or_else(|| ConfigError::InvalidValue(String::from("quantize_colors"), colors.to_string()))?,
+                None => 0
+            };
+            let quantizer = if quantize_colors > 0 {
+                let mut quantizer = Quantizer::new(quantize_colors);
+                quantizer.dither = settings.get_bool("quantize_dither")?;
+                if let Some(psnr) = settings.get("quantize_min_psnr") {
+                    quantizer.min_psnr = psnr.parse().map_err(|_| ConfigError::InvalidValue(
+                        String::from("quantize_min_psnr"),
+                        psnr.to_string()
+                    ))?;
+                }
+                Some(quantizer)
+            } else {
+                None
+            };
+
+            let threshold = |key: &str, default: f64| match settings.get(key) {
+                Some(value) => value.parse()
+                    .ok()
+                    .filter(|threshold: &f64| (0.0..=1.0).contains(threshold))
+                    .ok_or_else(|| ConfigError::InvalidValue(String::from(key), value.to_string())),
+                None => Ok(default)
+            };
+            let render_check = RenderCheck {
+                transparent: threshold("transparent_threshold", RenderCheck::default().transparent)?,
+                black: threshold("black_threshold", RenderCheck::default().black)?,
+            };
+
+            // Additionally compress the fonts to WOFF2
+            let woff2 = settings.get_bool("woff2")?;
+
+            // Delete PNG files of emojis that are not part of the emoji set (anymore)
+            let prune_stale = settings.get_bool("prune_stale")?;
+
+            // How the bitmaps are embedded in the font
+            let cbdt_metrics = match settings.get("cbdt_metrics") {
+                Some(metrics) => metrics.parse()?,
+                None => CbdtMetrics::default()
+            };
+            let metric = |key: &str| -> Result<Option<i32>, ConfigError> {
+                settings.get(key)
+                    .map(|value| value.parse().map_err(|_| ConfigError::InvalidValue(String::from(key), value.to_string())))
+                    .transpose()
+            };
+            let metrics = FontMetrics {
+                ascent: metric("ascent")?,
+                descent: metric("descent")?,
+                line_gap: metric("line_gap")?,
+            };
+            metrics.validate()?;
+            let chunk_size = match settings.get("chunk_size") {
+                Some(chunk_size) => match chunk_size.parse::<usize>() {
+                    Ok(size) if size > 0 => Some(size),
+                    _ => return Err(ConfigError::InvalidValue(String::from("chunk_size"), chunk_size.to_string()).into())
+                },
+                None => None
+            };
+            let keep_outlines = settings.get_bool("keep_glyph_outlines")?;
+            let ttx_compiler = match settings.get("ttx_compiler") {
+                Some(compiler) => compiler.parse()?,
+                None => TtxCompiler::default()
+            };
+            let keep_intermediate = settings.get_bool("keep_intermediate")?;
+            let from_stage = match settings.get("from_stage") {
+                Some(from_stage) => Some(from_stage.parse()?),
+                None => None
+            };
+            let stages = StageStore::open(&build_dir, from_stage);
+            let python_mode = match settings.get("python_mode") {
+                Some(python_mode) => python_mode.parse()?,
+                None => PythonMode::detect()
+            };
+            info!("Running the Python steps {}", python_mode);
+            let font_steps = python_mode.font_steps()?;
+            let content_addressed_pngs = settings.get_bool("content_addressed_pngs")?;
+            let glyph_map = settings.get_bool("glyph_map")?;
+            let export_assets = settings.get_path("export_assets")?;
+
+            // Switching the algorithm means that all emojis are rendered again (once)
+            let hash_algo: HashAlgorithm = match settings.get("hash_algo") {
+                Some(hash_algo) => hash_algo.parse()?,
+                None => HashAlgorithm::default()
+            };
+            let mut hashes = hashes;
+            hashes.use_algorithm(hash_algo);
+            let hashes = Arc::new(hashes);
+            let hash_flush = HashFlush::new(Arc::clone(&hashes), build_dir.hashes());
+
+            // Annotated images for debugging the rendering
+            let debug_pngs = settings.get_path("debug_pngs")?;
+            if let Some(debug_pngs) = &debug_pngs {
+                create_dir_all(debug_pngs)?;
+            }
+
+            // A customized TTX file replaces the template and add_glyphs.py
+            let prebuilt_ttx = settings.get_path("prebuilt_ttx")?;
+            if let Some(prebuilt_ttx) = &prebuilt_ttx {
+                if aliases.is_some() || !flag_aliases.is_empty() {
+                    return Err(ConfigError::InvalidValue(
+                        String::from("prebuilt_ttx"),
+                        format!("{} (can't be combined with aliases as they are added by add_glyphs.py)", prebuilt_ttx.to_string_lossy())
+                    ).into());
+                }
+                if !metrics.is_empty() {
+                    return Err(ConfigError::InvalidValue(
+                        String::from("prebuilt_ttx"),
+                        format!("{} (can't be combined with overridden metrics as they are set by add_glyphs.py)", prebuilt_ttx.to_string_lossy())
+                    ).into());
+                }
+            }
 
             Ok(Box::new(Blobmoji {
-                build_path,
+                build_dir,
                 hashes,
+                hash_flush,
                 aliases,
+                flag_aliases,
                 render_only,
                 default_font,
                 fontdb,
+                require_fonts,
                 waveflag,
+                waveflag_mask,
+                scale_mode,
                 reduce_colors,
-                build_win
+                build_win,
+                subset,
+                default_output: true,
+                tweaks,
+                render_policy,
+                render_check,
+                size_budget,
+                quantizer,
+                quantization: QuantizationStats::default(),
+                woff2,
+                prune_stale,
+                cbdt_metrics,
+                metrics,
+                chunk_size,
+                keep_outlines,
+                ttx_compiler,
+                debug_pngs,
+                prebuilt_ttx,
+                keep_intermediate,
+                content_addressed_pngs,
+                glyph_map,
+                export_assets,
+                asset_table: None,
+                font_steps,
+                variation_bases: None,
+                short_ids: None,
+                attribution: Attribution::default(),
+                render_options_built: AtomicUsize::new(0),
+                renders: AtomicUsize::new(0),
+                stages,
+                diagnostics: DiagnosticsSink::default()
             }))
         } else {
+            let stages = StageStore::open(&build_dir, None);
+            let hashes = Arc::new(hashes);
+            let hash_flush = HashFlush::new(Arc::clone(&hashes), build_dir.hashes());
             Ok(Box::new(Blobmoji {
-                build_path,
+                build_dir,
                 hashes,
+                hash_flush,
                 aliases: None,
+                flag_aliases: FlagAliases::default(),
                 render_only: false,
                 default_font: String::from("cursive"),
                 fontdb,
+                require_fonts: false,
                 waveflag: false,
+                waveflag_mask: None,
+                scale_mode: ScaleMode::default(),
                 reduce_colors: None,
-                build_win: false
+                build_win: false,
+                subset: None,
+                default_output: true,
+                tweaks: None,
+                render_policy: RenderPolicy::default(),
+                render_check: RenderCheck::default(),
+                size_budget: SizeBudget::default(),
+                quantizer: None,
+                quantization: QuantizationStats::default(),
+                woff2: false,
+                prune_stale: false,
+                cbdt_metrics: CbdtMetrics::default(),
+                metrics: FontMetrics::default(),
+                chunk_size: None,
+                keep_outlines: false,
+                ttx_compiler: TtxCompiler::default(),
+                debug_pngs: None,
+                prebuilt_ttx: None,
+                keep_intermediate: false,
+                content_addressed_pngs: false,
+                glyph_map: false,
+                export_assets: None,
+                asset_table: None,
+                font_steps: Box::new(PythonSteps),
+                variation_bases: None,
+                short_ids: None,
+                attribution: Attribution::default(),
+                render_options_built: AtomicUsize::new(0),
+                renders: AtomicUsize::new(0),
+                stages,
+                diagnostics: DiagnosticsSink::default()
             }))
         }
     }
 
     fn finish(&mut self, emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>) -> Result<(), Self::Err> {
-        self.store_prepared(&emojis)
+        self.store_prepared(&emojis)?;
+        self.export_assets(&emojis)
     }
 
-    fn prepare(&self, emoji: &Emoji) -> PreparationResult<Self::PreparedEmoji, Self:: Err> {
-        info!("Preparing {}", emoji);
-
-        // Where to store the image?
-        let path = self.build_path
-            .join(PNG_DIR)
-            .join(PathBuf::from(Blobmoji::generate_filename(emoji)));
-
-        if let Err(err) = self.hashes.check(emoji) {
-            warn!("Hash of an emoji ({}) could not be checked: {:?}", emoji, err);
+    /// Emojis without an SVG file can't be rendered and emojis with very long sequences would
+    /// have PNG file names that are longer than most filesystems allow
+    fn supports_emoji(&self, emoji: &Emoji) -> SupportLevel {
+        let filename = if self.content_addressed_pngs {
+            Blobmoji::generate_content_addressed_filename(emoji, &[0; CONTENT_ADDRESS_BYTES])
+        } else {
+            Blobmoji::generate_filename(emoji)
+        };
+        if emoji.svg_path.is_none() {
+            SupportLevel::Unsupported(String::from("no SVG file"))
+        } else if filename.len() > MAX_FILE_NAME_LENGTH {
+            SupportLevel::Unsupported(format!("PNG file name longer than {} bytes", MAX_FILE_NAME_LENGTH))
+        } else {
+            SupportLevel::Full
         }
+    }
 
-        // Only render if sth. has changed or if it isn't available
-        if (!self.hashes.check(emoji).unwrap_or(false)) || (!path.exists()) {
-            // Render the SVG to an appropriate, but unpadded size
-            if let Some((rendered, (width, height))) = self.render_svg(emoji) {
-                // Wave the flag if it is one and if we're supposed to.
-                let (rendered, width, height) = if self.waveflag && emoji.is_flag() {
-                    waveflag::waveflag(
-                        rendered.data(),
-                        width as usize,
-                        height,
-                        (height as f32 * WAVE_FACTOR) as usize)
-                } else {
-                    (rendered.data().to_vec(), width, height)
-                };
-                // The rendering already accounted for the case that this is a flag and that the
-                // image will get taller.
-
-                // Add the padding
-                let mut image = image_utils::enlarge_to(
-                    &rendered,
-                    width,
-                    height,
-                    CHARACTER_WIDTH,
-                    RENDER_AND_CHARACTER_HEIGHT,
-                );
-
-                // Oxipng needs to work on PNGs and not raw pixels, so it's encoded here.
-                // It also makes sense to do quantization at this step, if it is performed at all
-                // (which is only the case for the GPL-version which is currently not public)
-                let encoded = match self.quantize_to_png(&emoji, &mut image) {
-                    Some(quantized) => quantized,
-                    None => image_utils::pixels_to_png(&image).unwrap()
-                };
+    fn set_diagnostics(&mut self, diagnostics: DiagnosticsSink) {
+        self.diagnostics = diagnostics;
+    }
 
-                // Lossless compression
-                let optimized = match image_utils::optimize_png(&encoded) {
-                    Ok(optimized) => optimized,
-                    Err(e) => {
-                        warn!("Error in optimizing {:?}: {:?}", emoji, e);
-                        encoded
-                    },
-                };
+    /// Takes the codepoints with emoji variation sequences from the table.
+    /// Tables without `emoji-variation-sequences.txt` don't have any, in which case the defaults
+    /// are kept (see `noto_emoji_utils::DEFAULT_VS_BASES`).
+    fn set_table(&mut self, table: &EmojiTable) {
+        let bases = table.emoji_variation_bases();
+        if !bases.is_empty() {
+            self.variation_bases = Some(bases.clone());
+        }
+        self.short_ids = Some(table.short_ids());
+        if self.export_assets.is_some() {
+            self.asset_table = Some(AssetTable::from_table(table));
+        }
+    }
 
-                // Save it
-                image_utils::write_png(&self.build_path, emoji, optimized).unwrap();
+    fn set_attribution(&mut self, attribution: &Attribution) {
+        self.attribution = attribution.clone();
+    }
 
-                // Save the hash value of the source (to prevent unnecessary re-renders)
-                let hash = FileHashes::hash(emoji);
+    /// An explicit output path is used as it is, even for a subset
+    fn set_output_source(&mut self, source: &SettingSource) {
+        self.default_output = *source == SettingSource::Default;
+    }
 
-                Ok(((path, hash), None))
-            } else {
-                error!("Couldn't render Emoji {}", emoji);
-                Err(BlobmojiError::UnknownError)
-            }
-        } else {
-            info!("Emoji is already available");
-            let hash = &self.hashes[emoji];
-            // As the hash values can be assumed to be generated just like above,
-            // We can safely assume their size to be like this
-            let hash: GenericArray<u8, <Sha256 as Digest>::OutputSize> = GenericArray::clone_from_slice(hash);
-            Ok(((path, Ok(hash)), None))
-        }
+    fn prepare(&self, emoji: &Emoji) -> PreparationResult<Self::PreparedEmoji, Self:: Err> {
+        self.prepare_with(emoji, &self.render_options())
     }
 
+    /// Prepares the emojis in chunks, with the render options (including the font database)
+    /// only being set up once for all of them.
+    fn prepare_batch<'e>(&self, emojis: &[&'e Emoji]) -> HashMap<&'e Emoji, PreparationResult<Self::PreparedEmoji, Self::Err>> {
+        let opt = self.render_options();
+        emojis.par_chunks(PREPARE_CHUNK_SIZE)
+            .flat_map(|chunk| chunk.iter()
+                .map(|emoji| (*emoji, self.prepare_with(emoji, &opt)))
+                .collect::<Vec<_>>())
+            .collect()
+    }
 
     // TODO: Implement
     fn build(
@@ -316,37 +680,46 @@ impl EmojiBuilder for Blobmoji {
         emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>,
         output_file: PathBuf,
         ) -> Result<(), Self::Err> {
-        assert!(!emojis.is_empty());
-
-        self.store_prepared(&emojis)?;
+        self.build_to(emojis, FontOutput::File(output_file))
+    }
 
-        if !self.render_only {
-            // Normal
-            self.build_font(&emojis, &output_file, false);
-            // For Windows 10 support
-            let mut output_file_stem_windows = output_file.file_stem().unwrap_or_default().to_os_string();
-            output_file_stem_windows.push("_win");
-            let output_file_windows = output_file
-                .with_file_name(output_file_stem_windows)
-                .with_extension(output_file.extension().unwrap_or_default());
-            self.build_font(&emojis, &output_file_windows, true);
+    /// Only the normal font is built, without any of the files that are usually written next
+    /// to it (i.e. the WOFF2 file, the glyph map and the licenses)
+    fn build_to_writer(
+        &mut self,
+        emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>,
+        writer: &mut dyn Write,
+    ) -> Result<(), Self::Err> {
+        if self.build_win {
+            return Err(ConfigError::InvalidValue(
+                String::from("win10"),
+                String::from("true (only one font can be written to a stream)")
+            ).into());
         }
-
-        Ok(())
+        if self.woff2 || self.glyph_map {
+            warn!("Only the font is written to the stream, without the WOFF2 file and the glyph map");
+        }
+        // The scripts must not mix their messages into the font
+        self.font_steps.redirect_stdout()?;
+        self.build_to(emojis, FontOutput::Writer(writer))
     }
 
     fn undo(&self,
             emoji: &Emoji,
             prepared: Result<Self::PreparedEmoji, Self::Err>
         )  -> Result<Result<Self::PreparedEmoji, Self::Err>, Self::Err> {
-        if prepared.is_ok() {
-            // Delete the image. It will be overwritten the next time,
-            // but the building scripts might still use it
-            let filename = Blobmoji::generate_filename(emoji);
-            let path = self.build_path
-                .join(PNG_DIR)
-                .join(&PathBuf::from(filename));
-            std::fs::remove_file(path)?;
+        match prepared {
+            // Content-addressed images are only used through the manifest, so they can stay
+            Ok((_, Ok(_))) if self.content_addressed_pngs => (),
+            // Keep the image (together with the hash of its source), so it can be restored if
+            // the emoji is prepared again without any changes.
+            // It must not stay in the PNG directory as the building scripts would use it.
+            Ok((path, Ok(hash))) => {
+                create_dir_all(self.build_dir.undo_dir())?;
+                rename(path, self.build_dir.undo_png(emoji, &hash))?;
+            },
+            Ok((path, Err(_))) => std::fs::remove_file(path)?,
+            Err(_) => ()
         }
         // When it comes to the hash-saving part, this emoji will be ignored
         // (unless it has been re-rendered until then)
@@ -389,15 +762,78 @@ impl EmojiBuilder for Blobmoji {
                 .required(false)
                 .value_name("FILE/DIR")
                 .multiple(true))
+            .arg(Arg::with_name("require_fonts")
+                .long("require-fonts")
+                .help("Fail to prepare emojis whose text uses a font that isn't available")
+                .long_help("Fail to prepare emojis whose text uses a font that isn't available. \
+                Without this, the text falls back to another font (e.g. --default_font), which is only reported as a warning. \
+                Fonts can be added with --font_files.")
+                .takes_value(false)
+                .required(false))
             .arg(Arg::with_name("waveflag")
                 .short("w")
                 .long("waveflag")
                 .help("Enable if the flags should get a wavy appearance.")
                 .takes_value(false)
                 .required(false))
+            .arg(Arg::with_name("waveflag_mask")
+                .long("waveflag-mask")
+                .help("A grayscale PNG file that defines how far each column of a waved flag is moved up")
+                .long_help("A grayscale PNG file that defines how far each column of a waved flag is moved up \
+                (black: not at all, white: the whole height of the wave) instead of a sine. \
+                It's stretched to the width of the flag and the values of each column are averaged, \
+                so it may have any size. Only used with --waveflag.")
+                .takes_value(true)
+                .required(false)
+                .value_name("FILE"))
+            .arg(Arg::with_name("fast_downscaling")
+                .long("fast-downscaling")
+                .help("Average the sRGB values when a render has to be scaled down")
+                .long_help("Average the sRGB values when a render is larger than the character and has to be scaled down. \
+                This is faster, but darkens antialiased edges; \
+                by default, the colors are averaged in linear light.")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("python_mode")
+                .long("python-mode")
+                .help("Run the Python steps in the embedded interpreter or with python3")
+                .long_help("Run the Python steps (add_glyphs.py, emoji_builder.py, etc.) in the embedded interpreter \
+                or in python3 processes with the bundled scripts. \
+                By default, the embedded interpreter is used if it can be initialized and finds fontTools.")
+                .takes_value(true)
+                .possible_values(&["embedded", "subprocess"])
+                .required(false))
+            .arg(Arg::with_name("chunk_size")
+                .long("chunk-size")
+                .help("The number of images that add_glyphs.py reads at once")
+                .long_help("The number of images that add_glyphs.py reads at once (by default all of them). \
+                Smaller chunks limit the memory that's needed for very large emoji sets; \
+                the font is the same regardless of the chunk size.")
+                .takes_value(true)
+                .required(false)
+                .value_name("N"))
+            .arg(Arg::with_name("cache_key")
+                .long("cache-key")
+                .help("Keep the hashes and the images of this build apart from other builds in the same build directory")
+                .long_help("Keep the hashes and the images of this build apart from other builds in the same build directory \
+                (in hashes.<KEY>.csv and png/<KEY>/), e.g. for different branches of a pack. \
+                Packs use their fingerprint by default. \
+                The key may only contain ASCII letters, digits, -, _ and .")
+                .takes_value(true)
+                .required(false)
+                .value_name("KEY"))
+            .arg(Arg::with_name("max_cache_namespaces")
+                .long("max-cache-namespaces")
+                .help("Remove the hashes and images of the least recently used cache keys, so only this many are kept")
+                .takes_value(true)
+                .required(false)
+                .value_name("N"))
             .arg(Arg::with_name("ttx_tmpl")
                 .long("ttx-tmpl")
                 .help("A template file for the font, e.g. containing version and author information")
+                .long_help("A template file for the font, e.g. containing version and author information.\n\
+                If it has vertical metrics (a vhea table), every emoji gets the same vertical advance \
+                (the height of the image in font units) for vertical text.")
                 .takes_value(true)
                 .required(false)
                 .value_name("FILE"))
@@ -406,6 +842,242 @@ impl EmojiBuilder for Blobmoji {
                 .help("Build a Windows 10-compatible font as well (it contains additional font tables)")
                 .long_help("Build a Windows 10-compatible font as well (it contains additional font tables).\nMicrosoft, Windows are trademarks of the Microsoft group of companies.")
                 .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("subset")
+                .long("subset")
+                .help("Only include a subset of the emojis in the font")
+                .long_help("Only include a subset of the emojis in the font. \
+                This is either the name of an emoji kind (e.g. emoji_flag_sequence) or @FILE with FILE \
+                containing one sequence or name per line. All emojis are still rendered. \
+                The output file gets a _subset suffix unless the output is set explicitly (e.g. with --output).")
+                .takes_value(true)
+                .required(false)
+                .value_name("KIND|@FILE"))
+            .arg(Arg::with_name("tweaks")
+                .long("tweaks")
+                .help("A JSON file with manual adjustments (scale, offset, background) for single emojis")
+                .long_help("A JSON file with manual adjustments for single emojis. \
+                It maps sequences or names to objects with the (optional) keys scale, offset_x, \
+                offset_y, background_color (#RRGGBB or #RRGGBBAA) and force_fit (width or height).")
+                .takes_value(true)
+                .required(false)
+                .value_name("FILE"))
+            .arg(Arg::with_name("render_policy")
+                .long("render-policy")
+                .help("A JSON file that changes how kinds of emojis (e.g. flags or keycaps) are rendered")
+                .long_help("A JSON file that changes how kinds of emojis are rendered. \
+                It maps kinds (e.g. emoji_keycap_sequence) to objects with the (optional) keys \
+                waveflag, fit (width, height or contain), reduce_colors, margin (in pixels), \
+                anchor (center, bottom or top) and anchor_margin (in pixels). \
+                By default, flags are waved (with --waveflag) and put at the bottom, keycaps get a \
+                margin and components (e.g. skin tone swatches) keep their colors.")
+                .takes_value(true)
+                .required(false)
+                .value_name("FILE"))
+            .arg(Arg::with_name("max_png_bytes")
+                .long("max-png-bytes")
+                .help("Warn about emojis whose optimized PNG file is larger than this")
+                .takes_value(true)
+                .required(false)
+                .value_name("BYTES"))
+            .arg(Arg::with_name("enforce_size_budget")
+                .long("enforce-size-budget")
+                .help("Emojis that exceed --max-png-bytes are treated as errors instead of warnings")
+                .takes_value(false)
+                .required(false)
+                .requires("max_png_bytes"))
+            .arg(Arg::with_name("quantize_colors")
+                .long("quantize-colors")
+                .help("Reduce the colors of each emoji to at most this many (0 to 256, 0 = off)")
+                .long_help("Reduce the colors of each emoji to a palette with at most this many colors (0 to 256, 0 = off) \
+                and store it as an indexed PNG file, which is usually smaller. \
+                Emojis that would lose too much quality (see --quantize-min-psnr) keep all of their colors.")
+                .takes_value(true)
+                .required(false)
+                .value_name("COLORS"))
+            .arg(Arg::with_name("quantize_dither")
+                .long("quantize-dither")
+                .help("Use dithering for the quantization (hides the banding in gradients)")
+                .takes_value(false)
+                .required(false)
+                .requires("quantize_colors"))
+            .arg(Arg::with_name("quantize_min_psnr")
+                .long("quantize-min-psnr")
+                .help("Emojis whose quantized image has a lower PSNR (in dB) than this keep all of their colors (default: 35)")
+                .takes_value(true)
+                .required(false)
+                .value_name("DB")
+                .requires("quantize_colors"))
+            .arg(Arg::with_name("transparent_threshold")
+                .long("transparent-threshold")
+                .help("Report renders with at least this fraction of transparent pixels (default: 1, i.e. empty ones)")
+                .long_help("Report renders with at least this fraction (0 to 1) of fully transparent pixels as warnings \
+                of the render category (default: 1, i.e. only empty ones). The margin isn't counted.")
+                .takes_value(true)
+                .required(false)
+                .value_name("FRACTION"))
+            .arg(Arg::with_name("black_threshold")
+                .long("black-threshold")
+                .help("Report renders with at least this fraction of opaque black pixels (default: 0.95)")
+                .long_help("Report renders with at least this fraction (0 to 1) of opaque black pixels as warnings \
+                of the render category (default: 0.95), as they're usually caused by a missing fill. \
+                The margin isn't counted.")
+                .takes_value(true)
+                .required(false)
+                .value_name("FRACTION"))
+            .arg(Arg::with_name("prune_stale")
+                .long("prune-stale")
+                .help("Delete PNG files in the build directory that don't belong to any of the emojis")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("woff2")
+                .long("woff2")
+                .help("Additionally create a WOFF2 version of the font(s) next to the TTF file (requires brotli)")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("cbdt_metrics")
+                .long("cbdt-metrics")
+                .help("Store the bitmaps with small (legacy, used by Noto) or big glyph metrics")
+                .long_help("Store the bitmaps with small or big glyph metrics (CBDT image format 17 or 18). \
+                Small metrics are what Noto Color Emoji uses and what Android expects. \
+                FreeType-based renderers (e.g. on Linux) support both formats.")
+                .takes_value(true)
+                .possible_values(&["small", "big"])
+                .default_value("small")
+                .required(false))
+            .arg(Arg::with_name("ascent")
+                .long("ascent")
+                .help("Override the template's ascent (in font units, above the baseline)")
+                .long_help("Override the template's ascent (in font units, above the baseline), \
+                e.g. to match the text font that the emojis are used with. \
+                It's set in hhea and OS/2 (typographic and Windows ascent). \
+                The advances of the glyphs are scaled to the new line height (ascent - descent). \
+                It can't be used with --prebuilt-ttx.")
+                .value_name("UNITS")
+                .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("descent")
+                .long("descent")
+                .help("Override the template's descent (in font units, below the baseline, i.e. negative)")
+                .long_help("Override the template's descent (in font units, below the baseline, i.e. negative). \
+                It's set in hhea and OS/2 (the Windows descent is positive). \
+                The advances of the glyphs are scaled to the new line height (ascent - descent). \
+                It can't be used with --prebuilt-ttx.")
+                .value_name("UNITS")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .required(false))
+            .arg(Arg::with_name("line_gap")
+                .long("line-gap")
+                .help("Override the template's line gap (in font units)")
+                .long_help("Override the template's line gap (in font units) in hhea and OS/2. \
+                It can't be used with --prebuilt-ttx.")
+                .value_name("UNITS")
+                .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("keep_glyph_outlines")
+                .long("keep-glyph-outlines")
+                .help("Keep the outline tables (glyf, loca, etc.) in the font")
+                .long_help("Keep the outline tables (glyf, loca, etc.) in the font. \
+                They are always kept in the Windows font (--win) as Windows needs them.")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("ttx_compiler")
+                .long("ttx-compiler")
+                .help("Compile the font template with fontTools' ttx or with the built-in compiler")
+                .long_help("Compile the font template with fontTools' ttx or with the built-in compiler. \
+                The built-in compiler only supports the tables that the template and add_glyphs.py use; \
+                for anything else, it falls back to fontTools.")
+                .takes_value(true)
+                .possible_values(&["fonttools", "rust"])
+                .default_value("fonttools")
+                .required(false))
+            .arg(Arg::with_name("content_addressed_pngs")
+                .long("content-addressed-pngs")
+                .help("Name the PNG files by the hash of their source (e.g. emoji_u1f914.0123abcd.png) and keep older ones")
+                .long_help("Name the PNG files by the hash of their source (emoji_u<sequence>.<first 8 hex digits of the hash>.png). \
+                Files of earlier builds are kept, so external build systems can use them as a cache; \
+                an emoji is only rendered if there's no file for the hash of its source. \
+                manifest.csv in the build directory lists the current file, the short ID and the variant of each sequence \
+                (and the name of its glyph once the font has been built).")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("hash_algo")
+                .long("hash-algo")
+                .help("The algorithm for the hashes that detect changed sources")
+                .long_help("The algorithm for the hashes that detect changed sources (and render parameters). \
+                xxh3 is a lot faster than SHA256, but it's not a cryptographic hash. \
+                The algorithm is stored with the hashes; switching it means that all emojis are rendered again once.")
+                .takes_value(true)
+                .value_name("ALGORITHM")
+                .possible_values(&["sha256", "xxh3"])
+                .required(false))
+            .arg(Arg::with_name("glyph_map")
+                .long("glyph-map")
+                .help("Write the glyph name of each sequence next to the font (<font name>.glyph_map.json)")
+                .long_help("Write the glyph order and the glyph name of each sequence next to the font \
+                (<font name>.glyph_map.json), e.g. for subsetting it with pyftsubset. \
+                The map of the normal font is always written to glyph_map.json in the build directory. \
+                It isn't available with --prebuilt-ttx.")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("export_assets")
+                .long("export-assets")
+                .help("Additionally export the PNG files as Android assets to this directory")
+                .long_help("Additionally export the PNG files as Android assets to this directory \
+                (e.g. for filemojicompat-style integrations): emoji/<sequence>.png (lowercase, joined with \
+                underscores and without U+FE0F, like in the font's cmap), metadata.json with the fully-qualified \
+                sequence, the name and the first Android API level of each emoji (sdkAdded) and checksums.sha256.")
+                .value_name("DIR")
+                .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("keep_intermediate")
+                .long("keep-intermediate")
+                .help("Keep the intermediate files of the fonts (in the build directory's work directory)")
+                .long_help("Keep the intermediate files of the fonts (TTX template, font without PUA mappings, etc.). \
+                Each font is built in its own directory work/<target>-<random suffix> inside of the build directory, \
+                which is usually removed after the font has been built successfully.")
+                .takes_value(false)
+                .required(false))
+            .arg(Arg::with_name("from_stage")
+                .long("from-stage")
+                .help("Run the stages of building the fonts from this one on, even if their input hasn't changed")
+                .long_help("Run the stages of building the fonts from this one on, even if their input hasn't changed. \
+                The outputs of the completed stages are kept in the build directory (stages.json and stages/), \
+                so after a failed build, the stages that have been completed before are usually skipped.")
+                .value_name("STAGE")
+                .takes_value(true)
+                .possible_values(&Stage::NAMES)
+                .required(false))
+            .arg(Arg::with_name("debug_pngs")
+                .long("debug-pngs")
+                .help("Additionally store annotated copies of the rendered emojis in this directory")
+                .long_help("Additionally store annotated copies of the rendered emojis in this directory. \
+                They have a red border at the character box (136x128), a green one at the render box \
+                (128x128) and their sequence in the top left corner. \
+                Only emojis that are actually (re-)rendered are written.")
+                .value_name("DIR")
+                .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("prebuilt_ttx")
+                .long("prebuilt-ttx")
+                .help("Use this TTX file instead of generating one from the template with add_glyphs.py")
+                .long_help("Use this TTX file instead of generating one from the template with add_glyphs.py. \
+                It has to contain the glyphs, cmap and GSUB entries for all emojis already; \
+                glyphs that don't match the prepared emojis are reported. \
+                It's used for the Windows font (--win10) as well.")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with_all(&["aliases", "flag_aliases"])
+                .required(false))
+            .arg(Arg::with_name("flag_aliases")
+                .long("flag-aliases")
+                .help("A JSON file with flags that use the image of another flag (e.g. {\"NO\": [\"SJ\", \"BV\"]})")
+                .long_help("A JSON file with flags that use the image of another flag, e.g. {\"NO\": [\"SJ\", \"BV\"]}. \
+                The flags are given by their ISO 3166-1/2 codes; aliases may also be codepoint sequences. \
+                The image is only rendered and embedded once and the aliases use its glyph.")
+                .value_name("FILE")
+                .takes_value(true)
                 .required(false));
         let reduce_color_args = ReduceColors::cli_arguments(&subcommand.p.global_args);
         subcommand.args(&reduce_color_args)
@@ -417,6 +1089,45 @@ impl EmojiBuilder for Blobmoji {
             String::from(module_path!())
         ]
     }
+
+    /// The scripts from Noto Emoji and nototools and the font template
+    fn bundled_files() -> Vec<BundledFile> {
+        vec![
+            BundledFile::new("add_glyphs/add_glyphs.py", noto_emoji_utils::ADD_GLYPHS_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("add_glyphs/add_aliases.py", noto_emoji_utils::ADD_ALIASES_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("add_glyphs/add_emoji_gsub.py", noto_emoji_utils::ADD_EMOJI_GSUB_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("color_emoji/emoji_builder.py", noto_emoji_utils::EMOJI_BUILDER_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("color_emoji/png.py", noto_emoji_utils::PNG_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("map_pua_emoji/map_pua_emoji.py", noto_emoji_utils::MAP_PUA_EMOJI_PY.as_bytes(), Some("Apache-2.0")),
+            BundledFile::new("noto-emoji/NotoColorEmoji.tmpl.ttx.tmpl", TMPL_TTX_TMPL_CONTENT, Some("Apache-2.0")),
+        ]
+    }
+
+    fn output_file_name(emoji: &Emoji) -> Option<String> {
+        Some(Self::generate_filename(emoji))
+    }
+
+    /// Checks the emoji against the hashes of the cache namespace that [Blobmoji::new] would use
+    fn cache_status(build_path: &Path, matches: Option<&ArgMatches>, emoji: &Emoji) -> CacheStatus {
+        let mut settings = Settings::new();
+        if let Some(matches) = matches {
+            settings.load_matches(matches, &["cache_key"]);
+        }
+        settings.load_env(std::env::vars());
+        let build_dir = match settings.get("cache_key").filter(|cache_key| BuildDir::is_valid_namespace(cache_key)) {
+            Some(cache_key) => BuildDir::at(build_path).in_namespace(cache_key),
+            None => BuildDir::at(build_path)
+        };
+        match FileHashes::from_path(build_dir.hashes()) {
+            Err(_) => CacheStatus::NoCache,
+            Ok(hashes) if !hashes.contains(&emoji.sequence) => CacheStatus::NotBuilt,
+            Ok(hashes) => match hashes.check(emoji) {
+                Ok(true) => CacheStatus::UpToDate,
+                Ok(false) => CacheStatus::Changed,
+                Err(_) => CacheStatus::Unreadable
+            }
+        }
+    }
 }
 
 /// The width of the image that's _embedded_ into the font
@@ -428,106 +1139,320 @@ const RENDER_AND_CHARACTER_HEIGHT: u32 = 128;
 
 
 impl Blobmoji {
-    /// Renders a single emoji.
-    /// It will not pad the image, however it will return whether it is taller than wide
-    /// (`FitTo::Height`) or if it's wider than tall (`FitTo::Width`).
-    /// The exact value is always 128px (i.e. the target size for the largest dimension).
-    /// # Arguments
-    /// * `emoji` - the emoji to be rendered
-    /// # Returns
-    /// An `Option` containing the image as a vector of RGBA pixels and the dimensions of the
-    /// image.
-    fn render_svg(&self, emoji: &Emoji) -> Option<(Pixmap, (u32, u32))> {
-        if let Some(svg_path) = &emoji.svg_path {
-            let opt = usvg::Options {
-                // Just as a fallback. Default is "cursive",
-                // which on Windows and Mac OS it will use Comic Sans
-                // which is pretty close to Comic Neue, that is used in Blobmoji
-                font_family: self.default_font.clone(),
-                fontdb: self.fontdb.clone(),
-                ..Default::default()
+    /// Renders, pads and optimizes a single emoji (unless it is unchanged) and saves it as a PNG
+    /// file, using the given render options.
+    fn prepare_with(&self, emoji: &Emoji, opt: &usvg::Options) -> PreparationResult<<Self as EmojiBuilder>::PreparedEmoji, BlobmojiError> {
+        info!("Preparing {}", emoji);
+
+        // Changes to the tweak of an emoji require it to be re-rendered as well
+        let tweak = self.tweaks.as_ref().and_then(|tweaks| tweaks.get(emoji));
+        let params = self.render_policy.for_emoji(emoji);
+        let mut cache_key = tweak.map(Tweak::cache_key).unwrap_or_default();
+        // Only special parameters are hashed, so the other emojis keep their hashes
+        if params != RenderParams::default() {
+            cache_key.extend(params.cache_key());
+        }
+        if let Some(mask) = self.waveflag_mask.as_ref().filter(|_| self.waveflag && params.waveflag) {
+            cache_key.extend(mask.cache_key());
+        }
+
+        // The source is only read once, for its hash as well as for rendering it
+        let (source, checked) = match FileHashes::read_source(emoji) {
+            Ok(source) => {
+                self.check_fonts(emoji, &source, opt)?;
+                let checked = self.hashes.check_bytes(emoji, &source, &cache_key);
+                (Some(source), Ok(checked))
+            }
+            Err(err) => (None, Err(err))
+        };
+
+        let (path, cached, hash) = if self.content_addressed_pngs {
+            // The hashes are only advisory here, as the file name already contains the hash
+            let hash = checked.map(|(_, hash)| hash);
+            let path = match &hash {
+                Ok(hash) => self.build_dir.content_addressed_png(emoji, hash),
+                Err(_) => self.build_dir.png(emoji)
             };
+            let cached = hash.is_ok() && path.exists() && Self::has_current_size(emoji, &path);
+            (path, cached, hash)
+        } else {
+            // Where to store the image?
+            let path = self.build_dir.png(emoji);
 
-            let data = std::fs::read(svg_path).ok()?;
-            let tree = usvg::Tree::from_data(&data, &opt);
-
-            if let Ok(tree) = tree {
-                // Reduce the colors to a certain palette if possible
-                let tree = if let Some(reduce_colors) = &self.reduce_colors {
-                    match reduce_colors.process(emoji, tree) {
-                        Ok(tree) => tree,
-                        Err((tree, err)) => {
-                            error!("Could not reduce colors on emoji {}: {:?}", &emoji, err);
-                            tree
-                        }
-                    }
-                } else {
-                    tree
-                };
+            if let Err(err) = &checked {
+                warn!("Hash of an emoji ({}) could not be checked: {:?}", emoji, err);
+            }
+            let unchanged = matches!(checked, Ok((true, _)));
+
+            // Only render if sth. has changed or if it isn't available (in the current size)
+            let cached = unchanged && path.exists() && Self::has_current_size(emoji, &path);
+            // The hash value of the source (to prevent unnecessary re-renders)
+            let hash = checked.map(|(_, hash)| hash);
+            (path, cached, hash)
+        };
 
-                // It's easier to get the dimensions here than at some later point
-                let size = tree.svg_node().size;
+        if cached {
+            info!("Emoji is already available");
+            self.record_hash(emoji, &hash);
+            Ok(((path, hash), None))
+        } else if !self.content_addressed_pngs && self.restore_undone(emoji, &hash, &path) {
+            info!("Restored the undone image of {}", emoji);
+            self.record_hash(emoji, &hash);
+            Ok(((path, hash), None))
+        } else {
+            self.renders.fetch_add(1, Ordering::Relaxed);
+            // Render the SVG to an appropriate, but unpadded size
+            if let Some((rendered, (width, height))) = self.render_svg(emoji, source.as_deref(), tweak, &params, opt) {
+                // Before the padding, which would count as transparent
+                let stats = RenderStats::from_pixels(rendered.data());
+                if let Some(problem) = self.render_check.check(&stats) {
+                    warn!("{}: {}", emoji, problem);
+                    self.diagnostics.report(Diagnostic::warning(Category::Render, problem.to_string())
+                        .with_emoji(emoji));
+                }
 
-                let waved_height = if emoji.is_flag() && self.waveflag {
-                    size.height() * (1.0 + WAVE_FACTOR as f64)
+                // Wave the flag if it is one and if we're supposed to.
+                let (rendered, width, height) = if self.waveflag && params.waveflag {
+                    rendering::wave_flag(&rendered, self.waveflag_mask.as_ref())
                 } else {
-                    size.height()
+                    (rendered.data().to_vec(), width, height)
                 };
+                // The rendering already accounted for the case that this is a flag and that the
+                // image will get taller.
 
-                let fit_to = if waved_height > size.width() {
-                    if emoji.is_flag() && self.waveflag {
-                        FitTo::Height((RENDER_AND_CHARACTER_HEIGHT as f32 / (1.0 + WAVE_FACTOR)) as u32)
-                    } else {
-                        FitTo::Height(RENDER_AND_CHARACTER_HEIGHT)
+                // Add the padding
+                let image = match tweak {
+                    // Tweaked emojis might be larger than the character or moved around
+                    Some(tweak) => tweak.apply(&rendered, width, height),
+                    // Flags are put at the bottom, so the wave's trough is aligned
+                    None => {
+                        // Anything else that doesn't fit (e.g. with `fit: height` for a wide
+                        // image) is scaled down instead of being cut off
+                        let (rendered, width, height) = image_utils::fit_into(
+                            rendered,
+                            width,
+                            height,
+                            CHARACTER_WIDTH,
+                            RENDER_AND_CHARACTER_HEIGHT,
+                            self.scale_mode,
+                        );
+                        image_utils::anchor_to(
+                            &rendered,
+                            width,
+                            height,
+                            CHARACTER_WIDTH,
+                            RENDER_AND_CHARACTER_HEIGHT,
+                            params.anchor,
+                            params.anchor_margin,
+                        )
                     }
-                } else {
-                    FitTo::Width(RENDER_WIDTH)
                 };
 
-                // Now, how large will it get?
-                // This is now done in the same way as the rendering
-                let rendered_size = fit_to.fit_to(size.to_screen_size()).unwrap();
+                if let Some(debug_pngs) = &self.debug_pngs {
+                    self.write_debug_png(debug_pngs, emoji, &image);
+                }
 
-                // This is copied from the minimal example for resvg
-                let mut pixmap = tiny_skia::Pixmap::new(rendered_size.width(), rendered_size.height()).unwrap();
+                // Oxipng needs to work on PNGs and not raw pixels, so it's encoded here.
+                // It also makes sense to do quantization at this step (if it's enabled)
+                let encoded = match self.quantize_to_png(emoji, &image) {
+                    Some(quantized) => quantized,
+                    None => image_utils::pixels_to_png(&image).unwrap()
+                };
 
-                // This is the point where it's actually rendered
-                let img = resvg::render(&tree, fit_to, pixmap.as_mut());
+                // Lossless compression
+                let optimized = match image_utils::optimize_png(&encoded) {
+                    Ok(optimized) => optimized,
+                    Err(e) => {
+                        warn!("Error in optimizing {:?}: {:?}", emoji, e);
+                        self.diagnostics.report(Diagnostic::warning(Category::Render, format!("Couldn't optimize the PNG file: {:?}", e))
+                            .with_emoji(emoji));
+                        encoded
+                    },
+                };
 
-                if img.is_some() {
-                    Some((pixmap, rendered_size.dimensions()))
-                } else {
-                    error!("Failed to render {}", emoji);
-                    None
-                }
+                self.size_budget.check(emoji, optimized.len() as u64)?;
+
+                // Save it
+                image_utils::write_png(&path, optimized).unwrap();
+                self.record_hash(emoji, &hash);
+
+                Ok(((path, hash), None))
             } else {
-                let err = tree.err().unwrap();
-                error!("Error in loading the SVG file for {}: {:?}", emoji, err);
-                None
+                error!("Couldn't render Emoji {}", emoji);
+                self.diagnostics.report(Diagnostic::error(Category::Render, format!("Couldn't render {}", emoji))
+                    .with_emoji(emoji));
+                Err(BlobmojiError::UnknownError)
             }
-        } else {
-            error!("No file available for {}", emoji);
-            None
         }
     }
 
-    /// Performs the quantization process which apparently does some sort of posterization to reduce
-    /// the number of colors in the image.
-    /// Due to licensing issues, this function (unfortunately) does nothing at all and is only
-    /// implemented in a fork (which is - at the moment of writing - not released).
-    ///
-    /// Errors are not returned as this would need knowledge of the error type which relies on the
-    /// library being present. Therefore any errors are directly shown (using `warn!`) inside of the
-    /// function.
-    /// This is also the reason why `emoji` is required here, it's used to generate meaningful error
-    /// messages.
-    fn quantize_to_png(&self, _emoji: &Emoji, _img: &mut [u8]) -> Option<Vec<u8>> {
-        None
+    /// Records the hash of a prepared emoji right away, so it doesn't get lost if the build
+    /// is interrupted
+    fn record_hash(&self, emoji: &Emoji, hash: &Result<SourceHash, CheckError>) {
+        if let Ok(hash) = hash {
+            self.hash_flush.record(emoji, hash);
+        }
     }
 
-    const EMPTY_PIXEL: [u8; 4] = [0; 4];
-
-    fn generate_filename(emoji: &Emoji) -> String {
+    /// Reports the font families of the emoji's text that aren't available (see
+    /// [rendering::font_fallbacks]). This is also done for emojis that don't need to be rendered
+    /// again, so `--require-fonts` doesn't depend on the cache.
+    /// # Errors
+    /// The missing families if `--require-fonts` is set
+    fn check_fonts(&self, emoji: &Emoji, source: &[u8], opt: &usvg::Options) -> Result<(), BlobmojiError> {
+        let fallbacks = rendering::font_fallbacks(source, opt);
+        for fallback in &fallbacks {
+            let message = format!("The font {:?} is not available, {:?} is used instead", fallback.family, fallback.substitute);
+            warn!("{}: {}", emoji, message);
+            let diagnostic = if self.require_fonts {
+                Diagnostic::error(Category::Render, message)
+            } else {
+                Diagnostic::warning(Category::Render, message)
+            };
+            self.diagnostics.report(diagnostic.with_emoji(emoji));
+        }
+        if self.require_fonts && !fallbacks.is_empty() {
+            Err(BlobmojiError::MissingFonts(fallbacks.into_iter().map(|fallback| fallback.family).collect()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Moves the undone PNG file of an emoji back to the PNG directory if its source is still the
+    /// same. Returns whether that was successful.
+    fn restore_undone(&self, emoji: &Emoji, hash: &Result<SourceHash, CheckError>, path: &Path) -> bool {
+        let undo_path = match hash {
+            Ok(hash) => self.build_dir.undo_png(emoji, hash),
+            Err(_) => return false
+        };
+        if !undo_path.is_file() || !Self::has_current_size(emoji, &undo_path) {
+            return false;
+        }
+        match rename(&undo_path, path) {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("Couldn't restore {:?}: {:?}", undo_path, err);
+                false
+            }
+        }
+    }
+
+    /// Checks whether a cached PNG file has the size of the current build, as PNG files from older
+    /// builds might have a different size which breaks some renderers
+    fn has_current_size(emoji: &Emoji, path: &Path) -> bool {
+        match image_utils::png_dimensions(path) {
+            Ok((CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT)) => true,
+            Ok((width, height)) => {
+                info!("Re-rendering {} as its PNG file is {}x{} instead of {}x{}",
+                      emoji, width, height, CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT);
+                false
+            },
+            Err(err) => {
+                warn!("Re-rendering {} as the size of {:?} couldn't be read: {:?}", emoji, path, err);
+                false
+            }
+        }
+    }
+
+    /// Saves an annotated, unoptimized copy of the padded image (see [image_utils::annotate])
+    fn write_debug_png(&self, debug_pngs: &Path, emoji: &Emoji, image: &[u8]) {
+        let mut annotated = image.to_vec();
+        let label = emoji.sequence.iter()
+            .map(|codepoint| format!("{:x}", codepoint))
+            .join(" ");
+        image_utils::annotate(&mut annotated, CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT, RENDER_WIDTH, &label);
+        let result = image_utils::pixels_to_png(&annotated)
+            .map_err(std::io::Error::other)
+            .and_then(|encoded| {
+                let path = debug_pngs.join(Blobmoji::generate_filename(emoji));
+                File::create(path)?.write_all(&encoded)
+            });
+        if let Err(err) = result {
+            warn!("Couldn't write the debug image of {}: {:?}", emoji, err);
+        }
+    }
+
+    /// The options for usvg. As it contains the font database, it should only be created once for
+    /// many emojis.
+    fn render_options(&self) -> usvg::Options {
+        self.render_options_built.fetch_add(1, Ordering::Relaxed);
+        usvg::Options {
+            // Just as a fallback. Default is "cursive",
+            // which on Windows and Mac OS it will use Comic Sans
+            // which is pretty close to Comic Neue, that is used in Blobmoji
+            font_family: self.default_font.clone(),
+            fontdb: self.fontdb.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Renders a single emoji.
+    /// It will not pad the image, however it will return whether it is taller than wide
+    /// (`FitTo::Height`) or if it's wider than tall (`FitTo::Width`).
+    /// The exact value is always 128px (i.e. the target size for the largest dimension).
+    /// # Arguments
+    /// * `emoji` - the emoji to be rendered
+    /// * `source` - the content of its SVG file (if it could be read)
+    /// * `tweak` - manual adjustments for this emoji
+    /// * `params` - how this kind of emoji is rendered (see [RenderPolicy])
+    /// * `opt` - the options for usvg (see [Blobmoji::render_options])
+    /// # Returns
+    /// An `Option` containing the image as a vector of RGBA pixels and the dimensions of the
+    /// image.
+    fn render_svg(&self, emoji: &Emoji, source: Option<&[u8]>, tweak: Option<&Tweak>, params: &RenderParams, opt: &usvg::Options) -> Option<(Pixmap, (u32, u32))> {
+        if let Some(source) = source {
+            // Reduce the colors to a certain palette if possible
+            let reduce_colors = self.reduce_colors.as_deref().filter(|_| params.reduce_colors);
+            let waved = self.waveflag && params.waveflag;
+            let render_box = RenderBox::new(params);
+
+            let pixmap = rendering::render_data(
+                emoji,
+                source,
+                opt,
+                reduce_colors,
+                |size| rendering::compute_fit(size, waved, render_box, params.fit, tweak),
+            )?;
+            let dimensions = (pixmap.width(), pixmap.height());
+            Some((pixmap, dimensions))
+        } else {
+            error!("No (readable) file available for {}", emoji);
+            None
+        }
+    }
+
+    /// Reduces the colors of the image to a palette (if `--quantize-colors` is set) and encodes it
+    /// as an indexed PNG file (see [quantize]).
+    /// Returns `None` if the image should be encoded without quantization, i.e. if it's disabled,
+    /// the quality would be too low or it couldn't be encoded.
+    ///
+    /// Errors are not returned, as the unquantized image can be used instead. Therefore any errors
+    /// are directly shown (using `warn!`) inside of the function.
+    /// This is also the reason why `emoji` is required here, it's used to generate meaningful error
+    /// messages.
+    fn quantize_to_png(&self, emoji: &Emoji, img: &[u8]) -> Option<Vec<u8>> {
+        let quantizer = self.quantizer.as_ref()?;
+        match quantizer.encode(img, CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT) {
+            Ok(Quantization::Encoded { png, psnr }) => {
+                let original = image_utils::pixels_to_png(img).map(|original| original.len()).unwrap_or_default();
+                debug!("Quantized {} (PSNR: {:.1} dB, {} instead of {} bytes)", emoji, psnr, png.len(), original);
+                self.quantization.record_quantized(original, png.len());
+                Some(png)
+            },
+            Ok(Quantization::TooLossy { psnr }) => {
+                debug!("Not quantizing {} (PSNR: {:.1} dB)", emoji, psnr);
+                self.quantization.record_too_lossy();
+                None
+            },
+            Err(err) => {
+                warn!("Couldn't quantize {}: {:?}", emoji, err);
+                None
+            }
+        }
+    }
+
+    /// The name of an emoji's PNG file (e.g. `emoji_u1f914.png`)
+    pub fn generate_filename(emoji: &Emoji) -> String {
         let mut codepoints = emoji.sequence.iter()
             .map(|codepoint| format!("{:x}", codepoint));
         let codelength: usize = emoji.sequence.iter()
@@ -542,6 +1467,87 @@ impl Blobmoji {
         filename
     }
 
+    /// The name of an emoji's PNG file with the first bytes of its source's hash
+    /// (e.g. `emoji_u1f914.0123abcd.png`), which is used with `--content-addressed-pngs`
+    pub fn generate_content_addressed_filename(emoji: &Emoji, hash: &[u8]) -> String {
+        let filename = Blobmoji::generate_filename(emoji);
+        let hash = &hash[..hash.len().min(CONTENT_ADDRESS_BYTES)];
+        format!("{}.{}.png", filename.trim_end_matches(".png"), hex::encode(hash))
+    }
+
+    /// Whether a file name is the content-addressed name of an image with one of the `expected`
+    /// (regular) file names
+    fn is_content_addressed(name: &str, expected: &HashSet<String>) -> bool {
+        let stem = match name.strip_suffix(".png") {
+            Some(stem) => stem,
+            None => return false
+        };
+        let mut parts = stem.rsplitn(2, '.');
+        match (parts.next(), parts.next()) {
+            (Some(hash), Some(stem)) => hash.len() == 2 * CONTENT_ADDRESS_BYTES
+                && hash.chars().all(|c| c.is_ascii_hexdigit())
+                && expected.contains(&format!("{}.png", stem)),
+            _ => false
+        }
+    }
+
+    /// Compares the PNG directory with the prepared emojis.
+    /// Files that don't belong to any of the emojis are reported (and deleted with `--prune-stale`).
+    /// If the PNG file of a successfully prepared emoji is missing, an error with all missing files
+    /// is returned.
+    fn check_png_dir(&self, emojis: &HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>>) -> Result<(), BlobmojiError> {
+        // Emojis that failed this time might still have a valid file from an earlier build
+        let expected: HashSet<String> = emojis.keys()
+            .map(|emoji| Blobmoji::generate_filename(emoji))
+            .collect();
+
+        let png_dir = self.build_dir.png_dir();
+        let unexpected = std::fs::read_dir(&png_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| !path.file_name()
+                .map(|name| name.to_string_lossy())
+                .map(|name| expected.contains(name.as_ref())
+                    // Images of earlier sources are kept as a cache
+                    || (self.content_addressed_pngs && Blobmoji::is_content_addressed(&name, &expected)))
+                .unwrap_or(false));
+        for path in unexpected {
+            if self.prune_stale {
+                info!("Deleting unexpected file {:?}", path);
+                remove_file(path)?;
+            } else {
+                warn!("Unexpected file {:?} (use --prune-stale to delete it)", path);
+            }
+        }
+
+        // Undone images that haven't been restored until now won't be needed anymore
+        let undo_dir = self.build_dir.undo_dir();
+        if self.prune_stale && undo_dir.is_dir() {
+            for path in std::fs::read_dir(&undo_dir)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file()) {
+                info!("Deleting undone file {:?}", path);
+                remove_file(path)?;
+            }
+        }
+
+        let missing = emojis.values()
+            .filter_map(|prepared| prepared.as_ref().ok())
+            .map(|(path, _)| path)
+            .filter(|path| !path.exists())
+            .cloned()
+            .sorted()
+            .collect_vec();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            error!("Missing PNG files: {:?}", missing);
+            Err(BlobmojiError::MissingPngs(missing))
+        }
+    }
+
     fn store_prepared(&mut self, emojis: &HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>>) -> Result<(), BlobmojiError> {
         // Collect all errors that occurred while checking the hashes and save those that were successful
         let hashing_errors = emojis.iter()
@@ -553,16 +1559,20 @@ impl Blobmoji {
             .filter_map(|(emoji, hash)|
                 match hash {
                     Ok(hash) => {
-                        // Update the hash value
-                        self.hashes.update(emoji, hash);
+                        // Update the hash value (if it hasn't been recorded while preparing it)
+                        self.hashes.update_concurrent(emoji, hash);
                         None
                     },
                     Err(error) => Some((emoji, error))
                 })
             .collect_vec();
 
-        // Save all hashes
-        let saving_results = self.hashes.write_to_path(self.build_path.join(HASHES));
+        // Save all hashes (after the ones that are still written in the background)
+        let saving_results = self.hash_flush.flush();
+
+        if self.content_addressed_pngs {
+            self.write_manifest(emojis, None)?;
+        }
 
         for (emoji, err) in hashing_errors {
             error!("Error in updating a hash value for emoji {}: {:?}", emoji, err);
@@ -574,11 +1584,187 @@ impl Blobmoji {
         }
     }
 
+    /// Exports the PNG files of the successfully prepared emojis as Android assets
+    /// (if `--export-assets` is set)
+    fn export_assets(&self, emojis: &PreparedEmojis) -> Result<(), BlobmojiError> {
+        if let Some(dir) = &self.export_assets {
+            let prepared = emojis.iter()
+                .filter_map(|(emoji, prepared)| prepared.as_ref().ok().map(|(path, _)| (*emoji, path.as_path())))
+                .collect_vec();
+            let exported = android_assets::export(dir, &prepared, self.asset_table.as_ref())?;
+            info!("Exported {} emojis as Android assets to {:?}", exported.len(), dir);
+        }
+        Ok(())
+    }
+
+    /// Writes the PNG file of each successfully prepared emoji to the manifest
+    /// (the sequence in the same format as in the hashes, the file name and the short ID).
+    /// With a glyph map, the name of the emoji's glyph is added as a fourth column.
+    fn write_manifest(&self, emojis: &PreparedEmojis, glyph_map: Option<&GlyphMap>) -> Result<(), BlobmojiError> {
+        let entries = emojis.iter()
+            .filter_map(|(emoji, prepared)| prepared.as_ref().ok().map(|(path, _)| (emoji, path)))
+            .map(|(emoji, path)| (
+                GlyphMap::sequence_key(&emoji.sequence),
+                path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                match &self.short_ids {
+                    Some(short_ids) => short_ids.id(&emoji.sequence),
+                    None => emoji.short_id()
+                },
+                emoji.variant().unwrap_or_default(),
+                glyph_map.and_then(|glyph_map| glyph_map.get(&emoji.sequence))
+                    .unwrap_or_default()
+                    .to_string()
+            ))
+            .sorted();
+        let mut writer = csv::Writer::from_path(self.build_dir.manifest())?;
+        for (sequence, file, short_id, variant, glyph) in entries {
+            match glyph_map {
+                Some(_) => writer.write_record(&[sequence, file, short_id, variant, glyph])?,
+                None => writer.write_record(&[sequence, file, short_id, variant])?
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Links the (content-addressed) images of the emojis into the working directory under their
+    /// usual names, as emoji_builder.py finds the images by their names
+    fn stage_pngs(emojis: &PreparedEmojis, work_dir: &WorkDir) -> std::io::Result<PathBuf> {
+        let png_dir = work_dir.png_dir();
+        create_dir_all(&png_dir)?;
+        for (emoji, (path, _)) in emojis.iter()
+            .filter_map(|(emoji, prepared)| prepared.as_ref().ok().map(|prepared| (emoji, prepared))) {
+            let staged = png_dir.join(Blobmoji::generate_filename(emoji));
+            if std::fs::hard_link(path, &staged).is_err() {
+                copy(path, &staged)?;
+            }
+        }
+        Ok(png_dir)
+    }
+
+    /// Copies the pre-built TTX file to where `add_glyphs.py` would have written it and warns
+    /// about glyphs that don't match the prepared emojis
+    fn use_prebuilt_ttx(&self,
+                        prebuilt_ttx: &Path,
+                        emojis: &PreparedEmojis,
+                        target: &Path
+    ) {
+        let ttx = std::fs::read_to_string(prebuilt_ttx).unwrap();
+        let prepared = emojis.iter()
+            .filter(|(_, prepared)| prepared.is_ok())
+            .map(|(emoji, _)| *emoji);
+        let mismatch = TtxMismatch::check(&ttx, prepared);
+        if !mismatch.missing.is_empty() {
+            let message = format!("The pre-built TTX file doesn't contain the glyphs of {} prepared emoji(s): {}",
+                                  mismatch.missing.len(), mismatch.missing.join(", "));
+            warn!("{}", message);
+            self.diagnostics.report(Diagnostic::warning(Category::Font, message).with_path(prebuilt_ttx.to_path_buf()));
+        }
+        if !mismatch.unused.is_empty() {
+            let message = format!("The pre-built TTX file contains {} emoji glyph(s) without a prepared emoji: {}",
+                                  mismatch.unused.len(), mismatch.unused.join(", "));
+            warn!("{}", message);
+            self.diagnostics.report(Diagnostic::warning(Category::Font, message).with_path(prebuilt_ttx.to_path_buf()));
+        }
+        std::fs::write(target, ttx).unwrap();
+    }
+
+    /// Builds the fonts from the prepared emojis (see [EmojiBuilder::build] and
+    /// [EmojiBuilder::build_to_writer])
+    fn build_to(&mut self, emojis: PreparedEmojis, output: FontOutput) -> Result<(), BlobmojiError> {
+        assert!(!emojis.is_empty());
+
+        self.store_prepared(&emojis)?;
+        self.export_assets(&emojis)?;
+
+        // The Python scripts use whatever is in the PNG directory
+        self.check_png_dir(&emojis)?;
+
+        // The preparation covered all emojis (so the cache stays warm), but only the subset
+        // will end up in the font
+        let (emojis, output) = if let Some(subset) = &self.subset {
+            for missing in subset.missing(emojis.keys().copied()) {
+                warn!("Missing subset member: {}", missing.raw);
+            }
+            let emojis: HashMap<_, _> = emojis.into_iter()
+                .filter(|(emoji, _)| subset.contains(emoji))
+                .collect();
+            if emojis.is_empty() {
+                return Err(BlobmojiError::EmptySubset);
+            }
+            let output = match output {
+                FontOutput::File(output_file) if self.default_output => {
+                    let mut output_file_stem_subset = output_file.file_stem().unwrap_or_default().to_os_string();
+                    output_file_stem_subset.push("_subset");
+                    FontOutput::File(output_file
+                        .with_file_name(output_file_stem_subset)
+                        .with_extension(output_file.extension().unwrap_or_default()))
+                },
+                writer => writer
+            };
+            (emojis, output)
+        } else {
+            (emojis, output)
+        };
+
+        // The size of the embedded bitmaps is interesting even without a size budget
+        let sizes = emojis.iter()
+            .filter_map(|(emoji, prepared)| prepared.as_ref().ok().map(|(path, _)| (emoji, path)))
+            .filter_map(|(emoji, path)| std::fs::metadata(path).ok()
+                .map(|metadata| (Blobmoji::generate_filename(emoji), metadata.len())));
+        let report = SizeReport::from_sizes(sizes, LARGEST_GLYPHS);
+        for line in report.to_string().lines() {
+            info!("{}", line);
+        }
+        if self.quantizer.is_some() {
+            info!("{}", self.quantization);
+        }
+
+        if self.render_only {
+            return Ok(());
+        }
+        match output {
+            FontOutput::File(output_file) => {
+                // For Windows 10 support
+                let mut output_file_stem_windows = output_file.file_stem().unwrap_or_default().to_os_string();
+                output_file_stem_windows.push("_win");
+                let output_file_windows = output_file
+                    .with_file_name(output_file_stem_windows)
+                    .with_extension(output_file.extension().unwrap_or_default());
+                // Both fonts have their own working directory, so they can be built at the same time
+                let this = &*self;
+                let (normal, windows) = rayon::join(
+                    || this.build_font(&emojis, &output_file, false),
+                    || this.build_font(&emojis, &output_file_windows, true)
+                );
+                normal?;
+                windows?;
+
+                if !self.attribution.is_empty() {
+                    let attribution_file = output_file.with_file_name(ATTRIBUTION_FILE);
+                    info!("Writing the licenses to {:?}", attribution_file);
+                    self.attribution.write(attribution_file)?;
+                }
+            },
+            FontOutput::Writer(writer) => self.build_font_to(&emojis, FontOutput::Writer(writer), false)?
+        }
+
+        Ok(())
+    }
+
     fn build_font(&self,
-                  emojis: &HashMap<&Emoji, Result<<Self as EmojiBuilder>::PreparedEmoji, <Self as EmojiBuilder>::Err>>,
+                  emojis: &PreparedEmojis,
                   output_file: &Path,
                   add_cmap_and_glyf: bool
-    ) {
+    ) -> Result<(), BlobmojiError> {
+        self.build_font_to(emojis, FontOutput::File(output_file.to_path_buf()), add_cmap_and_glyf)
+    }
+
+    fn build_font_to(&self,
+                     emojis: &PreparedEmojis,
+                     output: FontOutput,
+                     add_cmap_and_glyf: bool
+    ) -> Result<(), BlobmojiError> {
         // TODO: Build the font (the following steps are copied from the original Makefile
         //       (cf. https://github.com/googlefonts/noto-emoji/blob/master/Makefile)
         // (% is just used as a placeholder, just like in the Makefile)
@@ -607,84 +1793,207 @@ impl Blobmoji {
         //         moved to an earlier step.
         //       - Implement
 
-        // TODO: Handle errors
-        info!("Adding glyphs");
-        match noto_emoji_utils::add_glyphs(
-            &self.aliases,
-            &emojis,
-            self.build_path.join(TMPL_TTX_TMPL),
-            self.build_path.join(TMPL_TTX),
-            add_cmap_and_glyf
-        ) {
-            Ok(_) => (),
-            Err(err) => {
-                let gil = Python::acquire_gil();
-                let py = gil.python();
-                err.print(py);
-            }
-        };
+        let font = if add_cmap_and_glyf { "win" } else { "normal" };
+        let work_dir = self.build_dir.work_dir(font)?;
+        debug!("Building {} in {:?}", output, work_dir.path());
+        let steps = self.font_steps.as_ref();
 
-        let tmpl_ttf = self.build_path.join(TMPL_TTF);
-        // TODO: This if-condition might be unnecessary
-        if tmpl_ttf.exists() {
-            remove_file(tmpl_ttf).unwrap();
-        }
+        let glyph_map = if let Some(prebuilt_ttx) = &self.prebuilt_ttx {
+            info!("Using the pre-built TTX file {:?}", prebuilt_ttx);
+            self.use_prebuilt_ttx(prebuilt_ttx, emojis, &work_dir.ttx_tmpl());
+            None
+        } else {
+            let available: HashSet<Vec<u32>> = emojis.iter()
+                .filter(|(_, prepared)| prepared.is_ok())
+                .map(|(emoji, _)| emoji.sequence.iter()
+                    .filter(|codepoint| **codepoint != 0xfe0f)
+                    .copied()
+                    .collect())
+                .collect();
+            let flag_aliases = self.flag_aliases.resolve(&available);
+
+            let mut input = Fingerprint::new();
+            input.add_file(self.build_dir.ttx_tmpl_tmpl())
+                .add(self.metrics.to_string())
+                .add(format!("{:?}", flag_aliases.iter().sorted().collect_vec()))
+                .add(format!("{:?}", available.iter().sorted().collect_vec()));
+            if let Some(aliases) = &self.aliases {
+                input.add_file(aliases);
+            }
+            let input = input.finish();
+            let outputs = [work_dir.ttx_tmpl(), work_dir.glyph_map()];
+            let outputs = [outputs[0].as_path(), outputs[1].as_path()];
 
-        info!("Building TTF");
-        match noto_emoji_utils::build_ttf(&self.build_path) {
-            Ok(_) => (),
-            Err(err) => {
-                let gil = Python::acquire_gil();
-                let py = gil.python();
-                err.print(py);
-                panic!()
+            let glyph_map = if self.stages.restore(font, Stage::AddGlyphs, &input, &outputs) {
+                info!("The glyphs haven't changed since the last build");
+                GlyphMap::from_file(work_dir.glyph_map())?
+            } else {
+                info!("Adding glyphs");
+                if self.chunk_size.is_none() && emojis.len() > LARGE_SET_EMOJIS {
+                    info!("All {} images are handed to add_glyphs.py at once, --chunk-size limits how many of them are read at a time", emojis.len());
+                }
+                if !self.metrics.is_empty() {
+                    info!("Using the font metrics {}", self.metrics);
+                }
+                let glyph_map = steps.add_glyphs(
+                    &self.aliases,
+                    &flag_aliases,
+                    emojis,
+                    &self.build_dir.ttx_tmpl_tmpl(),
+                    &work_dir.ttx_tmpl(),
+                    &GlyphOptions {
+                        add_cmap4_and_glyf: add_cmap_and_glyf,
+                        metrics: self.metrics,
+                        chunk_size: self.chunk_size
+                    }
+                )?;
+                glyph_map.write(work_dir.glyph_map())?;
+                self.stages.complete(font, Stage::AddGlyphs, &input, &outputs)?;
+                glyph_map
+            };
+            // Both fonts may be built at the same time, so only the normal one writes to the
+            // build directory
+            if !add_cmap_and_glyf && !glyph_map.glyphs.is_empty() {
+                glyph_map.write(self.build_dir.glyph_map())?;
+                if self.content_addressed_pngs {
+                    self.write_manifest(emojis, Some(&glyph_map))?;
+                }
+            }
+            if let (true, FontOutput::File(output_file)) = (self.glyph_map, &output) {
+                glyph_map.write(GlyphMap::next_to(output_file))?;
             }
+            Some(glyph_map)
         };
 
-        info!("Doing... something");
-        match noto_emoji_utils::emoji_builder(&self.build_path, add_cmap_and_glyf) {
-            Ok(_) => (),
-            Err(err) => {
-                let gil = Python::acquire_gil();
-                let py = gil.python();
-                err.print(py);
-                panic!()
+        let mut input = Fingerprint::new();
+        input.add_file(work_dir.ttx_tmpl())
+            .add(format!("{:?}", self.ttx_compiler));
+        let input = input.finish();
+        let tmpl_ttf = work_dir.tmpl_ttf();
+        if self.stages.restore(font, Stage::BuildTtf, &input, &[&tmpl_ttf]) {
+            info!("The TTX file hasn't changed since the last build");
+        } else {
+            info!("Building TTF");
+            match self.ttx_compiler {
+                TtxCompiler::FontTools => steps.build_ttf(&work_dir.ttx_tmpl(), &tmpl_ttf)?,
+                TtxCompiler::Rust => if let Err(err) = ttx_compiler::compile(&work_dir.ttx_tmpl(), &tmpl_ttf) {
+                    warn!("Couldn't compile the TTX file without fontTools, falling back to it: {}", err);
+                    self.diagnostics.report(Diagnostic::warning(Category::Font, format!("Couldn't compile the TTX file without fontTools: {}", err)));
+                    steps.build_ttf(&work_dir.ttx_tmpl(), &tmpl_ttf)?;
+                }
             }
-        };
+            self.stages.complete(font, Stage::BuildTtf, &input, &[&tmpl_ttf])?;
+        }
 
-        info!("Mapping PUA");
-        match noto_emoji_utils::map_pua(&self.build_path) {
-            Ok(_) => (),
-            Err(err) => {
-                let gil = Python::acquire_gil();
-                let py = gil.python();
-                err.print(py);
-                panic!()
+        // The Windows font always needs the outlines
+        let keep_outlines = add_cmap_and_glyf || self.keep_outlines;
+        let mut input = Fingerprint::new();
+        input.add_file(&tmpl_ttf)
+            .add(format!("{:?} {:?}", keep_outlines, self.cbdt_metrics));
+        for (emoji, prepared) in emojis.iter().sorted_by(|(a, _), (b, _)| a.sequence.cmp(&b.sequence)) {
+            input.add(GlyphMap::sequence_key(&emoji.sequence));
+            if let Ok((path, _)) = prepared {
+                input.add_file(path);
             }
-        };
+        }
+        let input = input.finish();
+        let ttf = work_dir.ttf();
+        if self.stages.restore(font, Stage::EmojiBuilder, &input, &[&ttf]) {
+            info!("The images haven't changed since the last build");
+        } else {
+            info!("Doing... something");
+            let png_dir = if self.content_addressed_pngs {
+                Blobmoji::stage_pngs(emojis, &work_dir)?
+            } else {
+                self.build_dir.png_dir()
+            };
+            steps.emoji_builder(
+                &tmpl_ttf,
+                &ttf,
+                &png_dir.join("emoji_u"),
+                keep_outlines,
+                self.cbdt_metrics
+            )?;
+            self.stages.complete(font, Stage::EmojiBuilder, &input, &[&ttf])?;
+        }
 
-        info!("Adding Version Selector");
-        match noto_emoji_utils::add_vs_cmap(&self.build_path) {
-            Ok(_) => (),
-            Err(err) => {
-                let gil = Python::acquire_gil();
-                let py = gil.python();
-                err.print(py);
-                panic!()
+        // Vertical text (e.g. in CJK contexts) needs the vertical metrics of every emoji if the
+        // template has them at all
+        if let (Some(glyph_map), Some(vertical)) = (&glyph_map, steps.vertical_glyphs(&ttf)?) {
+            let missing = glyph_map.glyphs.values()
+                .filter(|glyph| !vertical.contains(*glyph))
+                .unique()
+                .sorted()
+                .cloned()
+                .collect_vec();
+            if !missing.is_empty() {
+                return Err(BlobmojiError::MissingVerticalMetrics(missing));
             }
-        };
+        }
 
-        rename(
-            self.build_path.join(TTF_WITH_PUA_VARSE1),
-            self.build_path.join(TTF)
-        ).unwrap();
+        // Private emoji sets are already in the PUA and don't contain any of the emojis that
+        // would get a variation selector
+        let private_set = emojis.keys().all(|emoji| emoji.is_private_use());
+        if private_set {
+            info!("Only Private Use Area codepoints, not mapping PUA and variation selectors");
+        } else {
+            info!("Mapping PUA");
+            steps.map_pua(&work_dir.ttf(), &work_dir.ttf_with_pua())?;
 
-        copy(self.build_path.join(TTF), output_file).unwrap();
+            let vs_added = noto_emoji_utils::variation_selector_bases(self.variation_bases.as_ref(), emojis);
+            info!("Adding {} emoji variation sequences", vs_added.len());
+            steps.add_vs_cmap(&work_dir.ttf_with_pua(), &work_dir.ttf_with_pua_varse1(), &vs_added)?;
 
-        remove_file(self.build_path.join(TTF_WITH_PUA)).unwrap();
-        remove_file(self.build_path.join(TMPL_TTX)).unwrap();
-        remove_file(self.build_path.join(TMPL_TTF)).unwrap();
-        remove_file(self.build_path.join(TTF)).unwrap();
+            rename(
+                work_dir.ttf_with_pua_varse1(),
+                work_dir.ttf()
+            )?;
+        }
+
+        if let Some((description, url)) = font_license(&self.attribution) {
+            info!("Adding the license");
+            steps.set_license(&work_dir.ttf(), &work_dir.ttf_with_license(), &description, url.as_deref())?;
+            rename(
+                work_dir.ttf_with_license(),
+                work_dir.ttf()
+            )?;
+        }
+
+        match output {
+            FontOutput::File(output_file) => {
+                copy(work_dir.ttf(), &output_file)?;
+
+                if self.woff2 {
+                    let woff2_file = output_file.with_extension("woff2");
+                    info!("Compressing to WOFF2");
+                    match steps.compress_woff2(&output_file, &woff2_file) {
+                        Ok(_) => match std::fs::metadata(&woff2_file) {
+                            Ok(metadata) => info!("Wrote {:?} ({} bytes)", woff2_file, metadata.len()),
+                            Err(err) => warn!("Couldn't read the size of {:?}: {:?}", woff2_file, err)
+                        },
+                        // The TTF file is still usable, so this isn't fatal
+                        Err(err) => {
+                            match err {
+                                BlobmojiError::PythonError(message) => warn!("Couldn't create {:?}:\n{}", woff2_file, message),
+                                err => warn!("Couldn't create {:?}: {:?}", woff2_file, err)
+                            }
+                            self.diagnostics.report(Diagnostic::warning(Category::Font, "Couldn't compress the font to WOFF2").with_path(woff2_file));
+                        }
+                    }
+                }
+            },
+            FontOutput::Writer(writer) => {
+                std::io::copy(&mut File::open(work_dir.ttf())?, writer)?;
+                writer.flush()?;
+            }
+        }
+
+        if self.keep_intermediate {
+            info!("Keeping the intermediate files in {:?}", work_dir.path());
+        } else {
+            work_dir.remove()?;
+        }
+        Ok(())
     }
 }
 
@@ -710,4 +2019,774 @@ fn test_hex() {
     assert_eq!(5, hex_len(b));
     assert_eq!(0, hex_len(c));
     assert_eq!(1, hex_len(d));
-}
\ No newline at end of file
+}
+#[test]
+fn test_prepare_batch() {
+    let emojis: Vec<Emoji> = std::fs::read_dir("test_files/svg").unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| Emoji::from_path(entry.path(), None, false).unwrap())
+        .collect();
+    let emojis: Vec<&Emoji> = emojis.iter().collect();
+
+    let single_dir = tempfile::tempdir().unwrap();
+    let single = Blobmoji::new(single_dir.path().to_path_buf(), None).unwrap();
+    let batch_dir = tempfile::tempdir().unwrap();
+    let batch = Blobmoji::new(batch_dir.path().to_path_buf(), None).unwrap();
+
+    let prepared_single: HashMap<_, _> = emojis.iter()
+        .map(|emoji| (*emoji, single.prepare(emoji)))
+        .collect();
+    let prepared_batch = batch.prepare_batch(&emojis);
+
+    // The render options are set up once per emoji without the batch, but only once with it
+    assert_eq!(single.render_options_built.load(Ordering::Relaxed), emojis.len());
+    assert_eq!(batch.render_options_built.load(Ordering::Relaxed), 1);
+
+    assert_eq!(prepared_batch.len(), prepared_single.len());
+    for emoji in &emojis {
+        let ((single_path, single_hash), _) = prepared_single[emoji].as_ref().unwrap();
+        let ((batch_path, batch_hash), _) = prepared_batch[emoji].as_ref().unwrap();
+        assert_eq!(single_path.file_name(), batch_path.file_name());
+        assert_eq!(single_hash.as_ref().unwrap(), batch_hash.as_ref().unwrap());
+        assert_eq!(std::fs::read(single_path).unwrap(), std::fs::read(batch_path).unwrap());
+    }
+}
+
+#[test]
+fn test_corrupt_svg_diagnostics() {
+    let dir = tempfile::tempdir().unwrap();
+    let svg = dir.path().join("emoji_u1f914.svg");
+    std::fs::write(&svg, b"<svg this is not").unwrap();
+    let emoji = Emoji::from_path(svg.clone(), None, false).unwrap();
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let diagnostics = DiagnosticsSink::new();
+    blobmoji.set_diagnostics(diagnostics.clone());
+    assert!(blobmoji.prepare(&emoji).is_err());
+
+    let diagnostics = diagnostics.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, crate::diagnostics::Severity::Error);
+    assert_eq!(diagnostics[0].category, Category::Render);
+    assert_eq!(diagnostics[0].sequence, Some(String::from("1F914")));
+    assert_eq!(diagnostics[0].path, Some(svg));
+}
+
+#[test]
+fn test_font_fallback_diagnostics() {
+    let dir = tempfile::tempdir().unwrap();
+    let svg = dir.path().join("emoji_u1f524.svg");
+    std::fs::write(&svg, br#"<svg xmlns="http://www.w3.org/2000/svg" width="32" height="32">
+        <text x="2" y="20" font-family="Nonexistent Emoji Sans">abc</text>
+    </svg>"#).unwrap();
+    let emoji = Emoji::from_path(svg, None, false).unwrap();
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let diagnostics = DiagnosticsSink::new();
+    blobmoji.set_diagnostics(diagnostics.clone());
+    let _ = blobmoji.prepare(&emoji);
+    let fallbacks: Vec<_> = diagnostics.diagnostics().into_iter()
+        .filter(|diagnostic| diagnostic.message.contains("Nonexistent Emoji Sans"))
+        .collect();
+    assert_eq!(fallbacks.len(), 1);
+    assert_eq!(fallbacks[0].severity, crate::diagnostics::Severity::Warning);
+    assert_eq!(fallbacks[0].sequence, Some(String::from("1F524")));
+
+    blobmoji.require_fonts = true;
+    match blobmoji.prepare(&emoji) {
+        Err(BlobmojiError::MissingFonts(families)) => assert_eq!(families, vec![String::from("Nonexistent Emoji Sans")]),
+        other => panic!("Expected missing fonts, got {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn test_cached_png_size() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    let prepare = || {
+        let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+        let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+        let path = prepared.as_ref().unwrap().0.clone();
+        blobmoji.finish(vec![(&emoji, prepared)].into_iter().collect()).unwrap();
+        path
+    };
+    let path = prepare();
+
+    // A PNG file from an older build with a different size is rendered again
+    tiny_skia::Pixmap::new(72, 64).unwrap().save_png(&path).unwrap();
+    assert_eq!(prepare(), path);
+    assert_eq!(image_utils::png_dimensions(&path).unwrap(), (CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT));
+
+    // But one with the current size is reused
+    let mut cached = tiny_skia::Pixmap::new(CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT).unwrap();
+    cached.fill(tiny_skia::Color::from_rgba8(255, 0, 0, 255));
+    cached.save_png(&path).unwrap();
+    let cached = std::fs::read(&path).unwrap();
+    prepare();
+    assert_eq!(std::fs::read(&path).unwrap(), cached);
+}
+
+#[test]
+fn test_single_source_read() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let reads = || crate::changes::SOURCE_READS.with(|reads| reads.get());
+
+    // The file is read once for its hash and for rendering it
+    let before = reads();
+    let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+    assert_eq!(reads() - before, 1);
+    assert_eq!(blobmoji.renders.load(Ordering::Relaxed), 1);
+    let hash = prepared.as_ref().unwrap().1.as_ref().unwrap().to_vec();
+    blobmoji.finish(vec![(&emoji, prepared)].into_iter().collect()).unwrap();
+
+    // And once to check that it hasn't changed
+    let before = reads();
+    let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+    assert_eq!(reads() - before, 1);
+    assert_eq!(blobmoji.renders.load(Ordering::Relaxed), 1);
+    assert_eq!(prepared.unwrap().1.unwrap().to_vec(), hash);
+    assert_eq!(FileHashes::hash(&emoji).unwrap().to_vec(), hash);
+}
+
+#[test]
+fn test_waveflag_mask_cache() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let mask_dir = tempfile::tempdir().unwrap();
+    let mask_path = mask_dir.path().join("mask.png");
+    let write_mask = |gray: u8| {
+        let mut encoder = png::Encoder::new(File::create(&mask_path).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&[gray, 255 - gray]).unwrap();
+        WaveMask::from_file(&mask_path).unwrap()
+    };
+    let flag = Emoji::from_path(PathBuf::from("test_files/flags/DE.svg"), None, true).unwrap();
+
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    blobmoji.waveflag = true;
+    blobmoji.waveflag_mask = Some(write_mask(0));
+    let prepare = |blobmoji: &mut Blobmoji| {
+        let prepared = blobmoji.prepare(&flag).map(|prepared| prepared.0);
+        blobmoji.finish(vec![(&flag, prepared)].into_iter().collect()).unwrap();
+        blobmoji.renders.load(Ordering::Relaxed)
+    };
+    assert_eq!(prepare(&mut blobmoji), 1);
+    assert_eq!(prepare(&mut blobmoji), 1);
+
+    // Another mask invalidates the cached flag
+    blobmoji.waveflag_mask = Some(write_mask(255));
+    assert_eq!(prepare(&mut blobmoji), 2);
+}
+
+#[test]
+fn test_undo() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    let undo_dir = blobmoji.build_dir.undo_dir();
+    let undone_files = || std::fs::read_dir(&undo_dir).unwrap().count();
+
+    let ((path, hash), _) = blobmoji.prepare(&emoji).unwrap();
+    let hash = hash.unwrap();
+    let png = std::fs::read(&path).unwrap();
+    let undone = blobmoji.undo(&emoji, Ok((path.clone(), Ok(hash.clone())))).unwrap();
+    assert!(matches!(undone, Err(BlobmojiError::EmojiInvalidated)));
+    assert!(!path.exists());
+    assert_eq!(undone_files(), 1);
+
+    // Preparing it again restores the image instead of rendering it
+    let ((restored_path, restored_hash), _) = blobmoji.prepare(&emoji).unwrap();
+    assert_eq!(blobmoji.renders.load(Ordering::Relaxed), 1);
+    assert_eq!(restored_path, path);
+    assert_eq!(restored_hash.unwrap(), hash);
+    assert_eq!(std::fs::read(&path).unwrap(), png);
+    assert_eq!(undone_files(), 0);
+
+    // Undone images that are never restored are pruned
+    let undone = blobmoji.undo(&emoji, Ok((path, Ok(hash)))).unwrap();
+    assert!(matches!(undone, Err(BlobmojiError::EmojiInvalidated)));
+    assert_eq!(undone_files(), 1);
+    blobmoji.prune_stale = true;
+    let failed = vec![(&emoji, Err(BlobmojiError::UnknownError))].into_iter().collect();
+    blobmoji.check_png_dir(&failed).unwrap();
+    assert_eq!(undone_files(), 0);
+}
+
+#[test]
+fn test_migrate_build_dir() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+    let path = prepared.as_ref().unwrap().0.clone();
+    let png = std::fs::read(&path).unwrap();
+    blobmoji.finish(vec![(&emoji, prepared)].into_iter().collect()).unwrap();
+
+    // Turn it into a build directory from before the PNG directory
+    let old_png = build_dir.path().join(Blobmoji::generate_filename(&emoji));
+    rename(&path, &old_png).unwrap();
+    std::fs::remove_dir(path.parent().unwrap()).unwrap();
+    rename(blobmoji.build_dir.hashes(), build_dir.path().join("hash.csv")).unwrap();
+    remove_file(build_dir.path().join("layout_version")).unwrap();
+
+    // The image is still cached after the migration
+    let blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    assert_eq!(blobmoji.build_dir.layout_version().unwrap(), build_dir::LAYOUT_VERSION);
+    let ((migrated_path, _), _) = blobmoji.prepare(&emoji).unwrap();
+    assert_eq!(blobmoji.renders.load(Ordering::Relaxed), 0);
+    assert_eq!(migrated_path, path);
+    assert_eq!(std::fs::read(&path).unwrap(), png);
+    assert!(!old_png.exists());
+}
+
+#[test]
+fn test_check_png_dir() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let png_dir = blobmoji.build_dir.png_dir();
+
+    let emojis: Vec<Emoji> = std::fs::read_dir("test_files/svg").unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| Emoji::from_path(entry.path(), None, false).unwrap())
+        .collect();
+    let prepared = || emojis.iter()
+        .map(|emoji| (emoji, Ok((
+            png_dir.join(Blobmoji::generate_filename(emoji)),
+            FileHashes::hash(emoji)
+        ))))
+        .collect::<HashMap<_, _>>();
+    for emoji in &emojis {
+        std::fs::write(png_dir.join(Blobmoji::generate_filename(emoji)), b"png").unwrap();
+    }
+
+    // A leftover from an older build
+    let stale = png_dir.join("emoji_u1f9ff.png");
+    std::fs::write(&stale, b"png").unwrap();
+
+    // Without pruning, it's only a warning
+    assert!(blobmoji.check_png_dir(&prepared()).is_ok());
+    assert!(stale.exists());
+
+    blobmoji.prune_stale = true;
+    assert!(blobmoji.check_png_dir(&prepared()).is_ok());
+    assert!(!stale.exists());
+    assert_eq!(std::fs::read_dir(&png_dir).unwrap().count(), emojis.len());
+
+    // Files of emojis that failed to be prepared are not touched
+    let mut failed = prepared();
+    failed.insert(&emojis[0], Err(BlobmojiError::UnknownError));
+    assert!(blobmoji.check_png_dir(&failed).is_ok());
+    assert!(png_dir.join(Blobmoji::generate_filename(&emojis[0])).exists());
+
+    // Missing files are reported all at once
+    let missing = vec![
+        png_dir.join(Blobmoji::generate_filename(&emojis[1])),
+        png_dir.join(Blobmoji::generate_filename(&emojis[2])),
+    ];
+    missing.iter().for_each(|path| std::fs::remove_file(path).unwrap());
+    match blobmoji.check_png_dir(&prepared()) {
+        Err(BlobmojiError::MissingPngs(actual)) => assert_eq!(actual, missing.into_iter().sorted().collect_vec()),
+        other => panic!("Expected missing PNGs, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_supports_emoji() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+
+    let mut thinking = Emoji::from(vec![0x1f914]);
+    assert_eq!(blobmoji.supports_emoji(&thinking), SupportLevel::Unsupported(String::from("no SVG file")));
+    thinking.set_path(PathBuf::from("1f914.svg"));
+    assert_eq!(blobmoji.supports_emoji(&thinking), SupportLevel::Full);
+
+    // 50 codepoints with 5 hex digits each
+    let mut long = Emoji::from(vec![0x1f914; 50]);
+    long.set_path(PathBuf::from("long.svg"));
+    assert!(matches!(blobmoji.supports_emoji(&long), SupportLevel::Unsupported(_)));
+}
+
+#[test]
+fn test_concurrent_build_font() {
+    /// Passes the name of the font through all steps, like the actual scripts pass the glyphs
+    struct CopySteps;
+
+    impl CopySteps {
+        fn copy(input: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            let content = std::fs::read(input)?;
+            // Give the other font's build a chance to interfere
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            std::fs::write(output, content)?;
+            Ok(())
+        }
+    }
+
+    impl FontSteps for CopySteps {
+        fn add_glyphs(&self, _: &Option<PathBuf>, _: &HashMap<Vec<u32>, Vec<u32>>, _: &PreparedEmojis, _: &Path, ttx: &Path, options: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+            std::fs::write(ttx, if options.add_cmap4_and_glyf { "win" } else { "normal" })?;
+            Ok(GlyphMap::default())
+        }
+
+        fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+            CopySteps::copy(ttx, ttf)
+        }
+
+        fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, _: &Path, _: bool, _: CbdtMetrics) -> Result<(), BlobmojiError> {
+            CopySteps::copy(tmpl_ttf, ttf)
+        }
+
+        fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            CopySteps::copy(ttf, output)
+        }
+
+        fn add_vs_cmap(&self, ttf: &Path, output: &Path, _: &HashSet<u32>) -> Result<(), BlobmojiError> {
+            CopySteps::copy(ttf, output)
+        }
+
+        fn set_license(&self, ttf: &Path, output: &Path, _: &str, _: Option<&str>) -> Result<(), BlobmojiError> {
+            CopySteps::copy(ttf, output)
+        }
+    }
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    blobmoji.font_steps = Box::new(CopySteps);
+
+    let emoji = Emoji::from(vec![0x1f914]);
+    let emojis: PreparedEmojis = vec![(&emoji, Ok((blobmoji.build_dir.png(&emoji), Ok(Default::default()))))]
+        .into_iter()
+        .collect();
+    let normal = output_dir.path().join("font.ttf");
+    let windows = output_dir.path().join("font_win.ttf");
+
+    let (normal_result, windows_result) = rayon::join(
+        || blobmoji.build_font(&emojis, &normal, false),
+        || blobmoji.build_font(&emojis, &windows, true)
+    );
+    normal_result.unwrap();
+    windows_result.unwrap();
+    assert_eq!(std::fs::read_to_string(&normal).unwrap(), "normal");
+    assert_eq!(std::fs::read_to_string(&windows).unwrap(), "win");
+    // The working directories are removed after a successful build
+    let work = build_dir.path().join("work");
+    assert_eq!(std::fs::read_dir(&work).unwrap().count(), 0);
+
+    blobmoji.keep_intermediate = true;
+    blobmoji.build_font(&emojis, &normal, false).unwrap();
+    let kept: Vec<PathBuf> = std::fs::read_dir(&work).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(kept.len(), 1);
+    assert!(kept[0].file_name().unwrap().to_string_lossy().starts_with("normal-"));
+    assert!(kept[0].join("font.tmpl.ttx").exists());
+}
+
+#[test]
+fn test_resume_stages() {
+    use std::sync::atomic::AtomicBool;
+
+    /// Counts how often each step has been run, emoji_builder fails if `fail` is set
+    #[derive(Default)]
+    struct FailingSteps {
+        runs: [AtomicUsize; 3],
+        fail: AtomicBool,
+    }
+
+    impl FailingSteps {
+        fn runs(&self) -> [usize; 3] {
+            [
+                self.runs[0].load(Ordering::SeqCst),
+                self.runs[1].load(Ordering::SeqCst),
+                self.runs[2].load(Ordering::SeqCst),
+            ]
+        }
+    }
+
+    impl FontSteps for Arc<FailingSteps> {
+        fn add_glyphs(&self, _: &Option<PathBuf>, _: &HashMap<Vec<u32>, Vec<u32>>, emojis: &PreparedEmojis, _: &Path, ttx: &Path, _: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+            self.runs[0].fetch_add(1, Ordering::SeqCst);
+            std::fs::write(ttx, format!("{} glyphs", emojis.len()))?;
+            let mut glyph_map = GlyphMap::default();
+            glyph_map.insert(&[0x1f914], String::from("u1F914"));
+            Ok(glyph_map)
+        }
+
+        fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+            self.runs[1].fetch_add(1, Ordering::SeqCst);
+            copy(ttx, ttf)?;
+            Ok(())
+        }
+
+        fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, _: &Path, _: bool, _: CbdtMetrics) -> Result<(), BlobmojiError> {
+            self.runs[2].fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(BlobmojiError::PythonError(String::from("No module named 'nototools'")));
+            }
+            copy(tmpl_ttf, ttf)?;
+            Ok(())
+        }
+
+        fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn add_vs_cmap(&self, ttf: &Path, output: &Path, _: &HashSet<u32>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn set_license(&self, ttf: &Path, output: &Path, _: &str, _: Option<&str>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+    }
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let output = output_dir.path().join("font.ttf");
+    let steps = Arc::new(FailingSteps::default());
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    blobmoji.font_steps = Box::new(steps.clone());
+    blobmoji.glyph_map = true;
+
+    let emoji = Emoji::from(vec![0x1f914]);
+    let emojis: PreparedEmojis = vec![(&emoji, Ok((blobmoji.build_dir.png(&emoji), Ok(Default::default()))))]
+        .into_iter()
+        .collect();
+
+    // The build fails between the stages
+    steps.fail.store(true, Ordering::SeqCst);
+    assert!(blobmoji.build_font(&emojis, &output, false).is_err());
+    assert_eq!(steps.runs(), [1, 1, 1]);
+    assert!(build_dir.path().join("stages.json").is_file());
+
+    // The rerun continues with the stage that has failed
+    steps.fail.store(false, Ordering::SeqCst);
+    blobmoji.build_font(&emojis, &output, false).unwrap();
+    assert_eq!(steps.runs(), [1, 1, 2]);
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "1 glyphs");
+    // The glyph map is restored as well
+    let glyph_map = GlyphMap::from_file(GlyphMap::next_to(&output)).unwrap();
+    assert_eq!(glyph_map.get(&[0x1f914]), Some("u1F914"));
+
+    // Nothing has changed
+    blobmoji.build_font(&emojis, &output, false).unwrap();
+    assert_eq!(steps.runs(), [1, 1, 2]);
+    // The other font has its own stages
+    blobmoji.build_font(&emojis, &output_dir.path().join("font_win.ttf"), true).unwrap();
+    assert_eq!(steps.runs(), [2, 2, 3]);
+
+    // --from-stage runs the stages again
+    blobmoji.stages = StageStore::open(&blobmoji.build_dir, Some(Stage::BuildTtf));
+    blobmoji.build_font(&emojis, &output, false).unwrap();
+    assert_eq!(steps.runs(), [2, 3, 4]);
+
+    // Changed inputs as well
+    blobmoji.stages = StageStore::open(&blobmoji.build_dir, None);
+    let other = Emoji::from(vec![0x1f600]);
+    let more: PreparedEmojis = vec![&emoji, &other].into_iter()
+        .map(|emoji| (emoji, Ok((blobmoji.build_dir.png(emoji), Ok(Default::default())))))
+        .collect();
+    blobmoji.build_font(&more, &output, false).unwrap();
+    assert_eq!(steps.runs(), [3, 4, 5]);
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "2 glyphs");
+}
+
+#[test]
+fn test_missing_vertical_metrics() {
+    /// Copies the files around, the font has vertical metrics for the given glyphs
+    struct VerticalSteps(Option<HashSet<String>>);
+
+    impl FontSteps for VerticalSteps {
+        fn add_glyphs(&self, _: &Option<PathBuf>, _: &HashMap<Vec<u32>, Vec<u32>>, _: &PreparedEmojis, _: &Path, ttx: &Path, _: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+            std::fs::write(ttx, "")?;
+            let mut glyph_map = GlyphMap::default();
+            glyph_map.insert(&[0x1f914], String::from("u1F914"));
+            glyph_map.insert(&[0x1f600], String::from("u1F600"));
+            Ok(glyph_map)
+        }
+
+        fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+            copy(ttx, ttf)?;
+            Ok(())
+        }
+
+        fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, _: &Path, _: bool, _: CbdtMetrics) -> Result<(), BlobmojiError> {
+            copy(tmpl_ttf, ttf)?;
+            Ok(())
+        }
+
+        fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn add_vs_cmap(&self, ttf: &Path, output: &Path, _: &HashSet<u32>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn set_license(&self, ttf: &Path, output: &Path, _: &str, _: Option<&str>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn vertical_glyphs(&self, _: &Path) -> Result<Option<HashSet<String>>, BlobmojiError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let output = build_dir.path().join("font.ttf");
+    let mut blobmoji = Blobmoji::new(build_dir.path().join("build"), None).unwrap();
+    let thinking = Emoji::from(vec![0x1f914]);
+    let grinning = Emoji::from(vec![0x1f600]);
+    let emojis: PreparedEmojis = vec![&thinking, &grinning].into_iter()
+        .map(|emoji| (emoji, Ok((blobmoji.build_dir.png(emoji), Ok(Default::default())))))
+        .collect();
+
+    // Without vhea, the glyphs don't need vertical metrics
+    blobmoji.font_steps = Box::new(VerticalSteps(None));
+    blobmoji.build_font(&emojis, &output, false).unwrap();
+
+    blobmoji.font_steps = Box::new(VerticalSteps(Some(vec![String::from("u1F914"), String::from("u1F600")].into_iter().collect())));
+    blobmoji.build_font(&emojis, &output, false).unwrap();
+
+    blobmoji.font_steps = Box::new(VerticalSteps(Some(vec![String::from("u1F914")].into_iter().collect())));
+    match blobmoji.build_font(&emojis, &output, false) {
+        Err(BlobmojiError::MissingVerticalMetrics(missing)) => assert_eq!(missing, vec![String::from("u1F600")]),
+        other => panic!("Unexpected result {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn test_content_addressed_pngs() {
+    /// Uses the staged image of U+1F93F as the font
+    struct PngSteps;
+
+    impl FontSteps for PngSteps {
+        fn add_glyphs(&self, _: &Option<PathBuf>, _: &HashMap<Vec<u32>, Vec<u32>>, _: &PreparedEmojis, _: &Path, ttx: &Path, _: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+            std::fs::write(ttx, "")?;
+            Ok(GlyphMap::default())
+        }
+
+        fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+            copy(ttx, ttf)?;
+            Ok(())
+        }
+
+        fn emoji_builder(&self, _: &Path, ttf: &Path, prefix: &Path, _: bool, _: CbdtMetrics) -> Result<(), BlobmojiError> {
+            copy(format!("{}1f93f.png", prefix.to_string_lossy()), ttf)?;
+            Ok(())
+        }
+
+        fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn add_vs_cmap(&self, ttf: &Path, output: &Path, _: &HashSet<u32>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn set_license(&self, ttf: &Path, output: &Path, _: &str, _: Option<&str>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+    }
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let source_dir = tempfile::tempdir().unwrap();
+    let svg = source_dir.path().join("emoji_u1f93f.svg");
+    let write_svg = |color: &str| std::fs::write(&svg, format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="128" height="128"><rect width="128" height="128" fill="{}"/></svg>"#,
+        color
+    )).unwrap();
+    let emoji = Emoji::from_path(svg.clone(), None, false).unwrap();
+
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    blobmoji.content_addressed_pngs = true;
+    blobmoji.prune_stale = true;
+    blobmoji.font_steps = Box::new(PngSteps);
+    let mut prepare = || {
+        let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+        let path = prepared.as_ref().unwrap().0.clone();
+        blobmoji.finish(vec![(&emoji, prepared)].into_iter().collect()).unwrap();
+        path
+    };
+
+    write_svg("red");
+    let red = prepare();
+    write_svg("blue");
+    let blue = prepare();
+    assert_ne!(red, blue);
+    let name = blue.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(name.starts_with("emoji_u1f93f.") && name.ends_with(".png"));
+    assert_eq!(name.len(), "emoji_u1f93f.0123abcd.png".len());
+
+    // Both generations are kept, even when pruning
+    let prepared: PreparedEmojis = vec![(&emoji, Ok((blue.clone(), FileHashes::hash(&emoji))))]
+        .into_iter()
+        .collect();
+    blobmoji.check_png_dir(&prepared).unwrap();
+    assert!(red.exists());
+    assert!(blue.exists());
+
+    // The manifest points to the current generation
+    let manifest = std::fs::read_to_string(blobmoji.build_dir.manifest()).unwrap();
+    assert_eq!(manifest.trim(), format!("1f93f,{},{},", name, emoji.short_id()));
+
+    // Going back to the old source doesn't render again
+    write_svg("red");
+    let renders = blobmoji.renders.load(Ordering::Relaxed);
+    let prepared: PreparedEmojis = vec![(&emoji, Ok((red.clone(), FileHashes::hash(&emoji))))]
+        .into_iter()
+        .collect();
+    let ((path, _), _) = blobmoji.prepare(&emoji).unwrap();
+    assert_eq!(path, red);
+    assert_eq!(blobmoji.renders.load(Ordering::Relaxed), renders);
+
+    // The font is built from the image under its usual name
+    let output_dir = tempfile::tempdir().unwrap();
+    let font = output_dir.path().join("font.ttf");
+    blobmoji.build_font(&prepared, &font, false).unwrap();
+    assert_eq!(std::fs::read(&font).unwrap(), std::fs::read(&red).unwrap());
+}
+
+#[test]
+fn test_render_check() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    let diagnostics = DiagnosticsSink::new();
+    blobmoji.set_diagnostics(diagnostics.clone());
+
+    let empty = Emoji::from_path(PathBuf::from("test_files/render_check/emoji_u1f47b.svg"), None, false).unwrap();
+    assert!(blobmoji.prepare(&empty).is_ok());
+    let reported = diagnostics.diagnostics();
+    assert_eq!(reported.len(), 1);
+    assert_eq!(reported[0].category, Category::Render);
+    assert!(reported[0].message.contains("transparent"), "{}", reported[0].message);
+
+    // A regular emoji isn't reported
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    assert!(blobmoji.prepare(&emoji).is_ok());
+    assert_eq!(diagnostics.diagnostics().len(), 1);
+}
+
+#[test]
+fn test_build_to_writer() {
+    /// Builds a font that only consists of the sfnt version
+    struct SfntSteps;
+
+    impl FontSteps for SfntSteps {
+        fn add_glyphs(&self, _: &Option<PathBuf>, _: &HashMap<Vec<u32>, Vec<u32>>, _: &PreparedEmojis, _: &Path, ttx: &Path, _: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+            std::fs::write(ttx, [0u8, 1, 0, 0])?;
+            Ok(GlyphMap::default())
+        }
+
+        fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+            copy(ttx, ttf)?;
+            Ok(())
+        }
+
+        fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, _: &Path, _: bool, _: CbdtMetrics) -> Result<(), BlobmojiError> {
+            copy(tmpl_ttf, ttf)?;
+            Ok(())
+        }
+
+        fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn add_vs_cmap(&self, ttf: &Path, output: &Path, _: &HashSet<u32>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+
+        fn set_license(&self, ttf: &Path, output: &Path, _: &str, _: Option<&str>) -> Result<(), BlobmojiError> {
+            copy(ttf, output)?;
+            Ok(())
+        }
+    }
+
+    let build_dir = tempfile::tempdir().unwrap();
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), None).unwrap();
+    blobmoji.font_steps = Box::new(SfntSteps);
+
+    let emoji = Emoji::from(vec![0x1f914]);
+    let png = blobmoji.build_dir.png(&emoji);
+    Pixmap::new(136, 128).unwrap().save_png(&png).unwrap();
+    let prepared = || -> PreparedEmojis {
+        vec![(&emoji, Ok((png.clone(), Ok(Default::default()))))].into_iter().collect()
+    };
+
+    let mut output = Vec::new();
+    blobmoji.build_to_writer(prepared(), &mut output).unwrap();
+    assert_eq!(output, [0, 1, 0, 0]);
+    // Only the normal font has been built
+    let stages = std::fs::read_to_string(blobmoji.build_dir.stages()).unwrap();
+    assert!(stages.contains("normal") && !stages.contains("win"), "{}", stages);
+
+    // There's only one stream for the Windows font as well
+    blobmoji.build_win = true;
+    match blobmoji.build_to_writer(prepared(), &mut Vec::new()) {
+        Err(BlobmojiError::ConfigError(ConfigError::InvalidValue(key, _))) => assert_eq!(key, "win10"),
+        other => panic!("Expected a config error, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_cache_namespaces() {
+    let build_dir = tempfile::tempdir().unwrap();
+    // Another version of the same emoji, e.g. from another branch
+    let other_dir = tempfile::tempdir().unwrap();
+    let other_svg = other_dir.path().join("emoji_u1f93f.svg");
+    std::fs::copy("test_files/svg/emoji_u1f9a6.svg", &other_svg).unwrap();
+    let sources = vec![
+        ("a", Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap()),
+        ("b", Emoji::from_path(other_svg, None, false).unwrap()),
+    ];
+    let prepare = |key: &str, emoji: &Emoji| {
+        let matches = Blobmoji::sub_command().get_matches_from(vec!["blobmoji", "--cache-key", key]);
+        let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), Some(matches)).unwrap();
+        let prepared = blobmoji.prepare(emoji).map(|prepared| prepared.0);
+        blobmoji.finish(vec![(emoji, prepared)].into_iter().collect()).unwrap();
+        blobmoji.renders.load(Ordering::Relaxed)
+    };
+    for (key, emoji) in &sources {
+        assert_eq!(prepare(key, emoji), 1);
+    }
+    // Switching between the keys doesn't invalidate the other one's cache
+    for (key, emoji) in &sources {
+        assert_eq!(prepare(key, emoji), 0);
+    }
+}
+
+#[test]
+fn test_cache_status() {
+    let build_dir = tempfile::tempdir().unwrap();
+    let emoji = Emoji::from_path(PathBuf::from("test_files/svg/emoji_u1f93f.svg"), None, false).unwrap();
+    let matches = |key: &str| Blobmoji::sub_command().get_matches_from(vec!["blobmoji", "--cache-key", key]);
+    let status = |key: &str| Blobmoji::cache_status(build_dir.path(), Some(&matches(key)), &emoji);
+    assert_eq!(status("a"), CacheStatus::NoCache);
+
+    let mut blobmoji = Blobmoji::new(build_dir.path().to_path_buf(), Some(matches("a"))).unwrap();
+    let prepared = blobmoji.prepare(&emoji).map(|prepared| prepared.0);
+    blobmoji.finish(vec![(&emoji, prepared)].into_iter().collect()).unwrap();
+    let namespaces = std::fs::read_to_string(build_dir.path().join("namespaces.json")).unwrap();
+
+    assert_eq!(status("a"), CacheStatus::UpToDate);
+    assert_eq!(status("b"), CacheStatus::NoCache);
+    assert_eq!(Blobmoji::cache_status(build_dir.path(), None, &emoji), CacheStatus::NoCache);
+    // Only looking at a namespace doesn't count as using it
+    assert_eq!(std::fs::read_to_string(build_dir.path().join("namespaces.json")).unwrap(), namespaces);
+    assert_eq!(Blobmoji::output_file_name(&emoji).as_deref(), Some("emoji_u1f93f.png"));
+}
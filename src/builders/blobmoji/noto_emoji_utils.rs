@@ -20,96 +20,328 @@ use std::collections::{HashMap, HashSet};
 use crate::emoji::Emoji;
 use crate::builders;
 use crate::builder::EmojiBuilder;
-use pyo3::{PyResult, Python, IntoPy};
+use pyo3::{PyAny, PyErr, PyResult, Python, ToPyObject};
 use itertools::Itertools;
 use pyo3::prelude::PyModule;
-use pyo3::types::{PyTuple, PyString, PyDict};
-use crate::builders::blobmoji::{TMPL_TTX, TMPL_TTF, TTF, PNG_DIR, TTF_WITH_PUA};
-use std::iter::FromIterator;
+use pyo3::types::{PyTuple, PyDict};
+#[cfg(test)]
+use crate::builders::blobmoji::BuildDir;
+use crate::builders::blobmoji::error::BlobmojiError;
+use crate::builders::blobmoji::gsub;
+use crate::builders::blobmoji::dedup::Duplicates;
+use crate::builders::blobmoji::glyph_map::GlyphMap;
+use std::str::FromStr;
+use crate::configs::ConfigError;
+use std::fmt::{Display, Formatter};
 
-const ADD_GLYPHS_PY: &str = include_str!("add_glyphs/add_glyphs.py");
-const ADD_ALIASES_PY: &str = include_str!("add_glyphs/add_aliases.py");
-const ADD_EMOJI_GSUB_PY: &str = include_str!("add_glyphs/add_emoji_gsub.py");
+pub(crate) const ADD_GLYPHS_PY: &str = include_str!("add_glyphs/add_glyphs.py");
+pub(crate) const ADD_ALIASES_PY: &str = include_str!("add_glyphs/add_aliases.py");
+pub(crate) const ADD_EMOJI_GSUB_PY: &str = include_str!("add_glyphs/add_emoji_gsub.py");
+
+/// The emojis with their prepared images (as they are given to [EmojiBuilder::build])
+pub type PreparedEmojis<'a> = HashMap<&'a Emoji, Result<
+    <builders::blobmoji::Blobmoji as EmojiBuilder>::PreparedEmoji,
+    <builders::blobmoji::Blobmoji as EmojiBuilder>::Err>
+>;
+
+/// The steps of the font build that are done by Python scripts.
+/// They only work on the files they are given, so that multiple fonts can be built from the same
+/// build directory at the same time (and so that they can be replaced in tests).
+pub trait FontSteps: Send + Sync {
+    /// Adds the glyphs of the emojis to the TTX template `ttx_tmpl` (as set up by `options`)
+    /// and writes it to `ttx`.
+    /// Returns the glyph names that have been assigned to the emojis (see [add_glyphs])
+    fn add_glyphs(&self,
+                  aliases: &Option<PathBuf>,
+                  flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                  emojis: &PreparedEmojis,
+                  ttx_tmpl: &Path,
+                  ttx: &Path,
+                  options: &GlyphOptions) -> Result<GlyphMap, BlobmojiError>;
+
+    /// Compiles `ttx` to `ttf` (see [build_ttf])
+    fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError>;
+
+    /// Adds the bitmaps to `tmpl_ttf` and writes the result to `ttf` (see [emoji_builder])
+    fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, png_prefix: &Path, keep_outlines: bool, metrics: CbdtMetrics) -> Result<(), BlobmojiError>;
+
+    /// Maps the emojis to the Private Use Area (see [map_pua])
+    fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError>;
+
+    /// Adds the emoji variation sequences for `vs_added` (besides the ones nototools knows anyway)
+    /// and checks that the font has all of them afterwards (see [add_vs_cmap])
+    fn add_vs_cmap(&self, ttf: &Path, output: &Path, vs_added: &HashSet<u32>) -> Result<(), BlobmojiError>;
+
+    /// Sets the license description and URL in the name table (see [set_license])
+    fn set_license(&self, ttf: &Path, output: &Path, description: &str, url: Option<&str>) -> Result<(), BlobmojiError>;
+
+    /// The glyphs that have vertical metrics in `ttf`, or `None` if it has none at all (i.e. no
+    /// `vhea` table, see [vertical_glyphs]). The default implementation doesn't read the font.
+    fn vertical_glyphs(&self, _ttf: &Path) -> Result<Option<HashSet<String>>, BlobmojiError> {
+        Ok(None)
+    }
+
+    /// Compresses a TTF file to WOFF2 (see [compress_woff2])
+    fn compress_woff2(&self, input: &Path, output: &Path) -> Result<(), BlobmojiError> {
+        with_python_error(compress_woff2(input, output))
+    }
+
+    /// Lets the steps print their messages to stderr instead of stdout, e.g. because the font
+    /// is written to stdout. The default implementation doesn't print anything anyway.
+    fn redirect_stdout(&self) -> Result<(), BlobmojiError> {
+        Ok(())
+    }
+}
+
+/// The actual [FontSteps], i.e. the scripts from noto-emoji
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PythonSteps;
+
+impl FontSteps for PythonSteps {
+    fn add_glyphs(&self,
+                  aliases: &Option<PathBuf>,
+                  flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                  emojis: &PreparedEmojis,
+                  ttx_tmpl: &Path,
+                  ttx: &Path,
+                  options: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+        add_glyphs(aliases, flag_aliases, emojis, ttx_tmpl.to_path_buf(), ttx.to_path_buf(), options)
+    }
+
+    fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+        with_python_error(build_ttf(ttx, ttf))
+    }
+
+    fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, png_prefix: &Path, keep_outlines: bool, metrics: CbdtMetrics) -> Result<(), BlobmojiError> {
+        with_python_error(emoji_builder(tmpl_ttf, ttf, png_prefix, keep_outlines, metrics))
+    }
+
+    fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+        with_python_error(map_pua(ttf, output))
+    }
+
+    fn add_vs_cmap(&self, ttf: &Path, output: &Path, vs_added: &HashSet<u32>) -> Result<(), BlobmojiError> {
+        with_python_error(add_vs_cmap(ttf, output, vs_added))?;
+        let in_font = with_python_error(read_vs_cmap(output))?;
+        debug!("The font has {} emoji variation sequences", in_font.len());
+        let missing = vs_added.difference(&in_font)
+            .copied()
+            .sorted()
+            .collect_vec();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(BlobmojiError::MissingVariationSequences(missing))
+        }
+    }
+
+    fn set_license(&self, ttf: &Path, output: &Path, description: &str, url: Option<&str>) -> Result<(), BlobmojiError> {
+        with_python_error(set_license(ttf, output, description, url))
+    }
+
+    fn vertical_glyphs(&self, ttf: &Path) -> Result<Option<HashSet<String>>, BlobmojiError> {
+        with_python_error(vertical_glyphs(ttf))
+    }
+
+    fn redirect_stdout(&self) -> Result<(), BlobmojiError> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let sys = PyModule::import(py, "sys").map_err(|err| python_error(py, err))?;
+        sys.getattr("stderr")
+            .and_then(|stderr| sys.setattr("stdout", stderr))
+            .map_err(|err| python_error(py, err))
+    }
+}
+
+fn with_python_error<T>(result: PyResult<T>) -> Result<T, BlobmojiError> {
+    result.map_err(|err| {
+        let gil = Python::acquire_gil();
+        python_error(gil.python(), err)
+    })
+}
 
 pub fn add_glyphs(aliases: &Option<PathBuf>,
-                  emojis: &HashMap<&Emoji, Result<
-                  <builders::blobmoji::Blobmoji as EmojiBuilder>::PreparedEmoji,
-                  <builders::blobmoji::Blobmoji as EmojiBuilder>::Err>
-              >,
+                  flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                  emojis: &PreparedEmojis,
                   ttx_tmpl: PathBuf,
                   ttx: PathBuf,
-                  // From https://github.com/googlefonts/noto-emoji/blob/main/Makefile $(EMOJI_WINDOWS).tmpl.ttx: ...
-                  add_cmap4_and_glyf: bool) -> PyResult<()> {
-    // seq_to_file: dir<codepoint sequence, file>
-    //  cps = emoji.sequence (with strings instead of u32)
-    //  seq = cps.filter(|cp| cp != fe0f)
-    //  check cps (codepoints) if between 0 and 0x10ffff
-    //  seq_to_file.add( sequence: path to corresponding image)
-    // Unfortunately parallel processing is not possible due to Python
-    let seq_to_file = emojis.iter()
-        .filter(|(_, prepared)| prepared.is_ok())
-        .map(|(emoji, prepared)| (
-            // First get the sequences as a list of strings instead of u32s
-            emoji.sequence.iter()
-                // In order to replicate the original behavior, we'll need to filter out fe0f
-                // variant selectors
-                // TODO: Revisit this behavior
-                .filter(|codepoint| **codepoint != 0xfe0fu32).collect_vec(),
-            // Then get the file output path
-            prepared.as_ref().unwrap().0.to_string_lossy().into_owned()
-        ));
-
-    // From https://pyo3.rs/master/python_from_rust.html
+                  options: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
     let gil = Python::acquire_gil();
     let py = gil.python();
+    add_glyphs_with(py, aliases, flag_aliases, emojis, ttx_tmpl, ttx, options)
+        .map_err(|err| python_error(py, err))
+}
 
+/// Formats a Python exception (with its traceback) as a [BlobmojiError::PythonError]
+fn python_error(py: Python, err: PyErr) -> BlobmojiError {
+    let formatted = PyModule::import(py, "traceback")
+        .and_then(|traceback| traceback.call1(
+            "format_exception",
+            (err.ptype(py), err.pvalue(py), err.ptraceback(py))
+        ))
+        .and_then(|lines| lines.extract::<Vec<String>>());
+    BlobmojiError::PythonError(match formatted {
+        Ok(lines) => lines.concat(),
+        Err(_) => format!("{:?}", err)
+    })
+}
+
+/// Converts a mapping with codepoint sequences as keys to a dict with tuples as keys (which is
+/// what add_glyphs.py uses).
+/// The items are added one by one, so there's no intermediate list of tuples.
+fn sequence_dict<'py, V: ToPyObject>(py: Python<'py>, entries: impl IntoIterator<Item = (Vec<u32>, V)>) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    for (sequence, value) in entries {
+        dict.set_item(PyTuple::new(py, sequence), value)?;
+    }
+    Ok(dict)
+}
 
-    // Prepare the modules that add_glyphs will need
+/// Loads add_glyphs.py and the modules it imports
+fn add_glyphs_module<'py>(py: Python<'py>) -> PyResult<&'py PyModule> {
     PyModule::from_code(
-        py,
-        ADD_EMOJI_GSUB_PY,
-        "add_emoji_gsub.py",
-        "add_emoji_gsub"
-    )?;
-    let add_aliases = PyModule::from_code(
         py,
         ADD_ALIASES_PY,
         "add_aliases.py",
         "add_aliases"
     )?;
+    PyModule::from_code(
+        py,
+        ADD_EMOJI_GSUB_PY,
+        "add_emoji_gsub.py",
+        "add_emoji_gsub"
+    )?;
     PyModule::from_code(
         py,
         PNG_PY,
         "third_party/color_emoji/png.py",
         "png"
     )?;
-
-    let add_glyphs_module = PyModule::from_code(
+    PyModule::from_code(
         py,
         ADD_GLYPHS_PY,
         "add_glyphs.py",
-        "add_glyphs")?;
+        "add_glyphs"
+    )
+}
 
+/// The glyphs and aliases that add_glyphs.py adds to the font, i.e. after the aliases (from the
+/// aliases file and the flag aliases) and the emojis with the same image have been resolved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct GlyphInput {
+    /// The sequences (without `U+FE0F`) and their images
+    pub seq_to_file: Vec<(Vec<u32>, String)>,
+    /// Sequences that use the glyph of another sequence
+    pub aliases: HashMap<Vec<u32>, Vec<u32>>,
+    /// All sequences that end up in the font, with the sequence that they're published as
+    pub published: Vec<(Vec<u32>, Vec<u32>)>,
+    /// The ligatures that every sequence gets (only with `add_cmap4_and_glyf`)
+    pub ligatures: Vec<gsub::Ligature>,
+}
 
-    // In order to use this mapping, we'll need to replace the update_ttx-function
-    // This code is mostly copied from https://github.com/googlefonts/noto-emoji/blob/f8131fc45736000552cd04a8388dc414d666a829/add_glyphs.py#L353
-    let aliases = match aliases {
-        Some(aliases) => Some(add_aliases.call1(
-            "read_emoji_aliases", (aliases.to_string_lossy().into_owned(),))?),
-        None => None
-    };
+impl GlyphInput {
+    /// `aliases` are the ones from the aliases file (as read by add_aliases.py), which take
+    /// precedence over the flag aliases
+    pub(crate) fn new(aliases: HashMap<Vec<u32>, Vec<u32>>,
+                      flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                      emojis: &PreparedEmojis,
+                      add_cmap4_and_glyf: bool) -> GlyphInput {
+        // seq_to_file: dir<codepoint sequence, file>
+        //  cps = emoji.sequence (with strings instead of u32)
+        //  seq = cps.filter(|cp| cp != fe0f)
+        //  check cps (codepoints) if between 0 and 0x10ffff
+        //  seq_to_file.add( sequence: path to corresponding image)
+        // Unfortunately parallel processing is not possible due to Python
+        let seq_to_file = emojis.iter()
+            .filter(|(_, prepared)| prepared.is_ok())
+            .map(|(emoji, prepared)| (
+                // First get the sequences as a list of strings instead of u32s
+                emoji.sequence.iter()
+                    // In order to replicate the original behavior, we'll need to filter out fe0f
+                    // variant selectors
+                    // TODO: Revisit this behavior
+                    .filter(|codepoint| **codepoint != 0xfe0fu32)
+                    .copied()
+                    .collect_vec(),
+                // Then get the file output path
+                prepared.as_ref().unwrap().0.to_string_lossy().into_owned()
+            ))
+            .collect_vec();
 
-    let seq_to_file: Vec<(&PyTuple, &PyString)> = seq_to_file
-        .map(|(sequence, filepath)|
-            (PyTuple::new(py, sequence), PyString::new(py, &filepath)))
-        .collect();
+        let mut aliases = aliases;
+        // The aliases file takes precedence
+        for (alias, flag) in flag_aliases {
+            aliases.entry(alias.clone()).or_insert_with(|| flag.clone());
+        }
+
+        // Emojis with identical PNGs share a glyph, so their bitmap is only embedded once
+        let excluded: HashSet<Vec<u32>> = aliases.keys().cloned().collect();
+        let duplicates = Duplicates::find(&seq_to_file, &excluded);
+        if !duplicates.is_empty() {
+            info!("Found {} emoji(s) with the same image as another one, saving {} bytes",
+                  duplicates.aliases.len(), duplicates.saved_bytes);
+        }
+        let seq_to_file = seq_to_file.into_iter()
+            .filter(|(sequence, _)| !duplicates.aliases.contains_key(sequence))
+            .collect_vec();
+        // Aliases whose target has been deduplicated use the remaining glyph instead
+        let mut aliases: HashMap<Vec<u32>, Vec<u32>> = aliases.into_iter()
+            .map(|(alias, target)| match duplicates.aliases.get(&target) {
+                Some(deduplicated) => (alias, deduplicated.clone()),
+                None => (alias, target)
+            })
+            .collect();
+        let mut published: Vec<(Vec<u32>, Vec<u32>)> = emojis.iter()
+            .filter(|(_, prepared)| prepared.is_ok())
+            .map(|(emoji, _)| (
+                emoji.sequence.iter()
+                    .filter(|codepoint| **codepoint != 0xfe0fu32)
+                    .copied()
+                    .collect_vec(),
+                emoji.sequence.clone()
+            ))
+            .collect();
+        published.extend(excluded.into_iter().map(|alias| (alias.clone(), alias)));
+        aliases.extend(duplicates.aliases);
 
-    let seq_to_file_dict = PyDict::from_sequence(py, seq_to_file.into_py(py))?;
+        let ligatures = if add_cmap4_and_glyf {
+            gsub::ligatures(seq_to_file.iter().map(|(sequence, _)| sequence.clone()))
+        } else {
+            vec![]
+        };
 
-    let aliases = aliases.map(|aliases| add_glyphs_module.call1(
-            "apply_aliases", (seq_to_file_dict, aliases)
-        ).unwrap());
+        GlyphInput {
+            seq_to_file,
+            aliases,
+            published,
+            ligatures,
+        }
+    }
+}
+
+fn add_glyphs_with(py: Python,
+                   aliases: &Option<PathBuf>,
+                   flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                   emojis: &HashMap<&Emoji, Result<
+                       <builders::blobmoji::Blobmoji as EmojiBuilder>::PreparedEmoji,
+                       <builders::blobmoji::Blobmoji as EmojiBuilder>::Err>
+                   >,
+                   ttx_tmpl: PathBuf,
+                   ttx: PathBuf,
+                   options: &GlyphOptions) -> PyResult<GlyphMap> {
+    let GlyphOptions { add_cmap4_and_glyf, metrics, chunk_size } = *options;
+    let add_glyphs_module = add_glyphs_module(py)?;
+    let add_aliases = PyModule::import(py, "add_aliases")?;
+    let aliases: HashMap<Vec<u32>, Vec<u32>> = match aliases {
+        Some(aliases) => add_aliases.call1(
+            "read_emoji_aliases", (aliases.to_string_lossy().into_owned(),))?.extract()?,
+        None => HashMap::new()
+    };
+    let GlyphInput { seq_to_file, aliases, published, ligatures } =
+        GlyphInput::new(aliases, flag_aliases, emojis, add_cmap4_and_glyf);
 
+    // In order to use this mapping, we'll need to replace the update_ttx-function
+    // This code is mostly copied from https://github.com/googlefonts/noto-emoji/blob/f8131fc45736000552cd04a8388dc414d666a829/add_glyphs.py#L353
     let ttx_module = PyModule::import(py, "fontTools.ttx")?;
 
 
@@ -117,6 +349,12 @@ pub fn add_glyphs(aliases: &Option<PathBuf>,
     // FIXME: Input file missing
     font.call_method1("importXML", (ttx_tmpl.to_string_lossy().into_owned(), ))?;
 
+    // The advances of the glyphs depend on the line height, so this has to be done first
+    if !metrics.is_empty() {
+        let override_metrics = PyModule::from_code(py, OVERRIDE_METRICS_PY, "override_metrics.py", "override_metrics")?;
+        override_metrics.call1("override_metrics", (font, metrics.ascent, metrics.descent, metrics.line_gap))?;
+    }
+
     let hhea = font.get_item("hhea")?;
     let ascent = hhea.getattr("ascent")?;
     let descent = hhea.getattr("descent")?;
@@ -129,69 +367,249 @@ pub fn add_glyphs(aliases: &Option<PathBuf>,
         "get_png_file_to_advance_mapper",
         (lineheight,)
     )?;
-    let seq_to_advance = add_glyphs_module.call1(
-        "remap_values",
-        (seq_to_file_dict, map_fn)
-    )?;
+    // Only the advances are kept, so the paths of a chunk (on both sides) can be dropped once
+    // its images have been read
+    let seq_to_advance = PyDict::new(py);
+    let chunk_size = chunk_size.unwrap_or(seq_to_file.len()).max(1);
+    for chunk in &seq_to_file.into_iter().chunks(chunk_size) {
+        // Nothing that's created in this pool is used after the chunk
+        let pool = unsafe { py.new_pool() };
+        let py = pool.python();
+        let chunk = sequence_dict(py, chunk)?;
+        let advances = add_glyphs_module.call1("remap_values", (chunk, map_fn))?;
+        seq_to_advance.call_method1("update", (advances,))?;
+    }
 
-    let vadvance = if font.call_method1("__contains__", ("vhea",))?.extract()? {
-        font.get_item("vhea")?.getattr("advanceHeightMax")?.extract()?
+    // The sequences are the same as the ones of the images, so the aliases can be applied now
+    let aliases = if aliases.is_empty() {
+        None
     } else {
-        lineheight
+        let aliases_dict = sequence_dict(py, aliases.into_iter()
+            .map(|(alias, target)| (alias, PyTuple::new(py, target))))?;
+        Some(add_glyphs_module.call1("apply_aliases", (seq_to_advance, aliases_dict))?)
     };
 
-    add_glyphs_module.call1("update_font_data", (font, seq_to_advance, vadvance, aliases, add_cmap4_and_glyf, add_cmap4_and_glyf))?;
+    // Only templates with vertical metrics (vhea) get them for every glyph
+    let (vadvance, add_vmtx): (i32, bool) = add_glyphs_module.call1("get_vertical_metrics", (font, lineheight))?.extract()?;
+
+    add_glyphs_module.call1("update_font_data", (font, seq_to_advance, vadvance, aliases, add_cmap4_and_glyf, add_cmap4_and_glyf, add_vmtx))?;
+
+    // Some applications on Windows don't render sequences correctly that only rely on the
+    // template's GSUB table, so we make sure that every sequence has its ligature.
+    if add_cmap4_and_glyf {
+        let added = gsub::add_ligatures(py, add_glyphs_module, font, &ligatures)?;
+        debug!("Added {} ligatures to GSUB", added);
+    }
+
+    let glyph_map = read_glyph_map(py, font, published)?;
 
     font.call_method1("saveXML", (ttx.to_string_lossy().into_owned(),))?;
 
-    Ok(())
+    Ok(glyph_map)
+}
+
+pub(crate) const OVERRIDE_METRICS_PY: &str = r#"
+def override_metrics(font, ascent, descent, line_gap):
+    hhea = font['hhea']
+    os2 = font['OS/2'] if 'OS/2' in font else None
+    if ascent is not None:
+        hhea.ascent = ascent
+        if os2 is not None:
+            os2.sTypoAscender = ascent
+            os2.usWinAscent = ascent
+    if descent is not None:
+        hhea.descent = descent
+        if os2 is not None:
+            os2.sTypoDescender = descent
+            os2.usWinDescent = -descent
+    if line_gap is not None:
+        hhea.lineGap = line_gap
+        if os2 is not None:
+            os2.sTypoLineGap = line_gap
+"#;
+
+/// Overrides of the template's vertical metrics (in font units), e.g. to match the metrics of
+/// a text font that the emojis are used with.
+/// They are written to `hhea` and `OS/2` (typographic and Windows metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FontMetrics {
+    /// Above the baseline, i.e. positive
+    pub ascent: Option<i32>,
+    /// Below the baseline, i.e. negative
+    pub descent: Option<i32>,
+    pub line_gap: Option<i32>,
+}
+
+/// How [add_glyphs] sets up the TTX file of a font
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GlyphOptions {
+    /// Adds a cmap format 4 subtable and (empty) glyf outlines, see $(EMOJI_WINDOWS).tmpl.ttx in
+    /// https://github.com/googlefonts/noto-emoji/blob/main/Makefile
+    pub add_cmap4_and_glyf: bool,
+    pub metrics: FontMetrics,
+    /// The number of images that are read at once (all of them if it's `None`), which doesn't
+    /// change the result
+    pub chunk_size: Option<usize>,
 }
 
-pub fn build_ttf(build_path: &Path) -> PyResult<()>{
+impl FontMetrics {
+    /// Whether the template's metrics are used as they are
+    pub fn is_empty(&self) -> bool {
+        self.ascent.is_none() && self.descent.is_none() && self.line_gap.is_none()
+    }
+
+    /// Checks that the metrics follow the usual conventions (a positive ascent, a negative
+    /// descent and a line gap that isn't negative)
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, value: i32| Err(ConfigError::InvalidValue(String::from(key), value.to_string()));
+        match (self.ascent, self.descent, self.line_gap) {
+            (Some(ascent), _, _) if ascent <= 0 => invalid("ascent", ascent),
+            (_, Some(descent), _) if descent >= 0 => invalid("descent", descent),
+            (_, _, Some(line_gap)) if line_gap < 0 => invalid("line_gap", line_gap),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Display for FontMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let metrics = [("ascent", self.ascent), ("descent", self.descent), ("line gap", self.line_gap)];
+        let overridden = metrics.iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{} {}", name, value)))
+            .join(", ");
+        if overridden.is_empty() {
+            write!(f, "the template's metrics")
+        } else {
+            write!(f, "{}", overridden)
+        }
+    }
+}
+
+pub(crate) const READ_GLYPH_MAP_PY: &str = r#"
+def glyph_map(font, sequences):
+    cmap = font.getBestCmap() or {}
+    ligatures = {}
+    if 'GSUB' in font:
+        for lookup in font['GSUB'].table.LookupList.Lookup:
+            for subtable in lookup.SubTable:
+                subtable = getattr(subtable, 'ExtSubTable', subtable)
+                for first, same_first in getattr(subtable, 'ligatures', {}).items():
+                    for ligature in same_first:
+                        ligatures.setdefault(tuple([first] + list(ligature.Component)), ligature.LigGlyph)
+    glyphs = {}
+    for sequence in sequences:
+        components = tuple(cmap.get(codepoint) for codepoint in sequence)
+        if None in components:
+            continue
+        glyph = components[0] if len(components) == 1 else ligatures.get(components)
+        if glyph is not None:
+            glyphs[tuple(sequence)] = glyph
+    return (font.getGlyphOrder(), glyphs)
+"#;
+
+/// Reads the glyph order and the glyph of each sequence from the font, i.e. after aliases and
+/// deduplicated emojis have been resolved.
+/// `sequences` contains the sequences like they are in the font (without `U+FE0F`) and the
+/// sequence that they're listed under in the glyph map.
+fn read_glyph_map(py: Python, font: &PyAny, sequences: Vec<(Vec<u32>, Vec<u32>)>) -> PyResult<GlyphMap> {
+    let read_glyph_map = PyModule::from_code(py, READ_GLYPH_MAP_PY, "read_glyph_map.py", "read_glyph_map")?;
+    let in_font = sequences.iter().map(|(in_font, _)| in_font.clone()).unique().collect_vec();
+    let (glyph_order, glyphs): (Vec<String>, HashMap<Vec<u32>, String>) = read_glyph_map
+        .call1("glyph_map", (font, in_font))?
+        .extract()?;
+    Ok(to_glyph_map(glyph_order, &glyphs, sequences))
+}
+
+/// Lists the glyphs that `READ_GLYPH_MAP_PY` has found under the sequences they're published as
+pub(crate) fn to_glyph_map(glyph_order: Vec<String>, glyphs: &HashMap<Vec<u32>, String>, sequences: Vec<(Vec<u32>, Vec<u32>)>) -> GlyphMap {
+    let mut glyph_map = GlyphMap {
+        glyph_order,
+        ..GlyphMap::default()
+    };
+    for (in_font, published) in sequences {
+        match glyphs.get(&in_font) {
+            Some(glyph) => glyph_map.insert(&published, glyph.clone()),
+            None => warn!("Couldn't find the glyph of {} in the font", GlyphMap::sequence_key(&published))
+        }
+    }
+    glyph_map
+}
+
+pub fn build_ttf(ttx: &Path, ttf: &Path) -> PyResult<()>{
     // TODO: Do this in a venv or similar
     // TODO: Don't require fonttools
     let gil = Python::acquire_gil();
     let py = gil.python();
     let ttx_module = PyModule::import(py, "fontTools.ttx")?;
 
-    ttx_module.call1("main", (vec![build_path.join(TMPL_TTX).to_string_lossy().into_owned()],))?;
+    ttx_module.call1("main", (vec![
+        String::from("-o"),
+        ttf.to_string_lossy().into_owned(),
+        ttx.to_string_lossy().into_owned()
+    ],))?;
 
     Ok(())
 }
 
-const EMOJI_BUILDER_PY: &str = include_str!("color_emoji/emoji_builder.py");
-const PNG_PY: &str = include_str!("color_emoji/png.py");
+pub(crate) const EMOJI_BUILDER_PY: &str = include_str!("color_emoji/emoji_builder.py");
+pub(crate) const PNG_PY: &str = include_str!("color_emoji/png.py");
 
-pub fn emoji_builder(build_path: &Path, keep_outlines: bool) -> PyResult<()> {
+/// The format of the glyph metrics in the CBDT table.
+///
+/// Noto Color Emoji uses small metrics (CBDT image format 17), which is what Android expects
+/// and what FreeType (i.e. most Linux renderers) supports as well as big metrics (format 18).
+/// Windows 10 ignores the metrics format, but it needs the outline tables, which is why the
+/// `_win` font always keeps them (see `--keep-glyph-outlines` for the other fonts).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CbdtMetrics {
+    #[default]
+    Small,
+    Big,
+}
+
+impl FromStr for CbdtMetrics {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "small" => Ok(CbdtMetrics::Small),
+            "big" => Ok(CbdtMetrics::Big),
+            _ => Err(ConfigError::InvalidValue(String::from("cbdt_metrics"), s.to_string()))
+        }
+    }
+}
+
+/// Adds the images (`png_prefix` followed by the sequence, e.g. `png/emoji_u`) as bitmaps to
+/// `tmpl_ttf` and writes the result to `ttf`
+pub fn emoji_builder(tmpl_ttf: &Path, ttf: &Path, png_prefix: &Path, keep_outlines: bool, metrics: CbdtMetrics) -> PyResult<()> {
     // TODO: We need access to that file. Embedding with include_str! is probably easier
     /*let emoji_builder_path: PathBuf =
         ["noto-emoji", "third_party", "color_emoji", "emoji_builder.py"]
             .iter().collect();*/
 
-    let tmpl_ttf = build_path
-        .join(TMPL_TTF)
+    let tmpl_ttf = tmpl_ttf
         .to_string_lossy()
         .into_owned();
-    let ttf = build_path
-        .join(TTF)
+    let ttf = ttf
         .to_string_lossy()
         .into_owned();
-    let png_dir = build_path
-        .join(PNG_DIR)
-        .join("emoji_u")
+    let png_dir = png_prefix
         .to_string_lossy()
         .into_owned();
 
     let mut argv = vec![
         "emoji_builder.py",
-        "-S",
         "-V",
         &tmpl_ttf,
         &ttf,
         &png_dir
     ];
+    // Big metrics are emoji_builder.py's default
+    if metrics == CbdtMetrics::Small {
+        argv.insert(1, "-S");
+    }
     if keep_outlines {
-        argv.insert(2, "-O");
+        argv.insert(1, "-O");
     }
 
     let gil = Python::acquire_gil();
@@ -216,10 +634,10 @@ pub fn emoji_builder(build_path: &Path, keep_outlines: bool) -> PyResult<()> {
     Ok(())
 }
 
-const MAP_PUA_EMOJI_PY: &str = include_str!("map_pua_emoji/map_pua_emoji.py");
+pub(crate) const MAP_PUA_EMOJI_PY: &str = include_str!("map_pua_emoji/map_pua_emoji.py");
 // We can reuse ADD_EMOJI_GSUB_PY from add_glyphs
 
-pub fn map_pua(build_path: &Path) -> PyResult<()> {
+pub fn map_pua(ttf: &Path, output: &Path) -> PyResult<()> {
     let gil = Python::acquire_gil();
     let py = gil.python();
 
@@ -239,31 +657,695 @@ pub fn map_pua(build_path: &Path) -> PyResult<()> {
     )?;
 
     map_pua_module.call1("add_pua_cmap", (
-        build_path.join(TTF).to_string_lossy().into_owned(),
-        build_path.join(TTF_WITH_PUA).to_string_lossy().into_owned()
+        ttf.to_string_lossy().into_owned(),
+        output.to_string_lossy().into_owned()
     ))?;
 
     Ok(())
 }
 
-pub fn add_vs_cmap(build_path: &Path) -> PyResult<()> {
+/// The codepoints that get an emoji variation sequence if there's no emoji table
+/// (cf. `add_vs_cmap.py -vs 2640 2642 2695` in noto-emoji's Makefile)
+pub const DEFAULT_VS_BASES: [u32; 3] = [0x2640, 0x2642, 0x2695];
+
+/// The codepoints of the single-codepoint emojis in the font that get an emoji variation sequence,
+/// i.e. the ones in `table_bases` (see [EmojiTable::emoji_variation_bases]) or in
+/// [DEFAULT_VS_BASES] if there is no table.
+/// Emojis that couldn't be prepared aren't in the font, so they're left out as well.
+///
+/// [EmojiTable::emoji_variation_bases]: crate::emoji_tables::EmojiTable::emoji_variation_bases
+pub fn variation_selector_bases(table_bases: Option<&HashSet<u32>>, emojis: &PreparedEmojis) -> HashSet<u32> {
+    let default_bases: HashSet<u32> = DEFAULT_VS_BASES.iter().copied().collect();
+    let bases = table_bases.unwrap_or(&default_bases);
+    emojis.iter()
+        .filter(|(_, prepared)| prepared.is_ok())
+        .filter_map(|(emoji, _)| {
+            let codepoints = emoji.sequence.iter()
+                .filter(|codepoint| **codepoint != 0xfe0f)
+                .collect_vec();
+            match codepoints.as_slice() {
+                [codepoint] => Some(**codepoint),
+                _ => None
+            }
+        })
+        .filter(|codepoint| bases.contains(codepoint))
+        .collect()
+}
+
+pub fn add_vs_cmap(ttf: &Path, output: &Path, vs_added: &HashSet<u32>) -> PyResult<()> {
     let gil = Python::acquire_gil();
     let py = gil.python();
     let vs_mapper = PyModule::import(py, "nototools.add_vs_cmap")?;
-    //    [python3] add_vs_cmap.py -vs 2640 2642 2695 --dstdir '.' -o "<name>.ttf-with-pua-varse1" "<name>.ttf-with-pua"
+    //    [python3] add_vs_cmap.py -vs <vs_added> --dstdir '.' -o "<name>.ttf-with-pua-varse1" "<name>.ttf-with-pua"
     let kwargs = PyDict::new(py);
-    let vs_added = HashSet::from_iter(vec![0x2640, 0x2642, 0x2695]);
 
     kwargs.set_item("presentation", "'emoji'")?;
-    kwargs.set_item("output", format!("{}-{}", TTF_WITH_PUA, "varse1"))?;
-    kwargs.set_item("dst_dir", build_path.to_string_lossy().into_owned())?;
+    kwargs.set_item("output", output.file_name().unwrap().to_string_lossy().into_owned())?;
+    kwargs.set_item("dst_dir", output.parent().unwrap_or_else(|| Path::new(".")).to_string_lossy().into_owned())?;
     kwargs.set_item("vs_added", vs_added)?;
 
     vs_mapper.call_method(
         "modify_fonts",
-        (vec![build_path.join(TTF_WITH_PUA).to_string_lossy().into_owned()],),
+        (vec![ttf.to_string_lossy().into_owned()],),
         Some(kwargs)
     )?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub(crate) const READ_VS_CMAP_PY: &str = r#"
+from fontTools.ttLib import TTFont
+
+def emoji_variation_bases(path):
+    cmap14 = TTFont(path)['cmap'].getcmap(0, 5)
+    if cmap14 is None:
+        return set()
+    return {codepoint for codepoint, _ in cmap14.uvsDict.get(0xFE0F, [])}
+"#;
+
+/// Reads the codepoints that have an emoji variation sequence (`U+FE0F`) from the format 14
+/// subtable of a font's cmap
+pub fn read_vs_cmap(ttf: &Path) -> PyResult<HashSet<u32>> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let read_vs_cmap = PyModule::from_code(py, READ_VS_CMAP_PY, "read_vs_cmap.py", "read_vs_cmap")?;
+    read_vs_cmap.call1("emoji_variation_bases", (ttf.to_string_lossy().into_owned(),))?.extract()
+}
+pub(crate) const READ_VMTX_PY: &str = r#"
+from fontTools.ttLib import TTFont
+
+def vertical_glyphs(path):
+    font = TTFont(path)
+    if 'vhea' not in font:
+        return None
+    if 'vmtx' not in font:
+        return set()
+    return set(font['vmtx'].metrics.keys())
+"#;
+
+/// The glyphs that have vertical metrics (in `vmtx`), or `None` if the font doesn't have any
+/// (i.e. no `vhea` table)
+pub fn vertical_glyphs(ttf: &Path) -> PyResult<Option<HashSet<String>>> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let read_vmtx = PyModule::from_code(py, READ_VMTX_PY, "read_vmtx.py", "read_vmtx")?;
+    read_vmtx.call1("vertical_glyphs", (ttf.to_string_lossy().into_owned(),))?.extract()
+}
+
+pub(crate) const SET_LICENSE_PY: &str = r#"
+from fontTools.ttLib import TTFont
+
+LICENSE_DESCRIPTION = 13
+LICENSE_URL = 14
+
+def set_license(path, output, description, url):
+    font = TTFont(path)
+    name = font['name']
+    for name_id, value in ((LICENSE_DESCRIPTION, description), (LICENSE_URL, url)):
+        name.removeNames(nameID=name_id)
+        if value is not None:
+            name.setName(value, name_id, 3, 1, 0x409)
+    font.save(output)
+
+def names(path):
+    return {record.nameID: record.toUnicode() for record in TTFont(path)['name'].names}
+"#;
+
+/// Writes the license description (name ID 13) and URL (name ID 14) to the name table,
+/// replacing the ones from the template
+pub fn set_license(ttf: &Path, output: &Path, description: &str, url: Option<&str>) -> PyResult<()> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let set_license = PyModule::from_code(py, SET_LICENSE_PY, "set_license.py", "set_license")?;
+    set_license.call1("set_license", (
+        ttf.to_string_lossy().into_owned(),
+        output.to_string_lossy().into_owned(),
+        description,
+        url
+    ))?;
+    Ok(())
+}
+
+/// Compresses a TTF file to WOFF2 (using fontTools, which requires the `brotli` module)
+pub fn compress_woff2(input: &Path, output: &Path) -> PyResult<()> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let woff2 = PyModule::import(py, "fontTools.ttLib.woff2")?;
+
+    woff2.call1("compress", (
+        input.to_string_lossy().into_owned(),
+        output.to_string_lossy().into_owned()
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_compress_woff2() {
+    const BUILD_TEST_FONT: &str = r#"
+from fontTools.fontBuilder import FontBuilder
+from fontTools.pens.ttGlyphPen import TTGlyphPen
+
+def build(path):
+    fb = FontBuilder(1024, isTTF=True)
+    fb.setupGlyphOrder([".notdef", "a"])
+    fb.setupCharacterMap({ord("a"): "a"})
+    fb.setupGlyf({".notdef": TTGlyphPen(None).glyph(), "a": TTGlyphPen(None).glyph()})
+    fb.setupHorizontalMetrics({".notdef": (500, 0), "a": (500, 0)})
+    fb.setupHorizontalHeader(ascent=800, descent=-200)
+    fb.setupNameTable({"familyName": "Test", "styleName": "Regular"})
+    fb.setupOS2()
+    fb.setupPost()
+    fb.save(path)
+
+def glyphs(path):
+    from fontTools.ttLib import TTFont
+    font = TTFont(path)
+    return (font.flavor, font.getGlyphOrder())
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let ttf = dir.path().join("test.ttf");
+    let woff2 = dir.path().join("test.woff2");
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let test_font = PyModule::from_code(py, BUILD_TEST_FONT, "test_font.py", "test_font").unwrap();
+    test_font.call1("build", (ttf.to_string_lossy().into_owned(),)).unwrap();
+
+    compress_woff2(&ttf, &woff2).unwrap();
+
+    let compressed = std::fs::read(&woff2).unwrap();
+    assert_eq!(&compressed[..4], b"wOF2");
+
+    // Decompressing it yields the same font
+    let (flavor, glyphs): (String, Vec<String>) = test_font
+        .call1("glyphs", (woff2.to_string_lossy().into_owned(),)).unwrap()
+        .extract().unwrap();
+    assert_eq!(flavor, "woff2");
+    assert_eq!(glyphs, vec![".notdef", "a"]);
+}
+
+#[test]
+fn test_emoji_builder_metrics() {
+    const TEST_FONT: &str = r#"
+from fontTools.fontBuilder import FontBuilder
+from fontTools.pens.ttGlyphPen import TTGlyphPen
+from fontTools.ttLib import TTFont
+
+def build(path):
+    glyphs = [".notdef", "u1F914"]
+    fb = FontBuilder(2048, isTTF=True)
+    fb.setupGlyphOrder(glyphs)
+    fb.setupCharacterMap({0x1F914: "u1F914"})
+    fb.setupGlyf({glyph: TTGlyphPen(None).glyph() for glyph in glyphs})
+    fb.setupHorizontalMetrics({glyph: (2550, 0) for glyph in glyphs})
+    fb.setupHorizontalHeader(ascent=1900, descent=-500)
+    fb.setupNameTable({"familyName": "Test", "styleName": "Regular"})
+    fb.setupOS2()
+    fb.setupPost()
+    fb.save(path)
+
+def metrics(path):
+    font = TTFont(path)
+    return (font['CBLC'].strikes[0].indexSubTables[0].imageFormat, 'glyf' in font)
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    tiny_skia::Pixmap::new(136, 128).unwrap()
+        .save_png(build_dir.png_dir().join("emoji_u1f914.png"))
+        .unwrap();
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let test_font = PyModule::from_code(py, TEST_FONT, "test_font.py", "test_font").unwrap();
+    let work_dir = build_dir.work_dir("test").unwrap();
+    let ttf = work_dir.ttf().to_string_lossy().into_owned();
+    let read_back = |keep_outlines: bool, metrics: CbdtMetrics| -> (u8, bool) {
+        test_font.call1("build", (work_dir.tmpl_ttf().to_string_lossy().into_owned(),)).unwrap();
+        emoji_builder(&work_dir.tmpl_ttf(), &work_dir.ttf(), &build_dir.png_dir().join("emoji_u"), keep_outlines, metrics).unwrap();
+        test_font.call1("metrics", (ttf.clone(),)).unwrap().extract().unwrap()
+    };
+
+    assert_eq!(read_back(false, CbdtMetrics::Small), (17, false));
+    assert_eq!(read_back(true, CbdtMetrics::Big), (18, true));
+
+    assert_eq!("big".parse::<CbdtMetrics>().unwrap(), CbdtMetrics::Big);
+    assert!("medium".parse::<CbdtMetrics>().is_err());
+}
+
+#[test]
+fn test_deduplicated_bitmaps() {
+    const TEST_FONT: &str = r#"
+from fontTools.ttLib import TTFont
+
+def bitmaps(path):
+    font = TTFont(path)
+    return font.reader['CBDT'].count(b'\x89PNG')
+"#;
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    let grinning = Emoji::from(vec![0x1f600]);
+    let smiley = Emoji::from(vec![0x1f603]);
+    let thinking = Emoji::from(vec![0x1f914]);
+    let mut red = tiny_skia::Pixmap::new(136, 128).unwrap();
+    red.fill(tiny_skia::Color::from_rgba8(255, 0, 0, 255));
+    let images = vec![
+        (&grinning, red.clone()),
+        (&smiley, red),
+        (&thinking, tiny_skia::Pixmap::new(136, 128).unwrap()),
+    ];
+
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = images.into_iter()
+        .map(|(emoji, image)| {
+            let path = build_dir.png_dir()
+                .join(format!("emoji_u{}.png", emoji.sequence.iter().map(|codepoint| format!("{:x}", codepoint)).join("_")));
+            image.save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+
+    let work_dir = build_dir.work_dir("test").unwrap();
+    add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions::default()).unwrap();
+    build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+    emoji_builder(&work_dir.tmpl_ttf(), &work_dir.ttf(), &build_dir.png_dir().join("emoji_u"), false, CbdtMetrics::Small).unwrap();
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let test_font = PyModule::from_code(py, TEST_FONT, "test_font.py", "test_font").unwrap();
+    let bitmaps: usize = test_font
+        .call1("bitmaps", (work_dir.ttf().to_string_lossy().into_owned(),)).unwrap()
+        .extract().unwrap();
+    // The two identical images are only embedded once
+    assert_eq!(bitmaps, 2);
+}
+
+#[test]
+fn test_flag_aliases() {
+    const TEST_FONT: &str = r#"
+from fontTools.ttLib import TTFont
+
+def bitmaps(path):
+    font = TTFont(path)
+    return font.reader['CBDT'].count(b'\x89PNG')
+"#;
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+    use crate::builders::blobmoji::flag_aliases::FlagAliases;
+    use crate::builders::blobmoji::font_sequences;
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    // The flag of Germany and the regional indicators A, D, E and T
+    let flag = |code: &str| Emoji::from_flag(code, None).unwrap();
+    let emojis = [flag("DE"), Emoji::from(vec![0x1f1e6]), Emoji::from(vec![0x1f1e9]), Emoji::from(vec![0x1f1ea]), Emoji::from(vec![0x1f1f9])];
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .enumerate()
+        .map(|(index, emoji)| {
+            let path = build_dir.png(emoji);
+            let mut image = tiny_skia::Pixmap::new(136, 128).unwrap();
+            image.fill(tiny_skia::Color::from_rgba8(index as u8 * 50, 0, 0, 255));
+            image.save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+
+    let flag_aliases = FlagAliases::from_reader(r#"{"DE": ["AT", "ET"]}"#.as_bytes()).unwrap();
+    let available: HashSet<Vec<u32>> = emojis.keys().map(|emoji| emoji.sequence.clone()).collect();
+    let flag_aliases = flag_aliases.resolve(&available);
+    assert_eq!(flag_aliases.len(), 2);
+
+    let work_dir = build_dir.work_dir("test").unwrap();
+    add_glyphs(&None, &flag_aliases, &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions::default()).unwrap();
+    build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+    emoji_builder(&work_dir.tmpl_ttf(), &work_dir.ttf(), &build_dir.png_dir().join("emoji_u"), false, CbdtMetrics::Small).unwrap();
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let test_font = PyModule::from_code(py, TEST_FONT, "test_font.py", "test_font").unwrap();
+    let bitmaps: usize = test_font
+        .call1("bitmaps", (work_dir.ttf().to_string_lossy().into_owned(),)).unwrap()
+        .extract().unwrap();
+    // The flag is only embedded once (besides the regional indicators)
+    assert_eq!(bitmaps, 5);
+
+    let sequences = font_sequences::read_sequences(&work_dir.ttf()).unwrap();
+    for code in &["DE", "AT", "ET"] {
+        assert!(sequences.contains(&flag(code).sequence), "{} is missing", code);
+    }
+}
+
+#[test]
+fn test_glyph_map() {
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    // The rainbow flag and its components
+    let rainbow_flag = vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+    let emojis = [Emoji::from(rainbow_flag.clone()), Emoji::from(vec![0x1f3f3]), Emoji::from(vec![0x1f308])];
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .enumerate()
+        .map(|(index, emoji)| {
+            let path = build_dir.png(emoji);
+            let mut image = tiny_skia::Pixmap::new(136, 128).unwrap();
+            image.fill(tiny_skia::Color::from_rgba8(index as u8 * 50, 0, 0, 255));
+            image.save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+
+    let work_dir = build_dir.work_dir("test").unwrap();
+    let glyph_map = add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions::default()).unwrap();
+    glyph_map.write(build_dir.glyph_map()).unwrap();
+
+    let json: serde_json::Value = serde_json::from_reader(std::fs::File::open(build_dir.glyph_map()).unwrap()).unwrap();
+    let glyph = json["glyphs"]["1f3f3 fe0f 200d 1f308"].as_str().unwrap();
+    assert_eq!(glyph, gsub::glyph_name(&[0x1f3f3, 0x200d, 0x1f308]));
+    assert!(json["glyph_order"].as_array().unwrap().iter().any(|name| name.as_str() == Some(glyph)));
+    assert_eq!(json["glyphs"]["1f308"].as_str(), Some("u1F308"));
+}
+
+#[test]
+fn test_chunked_add_glyphs() {
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    // A private set with some sequences and images of different widths
+    let emojis: Vec<Emoji> = (0..40)
+        .map(|index| if index % 5 == 0 {
+            Emoji::from(vec![0xf0000 + index, 0x200d, 0xf0001])
+        } else {
+            Emoji::from(vec![0xf0000 + index])
+        })
+        .collect();
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .enumerate()
+        .map(|(index, emoji)| {
+            let path = build_dir.png(emoji);
+            let mut image = tiny_skia::Pixmap::new(128 + index as u32 % 3 * 8, 128).unwrap();
+            image.fill(tiny_skia::Color::from_rgba8(index as u8 * 6, 0, 0, 255));
+            image.save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+    let mut aliases = HashMap::new();
+    aliases.insert(vec![0xf1000], vec![0xf0002]);
+
+    let build = |chunk_size: Option<usize>| {
+        let work_dir = build_dir.work_dir(&format!("{:?}", chunk_size)).unwrap();
+        let glyph_map = add_glyphs(&None, &aliases, &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions { add_cmap4_and_glyf: true, chunk_size, ..GlyphOptions::default() }).unwrap();
+        (std::fs::read_to_string(work_dir.ttx_tmpl()).unwrap(), glyph_map)
+    };
+    let (ttx, glyph_map) = build(None);
+    assert_eq!(glyph_map.glyphs.len(), 41);
+    // The glyph order is the same, regardless of how the emojis have been split
+    for chunk_size in &[1, 7, 40, 100] {
+        let (chunked_ttx, chunked_glyph_map) = build(Some(*chunk_size));
+        assert_eq!(chunked_glyph_map, glyph_map, "chunk size {}", chunk_size);
+        assert!(chunked_ttx == ttx, "chunk size {}", chunk_size);
+    }
+}
+
+#[test]
+fn test_metrics_override() {
+    const TEST_FONT: &str = r#"
+from fontTools.ttLib import TTFont
+
+def metrics(path):
+    font = TTFont(path)
+    hhea = font['hhea']
+    os2 = font['OS/2']
+    return ((hhea.ascent, hhea.descent, hhea.lineGap),
+            (os2.sTypoAscender, os2.sTypoDescender, os2.sTypoLineGap),
+            (os2.usWinAscent, os2.usWinDescent),
+            font['hmtx']['u1F914'][0])
+"#;
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    let thinking = Emoji::from(vec![0x1f914]);
+    let path = build_dir.png_dir().join("emoji_u1f914.png");
+    tiny_skia::Pixmap::new(136, 128).unwrap().save_png(&path).unwrap();
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> =
+        vec![(&thinking, Ok((path, Ok(Default::default()))))].into_iter().collect();
+
+    let metrics = FontMetrics {
+        ascent: Some(950),
+        descent: Some(-250),
+        line_gap: Some(100),
+    };
+    let work_dir = build_dir.work_dir("test").unwrap();
+    add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions { metrics, ..GlyphOptions::default() }).unwrap();
+    build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let test_font = PyModule::from_code(py, TEST_FONT, "test_font.py", "test_font").unwrap();
+    // Ascent, descent and line gap
+    type Metrics = (i32, i32, i32);
+    let (hhea, typo, win, advance): (Metrics, Metrics, (u32, u32), u32) = test_font
+        .call1("metrics", (work_dir.tmpl_ttf().to_string_lossy().into_owned(),)).unwrap()
+        .extract().unwrap();
+    assert_eq!(hhea, (950, -250, 100));
+    assert_eq!(typo, (950, -250, 100));
+    assert_eq!(win, (950, 250));
+    // The advance is scaled to the new line height
+    assert_eq!(advance, (1200.0f64 * 136.0 / 128.0).round() as u32);
+
+    assert!(FontMetrics { ascent: None, descent: Some(250), line_gap: None }.validate().is_err());
+    assert!(FontMetrics { ascent: Some(0), descent: None, line_gap: None }.validate().is_err());
+    assert!(metrics.validate().is_ok());
+    assert_eq!(metrics.to_string(), "ascent 950, descent -250, line gap 100");
+}
+
+#[test]
+fn test_sequence_dict_stress() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let add_glyphs_module = add_glyphs_module(py).unwrap();
+
+    let sequence = |index: u32| vec![0xf0000 + index, 0x200d, 0x1f308];
+    let seq_to_file = sequence_dict(py, (0..20_000).map(|index| (sequence(index), format!("{}.png", index)))).unwrap();
+    assert_eq!(seq_to_file.len(), 20_000);
+    let file: String = seq_to_file.get_item(PyTuple::new(py, sequence(19_999))).unwrap().extract().unwrap();
+    assert_eq!(file, "19999.png");
+
+    // Every other sequence is an alias of the one before it
+    let aliases = sequence_dict(py, (0..10_000)
+        .map(|index| (sequence(2 * index + 1), PyTuple::new(py, sequence(2 * index))))).unwrap();
+    let usable: &PyDict = add_glyphs_module.call1("apply_aliases", (seq_to_file, aliases)).unwrap()
+        .downcast().unwrap();
+    assert_eq!(usable.len(), 10_000);
+    assert_eq!(seq_to_file.len(), 10_000);
+}
+
+#[test]
+fn test_add_glyphs_error() {
+    use crate::builders::blobmoji::Blobmoji;
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    let emoji = Emoji::from(vec![0x1f914]);
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> =
+        vec![(&emoji, Ok((build_dir.png(&emoji), Ok(Default::default()))))].into_iter().collect();
+
+    // There's neither a template nor an image, which is reported with the Python traceback
+    let work_dir = build_dir.work_dir("test").unwrap();
+    let result = add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions::default());
+    match result {
+        Err(BlobmojiError::PythonError(message)) => assert!(message.contains("Traceback"), "{}", message),
+        other => panic!("Expected a Python error, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_variation_selector_bases() {
+    use crate::emoji_tables::EmojiTable;
+
+    let mut table = EmojiTable::new();
+    table.expand_from_file("test_files/variation_sequences/emoji-variation-sequences.txt").unwrap();
+
+    let female_sign = Emoji::from(vec![0x2640, 0xfe0f]);
+    let male_sign = Emoji::from(vec![0x2642, 0xfe0f]);
+    let heart = Emoji::from(vec![0x2764, 0xfe0f]);
+    let thinking = Emoji::from(vec![0x1f914]);
+    let keycap = Emoji::from(vec![0x23, 0xfe0f, 0x20e3]);
+    let emojis: PreparedEmojis = vec![
+        (&female_sign, Ok((PathBuf::from("emoji_u2640.png"), Ok(Default::default())))),
+        (&male_sign, Ok((PathBuf::from("emoji_u2642.png"), Ok(Default::default())))),
+        (&heart, Err(BlobmojiError::UnknownError)),
+        (&thinking, Ok((PathBuf::from("emoji_u1f914.png"), Ok(Default::default())))),
+        (&keycap, Ok((PathBuf::from("emoji_u23_20e3.png"), Ok(Default::default())))),
+    ].into_iter().collect();
+
+    // U+2642 isn't in the table, the heart isn't in the font and U+0023 only in a sequence
+    let expected: HashSet<u32> = vec![0x2640].into_iter().collect();
+    assert_eq!(variation_selector_bases(Some(table.emoji_variation_bases()), &emojis), expected);
+
+    let expected: HashSet<u32> = vec![0x2640, 0x2642].into_iter().collect();
+    assert_eq!(variation_selector_bases(None, &emojis), expected);
+}
+
+#[test]
+fn test_add_vs_cmap() {
+    const BUILD_TEST_FONT: &str = r#"
+from fontTools.fontBuilder import FontBuilder
+from fontTools.pens.ttGlyphPen import TTGlyphPen
+
+def build(path):
+    glyphs = [".notdef", "uni0023", "uni2640", "uni2764", "u1F914"]
+    fb = FontBuilder(1024, isTTF=True)
+    fb.setupGlyphOrder(glyphs)
+    fb.setupCharacterMap({0x23: "uni0023", 0x2640: "uni2640", 0x2764: "uni2764", 0x1F914: "u1F914"})
+    fb.setupGlyf({glyph: TTGlyphPen(None).glyph() for glyph in glyphs})
+    fb.setupHorizontalMetrics({glyph: (1024, 0) for glyph in glyphs})
+    fb.setupHorizontalHeader(ascent=800, descent=-200)
+    fb.setupNameTable({"familyName": "Test", "styleName": "Regular"})
+    fb.setupOS2()
+    fb.setupPost()
+    fb.save(path)
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let ttf = dir.path().join("test.ttf");
+    let output = dir.path().join("test.ttf-with-varse1");
+
+    {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let test_font = PyModule::from_code(py, BUILD_TEST_FONT, "test_font.py", "test_font").unwrap();
+        test_font.call1("build", (ttf.to_string_lossy().into_owned(),)).unwrap();
+    }
+    assert!(read_vs_cmap(&ttf).unwrap().is_empty());
+
+    let vs_added: HashSet<u32> = vec![0x23, 0x2640, 0x2764].into_iter().collect();
+    PythonSteps.add_vs_cmap(&ttf, &output, &vs_added).unwrap();
+    let in_font = read_vs_cmap(&output).unwrap();
+    assert!(in_font.is_superset(&vs_added), "{:?}", in_font);
+    // Emojis with an emoji presentation don't need one
+    assert!(!in_font.contains(&0x1f914));
+
+    // A codepoint that's not in the font can't get a variation sequence
+    let vs_added: HashSet<u32> = vec![0x2642].into_iter().collect();
+    match PythonSteps.add_vs_cmap(&ttf, &output, &vs_added) {
+        Err(BlobmojiError::MissingVariationSequences(missing)) => assert_eq!(missing, vec![0x2642]),
+        other => panic!("Expected missing variation sequences, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_set_license() {
+    const BUILD_TEST_FONT: &str = r#"
+from fontTools.fontBuilder import FontBuilder
+from fontTools.pens.ttGlyphPen import TTGlyphPen
+
+def build(path):
+    fb = FontBuilder(1024, isTTF=True)
+    fb.setupGlyphOrder([".notdef"])
+    fb.setupCharacterMap({})
+    fb.setupGlyf({".notdef": TTGlyphPen(None).glyph()})
+    fb.setupHorizontalMetrics({".notdef": (500, 0)})
+    fb.setupHorizontalHeader(ascent=800, descent=-200)
+    fb.setupNameTable({"familyName": "Test", "styleName": "Regular", "licenseDescription": "OFL", "licenseInfoURL": "https://scripts.sil.org/OFL"})
+    fb.setupOS2()
+    fb.setupPost()
+    fb.save(path)
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let ttf = dir.path().join("test.ttf");
+    let output = dir.path().join("test.ttf-with-license");
+
+    let names = |path: &Path| -> HashMap<u16, String> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let set_license = PyModule::from_code(py, SET_LICENSE_PY, "set_license.py", "set_license").unwrap();
+        set_license.call1("names", (path.to_string_lossy().into_owned(),)).unwrap().extract().unwrap()
+    };
+
+    {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let test_font = PyModule::from_code(py, BUILD_TEST_FONT, "test_font.py", "test_font").unwrap();
+        test_font.call1("build", (ttf.to_string_lossy().into_owned(),)).unwrap();
+    }
+    assert_eq!(names(&ttf).get(&13).map(String::as_str), Some("OFL"));
+
+    PythonSteps.set_license(&ttf, &output, "The artwork is licensed under CC-BY-4.0.", Some("https://creativecommons.org/licenses/by/4.0/")).unwrap();
+    let in_font = names(&output);
+    assert_eq!(in_font.get(&13).map(String::as_str), Some("The artwork is licensed under CC-BY-4.0."));
+    assert_eq!(in_font.get(&14).map(String::as_str), Some("https://creativecommons.org/licenses/by/4.0/"));
+    assert_eq!(in_font.get(&1).map(String::as_str), Some("Test"));
+
+    // Without a URL, the one of the template is removed
+    PythonSteps.set_license(&ttf, &output, "The artwork is licensed under LicenseRef-Custom.", None).unwrap();
+    assert_eq!(names(&output).get(&14), None);
+}
+
+#[test]
+fn test_vertical_metrics() {
+    const TEST_FONT: &str = r#"
+from fontTools.ttLib import TTFont
+
+def vertical_metrics(path, glyphs):
+    vmtx = TTFont(path)['vmtx']
+    return [tuple(vmtx[glyph]) for glyph in glyphs]
+"#;
+    use crate::builders::blobmoji::{Blobmoji, TMPL_TTX_TMPL_CONTENT};
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+
+    // Images of different widths, the vertical advance is the same anyway
+    let emojis = [Emoji::from(vec![0x1f914]), Emoji::from(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308])];
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .enumerate()
+        .map(|(index, emoji)| {
+            let path = build_dir.png(emoji);
+            tiny_skia::Pixmap::new(128 + index as u32 * 8, 128).unwrap().save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+    let build = |name: &str, template: &[u8]| {
+        std::fs::write(build_dir.ttx_tmpl_tmpl(), template).unwrap();
+        let work_dir = build_dir.work_dir(name).unwrap();
+        let glyph_map = add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions::default()).unwrap();
+        build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+        (work_dir, glyph_map)
+    };
+
+    // The template declares vhea, but doesn't have a vmtx table
+    let (work_dir, glyph_map) = build("vertical", &std::fs::read("test_files/ttx/vertical.tmpl.ttx").unwrap());
+    let glyphs = glyph_map.glyphs.values().cloned().unique().collect_vec();
+    let vertical = vertical_glyphs(&work_dir.tmpl_ttf()).unwrap().unwrap();
+    assert!(glyphs.iter().all(|glyph| vertical.contains(glyph)));
+    assert_eq!(PythonSteps.vertical_glyphs(&work_dir.tmpl_ttf()).unwrap(), Some(vertical));
+    let metrics: Vec<(u32, i32)> = {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let test_font = PyModule::from_code(py, TEST_FONT, "test_font.py", "test_font").unwrap();
+        test_font.call1("vertical_metrics", (work_dir.tmpl_ttf().to_string_lossy().into_owned(), glyphs.clone())).unwrap()
+            .extract().unwrap()
+    };
+    // The height of the cell in font units (ascent 1900, descent -500), with the top at the origin
+    assert_eq!(metrics, vec![(2400, 0); glyphs.len()]);
+
+    // Without vhea, there are no vertical metrics
+    let without_vhea = regex::Regex::new(r"(?s)\s*<vhea>.*</vmtx>").unwrap()
+        .replace(&String::from_utf8_lossy(TMPL_TTX_TMPL_CONTENT), "")
+        .into_owned();
+    let (work_dir, _) = build("horizontal", without_vhea.as_bytes());
+    assert!(!std::fs::read_to_string(work_dir.ttx_tmpl()).unwrap().contains("<vmtx>"));
+    assert_eq!(vertical_glyphs(&work_dir.tmpl_ttf()).unwrap(), None);
+}
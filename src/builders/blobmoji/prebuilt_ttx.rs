@@ -0,0 +1,90 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::builders::blobmoji::gsub;
+use crate::emoji::Emoji;
+
+/// The differences between the glyphs of a pre-built TTX file (i.e. one that is used instead of
+/// the output of `add_glyphs.py`) and the prepared emojis
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TtxMismatch {
+    /// Glyphs of prepared emojis that are not in the TTX file, i.e. their PNGs are not used
+    pub missing: Vec<String>,
+    /// Emoji glyphs in the TTX file without a prepared emoji
+    pub unused: Vec<String>,
+}
+
+impl TtxMismatch {
+    /// Compares the `GlyphOrder` of the TTX file with the glyph names that `add_glyphs.py` would
+    /// use for the emojis.
+    /// Only glyphs that are named like emojis (e.g. `u1F914`) can be unused.
+    pub fn check<'e, I: IntoIterator<Item=&'e Emoji>>(ttx: &str, emojis: I) -> TtxMismatch {
+        lazy_static! {
+            static ref GLYPH_ID: Regex = Regex::new(r#"<GlyphID\b[^>]*\bname="([^"]+)""#).unwrap();
+            static ref EMOJI_GLYPH: Regex = Regex::new(r"^(u[0-9A-F]{5}|u[0-9A-F]{4,6}(_[0-9A-F]{4,6})+|uni[0-9A-F]{4})$").unwrap();
+        }
+        let glyphs: HashSet<&str> = GLYPH_ID.captures_iter(ttx)
+            .filter_map(|captures| captures.get(1))
+            .map(|name| name.as_str())
+            .collect();
+        let expected: HashSet<String> = emojis.into_iter()
+            .map(|emoji| emoji.sequence.iter()
+                .filter(|codepoint| **codepoint != 0xfe0f)
+                .copied()
+                .collect::<Vec<u32>>())
+            .map(|sequence| gsub::glyph_name(&sequence))
+            .collect();
+
+        let mut missing: Vec<String> = expected.iter()
+            .filter(|glyph| !glyphs.contains(glyph.as_str()))
+            .cloned()
+            .collect();
+        let mut unused: Vec<String> = glyphs.iter()
+            .filter(|glyph| EMOJI_GLYPH.is_match(glyph) && !expected.contains(**glyph))
+            .map(|glyph| glyph.to_string())
+            .collect();
+        missing.sort();
+        unused.sort();
+        TtxMismatch {
+            missing,
+            unused,
+        }
+    }
+}
+
+#[test]
+fn test_check() {
+    let ttx = std::fs::read_to_string("test_files/ttx/prebuilt.ttx").unwrap();
+    let emojis = vec![
+        Emoji::from(vec![0x1f914]),
+        Emoji::from(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]),
+        Emoji::from(vec![0x1f9a8]),
+    ];
+
+    let mismatch = TtxMismatch::check(&ttx, &emojis);
+    assert_eq!(mismatch, TtxMismatch {
+        missing: vec![String::from("u1F9A8")],
+        unused: vec![String::from("u1F600")],
+    });
+
+    let mismatch = TtxMismatch::check(&ttx, &emojis[..2]);
+    assert_eq!(mismatch.missing, Vec::<String>::new());
+    assert_eq!(mismatch.unused, vec![String::from("u1F600")]);
+}
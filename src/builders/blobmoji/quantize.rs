@@ -0,0 +1,390 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Reduces the colors of the rendered emojis to a palette (median cut, optionally with
+//! Floyd-Steinberg dithering), so they can be stored as indexed PNG files.
+//!
+//! As this is lossy, the result is compared with the original image (by its PSNR) and only used
+//! if it's close enough.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use png::{BitDepth, ColorType, EncodingError};
+
+/// The PSNR (in dB) below which the unquantized image is used by default
+pub const DEFAULT_MIN_PSNR: f64 = 35.0;
+
+/// The settings of the quantization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantizer {
+    /// The maximum number of colors (1 to 256)
+    pub colors: u16,
+    /// Whether to use dithering to hide the banding in gradients
+    pub dither: bool,
+    /// The minimum PSNR (in dB) of the quantized image
+    pub min_psnr: f64,
+}
+
+/// An image with a palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quantized {
+    /// The RGBA colors of the palette
+    pub palette: Vec<[u8; 4]>,
+    /// The index of each pixel's color in the palette
+    pub indices: Vec<u8>,
+}
+
+/// The result of [Quantizer::encode]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quantization {
+    /// The image is close enough to the original
+    Encoded { png: Vec<u8>, psnr: f64 },
+    /// The quantization would have lost too much, so the original should be used
+    TooLossy { psnr: f64 },
+}
+
+impl Quantizer {
+    /// A quantizer without dithering and with the [DEFAULT_MIN_PSNR]
+    pub fn new(colors: u16) -> Quantizer {
+        Quantizer {
+            colors,
+            dither: false,
+            min_psnr: DEFAULT_MIN_PSNR,
+        }
+    }
+
+    /// Quantizes an RGBA image and encodes it as an indexed PNG file if it's close enough to the
+    /// original
+    pub fn encode(&self, rgba: &[u8], width: u32, height: u32) -> Result<Quantization, EncodingError> {
+        let quantized = self.quantize(rgba, width);
+        let psnr = psnr(rgba, &quantized.to_rgba());
+        if psnr < self.min_psnr {
+            Ok(Quantization::TooLossy { psnr })
+        } else {
+            Ok(Quantization::Encoded { png: quantized.to_png(width, height)?, psnr })
+        }
+    }
+
+    /// Reduces the colors of an RGBA image (with rows of `width` pixels)
+    pub fn quantize(&self, rgba: &[u8], width: u32) -> Quantized {
+        let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+        for pixel in rgba.chunks_exact(4) {
+            *histogram.entry(normalize(pixel)).or_default() += 1;
+        }
+        let max_colors = self.colors.clamp(1, 256) as usize;
+        // Transparent pixels get their own palette entry, so they don't get mixed with dark colors
+        let transparent = histogram.remove(&[0; 4]).is_some();
+        let mut colors: Vec<([u8; 4], u32)> = histogram.into_iter().collect();
+        // The order of a HashMap changes between runs, but the palette shouldn't
+        colors.sort_unstable();
+        let palette = if transparent {
+            let mut palette = vec![[0; 4]];
+            palette.extend(median_cut(colors, max_colors - 1));
+            palette
+        } else {
+            median_cut(colors, max_colors)
+        };
+
+        let indices = if self.dither {
+            dither(rgba, width as usize, &palette)
+        } else {
+            let mut nearest: HashMap<[u8; 4], u8> = HashMap::new();
+            rgba.chunks_exact(4)
+                .map(|pixel| {
+                    let color = normalize(pixel);
+                    *nearest.entry(color).or_insert_with(|| nearest_index(&palette, to_float(color)))
+                })
+                .collect()
+        };
+
+        Quantized { palette, indices }
+    }
+}
+
+impl Quantized {
+    /// The colors of the pixels
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.indices.iter()
+            .flat_map(|index| self.palette[*index as usize].iter().copied())
+            .collect()
+    }
+
+    /// Encodes the image as an indexed PNG file (with a `tRNS` chunk for the transparency)
+    pub fn to_png(&self, width: u32, height: u32) -> Result<Vec<u8>, EncodingError> {
+        let mut png_target = Vec::with_capacity(self.indices.len() + 8);
+        let mut encoder = png::Encoder::new(&mut png_target, width, height);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(self.palette.iter()
+            .flat_map(|color| color[..3].iter().copied())
+            .collect());
+        if self.palette.iter().any(|color| color[3] != 255) {
+            encoder.set_trns(self.palette.iter().map(|color| color[3]).collect());
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.indices)?;
+        std::mem::drop(writer);
+        Ok(png_target)
+    }
+}
+
+/// The peak signal-to-noise ratio (in dB) of two RGBA images of the same size
+/// (infinite for identical images)
+pub fn psnr(original: &[u8], changed: &[u8]) -> f64 {
+    assert_eq!(original.len(), changed.len());
+    if original.is_empty() {
+        return f64::INFINITY;
+    }
+    let squared_error: u64 = original.iter()
+        .zip(changed)
+        .map(|(a, b)| (*a as i64 - *b as i64).pow(2) as u64)
+        .sum();
+    if squared_error == 0 {
+        f64::INFINITY
+    } else {
+        let mse = squared_error as f64 / original.len() as f64;
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+/// All fully transparent pixels are the same color
+fn normalize(pixel: &[u8]) -> [u8; 4] {
+    if pixel[3] == 0 {
+        [0; 4]
+    } else {
+        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    }
+}
+
+fn to_float(color: [u8; 4]) -> [f32; 4] {
+    [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32]
+}
+
+/// Splits the colors into (at most) `max_colors` boxes, always splitting the box with the largest
+/// range in one channel at the median of that channel.
+/// The palette contains the (weighted) average color of each box.
+fn median_cut(colors: Vec<([u8; 4], u32)>, max_colors: usize) -> Vec<[u8; 4]> {
+    if max_colors == 0 {
+        return vec![];
+    } else if colors.len() <= max_colors {
+        return colors.into_iter().map(|(color, _)| color).collect();
+    }
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        // The channel with the largest range in each box
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(index, colors)| (0..4).map(move |channel| {
+                let (min, max) = colors.iter()
+                    .map(|(color, _)| color[channel])
+                    .fold((255, 0), |(min, max), value| (min.min(value), max.max(value)));
+                (max - min, index, channel)
+            }))
+            .max_by_key(|(range, index, channel)| (*range, std::cmp::Reverse(*index), std::cmp::Reverse(*channel)));
+        let (index, channel) = match widest {
+            Some((range, index, channel)) if range > 0 => (index, channel),
+            _ => break
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_by_key(|(color, _)| (color[channel], *color));
+        let total: u64 = colors.iter().map(|(_, count)| *count as u64).sum();
+        let mut seen = 0;
+        let mut median = colors.len() - 1;
+        for (position, (_, count)) in colors.iter().enumerate() {
+            seen += *count as u64;
+            if seen * 2 >= total {
+                median = position + 1;
+                break;
+            }
+        }
+        // Both halves need at least one color
+        let median = median.max(1).min(colors.len() - 1);
+        let upper = colors.split_off(median);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|(_, count)| *count as u64).sum();
+            let mut average = [0u8; 4];
+            for (channel, value) in average.iter_mut().enumerate() {
+                let sum: u64 = colors.iter()
+                    .map(|(color, count)| color[channel] as u64 * *count as u64)
+                    .sum();
+                *value = ((sum + total / 2) / total) as u8;
+            }
+            average
+        })
+        .collect()
+}
+
+/// The index of the palette color that is closest to a color (in RGBA space)
+fn nearest_index(palette: &[[u8; 4]], color: [f32; 4]) -> u8 {
+    palette.iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let distance: f32 = candidate.iter()
+                .zip(color.iter())
+                .map(|(a, b)| (*a as f32 - b).powi(2))
+                .sum();
+            (index, distance)
+        })
+        .fold((0, f32::INFINITY), |best, current| if current.1 < best.1 { current } else { best })
+        .0 as u8
+}
+
+/// Maps the pixels to the palette with Floyd-Steinberg dithering.
+/// Fully transparent pixels neither receive nor spread any error, so the edges of the emojis
+/// stay clean.
+fn dither(rgba: &[u8], width: usize, palette: &[[u8; 4]]) -> Vec<u8> {
+    let width = width.max(1);
+    let height = rgba.len() / 4 / width;
+    let mut errors = vec![[0f32; 4]; width * 2];
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (current, next) = errors.split_at_mut(width);
+        for x in 0..width {
+            let pixel = &rgba[(y * width + x) * 4..(y * width + x + 1) * 4];
+            if pixel[3] == 0 {
+                indices.push(nearest_index(palette, [0.0; 4]));
+                continue;
+            }
+            let mut color = to_float(normalize(pixel));
+            for (value, error) in color.iter_mut().zip(current[x].iter()) {
+                *value = (*value + error).clamp(0.0, 255.0);
+            }
+            let index = nearest_index(palette, color);
+            indices.push(index);
+
+            let chosen = to_float(palette[index as usize]);
+            let spread = |target: &mut [f32; 4], weight: f32| {
+                for channel in 0..4 {
+                    target[channel] += (color[channel] - chosen[channel]) * weight;
+                }
+            };
+            if x + 1 < width {
+                spread(&mut current[x + 1], 7.0 / 16.0);
+                spread(&mut next[x + 1], 1.0 / 16.0);
+            }
+            if x > 0 {
+                spread(&mut next[x - 1], 3.0 / 16.0);
+            }
+            spread(&mut next[x], 5.0 / 16.0);
+        }
+        // The next row becomes the current one
+        errors.rotate_left(width);
+        errors[width..].iter_mut().for_each(|error| *error = [0.0; 4]);
+    }
+    indices
+}
+
+/// How many emojis have been quantized and how much that has saved
+#[derive(Debug, Default)]
+pub struct QuantizationStats {
+    quantized: AtomicUsize,
+    too_lossy: AtomicUsize,
+    saved_bytes: AtomicUsize,
+}
+
+impl QuantizationStats {
+    /// Records a quantized emoji with the size of its PNG file with and without quantization
+    /// (both before they are optimized)
+    pub fn record_quantized(&self, original_bytes: usize, quantized_bytes: usize) {
+        self.quantized.fetch_add(1, Ordering::Relaxed);
+        self.saved_bytes.fetch_add(original_bytes.saturating_sub(quantized_bytes), Ordering::Relaxed);
+    }
+
+    /// Records an emoji that kept all of its colors
+    pub fn record_too_lossy(&self) {
+        self.too_lossy.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Display for QuantizationStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Quantized {} emoji(s), saving {} bytes before optimizing; {} emoji(s) kept all colors as the quality would have been too low",
+            self.quantized.load(Ordering::Relaxed),
+            self.saved_bytes.load(Ordering::Relaxed),
+            self.too_lossy.load(Ordering::Relaxed)
+        )
+    }
+}
+
+#[cfg(test)]
+fn gradient(width: u32, height: u32) -> Vec<u8> {
+    (0..height)
+        .flat_map(|y| (0..width).flat_map(move |x| vec![
+            (x * 255 / (width - 1)) as u8,
+            (y * 255 / (height - 1)) as u8,
+            128,
+            if x == 0 { 0 } else { 255 }
+        ]))
+        .collect()
+}
+
+#[test]
+fn test_quantize_gradient() {
+    let image = gradient(64, 64);
+    let quantizer = Quantizer { colors: 16, dither: false, min_psnr: 20.0 };
+    let quantized = quantizer.quantize(&image, 64);
+    assert!(quantized.palette.len() <= 16);
+    assert_eq!(quantized.indices.len(), 64 * 64);
+    // The transparent column stays transparent
+    assert_eq!(quantized.palette[quantized.indices[0] as usize], [0; 4]);
+
+    let dithered = Quantizer { dither: true, ..quantizer }.quantize(&image, 64);
+    assert!(dithered.palette.len() <= 16);
+    assert_eq!(dithered.palette, quantized.palette);
+    assert_eq!(dithered.palette[dithered.indices[64] as usize], [0; 4]);
+
+    let png = match quantizer.encode(&image, 64, 64).unwrap() {
+        Quantization::Encoded { png, psnr } => {
+            assert!(psnr >= 20.0);
+            png
+        },
+        Quantization::TooLossy { psnr } => panic!("The PSNR is only {}", psnr)
+    };
+    // The color type in the IHDR chunk is "indexed"
+    assert_eq!(png[25], 3);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gradient.png");
+    std::fs::write(&path, &png).unwrap();
+    let (width, height, pixels) = crate::builders::blobmoji::image_utils::read_rgba(&path).unwrap();
+    assert_eq!((width, height), (64, 64));
+    assert_eq!(pixels, quantized.to_rgba());
+}
+
+#[test]
+fn test_quantize_fallback() {
+    let image = gradient(64, 64);
+    // Two colors can't show a gradient
+    let quantizer = Quantizer::new(2);
+    assert!(matches!(quantizer.encode(&image, 64, 64).unwrap(), Quantization::TooLossy { psnr } if psnr < DEFAULT_MIN_PSNR));
+
+    // Images with few colors aren't changed at all
+    let two_colors: Vec<u8> = [[255, 0, 0, 255], [0, 0, 0, 0]].iter().cycle().take(16).flatten().copied().collect();
+    let quantized = quantizer.quantize(&two_colors, 4);
+    assert_eq!(quantized.to_rgba(), two_colors);
+    assert_eq!(psnr(&two_colors, &quantized.to_rgba()), f64::INFINITY);
+}
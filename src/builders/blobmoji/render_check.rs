@@ -0,0 +1,156 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Finds renders that are most likely broken: SVG files that parse fine but render to nothing
+//! (fully transparent) or to a solid black square (e.g. because of a missing fill).
+//! The check runs on the rendered image before it's padded, so the margin doesn't count.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Channels up to this value count as black
+const BLACK_LEVEL: u8 = 16;
+
+/// Cheap statistics of a rendered image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderStats {
+    /// The fraction of fully transparent pixels
+    pub transparent: f64,
+    /// The fraction of opaque (nearly) black pixels
+    pub black: f64,
+    /// The most common color of the pixels that aren't fully transparent (RGBA, premultiplied)
+    pub dominant_color: Option<[u8; 4]>,
+    /// The fraction of all pixels that have the dominant color
+    pub dominant: f64,
+}
+
+/// From which fraction of transparent or black pixels on a render is reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderCheck {
+    pub transparent: f64,
+    pub black: f64,
+}
+
+/// What's wrong with a render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderProblem {
+    Transparent(RenderStats),
+    Black(RenderStats),
+}
+
+impl Default for RenderCheck {
+    fn default() -> Self {
+        RenderCheck {
+            transparent: 1.0,
+            black: 0.95,
+        }
+    }
+}
+
+impl RenderStats {
+    /// Computes the statistics of RGBA pixels
+    pub fn from_pixels(pixels: &[u8]) -> RenderStats {
+        let count = pixels.len() / 4;
+        let mut transparent = 0;
+        let mut black = 0;
+        let mut colors: HashMap<[u8; 4], usize> = HashMap::new();
+        for pixel in pixels.chunks_exact(4) {
+            if pixel[3] == 0 {
+                transparent += 1;
+                continue;
+            }
+            if pixel[3] == 255 && pixel[..3].iter().all(|channel| *channel <= BLACK_LEVEL) {
+                black += 1;
+            }
+            *colors.entry([pixel[0], pixel[1], pixel[2], pixel[3]]).or_default() += 1;
+        }
+        // Ties are broken by the color, so the result doesn't depend on the hash map's order
+        let dominant = colors.into_iter()
+            .max_by_key(|(color, count)| (*count, *color));
+        let fraction = |part: usize| if count > 0 {
+            part as f64 / count as f64
+        } else {
+            0.0
+        };
+        RenderStats {
+            transparent: if count > 0 { fraction(transparent) } else { 1.0 },
+            black: fraction(black),
+            dominant_color: dominant.map(|(color, _)| color),
+            dominant: fraction(dominant.map(|(_, count)| count).unwrap_or_default()),
+        }
+    }
+}
+
+impl RenderCheck {
+    /// Checks the statistics of a render against the thresholds
+    pub fn check(&self, stats: &RenderStats) -> Option<RenderProblem> {
+        if stats.transparent >= self.transparent {
+            Some(RenderProblem::Transparent(*stats))
+        } else if stats.black >= self.black {
+            Some(RenderProblem::Black(*stats))
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}% transparent, {:.1}% black", self.transparent * 100.0, self.black * 100.0)?;
+        if let Some(color) = self.dominant_color {
+            write!(f, ", dominant color #{} ({:.1}%)", hex::encode(color), self.dominant * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for RenderProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderProblem::Transparent(stats) => write!(f, "The render is (almost) fully transparent ({})", stats),
+            RenderProblem::Black(stats) => write!(f, "The render is (almost) solid black ({})", stats),
+        }
+    }
+}
+
+#[test]
+fn test_render_stats() {
+    let transparent = vec![0; 4 * 4];
+    let stats = RenderStats::from_pixels(&transparent);
+    assert_eq!(stats.transparent, 1.0);
+    assert_eq!(stats.dominant_color, None);
+    assert!(matches!(RenderCheck::default().check(&stats), Some(RenderProblem::Transparent(_))));
+
+    // Three black pixels and a transparent one
+    let black = [[0, 0, 0, 255], [0, 0, 0, 255], [8, 8, 8, 255], [0, 0, 0, 0]].concat();
+    let stats = RenderStats::from_pixels(&black);
+    assert_eq!(stats.transparent, 0.25);
+    assert_eq!(stats.black, 0.75);
+    assert_eq!(stats.dominant_color, Some([0, 0, 0, 255]));
+    assert_eq!(stats.dominant, 0.5);
+    assert_eq!(RenderCheck::default().check(&stats), None);
+    let strict = RenderCheck { transparent: 0.5, black: 0.75 };
+    assert_eq!(strict.check(&stats), Some(RenderProblem::Black(stats)));
+    assert!(strict.check(&RenderStats::from_pixels(&transparent)).is_some());
+
+    // Half-transparent black doesn't count as black
+    let shadow = [[0, 0, 0, 128], [0, 0, 0, 128], [200, 10, 10, 255], [0, 0, 0, 128]].concat();
+    let stats = RenderStats::from_pixels(&shadow);
+    assert_eq!(stats.black, 0.0);
+    assert_eq!(stats.dominant_color, Some([0, 0, 0, 128]));
+    assert_eq!(stats.to_string(), "0.0% transparent, 0.0% black, dominant color #00000080 (75.0%)");
+
+    assert_eq!(RenderStats::from_pixels(&[]).transparent, 1.0);
+}
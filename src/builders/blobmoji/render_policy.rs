@@ -0,0 +1,293 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Decides how an emoji is rendered depending on its kinds, e.g. flags may be waved,
+//! keycaps get an additional margin and components (like skin tone swatches) are rendered as
+//! they are.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emoji::{Emoji, EmojiKind};
+
+/// Which side of the emoji is fitted to the render size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    Width,
+    Height,
+    /// The longer side, i.e. the whole emoji fits into the (square) render box
+    Contain,
+}
+
+/// Where an emoji is put vertically within the character, if it's smaller than that
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Anchor {
+    Center,
+    /// The lowest visible line is `anchor_margin` pixels above the bottom
+    Bottom,
+    /// The highest visible line is `anchor_margin` pixels below the top
+    Top,
+}
+
+/// How a single emoji is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RenderParams {
+    /// Whether the emoji gets a wavy appearance (if that's enabled with `--waveflag`)
+    pub waveflag: bool,
+    pub fit: Fit,
+    /// Whether the colors are reduced to the palette (if there is one)
+    pub reduce_colors: bool,
+    /// The space that's kept free on each side of the render box (in pixels)
+    pub margin: u32,
+    pub anchor: Anchor,
+    /// The distance to the top or bottom of the character for [Anchor::Top] and [Anchor::Bottom]
+    /// (in pixels)
+    pub anchor_margin: u32,
+}
+
+/// Changes to the [RenderParams] of a kind of emojis.
+/// Unset values are left as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderOverride {
+    pub waveflag: Option<bool>,
+    pub fit: Option<Fit>,
+    pub reduce_colors: Option<bool>,
+    pub margin: Option<u32>,
+    pub anchor: Option<Anchor>,
+    pub anchor_margin: Option<u32>,
+}
+
+/// Chooses the [RenderParams] for the emojis.
+/// The built-in defaults can be overridden per kind with a JSON file like this one:
+/// ```json
+/// {
+///   "emoji_keycap_sequence": { "margin": 12 },
+///   "emoji_component": { "reduce_colors": true },
+///   "emoji_flag_sequence": { "anchor": "bottom", "anchor_margin": 4 }
+/// }
+/// ```
+/// If an emoji has multiple kinds, they are applied in their sorted order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderPolicy(BTreeMap<EmojiKind, RenderOverride>);
+
+/// Skin tone modifiers
+const SKIN_TONES: RangeInclusive<u32> = 0x1f3fb..=0x1f3ff;
+/// Hair components (red, curly, bald, white)
+const HAIR: RangeInclusive<u32> = 0x1f9b0..=0x1f9b3;
+
+/// The margin of keycaps, as their keys would look larger than other emojis otherwise
+const KEYCAP_MARGIN: u32 = 8;
+/// The distance of (waved) flags to the bottom of the character, as they'd float otherwise
+const FLAG_ANCHOR_MARGIN: u32 = 8;
+
+impl Default for RenderParams {
+    fn default() -> Self {
+        RenderParams {
+            waveflag: false,
+            fit: Fit::Contain,
+            reduce_colors: true,
+            margin: 0,
+            anchor: Anchor::Center,
+            anchor_margin: 0,
+        }
+    }
+}
+
+impl RenderParams {
+    /// The data that's hashed together with the SVG, so that an emoji gets re-rendered if its
+    /// parameters change
+    pub fn cache_key(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+}
+
+impl RenderOverride {
+    fn apply(&self, params: &mut RenderParams) {
+        if let Some(waveflag) = self.waveflag {
+            params.waveflag = waveflag;
+        }
+        if let Some(fit) = self.fit {
+            params.fit = fit;
+        }
+        if let Some(reduce_colors) = self.reduce_colors {
+            params.reduce_colors = reduce_colors;
+        }
+        if let Some(margin) = self.margin {
+            params.margin = margin;
+        }
+        if let Some(anchor) = self.anchor {
+            params.anchor = anchor;
+        }
+        if let Some(anchor_margin) = self.anchor_margin {
+            params.anchor_margin = anchor_margin;
+        }
+    }
+}
+
+impl RenderPolicy {
+    /// Loads the overrides from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<RenderPolicy> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads the overrides from a JSON object that maps kinds (e.g. `emoji_flag_sequence`) to
+    /// [RenderOverride]s
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<RenderPolicy> {
+        let entries: BTreeMap<String, RenderOverride> = serde_json::from_reader(reader)?;
+        Ok(RenderPolicy(entries.into_iter()
+            .map(|(kind, render_override)| (
+                EmojiKind::from_str(&kind).unwrap_or_else(|err| err.get()),
+                render_override
+            ))
+            .collect()))
+    }
+
+    /// The kinds of an emoji that are relevant for the rendering.
+    /// Besides the ones from the emoji tables (or the guessed ones), flags and components are
+    /// also detected by their codepoints.
+    fn kinds(emoji: &Emoji) -> Vec<EmojiKind> {
+        let mut kinds = emoji.kinds.clone()
+            .or_else(|| emoji.guess_kinds())
+            .unwrap_or_default();
+        if emoji.is_flag() {
+            kinds.push(EmojiKind::EmojiFlagSequence);
+        }
+        if let [codepoint] = emoji.sequence.as_slice() {
+            if SKIN_TONES.contains(codepoint) || HAIR.contains(codepoint) {
+                kinds.push(EmojiKind::EmojiComponent);
+            }
+        }
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    }
+
+    /// The built-in parameters for a kind
+    fn default_override(kind: &EmojiKind) -> RenderOverride {
+        match kind {
+            EmojiKind::EmojiFlagSequence => RenderOverride {
+                waveflag: Some(true),
+                anchor: Some(Anchor::Bottom),
+                anchor_margin: Some(FLAG_ANCHOR_MARGIN),
+                ..Default::default()
+            },
+            EmojiKind::EmojiKeycapSequence => RenderOverride {
+                margin: Some(KEYCAP_MARGIN),
+                anchor: Some(Anchor::Center),
+                ..Default::default()
+            },
+            // Components are shown on their own (e.g. skin tone swatches), so they're rendered
+            // as plain squares in their exact colors
+            EmojiKind::EmojiComponent => RenderOverride {
+                waveflag: Some(false),
+                fit: Some(Fit::Contain),
+                reduce_colors: Some(false),
+                margin: Some(0),
+                anchor: Some(Anchor::Center),
+                anchor_margin: Some(0),
+            },
+            _ => RenderOverride::default()
+        }
+    }
+
+    /// Chooses how an emoji is rendered
+    pub fn for_emoji(&self, emoji: &Emoji) -> RenderParams {
+        let mut params = RenderParams::default();
+        for kind in Self::kinds(emoji) {
+            Self::default_override(&kind).apply(&mut params);
+            if let Some(render_override) = self.0.get(&kind) {
+                render_override.apply(&mut params);
+            }
+        }
+        params
+    }
+}
+
+#[test]
+fn test_for_emoji() {
+    let policy = RenderPolicy::default();
+    let params = |sequence: Vec<u32>| policy.for_emoji(&Emoji::from(sequence));
+
+    assert_eq!(params(vec![0x1f914]), RenderParams::default());
+    assert_eq!(params(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]), RenderParams::default());
+    assert_eq!(params(vec![0x1f469, 0x1f3fd]), RenderParams::default());
+
+    let flag = RenderParams {
+        waveflag: true,
+        anchor: Anchor::Bottom,
+        anchor_margin: FLAG_ANCHOR_MARGIN,
+        ..Default::default()
+    };
+    assert_eq!(params(vec![0x1f1e9, 0x1f1ea]), flag);
+    assert_eq!(policy.for_emoji(&Emoji::from_flag("DE-NW", None).unwrap()), flag);
+
+    assert_eq!(params(vec![0x23, 0xfe0f, 0x20e3]), RenderParams {
+        margin: KEYCAP_MARGIN,
+        ..Default::default()
+    });
+
+    let component = RenderParams {
+        reduce_colors: false,
+        ..Default::default()
+    };
+    assert_eq!(params(vec![0x1f3fd]), component);
+    assert_eq!(params(vec![0x1f9b0]), component);
+
+    // The kinds from the emoji tables are used as well
+    let mut number_sign = Emoji::from(vec![0x23]);
+    number_sign.kinds = Some(vec![EmojiKind::Emoji, EmojiKind::EmojiComponent]);
+    assert_eq!(policy.for_emoji(&number_sign), component);
+}
+
+#[test]
+fn test_overrides() {
+    let json = r#"{
+        "emoji_keycap_sequence": { "margin": 12, "fit": "height" },
+        "Emoji_Component": { "reduce_colors": true },
+        "emoji_flag_sequence": { "waveflag": false, "anchor": "center", "anchor_margin": 0 }
+    }"#;
+    let policy = RenderPolicy::from_reader(json.as_bytes()).unwrap();
+
+    assert_eq!(policy.for_emoji(&Emoji::from(vec![0x2a, 0xfe0f, 0x20e3])), RenderParams {
+        margin: 12,
+        fit: Fit::Height,
+        ..Default::default()
+    });
+    assert_eq!(policy.for_emoji(&Emoji::from(vec![0x1f3fb])), RenderParams::default());
+    assert_eq!(policy.for_emoji(&Emoji::from(vec![0x1f1e9, 0x1f1ea])), RenderParams::default());
+    assert_eq!(policy.for_emoji(&Emoji::from(vec![0x1f914])), RenderParams::default());
+
+    assert!(RenderPolicy::from_reader(r#"{"emoji": {"zoom": 2}}"#.as_bytes()).is_err());
+    assert!(RenderPolicy::from_reader(r#"{"emoji": {"fit": "stretch"}}"#.as_bytes()).is_err());
+    assert!(RenderPolicy::from_reader(r#"{"emoji": {"anchor": "left"}}"#.as_bytes()).is_err());
+
+    // Only the margin is changed, flags stay at the bottom
+    let policy = RenderPolicy::from_reader(r#"{"emoji_flag_sequence": {"anchor_margin": 2}}"#.as_bytes()).unwrap();
+    assert_eq!(policy.for_emoji(&Emoji::from(vec![0x1f1e9, 0x1f1ea])), RenderParams {
+        waveflag: true,
+        anchor: Anchor::Bottom,
+        anchor_margin: 2,
+        ..Default::default()
+    });
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The separate stages of rendering an emoji's SVG file to a bitmap.
+//! Each stage only works on data in memory, so they can be tested without a build directory.
+
+use tiny_skia::Pixmap;
+use usvg::FitTo;
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+use crate::emoji::Emoji;
+use crate::emoji_processor::EmojiProcessor;
+use crate::emoji_processors::reduce_colors::ReduceColors;
+use crate::keywords::unescape_xml;
+use crate::builders::blobmoji::{RENDER_AND_CHARACTER_HEIGHT, RENDER_WIDTH, WAVE_FACTOR};
+use crate::builders::blobmoji::render_policy::{Fit, RenderParams};
+use crate::builders::blobmoji::tweaks::{ForceFit, Tweak};
+use crate::builders::blobmoji::waveflag;
+use crate::builders::blobmoji::waveflag::WaveMask;
+
+/// The size of the box that an emoji gets fitted into (i.e. the image without its margin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderBox {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RenderBox {
+    /// The render box that's left over if `params.margin` is kept free on each side
+    pub fn new(params: &RenderParams) -> RenderBox {
+        RenderBox {
+            width: RENDER_WIDTH.saturating_sub(2 * params.margin),
+            height: RENDER_AND_CHARACTER_HEIGHT.saturating_sub(2 * params.margin),
+        }
+    }
+}
+
+/// Parses the (already loaded) SVG file
+pub fn load_tree(data: &[u8], opt: &usvg::Options) -> Result<usvg::Tree, usvg::Error> {
+    usvg::Tree::from_data(data, opt)
+}
+
+/// The generic font families of CSS, which usvg always resolves to some font
+const GENERIC_FAMILIES: &[&str] = &["serif", "sans-serif", "cursive", "fantasy", "monospace"];
+
+/// A font family that the text of an SVG file asks for, but which isn't in the font database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontFallback {
+    /// The requested family
+    pub family: String,
+    /// The font that will be used instead (the next available family or the default font)
+    pub substitute: String,
+}
+
+/// Finds the font families of the text in an SVG file (`<text>`, `<tspan>` and `<textPath>`)
+/// that aren't available in `opt.fontdb`, as the text would silently be rendered with another
+/// font otherwise.
+///
+/// The families are taken from the `font-family` attribute and from `style` attributes
+/// (including the ones inherited from the parent elements), but not from stylesheets.
+/// Files that aren't valid UTF-8 (or XML) are skipped, as [load_tree] will complain about them.
+pub fn font_fallbacks(data: &[u8], opt: &usvg::Options) -> Vec<FontFallback> {
+    let data = match std::str::from_utf8(data) {
+        Ok(data) => data,
+        Err(_) => return vec![]
+    };
+    let available = |family: &str| GENERIC_FAMILIES.contains(&family.to_lowercase().as_str())
+        || opt.fontdb.faces().iter().any(|face| face.family.eq_ignore_ascii_case(family));
+
+    let mut fallbacks: Vec<FontFallback> = vec![];
+    // The (inherited) families of the open elements and the name of the last element
+    let mut families: Vec<Option<Vec<String>>> = vec![];
+    let mut element = String::new();
+    for token in Tokenizer::from(data) {
+        let token = match token {
+            Ok(token) => token,
+            Err(_) => break
+        };
+        match token {
+            Token::ElementStart { local, .. } => {
+                element = local.as_str().to_string();
+                let inherited = families.last().cloned().flatten();
+                families.push(inherited);
+            },
+            Token::Attribute { local, value, .. } => {
+                let value = unescape_xml(value.as_str());
+                let requested = match local.as_str() {
+                    "font-family" => Some(value.as_str()),
+                    "style" => value.split(';')
+                        .filter_map(|declaration| {
+                            let mut parts = declaration.splitn(2, ':');
+                            match (parts.next(), parts.next()) {
+                                (Some(property), Some(value)) if property.trim() == "font-family" => Some(value),
+                                _ => None
+                            }
+                        })
+                        .next_back(),
+                    _ => None
+                };
+                if let (Some(requested), Some(current)) = (requested, families.last_mut()) {
+                    *current = Some(parse_families(requested));
+                }
+            },
+            Token::ElementEnd { end: ElementEnd::Open, .. } | Token::ElementEnd { end: ElementEnd::Empty, .. } => {
+                if let Some(Some(requested)) = families.last().filter(|_| matches!(element.as_str(), "text" | "tspan" | "textPath")) {
+                    let substitute = requested.iter()
+                        .find(|family| available(family))
+                        .cloned()
+                        .unwrap_or_else(|| opt.font_family.clone());
+                    for family in requested.iter().take_while(|family| !available(family)) {
+                        if !fallbacks.iter().any(|fallback| &fallback.family == family) {
+                            fallbacks.push(FontFallback { family: family.clone(), substitute: substitute.clone() });
+                        }
+                    }
+                }
+                if let Token::ElementEnd { end: ElementEnd::Empty, .. } = token {
+                    families.pop();
+                }
+            },
+            Token::ElementEnd { end: ElementEnd::Close(..), .. } => {
+                families.pop();
+            },
+            _ => ()
+        }
+    }
+    fallbacks
+}
+
+/// Splits the value of `font-family` into the (unquoted) families
+fn parse_families(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|family| family.trim().trim_matches(|c| c == '\'' || c == '"').trim().to_string())
+        .filter(|family| !family.is_empty())
+        .collect()
+}
+
+/// Runs the processors on the tree.
+/// A processor that fails is logged and skipped, i.e. its input is used for the next stage.
+/// At the moment, this is only the color reduction (if there is a palette).
+pub fn apply_processors(emoji: &Emoji, tree: usvg::Tree, reduce_colors: Option<&ReduceColors>) -> usvg::Tree {
+    match reduce_colors {
+        Some(reduce_colors) => match reduce_colors.process(emoji, tree) {
+            Ok(tree) => tree,
+            Err((tree, err)) => {
+                error!("Could not reduce colors on emoji {}: {:?}", emoji, err);
+                tree
+            }
+        },
+        None => tree
+    }
+}
+
+/// Decides which side of an image with the given `size` is fitted to the render box and how
+/// large it gets.
+/// # Arguments
+/// * `size` - the size of the SVG image
+/// * `waved` - whether the waveflag effect is applied later on (which makes the image taller)
+/// * `render_box` - the space that's available for the image
+/// * `fit` - which side is fitted if the tweak doesn't force one
+/// * `tweak` - manual adjustments for this emoji
+pub fn compute_fit(size: usvg::Size, waved: bool, render_box: RenderBox, fit: Fit, tweak: Option<&Tweak>) -> FitTo {
+    let waved_height = if waved {
+        size.height() * (1.0 + WAVE_FACTOR as f64)
+    } else {
+        size.height()
+    };
+
+    let fit_height = match tweak.and_then(|tweak| tweak.force_fit) {
+        Some(ForceFit::Height) => true,
+        Some(ForceFit::Width) => false,
+        None => match fit {
+            Fit::Height => true,
+            Fit::Width => false,
+            Fit::Contain => waved_height > size.width()
+        }
+    };
+    let scale = tweak.map(|tweak| tweak.scale).unwrap_or(1.0);
+
+    if fit_height {
+        if waved {
+            FitTo::Height((render_box.height as f32 * scale / (1.0 + WAVE_FACTOR)) as u32)
+        } else {
+            FitTo::Height((render_box.height as f32 * scale) as u32)
+        }
+    } else {
+        FitTo::Width((render_box.width as f32 * scale) as u32)
+    }
+}
+
+/// Renders the tree to a new pixmap with the size given by `fit_to`
+pub fn rasterize(tree: &usvg::Tree, fit_to: FitTo) -> Option<Pixmap> {
+    let rendered_size = fit_to.fit_to(tree.svg_node().size.to_screen_size())?;
+    // This is copied from the minimal example for resvg
+    let mut pixmap = Pixmap::new(rendered_size.width(), rendered_size.height())?;
+    resvg::render(tree, fit_to, pixmap.as_mut())?;
+    Some(pixmap)
+}
+
+/// Applies the waveflag effect to a rendered image, with the offsets from the mask if there is one.
+/// The image gets taller by [WAVE_FACTOR] of its height.
+/// # Returns
+/// The resulting pixels and their width and height
+pub fn wave_flag(pixmap: &Pixmap, mask: Option<&WaveMask>) -> (Vec<u8>, u32, u32) {
+    let height = pixmap.height();
+    let added_lines = (height as f32 * WAVE_FACTOR) as usize;
+    match mask {
+        Some(mask) => waveflag::waveflag_with_mask(pixmap.data(), pixmap.width() as usize, height, added_lines, mask),
+        None => waveflag::waveflag(pixmap.data(), pixmap.width() as usize, height, added_lines)
+    }
+}
+
+/// Parses, processes and renders the content of an SVG file (which has already been read, e.g.
+/// for its hash).
+/// Errors are logged with the emoji they occurred for.
+pub fn render_data(
+    emoji: &Emoji,
+    data: &[u8],
+    opt: &usvg::Options,
+    reduce_colors: Option<&ReduceColors>,
+    fit: impl FnOnce(usvg::Size) -> FitTo,
+) -> Option<Pixmap> {
+    let tree = match load_tree(data, opt) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("Error in loading the SVG file for {}: {:?}", emoji, err);
+            return None;
+        }
+    };
+    let tree = apply_processors(emoji, tree, reduce_colors);
+    let fit_to = fit(tree.svg_node().size);
+    let pixmap = rasterize(&tree, fit_to);
+    if pixmap.is_none() {
+        error!("Failed to render {}", emoji);
+    }
+    pixmap
+}
+
+
+#[cfg(test)]
+use std::path::PathBuf;
+
+/// How much a channel of a rendered pixel may differ from the golden image.
+/// Gradients and antialiasing aren't exactly the same in every version of tiny-skia.
+#[cfg(test)]
+const GOLDEN_TOLERANCE: u8 = 4;
+
+#[cfg(test)]
+fn load_test_tree(name: &str) -> usvg::Tree {
+    let data = std::fs::read(PathBuf::from("test_files/golden").join(name)).unwrap();
+    load_tree(&data, &usvg::Options::default()).unwrap()
+}
+
+/// Compares the pixels with a checked-in PNG file.
+/// If the environment variable `BLESS_GOLDEN` is set, the PNG file is replaced instead, which
+/// can be used after a (reviewed) change to the rendering.
+#[cfg(test)]
+fn assert_golden(name: &str, pixels: &[u8], width: u32, height: u32) {
+    let path = PathBuf::from("test_files/golden").join(name);
+    if std::env::var_os("BLESS_GOLDEN").is_some() {
+        let mut encoder = png::Encoder::new(std::fs::File::create(&path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+        return;
+    }
+
+    let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut golden = vec![0; info.buffer_size()];
+    reader.next_frame(&mut golden).unwrap();
+
+    assert_eq!((width, height), (info.width, info.height), "{} has a different size", name);
+    let mismatch = pixels.iter().zip(golden.iter())
+        .position(|(pixel, golden)| (*pixel as i16 - *golden as i16).abs() > GOLDEN_TOLERANCE as i16);
+    if let Some(index) = mismatch {
+        let pixel = index / 4;
+        panic!("{} differs at ({}, {}): {:?} instead of {:?}",
+               name,
+               pixel as u32 % width,
+               pixel as u32 / width,
+               &pixels[pixel * 4..pixel * 4 + 4],
+               &golden[pixel * 4..pixel * 4 + 4]);
+    }
+}
+
+#[cfg(test)]
+fn render_golden(name: &str, waved: bool) -> Pixmap {
+    let tree = load_test_tree(name);
+    let fit_to = compute_fit(tree.svg_node().size, waved, RenderBox::new(&RenderParams::default()), Fit::Contain, None);
+    rasterize(&tree, fit_to).unwrap()
+}
+
+#[test]
+fn test_load_tree() {
+    let tree = load_test_tree("flag.svg");
+    let size = tree.svg_node().size;
+    assert_eq!((64.0, 32.0), (size.width(), size.height()));
+
+    assert!(load_tree(b"<svg", &usvg::Options::default()).is_err());
+}
+
+#[test]
+fn test_apply_processors() {
+    let emoji = Emoji::from(vec![0x1f3f3]);
+    let tree = apply_processors(&emoji, load_test_tree("flat.svg"), None);
+    assert_eq!(32.0, tree.svg_node().size.width());
+}
+
+#[test]
+fn test_compute_fit() {
+    let square = usvg::Size::new(32.0, 32.0).unwrap();
+    let wide = usvg::Size::new(64.0, 32.0).unwrap();
+    let tall = usvg::Size::new(32.0, 64.0).unwrap();
+    let render_box = RenderBox::new(&RenderParams::default());
+
+    assert_eq!(FitTo::Width(128), compute_fit(square, false, render_box, Fit::Contain, None));
+    assert_eq!(FitTo::Width(128), compute_fit(wide, false, render_box, Fit::Contain, None));
+    assert_eq!(FitTo::Height(128), compute_fit(tall, false, render_box, Fit::Contain, None));
+    assert_eq!(FitTo::Height(128), compute_fit(wide, false, render_box, Fit::Height, None));
+    // The wave makes a square image taller than wide, so it needs to leave some space for that
+    assert_eq!(FitTo::Height(116), compute_fit(square, true, render_box, Fit::Contain, None));
+
+    let margin = RenderBox::new(&RenderParams { margin: 8, ..Default::default() });
+    assert_eq!(RenderBox { width: 112, height: 112 }, margin);
+    assert_eq!(FitTo::Height(112), compute_fit(tall, false, margin, Fit::Contain, None));
+
+    let tweak = Tweak {
+        scale: 0.5,
+        force_fit: Some(ForceFit::Height),
+        ..Default::default()
+    };
+    assert_eq!(FitTo::Height(64), compute_fit(wide, false, render_box, Fit::Contain, Some(&tweak)));
+}
+
+#[test]
+fn test_rasterize() {
+    let tree = load_test_tree("flag.svg");
+    let pixmap = rasterize(&tree, FitTo::Height(16)).unwrap();
+    assert_eq!((32, 16), (pixmap.width(), pixmap.height()));
+}
+
+#[test]
+fn test_golden_flat() {
+    let pixmap = render_golden("flat.svg", false);
+    assert_golden("flat.png", pixmap.data(), pixmap.width(), pixmap.height());
+}
+
+#[test]
+fn test_golden_gradient() {
+    let pixmap = render_golden("gradient.svg", false);
+    assert_golden("gradient.png", pixmap.data(), pixmap.width(), pixmap.height());
+}
+
+#[test]
+fn test_golden_waveflag() {
+    let pixmap = render_golden("flag.svg", true);
+    let (pixels, width, height) = wave_flag(&pixmap, None);
+    assert_eq!((128, 70), (width, height));
+    assert_golden("flag.png", &pixels, width, height);
+}
+
+#[test]
+fn test_font_fallbacks() {
+    let opt = usvg::Options {
+        font_family: String::from("cursive"),
+        ..Default::default()
+    };
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="32" height="32">
+        <text font-family="'Nonexistent Emoji Sans', serif">ABC</text>
+        <g style="fill: red; font-family: &quot;Missing Mono&quot;">
+            <text>1<tspan font-family="monospace">2</tspan></text>
+        </g>
+        <text font-family="sans-serif">OK</text>
+        <rect font-family="Not Text" width="1" height="1"/>
+    </svg>"#;
+    let fallbacks = font_fallbacks(svg, &opt);
+    assert_eq!(fallbacks, vec![
+        FontFallback { family: String::from("Nonexistent Emoji Sans"), substitute: String::from("serif") },
+        FontFallback { family: String::from("Missing Mono"), substitute: String::from("cursive") },
+    ]);
+
+    assert!(font_fallbacks(b"<svg xmlns=\"http://www.w3.org/2000/svg\"><text>ABC</text></svg>", &opt).is_empty());
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::builders::blobmoji::error::BlobmojiError;
+use crate::emoji::Emoji;
+
+/// The number of glyphs listed in the [SizeReport]
+pub const LARGEST_GLYPHS: usize = 10;
+
+/// A limit for the size of a single (optimized) PNG file.
+/// CBDT fonts embed them as they are, so a few large images can make the whole font large.
+#[derive(Debug, Clone, Default)]
+pub struct SizeBudget {
+    /// The maximum size in bytes (no limit if `None`)
+    pub max_bytes: Option<u64>,
+    /// Whether exceeding the budget is an error (otherwise it's only a warning)
+    pub enforce: bool,
+}
+
+/// A summary of the sizes of the PNG files that end up in the font
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeReport {
+    /// The largest glyphs (name and size in bytes), largest first
+    pub largest: Vec<(String, u64)>,
+    /// The sum of all sizes. This is an estimate for the size of the embedded bitmaps
+    pub total: u64,
+    /// The number of glyphs
+    pub count: usize,
+}
+
+impl SizeBudget {
+    /// Checks the size of an emoji's PNG file against the budget
+    pub fn check(&self, emoji: &Emoji, size: u64) -> Result<(), BlobmojiError> {
+        match self.max_bytes {
+            Some(max_bytes) if size > max_bytes => {
+                if self.enforce {
+                    error!("{} exceeds the size budget: {} bytes (max. {} bytes)", emoji, size, max_bytes);
+                    Err(BlobmojiError::SizeBudgetExceeded(size, max_bytes))
+                } else {
+                    warn!("{} exceeds the size budget: {} bytes (max. {} bytes)", emoji, size, max_bytes);
+                    Ok(())
+                }
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl SizeReport {
+    /// Summarizes the sizes of the glyphs, keeping the `top_n` largest ones
+    pub fn from_sizes<I: IntoIterator<Item=(String, u64)>>(sizes: I, top_n: usize) -> SizeReport {
+        let sizes = sizes.into_iter().collect_vec();
+        let total = sizes.iter().map(|(_, size)| size).sum();
+        let count = sizes.len();
+        let largest = sizes.into_iter()
+            .sorted_by(|(name_a, size_a), (name_b, size_b)| size_b.cmp(size_a).then(name_a.cmp(name_b)))
+            .take(top_n)
+            .collect();
+        SizeReport {
+            largest,
+            total,
+            count,
+        }
+    }
+}
+
+impl Display for SizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Embedded bitmaps: {} glyphs, {} bytes in total", self.count, self.total)?;
+        if !self.largest.is_empty() {
+            writeln!(f, "Largest glyphs:")?;
+            for (name, size) in &self.largest {
+                writeln!(f, "  {}: {} bytes", name, size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_size_budget() {
+    let emoji = Emoji::from(vec![0x2615]);
+    let warn = SizeBudget {
+        max_bytes: Some(1000),
+        enforce: false,
+    };
+    let enforce = SizeBudget {
+        max_bytes: Some(1000),
+        enforce: true,
+    };
+
+    assert!(SizeBudget::default().check(&emoji, 40_000).is_ok());
+    assert!(warn.check(&emoji, 1000).is_ok());
+    assert!(warn.check(&emoji, 1001).is_ok());
+    assert!(enforce.check(&emoji, 1000).is_ok());
+    assert!(matches!(enforce.check(&emoji, 1001), Err(BlobmojiError::SizeBudgetExceeded(1001, 1000))));
+}
+
+#[test]
+fn test_size_report() {
+    // File names with the sizes of their PNG files
+    let png_sizes = vec![
+        ("emoji_u1f3f3_200d_1f308.png", 1_024),
+        ("emoji_u1f914.png", 5_210),
+        ("emoji_u1f9a6.png", 40_960),
+        ("emoji_u2615.png", 5_210),
+    ];
+    let report = SizeReport::from_sizes(
+        png_sizes.into_iter().map(|(name, size)| (name.to_string(), size)),
+        3,
+    );
+
+    assert_eq!(report.count, 4);
+    assert_eq!(report.total, 52_404);
+    assert_eq!(report.largest, vec![
+        (String::from("emoji_u1f9a6.png"), 40_960),
+        (String::from("emoji_u1f914.png"), 5_210),
+        (String::from("emoji_u2615.png"), 5_210),
+    ]);
+    assert!(report.to_string().starts_with("Embedded bitmaps: 4 glyphs, 52404 bytes in total\n"));
+
+    assert_eq!(SizeReport::from_sizes(vec![], 3), SizeReport::default());
+}
@@ -0,0 +1,228 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Checkpoints of the steps that build a font from the images, so a build that has failed
+//! (usually because of the Python environment) continues where it stopped.
+//!
+//! `stages.json` in the build directory records the fingerprint of the input of each completed
+//! [Stage] (per font, i.e. `normal` and `win`), its output files are kept in `stages/<font>/`.
+//! If a stage's input is still the same in the next build, its output is restored instead of
+//! running it again.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{copy, create_dir_all, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::builders::blobmoji::build_dir::BuildDir;
+use crate::configs::ConfigError;
+
+/// The steps of building a font whose output is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// add_glyphs.py, which creates the TTX file (and the glyph map)
+    AddGlyphs,
+    /// Compiling the TTX file
+    BuildTtf,
+    /// emoji_builder.py, which adds the bitmaps
+    EmojiBuilder,
+}
+
+/// The fingerprint of the input of a [Stage], i.e. a hash of everything it depends on
+pub struct Fingerprint(Sha256);
+
+/// The completed stages of the fonts with the fingerprints of their inputs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Checkpoints(BTreeMap<String, BTreeMap<Stage, String>>);
+
+/// Restores and records the outputs of the stages (see the [module documentation](self))
+pub struct StageStore {
+    file: PathBuf,
+    dir: PathBuf,
+    checkpoints: Mutex<Checkpoints>,
+    /// This stage and the ones after it are always run
+    from_stage: Option<Stage>,
+}
+
+impl Stage {
+    /// The name that is used for `--from-stage` and in `stages.json`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::AddGlyphs => "add_glyphs",
+            Stage::BuildTtf => "build_ttf",
+            Stage::EmojiBuilder => "emoji_builder",
+        }
+    }
+
+    /// The names of all stages (in their order)
+    pub const NAMES: [&'static str; 3] = ["add_glyphs", "build_ttf", "emoji_builder"];
+}
+
+impl FromStr for Stage {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "add_glyphs" => Ok(Stage::AddGlyphs),
+            "build_ttf" => Ok(Stage::BuildTtf),
+            "emoji_builder" => Ok(Stage::EmojiBuilder),
+            _ => Err(ConfigError::InvalidValue(String::from("from_stage"), s.to_string()))
+        }
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Fingerprint {
+    pub fn new() -> Fingerprint {
+        Fingerprint(Sha256::new())
+    }
+
+    /// Adds some data. Its length is added as well, so the boundaries of the parts matter.
+    pub fn add<D: AsRef<[u8]>>(&mut self, data: D) -> &mut Self {
+        let data = data.as_ref();
+        self.0.input((data.len() as u64).to_le_bytes());
+        self.0.input(data);
+        self
+    }
+
+    /// Adds the content of a file. Files that can't be read are added as such, the stage that
+    /// reads them will fail anyway.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        match std::fs::read(path.as_ref()) {
+            Ok(content) => self.add(b"file").add(content),
+            Err(_) => self.add(b"unreadable").add(path.as_ref().to_string_lossy().as_bytes())
+        }
+    }
+
+    /// The fingerprint in hexadecimal
+    pub fn finish(self) -> String {
+        hex::encode(self.0.result())
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint::new()
+    }
+}
+
+impl StageStore {
+    /// Loads the checkpoints of the build directory. If they can't be read, all stages are run.
+    pub fn open(build_dir: &BuildDir, from_stage: Option<Stage>) -> StageStore {
+        let file = build_dir.stages();
+        let checkpoints = match File::open(&file) {
+            Ok(reader) => serde_json::from_reader(BufReader::new(reader)).unwrap_or_else(|err| {
+                warn!("Couldn't read the checkpoints {:?}, all stages will be run: {:?}", file, err);
+                Checkpoints::default()
+            }),
+            Err(_) => Checkpoints::default()
+        };
+        StageStore {
+            file,
+            dir: build_dir.stages_dir(),
+            checkpoints: Mutex::new(checkpoints),
+            from_stage,
+        }
+    }
+
+    /// The directory in which the outputs of a font's stages are kept
+    fn font_dir(&self, font: &str) -> PathBuf {
+        self.dir.join(font)
+    }
+
+    /// Copies the outputs of a stage back if it has been completed with the same input.
+    /// Returns whether they have been restored (i.e. the stage can be skipped).
+    pub fn restore(&self, font: &str, stage: Stage, input: &str, outputs: &[&Path]) -> bool {
+        if self.from_stage.map(|from_stage| stage >= from_stage).unwrap_or(false) {
+            return false;
+        }
+        let completed = self.checkpoints.lock().unwrap().0.get(font)
+            .and_then(|stages| stages.get(&stage))
+            .map(|completed| completed == input)
+            .unwrap_or(false);
+        if !completed {
+            return false;
+        }
+        for output in outputs {
+            let kept = self.font_dir(font).join(output.file_name().unwrap_or_default());
+            if let Err(err) = copy(&kept, output) {
+                warn!("Couldn't restore {:?} of the stage {}, running it again: {:?}", kept, stage, err);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Keeps the outputs of a completed stage and records the fingerprint of its input
+    pub fn complete(&self, font: &str, stage: Stage, input: &str, outputs: &[&Path]) -> std::io::Result<()> {
+        let font_dir = self.font_dir(font);
+        create_dir_all(&font_dir)?;
+        for output in outputs {
+            copy(output, font_dir.join(output.file_name().unwrap_or_default()))?;
+        }
+        // Both fonts may be built at the same time, so the file is written while it's locked
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        checkpoints.0.entry(font.to_string())
+            .or_default()
+            .insert(stage, input.to_string());
+        serde_json::to_writer_pretty(File::create(&self.file)?, &*checkpoints)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stage_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path().join("build")).unwrap();
+    let output = dir.path().join("font.tmpl.ttx");
+    let fingerprint = |content: &str| {
+        let mut fingerprint = Fingerprint::new();
+        fingerprint.add(content);
+        fingerprint.finish()
+    };
+
+    let store = StageStore::open(&build_dir, None);
+    assert!(!store.restore("normal", Stage::AddGlyphs, &fingerprint("a"), &[&output]));
+    std::fs::write(&output, "glyphs").unwrap();
+    store.complete("normal", Stage::AddGlyphs, &fingerprint("a"), &[&output]).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    // The checkpoints are kept in the build directory
+    let store = StageStore::open(&build_dir, None);
+    assert!(store.restore("normal", Stage::AddGlyphs, &fingerprint("a"), &[&output]));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "glyphs");
+    assert!(!store.restore("normal", Stage::AddGlyphs, &fingerprint("b"), &[&output]));
+    assert!(!store.restore("win", Stage::AddGlyphs, &fingerprint("a"), &[&output]));
+    assert!(!store.restore("normal", Stage::BuildTtf, &fingerprint("a"), &[&output]));
+
+    // Stages from --from-stage on are always run
+    assert!(StageStore::open(&build_dir, Some(Stage::BuildTtf)).restore("normal", Stage::AddGlyphs, &fingerprint("a"), &[&output]));
+    assert!(!StageStore::open(&build_dir, Some(Stage::AddGlyphs)).restore("normal", Stage::AddGlyphs, &fingerprint("a"), &[&output]));
+
+    assert_eq!("build-ttf".parse::<Stage>().unwrap(), Stage::BuildTtf);
+    assert!("ttx".parse::<Stage>().is_err());
+}
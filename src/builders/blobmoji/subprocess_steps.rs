@@ -0,0 +1,431 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Runs the Python steps of the font build with the system's `python3` instead of the embedded
+//! interpreter, e.g. if the `libpython` that pyo3 uses doesn't work or lacks fontTools.
+//!
+//! The bundled scripts are written to a temporary directory, together with a small driver
+//! (`steps.py`) that runs one step per process. Its arguments are passed on the command line,
+//! larger inputs (like the emojis for add_glyphs.py) are passed as JSON files.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use pyo3::Python;
+use pyo3::prelude::PyModule;
+use tempfile::TempDir;
+
+use crate::builders::blobmoji::error::BlobmojiError;
+use crate::builders::blobmoji::glyph_map::GlyphMap;
+use crate::builders::blobmoji::noto_emoji_utils::{self, CbdtMetrics, FontSteps, GlyphInput, GlyphOptions, PreparedEmojis, PythonSteps};
+use crate::configs::ConfigError;
+
+/// The interpreter that's used if there's no embedded one
+pub const PYTHON: &str = "python3";
+
+/// Where the Python steps are run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PythonMode {
+    /// In the interpreter that's embedded with pyo3
+    #[default]
+    Embedded,
+    /// In a `python3` process per step
+    Subprocess,
+}
+
+/// [FontSteps] that are run by `python3` processes (see the [module documentation](self))
+pub struct SubprocessSteps {
+    python: PathBuf,
+    /// Contains the bundled scripts and the inputs and outputs of the steps
+    scripts: TempDir,
+}
+
+const STEPS_PY: &str = r#"
+import json
+import os
+import sys
+
+def read_aliases(filename, output):
+    import add_aliases
+    aliases = add_aliases.read_emoji_aliases(filename)
+    with open(output, 'w') as f:
+        json.dump([[list(alias), list(target)] for alias, target in aliases.items()], f)
+
+def add_ligatures(add_glyphs, font, ligatures):
+    from fontTools.ttLib.tables import otTables
+    cmap = font['cmap'].tables[0].cmap
+    existing = add_glyphs.get_gsub_ligature_lookup(font).SubTable[0].ligatures
+    added = 0
+    for sequence, glyph in ligatures:
+        if any(codepoint not in cmap for codepoint in sequence):
+            print('Not all codepoints of %s are in the cmap' % glyph, file=sys.stderr)
+            continue
+        glyphs = [cmap[codepoint] for codepoint in sequence]
+        same_first = existing.setdefault(glyphs[0], [])
+        if any(list(other.Component) == glyphs[1:] for other in same_first):
+            continue
+        ligature = otTables.Ligature()
+        ligature.CompCount = len(sequence)
+        ligature.Component = glyphs[1:]
+        ligature.LigGlyph = glyph
+        same_first.append(ligature)
+        added += 1
+    # Longer sequences need to come first as they would be shadowed otherwise
+    for same_first in existing.values():
+        same_first.sort(key=lambda ligature: -ligature.CompCount)
+    return added
+
+def add_glyphs(input):
+    import add_glyphs
+    from fontTools.ttLib import TTFont
+    from override_metrics import override_metrics
+    from read_glyph_map import glyph_map
+
+    with open(input) as f:
+        data = json.load(f)
+    font = TTFont()
+    font.importXML(data['ttx_tmpl'])
+    # The advances of the glyphs depend on the line height, so this has to be done first
+    if any(metric is not None for metric in data['metrics']):
+        override_metrics(font, *data['metrics'])
+    lineheight = font['hhea'].ascent - font['hhea'].descent
+    map_fn = add_glyphs.get_png_file_to_advance_mapper(lineheight)
+    seq_to_file = data.pop('seq_to_file')
+    chunk_size = max(data['chunk_size'] or len(seq_to_file), 1)
+    seq_to_advance = {}
+    for start in range(0, len(seq_to_file), chunk_size):
+        chunk = {tuple(sequence): image for sequence, image in seq_to_file[start:start + chunk_size]}
+        seq_to_advance.update(add_glyphs.remap_values(chunk, map_fn))
+    del seq_to_file
+    aliases = {tuple(alias): tuple(target) for alias, target in data['aliases']}
+    aliases = add_glyphs.apply_aliases(seq_to_advance, aliases) if aliases else None
+    vadvance, add_vmtx = add_glyphs.get_vertical_metrics(font, lineheight)
+    add_cmap4_and_glyf = data['add_cmap4_and_glyf']
+    add_glyphs.update_font_data(font, seq_to_advance, vadvance, aliases, add_cmap4_and_glyf, add_cmap4_and_glyf, add_vmtx)
+    if add_cmap4_and_glyf:
+        print('Added %d ligatures to GSUB' % add_ligatures(add_glyphs, font, data['ligatures']))
+
+    glyph_order, glyphs = glyph_map(font, data['in_font'])
+    font.saveXML(data['ttx'])
+    with open(data['output'], 'w') as f:
+        json.dump([glyph_order, [[list(sequence), glyph] for sequence, glyph in glyphs.items()]], f)
+
+def build_ttf(ttx, ttf):
+    from fontTools import ttx as ttx_module
+    ttx_module.main(['-o', ttf, ttx])
+
+def emoji_builder(*argv):
+    import emoji_builder
+    emoji_builder.main(['emoji_builder.py'] + list(argv))
+
+def map_pua(ttf, output):
+    import map_pua_emoji
+    map_pua_emoji.add_pua_cmap(ttf, output)
+
+def add_vs_cmap(ttf, output, *vs_added):
+    from nototools import add_vs_cmap
+    add_vs_cmap.modify_fonts(
+        [ttf],
+        presentation="'emoji'",
+        output=os.path.basename(output),
+        dst_dir=os.path.dirname(output) or '.',
+        vs_added={int(codepoint, 16) for codepoint in vs_added})
+
+def read_vs_cmap(ttf, output):
+    from read_vs_cmap import emoji_variation_bases
+    with open(output, 'w') as f:
+        json.dump(sorted(emoji_variation_bases(ttf)), f)
+
+def read_vmtx(ttf, output):
+    from read_vmtx import vertical_glyphs
+    glyphs = vertical_glyphs(ttf)
+    with open(output, 'w') as f:
+        json.dump(None if glyphs is None else sorted(glyphs), f)
+
+def set_license(ttf, output, description, url=None):
+    import set_license
+    set_license.set_license(ttf, output, description, url)
+
+def compress_woff2(ttf, output):
+    from fontTools.ttLib import woff2
+    woff2.compress(ttf, output)
+
+if __name__ == '__main__':
+    globals()[sys.argv[1]](*sys.argv[2:])
+"#;
+
+impl PythonMode {
+    /// Uses the embedded interpreter if it can be initialized and finds fontTools,
+    /// `python3` otherwise (if it's available at all)
+    pub fn detect() -> PythonMode {
+        let embedded = std::panic::catch_unwind(|| {
+            let gil = Python::acquire_gil();
+            PyModule::import(gil.python(), "fontTools.ttx").is_ok()
+        }).unwrap_or(false);
+        if embedded || !SubprocessSteps::available(PYTHON) {
+            PythonMode::Embedded
+        } else {
+            PythonMode::Subprocess
+        }
+    }
+
+    /// The steps that run in this mode
+    pub fn font_steps(self) -> Result<Box<dyn FontSteps>, BlobmojiError> {
+        Ok(match self {
+            PythonMode::Embedded => Box::new(PythonSteps),
+            PythonMode::Subprocess => Box::new(SubprocessSteps::new(PYTHON)?)
+        })
+    }
+}
+
+impl FromStr for PythonMode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "embedded" => Ok(PythonMode::Embedded),
+            "subprocess" => Ok(PythonMode::Subprocess),
+            _ => Err(ConfigError::InvalidValue(String::from("python_mode"), s.to_string()))
+        }
+    }
+}
+
+impl Display for PythonMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PythonMode::Embedded => write!(f, "in the embedded Python interpreter"),
+            PythonMode::Subprocess => write!(f, "with {}", PYTHON)
+        }
+    }
+}
+
+impl SubprocessSteps {
+    /// Writes the bundled scripts to a temporary directory, they are run with `python`
+    pub fn new<P: AsRef<OsStr>>(python: P) -> std::io::Result<SubprocessSteps> {
+        let scripts = tempfile::Builder::new().prefix("emoji_builder_python").tempdir()?;
+        let files = [
+            ("steps.py", STEPS_PY),
+            ("add_glyphs.py", noto_emoji_utils::ADD_GLYPHS_PY),
+            ("add_aliases.py", noto_emoji_utils::ADD_ALIASES_PY),
+            ("add_emoji_gsub.py", noto_emoji_utils::ADD_EMOJI_GSUB_PY),
+            ("png.py", noto_emoji_utils::PNG_PY),
+            ("emoji_builder.py", noto_emoji_utils::EMOJI_BUILDER_PY),
+            ("map_pua_emoji.py", noto_emoji_utils::MAP_PUA_EMOJI_PY),
+            ("override_metrics.py", noto_emoji_utils::OVERRIDE_METRICS_PY),
+            ("read_glyph_map.py", noto_emoji_utils::READ_GLYPH_MAP_PY),
+            ("read_vs_cmap.py", noto_emoji_utils::READ_VS_CMAP_PY),
+            ("read_vmtx.py", noto_emoji_utils::READ_VMTX_PY),
+            ("set_license.py", noto_emoji_utils::SET_LICENSE_PY),
+        ];
+        for (name, content) in files.iter() {
+            std::fs::write(scripts.path().join(name), content)?;
+        }
+        Ok(SubprocessSteps {
+            python: PathBuf::from(python.as_ref()),
+            scripts,
+        })
+    }
+
+    /// Whether `python` can be run and finds fontTools
+    pub fn available<P: AsRef<OsStr>>(python: P) -> bool {
+        Command::new(python)
+            .args(["-c", "import fontTools"])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+
+    /// Runs a function of `steps.py`.
+    /// If it fails, its output (usually the traceback) is returned as a [BlobmojiError::PythonError].
+    fn run<I, S>(&self, step: &str, args: I) -> Result<(), BlobmojiError>
+        where I: IntoIterator<Item=S>,
+              S: AsRef<OsStr> {
+        let args = args.into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect_vec();
+        debug!("Running {} with {:?}", step, args);
+        let output = Command::new(&self.python)
+            .arg(self.scripts.path().join("steps.py"))
+            .arg(step)
+            .args(&args)
+            .output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            for line in stdout.lines().chain(stderr.lines()) {
+                debug!("{}: {}", step, line);
+            }
+            Ok(())
+        } else {
+            Err(BlobmojiError::PythonError(format!(
+                "{} {} failed ({}):\n{}{}",
+                self.python.to_string_lossy(), step, output.status, stdout, stderr
+            )))
+        }
+    }
+
+    /// A file for the input or output of a step, which is deleted afterwards
+    fn data_file(&self) -> Result<tempfile::TempPath, BlobmojiError> {
+        Ok(tempfile::Builder::new()
+            .suffix(".json")
+            .tempfile_in(self.scripts.path())?
+            .into_temp_path())
+    }
+
+    fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, BlobmojiError> {
+        serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|err| BlobmojiError::IoError(err.into()))
+    }
+}
+
+impl FontSteps for SubprocessSteps {
+    fn add_glyphs(&self,
+                  aliases: &Option<PathBuf>,
+                  flag_aliases: &HashMap<Vec<u32>, Vec<u32>>,
+                  emojis: &PreparedEmojis,
+                  ttx_tmpl: &Path,
+                  ttx: &Path,
+                  options: &GlyphOptions) -> Result<GlyphMap, BlobmojiError> {
+        let GlyphOptions { add_cmap4_and_glyf, metrics, chunk_size } = *options;
+        let aliases: HashMap<Vec<u32>, Vec<u32>> = match aliases {
+            Some(aliases) => {
+                let output = self.data_file()?;
+                self.run("read_aliases", [aliases.as_os_str(), output.as_os_str()])?;
+                Self::read_json::<Vec<(Vec<u32>, Vec<u32>)>>(&output)?.into_iter().collect()
+            }
+            None => HashMap::new()
+        };
+        let input = GlyphInput::new(aliases, flag_aliases, emojis, add_cmap4_and_glyf);
+        let in_font = input.published.iter().map(|(in_font, _)| in_font.clone()).unique().collect_vec();
+
+        let data = self.data_file()?;
+        let output = self.data_file()?;
+        serde_json::to_writer(File::create(&data)?, &serde_json::json!({
+            "seq_to_file": input.seq_to_file,
+            "aliases": input.aliases.iter().collect_vec(),
+            "ligatures": input.ligatures.iter().map(|ligature| (&ligature.sequence, &ligature.glyph)).collect_vec(),
+            "in_font": in_font,
+            "metrics": [metrics.ascent, metrics.descent, metrics.line_gap],
+            "chunk_size": chunk_size,
+            "add_cmap4_and_glyf": add_cmap4_and_glyf,
+            "ttx_tmpl": ttx_tmpl,
+            "ttx": ttx,
+            "output": output.to_path_buf(),
+        })).map_err(|err| BlobmojiError::IoError(err.into()))?;
+        self.run("add_glyphs", [data.as_os_str()])?;
+
+        let (glyph_order, glyphs): (Vec<String>, Vec<(Vec<u32>, String)>) = Self::read_json(&output)?;
+        let glyphs: HashMap<Vec<u32>, String> = glyphs.into_iter().collect();
+        Ok(noto_emoji_utils::to_glyph_map(glyph_order, &glyphs, input.published))
+    }
+
+    fn build_ttf(&self, ttx: &Path, ttf: &Path) -> Result<(), BlobmojiError> {
+        self.run("build_ttf", [ttx, ttf])
+    }
+
+    fn emoji_builder(&self, tmpl_ttf: &Path, ttf: &Path, png_prefix: &Path, keep_outlines: bool, metrics: CbdtMetrics) -> Result<(), BlobmojiError> {
+        let mut args: Vec<OsString> = vec![];
+        if keep_outlines {
+            args.push("-O".into());
+        }
+        // Big metrics are emoji_builder.py's default
+        if metrics == CbdtMetrics::Small {
+            args.push("-S".into());
+        }
+        args.extend(vec![OsString::from("-V"), tmpl_ttf.into(), ttf.into(), png_prefix.into()]);
+        self.run("emoji_builder", args)
+    }
+
+    fn map_pua(&self, ttf: &Path, output: &Path) -> Result<(), BlobmojiError> {
+        self.run("map_pua", [ttf, output])
+    }
+
+    fn add_vs_cmap(&self, ttf: &Path, output: &Path, vs_added: &HashSet<u32>) -> Result<(), BlobmojiError> {
+        let mut args: Vec<OsString> = vec![ttf.into(), output.into()];
+        args.extend(vs_added.iter().sorted().map(|codepoint| format!("{:x}", codepoint).into()));
+        self.run("add_vs_cmap", args)?;
+
+        let bases = self.data_file()?;
+        self.run("read_vs_cmap", [output.as_os_str(), bases.as_os_str()])?;
+        let in_font: HashSet<u32> = Self::read_json::<Vec<u32>>(&bases)?.into_iter().collect();
+        debug!("The font has {} emoji variation sequences", in_font.len());
+        let missing = vs_added.difference(&in_font)
+            .copied()
+            .sorted()
+            .collect_vec();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(BlobmojiError::MissingVariationSequences(missing))
+        }
+    }
+
+    fn vertical_glyphs(&self, ttf: &Path) -> Result<Option<HashSet<String>>, BlobmojiError> {
+        let output = self.data_file()?;
+        self.run("read_vmtx", [ttf.as_os_str(), output.as_os_str()])?;
+        Ok(Self::read_json::<Option<Vec<String>>>(&output)?.map(|glyphs| glyphs.into_iter().collect()))
+    }
+
+    fn set_license(&self, ttf: &Path, output: &Path, description: &str, url: Option<&str>) -> Result<(), BlobmojiError> {
+        let mut args: Vec<OsString> = vec![ttf.into(), output.into(), description.into()];
+        if let Some(url) = url {
+            args.push(url.into());
+        }
+        self.run("set_license", args)
+    }
+
+    fn compress_woff2(&self, input: &Path, output: &Path) -> Result<(), BlobmojiError> {
+        self.run("compress_woff2", [input, output])
+    }
+}
+
+#[test]
+fn test_python_mode() {
+    assert_eq!("subprocess".parse::<PythonMode>().unwrap(), PythonMode::Subprocess);
+    assert_eq!("Embedded".parse::<PythonMode>().unwrap(), PythonMode::Embedded);
+    assert!("python2".parse::<PythonMode>().is_err());
+}
+
+#[test]
+fn test_subprocess_build_ttf() {
+    use crate::builders::blobmoji::{BuildDir, TMPL_TTX_TMPL_CONTENT};
+
+    if !SubprocessSteps::available(PYTHON) {
+        warn!("{} (with fontTools) isn't available, skipping the test", PYTHON);
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    let work_dir = build_dir.work_dir("test").unwrap();
+    std::fs::write(work_dir.ttx_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    let steps = SubprocessSteps::new(PYTHON).unwrap();
+    steps.build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+    let ttf = std::fs::read(work_dir.tmpl_ttf()).unwrap();
+    assert_eq!(&ttf[..4], &[0, 1, 0, 0]);
+
+    // The output of the failed step is kept
+    match steps.build_ttf(&dir.path().join("missing.ttx"), &dir.path().join("missing.ttf")) {
+        Err(BlobmojiError::PythonError(message)) => assert!(message.contains("build_ttf"), "{}", message),
+        other => panic!("Expected a Python error, got {:?}", other)
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::configs::ConfigError;
+use crate::emoji::{Emoji, EmojiKind};
+use crate::emoji_tables::EmojiTable;
+
+/// The emoji kinds that a subset can be made of
+const KINDS: &[&str] = &[
+    "emoji", "emoji_zwj_sequence", "emoji_sequence", "emoji_presentation", "modifier_base",
+    "emoji_component", "emoji_keycap_sequence", "emoji_flag_sequence", "emoji_modifier_sequence",
+];
+
+/// Describes which emojis end up in a subsetted font (e.g. a companion font that only contains
+/// flags).
+#[derive(Debug, Clone)]
+pub enum Subset {
+    /// All emojis of a given kind
+    Kind(EmojiKind),
+    /// An explicit list of emojis, given by their sequences or names
+    List(Vec<SubsetEntry>),
+}
+
+/// One line of a subset file.
+/// As names like `bed` are valid hexadecimal numbers as well, a line might be both, a sequence
+/// and a name.
+#[derive(Debug, Clone)]
+pub struct SubsetEntry {
+    /// The line as it has been written in the file
+    pub raw: String,
+    sequence: Option<Vec<u32>>,
+    lookup_name: String,
+}
+
+impl Subset {
+    /// Parses the value of the `--subset` argument.
+    /// It's either an [EmojiKind] (e.g. `emoji_flag_sequence`) or `@file` with `file` being a
+    /// newline-separated list of sequences or names.
+    /// # Errors
+    /// If the file can't be read or the kind is none of [KINDS]
+    pub fn parse(subset: &str) -> Result<Subset, ConfigError> {
+        if let Some(path) = subset.strip_prefix('@') {
+            Ok(Self::from_file(path)?)
+        } else {
+            EmojiKind::from_str(subset)
+                .map(Subset::Kind)
+                .map_err(|_| ConfigError::InvalidValue(
+                    String::from("subset"),
+                    format!("{} (expected @FILE or one of {})", subset, KINDS.join(", "))
+                ))
+        }
+    }
+
+    /// Reads a subset list from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Subset> {
+        let reader = BufReader::new(File::open(path)?);
+        Self::from_reader(reader)
+    }
+
+    /// Reads a subset list. Empty lines and lines starting with `#` are ignored.
+    pub fn from_reader<R: BufRead>(reader: R) -> std::io::Result<Subset> {
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                entries.push(SubsetEntry::from(line));
+            }
+        }
+        Ok(Subset::List(entries))
+    }
+
+    /// Checks whether an emoji belongs to this subset
+    pub fn contains(&self, emoji: &Emoji) -> bool {
+        match self {
+            Subset::Kind(kind) => emoji.kinds.clone()
+                .or_else(|| emoji.guess_kinds())
+                .map(|kinds| kinds.contains(kind))
+                .unwrap_or(false)
+                // Flags from the flags directory don't necessarily carry their kind
+                || (*kind == EmojiKind::EmojiFlagSequence && emoji.is_flag()),
+            Subset::List(entries) => entries.iter().any(|entry| entry.matches(emoji)),
+        }
+    }
+
+    /// Returns all entries of a subset list that are not matched by any of the given emojis.
+    /// A subset of a kind can't miss any members.
+    pub fn missing<'a, I: IntoIterator<Item=&'a Emoji>>(&self, emojis: I) -> Vec<&SubsetEntry> {
+        match self {
+            Subset::Kind(_) => vec![],
+            Subset::List(entries) => {
+                let emojis = emojis.into_iter().collect_vec();
+                entries.iter()
+                    .filter(|entry| !emojis.iter().any(|emoji| entry.matches(emoji)))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl SubsetEntry {
+    /// Checks whether this entry describes the given emoji (either by its sequence, ignoring
+    /// `U+FE0F`, or by its name)
+    pub fn matches(&self, emoji: &Emoji) -> bool {
+        let sequence_matches = self.sequence.as_ref()
+            .map(|sequence| without_fe0f(sequence) == without_fe0f(&emoji.sequence))
+            .unwrap_or(false);
+        sequence_matches || emoji.name.as_ref()
+            .map(|name| EmojiTable::normalize_lookup_name(name) == self.lookup_name)
+            .unwrap_or(false)
+    }
+}
+
+impl From<&str> for SubsetEntry {
+    fn from(line: &str) -> Self {
+        lazy_static! {
+            static ref SEQUENCE: Regex = Regex::new(r"^(?i:u\+)?[a-fA-F0-9]{1,8}([-_ ]+(?i:u\+)?[a-fA-F0-9]{1,8})*$").unwrap();
+            static ref CODEPOINT: Regex = Regex::new(r"[a-fA-F0-9]{1,8}").unwrap();
+        }
+        let sequence = if SEQUENCE.is_match(line) {
+            Some(CODEPOINT.find_iter(line)
+                .filter_map(|codepoint| u32::from_str_radix(codepoint.as_str(), 16).ok())
+                .collect())
+        } else {
+            None
+        };
+        SubsetEntry {
+            raw: line.to_string(),
+            sequence,
+            lookup_name: EmojiTable::normalize_lookup_name(line),
+        }
+    }
+}
+
+fn without_fe0f(sequence: &[u32]) -> Vec<u32> {
+    sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect()
+}
+
+#[test]
+fn test_flags_subset() {
+    let flags: Vec<_> = std::fs::read_dir("test_files/flags").unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| Emoji::from_path(entry.path(), None, true).unwrap())
+        .collect();
+    let emojis: Vec<_> = std::fs::read_dir("test_files/svg").unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| Emoji::from_path(entry.path(), None, false).unwrap())
+        .collect();
+
+    let subset = Subset::parse("emoji_flag_sequence").unwrap();
+    assert!(flags.iter().all(|flag| subset.contains(flag)));
+    assert!(!emojis.iter().any(|emoji| subset.contains(emoji)));
+}
+
+#[test]
+fn test_subset_kinds() {
+    assert!(KINDS.iter().all(|kind| matches!(Subset::parse(kind), Ok(Subset::Kind(kind)) if !matches!(kind, EmojiKind::Other(_)))));
+    match Subset::parse("emoji_flag_sequences") {
+        Err(ConfigError::InvalidValue(key, message)) => {
+            assert_eq!(key, "subset");
+            assert!(message.contains("emoji_flag_sequence, emoji_modifier_sequence"));
+        },
+        other => panic!("Expected an invalid value, got {:?}", other)
+    }
+    assert!(matches!(Subset::parse("@test_files/missing_subset.txt"), Err(ConfigError::Io(_))));
+}
+
+#[test]
+fn test_list_subset() {
+    let list = "# Some flags\nDE\n1f1ea 1f1fa\n\nU+1F3F3-FE0F-200D-1F308\nthinking face\n";
+    let subset = Subset::from_reader(std::io::Cursor::new(list)).unwrap();
+
+    let eu = Emoji::from_flag("EU", None).unwrap();
+    let rainbow = Emoji::from(vec![0x1f3f3, 0x200d, 0x1f308]);
+    let mut thinking = Emoji::from(vec![0x1f914]);
+    thinking.name = Some(String::from("Thinking Face"));
+    let germany = Emoji::from_flag("DE", None).unwrap();
+
+    assert!(subset.contains(&eu));
+    assert!(subset.contains(&rainbow));
+    assert!(subset.contains(&thinking));
+    // Flags are only recognized by their sequence, so "DE" is reported as missing
+    assert!(!subset.contains(&germany));
+
+    let missing = subset.missing(&[eu, rainbow, thinking, germany]);
+    assert_eq!(missing.iter().map(|entry| entry.raw.as_str()).collect_vec(), vec!["DE"]);
+}
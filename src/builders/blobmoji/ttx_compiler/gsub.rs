@@ -0,0 +1,728 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The GSUB table with the lookups that the Noto Color Emoji template and `add_glyphs.py` use:
+//! single, multiple and ligature substitutions and contextual substitutions based on classes
+//! (format 2 of lookup type 5 and 6).
+//! Extension lookups aren't supported, i.e. the compilation fails if an offset doesn't fit into
+//! 16 bits (fontTools would move the lookups to extension subtables in this case).
+
+use std::collections::{HashMap, VecDeque};
+
+use itertools::Itertools;
+
+use crate::builders::blobmoji::ttx_compiler::{BigEndian, GlyphIds, glyph_id, TtxError};
+use crate::builders::blobmoji::ttx_compiler::xml::{Element, to_u16};
+
+/// The LookupFlag bit that indicates that the lookup has a MarkFilteringSet
+const USE_MARK_FILTERING_SET: u16 = 0x10;
+
+/// A table in GSUB that refers to its subtables with 16-bit offsets (relative to its start)
+#[derive(Debug, Default)]
+struct OtTable {
+    data: Vec<u8>,
+    /// The positions of the offsets in `data` and the tables they point to
+    subtables: Vec<(usize, OtTable)>,
+}
+
+/// The data of a laid out table with the positions of its offsets and the indices of the
+/// tables they point to
+type LaidOutTable = (Vec<u8>, Vec<(usize, usize)>);
+
+impl OtTable {
+    fn new() -> OtTable {
+        OtTable::default()
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.u16(value);
+    }
+
+    /// Writes the number of elements that follow
+    fn count(&mut self, count: usize) -> Result<(), TtxError> {
+        self.u16(to_u16("a count in GSUB", count as i64)?);
+        Ok(())
+    }
+
+    fn tag(&mut self, tag: &str) -> Result<(), TtxError> {
+        if tag.len() > 4 || !tag.is_ascii() {
+            return Err(TtxError::Invalid(format!("{:?} is not a valid tag", tag)));
+        }
+        // Shorter tags are padded with spaces
+        self.data.extend_from_slice(format!("{:<4}", tag).as_bytes());
+        Ok(())
+    }
+
+    /// Writes the offset to a subtable (or a null offset)
+    fn offset(&mut self, subtable: Option<OtTable>) {
+        if let Some(subtable) = subtable {
+            self.subtables.push((self.data.len(), subtable));
+        }
+        self.u16(0);
+    }
+
+    /// Lays out the table and its subtables level by level and fills in the offsets
+    fn compile(self) -> Result<Vec<u8>, TtxError> {
+        // The tables in the order they are written with the positions of their offsets and
+        // the indices of the tables these offsets point to
+        let mut tables: Vec<LaidOutTable> = Vec::new();
+        let mut queue: VecDeque<OtTable> = VecDeque::new();
+        queue.push_back(self);
+        let mut next_index = 1;
+        while let Some(table) = queue.pop_front() {
+            let mut offsets = Vec::with_capacity(table.subtables.len());
+            for (position, subtable) in table.subtables {
+                offsets.push((position, next_index));
+                next_index += 1;
+                queue.push_back(subtable);
+            }
+            tables.push((table.data, offsets));
+        }
+
+        let mut starts = Vec::with_capacity(tables.len());
+        let mut length = 0;
+        for (data, _) in &tables {
+            starts.push(length);
+            length += data.len();
+        }
+
+        let mut compiled = Vec::with_capacity(length);
+        for (index, (data, offsets)) in tables.iter().enumerate() {
+            let start = compiled.len();
+            compiled.extend_from_slice(data);
+            for (position, subtable) in offsets {
+                let offset = starts[*subtable] - starts[index];
+                if offset > 0xffff {
+                    return Err(TtxError::OffsetOverflow(String::from("GSUB")));
+                }
+                compiled[start + position..start + position + 2].copy_from_slice(&(offset as u16).to_be_bytes());
+            }
+        }
+        Ok(compiled)
+    }
+}
+
+/// A script or a language system (with its required feature and its features)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LangSys {
+    required_feature: u16,
+    features: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Script {
+    default: Option<LangSys>,
+    languages: Vec<(String, LangSys)>,
+}
+
+/// The classes of the glyphs (glyphs without a class are in class 0)
+type ClassDef = HashMap<String, u16>;
+
+/// A rule of a contextual substitution based on classes (without backtrack and lookahead if
+/// it's not chained)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClassRule {
+    backtrack: Vec<u16>,
+    /// The classes of the input glyphs after the first one
+    input: Vec<u16>,
+    lookahead: Vec<u16>,
+    /// The sequence index and the lookup that is applied there
+    lookups: Vec<(u16, u16)>,
+}
+
+/// Format 2 of a (chained) contextual substitution
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClassContext {
+    coverage: Vec<String>,
+    backtrack: Option<ClassDef>,
+    input: Option<ClassDef>,
+    lookahead: Option<ClassDef>,
+    /// The rules for each class of the first glyph
+    rule_sets: Vec<Option<Vec<ClassRule>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ligature {
+    /// The glyphs after the first one
+    components: Vec<String>,
+    glyph: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Subtable {
+    Single(HashMap<String, String>),
+    Multiple(HashMap<String, Vec<String>>),
+    /// The ligatures by their first glyph
+    Ligature(HashMap<String, Vec<Ligature>>),
+    Context(ClassContext),
+    ChainContext(ClassContext),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Lookup {
+    lookup_type: u16,
+    flag: u16,
+    mark_filtering_set: Option<u16>,
+    subtables: Vec<Subtable>,
+}
+
+/// The `GSUB` table (version 1.0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gsub {
+    scripts: Option<Vec<(String, Script)>>,
+    features: Option<Vec<(String, Vec<u16>)>>,
+    lookups: Option<Vec<Lookup>>,
+}
+
+impl Gsub {
+    pub fn from_xml(table: &Element) -> Result<Gsub, TtxError> {
+        let version = table.u32_value("Version")?;
+        if version != 0x10000 {
+            return Err(TtxError::Unsupported(format!("GSUB version {:#010x}", version)));
+        }
+        if let Some(unsupported) = table.children.iter()
+            .find(|child| !["Version", "ScriptList", "FeatureList", "LookupList"].contains(&child.name.as_str())) {
+            return Err(TtxError::Unsupported(format!("{} in GSUB", unsupported.name)));
+        }
+
+        Ok(Gsub {
+            scripts: table.child("ScriptList").map(read_scripts).transpose()?,
+            features: table.child("FeatureList").map(read_features).transpose()?,
+            lookups: table.child("LookupList")
+                .map(|list| list.children_named("Lookup").map(read_lookup).collect())
+                .transpose()?,
+        })
+    }
+
+    pub fn compile(&self, ids: &GlyphIds) -> Result<Vec<u8>, TtxError> {
+        let mut gsub = OtTable::new();
+        gsub.data.u32(0x10000);
+        gsub.offset(self.scripts.as_deref().map(compile_scripts).transpose()?);
+        gsub.offset(self.features.as_deref().map(compile_features).transpose()?);
+        gsub.offset(match &self.lookups {
+            Some(lookups) => {
+                let mut list = OtTable::new();
+                list.count(lookups.len())?;
+                for lookup in lookups {
+                    list.offset(Some(compile_lookup(lookup, ids)?));
+                }
+                Some(list)
+            },
+            None => None
+        });
+        gsub.compile()
+    }
+}
+
+fn read_scripts(list: &Element) -> Result<Vec<(String, Script)>, TtxError> {
+    list.children_named("ScriptRecord")
+        .map(|record| {
+            let script = record.required_child("Script")?;
+            let default = script.child("DefaultLangSys")
+                .filter(|lang_sys| !lang_sys.is_empty_offset())
+                .map(read_lang_sys)
+                .transpose()?;
+            let languages = script.children_named("LangSysRecord")
+                .map(|record| Ok((
+                    record.value("LangSysTag")?.to_string(),
+                    read_lang_sys(record.required_child("LangSys")?)?
+                )))
+                .collect::<Result<_, TtxError>>()?;
+            Ok((record.value("ScriptTag")?.to_string(), Script { default, languages }))
+        })
+        .collect()
+}
+
+fn read_lang_sys(lang_sys: &Element) -> Result<LangSys, TtxError> {
+    Ok(LangSys {
+        required_feature: lang_sys.u16_value("ReqFeatureIndex")?,
+        features: lang_sys.children_named("FeatureIndex")
+            .map(|index| index.u16_attribute("value"))
+            .collect::<Result<_, TtxError>>()?
+    })
+}
+
+fn read_features(list: &Element) -> Result<Vec<(String, Vec<u16>)>, TtxError> {
+    list.children_named("FeatureRecord")
+        .map(|record| {
+            let feature = record.required_child("Feature")?;
+            if feature.child("FeatureParams").is_some() {
+                return Err(TtxError::Unsupported(String::from("FeatureParams")));
+            }
+            let lookups = feature.children_named("LookupListIndex")
+                .map(|index| index.u16_attribute("value"))
+                .collect::<Result<_, TtxError>>()?;
+            Ok((record.value("FeatureTag")?.to_string(), lookups))
+        })
+        .collect()
+}
+
+fn read_lookup(lookup: &Element) -> Result<Lookup, TtxError> {
+    let lookup_type = lookup.u16_value("LookupType")?;
+    let flag = lookup.u16_value("LookupFlag")?;
+    let mark_filtering_set = if flag & USE_MARK_FILTERING_SET != 0 {
+        Some(lookup.u16_value("MarkFilteringSet")?)
+    } else {
+        None
+    };
+    let subtables = lookup.children.iter()
+        .filter(|child| !["LookupType", "LookupFlag", "MarkFilteringSet"].contains(&child.name.as_str()))
+        .map(|subtable| read_subtable(lookup_type, subtable))
+        .collect::<Result<_, TtxError>>()?;
+    Ok(Lookup { lookup_type, flag, mark_filtering_set, subtables })
+}
+
+fn read_subtable(lookup_type: u16, subtable: &Element) -> Result<Subtable, TtxError> {
+    // Older versions of fontTools used other elements for some of the subtables
+    let only = |name: &str| match subtable.children.iter().find(|child| child.name != name) {
+        Some(other) => Err(TtxError::Unsupported(format!("<{}> in {}", other.name, subtable.name))),
+        None => Ok(())
+    };
+    match (lookup_type, subtable.name.as_str()) {
+        (1, "SingleSubst") => {
+            only("Substitution")?;
+            let mapping = subtable.children.iter()
+                .map(|substitution| Ok((
+                    substitution.required("in")?.to_string(),
+                    substitution.required("out")?.to_string()
+                )))
+                .collect::<Result<_, TtxError>>()?;
+            Ok(Subtable::Single(mapping))
+        },
+        (2, "MultipleSubst") => {
+            only("Substitution")?;
+            let mapping = subtable.children.iter()
+                .map(|substitution| Ok((
+                    substitution.required("in")?.to_string(),
+                    glyph_list(substitution.required("out")?)
+                )))
+                .collect::<Result<_, TtxError>>()?;
+            Ok(Subtable::Multiple(mapping))
+        },
+        (4, "LigatureSubst") => {
+            only("LigatureSet")?;
+            let ligatures = subtable.children.iter()
+                .map(|set| {
+                    let ligatures = set.children_named("Ligature")
+                        .map(|ligature| Ok(Ligature {
+                            components: glyph_list(ligature.required("components")?),
+                            glyph: ligature.required("glyph")?.to_string()
+                        }))
+                        .collect::<Result<_, TtxError>>()?;
+                    Ok((set.required("glyph")?.to_string(), ligatures))
+                })
+                .collect::<Result<_, TtxError>>()?;
+            Ok(Subtable::Ligature(ligatures))
+        },
+        (5, "ContextSubst") => Ok(Subtable::Context(read_class_context(subtable, false)?)),
+        (6, "ChainContextSubst") => Ok(Subtable::ChainContext(read_class_context(subtable, true)?)),
+        (lookup_type, name) => Err(TtxError::Unsupported(format!("{} in a lookup of type {}", name, lookup_type)))
+    }
+}
+
+/// Splits a comma-separated list of glyphs (which may be empty)
+fn glyph_list(glyphs: &str) -> Vec<String> {
+    glyphs.split(',')
+        .filter(|glyph| !glyph.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn read_class_context(subtable: &Element, chained: bool) -> Result<ClassContext, TtxError> {
+    let format = subtable.required("Format")?;
+    if format != "2" {
+        return Err(TtxError::Unsupported(format!("{} format {}", subtable.name, format)));
+    }
+
+    let coverage = subtable.required_child("Coverage")?
+        .children_named("Glyph")
+        .map(|glyph| glyph.required("value").map(str::to_string))
+        .collect::<Result<_, TtxError>>()?;
+    let class_def = |name: &str| subtable.child(name)
+        .filter(|class_def| !class_def.is_empty_offset())
+        .map(read_class_def)
+        .transpose();
+    let (set_name, rule_name) = if chained {
+        ("ChainSubClassSet", "ChainSubClassRule")
+    } else {
+        ("SubClassSet", "SubClassRule")
+    };
+    let rule_sets = subtable.children_named(set_name)
+        .map(|set| if set.is_empty_offset() {
+            Ok(None)
+        } else {
+            set.children_named(rule_name)
+                .map(|rule| read_class_rule(rule, chained))
+                .collect::<Result<_, TtxError>>()
+                .map(Some)
+        })
+        .collect::<Result<_, TtxError>>()?;
+
+    Ok(ClassContext {
+        coverage,
+        backtrack: if chained { class_def("BacktrackClassDef")? } else { None },
+        input: class_def(if chained { "InputClassDef" } else { "ClassDef" })?,
+        lookahead: if chained { class_def("LookAheadClassDef")? } else { None },
+        rule_sets
+    })
+}
+
+fn read_class_def(class_def: &Element) -> Result<ClassDef, TtxError> {
+    class_def.children_named("ClassDef")
+        .map(|class| Ok((class.required("glyph")?.to_string(), class.u16_attribute("class")?)))
+        .collect()
+}
+
+fn read_class_rule(rule: &Element, chained: bool) -> Result<ClassRule, TtxError> {
+    let classes = |name: &str| rule.children_named(name)
+        .map(|class| class.u16_attribute("value"))
+        .collect::<Result<Vec<u16>, TtxError>>();
+    Ok(ClassRule {
+        backtrack: if chained { classes("Backtrack")? } else { Vec::new() },
+        input: classes(if chained { "Input" } else { "Class" })?,
+        lookahead: if chained { classes("LookAhead")? } else { Vec::new() },
+        lookups: rule.children_named("SubstLookupRecord")
+            .map(|record| Ok((record.u16_value("SequenceIndex")?, record.u16_value("LookupListIndex")?)))
+            .collect::<Result<_, TtxError>>()?
+    })
+}
+
+fn compile_lang_sys(lang_sys: &LangSys) -> Result<OtTable, TtxError> {
+    let mut table = OtTable::new();
+    // LookupOrder (reserved)
+    table.offset(None);
+    table.u16(lang_sys.required_feature);
+    table.count(lang_sys.features.len())?;
+    for feature in &lang_sys.features {
+        table.u16(*feature);
+    }
+    Ok(table)
+}
+
+fn compile_scripts(scripts: &[(String, Script)]) -> Result<OtTable, TtxError> {
+    let mut list = OtTable::new();
+    list.count(scripts.len())?;
+    for (tag, script) in scripts {
+        let mut table = OtTable::new();
+        table.offset(script.default.as_ref().map(compile_lang_sys).transpose()?);
+        table.count(script.languages.len())?;
+        for (language, lang_sys) in &script.languages {
+            table.tag(language)?;
+            table.offset(Some(compile_lang_sys(lang_sys)?));
+        }
+        list.tag(tag)?;
+        list.offset(Some(table));
+    }
+    Ok(list)
+}
+
+fn compile_features(features: &[(String, Vec<u16>)]) -> Result<OtTable, TtxError> {
+    let mut list = OtTable::new();
+    list.count(features.len())?;
+    for (tag, lookups) in features {
+        let mut feature = OtTable::new();
+        // FeatureParams
+        feature.offset(None);
+        feature.count(lookups.len())?;
+        for lookup in lookups {
+            feature.u16(*lookup);
+        }
+        list.tag(tag)?;
+        list.offset(Some(feature));
+    }
+    Ok(list)
+}
+
+fn compile_lookup(lookup: &Lookup, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let mut table = OtTable::new();
+    table.u16(lookup.lookup_type);
+    table.u16(lookup.flag);
+    table.count(lookup.subtables.len())?;
+    for subtable in &lookup.subtables {
+        let compiled = match subtable {
+            Subtable::Single(mapping) => compile_single(mapping, ids)?,
+            Subtable::Multiple(mapping) => compile_multiple(mapping, ids)?,
+            Subtable::Ligature(ligatures) => compile_ligatures(ligatures, ids)?,
+            Subtable::Context(context) => compile_class_context(context, false, ids)?,
+            Subtable::ChainContext(context) => compile_class_context(context, true, ids)?,
+        };
+        table.offset(Some(compiled));
+    }
+    if let Some(mark_filtering_set) = lookup.mark_filtering_set {
+        table.u16(mark_filtering_set);
+    }
+    Ok(table)
+}
+
+/// Compiles a Coverage table in the format that fontTools would choose,
+/// i.e. with ranges if they are more compact or if the glyphs aren't sorted
+fn compile_coverage(glyphs: &[u16]) -> Result<OtTable, TtxError> {
+    // (start, end, coverage index of start)
+    let mut ranges: Vec<(u16, u16, u16)> = Vec::new();
+    for (index, glyph) in glyphs.iter().enumerate() {
+        match ranges.last_mut() {
+            Some((_, end, _)) if *glyph as u32 == *end as u32 + 1 => *end = *glyph,
+            _ => ranges.push((*glyph, *glyph, to_u16("a coverage index", index as i64)?))
+        }
+    }
+    let unsorted = glyphs.windows(2).any(|pair| pair[0] > pair[1]);
+
+    let mut table = OtTable::new();
+    if !glyphs.is_empty() && (unsorted || ranges.len() * 3 < glyphs.len()) {
+        ranges.sort_by_key(|(start, _, _)| *start);
+        table.u16(2);
+        table.count(ranges.len())?;
+        for (start, end, index) in ranges {
+            table.u16(start);
+            table.u16(end);
+            table.u16(index);
+        }
+    } else {
+        table.u16(1);
+        table.count(glyphs.len())?;
+        for glyph in glyphs {
+            table.u16(*glyph);
+        }
+    }
+    Ok(table)
+}
+
+/// Compiles a ClassDef table in the format that fontTools would choose
+fn compile_class_def(classes: &ClassDef, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let mut glyphs: Vec<(u16, u16)> = classes.iter()
+        .filter(|(_, class)| **class != 0)
+        .map(|(glyph, class)| Ok((glyph_id(ids, glyph)?, *class)))
+        .collect::<Result<_, TtxError>>()?;
+    glyphs.sort();
+    // (class, start, end)
+    let mut ranges: Vec<(u16, u16, u16)> = Vec::new();
+    for (glyph, class) in glyphs {
+        match ranges.last_mut() {
+            Some((range_class, _, end)) if *range_class == class && glyph as u32 == *end as u32 + 1 => *end = glyph,
+            _ => ranges.push((class, glyph, glyph))
+        }
+    }
+
+    let mut table = OtTable::new();
+    let glyph_range = match (ranges.first(), ranges.last()) {
+        (Some((_, start, _)), Some((_, _, end))) => Some((*start, *end)),
+        _ => None
+    };
+    match glyph_range {
+        // A class for every glyph in the range is more compact than the ranges
+        Some((start, end)) if ranges.len() * 3 >= (end - start) as usize + 2 => {
+            let mut class_values = vec![0; (end - start) as usize + 1];
+            for (class, first, last) in &ranges {
+                for glyph in *first..=*last {
+                    class_values[(glyph - start) as usize] = *class;
+                }
+            }
+            table.u16(1);
+            table.u16(start);
+            table.count(class_values.len())?;
+            for class in class_values {
+                table.u16(class);
+            }
+        },
+        _ => {
+            table.u16(2);
+            table.count(ranges.len())?;
+            for (class, start, end) in ranges {
+                table.u16(start);
+                table.u16(end);
+                table.u16(class);
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// Compiles a single substitution (format 1 if all glyphs are replaced by the glyph at the same
+/// distance, format 2 otherwise)
+fn compile_single(mapping: &HashMap<String, String>, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let substitutions: Vec<(u16, u16)> = mapping.iter()
+        .map(|(input, output)| Ok((glyph_id(ids, input)?, glyph_id(ids, output)?)))
+        .collect::<Result<Vec<_>, TtxError>>()?
+        .into_iter()
+        .sorted()
+        .collect();
+    let coverage = compile_coverage(&substitutions.iter().map(|(input, _)| *input).collect_vec())?;
+    let delta = substitutions.first()
+        .map(|(input, output)| output.wrapping_sub(*input))
+        .filter(|delta| substitutions.iter().all(|(input, output)| input.wrapping_add(*delta) == *output));
+
+    let mut table = OtTable::new();
+    match delta {
+        Some(delta) => {
+            table.u16(1);
+            table.offset(Some(coverage));
+            table.u16(delta);
+        },
+        None => {
+            table.u16(2);
+            table.offset(Some(coverage));
+            table.count(substitutions.len())?;
+            for (_, output) in substitutions {
+                table.u16(output);
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// Looks up the glyph IDs of the keys and sorts the values by them
+fn by_glyph_id<'a, T>(map: &'a HashMap<String, T>, ids: &GlyphIds) -> Result<Vec<(u16, &'a T)>, TtxError> {
+    let mut sorted = map.iter()
+        .map(|(glyph, value)| Ok((glyph_id(ids, glyph)?, value)))
+        .collect::<Result<Vec<_>, TtxError>>()?;
+    sorted.sort_by_key(|(id, _)| *id);
+    Ok(sorted)
+}
+
+fn compile_multiple(mapping: &HashMap<String, Vec<String>>, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let sequences = by_glyph_id(mapping, ids)?;
+    let mut table = OtTable::new();
+    table.u16(1);
+    table.offset(Some(compile_coverage(&sequences.iter().map(|(input, _)| *input).collect_vec())?));
+    table.count(sequences.len())?;
+    for (_, glyphs) in sequences {
+        let mut sequence = OtTable::new();
+        sequence.count(glyphs.len())?;
+        for glyph in glyphs {
+            sequence.u16(glyph_id(ids, glyph)?);
+        }
+        table.offset(Some(sequence));
+    }
+    Ok(table)
+}
+
+fn compile_ligatures(ligatures: &HashMap<String, Vec<Ligature>>, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let sets = by_glyph_id(ligatures, ids)?;
+    let mut table = OtTable::new();
+    table.u16(1);
+    table.offset(Some(compile_coverage(&sets.iter().map(|(first, _)| *first).collect_vec())?));
+    table.count(sets.len())?;
+    for (_, ligatures) in sets {
+        let mut set = OtTable::new();
+        set.count(ligatures.len())?;
+        for ligature in ligatures {
+            let mut compiled = OtTable::new();
+            compiled.u16(glyph_id(ids, &ligature.glyph)?);
+            // The number of components includes the first glyph
+            compiled.count(ligature.components.len() + 1)?;
+            for component in &ligature.components {
+                compiled.u16(glyph_id(ids, component)?);
+            }
+            set.offset(Some(compiled));
+        }
+        table.offset(Some(set));
+    }
+    Ok(table)
+}
+
+fn compile_class_context(context: &ClassContext, chained: bool, ids: &GlyphIds) -> Result<OtTable, TtxError> {
+    let coverage = context.coverage.iter()
+        .map(|glyph| glyph_id(ids, glyph))
+        .collect::<Result<Vec<u16>, TtxError>>()?;
+    let class_def = |class_def: &Option<ClassDef>| class_def.as_ref()
+        .map(|class_def| compile_class_def(class_def, ids))
+        .transpose();
+
+    let mut table = OtTable::new();
+    table.u16(2);
+    table.offset(Some(compile_coverage(&coverage)?));
+    if chained {
+        table.offset(class_def(&context.backtrack)?);
+        table.offset(class_def(&context.input)?);
+        table.offset(class_def(&context.lookahead)?);
+    } else {
+        table.offset(class_def(&context.input)?);
+    }
+    table.count(context.rule_sets.len())?;
+    for rules in &context.rule_sets {
+        let rules = match rules {
+            Some(rules) => rules,
+            None => {
+                table.offset(None);
+                continue;
+            }
+        };
+        let mut set = OtTable::new();
+        set.count(rules.len())?;
+        for rule in rules {
+            let mut compiled = OtTable::new();
+            if chained {
+                compiled.count(rule.backtrack.len())?;
+                rule.backtrack.iter().for_each(|class| compiled.u16(*class));
+                compiled.count(rule.input.len() + 1)?;
+                rule.input.iter().for_each(|class| compiled.u16(*class));
+                compiled.count(rule.lookahead.len())?;
+                rule.lookahead.iter().for_each(|class| compiled.u16(*class));
+                compiled.count(rule.lookups.len())?;
+            } else {
+                compiled.count(rule.input.len() + 1)?;
+                compiled.count(rule.lookups.len())?;
+                rule.input.iter().for_each(|class| compiled.u16(*class));
+            }
+            for (sequence_index, lookup) in &rule.lookups {
+                compiled.u16(*sequence_index);
+                compiled.u16(*lookup);
+            }
+            set.offset(Some(compiled));
+        }
+        table.offset(Some(set));
+    }
+    Ok(table)
+}
+
+#[test]
+fn test_coverage_format() {
+    let format = |glyphs: &[u16]| compile_coverage(glyphs).unwrap().data[1];
+    assert_eq!(format(&[]), 1);
+    assert_eq!(format(&[3, 5, 7]), 1);
+    assert_eq!(format(&(10..20).collect_vec()), 2);
+    // Unsorted glyphs always use ranges
+    assert_eq!(format(&[7, 5]), 2);
+}
+
+#[test]
+fn test_class_def_format() {
+    let glyph_order: Vec<String> = (0..50).map(|id| format!("glyph{}", id)).collect();
+    let ids: GlyphIds = glyph_order.iter().enumerate().map(|(id, glyph)| (glyph.as_str(), id as u16)).collect();
+    let class_def = |classes: &[(u16, u16)]| -> ClassDef {
+        classes.iter().map(|(glyph, class)| (format!("glyph{}", glyph), *class)).collect()
+    };
+
+    // A single range
+    let compiled = compile_class_def(&class_def(&(10..40).map(|glyph| (glyph, 2)).collect_vec()), &ids).unwrap();
+    assert_eq!(compiled.data, vec![0, 2, 0, 1, 0, 10, 0, 39, 0, 2]);
+    // Alternating classes
+    let compiled = compile_class_def(&class_def(&[(10, 1), (11, 2), (12, 1), (13, 0)]), &ids).unwrap();
+    assert_eq!(compiled.data, vec![0, 1, 0, 10, 0, 3, 0, 1, 0, 2, 0, 1]);
+    // Empty
+    assert_eq!(compile_class_def(&ClassDef::new(), &ids).unwrap().data, vec![0, 2, 0, 0]);
+}
+
+#[test]
+fn test_offsets() {
+    let mut leaf = OtTable::new();
+    leaf.u16(0xabcd);
+    let mut middle = OtTable::new();
+    middle.offset(Some(leaf));
+    let mut root = OtTable::new();
+    root.offset(None);
+    root.offset(Some(middle));
+    assert_eq!(root.compile().unwrap(), vec![0, 0, 0, 4, 0, 2, 0xab, 0xcd]);
+}
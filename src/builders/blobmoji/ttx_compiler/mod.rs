@@ -0,0 +1,498 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! A compiler for the TTX files that the Blobmoji builder generates (i.e. `add_glyphs.py`'s
+//! output), which can be used instead of `ttx` from fontTools.
+//!
+//! It only supports the tables and formats that appear in these files (the template, the
+//! metrics, `cmap` formats 4 and 12, empty glyphs and the GSUB lookups of `add_glyphs.py`).
+//! Anything else results in [TtxError::Unsupported], in which case the caller should fall back
+//! to fontTools.
+//! The tables are recalculated in the same way as fontTools does it, so both compilers produce
+//! the same tables (although not necessarily byte by byte, e.g. for the order of subtables).
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::builders::blobmoji::ttx_compiler::gsub::Gsub;
+use crate::builders::blobmoji::ttx_compiler::tables::{Cmap, Glyf, Head, HHEA_FIELDS, MAC_EPOCH_OFFSET, Maxp, Metrics, MetricsHeader, Names, Os2, Post, VHEA_FIELDS};
+use crate::configs::ConfigError;
+
+mod xml;
+mod tables;
+mod gsub;
+
+/// The value of `checkSumAdjustment` is chosen such that the whole font has this checksum
+const CHECKSUM_MAGIC: u32 = 0xB1B0_AFBA;
+/// The position of `checkSumAdjustment` in `head`
+const CHECKSUM_ADJUSTMENT_OFFSET: usize = 8;
+
+/// The program that compiles the TTX file of the font template
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TtxCompiler {
+    /// `ttx` from fontTools
+    #[default]
+    FontTools,
+    /// [compile], which falls back to fontTools if the TTX file isn't supported
+    Rust,
+}
+
+impl FromStr for TtxCompiler {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fonttools" => Ok(TtxCompiler::FontTools),
+            "rust" => Ok(TtxCompiler::Rust),
+            _ => Err(ConfigError::InvalidValue(String::from("ttx_compiler"), s.to_string()))
+        }
+    }
+}
+
+/// An error that occurs while compiling a TTX file
+#[derive(Debug)]
+pub enum TtxError {
+    /// Wrapper for [std::io::Error]
+    Io(std::io::Error),
+    /// The file is not well-formed XML
+    Xml(String),
+    /// The file contains a table or a format that this compiler doesn't support
+    Unsupported(String),
+    /// The file contains values that can't be compiled (e.g. unknown glyphs)
+    Invalid(String),
+    /// An offset in the given table doesn't fit into 16 bits
+    OffsetOverflow(String),
+}
+
+impl Display for TtxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TtxError::Io(err) => write!(f, "{}", err),
+            TtxError::Xml(message) => write!(f, "Invalid TTX file: {}", message),
+            TtxError::Unsupported(what) => write!(f, "Unsupported: {}", what),
+            TtxError::Invalid(message) => write!(f, "{}", message),
+            TtxError::OffsetOverflow(table) => write!(f, "An offset in {} is too large", table),
+        }
+    }
+}
+
+impl From<std::io::Error> for TtxError {
+    fn from(err: std::io::Error) -> Self {
+        TtxError::Io(err)
+    }
+}
+
+/// The glyph IDs by glyph name
+type GlyphIds<'a> = HashMap<&'a str, u16>;
+
+/// The compiled tables with their tags
+type Tables = Vec<([u8; 4], Vec<u8>)>;
+
+fn glyph_id(ids: &GlyphIds, glyph: &str) -> Result<u16, TtxError> {
+    ids.get(glyph)
+        .copied()
+        .ok_or_else(|| TtxError::Invalid(format!("{} is not in the glyph order", glyph)))
+}
+
+/// Calculates searchRange, entrySelector and rangeShift for a binary search over `n` items
+fn search_range(n: usize, item_size: usize) -> (u16, u16, u16) {
+    let mut exponent = 0;
+    while (2 << exponent) <= n {
+        exponent += 1;
+    }
+    let search_range = if n == 0 { 0 } else { (1 << exponent) * item_size };
+    (search_range as u16, exponent as u16, (n * item_size - search_range) as u16)
+}
+
+/// Appends big endian values (as they are used in OpenType)
+trait BigEndian {
+    fn u16(&mut self, value: u16);
+    fn i16(&mut self, value: i16);
+    fn u32(&mut self, value: u32);
+    fn i32(&mut self, value: i32);
+    fn i64(&mut self, value: i64);
+}
+
+impl BigEndian for Vec<u8> {
+    fn u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i16(&mut self, value: i16) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// The tables of a TTX file
+#[derive(Debug, Default)]
+struct Font {
+    glyph_order: Vec<String>,
+    head: Option<Head>,
+    hhea: Option<MetricsHeader>,
+    vhea: Option<MetricsHeader>,
+    maxp: Option<Maxp>,
+    os2: Option<Os2>,
+    hmtx: Option<Metrics>,
+    vmtx: Option<Metrics>,
+    cmap: Option<Cmap>,
+    name: Option<Names>,
+    post: Option<Post>,
+    glyf: Option<Glyf>,
+    has_loca: bool,
+    gsub: Option<Gsub>,
+}
+
+impl Font {
+    fn from_ttx(ttx: &str) -> Result<Font, TtxError> {
+        let mut font = Font::default();
+        xml::read_tables(ttx, |table| font.add_table(table))?;
+        Ok(font)
+    }
+
+    fn add_table(&mut self, table: xml::Element) -> Result<(), TtxError> {
+        match table.name.as_str() {
+            "GlyphOrder" => self.glyph_order = table.children_named("GlyphID")
+                .map(|glyph| glyph.required("name").map(str::to_string))
+                .collect::<Result<_, TtxError>>()?,
+            "head" => self.head = Some(Head::from_xml(&table)?),
+            "hhea" => self.hhea = Some(MetricsHeader::from_xml(&table, &HHEA_FIELDS)?),
+            "vhea" => self.vhea = Some(MetricsHeader::from_xml(&table, &VHEA_FIELDS)?),
+            "maxp" => self.maxp = Some(Maxp::from_xml(&table)?),
+            "OS_2" => self.os2 = Some(Os2::from_xml(&table)?),
+            "hmtx" => self.hmtx = Some(Metrics::from_xml(&table, "width", "lsb")?),
+            "vmtx" => self.vmtx = Some(Metrics::from_xml(&table, "height", "tsb")?),
+            "cmap" => self.cmap = Some(Cmap::from_xml(&table)?),
+            "name" => self.name = Some(Names::from_xml(&table)?),
+            "post" => self.post = Some(Post::from_xml(&table)?),
+            "glyf" => self.glyf = Some(Glyf::from_xml(&table)?),
+            // loca is always generated from glyf
+            "loca" => self.has_loca = true,
+            "GSUB" => self.gsub = Some(Gsub::from_xml(&table)?),
+            other => return Err(TtxError::Unsupported(format!("the {} table", other)))
+        }
+        Ok(())
+    }
+
+    /// Compiles the tables (with `modified` as the modification time in `head`)
+    /// # Returns
+    /// The tables by their tag
+    fn compile(mut self, modified: i64) -> Result<Tables, TtxError> {
+        if self.glyph_order.is_empty() {
+            return Err(TtxError::Unsupported(String::from("TTX files without a GlyphOrder")));
+        }
+        if self.glyph_order.len() > 0xffff {
+            return Err(TtxError::Invalid(format!("The font has {} glyphs", self.glyph_order.len())));
+        }
+        let glyph_order = std::mem::take(&mut self.glyph_order);
+        let ids: GlyphIds = glyph_order.iter()
+            .enumerate()
+            .map(|(id, glyph)| (glyph.as_str(), id as u16))
+            .collect();
+
+        let mut tables: Tables = Vec::new();
+
+        match (&self.glyf, self.has_loca) {
+            (Some(glyf), _) => {
+                let (glyf, loca) = glyf.compile(&glyph_order)?;
+                tables.push((*b"glyf", glyf));
+                tables.push((*b"loca", loca));
+                if let Some(head) = self.head.as_mut() {
+                    head.index_to_loc_format = 0;
+                    // As all glyphs are empty, there's no bounding box and the left side
+                    // bearings are trivially at xMin
+                    head.bounding_box = [0; 4];
+                    head.flags |= 2;
+                }
+                if let Some(maxp) = self.maxp.as_mut() {
+                    maxp.recalc_without_outlines();
+                }
+            },
+            (None, true) => return Err(TtxError::Unsupported(String::from("loca without glyf"))),
+            (None, false) => {}
+        }
+
+        let has_glyf = self.glyf.is_some();
+        if let Some(hmtx) = &self.hmtx {
+            tables.push((*b"hmtx", compile_metrics(hmtx, self.hhea.as_mut(), "hhea", &glyph_order, has_glyf)?));
+        }
+        if let Some(vmtx) = &self.vmtx {
+            tables.push((*b"vmtx", compile_metrics(vmtx, self.vhea.as_mut(), "vhea", &glyph_order, has_glyf)?));
+        }
+
+        if let Some(head) = self.head.as_mut() {
+            head.modified = modified;
+        }
+        if let (Some(os2), Some(cmap)) = (self.os2.as_mut(), &self.cmap) {
+            let codepoints = cmap.unicode_codepoints();
+            let first = codepoints.iter().min().copied().unwrap_or(0).min(0xffff) as u16;
+            let last = codepoints.iter().max().copied().unwrap_or(0).min(0xffff) as u16;
+            os2.set_char_indices(first, last);
+        }
+
+        if let Some(head) = &self.head {
+            tables.push((*b"head", head.compile()));
+        }
+        if let Some(hhea) = &self.hhea {
+            tables.push((*b"hhea", hhea.compile()));
+        }
+        if let Some(vhea) = &self.vhea {
+            tables.push((*b"vhea", vhea.compile()));
+        }
+        if let Some(maxp) = &self.maxp {
+            tables.push((*b"maxp", maxp.compile(glyph_order.len() as u16)));
+        }
+        if let Some(os2) = &self.os2 {
+            tables.push((*b"OS/2", os2.compile()));
+        }
+        if let Some(cmap) = &self.cmap {
+            tables.push((*b"cmap", cmap.compile(&ids)?));
+        }
+        if let Some(name) = &self.name {
+            tables.push((*b"name", name.compile()?));
+        }
+        if let Some(post) = &self.post {
+            tables.push((*b"post", post.compile()));
+        }
+        if let Some(gsub) = &self.gsub {
+            tables.push((*b"GSUB", gsub.compile(&ids)?));
+        }
+        Ok(tables)
+    }
+}
+
+/// Compiles hmtx/vmtx and updates the number of metrics (and the extents if there are no
+/// outlines) in its header
+fn compile_metrics(metrics: &Metrics,
+                   header: Option<&mut MetricsHeader>,
+                   header_tag: &str,
+                   glyph_order: &[String],
+                   has_glyf: bool) -> Result<Vec<u8>, TtxError> {
+    let header = header.ok_or_else(|| TtxError::Invalid(format!("{} is missing", header_tag)))?;
+    let (data, number_of_metrics) = metrics.compile(glyph_order)?;
+    header.set_number_of_metrics(number_of_metrics);
+    if has_glyf {
+        header.recalc_without_outlines(metrics.advance_max());
+    }
+    Ok(data)
+}
+
+/// The OpenType checksum of a table (which is padded with zeros)
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(word)
+        })
+        .fold(0, u32::wrapping_add)
+}
+
+/// Writes the table directory and the tables (sorted by their tags) and sets
+/// `checkSumAdjustment` in `head`
+fn assemble(mut tables: Tables) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+    let (search_range, entry_selector, range_shift) = search_range(tables.len(), 16);
+
+    let mut font = Vec::new();
+    font.u32(0x0001_0000);
+    font.u16(tables.len() as u16);
+    font.u16(search_range);
+    font.u16(entry_selector);
+    font.u16(range_shift);
+
+    let mut offset = font.len() + 16 * tables.len();
+    let mut head_offset = None;
+    for (tag, data) in &tables {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        font.extend_from_slice(tag);
+        font.u32(checksum(data));
+        font.u32(offset as u32);
+        font.u32(data.len() as u32);
+        offset += data.len().next_multiple_of(4);
+    }
+    for (_, data) in &tables {
+        font.extend_from_slice(data);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&font));
+        let position = head_offset + CHECKSUM_ADJUSTMENT_OFFSET;
+        font[position..position + 4].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+/// Decodes the TTX file according to its XML declaration (fontTools used to write ISO-8859-1)
+fn decode(ttx: Vec<u8>) -> Result<String, TtxError> {
+    let declaration_end = ttx.iter().position(|byte| *byte == b'>').unwrap_or(0);
+    let declaration = String::from_utf8_lossy(&ttx[..declaration_end]).to_lowercase();
+    if declaration.starts_with("<?xml") && declaration.contains("iso-8859-1") {
+        Ok(ttx.into_iter().map(char::from).collect())
+    } else {
+        String::from_utf8(ttx).map_err(|err| TtxError::Xml(err.to_string()))
+    }
+}
+
+/// Compiles a TTX file to a TrueType font like `ttx -o ttf ttx` would do it.
+///
+/// Like `ttx`, it uses the modification time of the TTX file as the modification time of the font.
+pub fn compile(ttx: &Path, ttf: &Path) -> Result<(), TtxError> {
+    let mtime = std::fs::metadata(ttx)?.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+
+    let font = Font::from_ttx(&decode(std::fs::read(ttx)?)?)?;
+    let tables = font.compile(mtime + MAC_EPOCH_OFFSET)?;
+    std::fs::write(ttf, assemble(tables))?;
+    Ok(())
+}
+
+#[test]
+fn test_ttx_compiler_from_str() {
+    assert_eq!("Rust".parse::<TtxCompiler>().unwrap(), TtxCompiler::Rust);
+    assert_eq!("fonttools".parse::<TtxCompiler>().unwrap(), TtxCompiler::FontTools);
+    assert!("ttx".parse::<TtxCompiler>().is_err());
+}
+
+/// The font template with the metrics that `add_glyphs.py` adds for the glyphs that it doesn't
+/// list (a zero advance, see `add_glyph_data`)
+#[cfg(test)]
+fn complete_template() -> String {
+    use regex::Regex;
+
+    use crate::builders::blobmoji::TMPL_TTX_TMPL_CONTENT;
+
+    let mut ttx = decode(TMPL_TTX_TMPL_CONTENT.to_vec()).unwrap();
+    let glyphs: Vec<String> = Regex::new(r#"<GlyphID id="\d+" name="([^"]+)"/>"#).unwrap()
+        .captures_iter(&ttx)
+        .map(|glyph| glyph[1].to_string())
+        .collect();
+    for (table, advance, side_bearing) in &[("hmtx", "width", "lsb"), ("vmtx", "height", "tsb")] {
+        let end = format!("</{}>", table);
+        let start = ttx.find(&format!("<{}>", table)).unwrap();
+        let table_end = start + ttx[start..].find(&end).unwrap();
+        let missing: String = glyphs.iter()
+            .filter(|glyph| !ttx[start..table_end].contains(&format!(r#"<mtx name="{}""#, glyph)))
+            .map(|glyph| format!("  <mtx name=\"{}\" {}=\"0\" {}=\"0\"/>\n  ", glyph, advance, side_bearing))
+            .collect();
+        ttx.insert_str(table_end, &missing);
+    }
+    ttx
+}
+
+#[test]
+fn test_compile_template() {
+    let tables = Font::from_ttx(&complete_template()).unwrap()
+        .compile(MAC_EPOCH_OFFSET)
+        .unwrap();
+    let tags: Vec<[u8; 4]> = tables.iter().map(|(tag, _)| *tag).collect();
+    let font = assemble(tables);
+
+    assert_eq!(checksum(&font), CHECKSUM_MAGIC);
+    let num_tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+    assert_eq!(num_tables, tags.len());
+    let directory: Vec<&[u8]> = (0..num_tables).map(|index| &font[12 + 16 * index..12 + 16 * index + 4]).collect();
+    let mut sorted = directory.clone();
+    sorted.sort();
+    assert_eq!(directory, sorted);
+    assert!(directory.contains(&&b"GSUB"[..]));
+}
+
+/// Compiles the same TTX files with fontTools and with [compile] and compares the decompiled tables
+#[test]
+fn test_same_tables_as_fonttools() {
+    const DUMP_TABLES: &str = r#"
+from io import StringIO
+from fontTools.ttLib import TTFont
+from fontTools.misc.xmlWriter import XMLWriter
+
+def dump(path):
+    font = TTFont(path)
+    if 'head' in font:
+        font['head'].checkSumAdjustment = 0
+    tables = {}
+    for tag in font.keys():
+        if tag == 'GlyphOrder':
+            continue
+        output = StringIO()
+        writer = XMLWriter(output)
+        font[tag].toXML(writer, font)
+        tables[tag] = output.getvalue()
+    return tables
+"#;
+    use itertools::Itertools;
+    use pyo3::Python;
+    use pyo3::prelude::PyModule;
+    use crate::builder::EmojiBuilder;
+    use crate::builders::blobmoji::{Blobmoji, BuildDir, TMPL_TTX_TMPL_CONTENT};
+    use crate::builders::blobmoji::noto_emoji_utils::{add_glyphs, build_ttf, GlyphOptions};
+    use crate::emoji::Emoji;
+
+    let dir = tempfile::tempdir().unwrap();
+    let build_dir = BuildDir::open(dir.path()).unwrap();
+    std::fs::write(build_dir.ttx_tmpl_tmpl(), TMPL_TTX_TMPL_CONTENT).unwrap();
+
+    let sequences = vec![vec![0x1f600], vec![0x1f914], vec![0x1f1e9, 0x1f1ea], vec![0x1f469, 0x200d, 0x1f4bb]];
+    let emojis: Vec<Emoji> = sequences.into_iter().map(Emoji::from).collect();
+    let emojis: HashMap<&Emoji, Result<<Blobmoji as EmojiBuilder>::PreparedEmoji, <Blobmoji as EmojiBuilder>::Err>> = emojis.iter()
+        .map(|emoji| {
+            let path = build_dir.png_dir()
+                .join(format!("emoji_u{}.png", emoji.sequence.iter().map(|codepoint| format!("{:x}", codepoint)).join("_")));
+            tiny_skia::Pixmap::new(136, 128).unwrap().save_png(&path).unwrap();
+            (emoji, Ok((path, Ok(Default::default()))))
+        })
+        .collect();
+
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let dump_tables = PyModule::from_code(py, DUMP_TABLES, "dump_tables.py", "dump_tables").unwrap();
+    let dump = |path: &Path| -> HashMap<String, String> {
+        dump_tables.call1("dump", (path.to_string_lossy().into_owned(),)).unwrap().extract().unwrap()
+    };
+
+    for add_cmap_and_glyf in &[false, true] {
+        let work_dir = build_dir.work_dir("test").unwrap();
+        add_glyphs(&None, &HashMap::new(), &emojis, build_dir.ttx_tmpl_tmpl(), work_dir.ttx_tmpl(), &GlyphOptions { add_cmap4_and_glyf: *add_cmap_and_glyf, ..GlyphOptions::default() }).unwrap();
+        let rust_ttf = work_dir.tmpl_ttf().with_extension("rust.ttf");
+        build_ttf(&work_dir.ttx_tmpl(), &work_dir.tmpl_ttf()).unwrap();
+        compile(&work_dir.ttx_tmpl(), &rust_ttf).unwrap();
+
+        let expected = dump(&work_dir.tmpl_ttf());
+        let actual = dump(&rust_ttf);
+        assert_eq!(actual.keys().sorted().collect_vec(), expected.keys().sorted().collect_vec());
+        for (tag, table) in expected {
+            assert_eq!(actual[&tag], table, "{} differs (add_cmap_and_glyf: {})", tag, add_cmap_and_glyf);
+        }
+    }
+}
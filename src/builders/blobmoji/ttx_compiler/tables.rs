@@ -0,0 +1,887 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The tables of the Noto Color Emoji template besides GSUB.
+//! They are compiled like fontTools does it, including the values that it recalculates.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::builders::blobmoji::ttx_compiler::{BigEndian, GlyphIds, glyph_id, search_range, TtxError};
+use crate::builders::blobmoji::ttx_compiler::xml::{Element, parse_binary, parse_fixed, to_i16, to_u16, to_u32};
+
+/// The seconds between 1904-01-01 (the epoch of the timestamps in `head`) and 1970-01-01
+pub const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Parses a timestamp like fontTools writes it (e.g. `Wed May 22 20:00:43 2013`, in UTC)
+fn parse_timestamp(value: &str) -> Result<i64, TtxError> {
+    let invalid = || TtxError::Invalid(format!("{:?} is not a timestamp", value));
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (month, day, time, year) = match parts.as_slice() {
+        [_weekday, month, day, time, year] => (month, day, time, year),
+        _ => return Err(invalid())
+    };
+    let month = MONTHS.iter().position(|name| name == month).ok_or_else(invalid)? as i64 + 1;
+    let day: i64 = day.parse().map_err(|_| invalid())?;
+    let year: i64 = year.parse().map_err(|_| invalid())?;
+    let time: Vec<i64> = time.split(':')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let seconds = match time.as_slice() {
+        [hours, minutes, seconds] => hours * 3600 + minutes * 60 + seconds,
+        _ => return Err(invalid())
+    };
+
+    // Days since 1970-01-01 (cf. http://howardhinnant.github.io/date_algorithms.html#days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok(days * 86400 + seconds + MAC_EPOCH_OFFSET)
+}
+
+/// The `head` table (the checksum adjustment is set after the whole font has been assembled)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    version: i32,
+    font_revision: i32,
+    magic_number: u32,
+    pub flags: u16,
+    units_per_em: u16,
+    created: i64,
+    pub modified: i64,
+    /// xMin, yMin, xMax, yMax
+    pub bounding_box: [i16; 4],
+    mac_style: u16,
+    lowest_rec_ppem: u16,
+    font_direction_hint: i16,
+    pub index_to_loc_format: i16,
+    glyph_data_format: i16,
+}
+
+impl Head {
+    pub fn from_xml(table: &Element) -> Result<Head, TtxError> {
+        Ok(Head {
+            version: parse_fixed(table.value("tableVersion")?)?,
+            font_revision: parse_fixed(table.value("fontRevision")?)?,
+            magic_number: table.u32_value("magicNumber")?,
+            flags: to_u16("flags", parse_binary(table.value("flags")?)? as i64)?,
+            units_per_em: table.u16_value("unitsPerEm")?,
+            created: parse_timestamp(table.value("created")?)?,
+            modified: parse_timestamp(table.value("modified")?)?,
+            bounding_box: [
+                table.i16_value("xMin")?,
+                table.i16_value("yMin")?,
+                table.i16_value("xMax")?,
+                table.i16_value("yMax")?
+            ],
+            mac_style: to_u16("macStyle", parse_binary(table.value("macStyle")?)? as i64)?,
+            lowest_rec_ppem: table.u16_value("lowestRecPPEM")?,
+            font_direction_hint: table.i16_value("fontDirectionHint")?,
+            index_to_loc_format: table.i16_value("indexToLocFormat")?,
+            glyph_data_format: table.i16_value("glyphDataFormat")?,
+        })
+    }
+
+    pub fn compile(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(54);
+        data.i32(self.version);
+        data.i32(self.font_revision);
+        // checkSumAdjustment
+        data.u32(0);
+        data.u32(self.magic_number);
+        data.u16(self.flags);
+        data.u16(self.units_per_em);
+        data.i64(self.created);
+        data.i64(self.modified);
+        for value in &self.bounding_box {
+            data.i16(*value);
+        }
+        data.u16(self.mac_style);
+        data.u16(self.lowest_rec_ppem);
+        data.i16(self.font_direction_hint);
+        data.i16(self.index_to_loc_format);
+        data.i16(self.glyph_data_format);
+        data
+    }
+}
+
+/// The fields of `hhea` after its version
+pub const HHEA_FIELDS: [&str; 16] = [
+    "ascent", "descent", "lineGap", "advanceWidthMax", "minLeftSideBearing", "minRightSideBearing",
+    "xMaxExtent", "caretSlopeRise", "caretSlopeRun", "caretOffset", "reserved0", "reserved1",
+    "reserved2", "reserved3", "metricDataFormat", "numberOfHMetrics"
+];
+
+/// The fields of `vhea` after its version (which are laid out like the ones of `hhea`)
+pub const VHEA_FIELDS: [&str; 16] = [
+    "ascent", "descent", "lineGap", "advanceHeightMax", "minTopSideBearing", "minBottomSideBearing",
+    "yMaxExtent", "caretSlopeRise", "caretSlopeRun", "caretOffset", "reserved1", "reserved2",
+    "reserved3", "reserved4", "metricDataFormat", "numberOfVMetrics"
+];
+
+const ADVANCE_MAX: usize = 3;
+const MIN_SIDE_BEARINGS: [usize; 2] = [4, 5];
+const MAX_EXTENT: usize = 6;
+const NUMBER_OF_METRICS: usize = 15;
+
+/// The `hhea` or `vhea` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsHeader {
+    version: u32,
+    /// The (raw) values of the fields after the version
+    values: [u16; 16],
+}
+
+impl MetricsHeader {
+    pub fn from_xml(table: &Element, fields: &[&str; 16]) -> Result<MetricsHeader, TtxError> {
+        let mut values = [0; 16];
+        for (index, field) in fields.iter().enumerate() {
+            values[index] = match index {
+                // It's always set by the hmtx/vmtx table
+                NUMBER_OF_METRICS if table.child(field).is_none() => 0,
+                ADVANCE_MAX | NUMBER_OF_METRICS => table.u16_value(field)?,
+                _ => table.i16_value(field)? as u16
+            };
+        }
+        Ok(MetricsHeader {
+            version: table.u32_value("tableVersion")?,
+            values
+        })
+    }
+
+    pub fn set_number_of_metrics(&mut self, number_of_metrics: u16) {
+        self.values[NUMBER_OF_METRICS] = number_of_metrics;
+    }
+
+    /// Recalculates the values from the metrics like fontTools does if the font has a glyf table,
+    /// but none of its glyphs have outlines
+    pub fn recalc_without_outlines(&mut self, advance_max: Option<u16>) {
+        if let Some(advance_max) = advance_max {
+            self.values[ADVANCE_MAX] = advance_max;
+        }
+        for index in MIN_SIDE_BEARINGS.iter().chain(&[MAX_EXTENT]) {
+            self.values[*index] = 0;
+        }
+    }
+
+    pub fn compile(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(36);
+        data.u32(self.version);
+        for value in &self.values {
+            data.u16(*value);
+        }
+        data
+    }
+}
+
+/// The fields of `maxp` (version 1.0) after `numGlyphs`
+const MAXP_FIELDS: [&str; 13] = [
+    "maxPoints", "maxContours", "maxCompositePoints", "maxCompositeContours", "maxZones",
+    "maxTwilightPoints", "maxStorage", "maxFunctionDefs", "maxInstructionDefs", "maxStackElements",
+    "maxSizeOfInstructions", "maxComponentElements", "maxComponentDepth"
+];
+
+/// The fields of `maxp` that are calculated from the outlines
+const MAXP_OUTLINE_FIELDS: [usize; 6] = [0, 1, 2, 3, 11, 12];
+
+const MAXP_VERSION_0_5: u32 = 0x5000;
+
+/// The `maxp` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maxp {
+    /// The values of the fields of version 1.0 (if it isn't version 0.5)
+    values: Option<[u16; 13]>,
+}
+
+impl Maxp {
+    pub fn from_xml(table: &Element) -> Result<Maxp, TtxError> {
+        if table.u32_value("tableVersion")? == MAXP_VERSION_0_5 {
+            return Ok(Maxp { values: None });
+        }
+        let mut values = [0; 13];
+        for (index, field) in MAXP_FIELDS.iter().enumerate() {
+            values[index] = table.u16_value(field)?;
+        }
+        Ok(Maxp { values: Some(values) })
+    }
+
+    /// Sets the maximums to 0, as the glyf table only contains empty glyphs
+    pub fn recalc_without_outlines(&mut self) {
+        if let Some(values) = &mut self.values {
+            for index in &MAXP_OUTLINE_FIELDS {
+                values[*index] = 0;
+            }
+        }
+    }
+
+    pub fn compile(&self, num_glyphs: u16) -> Vec<u8> {
+        let mut data = Vec::with_capacity(32);
+        match &self.values {
+            None => {
+                data.u32(MAXP_VERSION_0_5);
+                data.u16(num_glyphs);
+            },
+            Some(values) => {
+                data.u32(0x10000);
+                data.u16(num_glyphs);
+                for value in values {
+                    data.u16(*value);
+                }
+            }
+        }
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Os2Field {
+    U16,
+    I16,
+    /// A 16-bit field that is written as binary digits
+    Bits16,
+    /// A 32-bit field that is written as binary digits
+    Bits32,
+    Panose,
+    VendorId,
+}
+
+const OS2_FIELDS: [(&str, Os2Field); 32] = [
+    ("xAvgCharWidth", Os2Field::I16), ("usWeightClass", Os2Field::U16), ("usWidthClass", Os2Field::U16),
+    ("fsType", Os2Field::Bits16), ("ySubscriptXSize", Os2Field::I16), ("ySubscriptYSize", Os2Field::I16),
+    ("ySubscriptXOffset", Os2Field::I16), ("ySubscriptYOffset", Os2Field::I16),
+    ("ySuperscriptXSize", Os2Field::I16), ("ySuperscriptYSize", Os2Field::I16),
+    ("ySuperscriptXOffset", Os2Field::I16), ("ySuperscriptYOffset", Os2Field::I16),
+    ("yStrikeoutSize", Os2Field::I16), ("yStrikeoutPosition", Os2Field::I16), ("sFamilyClass", Os2Field::I16),
+    ("panose", Os2Field::Panose), ("ulUnicodeRange1", Os2Field::Bits32), ("ulUnicodeRange2", Os2Field::Bits32),
+    ("ulUnicodeRange3", Os2Field::Bits32), ("ulUnicodeRange4", Os2Field::Bits32), ("achVendID", Os2Field::VendorId),
+    ("fsSelection", Os2Field::Bits16), ("usFirstCharIndex", Os2Field::U16), ("usLastCharIndex", Os2Field::U16),
+    ("sTypoAscender", Os2Field::I16), ("sTypoDescender", Os2Field::I16), ("sTypoLineGap", Os2Field::I16),
+    ("usWinAscent", Os2Field::U16), ("usWinDescent", Os2Field::U16),
+    // Version 1
+    ("ulCodePageRange1", Os2Field::Bits32), ("ulCodePageRange2", Os2Field::Bits32),
+    // Version 2 (the remaining fields are below)
+    ("sxHeight", Os2Field::I16),
+];
+
+const OS2_V2_FIELDS: [(&str, Os2Field); 4] = [
+    ("sCapHeight", Os2Field::I16), ("usDefaultChar", Os2Field::U16), ("usBreakChar", Os2Field::U16),
+    ("usMaxContext", Os2Field::U16),
+];
+
+/// The number of fields of version 0 and 1 (without the version itself)
+const OS2_V0_FIELD_COUNT: usize = 29;
+const OS2_V1_FIELD_COUNT: usize = 31;
+
+const PANOSE_FIELDS: [&str; 10] = [
+    "bFamilyType", "bSerifStyle", "bWeight", "bProportion", "bContrast", "bStrokeVariation",
+    "bArmStyle", "bLetterForm", "bMidline", "bXHeight"
+];
+
+/// Where `usFirstCharIndex` is in the table
+const OS2_FIRST_CHAR_INDEX: usize = 64;
+
+/// The `OS/2` table (versions 0 to 4), which is compiled right away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Os2 {
+    data: Vec<u8>,
+}
+
+impl Os2 {
+    pub fn from_xml(table: &Element) -> Result<Os2, TtxError> {
+        let version = table.u16_value("version")?;
+        let field_count = match version {
+            0 => OS2_V0_FIELD_COUNT,
+            1 => OS2_V1_FIELD_COUNT,
+            2..=4 => OS2_FIELDS.len(),
+            _ => return Err(TtxError::Unsupported(format!("OS/2 version {}", version)))
+        };
+        let v2_fields: &[(&str, Os2Field)] = if version >= 2 { &OS2_V2_FIELDS } else { &[] };
+        let fields = OS2_FIELDS[..field_count].iter().chain(v2_fields);
+
+        let mut data = Vec::with_capacity(96);
+        data.u16(version);
+        for (name, field) in fields {
+            match field {
+                Os2Field::U16 => data.u16(table.u16_value(name)?),
+                Os2Field::I16 => data.i16(table.i16_value(name)?),
+                Os2Field::Bits16 => data.u16(to_u16(name, parse_binary(table.value(name)?)? as i64)?),
+                Os2Field::Bits32 => data.u32(parse_binary(table.value(name)?)?),
+                Os2Field::Panose => {
+                    let panose = table.required_child(name)?;
+                    for panose_field in &PANOSE_FIELDS {
+                        let value = panose.int_value(panose_field)?;
+                        if !(0..=0xff).contains(&value) {
+                            return Err(TtxError::Invalid(format!("{} is out of range for {}", value, panose_field)));
+                        }
+                        data.push(value as u8);
+                    }
+                },
+                Os2Field::VendorId => {
+                    let vendor = table.value(name)?.as_bytes();
+                    if vendor.len() > 4 {
+                        return Err(TtxError::Invalid(format!("The vendor ID {:?} is longer than 4 bytes", table.value(name)?)));
+                    }
+                    data.extend_from_slice(vendor);
+                    data.resize(data.len() + 4 - vendor.len(), 0);
+                }
+            }
+        }
+        Ok(Os2 { data })
+    }
+
+    /// Sets `usFirstCharIndex` and `usLastCharIndex` (fontTools does this based on the cmap)
+    pub fn set_char_indices(&mut self, first: u16, last: u16) {
+        self.data[OS2_FIRST_CHAR_INDEX..OS2_FIRST_CHAR_INDEX + 2].copy_from_slice(&first.to_be_bytes());
+        self.data[OS2_FIRST_CHAR_INDEX + 2..OS2_FIRST_CHAR_INDEX + 4].copy_from_slice(&last.to_be_bytes());
+    }
+
+    pub fn compile(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+/// The `hmtx` or `vmtx` table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The advance and the side bearing of the glyphs
+    metrics: HashMap<String, (u16, i16)>,
+}
+
+impl Metrics {
+    /// Reads the `mtx` elements with the given attributes for the advance and the side bearing
+    /// (i.e. `width` and `lsb` for hmtx and `height` and `tsb` for vmtx)
+    pub fn from_xml(table: &Element, advance: &str, side_bearing: &str) -> Result<Metrics, TtxError> {
+        let metrics = table.children_named("mtx")
+            .map(|mtx| Ok((
+                mtx.required("name")?.to_string(),
+                (to_u16(advance, mtx.int(advance)?)?, to_i16(side_bearing, mtx.int(side_bearing)?)?)
+            )))
+            .collect::<Result<_, TtxError>>()?;
+        Ok(Metrics { metrics })
+    }
+
+    /// The largest advance (which is set in hhea/vhea)
+    pub fn advance_max(&self) -> Option<u16> {
+        self.metrics.values().map(|(advance, _)| *advance).max()
+    }
+
+    /// Compiles the metrics in the order of the glyphs.
+    /// Glyphs at the end with the same advance only store their side bearing.
+    /// # Returns
+    /// The table and the number of metrics with an advance (which is stored in hhea/vhea)
+    pub fn compile(&self, glyph_order: &[String]) -> Result<(Vec<u8>, u16), TtxError> {
+        let metrics = glyph_order.iter()
+            .map(|glyph| self.metrics.get(glyph).copied()
+                .ok_or_else(|| TtxError::Invalid(format!("There are no metrics for {}", glyph))))
+            .collect::<Result<Vec<(u16, i16)>, TtxError>>()?;
+        let last_advance = match metrics.last() {
+            Some((advance, _)) => *advance,
+            None => return Err(TtxError::Invalid(String::from("There are no glyphs")))
+        };
+        let long_metrics = metrics.iter()
+            .rposition(|(advance, _)| *advance != last_advance)
+            .map(|different| different + 2)
+            .unwrap_or(1);
+
+        let mut data = Vec::with_capacity(long_metrics * 2 + metrics.len() * 2);
+        for (index, (advance, side_bearing)) in metrics.iter().enumerate() {
+            if index < long_metrics {
+                data.u16(*advance);
+            }
+            data.i16(*side_bearing);
+        }
+        Ok((data, long_metrics as u16))
+    }
+}
+
+/// A subtable of `cmap`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CmapSubtable {
+    platform: u16,
+    encoding: u16,
+    language: u32,
+    format: u16,
+    mapping: BTreeMap<u32, String>,
+}
+
+impl CmapSubtable {
+    /// Whether the subtable maps Unicode codepoints (cf. `isUnicode` in fontTools)
+    fn is_unicode(&self) -> bool {
+        self.platform == 0 || (self.platform == 3 && [0, 1, 10].contains(&self.encoding))
+    }
+
+    fn compile(&self, ids: &GlyphIds) -> Result<Vec<u8>, TtxError> {
+        match self.format {
+            4 => self.compile_format_4(ids),
+            _ => self.compile_format_12(ids)
+        }
+    }
+
+    fn compile_format_4(&self, ids: &GlyphIds) -> Result<Vec<u8>, TtxError> {
+        let mut mapping: BTreeMap<u16, u16> = BTreeMap::new();
+        for (code, glyph) in &self.mapping {
+            if *code > 0xffff {
+                return Err(TtxError::Invalid(format!("cmap format 4 can't contain U+{:04X}", code)));
+            }
+            mapping.insert(*code as u16, glyph_id(ids, glyph)?);
+        }
+
+        // Consecutive codes are split into segments by split_range
+        let mut starts: Vec<u16> = Vec::new();
+        let mut ends: Vec<u16> = Vec::new();
+        let codes: Vec<u16> = mapping.keys().copied().collect();
+        if let Some((first, rest)) = codes.split_first() {
+            starts.push(*first);
+            let mut last = *first;
+            for code in rest {
+                if *code as u32 == last as u32 + 1 {
+                    last = *code;
+                    continue;
+                }
+                let (start, end) = split_range(*starts.last().unwrap(), last, &mapping);
+                starts.extend(start);
+                ends.extend(end);
+                starts.push(*code);
+                last = *code;
+            }
+            let (start, end) = split_range(*starts.last().unwrap(), last, &mapping);
+            starts.extend(start);
+            ends.extend(end);
+        }
+        starts.push(0xffff);
+        ends.push(0xffff);
+
+        let segments = ends.len();
+        let mut deltas: Vec<u16> = Vec::with_capacity(segments);
+        let mut range_offsets: Vec<usize> = Vec::with_capacity(segments);
+        let mut glyph_ids: Vec<u16> = Vec::new();
+        for segment in 0..segments - 1 {
+            let segment_ids: Vec<u16> = (starts[segment]..=ends[segment])
+                .map(|code| mapping[&code])
+                .collect();
+            let consecutive = segment_ids.iter()
+                .enumerate()
+                .all(|(index, id)| *id as usize == segment_ids[0] as usize + index);
+            if consecutive {
+                deltas.push(segment_ids[0].wrapping_sub(starts[segment]));
+                range_offsets.push(0);
+            } else {
+                deltas.push(0);
+                range_offsets.push(2 * (segments + glyph_ids.len() - segment));
+                glyph_ids.extend(segment_ids);
+            }
+        }
+        // The final segment maps 0xFFFF to .notdef
+        deltas.push(1);
+        range_offsets.push(0);
+
+        let length = 14 + 2 * (4 * segments + 1 + glyph_ids.len());
+        let (search_range, entry_selector, range_shift) = search_range(segments, 2);
+        let mut data = Vec::with_capacity(length);
+        data.u16(4);
+        data.u16(to_u16("the length of cmap format 4", length as i64)?);
+        data.u16(to_u16("language", self.language as i64)?);
+        data.u16(segments as u16 * 2);
+        data.u16(search_range);
+        data.u16(entry_selector);
+        data.u16(range_shift);
+        for end in ends {
+            data.u16(end);
+        }
+        // reservedPad
+        data.u16(0);
+        for start in starts {
+            data.u16(start);
+        }
+        for delta in deltas {
+            data.u16(delta);
+        }
+        for range_offset in range_offsets {
+            data.u16(range_offset as u16);
+        }
+        for id in glyph_ids {
+            data.u16(id);
+        }
+        Ok(data)
+    }
+
+    fn compile_format_12(&self, ids: &GlyphIds) -> Result<Vec<u8>, TtxError> {
+        // (start code, end code, start glyph ID)
+        let mut groups: Vec<(u32, u32, u32)> = Vec::new();
+        for (code, glyph) in &self.mapping {
+            let id = glyph_id(ids, glyph)? as u32;
+            match groups.last_mut() {
+                Some((start, end, start_id)) if *code == *end + 1 && id == *start_id + (*end - *start) + 1 => {
+                    *end = *code;
+                },
+                _ => groups.push((*code, *code, id))
+            }
+        }
+
+        let mut data = Vec::with_capacity(16 + 12 * groups.len());
+        data.u16(12);
+        // reserved
+        data.u16(0);
+        data.u32(16 + 12 * groups.len() as u32);
+        data.u32(self.language);
+        data.u32(groups.len() as u32);
+        for (start, end, start_id) in groups {
+            data.u32(start);
+            data.u32(end);
+            data.u32(start_id);
+        }
+        Ok(data)
+    }
+}
+
+/// Splits a range of consecutive codes into segments with consecutive glyph IDs where this makes
+/// cmap format 4 smaller (this is `splitRange` from fontTools).
+/// # Returns
+/// The start codes of the additional segments and the end codes of all segments
+fn split_range(start: u16, end: u16, mapping: &BTreeMap<u16, u16>) -> (Vec<u16>, Vec<u16>) {
+    if start == end {
+        return (Vec::new(), vec![end]);
+    }
+
+    // Find the ranges with consecutive glyph IDs
+    let mut last_id = mapping[&start];
+    let mut last_code = start;
+    let mut in_order = false;
+    let mut ordered_begin = start;
+    let mut sub_ranges: Vec<(u16, u16)> = Vec::new();
+    for code in start + 1..=end {
+        let id = mapping[&code];
+        if id as u32 == last_id as u32 + 1 {
+            if !in_order {
+                in_order = true;
+                ordered_begin = last_code;
+            }
+        } else if in_order {
+            in_order = false;
+            sub_ranges.push((ordered_begin, last_code));
+        }
+        last_id = id;
+        last_code = code;
+    }
+    if in_order {
+        sub_ranges.push((ordered_begin, last_code));
+    }
+
+    // A new segment costs 8 bytes, a code in a segment without consecutive glyph IDs costs 2 bytes
+    let mut useful_ranges: Vec<(u16, u16)> = Vec::new();
+    for (begin, finish) in sub_ranges {
+        if begin == start && finish == end {
+            break;
+        }
+        let threshold = if begin == start || finish == end { 4 } else { 8 };
+        if finish as u32 - begin as u32 + 1 > threshold {
+            useful_ranges.push((begin, finish));
+        }
+    }
+    let mut sub_ranges = useful_ranges;
+    if sub_ranges.is_empty() {
+        return (Vec::new(), vec![end]);
+    }
+
+    // Fill the gaps with segments that use the glyph ID array
+    if sub_ranges[0].0 != start {
+        sub_ranges.insert(0, (start, sub_ranges[0].0 - 1));
+    }
+    let last_end = sub_ranges[sub_ranges.len() - 1].1;
+    if last_end != end {
+        sub_ranges.push((last_end + 1, end));
+    }
+    let mut index = 1;
+    while index < sub_ranges.len() {
+        if sub_ranges[index - 1].1 + 1 != sub_ranges[index].0 {
+            sub_ranges.insert(index, (sub_ranges[index - 1].1 + 1, sub_ranges[index].0 - 1));
+            index += 1;
+        }
+        index += 1;
+    }
+
+    (
+        sub_ranges.iter().skip(1).map(|(begin, _)| *begin).collect(),
+        sub_ranges.iter().map(|(_, finish)| *finish).collect()
+    )
+}
+
+/// The `cmap` table (with subtables of format 4 and 12)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmap {
+    version: u16,
+    subtables: Vec<CmapSubtable>,
+}
+
+impl Cmap {
+    pub fn from_xml(table: &Element) -> Result<Cmap, TtxError> {
+        let version = match table.child("tableVersion") {
+            Some(version) => version.u16_attribute("version")?,
+            None => 0
+        };
+        let subtables = table.children.iter()
+            .filter(|child| child.name != "tableVersion")
+            .map(|subtable| {
+                let format = match subtable.name.as_str() {
+                    "cmap_format_4" => 4,
+                    "cmap_format_12" => 12,
+                    other => return Err(TtxError::Unsupported(format!("the cmap subtable {}", other)))
+                };
+                let mapping = subtable.children_named("map")
+                    .map(|map| Ok((to_u32("code", map.int("code")?)?, map.required("name")?.to_string())))
+                    .collect::<Result<_, TtxError>>()?;
+                Ok(CmapSubtable {
+                    platform: subtable.u16_attribute("platformID")?,
+                    encoding: subtable.u16_attribute("platEncID")?,
+                    language: to_u32("language", subtable.int("language")?)?,
+                    format,
+                    mapping
+                })
+            })
+            .collect::<Result<_, TtxError>>()?;
+        Ok(Cmap { version, subtables })
+    }
+
+    /// All codepoints in the Unicode subtables
+    pub fn unicode_codepoints(&self) -> HashSet<u32> {
+        self.subtables.iter()
+            .filter(|subtable| subtable.is_unicode())
+            .flat_map(|subtable| subtable.mapping.keys().copied())
+            .collect()
+    }
+
+    /// Compiles the subtables sorted by their platform, encoding and language.
+    /// Identical subtables are only stored once.
+    pub fn compile(&self, ids: &GlyphIds) -> Result<Vec<u8>, TtxError> {
+        let mut subtables: Vec<&CmapSubtable> = self.subtables.iter().collect();
+        subtables.sort_by_key(|subtable| (subtable.platform, subtable.encoding, subtable.language));
+
+        let mut data = Vec::new();
+        data.u16(self.version);
+        data.u16(to_u16("the number of cmap subtables", subtables.len() as i64)?);
+        let header_size = 4 + 8 * subtables.len();
+        let mut subtable_data: Vec<u8> = Vec::new();
+        let mut offsets: HashMap<Vec<u8>, u32> = HashMap::new();
+        for subtable in subtables {
+            let compiled = subtable.compile(ids)?;
+            let offset = match offsets.get(&compiled) {
+                Some(offset) => *offset,
+                None => {
+                    let offset = (header_size + subtable_data.len()) as u32;
+                    subtable_data.extend_from_slice(&compiled);
+                    offsets.insert(compiled, offset);
+                    offset
+                }
+            };
+            data.u16(subtable.platform);
+            data.u16(subtable.encoding);
+            data.u32(offset);
+        }
+        data.extend(subtable_data);
+        Ok(data)
+    }
+}
+
+/// A record of the `name` table with its encoded string
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NameRecord {
+    platform: u16,
+    encoding: u16,
+    language: u16,
+    name_id: u16,
+    string: Vec<u8>,
+}
+
+/// The `name` table (format 0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Names {
+    records: Vec<NameRecord>,
+}
+
+impl Names {
+    pub fn from_xml(table: &Element) -> Result<Names, TtxError> {
+        let records = table.children.iter()
+            .map(|record| {
+                if record.name != "namerecord" {
+                    return Err(TtxError::Unsupported(format!("<{}> in the name table", record.name)));
+                }
+                let platform = record.u16_attribute("platformID")?;
+                let encoding = record.u16_attribute("platEncID")?;
+                let text = record.text.trim();
+                let string = match (platform, encoding) {
+                    (0, _) | (3, 0) | (3, 1) | (3, 10) => text.encode_utf16()
+                        .flat_map(|unit| unit.to_be_bytes().to_vec())
+                        .collect(),
+                    // Mac Roman is the same as ASCII for these
+                    (1, 0) if text.is_ascii() => text.as_bytes().to_vec(),
+                    _ => return Err(TtxError::Unsupported(format!(
+                        "name records for platform {} with encoding {}", platform, encoding)))
+                };
+                Ok(NameRecord {
+                    platform,
+                    encoding,
+                    language: record.u16_attribute("langID")?,
+                    name_id: record.u16_attribute("nameID")?,
+                    string
+                })
+            })
+            .collect::<Result<_, TtxError>>()?;
+        Ok(Names { records })
+    }
+
+    /// Compiles the records sorted by their platform, encoding, language and name ID.
+    /// Identical strings are only stored once.
+    pub fn compile(&self) -> Result<Vec<u8>, TtxError> {
+        let mut records: Vec<&NameRecord> = self.records.iter().collect();
+        records.sort_by_key(|record| (record.platform, record.encoding, record.language, record.name_id));
+
+        let mut data = Vec::new();
+        // Format
+        data.u16(0);
+        data.u16(to_u16("the number of name records", records.len() as i64)?);
+        data.u16(to_u16("the offset of the name strings", 6 + 12 * records.len() as i64)?);
+        let mut strings: Vec<u8> = Vec::new();
+        let mut offsets: HashMap<&[u8], u16> = HashMap::new();
+        for record in records {
+            let offset = match offsets.get(record.string.as_slice()) {
+                Some(offset) => *offset,
+                None => {
+                    let offset = to_u16("the offset of a name string", strings.len() as i64)?;
+                    strings.extend_from_slice(&record.string);
+                    offsets.insert(&record.string, offset);
+                    offset
+                }
+            };
+            data.u16(record.platform);
+            data.u16(record.encoding);
+            data.u16(record.language);
+            data.u16(record.name_id);
+            data.u16(to_u16("the length of a name string", record.string.len() as i64)?);
+            data.u16(offset);
+        }
+        data.extend(strings);
+        Ok(data)
+    }
+}
+
+/// The `post` table (only format 3, i.e. without glyph names), which is compiled right away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Post {
+    data: Vec<u8>,
+}
+
+impl Post {
+    pub fn from_xml(table: &Element) -> Result<Post, TtxError> {
+        let format = parse_fixed(table.value("formatType")?)?;
+        if format != 0x30000 {
+            return Err(TtxError::Unsupported(format!("post format {}", table.value("formatType")?)));
+        }
+        let mut data = Vec::with_capacity(32);
+        data.i32(format);
+        data.i32(parse_fixed(table.value("italicAngle")?)?);
+        data.i16(table.i16_value("underlinePosition")?);
+        data.i16(table.i16_value("underlineThickness")?);
+        for field in &["isFixedPitch", "minMemType42", "maxMemType42", "minMemType1", "maxMemType1"] {
+            data.u32(table.u32_value(field)?);
+        }
+        Ok(Post { data })
+    }
+
+    pub fn compile(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+/// The `glyf` table, which may only contain empty glyphs (like the ones `add_glyphs.py` adds for
+/// Windows)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Glyf {
+    glyphs: HashSet<String>,
+}
+
+impl Glyf {
+    pub fn from_xml(table: &Element) -> Result<Glyf, TtxError> {
+        let glyphs = table.children_named("TTGlyph")
+            .map(|glyph| if glyph.children.is_empty() {
+                Ok(glyph.required("name")?.to_string())
+            } else {
+                Err(TtxError::Unsupported(String::from("glyphs with outlines")))
+            })
+            .collect::<Result<_, TtxError>>()?;
+        Ok(Glyf { glyphs })
+    }
+
+    /// Compiles the glyf and the loca table (in the short format)
+    pub fn compile(&self, glyph_order: &[String]) -> Result<(Vec<u8>, Vec<u8>), TtxError> {
+        if let Some(missing) = glyph_order.iter().find(|glyph| !self.glyphs.contains(*glyph)) {
+            return Err(TtxError::Invalid(format!("{} is missing in the glyf table", missing)));
+        }
+        // fontTools writes a single zero byte if all glyphs are empty, as an empty table is
+        // rejected by some implementations
+        Ok((vec![0], vec![0; 2 * (glyph_order.len() + 1)]))
+    }
+}
+
+#[test]
+fn test_parse_timestamp() {
+    assert_eq!(parse_timestamp("Wed May 22 20:00:43 2013").unwrap(), 1_369_252_843 + MAC_EPOCH_OFFSET);
+    assert_eq!(parse_timestamp("Fri Jan  1 00:00:00 1904").unwrap(), 0);
+    assert!(parse_timestamp("Wed 22 May 2013").is_err());
+}
+
+#[test]
+fn test_hmtx_number_of_metrics() {
+    let glyph_order: Vec<String> = vec![".notdef", "null", "space", "u1F600"].into_iter().map(String::from).collect();
+    let metrics = |advances: &[u16]| Metrics {
+        metrics: glyph_order.iter().cloned().zip(advances.iter().map(|advance| (*advance, 0))).collect()
+    };
+
+    // The last glyph only has its side bearing
+    let (data, number_of_metrics) = metrics(&[2550, 0, 2550, 2550]).compile(&glyph_order).unwrap();
+    assert_eq!(number_of_metrics, 3);
+    assert_eq!(data.len(), 3 * 4 + 2);
+
+    assert_eq!(metrics(&[2550, 2550, 2550, 2550]).compile(&glyph_order).unwrap().1, 1);
+    assert_eq!(metrics(&[2550, 2550, 2550, 0]).compile(&glyph_order).unwrap().1, 4);
+}
+
+#[test]
+fn test_cmap_format_4() {
+    let glyph_order: Vec<String> = (0..20).map(|id| format!("glyph{}", id)).collect();
+    let ids: GlyphIds = glyph_order.iter().enumerate().map(|(id, glyph)| (glyph.as_str(), id as u16)).collect();
+    // 0x20..=0x29 have consecutive glyph IDs except for one in the middle
+    let mut mapping: BTreeMap<u32, String> = (0x20..=0x29).map(|code| (code, format!("glyph{}", code - 0x20 + 1))).collect();
+    mapping.insert(0x25, String::from("glyph19"));
+    mapping.insert(0x263a, String::from("glyph15"));
+    let subtable = CmapSubtable { platform: 3, encoding: 1, language: 0, format: 4, mapping };
+
+    let mut by_code: BTreeMap<u16, u16> = BTreeMap::new();
+    for (code, glyph) in &subtable.mapping {
+        by_code.insert(*code as u16, ids[glyph.as_str()]);
+    }
+    // 0x26..=0x29 are too short to get their own segment
+    assert_eq!(split_range(0x20, 0x29, &by_code), (vec![0x25], vec![0x24, 0x29]));
+
+    let data = subtable.compile(&ids).unwrap();
+    let read = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+    // 0x20-0x24, 0x25-0x29, 0x263A, 0xFFFF
+    assert_eq!(read(6), 8);
+    assert_eq!(read(2) as usize, data.len());
+}
@@ -0,0 +1,341 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Reads TTX files with the (streaming) tokenizer of `xmlparser`.
+//! Only the element that is currently open is kept in memory, so every table is handed over
+//! (and compiled) as soon as it has been read completely.
+
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+use crate::builders::blobmoji::ttx_compiler::TtxError;
+
+/// The `sfntVersion` of TrueType fonts as it is written by fontTools
+const TRUETYPE_SFNT_VERSION: &str = "\\x00\\x01\\x00\\x00";
+
+/// An XML element with its attributes, its child elements and its text (without comments)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    /// The value of an attribute (if it is given multiple times, the last one counts)
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Like [Element::attribute], but a missing attribute is an error
+    pub fn required(&self, name: &str) -> Result<&str, TtxError> {
+        self.attribute(name)
+            .ok_or_else(|| TtxError::Invalid(format!("<{}> has no {} attribute", self.name, name)))
+    }
+
+    /// The integer value of an attribute
+    pub fn int(&self, name: &str) -> Result<i64, TtxError> {
+        parse_int(self.required(name)?)
+    }
+
+    /// The last child element with this name (later values override earlier ones in fontTools)
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().rev().find(|child| child.name == name)
+    }
+
+    /// All child elements with this name in the order of the document
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The `value` attribute of the (last) child element with this name,
+    /// which is how fontTools writes most of the fields
+    pub fn value(&self, name: &str) -> Result<&str, TtxError> {
+        match self.child(name) {
+            Some(child) => child.required("value"),
+            None => Err(TtxError::Invalid(format!("<{}> has no <{}>", self.name, name)))
+        }
+    }
+
+    /// The integer value of a field (see [Element::value])
+    pub fn int_value(&self, name: &str) -> Result<i64, TtxError> {
+        parse_int(self.value(name)?)
+    }
+
+    /// A field that is stored as `uint16`
+    pub fn u16_value(&self, name: &str) -> Result<u16, TtxError> {
+        to_u16(name, self.int_value(name)?)
+    }
+
+    /// A field that is stored as `int16`
+    pub fn i16_value(&self, name: &str) -> Result<i16, TtxError> {
+        to_i16(name, self.int_value(name)?)
+    }
+
+    /// A field that is stored as `uint32`
+    pub fn u32_value(&self, name: &str) -> Result<u32, TtxError> {
+        to_u32(name, self.int_value(name)?)
+    }
+
+    /// An attribute that is stored as `uint16`
+    pub fn u16_attribute(&self, name: &str) -> Result<u16, TtxError> {
+        to_u16(name, self.int(name)?)
+    }
+
+    /// Like [Element::child], but a missing child is an error
+    pub fn required_child(&self, name: &str) -> Result<&Element, TtxError> {
+        self.child(name)
+            .ok_or_else(|| TtxError::Invalid(format!("<{}> has no <{}>", self.name, name)))
+    }
+
+    /// Whether this element stands for a null offset (i.e. `empty="1"`)
+    pub fn is_empty_offset(&self) -> bool {
+        self.attribute("empty") == Some("1")
+    }
+}
+
+/// Reads the tables (i.e. the children of `<ttFont>`) of a TTX file one after another
+pub fn read_tables<F>(ttx: &str, mut table: F) -> Result<(), TtxError>
+    where F: FnMut(Element) -> Result<(), TtxError> {
+    let mut open: Vec<Element> = Vec::new();
+    let mut has_root = false;
+
+    for token in Tokenizer::from(ttx) {
+        match token.map_err(|err| TtxError::Xml(err.to_string()))? {
+            Token::ElementStart { local, .. } => {
+                if open.is_empty() && has_root {
+                    return Err(TtxError::Xml(format!("Unexpected element after the root: <{}>", local.as_str())));
+                }
+                open.push(Element {
+                    name: local.as_str().to_string(),
+                    ..Element::default()
+                });
+            },
+            Token::Attribute { local, value, .. } => {
+                if let Some(element) = open.last_mut() {
+                    element.attributes.push((local.as_str().to_string(), unescape(value.as_str())?));
+                }
+            },
+            Token::ElementEnd { end: ElementEnd::Open, .. } if open.len() == 1 => {
+                check_root(&open[0])?;
+                has_root = true;
+            },
+            Token::ElementEnd { end: ElementEnd::Open, .. } => (),
+            Token::ElementEnd { end, .. } => {
+                let element = match open.pop() {
+                    Some(element) => element,
+                    None => return Err(TtxError::Xml(String::from("Unexpected closing tag")))
+                };
+                if let ElementEnd::Close(_, local) = end {
+                    if local.as_str() != element.name {
+                        return Err(TtxError::Xml(format!("<{}> is closed by </{}>", element.name, local.as_str())));
+                    }
+                }
+                match open.len() {
+                    0 => {
+                        // An empty root element
+                        check_root(&element)?;
+                        has_root = true;
+                    },
+                    1 => table(element)?,
+                    _ => open.last_mut().unwrap().children.push(element)
+                }
+            },
+            Token::Text { text } => {
+                if let Some(element) = open.last_mut() {
+                    element.text.push_str(&unescape(text.as_str())?);
+                }
+            },
+            Token::Cdata { text, .. } => {
+                if let Some(element) = open.last_mut() {
+                    element.text.push_str(text.as_str());
+                }
+            },
+            // Declarations, comments, etc.
+            _ => {}
+        }
+    }
+
+    if !open.is_empty() {
+        Err(TtxError::Xml(format!("<{}> isn't closed", open.last().unwrap().name)))
+    } else if !has_root {
+        Err(TtxError::Xml(String::from("There's no <ttFont> element")))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_root(root: &Element) -> Result<(), TtxError> {
+    if root.name != "ttFont" {
+        return Err(TtxError::Xml(format!("The root element is <{}> instead of <ttFont>", root.name)));
+    }
+    match root.attribute("sfntVersion") {
+        None => Ok(()),
+        Some(TRUETYPE_SFNT_VERSION) => Ok(()),
+        // Most likely OTTO, i.e. a CFF-based font
+        Some(version) => Err(TtxError::Unsupported(format!("sfntVersion {}", version)))
+    }
+}
+
+/// Replaces the predefined entities and character references
+fn unescape(text: &str) -> Result<String, TtxError> {
+    if !text.contains('&') {
+        return Ok(text.to_string());
+    }
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = match rest[start..].find(';') {
+            Some(end) => start + end,
+            None => return Err(TtxError::Xml(format!("Unterminated entity in {:?}", text)))
+        };
+        let entity = &rest[start + 1..end];
+        let character = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None
+        };
+        match character {
+            Some(character) => unescaped.push(character),
+            None => return Err(TtxError::Xml(format!("Unknown entity &{};", entity)))
+        }
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+/// Parses an integer the way fontTools' `safeEval` does for the values in TTX files,
+/// i.e. as a decimal or as a hexadecimal number (`0x...`)
+pub fn parse_int(value: &str) -> Result<i64, TtxError> {
+    let value = value.trim();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value)
+    };
+    let parsed = if digits.starts_with("0x") || digits.starts_with("0X") {
+        i64::from_str_radix(&digits[2..], 16)
+    } else {
+        digits.parse()
+    };
+    match parsed {
+        Ok(parsed) if negative => Ok(-parsed),
+        Ok(parsed) => Ok(parsed),
+        Err(_) => Err(TtxError::Invalid(format!("{:?} is not an integer", value)))
+    }
+}
+
+pub fn to_u16(name: &str, value: i64) -> Result<u16, TtxError> {
+    if (0..=0xffff).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(out_of_range(name, value))
+    }
+}
+
+pub fn to_i16(name: &str, value: i64) -> Result<i16, TtxError> {
+    if (-0x8000..=0x7fff).contains(&value) {
+        Ok(value as i16)
+    } else {
+        Err(out_of_range(name, value))
+    }
+}
+
+pub fn to_u32(name: &str, value: i64) -> Result<u32, TtxError> {
+    if (0..=0xffff_ffff).contains(&value) {
+        Ok(value as u32)
+    } else {
+        Err(out_of_range(name, value))
+    }
+}
+
+fn out_of_range(name: &str, value: i64) -> TtxError {
+    TtxError::Invalid(format!("{} is out of range for {}", value, name))
+}
+
+/// Parses a number that is stored as a 16.16 fixed-point number (rounded like `otRound`)
+pub fn parse_fixed(value: &str) -> Result<i32, TtxError> {
+    let number: f64 = match parse_int(value) {
+        Ok(integer) => integer as f64,
+        Err(_) => value.trim().parse()
+            .map_err(|_| TtxError::Invalid(format!("{:?} is not a number", value)))?
+    };
+    let fixed = (number * 65536.0 + 0.5).floor();
+    if fixed < i32::MIN as f64 || fixed > i32::MAX as f64 {
+        Err(TtxError::Invalid(format!("{} is too large for a fixed-point number", value)))
+    } else {
+        Ok(fixed as i32)
+    }
+}
+
+/// Parses a bit field that fontTools writes as binary digits (e.g. `00000000 00001011`)
+pub fn parse_binary(value: &str) -> Result<u32, TtxError> {
+    let digits: String = value.chars().filter(|character| !character.is_whitespace()).collect();
+    u32::from_str_radix(&digits, 2)
+        .map_err(|_| TtxError::Invalid(format!("{:?} is not a binary number", value)))
+}
+
+#[test]
+fn test_read_tables() {
+    let ttx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ttFont sfntVersion="\x00\x01\x00\x00" ttLibVersion="4.22">
+  <head>
+    <!-- A comment -->
+    <flags value="00000000 00001011"/>
+  </head>
+  <name>
+    <namerecord nameID="0" platformID="3" platEncID="1" langID="0x409">
+      Copyright &amp; &#x263A;
+    </namerecord>
+  </name>
+</ttFont>"#;
+    let mut tables = Vec::new();
+    read_tables(ttx, |table| {
+        tables.push(table);
+        Ok(())
+    }).unwrap();
+
+    assert_eq!(tables.len(), 2);
+    assert_eq!(parse_binary(tables[0].value("flags").unwrap()).unwrap(), 0b1011);
+    let record = tables[1].child("namerecord").unwrap();
+    assert_eq!(record.int("langID").unwrap(), 0x409);
+    assert_eq!(record.text.trim(), "Copyright & \u{263a}");
+
+    let otto = "<ttFont sfntVersion=\"OTTO\"><head/></ttFont>";
+    match read_tables(otto, |_| Ok(())) {
+        Err(TtxError::Unsupported(_)) => {},
+        other => panic!("Expected an unsupported font, got {:?}", other)
+    }
+    assert!(read_tables("<ttFont><head></ttFont>", |_| Ok(())).is_err());
+}
+
+#[test]
+fn test_parse_numbers() {
+    assert_eq!(parse_int("0x409").unwrap(), 0x409);
+    assert_eq!(parse_int("-500").unwrap(), -500);
+    assert!(parse_int("1.5").is_err());
+    assert_eq!(parse_fixed("2.019").unwrap(), 132317);
+    assert_eq!(parse_fixed("1.0").unwrap(), 0x10000);
+    assert_eq!(parse_fixed("-12.5").unwrap(), -12 * 65536 - 32768);
+}
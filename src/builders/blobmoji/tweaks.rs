@@ -0,0 +1,205 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::builders::blobmoji::{CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT};
+use crate::builders::blobmoji::image_utils;
+use crate::builders::blobmoji::subset::SubsetEntry;
+use crate::emoji::Emoji;
+
+/// Which side of the emoji is fitted to the render size, regardless of its aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForceFit {
+    Width,
+    Height,
+}
+
+/// Manual adjustments for the rendering of a single emoji
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tweak {
+    /// The factor by which the rendered image is scaled
+    pub scale: f32,
+    /// Moves the image to the right (in pixels)
+    pub offset_x: i32,
+    /// Moves the image down (in pixels)
+    pub offset_y: i32,
+    /// A color in the format `#RRGGBB` or `#RRGGBBAA` that's put behind the emoji
+    pub background_color: Option<String>,
+    /// Fit this side instead of the longer one
+    pub force_fit: Option<ForceFit>,
+}
+
+/// A list of [Tweak]s with the emojis they apply to.
+/// It's loaded from a JSON file like this one:
+/// ```json
+/// {
+///   "2615": { "scale": 0.95, "offset_y": 3 },
+///   "flag: Germany": { "background_color": "#FFFFFF", "force_fit": "width" }
+/// }
+/// ```
+/// The keys are parsed in the same way as the lines of a subset file.
+#[derive(Debug, Clone, Default)]
+pub struct Tweaks(Vec<(SubsetEntry, Tweak)>);
+
+impl Default for Tweak {
+    fn default() -> Self {
+        Tweak {
+            scale: 1.0,
+            offset_x: 0,
+            offset_y: 0,
+            background_color: None,
+            force_fit: None,
+        }
+    }
+}
+
+impl Tweak {
+    /// The data that's hashed together with the SVG, so that an emoji gets re-rendered
+    /// if its tweak changes
+    pub fn cache_key(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// The background color as (non-premultiplied) RGBA
+    pub fn background(&self) -> Option<[u8; 4]> {
+        self.background_color.as_deref().and_then(image_utils::parse_color)
+    }
+
+    /// Puts the rendered image on the canvas (moved by the offset) and fills the background
+    pub fn apply(&self, content: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let mut image = image_utils::place(
+            content,
+            width,
+            height,
+            CHARACTER_WIDTH,
+            RENDER_AND_CHARACTER_HEIGHT,
+            self.offset_x,
+            self.offset_y,
+        );
+        if let Some(background) = self.background() {
+            image_utils::fill_background(&mut image, background);
+        }
+        image
+    }
+}
+
+impl Tweaks {
+    /// Loads the tweaks from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Tweaks> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads the tweaks from a JSON object that maps sequences or names to tweaks
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<Tweaks> {
+        let entries: BTreeMap<String, Tweak> = serde_json::from_reader(reader)?;
+        for (emoji, tweak) in &entries {
+            if let Some(color) = &tweak.background_color {
+                if image_utils::parse_color(color).is_none() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid background color for {}: {}", emoji, color),
+                    ));
+                }
+            }
+            if tweak.scale.is_nan() || tweak.scale <= 0.0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid scale for {}: {}", emoji, tweak.scale),
+                ));
+            }
+        }
+        Ok(Tweaks(entries.into_iter()
+            .map(|(emoji, tweak)| (SubsetEntry::from(emoji.as_str()), tweak))
+            .collect()))
+    }
+
+    /// Returns the tweak for an emoji (if there is one)
+    pub fn get(&self, emoji: &Emoji) -> Option<&Tweak> {
+        self.0.iter()
+            .find(|(entry, _)| entry.matches(emoji))
+            .map(|(_, tweak)| tweak)
+    }
+
+    /// The number of tweaked emojis
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[test]
+fn test_tweaks() {
+    let json = r##"{
+        "2615": { "scale": 0.9, "offset_y": 3 },
+        "U+1F1E9 U+1F1EA": { "background_color": "#FFFFFF80", "force_fit": "width" }
+    }"##;
+    let tweaks = Tweaks::from_reader(json.as_bytes()).unwrap();
+    assert_eq!(tweaks.len(), 2);
+
+    let coffee = tweaks.get(&Emoji::from(vec![0x2615, 0xfe0f])).unwrap();
+    assert_eq!(coffee.scale, 0.9);
+    assert_eq!(coffee.offset_x, 0);
+    assert_eq!(coffee.offset_y, 3);
+    assert_eq!(coffee.background(), None);
+
+    let germany = tweaks.get(&Emoji::from_flag("DE", None).unwrap()).unwrap();
+    assert_eq!(germany.background(), Some([255, 255, 255, 128]));
+    assert_eq!(germany.force_fit, Some(ForceFit::Width));
+    assert_ne!(germany.cache_key(), coffee.cache_key());
+
+    assert!(tweaks.get(&Emoji::from(vec![0x1f914])).is_none());
+
+    assert!(Tweaks::from_reader(r##"{"2615": {"background_color": "white"}}"##.as_bytes()).is_err());
+    assert!(Tweaks::from_reader(r##"{"2615": {"scale": 0}}"##.as_bytes()).is_err());
+    assert!(Tweaks::from_reader(r##"{"2615": {"zoom": 2}}"##.as_bytes()).is_err());
+}
+
+#[test]
+fn test_apply_tweak() {
+    // A 2x2 opaque red square
+    let content: Vec<u8> = [255, 0, 0, 255].iter().cycle().take(2 * 2 * 4).copied().collect();
+    let tweak = Tweak {
+        offset_x: 3,
+        offset_y: -2,
+        background_color: Some(String::from("#0000FF")),
+        ..Default::default()
+    };
+    let image = tweak.apply(&content, 2, 2);
+    assert_eq!(image.len(), CHARACTER_WIDTH as usize * RENDER_AND_CHARACTER_HEIGHT as usize * 4);
+
+    let pixel = |x: u32, y: u32| {
+        let start = ((y * CHARACTER_WIDTH + x) * 4) as usize;
+        &image[start..start + 4]
+    };
+    let left = (CHARACTER_WIDTH - 2) / 2 + 3;
+    let top = (RENDER_AND_CHARACTER_HEIGHT - 2) / 2 - 2;
+    assert_eq!(pixel(left, top), &[255, 0, 0, 255]);
+    assert_eq!(pixel(left + 1, top + 1), &[255, 0, 0, 255]);
+    assert_eq!(pixel(left - 1, top), &[0, 0, 255, 255]);
+    assert_eq!(pixel(0, 0), &[0, 0, 255, 255]);
+    // The unmodified image is just centered
+    assert_eq!(Tweak::default().apply(&content, 2, 2), image_utils::enlarge_to(
+        &content, 2, 2, CHARACTER_WIDTH, RENDER_AND_CHARACTER_HEIGHT
+    ));
+}
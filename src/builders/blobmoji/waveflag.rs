@@ -13,8 +13,13 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use std::path::Path;
+
 use itertools::Itertools;
 
+use crate::builders::blobmoji::image_utils;
+use crate::builders::blobmoji::image_utils::Padding;
+
 /// Adds a wavy style (a sinus based displacement) to a flag emoji.
 /// # Arguments
 /// * `content`: The pixels of the image (in RGBA or BGRA format)
@@ -25,14 +30,37 @@ use itertools::Itertools;
 /// * The resulting width (stays the same as the input)
 /// * The resulting height (`height` + `added_lines`)
 pub fn waveflag(content: &[u8], width: usize, height: u32, added_lines: usize) -> (Vec<u8>, u32, u32) {
-    // First of all, add a padding for the wave
-    let mut content = enlarge_by(content, width, added_lines + 1);
+    // The first line is reserved for antialiasing, so the wave amplitude will be a tiny bit smaller
+    let offsets = (0..width).map(|x| offset(x, width, added_lines - 1)).collect_vec();
+    waveflag_with_offsets(content, width, height, added_lines, &offsets)
+}
+
+/// Like [waveflag], but the offsets of the columns come from a mask instead of the sine
+/// (see [WaveMask])
+pub fn waveflag_with_mask(content: &[u8], width: usize, height: u32, added_lines: usize, mask: &WaveMask) -> (Vec<u8>, u32, u32) {
+    let offsets = mask.offsets(width, added_lines - 1);
+    waveflag_with_offsets(content, width, height, added_lines, &offsets)
+}
+
+/// Moves each column of the image upwards by its offset, which is antialiased if it's not an
+/// integer (see [blend]).
+/// # Arguments
+/// * `content`, `width`, `height`, `added_lines`: like in [waveflag]
+/// * `offsets`: the offset of each column; it's limited to `0..=added_lines - 1`
+pub fn waveflag_with_offsets(content: &[u8], width: usize, height: u32, added_lines: usize, offsets: &[f64]) -> (Vec<u8>, u32, u32) {
+    assert_eq!(offsets.len(), width);
+    // First of all, add a padding for the wave. There are two extra lines at the bottom, one of
+    // them is only used for antialiasing and removed later
+    let mut content = image_utils::pad(content, width as u32, height, Padding::vertical(added_lines as u32, added_lines as u32 + 2));
     let rgba_width = width * 4;
 
     let content_ptr = content.as_mut_ptr();
 
-    // The first line is reserved for antialiasing, so the wave amplitude will be a tiny bit smaller
-    let offsets = (0..width).map(|x| offsets(x, width, added_lines - 1)).collect_vec();
+    let max_offset = added_lines.saturating_sub(1) as f64;
+    let offsets = offsets.iter()
+        .map(|offset| offset.max(0.0).min(max_offset))
+        .map(|offset| (offset.floor() as usize, offset.fract()))
+        .collect_vec();
 
     // Go over all pixel positions with their offset
     (0..width).map(|x| (x, offsets[x]))
@@ -90,17 +118,6 @@ fn blend(
     }
 }
 
-/// Returns `(offset(...).floor(), offset(...).floor() + 1, offset(...).fract())`,
-/// with the first two values multiplied by the line width.
-/// Simply used for some precomputations.
-/// Unfortunately, caching doesn't seem to cause any benefits here, but it can be easily applied.
-#[inline]
-fn offsets(x_position: usize, width: usize, max_offset: usize) -> (usize, f64) {
-    let offset = offset(x_position, width, max_offset);
-    let floor = offset.floor() as usize;
-    (floor, offset.fract())
-}
-
 /// Taking _wave_flag seriously, we'll actually use a sinus function here.
 /// This function may be rather expensive as it's run `width` times per flag.
 /// the wavelength is the width of the flag,
@@ -122,15 +139,114 @@ fn offset(x_position: usize, width: usize, max_offset: usize) -> f64 {
     offset.min(max_offset)
 }
 
-/// Adds a vertical padding of `added_lines-1` lines before and `added_lines+1` lines after the image.
-fn enlarge_by(content: &[u8], width: usize, added_lines: usize) -> Vec<u8> {
-    let rgba_width = width * 4;
-    let mut new_content = Vec::with_capacity(
-        2 * added_lines * rgba_width + content.len()
-    );
-    new_content.append(&mut vec![0u8; (added_lines - 1) * rgba_width]);
-    new_content.extend_from_slice(content);
-    // One extra line for antialiasing, will be removed later
-    new_content.append(&mut vec![0u8; (added_lines + 1) * rgba_width]);
-    new_content
-}
\ No newline at end of file
+/// A grayscale image that defines the offset of each column instead of the sine.
+/// The brighter a column, the further it is moved upwards (black is not moved at all, white by
+/// the maximum offset). The mask may have any size; it's resampled to the width of the flag and
+/// each column's values are averaged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveMask(Vec<f64>);
+
+impl WaveMask {
+    /// Loads the mask from a PNG file
+    pub fn from_file(path: &Path) -> std::io::Result<WaveMask> {
+        let (width, height, pixels) = image_utils::read_rgba(path)?;
+        if width == 0 || height == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "The mask is empty"));
+        }
+        let columns = (0..width as usize)
+            .map(|x| (0..height as usize)
+                .map(|y| &pixels[(y * width as usize + x) * 4..(y * width as usize + x) * 4 + 3])
+                .map(|pixel| pixel.iter().map(|channel| *channel as f64).sum::<f64>() / (3.0 * 255.0))
+                .sum::<f64>() / height as f64)
+            .collect();
+        Ok(WaveMask(columns))
+    }
+
+    /// The offsets for a flag of the given width (by linear interpolation between the columns)
+    pub fn offsets(&self, width: usize, max_offset: usize) -> Vec<f64> {
+        let last = self.0.len() - 1;
+        (0..width)
+            .map(|x| if width > 1 {
+                x as f64 * last as f64 / (width - 1) as f64
+            } else {
+                0.0
+            })
+            .map(|position| {
+                let left = (position.floor() as usize).min(last);
+                let right = (left + 1).min(last);
+                let fract = position - left as f64;
+                self.0[left] + (self.0[right] - self.0[left]) * fract
+            })
+            .map(|value| value * max_offset as f64)
+            .collect()
+    }
+
+    /// Changes to the mask require the flags to be rendered again
+    pub fn cache_key(&self) -> Vec<u8> {
+        self.0.iter()
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect()
+    }
+}
+
+#[test]
+fn test_waveflag_with_offsets() {
+    // Two columns of two different colors in two lines
+    let content: Vec<u8> = vec![
+        1, 1, 1, 255, 2, 2, 2, 255,
+        3, 3, 3, 255, 4, 4, 4, 255,
+    ];
+    let transparent = [0; 8];
+
+    // Without an offset, the flag stays below the added lines
+    let (waved, width, height) = waveflag_with_offsets(&content, 2, 2, 3, &[0.0, 0.0]);
+    assert_eq!((width, height), (2, 5));
+    assert_eq!(waved, [&transparent[..], &transparent[..], &transparent[..], &content[..]].concat());
+
+    // Moved upwards by the maximum offset (larger ones are limited), which keeps the first line
+    // for antialiasing
+    let (waved, _, _) = waveflag_with_offsets(&content, 2, 2, 3, &[2.0, 5.0]);
+    assert_eq!(waved, [&transparent[..], &content[..], &transparent[..], &transparent[..]].concat());
+
+    // Only the second column is moved
+    let (waved, _, _) = waveflag_with_offsets(&content, 2, 2, 3, &[0.0, 1.0]);
+    assert_eq!(&waved[16..24], &[0, 0, 0, 0, 2, 2, 2, 255]);
+    assert_eq!(&waved[32..40], &[3, 3, 3, 255, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_waveflag_mask() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mask.png");
+    let write_mask = |width: u32, height: u32, pixels: &[u8]| {
+        let mut encoder = png::Encoder::new(std::fs::File::create(&path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+        WaveMask::from_file(&path).unwrap()
+    };
+
+    // A constant white mask moves the whole flag by the maximum offset
+    let content: Vec<u8> = (1..=8u8).flat_map(|value| vec![value, value, value, 255]).collect();
+    let white = write_mask(3, 2, &[255; 6]);
+    assert_eq!(white.offsets(4, 5), vec![5.0; 4]);
+    let (waved, _, height) = waveflag_with_mask(&content, 4, 2, 6, &white);
+    assert_eq!(height, 8);
+    // The first line is only used for antialiasing
+    let line = 4 * 4;
+    assert!(waved[..line].iter().all(|subpixel| *subpixel == 0));
+    assert_eq!(&waved[line..line + content.len()], &content[..]);
+    assert!(waved[line + content.len()..].iter().all(|subpixel| *subpixel == 0));
+
+    // A constant gray one moves all columns by the same offset
+    let gray = write_mask(1, 3, &[128; 3]);
+    let offsets = gray.offsets(4, 5);
+    assert!(offsets.iter().all(|offset| *offset == offsets[0]));
+    assert!((offsets[0] - 5.0 * 128.0 / 255.0).abs() < 1e-9);
+    assert_eq!(waveflag_with_mask(&content, 4, 2, 6, &gray), waveflag_with_offsets(&content, 4, 2, 6, &offsets));
+
+    // Columns are averaged and interpolated
+    let mask = write_mask(3, 2, &[0, 255, 0, 0, 255, 255]);
+    assert_eq!(mask.offsets(5, 4), vec![0.0, 2.0, 4.0, 3.0, 2.0]);
+    assert_ne!(mask.cache_key(), white.cache_key());
+}
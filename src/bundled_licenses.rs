@@ -0,0 +1,55 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Helpers for the licenses of emoji_builder and its dependencies, which are embedded into the
+//! binary (see the `licenses` subcommand).
+//!
+//! The directory is chosen by `build.rs`: it's `licenses` in the crate's directory or the one
+//! in the `EMOJI_BUILDER_LICENSES_DIR` environment variable at compile time.
+//! If it doesn't exist, nothing is embedded.
+
+use include_dir::{Dir, File};
+
+/// All files of an included directory and its subdirectories.
+/// The files of a directory come before the ones of its subdirectories.
+pub fn recurse_included_dir<'a>(dir: &'a Dir<'a>) -> Vec<&'a File<'a>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir];
+    while let Some(dir) = pending.pop() {
+        files.extend(dir.files());
+        // Reversed, so the first subdirectory is the next one to be popped
+        pending.extend(dir.dirs().iter().rev());
+    }
+    files
+}
+
+#[test]
+fn test_recurse_included_dir() {
+    const INCLUDED: Dir = include_dir::include_dir!("test_files/included");
+    let paths: Vec<String> = recurse_included_dir(&INCLUDED).iter()
+        .map(|file| file.path().to_string_lossy().replace('\\', "/"))
+        .collect();
+    assert_eq!(paths.len(), 4);
+    assert_eq!(paths[0], "LICENSE-A.txt");
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(sorted, vec!["LICENSE-A.txt", "nested/LICENSE-B.txt", "nested/deeper/LICENSE-C.txt", "other/LICENSE-D.txt"]);
+    // A directory's own files come before the ones of its subdirectories
+    let position = |path: &str| paths.iter().position(|other| other == path).unwrap();
+    assert!(position("nested/LICENSE-B.txt") < position("nested/deeper/LICENSE-C.txt"));
+
+    const DEEPER: Dir = include_dir::include_dir!("test_files/included/nested/deeper");
+    assert_eq!(recurse_included_dir(&DEEPER).len(), 1);
+}
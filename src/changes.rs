@@ -17,6 +17,9 @@
 //!
 //! It is mostly intended for the different `EmojiBuilder`s that shouldn't do heavy rendering tasks
 //! twice for the exact same file.
+//!
+//! The hashes are SHA256 by default; as they're only used to detect changes, the much faster
+//! xxh3 can be used instead (see [HashAlgorithm]).
 
 use std::{fs, io};
 use std::collections::HashMap;
@@ -24,19 +27,45 @@ use std::io::{Read, Write};
 // For some reason Cursor is marked as an unused import. However that's wrong as it's used in test_nocr().
 #[cfg(test)]
 use std::io::Cursor;
-use std::ops::Index;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
 
 use csv::Error;
-use digest::generic_array::GenericArray;
 use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
 
 use crate::changes::CheckError::{Io, NoFileSpecified};
+use crate::configs::ConfigError;
 use crate::emoji::Emoji;
+use crate::emoji_tables::EmojiTable;
 use crate::changes;
 
-/// A simple struct that maps code sequences to file hashes
-pub struct FileHashes(HashMap<Vec<u32>, Vec<u8>>);
+/// The first field of the record that contains the [HashAlgorithm] of a file
+const ALGORITHM_RECORD: &str = "#algorithm";
+
+/// A simple struct that maps code sequences to file hashes (of a [HashAlgorithm]).
+///
+/// The table can be updated from multiple threads (see [FileHashes::update_concurrent]), e.g. as
+/// soon as each emoji is prepared. The mutex is held while the table is written to a file,
+/// so concurrent writes don't interleave.
+pub struct FileHashes(RwLock<HashMap<Vec<u32>, Vec<u8>>>, HashAlgorithm, Mutex<()>);
+
+/// How the files are hashed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// The default; files without an algorithm (e.g. from older versions) use it as well
+    #[default]
+    Sha256,
+    /// The 128 bit variant of xxh3, which is a lot faster, but not cryptographically secure
+    Xxh3,
+}
+
+/// The hash of an emoji's source (and its additional data)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SourceHash(Vec<u8>);
 
 #[derive(Debug)]
 /// An error that can occur with change checking
@@ -47,6 +76,99 @@ pub enum CheckError {
     NoFileSpecified,
 }
 
+impl HashAlgorithm {
+    /// The name that is used for `--hash-algo` and in the files
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    /// The length of a hash in bytes
+    pub fn hash_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Xxh3 => 16,
+        }
+    }
+
+    fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            _ => Err(ConfigError::InvalidValue(String::from("hash_algo"), s.to_string()))
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Feeds the data to the hasher of a [HashAlgorithm]
+enum Hasher {
+    Sha256(Sha256),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    fn finish(self) -> SourceHash {
+        match self {
+            Hasher::Sha256(hasher) => SourceHash(hasher.result().to_vec()),
+            Hasher::Xxh3(hasher) => SourceHash(hasher.digest128().to_be_bytes().to_vec()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.input(buf),
+            Hasher::Xxh3(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SourceHash {
+    /// The bytes of the hash
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for SourceHash {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SourceHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl FileHashes {
     /// Parses an CSV file to a `FileHashes` table
     /// It assumes that there is **no** header.
@@ -67,92 +189,177 @@ impl FileHashes {
     }
 
 
+    /// The records of the hashes may be preceded by one with the algorithm (`#algorithm,xxh3`);
+    /// without it, they're SHA256 hashes.
+    /// Hashes that don't have the length of the algorithm's hashes are left out, so the emojis of
+    /// files with mixed algorithms are rendered again.
     fn from_csv_reader<R: io::Read>(reader: &mut csv::Reader<R>) -> changes::FileHashes {
+        let mut algorithm = HashAlgorithm::default();
         let records = reader.records();
         let entries: Vec<(Vec<u32>, Vec<u8>)> = records
             .filter(std::result::Result::is_ok)
             .map(std::result::Result::unwrap)
             .filter(|record| record.len() >= 2)
+            .filter(|record| if &record[0] == ALGORITHM_RECORD {
+                match record[1].parse() {
+                    Ok(parsed) => algorithm = parsed,
+                    Err(_) => warn!("Unknown hash algorithm: {}", &record[1])
+                }
+                false
+            } else {
+                true
+            })
             .map(|record| (parse_hex(&record[0]), hex::decode(&record[1])))
             .filter(|(_, hash)| hash.is_ok())
             .map(|(sequence, hash)| (sequence, hash.unwrap()))
             .collect();
         let mut table = HashMap::with_capacity(entries.len());
-        table.extend(entries);
-        FileHashes(table)
+        let expected_len = algorithm.hash_len();
+        let mismatched = entries.iter().filter(|(_, hash)| hash.len() != expected_len).count();
+        if mismatched > 0 {
+            warn!("{} hashes don't belong to the algorithm {}, they will be computed again", mismatched, algorithm);
+        }
+        table.extend(entries.into_iter().filter(|(_, hash)| hash.len() == expected_len));
+        FileHashes::from_table(table, algorithm)
+    }
+
+    fn from_table(table: HashMap<Vec<u32>, Vec<u8>>, algorithm: HashAlgorithm) -> FileHashes {
+        FileHashes(RwLock::new(table), algorithm, Mutex::new(()))
+    }
+
+    /// The algorithm of the hashes
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.1
+    }
+
+    /// Switches to another algorithm. If it's a different one, all hashes are removed, as they
+    /// can't be compared anymore (i.e. all emojis will be rendered again once).
+    pub fn use_algorithm(&mut self, algorithm: HashAlgorithm) {
+        if self.1 != algorithm {
+            let table = self.0.get_mut().unwrap();
+            if !table.is_empty() {
+                info!("Switching the hashes from {} to {}, all emojis will be rendered again", self.1, algorithm);
+            }
+            table.clear();
+            self.1 = algorithm;
+        }
     }
 
     /// Checks whether the hash of the file is still the same as the one in the table.
     pub fn check(&self, emoji: &Emoji) -> Result<bool, CheckError> {
-        if let Some(path) = &emoji.svg_path {
-            let mut hasher = Sha256::new();
-            let file = fs::File::open(path);
-            let hash = self.0.get(&emoji.sequence);
-
-            if let Some(hash) = hash {
-                match file {
-                    // To get consistent results, CRs will be ignored
-                    // (in order to get consistent line endings)
-                    // TODO: Maybe change this behavior in the future as it's messy and actually
-                    //       Only relevant in the tests.
-                    //       When used in production, line endings can actually be considered to
-                    //       stay the same, and even if not, re-rendering only impacts the
-                    //       performance, but not the correctness of the result.
-                    Ok(file) => match io::copy(&mut NoCrRead(file), &mut hasher) {
-                        Ok(_) => {
-                            let result = hasher.result();
-                            let result = result.as_slice();
-                            let hash = hash.as_slice();
-                            Ok(hash == result)
-                        }
-                        Err(error) => Err(Io(error)),
-                    },
-                    Err(error) => Err(Io(error)),
-                }
-            } else {
-                // If there is no entry, the hash can be assumed as different
-                Ok(false)
-            }
+        self.check_with(emoji, &[])
+    }
+
+    /// Checks whether the hash of the file and some additional data (e.g. settings that only
+    /// apply to this emoji) is still the same as the one in the table.
+    pub fn check_with(&self, emoji: &Emoji, extra: &[u8]) -> Result<bool, CheckError> {
+        if emoji.svg_path.is_none() {
+            return Err(NoFileSpecified);
+        }
+        if self.contains(emoji) {
+            let source = FileHashes::read_source(emoji)?;
+            Ok(self.check_bytes(emoji, &source, extra).0)
         } else {
-            Err(NoFileSpecified)
+            // If there is no entry, the hash can be assumed as different
+            Ok(false)
         }
     }
 
+    /// Like [FileHashes::check_with], but for the content of the file that has already been read
+    /// (see [FileHashes::read_source]).
+    /// The new hash is returned as well, so it doesn't need to be computed again.
+    pub fn check_bytes(&self, emoji: &Emoji, source: &[u8], extra: &[u8]) -> (bool, SourceHash) {
+        let result = FileHashes::hash_bytes_with(self.1, source, extra);
+        let unchanged = self.0.read().unwrap().get(&emoji.sequence)
+            .map(|hash| hash.as_slice() == result.as_slice())
+            .unwrap_or(false);
+        (unchanged, result)
+    }
+
     /// Replaces (or inserts) the hash for a given `Emoji`.
     pub fn update(
         &mut self,
         emoji: &Emoji,
         hash: &[u8],
     ) -> Option<Vec<u8>> {
-        self.0.insert(emoji.sequence.clone(), Vec::from(hash))
+        self.0.get_mut().unwrap().insert(emoji.sequence.clone(), Vec::from(hash))
+    }
+
+    /// Like [FileHashes::update], but through a shared reference, so the hashes can be recorded
+    /// by multiple threads at the same time.
+    pub fn update_concurrent(&self, emoji: &Emoji, hash: &[u8]) -> Option<Vec<u8>> {
+        self.0.write().unwrap().insert(emoji.sequence.clone(), Vec::from(hash))
+    }
+
+    /// The hash of an emoji (if there is one)
+    pub fn get<E: AsRef<[u32]>>(&self, emoji: E) -> Option<Vec<u8>> {
+        self.0.read().unwrap().get(emoji.as_ref()).cloned()
     }
 
     /// Computes the hash value of a single file.
     /// This is mostly useful for parallel implementations.
-    pub fn hash(emoji: &Emoji) -> Result<GenericArray<u8, <Sha256 as Digest>::OutputSize>, CheckError> {
-        if let Some(path) = &emoji.svg_path {
-            let mut hasher = Sha256::new();
-            let file = fs::File::open(path);
-            match file {
-                // To get consistent results, CRs will be ignored
-                // (in order to get consistent line endings)
-                // TODO: Maybe change this behavior in the future
-                Ok(file) => match io::copy(&mut NoCrRead(file), &mut hasher) {
-                    Ok(_) => Ok(hasher.result()),
-                    Err(error) => Err(Io(error))
-                },
-                Err(error) => Err(Io(error))
-            }
-        } else {
-            Err(NoFileSpecified)
+    pub fn hash(emoji: &Emoji) -> Result<SourceHash, CheckError> {
+        FileHashes::hash_with(emoji, &[])
+    }
+
+    /// Computes the hash value of a single file and some additional data.
+    /// Without additional data, this is the same as [FileHashes::hash].
+    pub fn hash_with(emoji: &Emoji, extra: &[u8]) -> Result<SourceHash, CheckError> {
+        let source = FileHashes::read_source(emoji)?;
+        Ok(FileHashes::hash_bytes(&source, extra))
+    }
+
+    /// Computes the hash value of the content of a file and some additional data, i.e. the same
+    /// as [FileHashes::hash_with] without reading the file.
+    pub fn hash_bytes(source: &[u8], extra: &[u8]) -> SourceHash {
+        FileHashes::hash_bytes_with(HashAlgorithm::default(), source, extra)
+    }
+
+    /// Like [FileHashes::hash_bytes], but with another algorithm
+    pub fn hash_bytes_with(algorithm: HashAlgorithm, source: &[u8], extra: &[u8]) -> SourceHash {
+        let mut hasher = algorithm.hasher();
+        // To get consistent results, CRs will be ignored
+        // (in order to get consistent line endings)
+        // TODO: Maybe change this behavior in the future as it's messy and actually
+        //       Only relevant in the tests.
+        //       When used in production, line endings can actually be considered to
+        //       stay the same, and even if not, re-rendering only impacts the
+        //       performance, but not the correctness of the result.
+        // Reading from a slice can't fail
+        io::copy(&mut NoCrRead(source), &mut hasher).unwrap();
+        hasher.write_all(extra).unwrap();
+        hasher.finish()
+    }
+
+    /// Reads the source file of an emoji.
+    /// Builders that need the content anyway (e.g. to render it) should read it only once with
+    /// this function and use [FileHashes::check_bytes] or [FileHashes::hash_bytes] with it.
+    pub fn read_source(emoji: &Emoji) -> Result<Vec<u8>, CheckError> {
+        match &emoji.svg_path {
+            Some(path) => {
+                #[cfg(test)]
+                SOURCE_READS.with(|reads| reads.set(reads.get() + 1));
+                fs::read(path).map_err(Io)
+            },
+            None => Err(NoFileSpecified)
         }
     }
 
     /// Saves the table to a CSV file.
     /// **Warning**: Any existing file with that name will be overwritten.
+    ///
+    /// The table is written to a temporary file next to it first, so a build that is interrupted
+    /// while writing keeps the previous file.
     pub fn write_to_path(&self, path: PathBuf) -> Result<(), Error> {
-        let mut writer = csv::Writer::from_path(path)?;
-        self.write_to_csv_writer(&mut writer)
+        let _writing = self.2.lock().unwrap();
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut writer = csv::Writer::from_path(&tmp_path)?;
+        self.write_to_csv_writer(&mut writer)?;
+        drop(writer);
+        fs::rename(tmp_path, path)?;
+        Ok(())
     }
 
 
@@ -163,8 +370,15 @@ impl FileHashes {
         self.write_to_csv_writer(&mut writer)
     }
 
+    /// The algorithm is only written if it's not the default, so SHA256 files stay readable by
+    /// older versions.
     fn write_to_csv_writer<W: Write>(&self, writer: &mut csv::Writer<W>) -> Result<(), Error> {
-        for entry in &self.0 {
+        if self.1 != HashAlgorithm::default() {
+            writer.write_record([ALGORITHM_RECORD, self.1.name()])?;
+        }
+        // The table isn't locked while it's written
+        let table = self.0.read().unwrap().clone();
+        for entry in &table {
             let sequence = entry.0.iter();
             let sequence: Vec<String> = sequence
                 .map(|codepoint| format!("{:x}", codepoint))
@@ -179,48 +393,142 @@ impl FileHashes {
 
     /// If a changelist is empty
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.0.read().unwrap().is_empty()
     }
 
     /// The length of a changelist
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.0.read().unwrap().len()
     }
 
     /// Checks whether an emoji occurs in a changelist
     pub fn contains<E: AsRef<[u32]>>(&self, emoji: E) -> bool {
-        self.0.contains_key(emoji.as_ref())
+        self.0.read().unwrap().contains_key(emoji.as_ref())
     }
 
     /// Create a new, empty changelist
     pub fn new() -> FileHashes {
         Self::default()
     }
-}
 
-impl Default for FileHashes {
-    fn default() -> Self {
-        FileHashes(HashMap::new())
+    /// Create a new, empty changelist for another algorithm
+    pub fn with_algorithm(algorithm: HashAlgorithm) -> FileHashes {
+        FileHashes::from_table(HashMap::new(), algorithm)
+    }
+
+    /// The hashes of the files of all emojis (emojis without a file are left out)
+    pub fn snapshot(emojis: &[&Emoji]) -> Result<FileHashes, CheckError> {
+        let mut hashes = FileHashes::new();
+        for emoji in emojis.iter().filter(|emoji| emoji.svg_path.is_some()) {
+            hashes.update(emoji, &FileHashes::hash(emoji)?);
+        }
+        Ok(hashes)
+    }
+
+    /// Compares the hashes with the ones of an earlier build.
+    /// Without an earlier build, all emojis are added.
+    pub fn changes_since(&self, previous: Option<&FileHashes>) -> Changelog {
+        let first_build = previous.is_none();
+        // A copy, as both might be the same table
+        let previous = previous.map(|previous| previous.0.read().unwrap().clone()).unwrap_or_default();
+        let current = self.0.read().unwrap();
+        let mut changelog = Changelog {
+            added: current.keys()
+                .filter(|sequence| !previous.contains_key(*sequence))
+                .cloned()
+                .collect(),
+            changed: current.iter()
+                .filter(|(sequence, hash)| previous.get(*sequence).map(|previous| previous != *hash).unwrap_or(false))
+                .map(|(sequence, _)| sequence.clone())
+                .collect(),
+            removed: previous.keys()
+                .filter(|sequence| !current.contains_key(*sequence))
+                .cloned()
+                .collect(),
+            first_build,
+        };
+        changelog.added.sort();
+        changelog.changed.sort();
+        changelog.removed.sort();
+        changelog
     }
 }
 
-impl<I: AsRef<[u32]>> Index<I> for FileHashes {
-    type Output = Vec<u8>;
+/// The emojis that have been added, changed (i.e. their file has a different hash) or removed
+/// since an earlier build
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changelog {
+    /// The emojis that are new in this build
+    pub added: Vec<Vec<u32>>,
+    /// The emojis whose files have changed
+    pub changed: Vec<Vec<u32>>,
+    /// The emojis that have been in the earlier build, but not in this one
+    pub removed: Vec<Vec<u32>>,
+    /// Whether there hasn't been an earlier build (so all emojis are added)
+    pub first_build: bool,
+}
+
+impl Changelog {
+    /// Whether nothing has changed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// A short summary like `2 new, 1 changed, 0 removed emojis`
+    pub fn summary(&self) -> String {
+        format!("{} new, {} changed, {} removed emojis", self.added.len(), self.changed.len(), self.removed.len())
+    }
 
-    fn index(&self, index: I) -> &Self::Output {
-        &self.0[index.as_ref()]
+    /// Writes the changelog as a Markdown document with the emojis and their names.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::changes::Changelog;
+    ///
+    /// let changelog = Changelog {
+    ///     added: vec![vec![0x1f914]],
+    ///     ..Changelog::default()
+    /// };
+    /// let mut markdown = Vec::new();
+    /// changelog.write_markdown(&mut markdown, None).unwrap();
+    /// assert!(String::from_utf8(markdown).unwrap().contains("- 🤔 [1F914] (`1f914`)"));
+    /// ```
+    pub fn write_markdown<W: Write>(&self, mut writer: W, table: Option<&EmojiTable>) -> io::Result<()> {
+        writeln!(writer, "# Changelog")?;
+        writeln!(writer)?;
+        writeln!(writer, "{}", self.summary())?;
+        let sections = [
+            ("New emojis", &self.added),
+            ("Changed emojis", &self.changed),
+            ("Removed emojis", &self.removed),
+        ];
+        for (title, sequences) in sections.iter().filter(|(_, sequences)| !sequences.is_empty()) {
+            writeln!(writer)?;
+            writeln!(writer, "## {} ({})", title, sequences.len())?;
+            writeln!(writer)?;
+            for sequence in sequences.iter() {
+                let hex = sequence.iter()
+                    .map(|codepoint| format!("{:x}", codepoint))
+                    .collect::<Vec<_>>()
+                    .join("_");
+                match Emoji::from_u32_sequence(sequence.clone(), table) {
+                    Ok(emoji) => writeln!(writer, "- {} (`{}`)", emoji, hex)?,
+                    Err(_) => writeln!(writer, "- `{}`", hex)?
+                }
+            }
+        }
+        Ok(())
     }
 }
 
-impl From<FileHashes> for HashMap<Vec<u32>, Vec<u8>> {
-    fn from(hashes: FileHashes) -> Self {
-        hashes.0
+impl Default for FileHashes {
+    fn default() -> Self {
+        FileHashes::with_algorithm(HashAlgorithm::default())
     }
 }
 
-impl AsRef<HashMap<Vec<u32>, Vec<u8>>> for FileHashes {
-    fn as_ref(&self) -> &HashMap<Vec<u32>, Vec<u8>> {
-        &self.0
+impl From<FileHashes> for HashMap<Vec<u32>, Vec<u8>> {
+    fn from(hashes: FileHashes) -> Self {
+        hashes.0.into_inner().unwrap()
     }
 }
 
@@ -234,6 +542,12 @@ fn parse_hex(sequence: &str) -> Vec<u32> {
         .collect()
 }
 
+#[cfg(test)]
+thread_local! {
+    /// How often [FileHashes::read_source] has been called on this thread
+    pub(crate) static SOURCE_READS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 /// A wrapper that discards all occurences of CR-characters (ASCII 0xD)
 struct NoCrRead<R: Read>(R);
 
@@ -291,4 +605,98 @@ fn test_nocr() {
     let read_bytes = cursor.read(&mut buf).unwrap();
     assert_eq!(read_bytes, 3);
     assert_eq!(buf, [0x41, 0xa, 0x42, 0x0]);
-}
\ No newline at end of file
+}
+#[test]
+fn test_hash_algorithms() {
+    let sha256 = FileHashes::hash_bytes_with(HashAlgorithm::Sha256, b"<svg/>", &[]);
+    let xxh3 = FileHashes::hash_bytes_with(HashAlgorithm::Xxh3, b"<svg/>", &[]);
+    assert_eq!(sha256, FileHashes::hash_bytes(b"<svg/>", &[]));
+    assert_eq!(sha256.len(), HashAlgorithm::Sha256.hash_len());
+    assert_eq!(xxh3.len(), HashAlgorithm::Xxh3.hash_len());
+    // CRs are ignored with both algorithms
+    assert_eq!(xxh3, FileHashes::hash_bytes_with(HashAlgorithm::Xxh3, b"<svg/>\r", &[]));
+    assert_ne!(xxh3, FileHashes::hash_bytes_with(HashAlgorithm::Xxh3, b"<svg/>", b"tweak"));
+
+    assert_eq!("XXH3".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Xxh3);
+    assert_eq!("sha256".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Sha256);
+    assert!("md5".parse::<HashAlgorithm>().is_err());
+}
+
+#[test]
+fn test_algorithm_header() {
+    let emoji = Emoji::from(vec![0x1f914]);
+    let mut hashes = FileHashes::with_algorithm(HashAlgorithm::Xxh3);
+    hashes.update(&emoji, &FileHashes::hash_bytes_with(HashAlgorithm::Xxh3, b"<svg/>", &[]));
+    let mut written = Vec::new();
+    hashes.write_to_writer(&mut written).unwrap();
+    assert!(String::from_utf8(written.clone()).unwrap().starts_with("#algorithm,xxh3\n"));
+
+    let read = FileHashes::from_reader(Cursor::new(&written)).unwrap();
+    assert_eq!(read.algorithm(), HashAlgorithm::Xxh3);
+    assert_eq!(read.len(), 1);
+    assert!(read.check_bytes(&emoji, b"<svg/>", &[]).0);
+
+    // Files without the header are SHA256, so the xxh3 hash is left out
+    let csv = format!("1f914,{}\n1f9a6,{}\n",
+                      hex::encode(FileHashes::hash_bytes(b"<svg/>", &[])),
+                      hex::encode(FileHashes::hash_bytes_with(HashAlgorithm::Xxh3, b"<svg/>", &[])));
+    let read = FileHashes::from_reader(Cursor::new(csv)).unwrap();
+    assert_eq!(read.algorithm(), HashAlgorithm::Sha256);
+    assert_eq!(read.len(), 1);
+    assert!(read.contains(vec![0x1f914]));
+
+    // The default algorithm isn't written
+    let mut written = Vec::new();
+    read.write_to_writer(&mut written).unwrap();
+    assert!(!String::from_utf8(written).unwrap().contains("#algorithm"));
+}
+
+#[test]
+fn test_switch_algorithm() {
+    let emoji = Emoji::from(vec![0x1f914]);
+    let mut hashes = FileHashes::new();
+    let (_, hash) = hashes.check_bytes(&emoji, b"<svg/>", &[]);
+    hashes.update(&emoji, &hash);
+    assert!(hashes.check_bytes(&emoji, b"<svg/>", &[]).0);
+
+    // The same algorithm keeps the hashes
+    hashes.use_algorithm(HashAlgorithm::Sha256);
+    assert_eq!(hashes.len(), 1);
+
+    // Switching means that everything is rendered again once
+    hashes.use_algorithm(HashAlgorithm::Xxh3);
+    assert!(hashes.is_empty());
+    let (unchanged, hash) = hashes.check_bytes(&emoji, b"<svg/>", &[]);
+    assert!(!unchanged);
+    assert_eq!(hash.len(), 16);
+    hashes.update(&emoji, &hash);
+    assert!(hashes.check_bytes(&emoji, b"<svg/>", &[]).0);
+}
+
+#[test]
+fn test_concurrent_updates() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hashes.csv");
+    let hashes = FileHashes::new();
+    let emojis = (0..400u32).map(|index| Emoji::from(vec![0xf0000 + index])).collect::<Vec<_>>();
+    // The threads write the table while the others are still updating it
+    rayon::scope(|scope| {
+        for chunk in emojis.chunks(100) {
+            let hashes = &hashes;
+            let path = &path;
+            scope.spawn(move |_| for emoji in chunk {
+                hashes.update_concurrent(emoji, &FileHashes::hash_bytes(&emoji.sequence[0].to_le_bytes(), &[]));
+                hashes.write_to_path(path.clone()).unwrap();
+            });
+        }
+    });
+    hashes.write_to_path(path.clone()).unwrap();
+
+    let csv = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(csv.lines().count(), emojis.len());
+    let read = FileHashes::from_path(&path).unwrap();
+    assert_eq!(read.len(), emojis.len());
+    for emoji in &emojis {
+        assert_eq!(read.get(emoji), hashes.get(emoji));
+    }
+}
@@ -0,0 +1,676 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Settings that can come from different sources (default values, configuration files,
+//! environment variables and the command line) and are merged by their precedence.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use clap::{App, ArgMatches};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use yaml_rust::{Yaml, YamlLoader};
+
+/// The prefix for environment variables, e.g. `EMOJI_BUILDER_OUTPUT_DIR` sets `output_dir`.
+pub const ENV_PREFIX: &str = "EMOJI_BUILDER_";
+
+/// The extension of the detached checksum of a pack (e.g. `pack.yaml.sha256`)
+pub const PACK_CHECKSUM_EXTENSION: &str = "sha256";
+/// The number of hex digits of a pack's fingerprint that are used as its cache key
+const PACK_CACHE_KEY_LENGTH: usize = 16;
+
+/// Where the value of a setting comes from.
+/// The order of the variants is the order of their precedence,
+/// i.e. a value from the command line overrides every other value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SettingSource {
+    /// A default value (e.g. from the CLI definition)
+    Default,
+    /// A configuration file
+    ConfigFile(PathBuf),
+    /// The configuration of an emoji pack
+    PackConfig(PathBuf),
+    /// An environment variable
+    Environment(String),
+    /// An argument on the command line
+    Cli,
+}
+
+/// A collection of settings with their source.
+/// Each setting is stored as a list of strings (most of them only have one value though).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings(BTreeMap<String, (Vec<String>, SettingSource)>);
+
+impl Settings {
+    /// Creates an empty set of settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a value, unless there already is a value from a source with a higher precedence.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::configs::{Settings, SettingSource};
+    ///
+    /// let mut settings = Settings::new();
+    /// settings.set("output", vec![String::from("cli.ttf")], SettingSource::Cli);
+    /// settings.set("output", vec![String::from("default.ttf")], SettingSource::Default);
+    ///
+    /// assert_eq!(settings.get("output"), Some("cli.ttf"));
+    /// assert_eq!(settings.source("output"), Some(&SettingSource::Cli));
+    /// ```
+    pub fn set(&mut self, key: &str, values: Vec<String>, source: SettingSource) {
+        let key = Self::normalize_key(key);
+        let overrides = match self.0.get(&key) {
+            Some((_, existing)) => source >= *existing,
+            None => true
+        };
+        if overrides {
+            self.0.insert(key, (values, source));
+        }
+    }
+
+    /// Adds the arguments with the given names from the command line.
+    /// Values that have not been given explicitly (i.e. clap's default values) are only added as
+    /// [SettingSource::Default], flags that are not present are not added at all.
+    pub fn load_matches(&mut self, matches: &ArgMatches, keys: &[&str]) {
+        for key in keys {
+            let source = if matches.occurrences_of(key) > 0 {
+                SettingSource::Cli
+            } else {
+                SettingSource::Default
+            };
+            // Flags that are present have an (empty) list of values, too
+            let values: Vec<String> = matches.values_of(key)
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect();
+            if !values.is_empty() {
+                self.set(key, values, source);
+            } else if matches.is_present(key) {
+                self.set(key, vec![String::from("true")], source);
+            }
+        }
+    }
+
+    /// Adds all variables that start with [ENV_PREFIX].
+    /// Lists are separated by commas.
+    /// Usually this will be called with `std::env::vars()`.
+    pub fn load_env<I: IntoIterator<Item=(String, String)>>(&mut self, vars: I) {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                let values = value.split(',')
+                    .map(|value| value.trim().to_owned())
+                    .filter(|value| !value.is_empty())
+                    .collect();
+                self.set(key, values, SettingSource::Environment(name.clone()));
+            }
+        }
+    }
+
+    /// Adds the settings from a YAML file with a flat mapping of keys to values or lists of values
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ConfigError> {
+        let content = std::fs::read_to_string(&path)?;
+        self.load_yaml(&content, SettingSource::ConfigFile(path.as_ref().to_path_buf()))
+    }
+
+    /// Adds the settings from a YAML string with a flat mapping of keys to values or lists of values
+    pub fn load_yaml(&mut self, content: &str, source: SettingSource) -> Result<(), ConfigError> {
+        let documents = YamlLoader::load_from_str(content)
+            .map_err(|err| ConfigError::Syntax(format!("{:?}", err)))?;
+        for document in documents {
+            let entries = match document.as_hash() {
+                Some(entries) => entries,
+                None => return Err(ConfigError::Syntax(String::from("Expected a mapping of settings")))
+            };
+            self.load_entries(entries, &source)?;
+        }
+        Ok(())
+    }
+
+    fn load_entries<'y, I: IntoIterator<Item=(&'y Yaml, &'y Yaml)>>(&mut self, entries: I, source: &SettingSource) -> Result<(), ConfigError> {
+        for (key, value) in entries {
+            let key = Self::yaml_to_string(key)
+                .ok_or_else(|| ConfigError::Syntax(format!("Invalid key: {:?}", key)))?;
+            let values = match value {
+                Yaml::Array(values) => values.iter()
+                    .map(|value| Self::yaml_to_string(value)
+                        .ok_or_else(|| ConfigError::InvalidValue(key.clone(), format!("{:?}", value))))
+                    .collect::<Result<Vec<_>, _>>()?,
+                value => vec![Self::yaml_to_string(value)
+                    .ok_or_else(|| ConfigError::InvalidValue(key.clone(), format!("{:?}", value)))?]
+            };
+            self.set(&key, values, source.clone());
+        }
+        Ok(())
+    }
+
+    /// Adds all settings from `other` (with their sources), keeping the precedence
+    pub fn merge(&mut self, other: Settings) {
+        for (key, (values, source)) in other.0 {
+            self.set(&key, values, source);
+        }
+    }
+
+    fn yaml_to_string(value: &Yaml) -> Option<String> {
+        match value {
+            Yaml::String(value) | Yaml::Real(value) => Some(value.clone()),
+            Yaml::Integer(value) => Some(value.to_string()),
+            Yaml::Boolean(value) => Some(value.to_string()),
+            _ => None
+        }
+    }
+
+    /// Returns the (first) value of a setting
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(&Self::normalize_key(key))
+            .and_then(|(values, _)| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Returns all values of a setting
+    pub fn get_list(&self, key: &str) -> Option<&[String]> {
+        self.0.get(&Self::normalize_key(key))
+            .map(|(values, _)| values.as_slice())
+    }
+
+    /// Returns a setting as a path.
+    /// Relative paths from a pack are relative to the pack's directory and must not leave it
+    /// (after resolving `..` and symbolic links).
+    /// # Examples
+    /// ```
+    /// use std::path::{Path, PathBuf};
+    /// use emoji_builder::configs::{Settings, SettingSource};
+    ///
+    /// let mut settings = Settings::new();
+    /// let source = SettingSource::PackConfig(PathBuf::from("packs/blobs.yaml"));
+    /// settings.load_yaml("{images: blobs/svg, tables: ../etc}", source).unwrap();
+    ///
+    /// assert_eq!(settings.get_path("images"), Ok(Some(PathBuf::from("packs/blobs/svg"))));
+    /// assert!(settings.get_path("tables").is_err());
+    /// assert_eq!(settings.get_path("flags"), Ok(None));
+    /// ```
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>, ConfigError> {
+        match self.0.get(&Self::normalize_key(key))
+            .and_then(|(values, source)| values.first().map(|value| (value, source))) {
+            Some((value, source)) => Self::confine_path(key, value, source).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    /// Returns all values of a setting as paths (see [Settings::get_path])
+    pub fn get_paths(&self, key: &str) -> Result<Vec<PathBuf>, ConfigError> {
+        match self.0.get(&Self::normalize_key(key)) {
+            Some((values, source)) => values.iter()
+                .map(|value| Self::confine_path(key, value, source))
+                .collect(),
+            None => Ok(vec![])
+        }
+    }
+
+    /// Checks that a path from a pack stays inside of it (other sources may point anywhere)
+    fn confine_path(key: &str, value: &str, source: &SettingSource) -> Result<PathBuf, ConfigError> {
+        let pack_dir = match source {
+            SettingSource::PackConfig(pack) => pack.parent().unwrap_or_else(|| Path::new("")),
+            _ => return Ok(PathBuf::from(value))
+        };
+        let path = pack_dir.join(value);
+        if Self::resolve_path(&path)?.starts_with(Self::resolve_path(pack_dir)?) {
+            Ok(path)
+        } else {
+            Err(ConfigError::PathOutsidePack(key.to_owned(), PathBuf::from(value)))
+        }
+    }
+
+    /// Canonicalizes a path one component after the other, so that the parts that don't exist
+    /// (yet) are resolved lexically
+    fn resolve_path(path: &Path) -> std::io::Result<PathBuf> {
+        let mut resolved = std::env::current_dir()?.canonicalize()?;
+        for component in path.components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => resolved.push(component),
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => {
+                    resolved.push(name);
+                    if let Ok(canonical) = resolved.canonicalize() {
+                        resolved = canonical;
+                    }
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Returns a setting as a boolean. Settings that aren't set are `false`.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::configs::{Settings, SettingSource};
+    ///
+    /// let mut settings = Settings::new();
+    /// settings.load_env(vec![
+    ///     (String::from("EMOJI_BUILDER_OFFLINE"), String::from("yes")),
+    ///     (String::from("EMOJI_BUILDER_NO_SEQUENCES"), String::from("maybe")),
+    /// ]);
+    ///
+    /// assert_eq!(settings.get_bool("offline"), Ok(true));
+    /// assert_eq!(settings.get_bool("waveflag"), Ok(false));
+    /// assert!(settings.get_bool("no_sequences").is_err());
+    /// ```
+    pub fn get_bool(&self, key: &str) -> Result<bool, ConfigError> {
+        match self.get(key) {
+            None => Ok(false),
+            Some(value) => match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" | "" => Ok(false),
+                _ => Err(ConfigError::InvalidValue(key.to_owned(), value.to_owned()))
+            }
+        }
+    }
+
+    /// Returns where the value of a setting comes from
+    pub fn source(&self, key: &str) -> Option<&SettingSource> {
+        self.0.get(&Self::normalize_key(key))
+            .map(|(_, source)| source)
+    }
+
+    /// Returns all settings with their values and sources in a human-readable format
+    pub fn dump(&self) -> String {
+        self.0.iter()
+            .map(|(key, (values, source))| format!("{} = {} (from {})", key, values.join(", "), source))
+            .join("\n")
+    }
+
+    /// Checks the settings from configuration files and environment variables (the other sources
+    /// are checked by `clap`) for keys that are not in `known` and for paths (given by
+    /// `path_keys`) that don't exist.
+    /// Unknown keys are usually typos, so they get the most similar known key as a suggestion.
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use emoji_builder::configs::{Settings, SettingSource};
+    ///
+    /// let mut settings = Settings::new();
+    /// let source = SettingSource::ConfigFile(PathBuf::from("config.yaml"));
+    /// settings.load_yaml("output_dr: ./fonts", source).unwrap();
+    ///
+    /// let issues = settings.validate(&["output", "output_dir"], &[]);
+    /// assert_eq!(issues.len(), 1);
+    /// assert_eq!(issues[0].suggestion.as_deref(), Some("output_dir"));
+    /// ```
+    pub fn validate(&self, known: &[&str], path_keys: &[&str]) -> Vec<SchemaIssue> {
+        let known: Vec<String> = known.iter().map(|key| Self::normalize_key(key)).collect();
+        let path_keys: Vec<String> = path_keys.iter().map(|key| Self::normalize_key(key)).collect();
+        let mut issues = vec![];
+        for (key, (values, source)) in &self.0 {
+            match source {
+                SettingSource::ConfigFile(_) | SettingSource::PackConfig(_) | SettingSource::Environment(_) => (),
+                SettingSource::Default | SettingSource::Cli => continue
+            }
+            if !known.contains(key) {
+                issues.push(SchemaIssue {
+                    key: key.clone(),
+                    source: source.clone(),
+                    message: String::from("unknown setting"),
+                    suggestion: Self::suggest(key, &known),
+                });
+            } else if path_keys.contains(key) {
+                // Paths from a pack are relative to its directory (see [Settings::get_path])
+                let dir = match source {
+                    SettingSource::PackConfig(pack) => pack.parent().unwrap_or_else(|| Path::new("")),
+                    _ => Path::new("")
+                };
+                for value in values.iter().filter(|value| !dir.join(value).exists()) {
+                    issues.push(SchemaIssue {
+                        key: key.clone(),
+                        source: source.clone(),
+                        message: format!("{} does not exist", value),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+        issues
+    }
+
+    /// The most similar known key, if it's similar enough
+    fn suggest(key: &str, known: &[String]) -> Option<String> {
+        let max_distance = (key.len() / 3).max(2);
+        known.iter()
+            .map(|known| (edit_distance(key, known), known))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known)| known.clone())
+    }
+
+    /// Keys are case-insensitive and dashes are the same as underscores
+    fn normalize_key(key: &str) -> String {
+        key.to_lowercase().replace('-', "_")
+    }
+}
+
+impl Display for SettingSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::ConfigFile(path) => write!(f, "config file {}", path.to_string_lossy()),
+            SettingSource::PackConfig(path) => write!(f, "pack config {}", path.to_string_lossy()),
+            SettingSource::Environment(name) => write!(f, "environment variable {}", name),
+            SettingSource::Cli => write!(f, "CLI"),
+        }
+    }
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A problem with a setting that has been found by [Settings::validate]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaIssue {
+    /// The (normalized) key of the setting
+    pub key: String,
+    pub source: SettingSource,
+    pub message: String,
+    /// A known key that was probably meant instead
+    pub suggestion: Option<String>,
+}
+
+impl Display for SchemaIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (from {}): {}", self.key, self.source, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{}`?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// The configuration of an emoji pack.
+/// It's a configuration file like the one for `--config`, which also declares the builder that
+/// builds the pack and the options for that builder:
+/// ```yaml
+/// images: ./svg
+/// output: MyEmoji.ttf
+/// builder:
+///   name: blobmoji
+///   options:
+///     render_only: true
+///     default_font: Comic Neue
+/// ```
+/// As YAML is a superset of JSON, the same can be written as a JSON file.
+/// If the builder doesn't need any options, `builder: blobmoji` is enough.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pack {
+    /// The general settings (with [SettingSource::PackConfig] as their source)
+    pub settings: Settings,
+    /// The name of the builder (i.e. of its subcommand)
+    pub builder: Option<String>,
+    /// The options for the builder with the names of its arguments as keys
+    pub builder_options: Settings,
+    /// The SHA256 hash of the configuration file (or of all layered ones), which builders
+    /// that have a `cache_key` use as their cache key (see [Pack::builder_matches])
+    pub fingerprint: Option<String>,
+}
+
+impl Pack {
+    /// Loads the configuration of a pack from a YAML or JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Pack, ConfigError> {
+        let content = std::fs::read_to_string(&path)?;
+        let mut pack = Self::from_yaml(&content, path.as_ref())?;
+        pack.fingerprint = Some(hex::encode(Sha256::digest(content.as_bytes())));
+        Ok(pack)
+    }
+
+    /// Loads the configuration of a pack after checking its SHA256 hash, so that a pack that has
+    /// been changed (e.g. after it has been downloaded) isn't used.
+    ///
+    /// The expected hash is either pinned with `sha256` (e.g. from `--pack-sha256`) or read from
+    /// the detached checksum next to the pack (`<pack>.sha256`, in the format of `sha256sum`).
+    /// A pinned hash takes precedence, as the checksum file might have been changed as well.
+    /// If there's neither, the pack is loaded without a check, like with [Pack::from_file].
+    pub fn from_file_verified<P: AsRef<Path>>(path: P, sha256: Option<&str>) -> Result<Pack, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read(path)?;
+        let expected = match sha256 {
+            Some(sha256) => Some(Self::parse_sha256(sha256)
+                .ok_or_else(|| ConfigError::InvalidValue(String::from("pack_sha256"), sha256.to_string()))?),
+            None => Self::read_checksum(path)?
+        };
+        let actual = hex::encode(Sha256::digest(&content));
+        if let Some(expected) = expected {
+            if actual != expected {
+                return Err(ConfigError::Checksum(path.to_path_buf(), expected, actual));
+            }
+        }
+        let content = String::from_utf8(content)
+            .map_err(|err| ConfigError::Syntax(format!("{:?}", err)))?;
+        let mut pack = Self::from_yaml(&content, path)?;
+        pack.fingerprint = Some(actual);
+        Ok(pack)
+    }
+
+    /// The hash from the detached checksum of a pack (if there is one)
+    fn read_checksum(path: &Path) -> Result<Option<String>, ConfigError> {
+        let mut checksum_file = path.as_os_str().to_os_string();
+        checksum_file.push(".");
+        checksum_file.push(PACK_CHECKSUM_EXTENSION);
+        let checksum_file = PathBuf::from(checksum_file);
+        if !checksum_file.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&checksum_file)?;
+        // sha256sum writes the hash, followed by the file name
+        let sha256 = content.split_whitespace().next().unwrap_or_default();
+        Self::parse_sha256(sha256)
+            .map(Some)
+            .ok_or_else(|| ConfigError::InvalidValue(checksum_file.to_string_lossy().into_owned(), sha256.to_string()))
+    }
+
+    /// A SHA256 hash as 64 hex digits (in lowercase)
+    fn parse_sha256(sha256: &str) -> Option<String> {
+        let sha256 = sha256.trim().to_lowercase();
+        if sha256.len() == 64 && sha256.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            Some(sha256)
+        } else {
+            None
+        }
+    }
+
+    /// Loads the configuration of a pack, `path` is only used as the source of its settings
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use emoji_builder::configs::{Pack, SettingSource};
+    ///
+    /// let pack = Pack::from_yaml(r#"{"output": "Blobmoji.ttf", "builder": "blobmoji"}"#, Path::new("pack.json")).unwrap();
+    ///
+    /// assert_eq!(pack.builder.as_deref(), Some("blobmoji"));
+    /// assert_eq!(pack.settings.get("output"), Some("Blobmoji.ttf"));
+    /// assert_eq!(pack.settings.source("output"), Some(&SettingSource::PackConfig(Path::new("pack.json").to_path_buf())));
+    /// ```
+    pub fn from_yaml(content: &str, path: &Path) -> Result<Pack, ConfigError> {
+        let documents = YamlLoader::load_from_str(content)
+            .map_err(|err| ConfigError::Syntax(format!("{:?}", err)))?;
+        let source = SettingSource::PackConfig(path.to_path_buf());
+        let builder_key = Yaml::String(String::from("builder"));
+        let mut pack = Pack::default();
+        for document in documents {
+            let entries = match document.as_hash() {
+                Some(entries) => entries,
+                None => return Err(ConfigError::Syntax(String::from("Expected a mapping of settings")))
+            };
+            match entries.get(&builder_key) {
+                None => (),
+                Some(Yaml::String(name)) => pack.builder = Some(name.clone()),
+                Some(Yaml::Hash(builder)) => {
+                    pack.builder = builder.get(&Yaml::String(String::from("name")))
+                        .and_then(|name| name.as_str())
+                        .map(String::from);
+                    match builder.get(&Yaml::String(String::from("options"))) {
+                        None => (),
+                        Some(Yaml::Hash(options)) => pack.builder_options.load_entries(options, &source)?,
+                        Some(options) => return Err(ConfigError::InvalidValue(String::from("builder.options"), format!("{:?}", options)))
+                    }
+                }
+                Some(builder) => return Err(ConfigError::InvalidValue(String::from("builder"), format!("{:?}", builder)))
+            }
+            pack.settings.load_entries(entries.iter().filter(|(key, _)| **key != builder_key), &source)?;
+        }
+        Ok(pack)
+    }
+
+    /// Layers several packs over each other (in the given order) to build them into one font:
+    /// the settings, the builder and its options of a later pack override the ones of the
+    /// earlier packs. The images are layered separately (see [PackLayer](crate::packs::PackLayer)).
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use emoji_builder::configs::Pack;
+    ///
+    /// let base = Pack::from_yaml("{images: ./base, output: Base.ttf, builder: blobmoji}", Path::new("base.yaml")).unwrap();
+    /// let extras = Pack::from_yaml("{images: ./extras, output: Extras.ttf}", Path::new("extras.yaml")).unwrap();
+    /// let layered = Pack::layered(&[base, extras]);
+    ///
+    /// assert_eq!(layered.settings.get("output"), Some("Extras.ttf"));
+    /// assert_eq!(layered.builder.as_deref(), Some("blobmoji"));
+    /// ```
+    pub fn layered(packs: &[Pack]) -> Pack {
+        let mut layered = Pack::default();
+        for pack in packs {
+            // All settings come from a pack, so the precedence of their sources doesn't matter
+            layered.settings.0.extend(pack.settings.0.clone());
+            layered.builder_options.0.extend(pack.builder_options.0.clone());
+            if pack.builder.is_some() {
+                layered.builder = pack.builder.clone();
+            }
+        }
+        let fingerprints = packs.iter().filter_map(|pack| pack.fingerprint.as_deref()).collect_vec();
+        layered.fingerprint = match fingerprints.as_slice() {
+            [] => None,
+            [fingerprint] => Some(fingerprint.to_string()),
+            fingerprints => Some(hex::encode(Sha256::digest(fingerprints.join("\n").as_bytes())))
+        };
+        layered
+    }
+
+    /// Parses the builder's options with the builder's subcommand `app`, as if they were given
+    /// on the command line.
+    /// Arguments that have actually been given on the command line (`overrides`) take precedence.
+    /// Options that the builder doesn't know are reported like unknown settings.
+    pub fn builder_matches<'a>(&self, app: App<'a, 'a>, overrides: Option<&ArgMatches<'a>>) -> Result<ArgMatches<'a>, ConfigError> {
+        // (name, the switch on the command line, whether it takes a value)
+        let known: Vec<(String, String, bool)> = app.p.flags.iter()
+            .map(|flag| (flag.b.name, flag.s.long, flag.s.short, false))
+            .chain(app.p.opts.iter().map(|opt| (opt.b.name, opt.s.long, opt.s.short, true)))
+            .filter_map(|(name, long, short, takes_value)| match (long, short) {
+                (Some(long), _) => Some((Settings::normalize_key(name), format!("--{}", long), takes_value)),
+                (None, Some(short)) => Some((Settings::normalize_key(name), format!("-{}", short), takes_value)),
+                (None, None) => None
+            })
+            .collect();
+        let known_names: Vec<String> = known.iter().map(|(name, _, _)| name.clone()).collect();
+
+        let mut arguments = vec![String::from(app.get_name())];
+        let mut issues = vec![];
+        for (key, (values, source)) in &self.builder_options.0 {
+            match known.iter().find(|(name, _, _)| name == key) {
+                Some((_, switch, true)) => arguments.extend(values.iter()
+                    .map(|value| format!("{}={}", switch, value))),
+                Some((_, switch, false)) => if self.builder_options.get_bool(key)? {
+                    arguments.push(switch.clone());
+                },
+                None => issues.push(SchemaIssue {
+                    key: key.clone(),
+                    source: source.clone(),
+                    message: format!("unknown option for {}", app.get_name()),
+                    suggestion: Settings::suggest(key, &known_names),
+                })
+            }
+        }
+        if !issues.is_empty() {
+            return Err(ConfigError::Schema(issues));
+        }
+        // Different packs (or versions of a pack) don't share their caches
+        if let Some(fingerprint) = &self.fingerprint {
+            let has_cache_key = known.iter().any(|(name, _, _)| name == "cache_key");
+            if has_cache_key && self.builder_options.get("cache_key").is_none() {
+                arguments.push(format!("--cache-key={}", &fingerprint[..PACK_CACHE_KEY_LENGTH.min(fingerprint.len())]));
+            }
+        }
+
+        let mut matches = app.get_matches_from_safe(arguments)
+            .map_err(|err| ConfigError::Syntax(err.message))?;
+        if let Some(overrides) = overrides {
+            matches.args.extend(overrides.args.iter()
+                .filter(|(name, _)| overrides.occurrences_of(name) > 0)
+                .map(|(name, arg)| (*name, arg.clone())));
+        }
+        Ok(matches)
+    }
+}
+
+/// An error that occurs while loading or reading settings
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Wrapper for [std::io::Error]
+    Io(std::io::Error),
+    /// The configuration file couldn't be parsed
+    Syntax(String),
+    /// A setting has a value that can't be used (setting, value)
+    InvalidValue(String, String),
+    /// Settings with unknown keys or missing paths (only if they are not just warnings)
+    Schema(Vec<SchemaIssue>),
+    /// A file doesn't have the expected SHA256 hash (file, expected, actual)
+    Checksum(PathBuf, String, String),
+    /// A path from a pack leads outside of its directory (setting, path)
+    PathOutsidePack(String, PathBuf),
+}
+
+impl PartialEq for ConfigError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ConfigError::Syntax(a), ConfigError::Syntax(b)) => a == b,
+            (ConfigError::InvalidValue(a, b), ConfigError::InvalidValue(c, d)) => a == c && b == d,
+            (ConfigError::Schema(a), ConfigError::Schema(b)) => a == b,
+            (ConfigError::Checksum(a, b, c), ConfigError::Checksum(d, e, f)) => a == d && b == e && c == f,
+            (ConfigError::PathOutsidePack(a, b), ConfigError::PathOutsidePack(c, d)) => a == c && b == d,
+            _ => false
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
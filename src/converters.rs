@@ -0,0 +1,233 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Converts emoji images in other formats (e.g. PDF or EPS) to SVG before they are rendered.
+//!
+//! The converted files are kept in a cache directory (named by the hash of their source's path)
+//! together with the hash of their source and the converter, so they are only converted again if
+//! either of them has changed.
+//! As the builders only see the converted SVG file, their change tracking works as usual.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::emoji::Emoji;
+
+/// Converts an image to an SVG file
+pub trait Converter: Send + Sync {
+    /// Converts `input` and writes the SVG file to `output`
+    fn convert(&self, input: &Path, output: &Path) -> Result<(), ConversionError>;
+
+    /// A short description that's used in error messages (e.g. the command line)
+    fn describe(&self) -> String;
+}
+
+/// A converter that runs an external command like `pdf2svg {in} {out}`.
+///
+/// The command is split at whitespace (there's no shell involved) and `{in}` and `{out}` are
+/// replaced by the paths of the source and the SVG file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandConverter {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandConverter {
+    /// Parses a command line with `{in}` and `{out}` placeholders
+    pub fn new(command: &str) -> Result<CommandConverter, ConversionError> {
+        let mut parts = command.split_whitespace().map(String::from);
+        match parts.next() {
+            Some(program) => Ok(CommandConverter {
+                program,
+                args: parts.collect(),
+            }),
+            None => Err(ConversionError::InvalidSpec(command.to_string()))
+        }
+    }
+}
+
+impl Converter for CommandConverter {
+    fn convert(&self, input: &Path, output: &Path) -> Result<(), ConversionError> {
+        let args = self.args.iter()
+            .map(|arg| arg.replace("{in}", &input.to_string_lossy())
+                .replace("{out}", &output.to_string_lossy()))
+            .collect_vec();
+        let result = Command::new(&self.program).args(&args).output()?;
+        if result.status.success() && output.is_file() {
+            Ok(())
+        } else {
+            Err(ConversionError::Failed {
+                command: std::iter::once(&self.program).chain(args.iter()).join(" "),
+                status: result.status.code(),
+                output: String::from_utf8_lossy(&result.stdout).into_owned()
+                    + &String::from_utf8_lossy(&result.stderr)
+            })
+        }
+    }
+
+    fn describe(&self) -> String {
+        std::iter::once(&self.program).chain(self.args.iter()).join(" ")
+    }
+}
+
+/// The converters for different file extensions and the directory where the converted files
+/// are kept
+pub struct Converters {
+    converters: HashMap<String, Box<dyn Converter>>,
+    cache_dir: PathBuf,
+}
+
+impl Converters {
+    /// Creates an empty set of converters that store their SVG files in `cache_dir`
+    pub fn new(cache_dir: PathBuf) -> Converters {
+        Converters {
+            converters: HashMap::new(),
+            cache_dir,
+        }
+    }
+
+    /// Parses converter commands like `pdf=pdf2svg {in} {out}` (see [CommandConverter])
+    pub fn from_specs<S: AsRef<str>>(specs: &[S], cache_dir: PathBuf) -> Result<Converters, ConversionError> {
+        let mut converters = Converters::new(cache_dir);
+        for spec in specs {
+            let spec = spec.as_ref();
+            let mut parts = spec.splitn(2, '=');
+            match (parts.next().map(str::trim), parts.next()) {
+                (Some(extension), Some(command)) if !extension.is_empty() =>
+                    converters.add(extension, Box::new(CommandConverter::new(command)
+                        .map_err(|_| ConversionError::InvalidSpec(spec.to_string()))?)),
+                _ => return Err(ConversionError::InvalidSpec(spec.to_string()))
+            }
+        }
+        Ok(converters)
+    }
+
+    /// Uses a converter for files with the given extension (without the dot)
+    pub fn add(&mut self, extension: &str, converter: Box<dyn Converter>) {
+        self.converters.insert(extension.trim_start_matches('.').to_lowercase(), converter);
+    }
+
+    /// Whether there are no converters at all
+    pub fn is_empty(&self) -> bool {
+        self.converters.is_empty()
+    }
+
+    /// Whether the file has to be converted
+    pub fn converts(&self, path: &Path) -> bool {
+        self.converter(path).is_some()
+    }
+
+    fn converter(&self, path: &Path) -> Option<&dyn Converter> {
+        path.extension()
+            .and_then(|extension| self.converters.get(&extension.to_string_lossy().to_lowercase()))
+            .map(|converter| converter.as_ref())
+    }
+
+    /// Converts the image of an emoji (if there is a converter for it) and uses the SVG file
+    /// instead.
+    /// An existing SVG file is reused if its source has the same hash as when it was converted
+    /// with the same converter.
+    pub fn convert(&self, emoji: &mut Emoji) -> Result<(), ConversionError> {
+        let source = match &emoji.svg_path {
+            Some(source) => source.clone(),
+            None => return Ok(())
+        };
+        let converter = match self.converter(&source) {
+            Some(converter) => converter,
+            None => return Ok(())
+        };
+        // Files with the same name (but in other directories or with another extension) must not
+        // share their converted file
+        let source_path = source.canonicalize().unwrap_or_else(|_| source.clone());
+        let name = hex::encode(Sha256::digest(source_path.to_string_lossy().as_bytes()));
+        let output = self.cache_dir.join(format!("{}.svg", name));
+        let hash_file = self.cache_dir.join(format!("{}.svg.sha256", name));
+
+        // Another command has to convert the file again
+        let mut hasher = Sha256::new();
+        hasher.input(converter.describe().as_bytes());
+        hasher.input(b"\0");
+        hasher.input(std::fs::read(&source)?);
+        let hash = hex::encode(hasher.result());
+        let unchanged = output.is_file() && std::fs::read_to_string(&hash_file)
+            .map(|cached| cached.trim() == hash)
+            .unwrap_or(false);
+        if unchanged {
+            debug!("Using the converted file {:?} for {}", output, emoji);
+        } else {
+            info!("Converting {:?} with `{}`", source, converter.describe());
+            create_dir_all(&self.cache_dir)?;
+            // A stale hash must not outlive a failed conversion
+            if hash_file.exists() {
+                std::fs::remove_file(&hash_file)?;
+            }
+            converter.convert(&source, &output)?;
+            std::fs::write(&hash_file, &hash)?;
+        }
+        emoji.set_path(output);
+        Ok(())
+    }
+}
+
+/// An error that occurred while converting an image
+#[derive(Debug)]
+pub enum ConversionError {
+    /// A converter specification is not of the form `ext=command {in} {out}`
+    InvalidSpec(String),
+    /// The converter couldn't be run or the files couldn't be read/written
+    IoError(std::io::Error),
+    /// The command failed or didn't write the SVG file
+    Failed {
+        /// The command line that has been run
+        command: String,
+        /// The exit code (if there is one)
+        status: Option<i32>,
+        /// What the command wrote to stdout and stderr
+        output: String,
+    },
+}
+
+impl From<std::io::Error> for ConversionError {
+    fn from(error: std::io::Error) -> Self {
+        ConversionError::IoError(error)
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidSpec(spec) => write!(f, "Invalid converter (expected ext=command {{in}} {{out}}): {}", spec),
+            ConversionError::IoError(error) => write!(f, "{}", error),
+            ConversionError::Failed { command, status, output } => {
+                match status {
+                    Some(0) => write!(f, "`{}` didn't write the SVG file", command)?,
+                    Some(status) => write!(f, "`{}` failed with exit code {}", command, status)?,
+                    None => write!(f, "`{}` has been terminated", command)?
+                };
+                if !output.trim().is_empty() {
+                    write!(f, ":\n{}", output.trim_end())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
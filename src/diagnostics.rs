@@ -0,0 +1,366 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Converts emoji images in other formats (e.g. PDF or EPS) to SVG before they are rendered.
+//! Collects the problems that are found while building (in addition to logging them), so they
+//! can be written to a JSON file, e.g. to annotate the affected files in CI.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::configs::ConfigError;
+use crate::emoji::Emoji;
+
+/// How bad a problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Where a problem has been found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// Parsing the emoji tables
+    Table,
+    /// Loading the emojis (e.g. invalid file names)
+    Emoji,
+    /// Rendering a single emoji
+    Render,
+    /// Comparing the emojis with the emoji table
+    Validation,
+    /// Building the font from the rendered emojis
+    Font,
+    /// Collecting the licenses of the emojis
+    License,
+}
+
+impl Category {
+    /// All categories
+    pub const ALL: [Category; 6] = [
+        Category::Table,
+        Category::Emoji,
+        Category::Render,
+        Category::Validation,
+        Category::Font,
+        Category::License,
+    ];
+
+    /// The name that is used for `--deny`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Table => "table-parse",
+            Category::Emoji => "emoji-parse",
+            Category::Render => "render",
+            Category::Validation => "validation",
+            Category::Font => "font-step",
+            Category::License => "license",
+        }
+    }
+}
+
+impl FromStr for Category {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        Category::ALL.iter()
+            .find(|category| category.name() == name)
+            .copied()
+            .ok_or_else(|| ConfigError::InvalidValue(String::from("deny"), s.to_string()))
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The categories of problems that make the build fail (`--deny`), e.g. in CI
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeniedCategories(Vec<Category>);
+
+impl DeniedCategories {
+    /// Parses comma-separated lists of category names (or `all`)
+    /// # Examples
+    /// ```
+    /// use emoji_builder::diagnostics::{Category, DeniedCategories};
+    ///
+    /// let denied = DeniedCategories::parse(&["render,validation"]).unwrap();
+    /// assert!(denied.contains(Category::Render));
+    /// assert!(!denied.contains(Category::Table));
+    /// assert!(DeniedCategories::parse(&["all"]).unwrap().contains(Category::Table));
+    /// ```
+    pub fn parse<S: AsRef<str>>(values: &[S]) -> Result<DeniedCategories, ConfigError> {
+        let mut categories = Vec::new();
+        for name in values.iter().flat_map(|value| value.as_ref().split(',')) {
+            if name.trim().eq_ignore_ascii_case("all") {
+                categories.extend_from_slice(&Category::ALL);
+            } else if !name.trim().is_empty() {
+                categories.push(name.parse()?);
+            }
+        }
+        categories.sort();
+        categories.dedup();
+        Ok(DeniedCategories(categories))
+    }
+
+    /// Whether no category is denied
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether problems of this category make the build fail
+    pub fn contains(&self, category: Category) -> bool {
+        self.0.contains(&category)
+    }
+}
+
+/// A single problem
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub category: Category,
+    /// The codepoints of the affected emoji (like in `emoji-test.txt`, e.g. `1F44B 1F3FB`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<String>,
+    /// The affected file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The pack that the problem comes from if several packs are built together
+    /// (see [packs](crate::packs))
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// A problem that doesn't belong to an emoji or a file (yet)
+    pub fn new<S: Into<String>>(severity: Severity, category: Category, message: S) -> Diagnostic {
+        Diagnostic {
+            severity,
+            category,
+            sequence: None,
+            path: None,
+            pack: None,
+            message: message.into(),
+        }
+    }
+
+    /// A warning (see [Diagnostic::new])
+    pub fn warning<S: Into<String>>(category: Category, message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, category, message)
+    }
+
+    /// An error (see [Diagnostic::new])
+    pub fn error<S: Into<String>>(category: Category, message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Error, category, message)
+    }
+
+    /// Sets the sequence and (if there is one) the file of the emoji
+    pub fn with_emoji(self, emoji: &Emoji) -> Diagnostic {
+        let diagnostic = self.with_sequence(&emoji.sequence);
+        match &emoji.svg_path {
+            Some(path) => diagnostic.with_path(path.clone()),
+            None => diagnostic
+        }
+    }
+
+    /// Sets the sequence of the affected emoji
+    pub fn with_sequence(mut self, sequence: &[u32]) -> Diagnostic {
+        self.sequence = Some(sequence.iter()
+            .map(|codepoint| format!("{:X}", codepoint))
+            .join(" "));
+        self
+    }
+
+    /// Sets the affected file
+    pub fn with_path(mut self, path: PathBuf) -> Diagnostic {
+        self.path = Some(path);
+        self
+    }
+
+    /// Sets the pack that the problem comes from
+    pub fn with_pack(mut self, pack: &str) -> Diagnostic {
+        self.pack = Some(pack.to_string());
+        self
+    }
+}
+
+/// Where the problems are collected.
+///
+/// It's a cheap handle that can be cloned and passed around; all clones collect into the same
+/// list. The default sink doesn't collect anything.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsSink(Option<Arc<Mutex<Vec<Diagnostic>>>>);
+
+impl DiagnosticsSink {
+    /// A sink that collects the problems
+    pub fn new() -> DiagnosticsSink {
+        DiagnosticsSink(Some(Arc::new(Mutex::new(Vec::new()))))
+    }
+
+    /// Whether the problems are collected at all
+    pub fn is_collecting(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Adds a problem (it should be logged as well)
+    pub fn report(&self, diagnostic: Diagnostic) {
+        if let Some(diagnostics) = &self.0 {
+            if let Ok(mut diagnostics) = diagnostics.lock() {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    /// All problems that have been reported so far (in the order in which they were reported)
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.0.as_ref()
+            .and_then(|diagnostics| diagnostics.lock().ok().map(|diagnostics| diagnostics.clone()))
+            .unwrap_or_default()
+    }
+
+    /// Changes the problems that have been reported so far
+    pub fn update<F: FnMut(&mut Diagnostic)>(&self, update: F) {
+        if let Some(diagnostics) = &self.0 {
+            if let Ok(mut diagnostics) = diagnostics.lock() {
+                diagnostics.iter_mut().for_each(update);
+            }
+        }
+    }
+
+    /// Writes the problems as a JSON array
+    pub fn write_json<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.diagnostics())
+    }
+
+    /// Checks whether any problems of the denied categories (regardless of their severity)
+    /// have been reported.
+    /// If there are any, they are returned as a summary that's grouped by their category.
+    pub fn check_denied(&self, denied: &DeniedCategories) -> Result<(), String> {
+        let mut by_category: BTreeMap<Category, Vec<Diagnostic>> = BTreeMap::new();
+        for diagnostic in self.diagnostics() {
+            if denied.contains(diagnostic.category) {
+                by_category.entry(diagnostic.category).or_default().push(diagnostic);
+            }
+        }
+        if by_category.is_empty() {
+            return Ok(());
+        }
+
+        let count: usize = by_category.values().map(Vec::len).sum();
+        let mut summary = format!("{} problem(s) of denied categories:", count);
+        for (category, diagnostics) in by_category {
+            summary.push_str(&format!("\n{} ({}):", category, diagnostics.len()));
+            for diagnostic in diagnostics {
+                let location = diagnostic.sequence.clone()
+                    .or_else(|| diagnostic.path.as_ref().map(|path| path.to_string_lossy().into_owned()));
+                let pack = diagnostic.pack.as_ref()
+                    .map(|pack| format!("[{}] ", pack))
+                    .unwrap_or_default();
+                match location {
+                    Some(location) => summary.push_str(&format!("\n  {}{}: {}", pack, location, diagnostic.message)),
+                    None => summary.push_str(&format!("\n  {}{}", pack, diagnostic.message))
+                }
+            }
+        }
+        Err(summary)
+    }
+}
+
+/// The sink isn't part of the data of whatever it's attached to (e.g. an emoji table),
+/// so it's ignored when comparing them
+impl PartialEq for DiagnosticsSink {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DiagnosticsSink {}
+
+#[test]
+fn test_write_json() {
+    let sink = DiagnosticsSink::new();
+    let clone = sink.clone();
+    clone.report(Diagnostic::error(Category::Render, "Couldn't render it")
+        .with_emoji(&Emoji::from_path(PathBuf::from("emoji_u1f44b_1f3fb.svg"), None, false).unwrap()));
+    sink.report(Diagnostic::warning(Category::Table, "Malformed line"));
+    // The default sink ignores everything
+    DiagnosticsSink::default().report(Diagnostic::warning(Category::Table, "Ignored"));
+    assert!(!DiagnosticsSink::default().is_collecting());
+
+    let mut json = Vec::new();
+    sink.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json, serde_json::json!([
+        {
+            "severity": "error",
+            "category": "render",
+            "sequence": "1F44B 1F3FB",
+            "path": "emoji_u1f44b_1f3fb.svg",
+            "message": "Couldn't render it"
+        },
+        {
+            "severity": "warning",
+            "category": "table",
+            "message": "Malformed line"
+        }
+    ]));
+}
+
+#[test]
+fn test_deny() {
+    let problems = [
+        Diagnostic::warning(Category::Table, "Malformed line in emoji-table: 1F600 ;"),
+        Diagnostic::error(Category::Emoji, "Invalid sequence").with_path(PathBuf::from("emoji_uzzzz.svg")),
+        Diagnostic::error(Category::Render, "Couldn't render 🤔").with_sequence(&[0x1f914]),
+        Diagnostic::warning(Category::Validation, "Missing emoji: 😀").with_sequence(&[0x1f600]),
+        Diagnostic::error(Category::Font, "An error occured while building the emoji set"),
+        Diagnostic::error(Category::License, "Conflicting licenses"),
+    ];
+    for problem in &problems {
+        let sink = DiagnosticsSink::new();
+        sink.report(problem.clone());
+        for category in &Category::ALL {
+            let denied = DeniedCategories::parse(&[category.name()]).unwrap();
+            assert_eq!(sink.check_denied(&denied).is_err(), *category == problem.category,
+                       "{:?} with --deny {}", problem, category);
+        }
+        assert!(sink.check_denied(&DeniedCategories::parse(&["all"]).unwrap()).is_err());
+        assert!(sink.check_denied(&DeniedCategories::default()).is_ok());
+    }
+
+    // The summary is grouped by category
+    let sink = DiagnosticsSink::new();
+    problems.iter().cloned().for_each(|problem| sink.report(problem));
+    sink.report(Diagnostic::warning(Category::Validation, "Missing emoji: 😃").with_sequence(&[0x1f603]));
+    let denied = DeniedCategories::parse(&["validation, render", "render"]).unwrap();
+    assert_eq!(sink.check_denied(&denied), Err(String::from("3 problem(s) of denied categories:\n\
+        render (1):\n  1F914: Couldn't render 🤔\n\
+        validation (2):\n  1F600: Missing emoji: 😀\n  1F603: Missing emoji: 😃")));
+
+    assert!(DeniedCategories::parse(&["render,warnings"]).is_err());
+}
@@ -15,22 +15,53 @@
  */
 //! The main data structs for single emojis.
 
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::RangeInclusive;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use itertools::Itertools;
+use rayon::prelude::*;
 use regex::{CaptureMatches, Regex};
 
 use crate::emoji::EmojiError::NotAFileName;
 use crate::emoji::EmojiKind::{EmojiFlagSequence, EmojiKeycapSequence};
-use crate::emoji_tables::{EmojiTable, EmojiTableError};
+use crate::attribution::SIDECAR_EXTENSION;
+use crate::diagnostics::{Category, Diagnostic, DiagnosticsSink};
+use crate::emoji_tables::{EmojiTable, EmojiTableError, Name};
 use crate::emoji_tables::EmojiTableError::KeyNotFound;
+use crate::ignore_files::IgnoreRules;
+use crate::short_ids;
 use std::cmp::Ordering;
 
+/// Creates an [Emoji] from a string with the emoji itself, i.e. its sequence consists of the
+/// string's characters (including variation selectors and Zero Width Joiners).
+/// This is mostly useful for tests, as `emoji!("🏳️‍🌈")` is easier to read than the codepoints.
+/// # Examples
+/// ```
+/// use emoji_builder::emoji;
+///
+/// assert_eq!(emoji!("🤔").sequence, vec![0x1f914]);
+/// assert_eq!(emoji!("🏳️‍🌈").sequence, vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+/// assert_eq!(emoji!("#️⃣").sequence, vec![0x23, 0xfe0f, 0x20e3]);
+/// assert_eq!(emoji!("👩🏽‍🔬"), emoji_builder::emoji::Emoji::from(vec![0x1f469, 0x1f3fd, 0x200d, 0x1f52c]));
+/// ```
+#[macro_export]
+macro_rules! emoji {
+    ($emoji:expr) => {
+        $crate::emoji::Emoji::from($emoji.chars()
+            .map(|character| character as u32)
+            .collect::<Vec<u32>>())
+    };
+}
+
+/// Separates the variant of an emoji from its name in file names (see [Emoji::variant])
+pub const VARIANT_SEPARATOR: char = '~';
+
 /// A struct that holds information for one particular emoji (which might also be a sequence).
 #[derive(Debug, Eq, Clone)]
 pub struct Emoji {
@@ -51,7 +82,10 @@ pub struct Emoji {
 }
 
 /// An internal representation for the different emoji types represented in the Unicode® Tables
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+///
+/// Kinds are compared (and hashed) in their normalized form (see [EmojiKind::normalized]), so
+/// `Other` kinds from different sources match regardless of their spelling.
+#[derive(Debug, Clone)]
 pub enum EmojiKind {
     /// A regular emoji
     Emoji,
@@ -99,10 +133,10 @@ impl Emoji {
     /// ```
     /// use emoji_builder::emoji::{Emoji, EmojiKind};
     /// use std::collections::HashMap;
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let mut table = EmojiTable::new();
-    /// table.insert(vec![0x1f914 as u32], (vec![EmojiKind::Emoji], Some(String::from("Thinking Face"))));
+    /// table.insert(vec![0x1f914 as u32], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("Thinking Face"));
     ///
     /// let thinking = Emoji::from_sequence("1f914.png", Some(&table)).unwrap();
     ///
@@ -160,6 +194,44 @@ impl Emoji {
         }
     }
 
+    /// Creates an emoji from a single line of an `emoji-test.txt`-like file
+    /// (see [EmojiTable::parse_test_data_line]), e.g. for test fixtures.
+    ///
+    /// The emoji gets the name from the line. Components (like skin tone modifiers) are of the
+    /// kind [EmojiKind::EmojiComponent], the kinds of other emojis are guessed from their
+    /// sequence (see [Emoji::guess_kinds]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::{Emoji, EmojiKind};
+    ///
+    /// let line = "1F3F3 FE0F 200D 1F308 ; fully-qualified # 🏳️‍🌈 E4.0 rainbow flag";
+    /// let rainbow_flag = Emoji::from_test_line(line).unwrap();
+    ///
+    /// assert_eq!(rainbow_flag, Emoji {
+    ///     sequence: vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308],
+    ///     name: Some(String::from("rainbow flag")),
+    ///     kinds: Some(vec![EmojiKind::EmojiZwjSequence]),
+    ///     svg_path: None
+    /// });
+    ///
+    /// let medium_skin_tone = Emoji::from_test_line("1F3FD ; component # 🏽 E1.0 medium skin tone").unwrap();
+    /// assert_eq!(medium_skin_tone.kinds, Some(vec![EmojiKind::EmojiComponent]));
+    ///
+    /// assert!(Emoji::from_test_line("# subgroup: face-smiling").is_err());
+    /// ```
+    pub fn from_test_line(line: &str) -> Result<Emoji, EmojiError> {
+        let parsed = EmojiTable::parse_test_data_line(line.trim())
+            .ok_or_else(|| EmojiError::NoValidCodepointsFound(line.to_string()))?;
+        let mut emoji = Emoji::from_u32_sequence(parsed.codepoints, None)?;
+        emoji.name = Some(parsed.name.to_string());
+        emoji.kinds = if parsed.status == "component" {
+            Some(vec![EmojiKind::EmojiComponent])
+        } else {
+            emoji.guess_kinds()
+        };
+        Ok(emoji)
+    }
+
     const FLAG_OFFSET: u32 = 0x1f185;
     const REGIONAL_OFFSET: u32 = 0xe0000;
     const CANCEL_TAG: u32 = 0xe007f;
@@ -227,8 +299,8 @@ impl Emoji {
                 .collect();
             let mut emoji = Emoji::from_u32_sequence(codepoints, table);
             if let Ok(emoji) = &mut emoji {
-                if let Some(kind) = &mut emoji.kinds {
-                    kind.push(EmojiKind::EmojiFlagSequence);
+                if let Some(kinds) = &mut emoji.kinds {
+                    EmojiKind::insert_sorted(kinds, EmojiKind::EmojiFlagSequence);
                 }
             };
             emoji
@@ -255,7 +327,8 @@ impl Emoji {
 
     /// Creates an emoji data object from a given file path.
     /// **Note:** it will _not_ use anything inside the file.
-    /// It also does _not_ check whether the file exists
+    /// It also does _not_ check whether the file exists.
+    /// File names that are not valid UTF-8 are converted lossily (with a warning).
     /// # Examples
     /// ```
     /// use std::path::{Path, PathBuf};
@@ -299,64 +372,347 @@ impl Emoji {
         table: Option<&EmojiTable>,
         flag: bool
     ) -> Result<Emoji, EmojiError> {
-        let name = file.file_stem();
-        if let Some(name) = name {
-            if let Some(name) = name.to_str() {
-                let mut emoji = if flag {
-                    Emoji::from_flag(name, table)
+        let name = match file.file_stem() {
+            Some(name) => name.to_string_lossy(),
+            None => return Err(NotAFileName(file))
+        };
+        if let Cow::Owned(name) = &name {
+            warn!("File name is not valid UTF-8: {:?} (using {})", file, name);
+        }
+        let (name, _) = Self::split_variant(name.as_ref());
+        let mut emoji = if flag {
+            Emoji::from_flag(name, table)
+        } else {
+            Self::from_name_or_sequence(name, table)
+        };
+        if let Ok(emoji) = &mut emoji {
+            emoji.set_path(file);
+        }
+        emoji
+    }
+
+    /// Splits the stem of a file name into the name of the emoji and its variant
+    /// (e.g. `1f600~blob` into `1f600` and `blob`, see [Emoji::variant])
+    fn split_variant(stem: &str) -> (&str, Option<&str>) {
+        match stem.find(VARIANT_SEPARATOR) {
+            Some(index) => {
+                let variant = &stem[index + VARIANT_SEPARATOR.len_utf8()..];
+                (&stem[..index], Some(variant).filter(|variant| !variant.is_empty()))
+            },
+            None => (stem, None)
+        }
+    }
+
+    /// The variant of the emoji's file, i.e. the suffix after a `~` in its name
+    /// (`1f600~blob.svg` is the `blob` variant of `1f600`).
+    /// Files without a suffix are the default variant (see [Emoji::select_variant]).
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// let blob = Emoji::from_path(PathBuf::from("emoji_u1f600~blob.svg"), None, false).unwrap();
+    /// assert_eq!(blob.sequence, vec![0x1f600]);
+    /// assert_eq!(blob.variant(), Some(String::from("blob")));
+    ///
+    /// let default = Emoji::from_path(PathBuf::from("emoji_u1f600.svg"), None, false).unwrap();
+    /// assert_eq!(default.variant(), None);
+    /// ```
+    pub fn variant(&self) -> Option<String> {
+        let stem = self.svg_path.as_ref()?.file_stem()?.to_string_lossy();
+        Self::split_variant(&stem).1.map(String::from)
+    }
+
+    /// Finds an emoji by some user input, which is tried as (in this order):
+    /// 1. the path of an existing file or a file name ending in `.svg` (see [Emoji::from_path]),
+    /// 2. the name of an emoji or of its base emoji with skin tones and/or a gender
+    ///    (if there is a table),
+    /// 3. a codepoint sequence (see [Emoji::from_sequence]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::Emoji;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x1f914], EmojiTableEntry::new(vec![]).with_name("thinking face"));
+    /// table.insert_lookup_name("thinking face", vec![0x1f914]);
+    ///
+    /// assert_eq!(Emoji::resolve("Thinking Face", Some(&table)).unwrap().sequence, vec![0x1f914]);
+    /// assert_eq!(Emoji::resolve("U+1F914", None).unwrap().sequence, vec![0x1f914]);
+    /// assert_eq!(Emoji::resolve("1f914.svg", None).unwrap().svg_path.unwrap().to_str(), Some("1f914.svg"));
+    /// ```
+    pub fn resolve(input: &str, table: Option<&EmojiTable>) -> Result<Emoji, EmojiError> {
+        let path = Path::new(input);
+        let svg = path.extension()
+            .map(|extension| extension.to_string_lossy().eq_ignore_ascii_case("svg"))
+            .unwrap_or(false);
+        if path.is_file() || svg {
+            Self::from_path(path.to_path_buf(), table, false)
+        } else {
+            Self::from_name_or_sequence(input, table)
+        }
+    }
+
+    /// First tries to find the emoji by its name (or the name of its base emoji),
+    /// then it's parsed as a sequence
+    fn from_name_or_sequence(name: &str, table: Option<&EmojiTable>) -> Result<Emoji, EmojiError> {
+        match table {
+            Some(table) => match Self::from_name(name, table).or_else(|_| Self::from_base_name(name, table)) {
+                Ok(emoji) => Ok(emoji),
+                Err(err) => if let EmojiError::NoValidCodepointsFound(_) = err {
+                    debug!("{} is not a recognized emoji name", name);
+                    // Now try to parse it as a sequence
+                    Self::from_sequence(name, Some(table))
                 } else {
-                    // First, try to find the emoji by its name, then by its sequence
-                    match table {
-                        Some(table) => match Self::from_name(name, table) {
-                            Ok(emoji) => Ok(emoji),
-                            Err(err) => if let EmojiError::NoValidCodepointsFound(_) = err {
-                                debug!("{} is not a recognized emoji name", name);
-                                // Now try to parse it as a sequence
-                                Self::from_sequence(name, Some(table))
-                            } else {
-                                // If it was something else than a failed lookup, pass the error
-                                Err(err)
-                            }
-                        },
-                        // In this case, we have no other choice but to interpret it as a sequence
-                        None => Self::from_sequence(name, None)
-                    }
-                };
-                if let Ok(emoji) = &mut emoji {
-                    emoji.set_path(file);
+                    // If it was something else than a failed lookup, pass the error
+                    Err(err)
                 }
-                return emoji;
+            },
+            // In this case, we have no other choice but to interpret it as a sequence
+            None => Self::from_sequence(name, None)
+        }
+    }
+
+    /// Creates the emojis for all files in a directory (see [Emoji::from_path]).
+    /// Everything that isn't a regular file (e.g. subdirectories or sockets) is skipped and
+    /// files that can't be parsed are logged and skipped as well.
+    /// # Errors
+    /// If the directory (or one of its entries) can't be read
+    pub fn from_dir<P: AsRef<Path>>(
+        dir: P,
+        table: Option<&EmojiTable>,
+        flag: bool
+    ) -> std::io::Result<Vec<Emoji>> {
+        Emoji::from_dir_with_diagnostics(dir, table, flag, &DiagnosticsSink::default())
+    }
+
+    /// Like [Emoji::from_dir], but files that can't be parsed are reported to `diagnostics` as well
+    pub fn from_dir_with_diagnostics<P: AsRef<Path>>(
+        dir: P,
+        table: Option<&EmojiTable>,
+        flag: bool,
+        diagnostics: &DiagnosticsSink
+    ) -> std::io::Result<Vec<Emoji>> {
+        Ok(Emoji::scan_dir(dir, table, flag)?.complete(table, diagnostics))
+    }
+
+    /// Reads a directory like [Emoji::from_dir], but keeps the files that can't be parsed
+    /// (instead of reporting them), so the directory can be read while the table is still loading.
+    /// Once the table is there, [ScannedDir::complete] adds what's missing.
+    ///
+    /// The files that are ignored in the directory (see [IgnoreRules]) are skipped.
+    /// # Errors
+    /// If the directory (or one of its entries) can't be read
+    pub fn scan_dir<P: AsRef<Path>>(
+        dir: P,
+        table: Option<&EmojiTable>,
+        flag: bool
+    ) -> std::io::Result<ScannedDir> {
+        Emoji::scan_dir_with_ignore(dir, table, flag, true)
+    }
+
+    /// Like [Emoji::scan_dir], but the ignored files are only skipped if `ignore` is set
+    /// (i.e. without `--no-ignore`)
+    pub fn scan_dir_with_ignore<P: AsRef<Path>>(
+        dir: P,
+        table: Option<&EmojiTable>,
+        flag: bool,
+        ignore: bool
+    ) -> std::io::Result<ScannedDir> {
+        let dir = dir.as_ref();
+        let rules = if ignore {
+            IgnoreRules::for_dir(dir)
+        } else {
+            IgnoreRules::disabled()
+        };
+        let (ignored, paths): (Vec<_>, Vec<_>) = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?
+            .into_iter()
+            .partition(|path| rules.is_ignored(path));
+        for path in &ignored {
+            debug!("Skipping {:?} as it is ignored", path);
+        }
+        let results: Vec<_> = paths.into_par_iter()
+            .filter(|path| if !path.is_file() {
+                debug!("Skipping {:?} as it is not a file", path);
+                false
+            } else if path.extension().unwrap_or_default() == SIDECAR_EXTENSION {
+                debug!("Skipping {:?} as it is a license file", path);
+                false
+            } else {
+                true
+            })
+            .map(|path| (path.clone(), Emoji::from_path(path, table, flag)))
+            .collect();
+        let mut scanned = ScannedDir {
+            emojis: Vec::with_capacity(results.len()),
+            failed: vec![],
+            ignored: ignored.len(),
+            flag,
+            with_table: table.is_some(),
+        };
+        for (path, emoji) in results {
+            match emoji {
+                Ok(emoji) => scanned.emojis.push(emoji),
+                Err(err) => scanned.failed.push((path, err))
+            }
+        }
+        Ok(scanned)
+    }
+
+    /// Sets the name and the kinds from the table if they haven't been set yet
+    /// (e.g. because the emoji has been created before the table was available).
+    /// Returns whether the table contains this emoji.
+    pub fn backfill(&mut self, table: &EmojiTable) -> bool {
+        let name = self.name.is_some() || self.set_name(table).is_ok();
+        let kinds = self.kinds.is_some() || self.set_kind(table).is_ok();
+        name && kinds
+    }
+
+    /// Keeps only one emoji per sequence if there are multiple files for it
+    /// (e.g. `1f914.svg` and a pre-rendered `1f914.png`, possibly in different directories).
+    ///
+    /// SVG files are preferred over files in any other format (which are usually raster images),
+    /// unless `prefer_raster` is set. Files of the same kind are ordered by their path, so the
+    /// result doesn't depend on the order in which the directories have been read.
+    /// The files that aren't used are logged and reported to `diagnostics`.
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use emoji_builder::diagnostics::DiagnosticsSink;
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// let emojis = vec![
+    ///     Emoji::from_path(PathBuf::from("1f914.png"), None, false).unwrap(),
+    ///     Emoji::from_path(PathBuf::from("1f914.svg"), None, false).unwrap(),
+    /// ];
+    ///
+    /// let svg = Emoji::deduplicate_sources(emojis.clone(), false, &DiagnosticsSink::default());
+    /// assert_eq!(svg.len(), 1);
+    /// assert_eq!(svg[0].svg_path, Some(PathBuf::from("1f914.svg")));
+    ///
+    /// let png = Emoji::deduplicate_sources(emojis, true, &DiagnosticsSink::default());
+    /// assert_eq!(png[0].svg_path, Some(PathBuf::from("1f914.png")));
+    /// ```
+    pub fn deduplicate_sources(emojis: Vec<Emoji>, prefer_raster: bool, diagnostics: &DiagnosticsSink) -> Vec<Emoji> {
+        let mut unique: Vec<Emoji> = Vec::with_capacity(emojis.len());
+        let mut indices: HashMap<Vec<u32>, usize> = HashMap::with_capacity(emojis.len());
+        for emoji in emojis {
+            let index = match indices.get(&emoji.sequence) {
+                Some(index) => *index,
+                None => {
+                    indices.insert(emoji.sequence.clone(), unique.len());
+                    unique.push(emoji);
+                    continue;
+                }
+            };
+            let shadowed = if emoji.source_order(prefer_raster) < unique[index].source_order(prefer_raster) {
+                std::mem::replace(&mut unique[index], emoji)
+            } else {
+                emoji
+            };
+            let message = format!("{:?} is not used, {:?} has the same sequence",
+                                  shadowed.svg_path.clone().unwrap_or_default(),
+                                  unique[index].svg_path.clone().unwrap_or_default());
+            warn!("{}", message);
+            diagnostics.report(Diagnostic::warning(Category::Emoji, message).with_emoji(&shadowed));
+        }
+        unique
+    }
+
+    /// Chooses the files of a variant (see [Emoji::variant]): if there's a file of the variant for
+    /// an emoji, it replaces the default file, otherwise the default file is used.
+    /// Files of other variants are left out, as well as all variants if `variant` is `None`.
+    ///
+    /// If none of the files has the variant, it's probably a typo, so it's logged and reported to
+    /// `diagnostics`.
+    pub fn select_variant(emojis: Vec<Emoji>, variant: Option<&str>, diagnostics: &DiagnosticsSink) -> Vec<Emoji> {
+        let variants: Vec<Option<String>> = emojis.iter().map(Emoji::variant).collect();
+        if variants.iter().all(Option::is_none) && variant.is_none() {
+            return emojis;
+        }
+        let selected: HashSet<&Vec<u32>> = emojis.iter()
+            .zip(&variants)
+            .filter(|(_, file_variant)| file_variant.is_some() && file_variant.as_deref() == variant)
+            .map(|(emoji, _)| &emoji.sequence)
+            .collect();
+        if let Some(variant) = variant {
+            if selected.is_empty() {
+                let message = format!("There are no files of the variant {}, the default files are used", variant);
+                warn!("{}", message);
+                diagnostics.report(Diagnostic::warning(Category::Emoji, message));
+            } else {
+                info!("Using the variant {} for {} emoji(s)", variant, selected.len());
             }
         }
-        Err(NotAFileName(file.to_path_buf()))
+        let keep: Vec<bool> = emojis.iter()
+            .zip(&variants)
+            .map(|(emoji, file_variant)| match file_variant {
+                Some(_) => file_variant.as_deref() == variant,
+                None => !selected.contains(&emoji.sequence)
+            })
+            .collect();
+        emojis.into_iter()
+            .zip(keep)
+            .filter_map(|(emoji, keep)| if keep {
+                Some(emoji)
+            } else {
+                debug!("Skipping {:?} as its variant is not used", emoji.svg_path.clone().unwrap_or_default());
+                None
+            })
+            .collect()
+    }
+
+    /// The order in which the files of emojis with the same sequence are preferred
+    /// (see [Emoji::deduplicate_sources])
+    fn source_order(&self, prefer_raster: bool) -> (bool, Option<&Path>) {
+        let svg = self.svg_path.as_ref()
+            .and_then(|path| path.extension())
+            .map(|extension| extension.to_string_lossy().eq_ignore_ascii_case("svg"))
+            .unwrap_or(false);
+        (svg == prefer_raster, self.svg_path.as_deref())
     }
 
     fn from_name(name: &str, table: &EmojiTable) -> Result<Emoji, EmojiError> {
         match table.get_by_name(name) {
-            Some((sequence, (kinds, _))) => Ok(Emoji {
+            Some((sequence, entry)) => Ok(Emoji {
                 sequence,
-                name: Some(name.to_string()),
-                kinds: Some(kinds.clone()),
+                name: Some(entry.name().map_or_else(|| name.to_string(), Name::to_string)),
+                kinds: Some(entry.kinds().to_vec()),
                 svg_path: None,
             }),
             None => Err(EmojiError::NoValidCodepointsFound(name.to_owned()))
         }
     }
 
+    /// Looks up the base emoji of a name with skin tones and/or a gender
+    /// (see [EmojiTable::get_base_by_name]) and adds them to its sequence.
+    fn from_base_name(name: &str, table: &EmojiTable) -> Result<Emoji, EmojiError> {
+        match table.get_base_by_name(name) {
+            Some((base, _, modifiers)) if !modifiers.is_empty() => {
+                let sequence = modifiers.apply(&base);
+                debug!("Synthesized {:X?} for {} from its base emoji", sequence, name);
+                Self::from_u32_sequence(sequence, Some(table))
+            },
+            _ => Err(EmojiError::NoValidCodepointsFound(name.to_owned()))
+        }
+    }
+
     /// Performs a lookup in the given `EmojiTable`
     /// and assigns the proper kind attribute to this `Emoji`.
     /// # Example
     /// ```
     /// use std::collections::HashMap;
     /// use emoji_builder::emoji::{EmojiKind, Emoji};
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let mut table = EmojiTable::new();
     /// let sequence = vec![0x1f914];
     /// let kind = vec![EmojiKind::Emoji];
     /// let name = String::from("Thinking Face");
     ///
-    /// table.insert(sequence.clone(), (kind.clone(), Some(name.clone())));
+    /// table.insert(sequence.clone(), EmojiTableEntry::new(kind.clone()).with_name(name.clone()));
     ///
     /// let mut emoji = Emoji::from(sequence.clone());
     /// emoji.set_kind(&table);
@@ -370,9 +726,9 @@ impl Emoji {
     /// ```
     pub fn set_kind(&mut self, table: &EmojiTable) -> Result<(), EmojiTableError> {
         let seq = &self.sequence;
-        match &table.get(seq) {
-            Some((kind, _)) => {
-                self.kinds = Some(kind.clone());
+        match table.get(seq) {
+            Some(entry) => {
+                self.kinds = Some(entry.kinds().to_vec());
                 Ok(())
             }
             None => Err(KeyNotFound(seq.clone())),
@@ -448,10 +804,19 @@ impl Emoji {
     ///
     /// assert_eq!(kind, Some(vec![EmojiKeycapSequence, EmojiSequence]));
     /// ```
+    ///
+    /// Sequences in the Private Use Area have no structure that could be guessed:
+    /// ```
+    /// use emoji_builder::emoji::{Emoji, EmojiKind};
+    ///
+    /// let emoji = Emoji::from(vec![0xf0000, 0x200d, 0xf0001]);
+    ///
+    /// assert_eq!(emoji.guess_kinds(), Some(vec![EmojiKind::Emoji]));
+    /// ```
     pub fn guess_kinds(&self) -> Option<Vec<EmojiKind>> {
         if self.sequence.is_empty() {
             None
-        } else if self.sequence.len() == 1 {
+        } else if self.sequence.len() == 1 || self.is_private_use() {
             Some(vec![EmojiKind::Emoji])
         } else {
             let flag = self.is_flag();
@@ -478,14 +843,14 @@ impl Emoji {
     /// ```
     /// use std::collections::HashMap;
     /// use emoji_builder::emoji::{EmojiKind, Emoji};
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let mut table = EmojiTable::new();
     /// let sequence = vec![0x1f914];
     /// let kind = vec![EmojiKind::Emoji];
     /// let name = String::from("Thinking Face");
     ///
-    /// table.insert(sequence.clone(), (kind.clone(), Some(name.clone())));
+    /// table.insert(sequence.clone(), EmojiTableEntry::new(kind.clone()).with_name(name.clone()));
     ///
     /// let mut emoji = Emoji::from(sequence.clone());
     /// emoji.set_name(&table);
@@ -499,15 +864,33 @@ impl Emoji {
     /// ```
     pub fn set_name(&mut self, table: &EmojiTable) -> Result<(), EmojiTableError> {
         let seq = &self.sequence;
-        match &table.get(seq) {
-            Some((_, name)) => {
-                self.name = name.clone();
+        match table.get(seq) {
+            Some(entry) => {
+                self.name = entry.name().map(Name::to_string);
                 Ok(())
             }
             None => Err(KeyNotFound(seq.clone())),
         }
     }
 
+    /// Adds missing and removes redundant `U+FE0F`s from the sequence, so it's spelled like the
+    /// fully-qualified emoji (see [EmojiTable::canonical_sequence])
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::{Emoji, EmojiKind};
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x2764], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("red heart"));
+    ///
+    /// let mut heart = Emoji::from_sequence("2764.svg", None).unwrap();
+    /// heart.canonicalize(&table);
+    /// assert_eq!(heart.sequence, vec![0x2764, 0xfe0f]);
+    /// ```
+    pub fn canonicalize(&mut self, table: &EmojiTable) {
+        self.sequence = table.canonical_sequence(&self.sequence);
+    }
+
     /// Assigns a given path to the Emoji
     pub fn set_path(&mut self, path: PathBuf) {
         self.svg_path = Some(path);
@@ -603,6 +986,99 @@ impl Emoji {
             || self.is_subdiv_flag()
     }
 
+    const PRIVATE_USE_AREA: RangeInclusive<u32> = 0xe000..=0xf8ff;
+    const SUPPLEMENTARY_PRIVATE_USE_AREA_A: RangeInclusive<u32> = 0xf0000..=0xffffd;
+    const SUPPLEMENTARY_PRIVATE_USE_AREA_B: RangeInclusive<u32> = 0x100000..=0x10fffd;
+
+    /// A short, URL-safe ID of this emoji that doesn't depend on its spelling with or without
+    /// `U+FE0F` (see [crate::short_ids]).
+    /// Within an emoji table, [EmojiTable::short_id] should be used instead, as it avoids
+    /// collisions.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// let id = Emoji::from(vec![0x2764, 0xfe0f]).short_id();
+    /// assert_eq!(id.len(), 8);
+    /// assert_eq!(id, Emoji::from(vec![0x2764]).short_id());
+    /// ```
+    pub fn short_id(&self) -> String {
+        short_ids::short_id(&self.sequence, short_ids::SHORT_ID_LENGTH)
+    }
+
+    /// Checks whether all codepoints of this emoji are in one of the Private Use Areas
+    /// (ignoring joiners and variation selectors)
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// assert!(Emoji::from(vec![0xf0000]).is_private_use());
+    /// assert!(Emoji::from(vec![0xe000, 0x200d, 0x10fffd]).is_private_use());
+    /// assert!(!Emoji::from(vec![0x1f914]).is_private_use());
+    /// assert!(!Emoji::from(vec![0x200d]).is_private_use());
+    /// ```
+    pub fn is_private_use(&self) -> bool {
+        let is_private_use = |codepoint: &u32| Self::PRIVATE_USE_AREA.contains(codepoint)
+            || Self::SUPPLEMENTARY_PRIVATE_USE_AREA_A.contains(codepoint)
+            || Self::SUPPLEMENTARY_PRIVATE_USE_AREA_B.contains(codepoint);
+        self.sequence.iter().any(is_private_use)
+            && self.sequence.iter()
+            .all(|codepoint| is_private_use(codepoint) || [0x200d, 0xfe0e, 0xfe0f].contains(codepoint))
+    }
+
+    /// The highest Unicode codepoint
+    const MAX_CODEPOINT: u32 = 0x10ffff;
+    /// The longest sequence that is accepted by [Emoji::validate_sequence].
+    /// The longest RGI emoji (kissing couples with skin tones) consist of 10 codepoints.
+    pub const MAX_SEQUENCE_LENGTH: usize = 10;
+    const ZWJ: u32 = 0x200d;
+    const VS16: u32 = 0xfe0f;
+
+    /// Checks that the sequence can actually be built into a font, i.e. the codepoints are valid,
+    /// it's not longer than [Emoji::MAX_SEQUENCE_LENGTH], it neither starts nor ends with a
+    /// Zero Width Joiner and `U+FE0F` isn't repeated.
+    ///
+    /// These are most likely typos in file names, which would otherwise only be noticed when
+    /// building the font.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::{Emoji, SequenceError};
+    ///
+    /// assert!(Emoji::from(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]).validate_sequence().is_ok());
+    /// assert_eq!(
+    ///     Emoji::from(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f30800]).validate_sequence(),
+    ///     Err(SequenceError::InvalidCodepoint(0x1f30800))
+    /// );
+    /// ```
+    pub fn validate_sequence(&self) -> Result<(), SequenceError> {
+        self.validate_sequence_with(Self::MAX_SEQUENCE_LENGTH)
+    }
+
+    /// Like [Emoji::validate_sequence], but with a custom maximum length
+    pub fn validate_sequence_with(&self, max_length: usize) -> Result<(), SequenceError> {
+        let sequence = &self.sequence;
+        if let Some(codepoint) = sequence.iter().find(|codepoint| **codepoint > Self::MAX_CODEPOINT) {
+            return Err(SequenceError::InvalidCodepoint(*codepoint));
+        }
+        if sequence.len() > max_length {
+            return Err(SequenceError::TooLong {
+                length: sequence.len(),
+                max_length
+            });
+        }
+        if sequence.first() == Some(&Self::ZWJ) {
+            return Err(SequenceError::LeadingZwj);
+        }
+        if sequence.last() == Some(&Self::ZWJ) {
+            return Err(SequenceError::TrailingZwj);
+        }
+        if let Some(position) = sequence.windows(2)
+            .position(|pair| pair == [Self::VS16, Self::VS16]) {
+            return Err(SequenceError::RepeatedVs16(position + 1));
+        }
+        Ok(())
+    }
+
     /// Checks whether this is a country's flag (e.g. DE, EU, etc.)
     pub fn is_country_flag(&self) -> bool {
         !self.sequence.is_empty()
@@ -750,10 +1226,14 @@ impl From<UnknownEmojiKind> for EmojiKind {
 }
 
 impl Display for Emoji {
-    /// Tries to show the appropriate (if possible human-understandable) name for this emoji.
+    /// Shows the emoji itself followed by the appropriate (if possible human-understandable) name.
     /// If the name attribute is not `None`, it will output that one.
     /// If not it will either output the flag sequence (e.g. `Flag EU`) or the code sequence
     /// in square brackets (e.g. `[1F3F3-FE0F-200D-1F308]`).
+    /// The emoji itself is left out if the sequence contains codepoints that aren't characters.
+    ///
+    /// The alternate form (`{:#}`) only outputs the code sequence in square brackets,
+    /// e.g. for output that is parsed again.
     /// # Examples
     /// ```
     ///
@@ -761,7 +1241,8 @@ impl Display for Emoji {
     ///
     /// let thinking = Emoji::from_u32_sequence(vec![0x1f914], None).unwrap();
     ///
-    /// assert_eq!("[1F914]", format!("{}", thinking));
+    /// assert_eq!("🤔 [1F914]", format!("{}", thinking));
+    /// assert_eq!("[1F914]", format!("{:#}", thinking));
     /// ```
     ///
     /// ```
@@ -770,7 +1251,8 @@ impl Display for Emoji {
     ///
     /// let rainbow = Emoji::from_u32_sequence(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308], None).unwrap();
     ///
-    /// assert_eq!("[1F3F3-FE0F-200D-1F308]", format!("{}", rainbow));
+    /// assert_eq!("🏳️‍🌈 [1F3F3-FE0F-200D-1F308]", format!("{}", rainbow));
+    /// assert_eq!("[1F3F3-FE0F-200D-1F308]", format!("{:#}", rainbow));
     /// ```
     ///
     /// ```
@@ -778,7 +1260,8 @@ impl Display for Emoji {
     ///
     /// let nrw = Emoji::from_flag("de-nw", None).unwrap();
     ///
-    /// assert_eq!("Flag DE-NW", format!("{}", nrw));
+    /// assert_eq!("🏴\u{e0064}\u{e0065}\u{e006e}\u{e0077}\u{e007f} Flag DE-NW", format!("{}", nrw));
+    /// assert_eq!("[1F3F4-E0064-E0065-E006E-E0077-E007F]", format!("{:#}", nrw));
     /// ```
     ///
     /// ```
@@ -787,17 +1270,37 @@ impl Display for Emoji {
     /// let mut party = Emoji::from_u32_sequence(vec![0x1f973], None).unwrap();
     /// party.name = Some(String::from("Party face"));
     ///
-    /// assert_eq!("Party face", format!("{}", party));
+    /// assert_eq!("🥳 Party face", format!("{}", party));
+    /// assert_eq!("[1F973]", format!("{:#}", party));
+    /// ```
+    ///
+    /// ```
+    /// use emoji_builder::emoji::Emoji;
+    ///
+    /// // A surrogate isn't a character
+    /// let invalid = Emoji::from(vec![0xd83e]);
+    ///
+    /// assert_eq!("[D83E]", format!("{}", invalid));
     /// ```
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let sequence = format!("[{}]", self.sequence.iter()
+            .map(|codepoint| format!("{:X}", codepoint))
+            .join("-"));
+        if f.alternate() {
+            return write!(f, "{}", sequence);
+        }
+        let literal: Option<String> = self.sequence.iter()
+            .map(|codepoint| char::from_u32(*codepoint))
+            .collect();
+        if let Some(literal) = literal.filter(|literal| !literal.is_empty()) {
+            write!(f, "{} ", literal)?;
+        }
         if let Some(name) = &self.name {
             write!(f, "{}", name)
         } else if let Some(name) = self.get_flag_name() {
             write!(f, "Flag {}", name)
         } else {
-            write!(f, "[{}]", self.sequence.iter()
-                .map(|codepoint| format!("{:X}", codepoint))
-                .join("-"))
+            write!(f, "{}", sequence)
         }
     }
 }
@@ -819,15 +1322,147 @@ impl ToString for EmojiKind {
     }
 }
 
+impl EmojiKind {
+    /// Turns `Other` kinds into the kind they're spelling (e.g. `Other("emoji presentation")`
+    /// becomes `EmojiPresentation`) or into lowercase with spaces (just like [EmojiKind::from_str]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    ///
+    /// let kind = EmojiKind::Other(String::from("Extended_Pictographic"));
+    /// assert_eq!(kind.normalized().to_string(), "extended_pictographic");
+    /// assert_eq!(EmojiKind::Other(String::from("Emoji_Presentation")).normalized().to_string(), "Emoji_Presentation");
+    /// ```
+    pub fn normalized(&self) -> EmojiKind {
+        match self {
+            EmojiKind::Other(kind) => kind.parse().unwrap_or_else(EmojiKind::from),
+            kind => kind.clone()
+        }
+    }
+
+    /// What the kinds are compared by
+    fn sort_key(&self) -> String {
+        self.normalized().to_string()
+    }
+
+    /// Adds a kind to kinds that are sorted and free of duplicates (unless it's already there).
+    /// Returns whether it has been added.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    ///
+    /// let mut kinds = vec![];
+    /// assert!(EmojiKind::insert_sorted(&mut kinds, EmojiKind::EmojiPresentation));
+    /// assert!(EmojiKind::insert_sorted(&mut kinds, EmojiKind::Emoji));
+    /// assert!(!EmojiKind::insert_sorted(&mut kinds, EmojiKind::Other(String::from("emoji"))));
+    /// assert_eq!(kinds, vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]);
+    /// ```
+    pub fn insert_sorted(kinds: &mut Vec<EmojiKind>, kind: EmojiKind) -> bool {
+        match kinds.binary_search(&kind) {
+            Ok(_) => false,
+            Err(index) => {
+                kinds.insert(index, kind);
+                true
+            }
+        }
+    }
+
+    /// Sorts the kinds and removes duplicates, e.g. for kinds that haven't been added with
+    /// [EmojiKind::insert_sorted] (like the ones of older tables)
+    pub fn sort_unique(kinds: &mut Vec<EmojiKind>) {
+        kinds.sort();
+        kinds.dedup();
+    }
+}
+
+impl PartialEq for EmojiKind {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for EmojiKind {}
+
+impl Hash for EmojiKind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sort_key().hash(state)
+    }
+}
+
 impl PartialOrd for EmojiKind {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.to_string().partial_cmp(&other.to_string())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for EmojiKind {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.to_string().cmp(&other.to_string())
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// The emojis of a directory that has been read before the table was (necessarily) available
+/// (see [Emoji::scan_dir])
+#[derive(Debug)]
+pub struct ScannedDir {
+    /// The emojis that could be created from their file names
+    pub emojis: Vec<Emoji>,
+    /// The files that couldn't be parsed (yet)
+    pub failed: Vec<(PathBuf, EmojiError)>,
+    /// The number of entries that have been skipped because they are ignored
+    pub ignored: usize,
+    flag: bool,
+    with_table: bool,
+}
+
+impl ScannedDir {
+    /// Finishes the emojis with the table (if it hasn't been used for the scan already):
+    /// - Files that are named after an emoji in the table are parsed again, as names take
+    ///   precedence over sequences (a name like `face` is a valid sequence as well).
+    /// - All other emojis get their name and kinds from the table (see [Emoji::backfill]).
+    /// - The files that couldn't be parsed are tried again.
+    ///
+    /// The files that still can't be parsed are logged and reported to `diagnostics`.
+    pub fn complete(self, table: Option<&EmojiTable>, diagnostics: &DiagnosticsSink) -> Vec<Emoji> {
+        let flag = self.flag;
+        let late_table = table.filter(|_| !self.with_table);
+        let mut emojis = self.emojis;
+        if let Some(table) = late_table {
+            for emoji in &mut emojis {
+                let named = !flag && emoji.svg_path.as_ref()
+                    .and_then(|path| path.file_stem())
+                    .map(|name| {
+                        let name = name.to_string_lossy();
+                        let (name, _) = Emoji::split_variant(&name);
+                        table.get_by_name(name).is_some() || table.get_base_by_name(name).is_some()
+                    })
+                    .unwrap_or(false);
+                if named {
+                    if let Some(Ok(reparsed)) = emoji.svg_path.clone().map(|path| Emoji::from_path(path, Some(table), flag)) {
+                        *emoji = reparsed;
+                        continue;
+                    }
+                }
+                emoji.backfill(table);
+            }
+        }
+        for (path, err) in self.failed {
+            let retried = match late_table {
+                Some(table) => Emoji::from_path(path.clone(), Some(table), flag),
+                None => Err(err)
+            };
+            match retried {
+                Ok(emoji) => {
+                    debug!("Parsed {:?} with the emoji table", path);
+                    emojis.push(emoji);
+                },
+                Err(err) => {
+                    error!("{:?}", err);
+                    diagnostics.report(Diagnostic::error(Category::Emoji, format!("{:?}", err)).with_path(path));
+                }
+            }
+        }
+        emojis
     }
 }
 
@@ -844,4 +1479,40 @@ pub enum EmojiError {
     /// Indicates that the given `PathBuf` did not find a valid file name
     /// (i.e. "if the path terminates in `..`").
     NotAFileName(PathBuf),
+    /// Indicates that the sequence of the emoji from the given file can't be used in a font
+    /// (see [Emoji::validate_sequence])
+    InvalidSequence(PathBuf, SequenceError),
+}
+
+/// A reason why a sequence can't be used in a font (see [Emoji::validate_sequence])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The codepoint is larger than `U+10FFFF`
+    InvalidCodepoint(u32),
+    /// The sequence is longer than allowed
+    TooLong {
+        /// The length of the sequence
+        length: usize,
+        /// The maximum length
+        max_length: usize
+    },
+    /// The sequence starts with a Zero Width Joiner
+    LeadingZwj,
+    /// The sequence ends with a Zero Width Joiner
+    TrailingZwj,
+    /// `U+FE0F` follows itself (the position is the one of the repeated `U+FE0F`)
+    RepeatedVs16(usize),
+}
+
+impl Display for SequenceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::InvalidCodepoint(codepoint) => write!(f, "{:X} is not a valid codepoint", codepoint),
+            SequenceError::TooLong { length, max_length } =>
+                write!(f, "The sequence has {} codepoints, but only {} are allowed", length, max_length),
+            SequenceError::LeadingZwj => write!(f, "The sequence starts with a Zero Width Joiner"),
+            SequenceError::TrailingZwj => write!(f, "The sequence ends with a Zero Width Joiner"),
+            SequenceError::RepeatedVs16(position) => write!(f, "FE0F is repeated at position {}", position)
+        }
+    }
 }
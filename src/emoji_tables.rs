@@ -18,39 +18,670 @@
 //!
 //! [unicode]: https://unicode.org/Public/emoji/13.0/
 
-use std::collections::hash_map::RandomState;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::ops::{Add, AddAssign, RangeInclusive};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use itertools::Itertools;
 use regex::Regex;
+use serde::Deserialize;
 
+use crate::configs::ConfigError;
+use crate::diagnostics::{Category, Diagnostic, DiagnosticsSink};
 use crate::emoji::{EmojiKind, Emoji};
+use crate::keywords::{self, Keywords};
+use crate::prefix_index::{PrefixIndex, PrefixIndexCache};
+use crate::short_ids::{ShortIdCache, ShortIds};
+#[cfg(feature = "cache")]
+use crate::online_cache::{FetchError, HttpFetcher, OnlineCache};
 
 /// A code sequence
-type EmojiTableKey = Vec<u32>;
-// The EmojiKinds and optionally a description/name
-type EmojiTableEntry = (Vec<EmojiKind>, Option<String>);
+pub(crate) type EmojiTableKey = Vec<u32>;
+/// The contributions that different sources made to an entry
+type Provenance = HashMap<EmojiTableKey, Vec<(EntryField, String)>>;
+/// The position of each sequence (without `U+FE0F`) in an emoji ordering file
+type Ordering = HashMap<EmojiTableKey, usize>;
+/// The Emoji version in which each sequence has been introduced
+type Versions = HashMap<EmojiTableKey, EmojiVersion>;
+/// The codepoints that have an emoji presentation sequence (i.e. with `U+FE0F`)
+type VariationBases = HashSet<u32>;
+/// The status of each sequence in `emoji-test.txt` (e.g. `fully-qualified`)
+type Statuses = HashMap<EmojiTableKey, String>;
 
 const EMOJI_SEQUENCE_SPACE_REGEX: &str = r"(([A-F0-9a-f]{1,8})(\s+([A-F0-9a-f]{1,8}))*)";
-const EMOJI_STATUS_REGEX: &str = r"(component|fully-qualified|minimally-qualified|unqualified)";
-const EMOJI_NAME_REGEX: &str = r"(.*)?\s*E(\d+.\d+) (.+)";
+// non-fully-qualified has been used up to Emoji 11.0,
+// custom is used for the emojis of a private set (see Layout::write_test_data)
+const EMOJI_STATUS_REGEX: &str = r"(component|fully-qualified|minimally-qualified|non-fully-qualified|unqualified|custom)";
 
 /// An internal representation of one or more Unicode® emoji data tables
 /// <https://unicode.org/Public/emoji/12.0/>
 /// It maps emoji code sequences to their kind and (if given) a description/name.
+///
+/// Optionally it also keeps track of which source (e.g. which file) contributed which kind or
+/// name to an entry (see [EmojiTable::with_provenance]) and in which order the emojis should be
+/// shown (see [EmojiTable::expand_ordering]).
+/// It also keeps the Emoji version in which an emoji has been introduced (see [EmojiTable::version])
+/// and the codepoints with emoji variation sequences (see [EmojiTable::emoji_variation_bases]).
+/// Malformed lines in the sources are reported to its [DiagnosticsSink] (see [EmojiTable::set_diagnostics]).
+/// The short IDs of the emojis are only computed when they're needed (see [EmojiTable::short_ids]),
+/// just like the index for prefix lookups (see [EmojiTable::sequences_with_prefix]).
+/// The search keywords of the emojis are kept separately from their names (see [EmojiTable::keywords]).
+/// A summary of all of this is available with [EmojiTable::stats].
 #[derive(Debug)]
 #[derive(PartialEq)]
 #[derive(Eq)]
-pub struct EmojiTable(HashMap<EmojiTableKey, EmojiTableEntry>, HashMap<String, EmojiTableKey>);
+pub struct EmojiTable {
+    entries: HashMap<EmojiTableKey, EmojiTableEntry>,
+    /// The sequences by their lookup names (see [Name::key])
+    names: HashMap<String, EmojiTableKey>,
+    /// Only kept track of if the table has been created [EmojiTable::with_provenance]
+    provenance: Option<Provenance>,
+    ordering: Ordering,
+    versions: Versions,
+    diagnostics: DiagnosticsSink,
+    variation_bases: VariationBases,
+    short_ids: ShortIdCache,
+    keywords: Keywords,
+    prefix_index: PrefixIndexCache,
+    statuses: Statuses,
+}
+
+/// A part of an [EmojiTable]'s entry that has been contributed by a source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryField {
+    /// The source added this kind to the entry
+    Kind(EmojiKind),
+    /// The source set the entry's name/description
+    Name(String),
+}
+
+/// The name of an emoji as it's written in its source (e.g. `woman: medium skin tone, white hair`),
+/// which is what's shown to users, together with the key it's looked up with
+/// (see [EmojiTable::normalize_lookup_name]).
+/// # Examples
+/// ```
+/// use emoji_builder::emoji_tables::Name;
+///
+/// let name = Name::new("smiling face with heart-eyes");
+/// assert_eq!(name.to_string(), "smiling face with heart-eyes");
+/// assert_eq!(name.key(), "smiling face with heart eyes");
+/// assert!(name.matches("Smiling_Face_With_Heart_Eyes"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Name {
+    display: String,
+    key: String,
+}
+
+impl Name {
+    pub fn new<S: Into<String>>(display: S) -> Name {
+        let display = display.into();
+        let key = EmojiTable::normalize_lookup_name(&display);
+        Name { display, key }
+    }
+
+    /// The name as it's shown to users
+    pub fn as_str(&self) -> &str {
+        &self.display
+    }
+
+    /// The normalized name that is used for lookups
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether a (possibly sloppily written) name refers to this one
+    pub fn matches(&self, name: &str) -> bool {
+        EmojiTable::normalize_lookup_name(name) == self.key
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display)
+    }
+}
+
+impl From<&str> for Name {
+    fn from(display: &str) -> Self {
+        Name::new(display)
+    }
+}
+
+impl From<String> for Name {
+    fn from(display: String) -> Self {
+        Name::new(display)
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        &self.display
+    }
+}
+
+/// The kinds and (optionally) the name of an emoji in an [EmojiTable]
+/// # Examples
+/// ```
+/// use emoji_builder::emoji::EmojiKind;
+/// use emoji_builder::emoji_tables::{EmojiTableEntry, Name};
+///
+/// let entry = EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking face");
+/// assert_eq!(entry.kinds(), &[EmojiKind::Emoji]);
+/// assert_eq!(entry.name(), Some(&Name::from("thinking face")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmojiTableEntry {
+    kinds: Vec<EmojiKind>,
+    name: Option<Name>,
+}
+
+impl EmojiTableEntry {
+    /// An entry without a name
+    pub fn new(kinds: Vec<EmojiKind>) -> EmojiTableEntry {
+        EmojiTableEntry {
+            kinds,
+            name: None,
+        }
+    }
+
+    /// Sets the name of the entry
+    pub fn with_name<N: Into<Name>>(mut self, name: N) -> EmojiTableEntry {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The kinds from the different tables (sorted, see [EmojiKind::insert_sorted])
+    pub fn kinds(&self) -> &[EmojiKind] {
+        &self.kinds
+    }
+
+    /// The name/description of the emoji (if any of the tables has one)
+    pub fn name(&self) -> Option<&Name> {
+        self.name.as_ref()
+    }
+
+    /// Replaces (or removes) the name of the entry
+    pub fn set_name(&mut self, name: Option<Name>) {
+        self.name = name;
+    }
+
+    /// Adds a kind if the entry doesn't have it yet.
+    /// Returns whether it has been added.
+    pub fn add_kind(&mut self, kind: EmojiKind) -> bool {
+        EmojiKind::insert_sorted(&mut self.kinds, kind)
+    }
+}
+
+/// Only for the transition from the tuples that have been used before;
+/// use [EmojiTableEntry::new] and [EmojiTableEntry::with_name] instead.
+impl From<(Vec<EmojiKind>, Option<Name>)> for EmojiTableEntry {
+    fn from((kinds, name): (Vec<EmojiKind>, Option<Name>)) -> Self {
+        EmojiTableEntry { kinds, name }
+    }
+}
+
+/// Only for the transition from the tuples that have been used before;
+/// use [EmojiTableEntry::kinds] and [EmojiTableEntry::name] instead.
+impl From<EmojiTableEntry> for (Vec<EmojiKind>, Option<Name>) {
+    fn from(entry: EmojiTableEntry) -> Self {
+        (entry.kinds, entry.name)
+    }
+}
 
 impl EmojiTable {
     /// Creates a new, empty emoji table
     pub fn new() -> Self {
-        Self(HashMap::new(), HashMap::new())
+        EmojiTable {
+            entries: HashMap::new(),
+            names: HashMap::new(),
+            provenance: None,
+            ordering: HashMap::new(),
+            versions: HashMap::new(),
+            diagnostics: DiagnosticsSink::default(),
+            variation_bases: HashSet::new(),
+            short_ids: ShortIdCache::default(),
+            keywords: Keywords::default(),
+            prefix_index: PrefixIndexCache::default(),
+            statuses: HashMap::new(),
+        }
+    }
+
+    /// Creates a new, empty emoji table that records the source of every kind and name that gets
+    /// added to it.
+    /// As this needs additional memory, it's not done by [EmojiTable::new].
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EntryField};
+    /// use emoji_builder::emoji::EmojiKind;
+    ///
+    /// let mut table = EmojiTable::with_provenance();
+    /// table.expand_from_file("test_files/tables/emoji-zwj-sequences.txt").unwrap();
+    ///
+    /// let rainbow = vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+    /// assert_eq!(
+    ///     table.get_provenance(&rainbow).unwrap(),
+    ///     &[(EntryField::Kind(EmojiKind::EmojiZwjSequence), String::from("emoji-zwj-sequences.txt"))]
+    /// );
+    /// ```
+    pub fn with_provenance() -> Self {
+        EmojiTable {
+            provenance: Some(HashMap::new()),
+            ..EmojiTable::new()
+        }
+    }
+
+    /// Whether this table records the sources of its entries
+    pub fn tracks_provenance(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    /// Returns the sources that contributed to an entry (if they are tracked)
+    pub fn get_provenance(&self, key: &[u32]) -> Option<&[(EntryField, String)]> {
+        self.provenance.as_ref()
+            .and_then(|provenance| provenance.get(key))
+            .map(|contributions| contributions.as_slice())
+    }
+
+    /// Reads the order in which emojis should be shown, e.g. from CLDR's `emoji-ordering.txt`.
+    ///
+    /// Each line either starts with a codepoint sequence in hex (like in the Unicode® data
+    /// tables, optionally with `U+` prefixes), followed by `;` or `#`, or it contains the emojis
+    /// themselves, separated by whitespace. Comments start with `#`.
+    /// Emojis that occur multiple times keep their first position and reading multiple files
+    /// appends their emojis.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let ordering = "# group: Smileys & Emotion\n\
+    ///                 U+1F600 ; E1.0 # 😀 grinning face\n\
+    ///                 😃 😄 #️⃣ # comment\n";
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_ordering(ordering.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.ordering_index(&[0x1f600]), Some(0));
+    /// assert_eq!(table.ordering_index(&[0x1f604]), Some(2));
+    /// assert_eq!(table.ordering_index(&[0x23, 0xfe0f, 0x20e3]), Some(3));
+    /// assert_eq!(table.ordering_index(&[0x23, 0x20e3]), Some(3));
+    /// assert_eq!(table.ordering_index(&[0x1f914]), None);
+    /// ```
+    pub fn expand_ordering<I: BufRead>(&mut self, reader: I) -> Result<(), Error> {
+        for line in reader.lines() {
+            for sequence in Self::parse_ordering_line(&line?) {
+                self.add_ordering(sequence);
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [EmojiTable::expand_ordering], but it reads the ordering from a file
+    pub fn expand_ordering_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.expand_ordering(BufReader::new(File::open(path)?))
+    }
+
+    /// Reports malformed lines of the sources that are read afterwards to `diagnostics`
+    pub fn set_diagnostics(&mut self, diagnostics: DiagnosticsSink) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Reports a malformed part of a source (the source is used as the file if it has a label)
+    fn report_malformed(&self, source: &str, message: String) {
+        let diagnostic = Diagnostic::warning(Category::Table, message);
+        self.diagnostics.report(if source == Self::UNLABELLED_SOURCE {
+            diagnostic
+        } else {
+            diagnostic.with_path(PathBuf::from(source))
+        });
+    }
+
+    /// The short IDs of all emojis in this table.
+    /// They're computed when they're needed for the first time (after emojis have been added),
+    /// which is also when collisions are resolved by making all IDs longer.
+    pub fn short_ids(&self) -> Arc<ShortIds> {
+        self.short_ids.get_or_build(|| ShortIds::build(self.entries.keys()))
+    }
+
+    /// The sequences that start with `prefix` (including `prefix` itself if it's in the table)
+    /// in ascending order, e.g. to complete a ZWJ sequence.
+    /// The index for this is built when it's needed for the first time (after emojis have been
+    /// added), so each lookup only takes a binary search.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x1f468], EmojiTableEntry::new(vec![]));
+    /// table.insert(vec![0x1f468, 0x200d, 0x1f4bb], EmojiTableEntry::new(vec![]).with_name("man technologist"));
+    /// table.insert(vec![0x1f469, 0x200d, 0x1f4bb], EmojiTableEntry::new(vec![]).with_name("woman technologist"));
+    ///
+    /// let completions: Vec<_> = table.sequences_with_prefix(&[0x1f468, 0x200d]).collect();
+    /// assert_eq!(completions, vec![&vec![0x1f468, 0x200d, 0x1f4bb]]);
+    /// ```
+    pub fn sequences_with_prefix<'a>(&'a self, prefix: &[u32]) -> impl Iterator<Item = &'a EmojiTableKey> + 'a {
+        let index = self.prefix_index.get_or_build(|| PrefixIndex::build(self.entries.keys()));
+        let range = index.range(prefix);
+        // The index is shared, so the keys are taken from the table itself
+        range.filter_map(move |position| self.entries.get_key_value(&index.sequences()[position])
+            .map(|(key, _)| key))
+    }
+
+    /// The short ID of a sequence (see [EmojiTable::short_ids])
+    pub fn short_id(&self, sequence: &[u32]) -> String {
+        self.short_ids().id(sequence)
+    }
+
+    /// Finds the sequence with this short ID (see [EmojiTable::short_ids])
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308], EmojiTableEntry::new(vec![]));
+    /// let id = table.short_id(&[0x1f3f3, 0x200d, 0x1f308]);
+    /// assert_eq!(table.resolve_short_id(&id), Some(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]));
+    /// ```
+    pub fn resolve_short_id(&self, id: &str) -> Option<EmojiTableKey> {
+        self.short_ids().resolve(id).cloned()
+    }
+
+    /// Whether an ordering has been read
+    pub fn has_ordering(&self) -> bool {
+        !self.ordering.is_empty()
+    }
+
+    /// The position of an emoji in the ordering (ignoring `U+FE0F`).
+    /// The positions are dense, i.e. they range from 0 to the number of ordered emojis.
+    pub fn ordering_index(&self, key: &[u32]) -> Option<usize> {
+        self.ordering.get(&Self::without_fe0f(key)).copied()
+    }
+
+    fn add_ordering(&mut self, key: EmojiTableKey) {
+        let key = Self::without_fe0f(&key);
+        let next = self.ordering.len();
+        self.ordering.entry(key).or_insert(next);
+    }
+
+    /// The Emoji version in which an emoji has been introduced (ignoring `U+FE0F`).
+    ///
+    /// It's taken from the `E<major>.<minor>` tokens in `emoji-test.txt` and in the comments of
+    /// `emoji-data.txt`-like files.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiVersion};
+    ///
+    /// let data = "1F600..1F606 ; Emoji # E1.0 [7] (😀..😆) grinning face..grinning squinting face\n\
+    ///             1F90C ; Emoji # E13.0 [1] (🤌) pinched fingers\n";
+    /// let mut table = EmojiTable::new();
+    /// table.expand(data.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.version(&[0x1f90c]), Some(EmojiVersion { major: 13, minor: 0, approximate: false }));
+    /// // Ranges only give a single version for all of their codepoints
+    /// assert!(table.version(&[0x1f603]).unwrap().approximate);
+    /// ```
+    pub fn version(&self, key: &[u32]) -> Option<EmojiVersion> {
+        self.versions.get(key)
+            .or_else(|| self.versions.get(&Self::without_fe0f(key)))
+            .copied()
+    }
+
+    /// The status of a sequence in `emoji-test.txt` (e.g. `fully-qualified` or `unqualified`)
+    pub fn status(&self, key: &[u32]) -> Option<&str> {
+        self.statuses.get(key).map(String::as_str)
+    }
+
+    /// How a codepoint is displayed by default, based on its `Emoji` and `Emoji_Presentation`
+    /// properties from `emoji-data.txt`.
+    /// Codepoints that aren't emojis (e.g. `U+200D`) don't have a presentation.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, Presentation};
+    ///
+    /// let data = "2764 ; Emoji # E0.6 [1] (❤️) red heart\n\
+    ///             1F914 ; Emoji # E1.0 [1] (🤔) thinking face\n\
+    ///             1F914 ; Emoji_Presentation # E1.0 [1] (🤔) thinking face\n";
+    /// let mut table = EmojiTable::new();
+    /// table.expand(data.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.default_presentation(0x2764), Some(Presentation::Text));
+    /// assert_eq!(table.default_presentation(0x1f914), Some(Presentation::Emoji));
+    /// assert_eq!(table.default_presentation(0x200d), None);
+    /// ```
+    pub fn default_presentation(&self, codepoint: u32) -> Option<Presentation> {
+        let kinds = &self.entries.get(&vec![codepoint])?.kinds;
+        if kinds.contains(&EmojiKind::EmojiPresentation) {
+            Some(Presentation::Emoji)
+        } else if kinds.contains(&EmojiKind::Emoji) {
+            Some(Presentation::Text)
+        } else {
+            None
+        }
+    }
+
+    /// The codepoints that can be followed by `U+FE0F` to be displayed as an emoji
+    /// (as listed in `emoji-variation-sequences.txt`).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let data = "2640 FE0E ; text style;  # (4.0) FEMALE SIGN\n\
+    ///             2640 FE0F ; emoji style; # (4.0) FEMALE SIGN\n";
+    /// let mut table = EmojiTable::new();
+    /// table.expand(data.as_bytes()).unwrap();
+    ///
+    /// assert!(table.emoji_variation_bases().contains(&0x2640));
+    /// assert_eq!(table.emoji_variation_bases().len(), 1);
+    /// // It's not an emoji (as far as this table knows)
+    /// assert!(table.is_empty());
+    /// ```
+    pub fn emoji_variation_bases(&self) -> &HashSet<u32> {
+        &self.variation_bases
+    }
+
+    /// The spelling of a sequence with the right variation selectors, i.e. `U+FE0F` after each
+    /// codepoint with [Presentation::Text] (unless it's followed by a skin tone) and none after
+    /// codepoints with [Presentation::Emoji].
+    /// Codepoints without a known presentation are left as they are.
+    pub fn canonical_sequence(&self, sequence: &[u32]) -> EmojiTableKey {
+        let mut canonical = Vec::with_capacity(sequence.len() + 1);
+        for (index, codepoint) in sequence.iter().enumerate() {
+            let next = sequence.get(index + 1);
+            match (*codepoint, self.default_presentation(*codepoint)) {
+                (0xfe0f, _) => {
+                    let previous = index.checked_sub(1).map(|previous| sequence[previous]);
+                    // A missing one has already been added with the previous codepoint
+                    match previous.and_then(|previous| self.default_presentation(previous)) {
+                        Some(_) => (),
+                        None => canonical.push(0xfe0f)
+                    }
+                }
+                (codepoint, Some(Presentation::Text)) => {
+                    canonical.push(codepoint);
+                    if !next.map(|next| Self::SKIN_TONES.contains(next)).unwrap_or(false) {
+                        canonical.push(0xfe0f);
+                    }
+                }
+                (codepoint, _) => canonical.push(codepoint)
+            }
+        }
+        canonical
+    }
+
+    /// Checks whether a sequence has all the variation selectors that it needs and no redundant
+    /// ones (see [EmojiTable::canonical_sequence]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry, PresentationIssue};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x2764], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("red heart"));
+    ///
+    /// assert_eq!(table.presentation_issue(&[0x2764]), Some(PresentationIssue::MissingVs16 {
+    ///     canonical: vec![0x2764, 0xfe0f]
+    /// }));
+    /// assert_eq!(table.presentation_issue(&[0x2764, 0xfe0f]), None);
+    /// ```
+    pub fn presentation_issue(&self, sequence: &[u32]) -> Option<PresentationIssue> {
+        let canonical = self.canonical_sequence(sequence);
+        let count_fe0f = |sequence: &[u32]| sequence.iter()
+            .filter(|codepoint| **codepoint == 0xfe0f)
+            .count();
+        if canonical == sequence {
+            None
+        } else if count_fe0f(&canonical) > count_fe0f(sequence) {
+            Some(PresentationIssue::MissingVs16 { canonical })
+        } else {
+            Some(PresentationIssue::RedundantVs16 { canonical })
+        }
+    }
+
+    /// All emojis that have been introduced in the given Emoji version or before.
+    /// Emojis without a known version are not included.
+    pub fn keys_until_version(&self, version: (u32, u32)) -> impl Iterator<Item = &EmojiTableKey> {
+        self.versions.iter()
+            .filter(move |(_, introduced)| introduced.as_tuple() <= version)
+            .map(|(key, _)| key)
+    }
+
+    /// Leaves out the emojis that have been introduced after the given Emoji version, e.g. for a
+    /// font that targets an older platform. Emojis without a known version are kept.
+    ///
+    /// With `downgrade`, excluded ZWJ sequences are kept with the image of their closest older
+    /// fallback (e.g. `woman running` with the image of `person running`), if that one is kept.
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use emoji_builder::emoji::Emoji;
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let data = "1F3C3 ; fully-qualified # 🏃 E0.6 person running\n\
+    ///             1F3C3 200D 2640 FE0F ; fully-qualified # 🏃‍♀️ E4.0 woman running\n";
+    /// let mut table = EmojiTable::new();
+    /// table.expand_descriptions_from_test_data(data.as_bytes()).unwrap();
+    /// let emojis = vec![
+    ///     Emoji::from_path(PathBuf::from("emoji_u1f3c3.svg"), None, false).unwrap(),
+    ///     Emoji::from_path(PathBuf::from("emoji_u1f3c3_200d_2640_fe0f.svg"), None, false).unwrap(),
+    /// ];
+    ///
+    /// let restricted = table.restrict_to_version(emojis.clone(), (3, 0), false);
+    /// assert_eq!(restricted.emojis, vec![emojis[0].clone()]);
+    /// assert_eq!(restricted.excluded.get(&(4, 0)), Some(&1));
+    ///
+    /// let downgraded = table.restrict_to_version(emojis.clone(), (3, 0), true);
+    /// assert_eq!(downgraded.emojis[1].sequence, emojis[1].sequence);
+    /// assert_eq!(downgraded.emojis[1].svg_path, emojis[0].svg_path);
+    /// ```
+    pub fn restrict_to_version(&self, emojis: Vec<Emoji>, max_version: (u32, u32), downgrade: bool) -> VersionRestriction {
+        let mut restriction = VersionRestriction::default();
+        let (excluded, kept): (Vec<Emoji>, Vec<Emoji>) = emojis.into_iter()
+            .partition(|emoji| self.version(&emoji.sequence)
+                .is_some_and(|version| version.as_tuple() > max_version));
+        for emoji in &excluded {
+            if let Some(version) = self.version(&emoji.sequence) {
+                *restriction.excluded.entry(version.as_tuple()).or_insert(0) += 1;
+            }
+        }
+
+        let mut downgraded = Vec::new();
+        if downgrade {
+            let fallbacks: HashMap<EmojiTableKey, &Emoji> = kept.iter()
+                .map(|emoji| (Self::without_fe0f(&emoji.sequence), emoji))
+                .collect();
+            for emoji in excluded.into_iter().filter(|emoji| emoji.sequence.contains(&0x200d)) {
+                let fallback = Self::older_fallbacks(&emoji.sequence).iter()
+                    .find_map(|fallback| fallbacks.get(&Self::without_fe0f(fallback)));
+                if let Some(fallback) = fallback {
+                    restriction.downgraded.push((emoji.sequence.clone(), fallback.sequence.clone()));
+                    downgraded.push(Emoji {
+                        svg_path: fallback.svg_path.clone(),
+                        ..emoji
+                    });
+                }
+            }
+        }
+        restriction.emojis = kept;
+        restriction.emojis.extend(downgraded);
+        restriction
+    }
+
+    /// The sequences that a ZWJ sequence can fall back to, the closest one first:
+    /// the sequence without its gender signs and then the sequence with fewer elements
+    fn older_fallbacks(sequence: &[u32]) -> Vec<EmojiTableKey> {
+        const ZWJ: u32 = 0x200d;
+        let elements = sequence.split(|codepoint| *codepoint == ZWJ).collect_vec();
+        let is_gender_sign = |element: &&[u32]| matches!(element.first(), Some(&0x2640) | Some(&0x2642));
+
+        let mut fallbacks = Vec::new();
+        if elements.iter().any(is_gender_sign) {
+            let without_gender = elements.iter().copied().filter(|element| !is_gender_sign(element)).collect_vec();
+            fallbacks.push(without_gender.join(&ZWJ));
+        }
+        for len in (1..elements.len()).rev() {
+            fallbacks.push(elements[..len].join(&ZWJ));
+        }
+        fallbacks.retain(|fallback| !fallback.is_empty());
+        fallbacks
+    }
+
+    /// Sets the version of an emoji (and its variant without `U+FE0F`).
+    /// An exact version is never replaced by an approximate one.
+    fn update_version(&mut self, emoji: &[u32], version: EmojiVersion) {
+        let keys = if emoji.contains(&0xfe0f) {
+            vec![emoji.to_vec(), Self::without_fe0f(emoji)]
+        } else {
+            vec![emoji.to_vec()]
+        };
+        for key in keys {
+            match self.versions.get(&key) {
+                Some(existing) if !existing.approximate || version.approximate => (),
+                _ => { self.versions.insert(key, version); }
+            }
+        }
+    }
+
+    /// The skin tone modifiers, which replace the `U+FE0F` of the codepoint before them
+    const SKIN_TONES: RangeInclusive<u32> = 0x1f3fb..=0x1f3ff;
+
+    fn without_fe0f(key: &[u32]) -> EmojiTableKey {
+        key.iter()
+            .filter(|codepoint| **codepoint != 0xfe0f)
+            .copied()
+            .collect()
+    }
+
+    /// Parses a single line of an ordering file (see [EmojiTable::expand_ordering])
+    fn parse_ordering_line(line: &str) -> Vec<EmojiTableKey> {
+        lazy_static! {
+            static ref HEX_SEQUENCE: Regex = Regex::new(r"^\s*((?:[Uu]\+)?[A-Fa-f0-9]{1,8}(?:\s+(?:[Uu]\+)?[A-Fa-f0-9]{1,8})*)\s*(?:[;#]|$)").unwrap();
+        }
+        if let Some(captures) = HEX_SEQUENCE.captures(line) {
+            let sequence = captures[1].split_whitespace()
+                .filter_map(|codepoint| u32::from_str_radix(codepoint.trim_start_matches(['U', 'u', '+']), 16).ok())
+                .collect();
+            return vec![sequence];
+        }
+        let mut sequences = vec![];
+        for token in line.split_whitespace() {
+            // `#` starts a comment, unless it's part of a keycap sequence
+            let mut chars = token.chars();
+            if chars.next() == Some('#') && !matches!(chars.next(), Some('\u{fe0f}') | Some('\u{20e3}')) {
+                break;
+            }
+            // Every emoji contains at least one non-ASCII character (even keycap sequences)
+            if token.is_ascii() {
+                continue;
+            }
+            sequences.push(token.chars().map(|character| character as u32).collect());
+        }
+        sequences
     }
 
     /// Reads multiple files which are formatted in the same way as the Unicode® emoji data tables
@@ -65,7 +696,7 @@ impl EmojiTable {
     /// ```
     /// use std::path::PathBuf;
     /// use emoji_builder::emoji::EmojiKind::EmojiZwjSequence;
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     /// use std::collections::HashMap;
     ///
     /// // Contains the entry
@@ -78,7 +709,7 @@ impl EmojiTable {
     /// let rainbow = vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
     /// let rainbow_no_fe0f = vec![0x1f3f3, 0x200d, 0x1f308];
     ///
-    /// let rainbow_entry = (vec![EmojiZwjSequence], None);
+    /// let rainbow_entry = EmojiTableEntry::new(vec![EmojiZwjSequence]);
     ///
     /// assert!(table.as_ref().contains_key(&rainbow));
     /// assert!(table.as_ref().contains_key(&rainbow_no_fe0f));
@@ -99,6 +730,12 @@ impl EmojiTable {
     /// Only the emoji itself and its kind(s) is/are extended.
     /// Names are extended from `emoji-test.txt`-like files, using [EmojiTable::expand_descriptions_from_test_data]
     pub fn expand<I: BufRead>(&mut self, reader: I) -> Result<(), Error> {
+        self.expand_with_source(reader, Self::UNLABELLED_SOURCE)
+    }
+
+    /// Like [EmojiTable::expand], but the entries will be attributed to the given source
+    /// (if the table tracks them).
+    pub fn expand_with_source<I: BufRead>(&mut self, reader: I, source: &str) -> Result<(), Error> {
         lazy_static! {
             static ref HEX_SEQUENCE: Regex = Regex::new(r"[a-fA-F0-9]{1,8}").unwrap();
             static ref RANGE: Regex = Regex::new(&format!(r"(?P<range>(?P<range_start>{hex})\.\.(?P<range_end>{hex}))", hex = &*HEX_SEQUENCE)).unwrap();
@@ -106,13 +743,22 @@ impl EmojiTable {
             static ref EMOJI_REGEX: Regex = Regex::new(&format!(r"(?P<codepoints>{}|{})", &*RANGE, &*SEQUENCE)).unwrap();
             // TODO: Maybe make this more specific
             static ref EMOJI_KIND_REGEX: Regex = Regex::new(r"(?P<kind>[A-Za-z_\-]+)").unwrap();
-            static ref DATA_REGEX: Regex = Regex::new(&format!(r"^{}\s*;\s*{}\s*(;(?P<name>.*)\s*)?(#.*)?$", &*EMOJI_REGEX, &*EMOJI_KIND_REGEX)).unwrap();
+            static ref DATA_REGEX: Regex = Regex::new(&format!(r"^{}\s*;\s*{}\s*(;(?P<name>[^#]*)\s*)?(#\s*(E(?P<major>\d+)\.(?P<minor>\d+)\b)?.*)?$", &*EMOJI_REGEX, &*EMOJI_KIND_REGEX)).unwrap();
+            // emoji-variation-sequences.txt, e.g. `2640 FE0F ; emoji style; # (4.0) FEMALE SIGN`
+            static ref VARIATION_REGEX: Regex = Regex::new(r"^(?P<base>[a-fA-F0-9]{1,8})\s+FE0(?P<selector>[EFef])\s*;\s*(?P<style>emoji|text) style\s*(;.*)?$").unwrap();
         }
 
         for line in reader.lines()
             .filter_map(|line| line.ok()) {
             let line = line.trim();
             if !line.starts_with('#') && !line.is_empty() {
+                if let Some(variation) = (&*VARIATION_REGEX as &Regex).captures(line) {
+                    // Text style sequences are what you get without a variation selector anyway
+                    if variation.name("selector").unwrap().as_str().eq_ignore_ascii_case("f") {
+                        self.variation_bases.insert(u32::from_str_radix(variation.name("base").unwrap().as_str(), 16).unwrap());
+                    }
+                    continue;
+                }
                 let captures = (&*DATA_REGEX as &Regex).captures(line);
                 if let Some(captures) = captures {
                     let kind = EmojiKind::from_str(captures.name("kind").unwrap().as_str())
@@ -121,17 +767,30 @@ impl EmojiTable {
                     // No, descriptions will not be used for now; these can be more easily obtained
                     // from emoji-test.txt
 
+                    let version = match (captures.name("major"), captures.name("minor")) {
+                        (Some(major), Some(minor)) => match (major.as_str().parse(), minor.as_str().parse()) {
+                            (Ok(major), Ok(minor)) => Some((major, minor)),
+                            _ => None
+                        },
+                        _ => None
+                    };
+
                     if captures.name("range").is_some() {
                         let start = captures.name("range_start").unwrap().as_str();
                         let end = captures.name("range_end").unwrap().as_str();
-                        self.update_range(start, end, Some(kind));
+                        self.update_range(start, end, Some(kind), version, source);
                     } else if let Some(sequence) = captures.name("sequence") {
-                        self.update_emoji(Self::get_codepoint_sequence(sequence.as_str()), Some(kind), None);
+                        let sequence = Self::get_codepoint_sequence(sequence.as_str());
+                        if let Some((major, minor)) = version {
+                            self.update_version(&sequence, EmojiVersion { major, minor, approximate: false });
+                        }
+                        self.update_emoji(sequence, Some(kind), None, source);
                     } else {
                         unreachable!("Either a range or a sequence has to be captured");
                     }
                 } else {
-                    eprintln!("Malformed line in emoji-table: {}", line);
+                    warn!("Malformed line in emoji-table: {}", line);
+                    self.report_malformed(source, format!("Malformed line in emoji-table: {}", line));
                 }
             }
         }
@@ -148,7 +807,7 @@ impl EmojiTable {
     /// Currently, descriptions will not be used
     /// # Examples
     /// ```
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     /// use emoji_builder::emoji::EmojiKind;
     /// use std::path::PathBuf;
     ///
@@ -160,7 +819,7 @@ impl EmojiTable {
     /// let rainbow = vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
     /// let rainbow_no_fe0f = vec![0x1f3f3, 0x200d, 0x1f308];
     ///
-    /// let rainbow_entry = (vec![EmojiKind::EmojiZwjSequence], None);
+    /// let rainbow_entry = EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]);
     ///
     /// assert!(table.as_ref().contains_key(&rainbow));
     /// assert!(table.as_ref().contains_key(&rainbow_no_fe0f));
@@ -168,14 +827,121 @@ impl EmojiTable {
     /// assert_eq!(*table.get(&rainbow).unwrap(), rainbow_entry);
     /// ```
     pub fn expand_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let source = path.as_ref().file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.as_ref().to_string_lossy().into_owned());
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        self.expand(reader)
+        self.expand_with_source(reader, &source)
+    }
+
+    const UNLABELLED_SOURCE: &'static str = "<unlabelled>";
+
+    /// Adds the emojis of an [emojibase](https://emojibase.dev) dataset (e.g. `data.json`).
+    ///
+    /// Each record (and each of its skins) becomes an entry with the `label` as its name, which
+    /// can also be looked up.
+    /// The kinds are derived from the structure of the sequence, the `type` (emoji or text
+    /// presentation) and the `group` (components).
+    /// The `order` of the records is used as the emoji ordering.
+    /// Groups, subgroups and versions are not kept.
+    ///
+    /// The `tags` are added as additional lookup names, but only if they belong to a single emoji
+    /// and aren't a name already. All of them are added as keywords (see [EmojiTable::keywords]).
+    ///
+    /// Existing entries are merged with the [MergePolicy::default].
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let data = r#"[{
+    ///     "label": "thinking face",
+    ///     "hexcode": "1F914",
+    ///     "tags": ["face", "thinking"],
+    ///     "type": 1,
+    ///     "order": 40
+    /// }]"#;
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_from_emojibase(data.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(
+    ///     *table.get(&vec![0x1f914]).unwrap(),
+    ///     EmojiTableEntry::new(vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]).with_name("thinking face")
+    /// );
+    /// assert_eq!(table.get_by_name("thinking").unwrap().0, vec![0x1f914]);
+    /// ```
+    pub fn expand_from_emojibase<R: Read>(&mut self, reader: R) -> Result<(), serde_json::Error> {
+        self.expand_from_emojibase_with_source(reader, "emojibase")
+    }
+
+    /// Like [EmojiTable::expand_from_emojibase], but the entries will be attributed to the given
+    /// source (if the table tracks them).
+    pub fn expand_from_emojibase_with_source<R: Read>(&mut self, reader: R, source: &str) -> Result<(), serde_json::Error> {
+        let records: Vec<EmojibaseRecord> = serde_json::from_reader(reader)?;
+
+        let mut other = if self.tracks_provenance() {
+            EmojiTable::with_provenance()
+        } else {
+            EmojiTable::new()
+        };
+        let mut ordered = Vec::with_capacity(records.len());
+        let mut tags: HashMap<String, HashSet<EmojiTableKey>> = HashMap::new();
+        for record in &records {
+            let skins = record.skins.iter()
+                .map(|skin| (skin, false, true));
+            for (record, modifier_base, modifier) in std::iter::once((record, !record.skins.is_empty(), false)).chain(skins) {
+                let key = match record.sequence() {
+                    Some(key) => key,
+                    None => {
+                        warn!("Malformed hexcode in the emojibase data: {}", record.hexcode);
+                        self.report_malformed(source, format!("Malformed hexcode in the emojibase data: {}", record.hexcode));
+                        continue;
+                    }
+                };
+                for kind in record.kinds(&key, modifier_base, modifier) {
+                    other.update_emoji(key.clone(), Some(kind), None, source);
+                }
+                other.update_emoji(key.clone(), None, Some(&record.label), source);
+                other.insert_lookup_name(&record.label, key.clone());
+                for tag in &record.tags {
+                    tags.entry(Self::normalize_lookup_name(tag)).or_default().insert(key.clone());
+                    other.add_keyword(&key, tag);
+                }
+                if let Some(order) = record.order {
+                    ordered.push((order, key));
+                }
+            }
+        }
+        ordered.sort();
+        for (_, key) in ordered {
+            other.add_ordering(key);
+        }
+
+        self.merge_with(other, MergePolicy::default());
+
+        // Tags like "face" are shared by lots of emojis and real names always take precedence
+        for (tag, keys) in tags {
+            if keys.len() == 1 && self.get_by_name(&tag).is_none() {
+                self.insert_lookup_name(&tag, keys.into_iter().next().unwrap());
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [EmojiTable::expand_from_emojibase], but it reads the dataset from a file
+    pub fn expand_from_emojibase_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), serde_json::Error> {
+        let source = path.as_ref().file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.as_ref().to_string_lossy().into_owned());
+        let file = File::open(path).map_err(serde_json::Error::io)?;
+        self.expand_from_emojibase_with_source(BufReader::new(file), &source)
     }
 
     fn _get_description(&self, sequence: &[u32]) -> Option<String> {
-        match self.0.get(sequence) {
-            Some((_, description)) => description.clone(),
+        match self.entries.get(sequence) {
+            Some(entry) => entry.name.as_ref().map(Name::to_string),
             None => None,
         }
     }
@@ -187,12 +953,17 @@ impl EmojiTable {
     /// Descriptions will _not_ be parsed as they would only be available for the start and end codepoint anyway.
     ///
     /// The table will be used to find existing kinds/descriptions
-    fn update_range(&mut self, start: &str, end: &str, kind: Option<EmojiKind>) {
+    fn update_range(&mut self, start: &str, end: &str, kind: Option<EmojiKind>, version: Option<(u32, u32)>, source: &str) {
         // Start and end are already built from a regular expression that only matches hexadecimal strings
         let start = u32::from_str_radix(start, 16).unwrap();
         let end = u32::from_str_radix(end, 16).unwrap();
         for codepoint in start..=end {
-            self.update_emoji(vec![codepoint], kind.clone(), None);
+            // The version of a range is the one of its first codepoint, so it's not necessarily the
+            // right one for the others
+            if let Some((major, minor)) = version {
+                self.update_version(&[codepoint], EmojiVersion { major, minor, approximate: start != end });
+            }
+            self.update_emoji(vec![codepoint], kind.clone(), None, source);
         }
     }
 
@@ -201,10 +972,12 @@ impl EmojiTable {
     /// `emoji`: The codepoint sequence for the emoji
     /// `kind`: The emoji kind to assign for this step
     /// `description`: The name of the emoji
+    /// `source`: Where this information comes from
     fn update_emoji(&mut self,
                     emoji: EmojiTableKey,
                     kind: Option<EmojiKind>,
-                    description: Option<&str>
+                    description: Option<&str>,
+                    source: &str
     ) {
         // If it contains FE0F, we'll also add it without it
         // TODO: Maybe drop this behavior?
@@ -216,40 +989,61 @@ impl EmojiTable {
                     None
                 }).collect(),
                 kind.clone(),
-                description
+                description,
+                source
             )
         }
-        let existing_entry = self.0.get_mut(&emoji);
-        if let Some((kinds, existing_description)) = existing_entry {
-            Self::add_kind(kinds, kind);
-            Self::update_description(existing_description, description);
+        self.record_provenance(&emoji, &kind, description, source);
+        let existing_entry = self.entries.get_mut(&emoji);
+        if let Some(entry) = existing_entry {
+            Self::add_kind(&mut entry.kinds, kind);
+            Self::update_description(&mut entry.name, description);
         } else {
-            let entry = (
+            let entry = EmojiTableEntry {
                 // We expect that at some point the emoji will have at least one kind
-                kind.map(|kind| vec![kind]).unwrap_or_else(|| Vec::with_capacity(1)),
-                description.map(|descr| descr.to_owned())
-            );
-            self.0.insert(emoji, entry);
+                kinds: kind.map(|kind| vec![kind]).unwrap_or_else(|| Vec::with_capacity(1)),
+                name: description.map(Name::from)
+            };
+            self.entries.insert(emoji, entry);
+            self.short_ids.invalidate();
+            self.prefix_index.invalidate();
+        }
+    }
+
+    fn record_provenance(&mut self,
+                         emoji: &[u32],
+                         kind: &Option<EmojiKind>,
+                         description: Option<&str>,
+                         source: &str
+    ) {
+        if let Some(provenance) = &mut self.provenance {
+            let contributions = provenance.entry(emoji.to_vec()).or_default();
+            if let Some(kind) = kind {
+                contributions.push((EntryField::Kind(kind.clone()), source.to_owned()));
+            }
+            if let Some(description) = description {
+                if !description.trim().is_empty() {
+                    contributions.push((EntryField::Name(description.to_owned()), source.to_owned()));
+                }
+            }
         }
     }
 
-    fn update_description(old_description: &mut Option<String>, new_description: Option<&str>) {
+    fn update_description(old_description: &mut Option<Name>, new_description: Option<&str>) {
         if let Some(old_description) = old_description {
             if let Some(new_description) = new_description {
                 if !new_description.trim().is_empty() {
-                    *old_description = new_description.to_owned();
+                    *old_description = Name::from(new_description);
                 }
             }
         } else {
-            *old_description = new_description.map(|description| description.to_owned());
+            *old_description = new_description.map(Name::from);
         }
     }
 
     fn add_kind(existing_kinds: &mut Vec<EmojiKind>, kind: Option<EmojiKind>) {
         if let Some(kind) = kind {
-            if !existing_kinds.contains(&kind) {
-                existing_kinds.insert(existing_kinds.binary_search(&kind).unwrap_err(), kind);
-            }
+            EmojiKind::insert_sorted(existing_kinds, kind);
         }
     }
 
@@ -271,31 +1065,33 @@ impl EmojiTable {
     /// Please be aware that no name-key-mapping is inserted.
     /// That means:
     /// ```
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let name = "thinking face";
     /// let codepoint = vec![0x1f914];
     /// let mut table = EmojiTable::new();
-    /// table.insert(codepoint.clone(), (vec![], Some(name.to_string())));
+    /// table.insert(codepoint.clone(), EmojiTableEntry::new(vec![]).with_name(name));
     ///
     /// // We can't find the emoji by its name!
     /// assert_eq!(table.get_by_name(name), None);
     /// ```
     pub fn insert(&mut self, key: EmojiTableKey, entry: EmojiTableEntry) -> Option<EmojiTableEntry> {
-        self.0.insert(key, entry)
+        self.short_ids.invalidate();
+        self.prefix_index.invalidate();
+        self.entries.insert(key, entry)
     }
 
     /// Inserts a new name to codepoint mapping with the name normalized to lowercase and space
     /// as a delimiter; returns the previous key that this name mapped to if there was one.
     /// # Example
     /// ```
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let name = "thinking face";
     /// let codepoint = vec![0x1f914];
     /// let mut table = EmojiTable::new();
     /// // Even if this description string is the same as the name, it does not have to be.
-    /// table.insert(codepoint.clone(), (vec![], Some(name.to_string())));
+    /// table.insert(codepoint.clone(), EmojiTableEntry::new(vec![]).with_name(name));
     /// table.insert_lookup_name(name, codepoint.clone());
     ///
     /// // Assert that we can find an entry with the given name (and that it's the correct one)
@@ -303,23 +1099,56 @@ impl EmojiTable {
     /// ```
     pub fn insert_lookup_name(&mut self, name: &str, key: EmojiTableKey) -> Option<EmojiTableKey> {
         let lookup_name = Self::normalize_lookup_name(name);
-        self.1.insert(lookup_name, key)
+        self.names.insert(lookup_name, key)
     }
 
     /// Returns the table entry for a given key
     pub fn get<T: AsRef<EmojiTableKey>>(&self, index: &T) -> Option<&EmojiTableEntry> {
         let index: &EmojiTableKey = index.as_ref();
-        self.0.get(index)
+        self.entries.get(index)
     }
 
-    /// Finds an emoji by its name (this is case-insensitive and converts delimiters to the desired format)
+    /// Like [EmojiTable::get], but it falls back to the key without `U+FE0F`
+    pub fn get_with_without_fe0f(&self, key: &[u32]) -> Option<&EmojiTableEntry> {
+        self.entries.get(key)
+            .or_else(|| self.entries.get(&Self::without_fe0f(key)))
+    }
+
+    /// Returns the mutable table entry for a key (or for the key without `U+FE0F`)
+    /// together with the key that has been found.
     /// # Examples
     /// ```
-    /// use emoji_builder::emoji_tables::EmojiTable;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x2615], EmojiTableEntry::new(vec![]).with_name("hot beverage"));
+    ///
+    /// let (key, entry) = table.get_entry_with_without_fe0f(&[0x2615, 0xfe0f]).unwrap();
+    /// assert_eq!(key, vec![0x2615]);
+    /// entry.set_name(Some("coffee".into()));
+    /// assert_eq!(table.get(&vec![0x2615]).unwrap().name(), Some(&"coffee".into()));
+    /// ```
+    pub fn get_entry_with_without_fe0f(&mut self, key: &[u32]) -> Option<(EmojiTableKey, &mut EmojiTableEntry)> {
+        // The key is resolved first, so there's only a single mutable borrow of the table
+        let key = if self.entries.contains_key(key) {
+            key.to_vec()
+        } else {
+            Self::without_fe0f(key)
+        };
+        match self.entries.get_mut(&key) {
+            Some(entry) => Some((key, entry)),
+            None => None
+        }
+    }
+
+    /// Finds an emoji by its name (this is case-insensitive and converts delimiters to the desired format)
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
     ///
     /// let mut table = EmojiTable::new();
     /// let key = vec![0x1f914];
-    /// let entry = (vec![], Some(String::from("Thinking")));
+    /// let entry = EmojiTableEntry::new(vec![]).with_name("Thinking");
     /// table.insert(key.clone(), entry.clone());
     /// table.insert_lookup_name("ThInKiNg_FaCe", key.clone());
     /// assert_eq!(Some((key.clone(), &entry)), table.get_by_name("tHiNkIng-fAcE"));
@@ -336,18 +1165,94 @@ impl EmojiTable {
         let chars = name.chars()
             .map(|character| character as u32)
             .collect_vec();
-        if let Some(entry) = self.0.get(&chars) {
+        if let Some(entry) = self.entries.get(&chars) {
             Some((chars, entry))
         } else {
             let lookup_name = Self::normalize_lookup_name(name);
-            if let Some(codepoint) = self.1.get(&lookup_name) {
-                self.0.get(codepoint).map(|entry| (codepoint.clone(), entry))
+            if let Some(codepoint) = self.names.get(&lookup_name) {
+                self.entries.get(codepoint).map(|entry| (codepoint.clone(), entry))
             } else {
                 None
             }
         }
     }
 
+    /// Finds the base emoji for a name that may contain skin tones (e.g. `dark skin tone`) or a
+    /// gender (a leading `woman` or `man`).
+    /// These words are removed from the name and returned as [NameModifiers] together with the
+    /// base emoji, which can be turned into the full sequence again with [NameModifiers::apply].
+    ///
+    /// Gendered names are also looked up with `person` instead of the gender (e.g. `woman running`
+    /// → `person running`). If the name without the gender can't be found, the gender is kept
+    /// (e.g. `woman dancing` is an emoji on its own).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry, Gender};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// let technologist = vec![0x1f9d1, 0x200d, 0x1f4bb];
+    /// table.insert(technologist.clone(), EmojiTableEntry::new(vec![]).with_name("technologist"));
+    /// table.insert_lookup_name("technologist", technologist.clone());
+    ///
+    /// let (base, _, modifiers) = table.get_base_by_name("woman technologist: dark skin tone").unwrap();
+    /// assert_eq!(base, technologist);
+    /// assert_eq!(modifiers.skin_tones, vec![0x1f3ff]);
+    /// assert_eq!(modifiers.gender, Some(Gender::Female));
+    /// assert_eq!(modifiers.apply(&base), vec![0x1f469, 0x1f3ff, 0x200d, 0x1f4bb]);
+    /// ```
+    pub fn get_base_by_name(&self, name: &str) -> Option<(EmojiTableKey, &EmojiTableEntry, NameModifiers)> {
+        let (name, skin_tones) = Self::strip_skin_tones(&Self::normalize_lookup_name(name));
+
+        let ungendered = name.splitn(2, ' ').collect_vec();
+        let gender = match ungendered.as_slice() {
+            ["woman", rest] => Some((Gender::Female, *rest)),
+            ["man", rest] => Some((Gender::Male, *rest)),
+            _ => None
+        };
+        if let Some((gender, rest)) = gender {
+            let found = self.get_by_name(rest)
+                .or_else(|| self.get_by_name(&format!("person {}", rest)));
+            if let Some((key, entry)) = found {
+                return Some((key, entry, NameModifiers {
+                    skin_tones,
+                    gender: Some(gender),
+                }));
+            }
+        }
+
+        self.get_by_name(&name).map(|(key, entry)| (key, entry, NameModifiers {
+            skin_tones,
+            gender: None,
+        }))
+    }
+
+    /// Removes all skin tones from a (normalized) name and returns them as modifier codepoints
+    fn strip_skin_tones(name: &str) -> (String, Vec<u32>) {
+        let mut words: Vec<&str> = vec![];
+        let mut skin_tones = vec![];
+        let mut remaining = name.split(' ').peekable();
+        while let Some(word) = remaining.next() {
+            if word == "skin" && remaining.peek() == Some(&"tone") {
+                let tone = match words.as_slice() {
+                    [.., "medium", "light"] => Some((2, 0x1f3fc)),
+                    [.., "medium", "dark"] => Some((2, 0x1f3fe)),
+                    [.., "light"] => Some((1, 0x1f3fb)),
+                    [.., "medium"] => Some((1, 0x1f3fd)),
+                    [.., "dark"] => Some((1, 0x1f3ff)),
+                    _ => None
+                };
+                if let Some((length, modifier)) = tone {
+                    words.truncate(words.len() - length);
+                    skin_tones.push(modifier);
+                    remaining.next();
+                    continue;
+                }
+            }
+            words.push(word);
+        }
+        (words.join(" "), skin_tones)
+    }
+
     /// Converts names to the format used in the lookup table for names.
     ///
     /// This method here might cause some issues when dealing with names with hyphens:
@@ -365,14 +1270,211 @@ impl EmojiTable {
         (&*DELIMITERS as &Regex).split(&REMOVED.replace_all(name, "")).join(" ").to_lowercase()
     }
 
+    /// All entries of the table (in no particular order)
+    pub fn iter(&self) -> impl Iterator<Item = (&EmojiTableKey, &EmojiTableEntry)> {
+        self.entries.iter()
+    }
+
     /// Returns the size of the table
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.entries.len()
     }
 
     /// Checks whether the table is empty
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.entries.is_empty()
+    }
+
+    /// Counts what the table contains, e.g. to check whether all sources have been loaded.
+    /// The sources are only counted if the table tracks them (see [EmojiTable::with_provenance]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let data = "1F914 ; fully-qualified # 🤔 E1.0 thinking face\n\
+    ///             263A FE0F ; fully-qualified # ☺️ E0.6 smiling face\n\
+    ///             263A ; unqualified # ☺ E0.6 smiling face\n";
+    /// let mut table = EmojiTable::with_provenance();
+    /// table.expand_descriptions_from_test_data_with_source(data.as_bytes(), "emoji-test.txt").unwrap();
+    /// table.expand("1F914 ; Emoji_Presentation # E1.0 [1] (🤔) thinking face".as_bytes()).unwrap();
+    ///
+    /// let stats = table.stats();
+    /// assert_eq!(stats.entries, 3);
+    /// assert_eq!(stats.named, 3);
+    /// assert_eq!(stats.by_kind[&EmojiKind::EmojiPresentation], 1);
+    /// assert_eq!(stats.by_status["fully-qualified"], 2);
+    /// assert_eq!(stats.by_status["unqualified"], 1);
+    /// assert_eq!(stats.max_version, Some((1, 0)));
+    /// assert_eq!(stats.by_source["emoji-test.txt"], 3);
+    /// ```
+    pub fn stats(&self) -> TableStats {
+        let mut stats = TableStats {
+            entries: self.entries.len(),
+            named: self.entries.values().filter(|entry| entry.name.is_some()).count(),
+            max_version: self.versions.values().map(EmojiVersion::as_tuple).max(),
+            ..Default::default()
+        };
+        for kind in self.entries.values().flat_map(|entry| entry.kinds.iter()) {
+            *stats.by_kind.entry(kind.clone()).or_default() += 1;
+        }
+        for status in self.statuses.values() {
+            *stats.by_status.entry(status.clone()).or_default() += 1;
+        }
+        for contributions in self.provenance.iter().flat_map(|provenance| provenance.values()) {
+            let sources: HashSet<&String> = contributions.iter().map(|(_, source)| source).collect();
+            for source in sources {
+                *stats.by_source.entry(source.clone()).or_default() += 1;
+            }
+        }
+        stats
+    }
+
+    /// Adds the entries of another table to this one.
+    /// For emojis that are in both tables, the policy decides which kinds and names are kept.
+    /// The lookup names follow the name policy as well.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry, MergePolicy, NamePolicy, KindPolicy};
+    /// use emoji_builder::emoji::EmojiKind;
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking"));
+    /// let mut other = EmojiTable::new();
+    /// other.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::EmojiPresentation]).with_name("thinking face"));
+    ///
+    /// table.merge_with(other, MergePolicy {
+    ///     kinds: KindPolicy::Union,
+    ///     names: NamePolicy::PreferLonger
+    /// });
+    /// assert_eq!(
+    ///     *table.get(&vec![0x1f914]).unwrap(),
+    ///     EmojiTableEntry::new(vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]).with_name("thinking face")
+    /// );
+    /// ```
+    pub fn merge_with(&mut self, other: EmojiTable, policy: MergePolicy) {
+        let EmojiTable { entries, names: lookup_names, provenance, ordering, versions, variation_bases, keywords, statuses, .. } = other;
+        // The other table's ordering is appended to this one
+        let ordering = ordering.into_iter()
+            .sorted_by_key(|(_, index)| *index)
+            .map(|(key, _)| key);
+        self.merge_entries(entries, lookup_names, provenance.into_iter().flatten(), ordering, versions, policy);
+        self.variation_bases.extend(variation_bases);
+        self.keywords.merge(&keywords);
+        for (key, status) in statuses {
+            self.statuses.entry(key).or_insert(status);
+        }
+    }
+
+    /// Like [EmojiTable::merge_with], but the other table is only borrowed.
+    /// Its entries are cloned one by one while they are merged, so the whole table is never
+    /// copied at once.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry, MergePolicy};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// let mut other = EmojiTable::new();
+    /// other.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking face"));
+    ///
+    /// table.merge_from(&other, MergePolicy::default());
+    /// assert_eq!(table.get(&vec![0x1f914]), other.get(&vec![0x1f914]));
+    /// ```
+    pub fn merge_from(&mut self, other: &EmojiTable, policy: MergePolicy) {
+        let ordering = other.ordering.iter()
+            .sorted_by_key(|(_, index)| **index)
+            .map(|(key, _)| key.clone());
+        self.merge_entries(
+            other.entries.iter().map(|(key, entry)| (key.clone(), entry.clone())),
+            other.names.iter().map(|(lookup_name, key)| (lookup_name.clone(), key.clone())),
+            other.provenance.iter().flatten().map(|(key, contributions)| (key.clone(), contributions.clone())),
+            ordering,
+            other.versions.iter().map(|(key, version)| (key.clone(), *version)),
+            policy
+        );
+        self.variation_bases.extend(other.variation_bases.iter().copied());
+        self.keywords.merge(&other.keywords);
+        for (key, status) in &other.statuses {
+            self.statuses.entry(key.clone()).or_insert_with(|| status.clone());
+        }
+    }
+
+    /// Merges the parts of another table (see [EmojiTable::merge_with]).
+    /// The ordering has to be in the order of the other table.
+    fn merge_entries<E, L, P, O, V>(&mut self, entries: E, lookup_names: L, provenance: P, ordering: O, versions: V, policy: MergePolicy)
+        where E: IntoIterator<Item=(EmojiTableKey, EmojiTableEntry)>,
+              L: IntoIterator<Item=(String, EmojiTableKey)>,
+              P: IntoIterator<Item=(EmojiTableKey, Vec<(EntryField, String)>)>,
+              O: IntoIterator<Item=EmojiTableKey>,
+              V: IntoIterator<Item=(EmojiTableKey, EmojiVersion)> {
+        for (key, EmojiTableEntry { kinds: other_kinds, name: other_name }) in entries {
+            match self.entries.get_mut(&key) {
+                Some(entry) => {
+                    Self::merge_kinds(&mut entry.kinds, other_kinds, policy.kinds);
+                    Self::merge_names(&mut entry.name, other_name, policy.names);
+                }
+                None => {
+                    let mut kinds = Vec::with_capacity(other_kinds.len());
+                    other_kinds.into_iter().for_each(|kind| Self::add_kind(&mut kinds, Some(kind)));
+                    self.entries.insert(key, EmojiTableEntry { kinds, name: other_name });
+                    self.short_ids.invalidate();
+                    self.prefix_index.invalidate();
+                }
+            }
+        }
+
+        for (lookup_name, key) in lookup_names {
+            if policy.names == NamePolicy::PreferOther || !self.names.contains_key(&lookup_name) {
+                self.names.insert(lookup_name, key);
+            }
+        }
+
+        if let Some(own_provenance) = &mut self.provenance {
+            for (key, contributions) in provenance {
+                own_provenance.entry(key).or_default().extend(contributions);
+            }
+        }
+
+        for key in ordering {
+            self.add_ordering(key);
+        }
+
+        for (key, version) in versions {
+            self.update_version(&key, version);
+        }
+    }
+
+    fn merge_kinds(kinds: &mut Vec<EmojiKind>, other_kinds: Vec<EmojiKind>, policy: KindPolicy) {
+        // Older versions (and EmojiTable::insert) didn't keep the kinds sorted
+        EmojiKind::sort_unique(kinds);
+        match policy {
+            KindPolicy::Union => other_kinds.into_iter()
+                .for_each(|kind| Self::add_kind(kinds, Some(kind))),
+            KindPolicy::PreferSelf => if kinds.is_empty() {
+                other_kinds.into_iter().for_each(|kind| Self::add_kind(kinds, Some(kind)));
+            },
+            KindPolicy::PreferOther => if !other_kinds.is_empty() {
+                kinds.clear();
+                other_kinds.into_iter().for_each(|kind| Self::add_kind(kinds, Some(kind)));
+            }
+        }
+    }
+
+    fn merge_names(name: &mut Option<Name>, other_name: Option<Name>, policy: NamePolicy) {
+        let other_name = match other_name {
+            Some(other_name) if !other_name.as_str().trim().is_empty() => other_name,
+            _ => return
+        };
+        let replace = match (&name, policy) {
+            (None, _) => true,
+            (Some(_), NamePolicy::PreferSelf) => false,
+            (Some(_), NamePolicy::PreferOther) => true,
+            (Some(name), NamePolicy::PreferLonger) => other_name.as_str().chars().count() > name.as_str().chars().count()
+        };
+        if replace {
+            *name = Some(other_name);
+        }
     }
 
 
@@ -387,61 +1489,479 @@ impl EmojiTable {
     /// The syntax of these files is:
     /// `Codepoint ; ("component"|"fully-qualified"|"minimally-qualified"|"unqualified") # Emoji "E"Version Emoji name`
     pub fn expand_descriptions_from_test_data<I: BufRead>(&mut self, reader: I) -> Result<(), Error> {
-        lazy_static! {
-            static ref EMOJI_TEST_REGEX: Regex = Regex::new(&format!(r"^{}\s*;\s*{}\s*#\s*{}$",
-                                               EMOJI_SEQUENCE_SPACE_REGEX,
-                                               EMOJI_STATUS_REGEX,
-                                               EMOJI_NAME_REGEX)
-            ).unwrap();
-        };
-        for line in reader.lines().flatten() {
+        self.expand_descriptions_from_test_data_with_source(reader, Self::UNLABELLED_SOURCE)
+    }
+
+    /// Like [EmojiTable::expand_descriptions_from_test_data], but the names will be attributed to
+    /// the given source (if the table tracks them).
+    pub fn expand_descriptions_from_test_data_with_source<I: BufRead>(&mut self, reader: I, source: &str) -> Result<(), Error> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => continue
+            };
             let line = line.trim();
             // Only check if it's not a comment/empty line
             if !line.starts_with('#') & !line.is_empty() {
                 // Try to match the line
-                if let Some(captures) = (&*EMOJI_TEST_REGEX as &Regex).captures(line) {
-                    // Extract information
-                    let codepoints: Vec<_> = Self::get_codepoint_sequence(captures.get(1).unwrap().as_str());
-                    let status = captures.get(5).unwrap().as_str();
-                    let _emoji = captures.get(6);
-                    let _version = captures.get(7).unwrap();
-                    let name = captures.get(8).unwrap().as_str();
+                if let Some(parsed) = Self::parse_test_data_line(line) {
+                    let codepoints = parsed.codepoints;
+                    let name = parsed.name;
 
-                    self.update_emoji(codepoints.clone(), None, Some(name));
+                    if let Some((major, minor)) = parsed.version {
+                        self.update_version(&codepoints, EmojiVersion { major, minor, approximate: false });
+                    }
+
+                    self.update_emoji(codepoints.clone(), None, Some(name), source);
+                    self.statuses.insert(codepoints.clone(), parsed.status.to_string());
 
                     // Don't insert unqualified codepoints unless we don't have a mapping for this name anyway
-                    if status != "unqualified" || self.get_by_name(&name).is_none() {
+                    let unqualified = ["unqualified", "non-fully-qualified"].contains(&parsed.status);
+                    if !unqualified || self.get_by_name(name).is_none() {
                         self.insert_lookup_name(&name, codepoints.clone());
                     }
                 } else {
-                    warn!("Malformed line in emoji-test.txt: {}", line);
+                    warn!("Malformed line {} in emoji-test.txt: {}", number + 1, line);
+                    self.report_malformed(source, format!("Malformed line {} in emoji-test.txt: {}", number + 1, line));
                 }
             }
         };
         Ok(())
     }
 
-    #[cfg(feature = "online")]
+    /// Adds names from a simple CSV file with lines like `F0000,party parrot`, e.g. for private
+    /// emoji sets that only use codepoints from the Private Use Area.
+    /// The codepoints of a sequence are separated by spaces or dashes and may start with `U+`.
+    /// The entries are of the kind [EmojiKind::Emoji].
+    ///
+    /// A header (`codepoint,name`), empty lines and lines starting with `#` are skipped.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let names = "codepoint,name\n\
+    ///              F0000,party parrot\n\
+    ///              U+F0001 U+F0002,Dancing, happy parrot\n";
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_names_from_csv(names.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.get(&vec![0xf0000]), Some(&EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("party parrot")));
+    /// assert_eq!(table.get_by_name("dancing, happy parrot").unwrap().0, vec![0xf0001, 0xf0002]);
+    /// assert_eq!(table.len(), 2);
+    /// ```
+    pub fn expand_names_from_csv<I: BufRead>(&mut self, reader: I) -> Result<(), Error> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(2, ',');
+            let codepoints: Option<EmojiTableKey> = fields.next()
+                .unwrap_or_default()
+                .split(|c: char| c.is_whitespace() || c == '-')
+                .filter(|codepoint| !codepoint.is_empty())
+                .map(|codepoint| codepoint.trim_start_matches("U+").trim_start_matches("u+"))
+                .map(|codepoint| u32::from_str_radix(codepoint, 16).ok())
+                .collect();
+            let name = fields.next().map(str::trim).filter(|name| !name.is_empty());
+            match (codepoints, name) {
+                (Some(codepoints), Some(name)) if !codepoints.is_empty() => {
+                    self.update_emoji(codepoints.clone(), Some(EmojiKind::Emoji), Some(name), Self::UNLABELLED_SOURCE);
+                    self.insert_lookup_name(name, codepoints);
+                },
+                // That's most likely the header
+                _ if number == 0 => (),
+                _ => {
+                    warn!("Malformed line {} in the names file: {}", number + 1, line);
+                    self.report_malformed(Self::UNLABELLED_SOURCE, format!("Malformed line {} in the names file: {}", number + 1, line));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [EmojiTable::expand_names_from_csv], but it reads the names from a file
+    pub fn expand_names_from_csv_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.expand_names_from_csv(BufReader::new(File::open(path)?))
+    }
+
+    /// Replaces the display names of some emojis, e.g. from a `names-override.txt` with lines like
+    /// `1F52B ; water gun` or `pistol ; water gun`.
+    /// The emoji is either given by its codepoints (separated by spaces, may start with `U+`)
+    /// or by one of its names (see [EmojiTable::get_by_name]).
+    ///
+    /// This should happen after all other expansions, as they'd replace the names again.
+    /// The new name becomes a lookup name as well, but the old one still finds the emoji
+    /// (so the file names don't have to be changed).
+    /// Emojis that aren't in the table are skipped with a warning.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x1f52b], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("pistol"));
+    /// table.insert_lookup_name("pistol", vec![0x1f52b]);
+    ///
+    /// let overrides = "# Our own names\n\
+    ///                  pistol ; water gun\n";
+    /// table.apply_name_overrides(overrides.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.get(&vec![0x1f52b]), Some(&EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("water gun")));
+    /// assert_eq!(table.get_by_name("water_gun").unwrap().0, vec![0x1f52b]);
+    /// assert_eq!(table.get_by_name("pistol").unwrap().0, vec![0x1f52b]);
+    /// ```
+    pub fn apply_name_overrides<R: BufRead>(&mut self, reader: R) -> Result<(), Error> {
+        self.apply_name_overrides_with_source(reader, Self::UNLABELLED_SOURCE)
+    }
+
+    /// Like [EmojiTable::apply_name_overrides], but the names will be attributed to the given
+    /// source (if the table tracks them).
+    pub fn apply_name_overrides_with_source<R: BufRead>(&mut self, reader: R, source: &str) -> Result<(), Error> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(2, ';');
+            let target = fields.next().unwrap_or_default().trim();
+            let name = match fields.next().map(str::trim) {
+                Some(name) if !name.is_empty() && !target.is_empty() => name,
+                _ => {
+                    warn!("Malformed line {} in the name overrides: {}", number + 1, line);
+                    self.report_malformed(source, format!("Malformed line {} in the name overrides: {}", number + 1, line));
+                    continue;
+                }
+            };
+            let key = match self.resolve_override(target) {
+                Some(key) => key,
+                None => {
+                    warn!("The emoji in line {} of the name overrides is not in the table: {}", number + 1, line);
+                    self.report_malformed(source, format!("The emoji in line {} of the name overrides is not in the table: {}", number + 1, line));
+                    continue;
+                }
+            };
+            // The old name keeps working for the file names
+            if let Some(old_name) = self.entries.get(&key).and_then(EmojiTableEntry::name) {
+                let old_lookup_name = Self::normalize_lookup_name(old_name.as_str());
+                self.names.entry(old_lookup_name).or_insert_with(|| key.clone());
+            }
+            self.update_emoji(key.clone(), None, Some(name), source);
+            self.insert_lookup_name(name, key);
+        }
+        Ok(())
+    }
+
+    /// Like [EmojiTable::apply_name_overrides], but it reads the overrides from a file
+    pub fn apply_name_overrides_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let source = path.as_ref().to_string_lossy().into_owned();
+        self.apply_name_overrides_with_source(BufReader::new(File::open(path)?), &source)
+    }
+
+    /// The key of the emoji that a line of the name overrides refers to, either by its codepoints
+    /// (with or without `U+FE0F`) or by its name
+    fn resolve_override(&self, target: &str) -> Option<EmojiTableKey> {
+        let codepoints: Option<EmojiTableKey> = target
+            .split_whitespace()
+            .map(|codepoint| codepoint.trim_start_matches("U+").trim_start_matches("u+"))
+            .map(|codepoint| u32::from_str_radix(codepoint, 16).ok())
+            .collect();
+        codepoints
+            .filter(|codepoints| !codepoints.is_empty())
+            .and_then(|codepoints| if self.entries.contains_key(&codepoints) {
+                Some(codepoints)
+            } else {
+                Some(Self::without_fe0f(&codepoints)).filter(|codepoints| self.entries.contains_key(codepoints))
+            })
+            .or_else(|| self.get_by_name(target).map(|(key, _)| key))
+    }
+
+    /// The search keywords of an emoji (ignoring `U+FE0F`), e.g. for a picker.
+    /// They're kept in lowercase and in the order in which they have been added.
+    pub fn keywords(&self, key: &[u32]) -> Vec<&str> {
+        self.keywords.get(&Self::without_fe0f(key)).iter()
+            .map(|keyword| keyword.as_ref())
+            .collect()
+    }
+
+    fn add_keyword(&mut self, key: &[u32], keyword: &str) {
+        self.keywords.add(Self::without_fe0f(key), keyword);
+    }
+
+    /// Finds the emojis whose name or keywords match a search term.
+    ///
+    /// The term is split into words and each of them has to be the beginning of a word of the
+    /// name or of one of the keywords (ignoring the case), so `thin fa` finds the thinking face.
+    /// Emojis that only differ in `U+FE0F` are only returned once (with the longer sequence).
+    /// The results are sorted by the ordering (see [EmojiTable::expand_ordering]) and then by their
+    /// sequence.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_keywords("1F914 ; face, thinking\n1F600 ; face, grin\n".as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.search("fac"), vec![vec![0x1f600], vec![0x1f914]]);
+    /// assert_eq!(table.search("Thin FACE"), vec![vec![0x1f914]]);
+    /// assert!(table.search("hand").is_empty());
+    /// ```
+    pub fn search(&self, term: &str) -> Vec<EmojiTableKey> {
+        let term = keywords::tokenize(term);
+        if term.is_empty() {
+            return vec![];
+        }
+        // The longest spelling of each emoji
+        let mut candidates: HashMap<EmojiTableKey, &EmojiTableKey> = HashMap::new();
+        for key in self.entries.keys().chain(self.keywords.keys()) {
+            let candidate = candidates.entry(Self::without_fe0f(key)).or_insert(key);
+            if key.len() > candidate.len() {
+                *candidate = key;
+            }
+        }
+        candidates.into_iter()
+            .filter(|(without_fe0f, key)| {
+                let name = self.entries.get(*key)
+                    .or_else(|| self.entries.get(without_fe0f))
+                    .and_then(EmojiTableEntry::name);
+                let words: Vec<String> = name.map(|name| name.as_str()).into_iter()
+                    .chain(self.keywords.get(without_fe0f).iter().map(|keyword| keyword.as_ref()))
+                    .flat_map(keywords::tokenize)
+                    .collect();
+                keywords::matches(&term, &words)
+            })
+            .map(|(_, key)| key.clone())
+            .sorted_by_key(|key| (self.ordering_index(key).unwrap_or(usize::MAX), key.clone()))
+            .collect()
+    }
+
+    /// Reads search keywords from a file with lines like `1F600 ; face, grin` (the sequence in hex,
+    /// optionally with `U+` prefixes, and the keywords separated by commas).
+    /// Empty lines and lines starting with `#` are skipped and the keywords are added to the ones
+    /// that the emojis already have.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let keywords = "# Custom keywords\n\
+    ///                 1F3F3 FE0F 200D 1F308 ; pride, rainbow, Flag\n";
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_keywords(keywords.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.keywords(&[0x1f3f3, 0x200d, 0x1f308]), vec!["pride", "rainbow", "flag"]);
+    /// ```
+    pub fn expand_keywords<I: BufRead>(&mut self, reader: I) -> Result<(), Error> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(2, ';');
+            let codepoints: Option<EmojiTableKey> = fields.next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|codepoint| codepoint.trim_start_matches("U+").trim_start_matches("u+"))
+                .map(|codepoint| u32::from_str_radix(codepoint, 16).ok())
+                .collect();
+            match (codepoints, fields.next()) {
+                (Some(codepoints), Some(keywords)) if !codepoints.is_empty() => {
+                    for keyword in keywords.split(',') {
+                        self.add_keyword(&codepoints, keyword);
+                    }
+                },
+                _ => {
+                    warn!("Malformed line {} in the keywords file: {}", number + 1, line);
+                    self.report_malformed(Self::UNLABELLED_SOURCE, format!("Malformed line {} in the keywords file: {}", number + 1, line));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the search keywords from CLDR's annotations (e.g.
+    /// [`common/annotations/en.xml`](https://github.com/unicode-org/cldr/blob/main/common/annotations/en.xml)):
+    /// ```xml
+    /// <annotation cp="🤔">face | thinking</annotation>
+    /// <annotation cp="🤔" type="tts">thinking face</annotation>
+    /// ```
+    /// The names for text-to-speech (`type="tts"`) are left out, as they usually are the names
+    /// of the emojis already.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let annotations = r#"<ldml><annotations>
+    ///     <annotation cp="🤔">face | thinking</annotation>
+    ///     <annotation cp="🤔" type="tts">thinking face</annotation>
+    /// </annotations></ldml>"#;
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_keywords_from_cldr(annotations.as_bytes()).unwrap();
+    ///
+    /// assert_eq!(table.keywords(&[0x1f914]), vec!["face", "thinking"]);
+    /// ```
+    pub fn expand_keywords_from_cldr<R: Read>(&mut self, mut reader: R) -> Result<(), Error> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+
+        // The sequence and whether it's a name for text-to-speech
+        let mut annotation: Option<(EmojiTableKey, bool)> = None;
+        let mut text = String::new();
+        for token in xmlparser::Tokenizer::from(content.as_str()) {
+            match token.map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))? {
+                xmlparser::Token::ElementStart { local, .. } if local.as_str() == "annotation" => {
+                    annotation = Some((vec![], false));
+                    text.clear();
+                },
+                xmlparser::Token::Attribute { local, value, .. } => if let Some((key, tts)) = &mut annotation {
+                    match local.as_str() {
+                        "cp" => *key = keywords::unescape_xml(value.as_str()).chars().map(u32::from).collect(),
+                        "type" => *tts = value.as_str() == "tts",
+                        _ => ()
+                    }
+                },
+                xmlparser::Token::Text { text: part } if annotation.is_some() => {
+                    text.push_str(part.as_str());
+                },
+                xmlparser::Token::ElementEnd { end: xmlparser::ElementEnd::Close(_, local), .. } if local.as_str() == "annotation" => {
+                    match annotation.take() {
+                        Some((key, false)) if !key.is_empty() => for keyword in keywords::unescape_xml(&text).split('|') {
+                            self.add_keyword(&key, keyword);
+                        },
+                        Some((_, true)) => (),
+                        _ => {
+                            warn!("Annotation without an emoji: {}", text);
+                            self.report_malformed(Self::UNLABELLED_SOURCE, format!("Annotation without an emoji: {}", text));
+                        }
+                    }
+                },
+                _ => ()
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the search keywords from a file, either CLDR's annotations (`.xml`, see
+    /// [EmojiTable::expand_keywords_from_cldr]) or a keywords file (see [EmojiTable::expand_keywords])
+    pub fn expand_keywords_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xml")) {
+            self.expand_keywords_from_cldr(reader)
+        } else {
+            self.expand_keywords(reader)
+        }
+    }
+
+    /// Parses a single (non-comment) line of an `emoji-test.txt`-like file.
+    ///
+    /// Both, the emoji itself and its version (`E<major>.<minor>`) are optional
+    /// as older files (before Emoji 12.0) and some vendor files don't include them.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let line = "1F3F3 FE0F 200D 1F308 ; fully-qualified # 🏳️‍🌈 E4.0 rainbow flag";
+    /// let parsed = EmojiTable::parse_test_data_line(line).unwrap();
+    /// assert_eq!(parsed.codepoints, vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    /// assert_eq!(parsed.status, "fully-qualified");
+    /// assert_eq!(parsed.emoji, Some("🏳️‍🌈"));
+    /// assert_eq!(parsed.version, Some((4, 0)));
+    /// assert_eq!(parsed.name, "rainbow flag");
+    ///
+    /// let line = "1F600 ; fully-qualified # grinning face";
+    /// let parsed = EmojiTable::parse_test_data_line(line).unwrap();
+    /// assert_eq!(parsed.emoji, None);
+    /// assert_eq!(parsed.version, None);
+    /// assert_eq!(parsed.name, "grinning face");
+    /// ```
+    pub fn parse_test_data_line(line: &str) -> Option<TestDataLine<'_>> {
+        lazy_static! {
+            static ref EMOJI_TEST_REGEX: Regex = Regex::new(&format!(r"^{}\s*;\s*{}\s*#\s*(?P<rest>.*)$",
+                                               EMOJI_SEQUENCE_SPACE_REGEX,
+                                               EMOJI_STATUS_REGEX)
+            ).unwrap();
+            static ref VERSION_REGEX: Regex = Regex::new(r"^E(?P<major>\d+)\.(?P<minor>\d+)\s+(?P<name>.*)$").unwrap();
+        }
+        let captures = EMOJI_TEST_REGEX.captures(line)?;
+        let codepoints = Self::get_codepoint_sequence(captures.get(1).unwrap().as_str());
+        let status = captures.get(5).unwrap().as_str();
+        let rest = captures.name("rest").unwrap().as_str().trim();
+
+        // The emoji is only recognized as such if it's the same as the codepoints
+        // (otherwise it's the first word of the name)
+        let mut split = rest.splitn(2, char::is_whitespace);
+        let (emoji, rest) = match (split.next(), split.next()) {
+            (Some(emoji), Some(name)) if Self::is_same_sequence(emoji, &codepoints) => (Some(emoji), name.trim_start()),
+            _ => (None, rest)
+        };
+
+        let (version, name) = match VERSION_REGEX.captures(rest) {
+            Some(captures) => (
+                Some((
+                    captures["major"].parse().ok()?,
+                    captures["minor"].parse().ok()?
+                )),
+                captures.name("name").unwrap().as_str()
+            ),
+            None => (None, rest)
+        };
+
+        let name = name.trim();
+        if name.is_empty() {
+            None
+        } else {
+            Some(TestDataLine {
+                codepoints,
+                status,
+                emoji,
+                version,
+                name
+            })
+        }
+    }
+
+    /// Checks whether a string consists of the given codepoints (ignoring `U+FE0F`)
+    fn is_same_sequence(emoji: &str, codepoints: &[u32]) -> bool {
+        let emoji = emoji.chars()
+            .map(|character| character as u32)
+            .filter(|codepoint| *codepoint != 0xfe0f);
+        let codepoints = codepoints.iter()
+            .copied()
+            .filter(|codepoint| *codepoint != 0xfe0f);
+        emoji.eq(codepoints)
+    }
+
+    #[cfg(feature = "cache")]
     const EMOJI_DATA: &'static str = "emoji-data.txt";
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     const EMOJI_SEQUENCES: &'static str = "emoji-sequences.txt";
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     const EMOJI_ZWJ_SEQUENCES: &'static str = "emoji-zwj-sequences.txt";
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     const EMOJI_VARIATION_SEQUENCES: &'static str = "emoji-variation-sequences.txt";
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     const EMOJI_TEST: &'static str = "emoji-test.txt";
-    #[cfg(feature = "online")]
-    const DATA_FILES: [&'static str; 3] = [
+    #[cfg(feature = "cache")]
+    const EMOJI_ORDERING_URL: &'static str = "https://unicode.org/emoji/charts/emoji-ordering.txt";
+    #[cfg(feature = "cache")]
+    const DATA_FILES: [&'static str; 4] = [
         Self::EMOJI_DATA,
         Self::EMOJI_SEQUENCES,
-        Self::EMOJI_ZWJ_SEQUENCES
+        Self::EMOJI_ZWJ_SEQUENCES,
+        Self::EMOJI_VARIATION_SEQUENCES
     ];
 
 
 
     /// This function is <del>equivalent to</del> creating an `EmojiTable` and directly calling `expand_all_online` on it.`
-    #[cfg(feature = "online")]
+    #[cfg(feature = "fetch")]
     pub fn load_online(version: (u32, u32)) -> Result<EmojiTable, ExpansionError> {
         let mut table = EmojiTable::new();
         match table.expand_all_online(version) {
@@ -460,16 +1980,38 @@ impl EmojiTable {
     /// - `emoji-data.txt`: The main list of single emoji codepoints.
     /// - `emoji-sequences.txt`: All sequences of codepoints _without_ the `U+200D` character.
     /// - `emoji-zwj-sequences.txt`: All sequences of codepoints _with_ the `U+200D` character.
+    /// - `emoji-variation-sequences.txt`: The codepoints that have a text and an emoji style.
     /// - `emoji-test.txt`: This file will be used to get the names of all emojis.
-    #[cfg(feature = "online")]
+    ///
+    /// The files are cached in [OnlineCache::shared].
+    #[cfg(feature = "fetch")]
     pub fn expand_all_online(&mut self, version: (u32, u32)) -> Result<(), ExpansionError> {
+        self.expand_all_online_with_cache(version, OnlineCache::shared())
+    }
+
+    /// Like [EmojiTable::expand_all_online], but with a specific cache
+    #[cfg(feature = "fetch")]
+    pub fn expand_all_online_with_cache(&mut self, version: (u32, u32), cache: &OnlineCache) -> Result<(), ExpansionError> {
         let client_builder = reqwest::blocking::ClientBuilder::new();
         let client = client_builder.build()?;
+        self.expand_all_online_with_fetcher_and_cache(&client, version, cache)
+    }
+
+    /// Like [EmojiTable::expand_all_online], but the files are downloaded with the given
+    /// [HttpFetcher] instead of the bundled client (which is only available with the `fetch`
+    /// feature)
+    #[cfg(feature = "cache")]
+    pub fn expand_all_online_with(&mut self, fetcher: &dyn HttpFetcher, version: (u32, u32)) -> Result<(), ExpansionError> {
+        self.expand_all_online_with_fetcher_and_cache(fetcher, version, OnlineCache::shared())
+    }
 
-        let test_expansion_result = self.expand_descriptions_from_test_online(&client, version);
+    /// Like [EmojiTable::expand_all_online_with], but with a specific cache
+    #[cfg(feature = "cache")]
+    pub fn expand_all_online_with_fetcher_and_cache(&mut self, fetcher: &dyn HttpFetcher, version: (u32, u32), cache: &OnlineCache) -> Result<(), ExpansionError> {
+        let test_expansion_result = self.expand_descriptions_from_test_online(fetcher, cache, version);
 
         let errors: Vec<_> = Self::DATA_FILES.iter()
-            .map(|file| self.expand_data_online(&client, version, file))
+            .map(|file| self.expand_data_online(fetcher, cache, version, file))
             .chain(vec![test_expansion_result])
             .filter_map(|result| result.err())
             .collect();
@@ -480,29 +2022,54 @@ impl EmojiTable {
         }
     }
 
-    #[cfg(feature = "online")]
-    fn expand_data_online(&mut self, client: &reqwest::blocking::Client, version: (u32, u32), file: &'static str) -> Result<(), ExpansionError> {
-        let reader = Self::get_data_file_online(client, version, file)?;
-        self.expand(reader)?;
+    #[cfg(feature = "cache")]
+    fn expand_data_online(&mut self, fetcher: &dyn HttpFetcher, cache: &OnlineCache, version: (u32, u32), file: &'static str) -> Result<(), ExpansionError> {
+        let reader = Self::get_data_file_online(fetcher, cache, version, file)?;
+        self.expand_with_source(reader, &Self::build_url(version, file))?;
         Ok(())
     }
 
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     #[inline]
-    fn get_data_file_online(client: &reqwest::blocking::Client, version: (u32, u32), file: &'static str) -> Result<std::io::Cursor<bytes::Bytes>, reqwest::Error> {
-        let request = client.get(&Self::build_url(version, file)).send();
-        let bytes = request?.bytes()?;
+    fn get_data_file_online(fetcher: &dyn HttpFetcher, cache: &OnlineCache, version: (u32, u32), file: &'static str) -> Result<std::io::Cursor<bytes::Bytes>, ExpansionError> {
+        let url = Self::build_url(version, file);
+        let bytes = cache.get(fetcher, &url, &format!("{}.{}", version.0, version.1), file)?;
         Ok(std::io::Cursor::new(bytes))
     }
 
-    #[cfg(feature = "online")]
-    fn expand_descriptions_from_test_online(&mut self, client: &reqwest::blocking::Client, version: (u32, u32)) -> Result<(), ExpansionError> {
-        let reader = Self::get_data_file_online(client, version, Self::EMOJI_TEST)?;
-        self.expand_descriptions_from_test_data(reader).map_err(|err| err.into())
+    #[cfg(feature = "cache")]
+    fn expand_descriptions_from_test_online(&mut self, fetcher: &dyn HttpFetcher, cache: &OnlineCache, version: (u32, u32)) -> Result<(), ExpansionError> {
+        let reader = Self::get_data_file_online(fetcher, cache, version, Self::EMOJI_TEST)?;
+        self.expand_descriptions_from_test_data_with_source(reader, &Self::build_url(version, Self::EMOJI_TEST))
+            .map_err(|err| err.into())
+    }
+
+    /// Reads the emoji ordering from <https://unicode.org/emoji/charts/emoji-ordering.txt>
+    /// (see [EmojiTable::expand_ordering]).
+    /// It's not versioned, so it's not part of [EmojiTable::expand_all_online].
+    #[cfg(feature = "fetch")]
+    pub fn expand_ordering_online(&mut self) -> Result<(), ExpansionError> {
+        self.expand_ordering_online_with_cache(OnlineCache::shared())
+    }
+
+    /// Like [EmojiTable::expand_ordering_online], but with a specific cache.
+    /// As the file is not versioned, it's stored as `charts/emoji-ordering.txt`.
+    #[cfg(feature = "fetch")]
+    pub fn expand_ordering_online_with_cache(&mut self, cache: &OnlineCache) -> Result<(), ExpansionError> {
+        let client = reqwest::blocking::ClientBuilder::new().build()?;
+        self.expand_ordering_online_with(&client, cache)
+    }
+
+    /// Like [EmojiTable::expand_ordering_online_with_cache], but with a custom [HttpFetcher]
+    #[cfg(feature = "cache")]
+    pub fn expand_ordering_online_with(&mut self, fetcher: &dyn HttpFetcher, cache: &OnlineCache) -> Result<(), ExpansionError> {
+        let bytes = cache.get(fetcher, Self::EMOJI_ORDERING_URL, "charts", "emoji-ordering.txt")?;
+        self.expand_ordering(std::io::Cursor::new(bytes))?;
+        Ok(())
     }
 
     /// A simple helper function to build the URLs for the different files.
-    #[cfg(feature = "online")]
+    #[cfg(feature = "cache")]
     #[inline]
     fn build_url(version: (u32, u32), file: &'static str) -> String {
         if version.0 >= 13 && [Self::EMOJI_DATA, Self::EMOJI_VARIATION_SEQUENCES].contains(&file) {
@@ -514,76 +2081,255 @@ impl EmojiTable {
 
     /// A helper function to get emojis by their name directly
     #[cfg(test)]
-    fn get_codepoint_by_name(&self, name: &str) -> Vec<u32> {
+    pub(crate) fn get_codepoint_by_name(&self, name: &str) -> Vec<u32> {
         self.get_by_name(name).unwrap().0.clone()
     }
 
-    // https://stackoverflow.com/a/34969944
+    /// Returns a human-readable breakdown of an entry, i.e. its name, kinds and (if they are
+    /// tracked) the sources that contributed to it.
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji_tables::EmojiTable;
+    ///
+    /// let mut table = EmojiTable::with_provenance();
+    /// table.expand_from_file("test_files/tables/emoji-zwj-sequences.txt").unwrap();
+    ///
+    /// let explanation = table.explain(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    /// assert!(explanation.contains("Emoji_ZWJ_Sequence (from emoji-zwj-sequences.txt)"));
+    /// ```
+    pub fn explain(&self, key: &[u32]) -> String {
+        let sequence = key.iter()
+            .map(|codepoint| format!("{:X}", codepoint))
+            .join(" ");
+        let mut explanation = format!("{} ({})\n", sequence, Emoji::from(key).display_emoji());
+        match self.entries.get(key) {
+            Some(entry) => {
+                explanation.push_str(&format!("Name: {}\n", entry.name().map(Name::as_str).unwrap_or("-")));
+                explanation.push_str(&format!("Kinds: {}\n", entry.kinds().iter()
+                    .map(|kind| kind.to_string())
+                    .join(", ")));
+                let keywords = self.keywords(key);
+                if !keywords.is_empty() {
+                    explanation.push_str(&format!("Keywords: {}\n", keywords.join(", ")));
+                }
+                match self.get_provenance(key) {
+                    Some(contributions) => {
+                        explanation.push_str("Sources:\n");
+                        for (field, source) in contributions {
+                            let line = match field {
+                                EntryField::Kind(kind) => format!("  Kind {} (from {})\n", kind.to_string(), source),
+                                EntryField::Name(name) => format!("  Name \"{}\" (from {})\n", name, source)
+                            };
+                            explanation.push_str(&line);
+                        }
+                    },
+                    None => if self.tracks_provenance() {
+                        explanation.push_str("Sources: -\n");
+                    } else {
+                        explanation.push_str("Sources: not tracked\n");
+                    }
+                }
+            },
+            None => explanation.push_str("Not in the table\n")
+        }
+        explanation
+    }
+
     /// Validates whether all emojis from this table can be found in a collection of emojis and vice versa.
     /// As it is usually not a problem to have additional emojis in a font, these are not returned as an error.
     /// # Returns
     /// `(result, additional_emojis)` with `result` being either `Ok(())`, if all emojis con be found
     /// or `Err(missing_emojis)` with the emojis that are missing.
     /// `additional_emojis` are those emojis that are found in the font, but not in the table; might be empty.
+    #[deprecated(note = "Use validate_with, which returns a ValidationResult")]
     pub fn validate(&self, emojis: &HashSet<EmojiTableKey>, ignore_fe0f: bool) -> (Result<(), Vec<Emoji>>, Vec<Emoji>) {
+        let result = self.validate_with(emojis, ValidationOptions { ignore_fe0f, max_version: None });
+        (
+            if result.missing.is_empty() {
+                Ok(())
+            } else {
+                Err(result.missing)
+            },
+            result.additional
+        )
+    }
+
+    /// Validates whether all emojis from this table can be found in a collection of emojis and vice versa.
+    /// The collection only has to borrow the sequences (e.g. `emojis.iter().map(|emoji| &emoji.sequence)`).
+    ///
+    /// Only entries with a name and at least one kind are validated, as other entries are likely
+    /// not emojis (or don't matter, as they aren't in `emoji-test.txt`).
+    /// The missing emojis are sorted by the table's ordering (if there is one, see
+    /// [EmojiTable::expand_ordering]) and then by their sequence, the additional ones only by their
+    /// sequence.
+    /// # Examples
+    /// ```
+    /// use std::collections::HashSet;
+    /// use emoji_builder::emoji::EmojiKind;
+    /// use emoji_builder::emoji_tables::{EmojiTable, EmojiTableEntry, ValidationOptions};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.insert(vec![0x2615, 0xfe0f], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("hot beverage"));
+    /// table.insert(vec![0x1f1e9, 0x1f1ea], EmojiTableEntry::new(vec![EmojiKind::EmojiFlagSequence]).with_name("flag: Germany"));
+    ///
+    /// let emojis: HashSet<Vec<u32>> = vec![vec![0x2615], vec![0x1f914]].into_iter().collect();
+    /// let result = table.validate_with(&emojis, ValidationOptions { ignore_fe0f: true, max_version: None });
+    ///
+    /// assert!(!result.is_complete());
+    /// assert_eq!(result.missing[0].sequence, vec![0x1f1e9, 0x1f1ea]);
+    /// assert_eq!(result.missing_by_kind[&EmojiKind::EmojiFlagSequence], 1);
+    /// assert_eq!(result.additional[0].sequence, vec![0x1f914]);
+    /// ```
+    pub fn validate_with<'e, I: IntoIterator<Item=&'e EmojiTableKey>>(&self, emojis: I, options: ValidationOptions) -> ValidationResult {
         // TODO: Introduce the status to filter out unqualified emojis/non-RGI
-        let table_emojis = self.0
+        let table_emojis: HashMap<Cow<[u32]>, &EmojiTableEntry> = self.entries
             .iter()
             // Only validate emojis that we have names for (i.e. they're in emoji-test.txt. Otherwise they won't matter anyway)
             // And those with an EmojiKind, as otherwise it's likely not an emoji
-            .filter_map(|(key, (kinds, name))| if name.is_some() && !kinds.is_empty() {
-                Some(key)
-            } else {
-                None
-            });
-        let table_emojis: HashSet<EmojiTableKey> = if ignore_fe0f {
-            table_emojis
-                .map(|emoji| emoji.iter()
-                    .filter_map(|codepoint| if *codepoint != 0xfe0f {
-                        Some(*codepoint)
-                    } else {
-                        None
-                    } )
-                    .collect_vec()
-                )
-                .collect()
-        } else {
-            table_emojis.cloned().collect()
-        };
-        let missing = table_emojis
-            .difference(emojis)
-            .filter_map(|emoji| Emoji::from_u32_sequence(emoji.clone(), Some(&self)).ok()).collect_vec();
-        let emojis = if ignore_fe0f {
-            // FIXME: We don't actually want to clone here
-            emojis.clone()
-        } else {
-            emojis.iter()
-                .map(|emoji| emoji.iter()
-                    .filter_map(|codepoint| if *codepoint != 0xfe0f {
-                        Some(*codepoint)
-                    } else {
-                        None
-                    } )
-                    .collect_vec()
-                )
-                .collect()
-        };
-        let additional = emojis
-            .difference(&table_emojis)
+            .filter(|(_, entry)| entry.name.is_some() && !entry.kinds.is_empty())
+            .map(|(key, entry)| (normalize_key(key, options.ignore_fe0f), entry))
+            .collect();
+        let emojis: HashSet<Cow<[u32]>> = normalized_keys(emojis, options.ignore_fe0f);
+
+        let mut missing_by_kind = HashMap::new();
+        let missing = table_emojis.iter()
+            .filter(|(key, _)| !emojis.contains(*key))
+            // Emojis that are newer than the targeted version aren't expected
+            .filter(|(key, _)| options.max_version
+                .and_then(|max_version| self.version(key).map(|version| version.as_tuple() <= max_version))
+                .unwrap_or(true))
+            .map(|(key, entry)| {
+                for kind in &entry.kinds {
+                    *missing_by_kind.entry(kind.clone()).or_insert(0) += 1;
+                }
+                Emoji {
+                    sequence: key.to_vec(),
+                    name: entry.name().map(Name::to_string),
+                    kinds: Some(entry.kinds.clone()),
+                    svg_path: None,
+                }
+            })
+            .sorted_by_key(|emoji| (self.ordering_index(&emoji.sequence).unwrap_or(usize::MAX), emoji.sequence.clone()))
+            .collect_vec();
+        let additional = emojis.iter()
+            .filter(|key| !table_emojis.contains_key(*key))
             // Note: it doesn't make sense here to provide this emoji table as we have just found out
             // that it doesn't contain this particular emoji!
-            .filter_map(|emoji| Emoji::from_u32_sequence(emoji.clone(), None).ok()).collect_vec();
-        (
-            if missing.is_empty() {
-                Ok(())
-            } else {
-                Err(missing)
-            },
-            additional
-        )
+            .filter_map(|key| Emoji::from_u32_sequence(key.to_vec(), None).ok())
+            .sorted_by(|a, b| a.sequence.cmp(&b.sequence))
+            .collect_vec();
+
+        ValidationResult {
+            missing,
+            additional,
+            missing_by_kind,
+            options,
+        }
+    }
+
+    /// Cross-checks the lookup names with a collection of emojis (e.g. the images of multiple
+    /// directories). A name only resolves to the sequence it has been inserted with last, so
+    /// images that are found by their names might silently end up with another sequence:
+    /// - [Inconsistency::NameCollision]: the name of an entry resolves to another sequence and
+    ///   both have an image
+    /// - [Inconsistency::SequenceCollision]: a name resolves to a sequence with multiple images
+    /// - [Inconsistency::MissingEmoji]: a name resolves to a sequence from the Private Use Area
+    ///   without an image (other emojis are already covered by [EmojiTable::validate_with])
+    ///
+    /// Sequences are compared and reported without `U+FE0F`.
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use emoji_builder::emoji::Emoji;
+    /// use emoji_builder::emoji_tables::{EmojiTable, Inconsistency};
+    ///
+    /// let mut table = EmojiTable::new();
+    /// table.expand_names_from_csv("F0000,party parrot\nF0001,party parrot\n".as_bytes()).unwrap();
+    ///
+    /// let mut first = Emoji::from(vec![0xf0000]);
+    /// first.set_path(PathBuf::from("emoji_uf0000.svg"));
+    /// let mut second = Emoji::from(vec![0xf0001]);
+    /// second.set_path(PathBuf::from("party_parrot.svg"));
+    ///
+    /// assert_eq!(table.check_consistency(&[first, second]), vec![Inconsistency::NameCollision {
+    ///     name: "party parrot".to_string(),
+    ///     named: vec![0xf0000],
+    ///     resolved: vec![0xf0001],
+    /// }]);
+    /// ```
+    pub fn check_consistency<'e, I: IntoIterator<Item=&'e Emoji>>(&self, emojis: I) -> Vec<Inconsistency> {
+        let mut images: HashMap<Cow<[u32]>, Vec<PathBuf>> = HashMap::new();
+        for emoji in emojis {
+            let paths = images.entry(normalize_key(&emoji.sequence, true)).or_default();
+            paths.extend(emoji.svg_path.clone());
+        }
+
+        let mut inconsistencies = Vec::new();
+        for (key, entry) in &self.entries {
+            let name = match &entry.name {
+                Some(name) => name,
+                None => continue
+            };
+            let named = normalize_key(key, true);
+            let resolved = match self.names.get(name.key()) {
+                Some(resolved) => normalize_key(resolved, true),
+                None => continue
+            };
+            if named != resolved && images.contains_key(&*named) && images.contains_key(&*resolved) {
+                inconsistencies.push(Inconsistency::NameCollision {
+                    name: name.to_string(),
+                    named: named.into_owned(),
+                    resolved: resolved.into_owned(),
+                });
+            }
+        }
+
+        let mut names: HashMap<Cow<[u32]>, Vec<&String>> = HashMap::new();
+        for (name, key) in &self.names {
+            names.entry(normalize_key(key, true)).or_default().push(name);
+        }
+        for (sequence, names) in names {
+            let names = names.into_iter().sorted().cloned().collect_vec();
+            match images.get(&*sequence) {
+                Some(paths) if paths.len() > 1 => inconsistencies.push(Inconsistency::SequenceCollision {
+                    names,
+                    sequence: sequence.into_owned(),
+                    paths: paths.iter().sorted().cloned().collect(),
+                }),
+                None if Emoji::from(sequence.to_vec()).is_private_use() => inconsistencies.push(Inconsistency::MissingEmoji {
+                    names,
+                    sequence: sequence.into_owned(),
+                }),
+                _ => ()
+            }
+        }
+
+        inconsistencies.sort();
+        inconsistencies.dedup();
+        inconsistencies
+    }
+}
+
+/// Removes `U+FE0F` from a sequence if `ignore_fe0f` is set.
+/// Sequences without it are only borrowed.
+fn normalize_key(key: &[u32], ignore_fe0f: bool) -> Cow<'_, [u32]> {
+    if ignore_fe0f && key.contains(&0xfe0f) {
+        Cow::Owned(key.iter()
+            .filter(|codepoint| **codepoint != 0xfe0f)
+            .copied()
+            .collect())
+    } else {
+        Cow::Borrowed(key)
     }
 }
 
+/// Normalizes all keys with [normalize_key]
+pub(crate) fn normalized_keys<'a, I: IntoIterator<Item=&'a EmojiTableKey>>(keys: I, ignore_fe0f: bool) -> HashSet<Cow<'a, [u32]>> {
+    keys.into_iter()
+        .map(|key| normalize_key(key, ignore_fe0f))
+        .collect()
+}
+
 impl Default for EmojiTable {
     fn default() -> Self {
         EmojiTable::new()
@@ -591,24 +2337,368 @@ impl Default for EmojiTable {
 }
 
 impl From<HashMap<EmojiTableKey, EmojiTableEntry>> for EmojiTable {
-    fn from(table: HashMap<Vec<u32>, (Vec<EmojiKind>, Option<String>), RandomState>) -> Self {
+    fn from(table: HashMap<EmojiTableKey, EmojiTableEntry>) -> Self {
         let names_map: HashMap<String, EmojiTableKey> = table
             .iter()
-            .filter_map(|(codepoint, (_, name))| name.as_ref().map(|name| (name.clone(), codepoint.clone())))
+            .filter_map(|(codepoint, entry)| entry.name().map(|name| (name.key().to_owned(), codepoint.clone())))
             .collect();
-        EmojiTable(table, names_map)
+        EmojiTable {
+            entries: table,
+            names: names_map,
+            ..EmojiTable::new()
+        }
     }
 }
 
 impl From<EmojiTable> for HashMap<EmojiTableKey, EmojiTableEntry> {
     fn from(table: EmojiTable) -> Self {
-        table.0
+        table.entries
     }
 }
 
 impl AsRef<HashMap<EmojiTableKey, EmojiTableEntry>> for EmojiTable {
-    fn as_ref(&self) -> &HashMap<Vec<u32>, (Vec<EmojiKind>, Option<String>), RandomState> {
-        &self.0
+    fn as_ref(&self) -> &HashMap<EmojiTableKey, EmojiTableEntry> {
+        &self.entries
+    }
+}
+
+/// Adds the entries of the right table, using [MergePolicy::default]
+impl Add for EmojiTable {
+    type Output = EmojiTable;
+
+    fn add(mut self, other: EmojiTable) -> Self::Output {
+        self.merge_with(other, MergePolicy::default());
+        self
+    }
+}
+
+/// Adds the entries of the right table, using [MergePolicy::default]
+impl AddAssign for EmojiTable {
+    fn add_assign(&mut self, other: EmojiTable) {
+        self.merge_with(other, MergePolicy::default());
+    }
+}
+
+/// Decides how two tables are merged in [EmojiTable::merge_with]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergePolicy {
+    /// What happens with the kinds of an emoji that is in both tables
+    pub kinds: KindPolicy,
+    /// What happens with the name of an emoji that is in both tables
+    pub names: NamePolicy,
+}
+
+/// How the kinds of an emoji are merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KindPolicy {
+    /// Keep the kinds of both tables
+    Union,
+    /// Only use the other table's kinds if there aren't any in this one
+    PreferSelf,
+    /// Replace the kinds with the other table's kinds (if there are any)
+    PreferOther,
+}
+
+/// How the names of an emoji are merged.
+/// Missing or empty names never replace existing ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// Keep the name of this table
+    PreferSelf,
+    /// Use the name of the other table
+    PreferOther,
+    /// Use the longer of both names (this table's one if they have the same length)
+    PreferLonger,
+}
+
+/// The default policy takes the kinds from both tables and the names from the other table,
+/// just like [EmojiTable::expand] does for new files.
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy {
+            kinds: KindPolicy::Union,
+            names: NamePolicy::PreferOther,
+        }
+    }
+}
+
+/// A gender that can be added to an emoji
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gender {
+    Female,
+    Male,
+}
+
+impl Gender {
+    /// The codepoint of the gender-specific variant of U+1F9D1 (`person`)
+    fn person(self) -> u32 {
+        match self {
+            Gender::Female => 0x1f469,
+            Gender::Male => 0x1f468,
+        }
+    }
+
+    /// The gender sign that is appended to other emojis
+    fn sign(self) -> u32 {
+        match self {
+            Gender::Female => 0x2640,
+            Gender::Male => 0x2642,
+        }
+    }
+}
+
+/// The skin tones and gender that have been removed from a name in [EmojiTable::get_base_by_name]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameModifiers {
+    /// The skin tone modifiers (U+1F3FB to U+1F3FF) in the order of the name
+    pub skin_tones: Vec<u32>,
+    pub gender: Option<Gender>,
+}
+
+impl NameModifiers {
+    /// Whether nothing has been removed from the name
+    pub fn is_empty(&self) -> bool {
+        self.skin_tones.is_empty() && self.gender.is_none()
+    }
+
+    /// Builds the full sequence from the base emoji.
+    ///
+    /// A gender replaces U+1F9D1 (`person`) at the beginning by `woman`/`man` or is otherwise
+    /// appended as a gender sign (e.g. `person running` → `woman running`).
+    /// A single skin tone is added to the first element of the sequence. Multiple skin tones are
+    /// added to the people in the sequence (e.g. in `kiss`) in order.
+    pub fn apply(&self, base: &[u32]) -> Vec<u32> {
+        const ZWJ: u32 = 0x200d;
+        const PEOPLE: [u32; 3] = [0x1f9d1, 0x1f468, 0x1f469];
+
+        let mut elements: Vec<Vec<u32>> = base.split(|codepoint| *codepoint == ZWJ)
+            .map(|element| element.to_vec())
+            .collect();
+
+        if let Some(gender) = self.gender {
+            match elements.first_mut() {
+                Some(first) if first.first() == Some(&0x1f9d1) => first[0] = gender.person(),
+                _ => elements.push(vec![gender.sign(), 0xfe0f])
+            }
+        }
+
+        let people = elements.iter()
+            .positions(|element| element.first().is_some_and(|first| PEOPLE.contains(first)))
+            .collect_vec();
+        let targets = if self.skin_tones.len() > 1 && people.len() >= self.skin_tones.len() {
+            people
+        } else {
+            (0..elements.len()).collect_vec()
+        };
+        for (skin_tone, target) in self.skin_tones.iter().zip(targets) {
+            let element = &mut elements[target];
+            if element.is_empty() {
+                continue;
+            }
+            // The modifier replaces the emoji presentation selector
+            if element.get(1) == Some(&0xfe0f) {
+                element.remove(1);
+            }
+            element.insert(1, *skin_tone);
+        }
+
+        elements.join(&ZWJ)
+    }
+}
+
+/// Options for [EmojiTable::validate_with]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ValidationOptions {
+    /// Compare the sequences without `U+FE0F` (on both sides)
+    pub ignore_fe0f: bool,
+    /// Only expect the emojis that have been introduced in this Emoji version or before
+    /// (see [EmojiTable::restrict_to_version])
+    pub max_version: Option<(u32, u32)>,
+}
+
+/// The result of [EmojiTable::validate_with]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationResult {
+    /// The emojis from the table that are not in the collection
+    pub missing: Vec<Emoji>,
+    /// The emojis from the collection that are not in the table.
+    /// It's usually not a problem to have them in a font.
+    pub additional: Vec<Emoji>,
+    /// The number of missing emojis for each kind (an emoji may have multiple kinds)
+    pub missing_by_kind: HashMap<EmojiKind, usize>,
+    /// The options that have been used
+    pub options: ValidationOptions,
+}
+
+impl ValidationResult {
+    /// Whether all emojis from the table have been found
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// A one-line summary like `2 missing (1 Emoji_Flag_Sequence, 1 Emoji_ZWJ_Sequence), 1 additional`
+    pub fn summary(&self) -> String {
+        if self.missing.is_empty() {
+            format!("0 missing, {} additional", self.additional.len())
+        } else {
+            let by_kind = self.missing_by_kind.iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(kind, count)| format!("{} {}", count, kind.to_string()))
+                .join(", ");
+            format!("{} missing ({}), {} additional", self.missing.len(), by_kind, self.additional.len())
+        }
+    }
+}
+
+/// The result of [EmojiTable::restrict_to_version]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionRestriction {
+    /// The emojis that are kept (including the downgraded ones)
+    pub emojis: Vec<Emoji>,
+    /// The number of excluded emojis for each Emoji version
+    pub excluded: BTreeMap<(u32, u32), usize>,
+    /// The excluded sequences that use the image of an older one instead (and that sequence)
+    pub downgraded: Vec<(EmojiTableKey, EmojiTableKey)>,
+}
+
+/// A summary of an [EmojiTable] (see [EmojiTable::stats])
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// The number of sequences (with and without `U+FE0F`)
+    pub entries: usize,
+    /// The number of entries with a name
+    pub named: usize,
+    /// The number of entries of each kind (an entry may have multiple kinds)
+    pub by_kind: BTreeMap<EmojiKind, usize>,
+    /// The number of sequences with each status in `emoji-test.txt`
+    pub by_status: BTreeMap<String, usize>,
+    /// The highest Emoji version of any sequence
+    pub max_version: Option<(u32, u32)>,
+    /// The number of entries that each source contributed to (if the table tracks them)
+    pub by_source: BTreeMap<String, usize>,
+}
+
+impl Display for TableStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn counts<K: Display>(counts: impl Iterator<Item = (K, usize)>) -> String {
+            let counts = counts.map(|(key, count)| format!("{}: {}", key, count)).join(", ");
+            if counts.is_empty() {
+                String::from("-")
+            } else {
+                counts
+            }
+        }
+        writeln!(f, "Entries:         {}", self.entries)?;
+        writeln!(f, "Named:           {}", self.named)?;
+        writeln!(f, "Highest version: {}", self.max_version
+            .map(|(major, minor)| format!("{}.{}", major, minor))
+            .unwrap_or_else(|| String::from("-")))?;
+        writeln!(f, "Kinds:           {}", counts(self.by_kind.iter().map(|(kind, count)| (kind.to_string(), *count))))?;
+        writeln!(f, "Statuses:        {}", counts(self.by_status.iter().map(|(status, count)| (status, *count))))?;
+        writeln!(f, "Sources:         {}", counts(self.by_source.iter().map(|(source, count)| (source, *count))))
+    }
+}
+
+/// A lookup name that doesn't match the emojis (see [EmojiTable::check_consistency])
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Inconsistency {
+    /// The name of an emoji resolves to another emoji, but both have an image
+    NameCollision {
+        name: String,
+        /// The emoji that has this name in the table
+        named: EmojiTableKey,
+        /// The emoji that is found by this name (e.g. for an image named after it)
+        resolved: EmojiTableKey,
+    },
+    /// The emoji that is found by these (lookup) names has multiple images
+    SequenceCollision {
+        names: Vec<String>,
+        sequence: EmojiTableKey,
+        paths: Vec<PathBuf>,
+    },
+    /// The emoji that is found by these (lookup) names has no image
+    MissingEmoji {
+        names: Vec<String>,
+        sequence: EmojiTableKey,
+    },
+}
+
+impl Inconsistency {
+    /// The affected emoji (for a name collision, it's the one that doesn't get its name)
+    pub fn sequence(&self) -> &[u32] {
+        match self {
+            Inconsistency::NameCollision { named, .. } => named,
+            Inconsistency::SequenceCollision { sequence, .. } => sequence,
+            Inconsistency::MissingEmoji { sequence, .. } => sequence,
+        }
+    }
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::NameCollision { name, named, resolved } =>
+                write!(f, "\"{}\" is the name of {:X?}, but it resolves to {:X?}", name, named, resolved),
+            Inconsistency::SequenceCollision { names, sequence, paths } =>
+                write!(f, "\"{}\" resolves to {:X?}, which has multiple images: {:?}", names.join("\", \""), sequence, paths),
+            Inconsistency::MissingEmoji { names, sequence } =>
+                write!(f, "\"{}\" resolves to {:X?}, which has no image", names.join("\", \""), sequence),
+        }
+    }
+}
+
+/// A single emoji of an emojibase dataset (only the fields that are used)
+#[derive(Debug, Deserialize)]
+struct EmojibaseRecord {
+    /// Older versions of emojibase call it annotation
+    #[serde(alias = "annotation")]
+    label: String,
+    hexcode: String,
+    #[serde(default, alias = "keywords")]
+    tags: Vec<String>,
+    /// 0 for text and 1 for emoji presentation
+    #[serde(rename = "type", default)]
+    presentation: Option<u8>,
+    #[serde(default)]
+    group: Option<u32>,
+    #[serde(default)]
+    order: Option<u64>,
+    #[serde(default)]
+    skins: Vec<EmojibaseRecord>,
+}
+
+impl EmojibaseRecord {
+    /// The group of skin tones and hair styles
+    const COMPONENT_GROUP: u32 = 2;
+
+    /// Parses the hexcode (e.g. `1F469-200D-1F52C`)
+    fn sequence(&self) -> Option<EmojiTableKey> {
+        self.hexcode.split('-')
+            .map(|codepoint| u32::from_str_radix(codepoint.trim(), 16).ok())
+            .collect::<Option<EmojiTableKey>>()
+            .filter(|sequence| !sequence.is_empty())
+    }
+
+    fn kinds(&self, key: &[u32], modifier_base: bool, modifier: bool) -> Vec<EmojiKind> {
+        // Basic emojis often have a U+FE0F, but they're still no sequences
+        let key = EmojiTable::without_fe0f(key);
+        let mut kinds = Emoji::from(key.clone()).guess_kinds().unwrap_or_default();
+        if key.len() == 1 {
+            if self.presentation == Some(1) {
+                kinds.push(EmojiKind::EmojiPresentation);
+            }
+            if modifier_base {
+                kinds.push(EmojiKind::ModifierBase);
+            }
+        }
+        if modifier && !key.contains(&0x200d) {
+            kinds.retain(|kind| *kind != EmojiKind::EmojiSequence);
+            kinds.push(EmojiKind::EmojiModifierSequence);
+        }
+        if self.group == Some(Self::COMPONENT_GROUP) {
+            kinds.push(EmojiKind::EmojiComponent);
+        }
+        kinds
     }
 }
 
@@ -619,6 +2709,79 @@ pub enum EmojiTableError {
     KeyNotFound(EmojiTableKey),
 }
 
+/// A single entry of an `emoji-test.txt`-like file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestDataLine<'a> {
+    /// The codepoint sequence of the emoji
+    pub codepoints: EmojiTableKey,
+    /// The status (e.g. `fully-qualified`)
+    pub status: &'a str,
+    /// The emoji itself (if it's included in the line)
+    pub emoji: Option<&'a str>,
+    /// The Emoji version in which this emoji has been introduced (if it's included in the line)
+    pub version: Option<(u32, u32)>,
+    /// The name of the emoji
+    pub name: &'a str,
+}
+
+/// The Emoji version in which an emoji has been introduced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EmojiVersion {
+    /// The major version (e.g. `13` for Emoji 13.1)
+    pub major: u32,
+    /// The minor version (e.g. `1` for Emoji 13.1)
+    pub minor: u32,
+    /// Whether the version is only known for a range of codepoints that this emoji is part of
+    pub approximate: bool,
+}
+
+impl EmojiVersion {
+    /// The major and minor version
+    pub fn as_tuple(&self) -> (u32, u32) {
+        (self.major, self.minor)
+    }
+}
+
+impl FromStr for EmojiVersion {
+    type Err = ConfigError;
+
+    /// Parses a version like `5.0`, `E5.0` or `13` (i.e. `13.0`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConfigError::InvalidValue(String::from("max_emoji_version"), s.to_string());
+        let mut parts = s.trim().trim_start_matches(['E', 'e']).splitn(2, '.');
+        let major = parts.next().unwrap_or_default().parse().map_err(|_| invalid())?;
+        let minor = match parts.next() {
+            Some(minor) => minor.parse().map_err(|_| invalid())?,
+            None => 0
+        };
+        Ok(EmojiVersion { major, minor, approximate: false })
+    }
+}
+
+/// How a single codepoint is displayed without a variation selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Presentation {
+    /// As an emoji (i.e. it has the `Emoji_Presentation` property), so `U+FE0F` is redundant
+    Emoji,
+    /// As text, so it needs `U+FE0F` to be displayed as an emoji
+    Text,
+}
+
+/// A sequence that isn't spelled like the fully-qualified emoji (see [EmojiTable::presentation_issue])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresentationIssue {
+    /// A codepoint with [Presentation::Text] isn't followed by `U+FE0F`
+    MissingVs16 {
+        /// The sequence with the right variation selectors
+        canonical: EmojiTableKey
+    },
+    /// A codepoint with [Presentation::Emoji] is followed by `U+FE0F`
+    RedundantVs16 {
+        /// The sequence with the right variation selectors
+        canonical: EmojiTableKey
+    },
+}
+
 /// The status of an emoji according to `emoji-test.txt` (currently not used
 pub enum _EmojiTestStatus {
     /// ? TODO: Find out, what this is
@@ -638,9 +2801,12 @@ pub enum ExpansionError {
     Io(std::io::Error),
     /// Wrapper for multiple errors
     Multiple(Vec<ExpansionError>),
-    #[cfg(feature = "online")]
+    #[cfg(feature = "fetch")]
     /// Wrappter for [reqwest::Error]
     Reqwest(reqwest::Error),
+    #[cfg(feature = "cache")]
+    /// A file couldn't be downloaded (see [HttpFetcher])
+    Fetch(FetchError),
 }
 
 impl From<std::io::Error> for ExpansionError {
@@ -655,14 +2821,21 @@ impl From<Vec<ExpansionError>> for ExpansionError {
     }
 }
 
-#[cfg(feature = "online")]
+#[cfg(feature = "fetch")]
 impl From<reqwest::Error> for ExpansionError {
     fn from(err: reqwest::Error) -> Self {
         ExpansionError::Reqwest(err)
     }
 }
 
-#[cfg(feature = "online")]
+#[cfg(feature = "cache")]
+impl From<FetchError> for ExpansionError {
+    fn from(err: FetchError) -> Self {
+        ExpansionError::Fetch(err)
+    }
+}
+
+#[cfg(feature = "fetch")]
 #[test]
 fn test_online() {
     let table = EmojiTable::load_online((13, 0)).unwrap();
@@ -677,14 +2850,45 @@ fn test_online() {
     assert_eq!(table.get_codepoint_by_name("woman medium SkiN ToNe WhITe hair"), woman_medium_skin_tone_white_hair);
 
     assert_eq!(
-        table.get_by_name("woman: medium skin tone, white hair").unwrap().1.0,
+        table.get_by_name("woman: medium skin tone, white hair").unwrap().1.kinds(),
         vec![EmojiKind::EmojiZwjSequence]
     );
 
     assert!(table.get_by_name("woman").is_some());
 
     assert_eq!(
-        table.get_by_name("woman").unwrap().1.0,
+        table.get_by_name("woman").unwrap().1.kinds(),
         vec![EmojiKind::Emoji, EmojiKind::ModifierBase, EmojiKind::EmojiPresentation, EmojiKind::Other(String::from("extended pictographic"))]
     );
 }
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_online_with_fetcher() {
+    use crate::online_cache::MockFetcher;
+
+    let fetcher = MockFetcher::new()
+        .serve("https://unicode.org/Public/13.0.0/ucd/emoji/emoji-data.txt", "\"data\"",
+               "1F617 ; Emoji # E1.0 [1] (😗) kissing face\n")
+        .serve("https://unicode.org/Public/emoji/13.0/emoji-sequences.txt", "\"sequences\"", "")
+        .serve("https://unicode.org/Public/emoji/13.0/emoji-zwj-sequences.txt", "\"zwj\"", "")
+        .serve("https://unicode.org/Public/emoji/13.0/emoji-test.txt", "\"test\"",
+               "1F617 ; fully-qualified # 😗 E1.0 kissing face\n");
+    let cache = OnlineCache::memory_only();
+
+    // emoji-variation-sequences.txt is missing, but the other files are still used
+    let mut table = EmojiTable::new();
+    match table.expand_all_online_with_fetcher_and_cache(&fetcher, (13, 0), &cache) {
+        Err(ExpansionError::Multiple(errors)) => assert!(matches!(errors.as_slice(), [ExpansionError::Fetch(FetchError::Status(404))])),
+        other => panic!("Expected a missing file, got {:?}", other)
+    }
+    assert_eq!(table.get_codepoint_by_name("kissing face"), vec![0x1f617]);
+    assert_eq!(table.get_by_name("kissing face").unwrap().1.kinds(), vec![EmojiKind::Emoji]);
+    assert_eq!(fetcher.requests().len(), 5);
+
+    // The files come from the cache the second time
+    let mut table = EmojiTable::new();
+    assert!(table.expand_all_online_with_fetcher_and_cache(&fetcher, (13, 0), &cache).is_err());
+    assert_eq!(table.get_codepoint_by_name("kissing face"), vec![0x1f617]);
+    assert_eq!(fetcher.requests().len(), 6);
+}
@@ -0,0 +1,230 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! A C-compatible interface for the emoji tables and the parsing of file names
+//! (enabled with the `ffi` feature). The header `emoji_builder.h` is generated by `cbindgen`
+//! during the build and written to the build script's `OUT_DIR`
+//! (`target/<profile>/build/emoji_builder-<hash>/out`).
+//!
+//! The crate is only built as an `rlib` by default, a shared library can be built with
+//! ```text
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! # Ownership
+//! - Strings are passed as NUL-terminated UTF-8 in both directions.
+//! - Everything that is returned as a pointer is owned by the caller and has to be freed with
+//!   the matching function: [emoji_table_free], [emoji_string_free] or [emoji_sequence_free].
+//!   It must not be freed with `free()`.
+//! - Arguments are only borrowed for the duration of the call.
+//! - Errors are reported by returning `NULL`.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+
+use crate::emoji::Emoji;
+use crate::emoji_tables::{EmojiTable, EmojiTableEntry};
+
+/// An opaque handle to an [EmojiTable]
+pub struct EmojiTableHandle(EmojiTable);
+
+/// Borrows a C string as UTF-8 (`None` if it's `NULL` or not valid UTF-8)
+unsafe fn borrow_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        None
+    } else {
+        CStr::from_ptr(string).to_str().ok()
+    }
+}
+
+/// Passes the ownership of a string to the caller (`NULL` if it contains a NUL character)
+fn into_c_string(string: String) -> *mut c_char {
+    CString::new(string)
+        .map(CString::into_raw)
+        .unwrap_or(null_mut())
+}
+
+/// Loads an emoji table from the given files (see [EmojiTable::from_files]).
+///
+/// Returns `NULL` if one of the paths is invalid or a file can't be read.
+/// The table has to be freed with [emoji_table_free].
+/// # Safety
+/// `paths` has to point to `count` valid, NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_table_load_files(paths: *const *const c_char, count: usize) -> *mut EmojiTableHandle {
+    if paths.is_null() && count > 0 {
+        return null_mut();
+    }
+    let paths: Option<Vec<&str>> = (0..count)
+        .map(|index| borrow_str(*paths.add(index)))
+        .collect();
+    let table = match paths {
+        Some(paths) => EmojiTable::from_files(&paths),
+        None => return null_mut()
+    };
+    match table {
+        Ok(table) => Box::into_raw(Box::new(EmojiTableHandle(table))),
+        Err(err) => {
+            error!("Couldn't load the emoji table: {}", err);
+            null_mut()
+        }
+    }
+}
+
+/// Frees a table that has been returned by [emoji_table_load_files]. `NULL` is ignored.
+/// # Safety
+/// `table` has to come from [emoji_table_load_files] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_table_free(table: *mut EmojiTableHandle) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+/// Returns the name of the emoji with the given sequence or `NULL` if it's not in the table
+/// or has no name.
+/// The name has to be freed with [emoji_string_free].
+/// # Safety
+/// `table` has to be a valid table and `sequence` has to point to `len` codepoints.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_table_get_name(table: *const EmojiTableHandle, sequence: *const u32, len: usize) -> *mut c_char {
+    if table.is_null() || (sequence.is_null() && len > 0) {
+        return null_mut();
+    }
+    let key = if len > 0 {
+        std::slice::from_raw_parts(sequence, len).to_vec()
+    } else {
+        vec![]
+    };
+    match (*table).get(&key).and_then(EmojiTableEntry::name) {
+        Some(name) => into_c_string(name.to_string()),
+        None => null_mut()
+    }
+}
+
+/// Parses a file name like `emoji_u1f914.png` or `1f3f3_fe0f_200d_1f308.svg` into a codepoint
+/// sequence (see [Emoji::from_sequence]) and stores its length in `len`.
+///
+/// Returns `NULL` if there are no codepoints in the file name.
+/// The sequence has to be freed with [emoji_sequence_free].
+/// # Safety
+/// `file_name` has to be a valid, NUL-terminated string and `len` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_parse_filename(file_name: *const c_char, len: *mut usize) -> *mut u32 {
+    if len.is_null() {
+        return null_mut();
+    }
+    *len = 0;
+    let emoji = match borrow_str(file_name).map(|file_name| Emoji::from_sequence(file_name, None)) {
+        Some(Ok(emoji)) => emoji,
+        _ => return null_mut()
+    };
+    let sequence = emoji.sequence.into_boxed_slice();
+    *len = sequence.len();
+    Box::into_raw(sequence) as *mut u32
+}
+
+/// Converts a name to the format that is used for looking up emojis by their name
+/// (see [EmojiTable::normalize_lookup_name]).
+///
+/// Returns `NULL` if the name is not valid UTF-8.
+/// The result has to be freed with [emoji_string_free].
+/// # Safety
+/// `name` has to be a valid, NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_normalize_name(name: *const c_char) -> *mut c_char {
+    match borrow_str(name) {
+        Some(name) => into_c_string(EmojiTable::normalize_lookup_name(name)),
+        None => null_mut()
+    }
+}
+
+/// Frees a string that has been returned by this library. `NULL` is ignored.
+/// # Safety
+/// `string` has to come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Frees a sequence that has been returned by [emoji_parse_filename]. `NULL` is ignored.
+/// # Safety
+/// `sequence` and `len` have to be the exact values from [emoji_parse_filename] and the
+/// sequence must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn emoji_sequence_free(sequence: *mut u32, len: usize) {
+    if !sequence.is_null() {
+        drop(Box::from_raw(std::slice::from_raw_parts_mut(sequence, len) as *mut [u32]));
+    }
+}
+
+#[test]
+fn test_table() {
+    let paths = [
+        CString::new("test_files/tables/emoji-data.txt").unwrap(),
+        CString::new("test_files/tables/emoji-zwj-sequences.txt").unwrap(),
+    ];
+    let path_pointers: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr()).collect();
+
+    unsafe {
+        let table = emoji_table_load_files(path_pointers.as_ptr(), path_pointers.len());
+        assert!(!table.is_null());
+
+        let rainbow_flag = [0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+        let name = emoji_table_get_name(table, rainbow_flag.as_ptr(), rainbow_flag.len());
+        assert!(!name.is_null());
+        assert_eq!(CStr::from_ptr(name).to_str().unwrap(), "rainbow flag");
+        emoji_string_free(name);
+
+        let unknown = [0x10ffff];
+        assert!(emoji_table_get_name(table, unknown.as_ptr(), unknown.len()).is_null());
+
+        emoji_table_free(table);
+
+        let missing = CString::new("test_files/tables/missing.txt").unwrap();
+        assert!(emoji_table_load_files(&missing.as_ptr(), 1).is_null());
+    }
+}
+
+#[test]
+fn test_parse_filename() {
+    unsafe {
+        let mut len = 0;
+        let file_name = CString::new("emoji_u1f3f3_fe0f_200d_1f308.png").unwrap();
+        let sequence = emoji_parse_filename(file_name.as_ptr(), &mut len);
+        assert!(!sequence.is_null());
+        assert_eq!(std::slice::from_raw_parts(sequence, len), &[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+        emoji_sequence_free(sequence, len);
+
+        let file_name = CString::new("not an emoji").unwrap();
+        assert!(emoji_parse_filename(file_name.as_ptr(), &mut len).is_null());
+        assert_eq!(len, 0);
+    }
+}
+
+#[test]
+fn test_normalize_name() {
+    unsafe {
+        let name = CString::new("Woman: Medium Skin-Tone").unwrap();
+        let normalized = emoji_normalize_name(name.as_ptr());
+        assert_eq!(CStr::from_ptr(normalized).to_str().unwrap(), "woman medium skin tone");
+        emoji_string_free(normalized);
+
+        assert!(emoji_normalize_name(std::ptr::null()).is_null());
+    }
+}
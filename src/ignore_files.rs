@@ -0,0 +1,98 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Excludes files from the image directories, e.g. editor backups or experiments.
+//!
+//! Each image directory may contain an [IGNORE_FILE] with patterns in the syntax of `.gitignore`.
+//! Hidden files and common backup files ([DEFAULT_PATTERNS]) are always ignored,
+//! unless ignoring is disabled altogether (`--no-ignore`).
+
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// The file with the patterns of the files that are ignored in an image directory
+pub const IGNORE_FILE: &str = ".emojiignore";
+
+/// The patterns that are ignored in every image directory: hidden files
+/// (including Emacs' lock files like `.#1f600.svg`) and backup or swap files of editors
+pub const DEFAULT_PATTERNS: &[&str] = &[".*", "*~", "#*#", "*.bak", "*.orig", "*.swp", "*.tmp"];
+
+/// The files that are ignored in an image directory
+pub struct IgnoreRules(Option<Gitignore>);
+
+impl IgnoreRules {
+    /// The default patterns and the ones from the directory's [IGNORE_FILE] (if there is one).
+    /// Invalid patterns are skipped with a warning.
+    pub fn for_dir<P: AsRef<Path>>(dir: P) -> IgnoreRules {
+        let dir = dir.as_ref();
+        let mut builder = GitignoreBuilder::new(dir);
+        for pattern in DEFAULT_PATTERNS {
+            builder.add_line(None, pattern).unwrap();
+        }
+        let ignore_file = dir.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            if let Some(err) = builder.add(&ignore_file) {
+                warn!("Invalid pattern(s) in {:?}: {}", ignore_file, err);
+            }
+        }
+        match builder.build() {
+            Ok(gitignore) => IgnoreRules(Some(gitignore)),
+            Err(err) => {
+                warn!("Couldn't read the ignored files of {:?}, all files are used: {}", dir, err);
+                IgnoreRules(None)
+            }
+        }
+    }
+
+    /// No file is ignored
+    pub fn disabled() -> IgnoreRules {
+        IgnoreRules(None)
+    }
+
+    /// Whether the file is ignored. Its path has to be inside the directory.
+    pub fn is_ignored<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        match &self.0 {
+            Some(gitignore) => gitignore.matched(path, path.is_dir()).is_ignore(),
+            None => false
+        }
+    }
+}
+
+#[test]
+fn test_ignore_rules() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(IGNORE_FILE), "# Experiments\n*_draft.svg\n!.keep.svg\n").unwrap();
+
+    let rules = IgnoreRules::for_dir(dir.path());
+    let ignored = |file: &str| rules.is_ignored(dir.path().join(file));
+    assert!(!ignored("1f600.svg"));
+    assert!(ignored("1f600.svg~"));
+    assert!(ignored(".#1f600.svg"));
+    assert!(ignored("1f600.svg.bak"));
+    assert!(ignored("1f600_draft.svg"));
+    assert!(ignored(IGNORE_FILE));
+    // Negations override the defaults
+    assert!(!ignored(".keep.svg"));
+
+    // Without an ignore file, only the defaults apply
+    let other = tempfile::tempdir().unwrap();
+    let rules = IgnoreRules::for_dir(other.path());
+    assert!(rules.is_ignored(other.path().join("1f600.svg~")));
+    assert!(!rules.is_ignored(other.path().join("1f600_draft.svg")));
+
+    assert!(!IgnoreRules::disabled().is_ignored(dir.path().join("1f600.svg~")));
+}
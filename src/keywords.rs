@@ -0,0 +1,127 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Search keywords of emojis (e.g. from CLDR's annotations), which pickers use to find them.
+//!
+//! Most keywords are shared by lots of emojis (`face`, `hand`, `person`, ...), so each of them is
+//! only stored once and the emojis refer to it.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::emoji_tables::EmojiTableKey;
+
+/// The keywords of the emojis in an [EmojiTable](crate::emoji_tables::EmojiTable).
+/// The sequences are stored without `U+FE0F` and the keywords in lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Keywords {
+    /// Every keyword (once)
+    interned: HashSet<Arc<str>>,
+    keywords: HashMap<EmojiTableKey, Vec<Arc<str>>>,
+}
+
+impl Keywords {
+    /// Adds a keyword to a sequence (without `U+FE0F`) if it doesn't have it already
+    pub(crate) fn add(&mut self, key: EmojiTableKey, keyword: &str) {
+        let keyword = keyword.trim().to_lowercase();
+        if keyword.is_empty() {
+            return;
+        }
+        let keyword = match self.interned.get(keyword.as_str()) {
+            Some(interned) => interned.clone(),
+            None => {
+                let interned: Arc<str> = Arc::from(keyword);
+                self.interned.insert(interned.clone());
+                interned
+            }
+        };
+        let keywords = self.keywords.entry(key).or_default();
+        if !keywords.contains(&keyword) {
+            keywords.push(keyword);
+        }
+    }
+
+    /// The keywords of a sequence (without `U+FE0F`) in the order in which they have been added
+    pub(crate) fn get(&self, key: &[u32]) -> &[Arc<str>] {
+        self.keywords.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// All sequences that have keywords
+    pub(crate) fn keys(&self) -> impl Iterator<Item=&EmojiTableKey> {
+        self.keywords.keys()
+    }
+
+    /// Adds the keywords of another table
+    pub(crate) fn merge(&mut self, other: &Keywords) {
+        for (key, keywords) in &other.keywords {
+            for keyword in keywords {
+                self.add(key.clone(), keyword);
+            }
+        }
+    }
+
+    /// The number of different keywords
+    #[cfg(test)]
+    pub(crate) fn interned_len(&self) -> usize {
+        self.interned.len()
+    }
+}
+
+/// Splits a name, a keyword or a search term into lowercase words
+/// # Examples
+/// ```
+/// use emoji_builder::keywords::tokenize;
+///
+/// assert_eq!(tokenize("Thinking Face"), vec!["thinking", "face"]);
+/// assert_eq!(tokenize("flag: Côte d’Ivoire"), vec!["flag", "côte", "d", "ivoire"]);
+/// ```
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether every token of the search term is the beginning of one of the words
+pub(crate) fn matches(term: &[String], words: &[String]) -> bool {
+    term.iter().all(|token| words.iter().any(|word| word.starts_with(token.as_str())))
+}
+
+/// Replaces the predefined entities of XML (which is all that CLDR's annotations use)
+pub(crate) fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[test]
+fn test_interning() {
+    let mut keywords = Keywords::default();
+    keywords.add(vec![0x1f600], "face");
+    keywords.add(vec![0x1f603], "Face ");
+    keywords.add(vec![0x1f603], "face");
+    keywords.add(vec![0x1f603], " ");
+    assert_eq!(keywords.interned_len(), 1);
+    assert_eq!(keywords.get(&[0x1f603]).len(), 1);
+    assert!(Arc::ptr_eq(&keywords.get(&[0x1f600])[0], &keywords.get(&[0x1f603])[0]));
+    assert!(keywords.get(&[0x1f914]).is_empty());
+
+    let term = tokenize("gri fa");
+    assert!(matches(&term, &tokenize("grinning face")));
+    assert!(!matches(&term, &tokenize("grinning cat")));
+    assert_eq!(unescape_xml("&lt;3 &amp; &amp;amp;"), "<3 & &amp;");
+}
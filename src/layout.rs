@@ -0,0 +1,314 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Builds the layout of an emoji keyboard/picker from the groups and subgroups in an
+//! `emoji-test.txt`-like file.
+//!
+//! Emojis with skin tone modifiers are not listed on their own, but as variants of their base
+//! emoji (as pickers usually show them in a popup).
+//!
+//! The layout can also be used to write an `emoji-test.txt`-like file for an emoji set
+//! (see [Layout::write_test_data]).
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::emoji::Emoji;
+use crate::emoji_tables::{EmojiTable, Name};
+
+const GROUP_PREFIX: &str = "# group:";
+const SUBGROUP_PREFIX: &str = "# subgroup:";
+/// The status of emojis that are not part of the layout (e.g. the ones of a private set) in
+/// files written by [Layout::write_test_data]
+pub const CUSTOM_STATUS: &str = "custom";
+const CUSTOM_GROUP: &str = "Custom";
+const CUSTOM_SUBGROUP: &str = "custom";
+
+/// The whole layout, i.e. a list of groups (which are usually shown as tabs)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Layout {
+    pub groups: Vec<LayoutGroup>,
+}
+
+/// A group, e.g. `Smileys & Emotion`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutGroup {
+    pub name: String,
+    pub subgroups: Vec<LayoutSubgroup>,
+}
+
+/// A subgroup, e.g. `face-smiling`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutSubgroup {
+    pub name: String,
+    pub emojis: Vec<LayoutEmoji>,
+}
+
+/// A single emoji in the layout
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutEmoji {
+    /// The codepoints in hexadecimal, separated by spaces (e.g. `1f44b 1f3fb`)
+    pub sequence: String,
+    /// The emoji itself
+    pub emoji: String,
+    pub name: String,
+    /// The file name of the glyph (`emoji_u<sequence without U+FE0F>.png`)
+    pub glyph: String,
+    /// The search keywords (see [Layout::add_keywords])
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// The same emoji with different skin tones
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<LayoutEmoji>,
+}
+
+impl Layout {
+    /// Reads the layout from an `emoji-test.txt`-like file.
+    /// Only fully-qualified emojis are used and components (i.e. the skin tones themselves) are
+    /// left out. Emojis before the first group or subgroup are put into one without a name
+    /// and empty groups and subgroups are removed.
+    pub fn from_test_data<R: BufRead>(reader: R) -> std::io::Result<Layout> {
+        let mut layout = Layout::default();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if let Some(group) = line.strip_prefix(GROUP_PREFIX) {
+                layout.groups.push(LayoutGroup {
+                    name: group.trim().to_string(),
+                    subgroups: vec![],
+                });
+            } else if let Some(subgroup) = line.strip_prefix(SUBGROUP_PREFIX) {
+                layout.current_group().subgroups.push(LayoutSubgroup {
+                    name: subgroup.trim().to_string(),
+                    emojis: vec![],
+                });
+            } else if !line.starts_with('#') && !line.is_empty() {
+                match EmojiTable::parse_test_data_line(line) {
+                    Some(parsed) if parsed.status == "fully-qualified" => {
+                        let emoji = LayoutEmoji::new(&parsed.codepoints, parsed.name);
+                        layout.current_subgroup().add(emoji);
+                    }
+                    Some(_) => (),
+                    None => warn!("Malformed line in emoji-test.txt: {}", line)
+                }
+            }
+        }
+        // Groups like `Component` only consist of emojis that are left out
+        for group in &mut layout.groups {
+            group.subgroups.retain(|subgroup| !subgroup.emojis.is_empty());
+        }
+        layout.groups.retain(|group| !group.subgroups.is_empty());
+        Ok(layout)
+    }
+
+    fn current_group(&mut self) -> &mut LayoutGroup {
+        if self.groups.is_empty() {
+            self.groups.push(LayoutGroup {
+                name: String::new(),
+                subgroups: vec![],
+            });
+        }
+        self.groups.last_mut().unwrap()
+    }
+
+    fn current_subgroup(&mut self) -> &mut LayoutSubgroup {
+        let group = self.current_group();
+        if group.subgroups.is_empty() {
+            group.subgroups.push(LayoutSubgroup {
+                name: String::new(),
+                emojis: vec![],
+            });
+        }
+        group.subgroups.last_mut().unwrap()
+    }
+
+    /// Sorts the emojis (and their variants) in each subgroup by the table's ordering
+    /// (see [EmojiTable::expand_ordering]).
+    /// Emojis without a position keep the order of the `emoji-test.txt` file, after the others.
+    pub fn sort_by_ordering(&mut self, table: &EmojiTable) {
+        if !table.has_ordering() {
+            return;
+        }
+        let position = |emoji: &LayoutEmoji| table.ordering_index(&emoji.codepoints()).unwrap_or(usize::MAX);
+        for subgroup in self.groups.iter_mut().flat_map(|group| group.subgroups.iter_mut()) {
+            subgroup.emojis.sort_by_key(position);
+            for emoji in &mut subgroup.emojis {
+                emoji.variants.sort_by_key(position);
+            }
+        }
+    }
+
+    /// Adds the search keywords from the table to the emojis (and their variants)
+    /// (see [EmojiTable::keywords])
+    pub fn add_keywords(&mut self, table: &EmojiTable) {
+        let emojis = self.groups.iter_mut()
+            .flat_map(|group| group.subgroups.iter_mut())
+            .flat_map(|subgroup| subgroup.emojis.iter_mut());
+        for emoji in emojis {
+            emoji.keywords = table.keywords(&emoji.codepoints()).into_iter().map(String::from).collect();
+            for variant in &mut emoji.variants {
+                variant.keywords = table.keywords(&variant.codepoints()).into_iter().map(String::from).collect();
+            }
+        }
+    }
+
+    /// The number of emojis (excluding the variants)
+    pub fn len(&self) -> usize {
+        self.groups.iter()
+            .flat_map(|group| group.subgroups.iter())
+            .map(|subgroup| subgroup.emojis.len())
+            .sum()
+    }
+
+    /// Whether there are no emojis at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes an `emoji-test.txt`-like file that contains exactly the given emojis.
+    ///
+    /// The emojis in the layout keep their group, subgroup, position and name and are written as
+    /// `fully-qualified` (with the sequence from the layout, i.e. with `U+FE0F` where necessary).
+    /// All other emojis (e.g. the ones of a private set) are written with the status
+    /// [CUSTOM_STATUS] to a `Custom` group at the end, ordered by their sequence.
+    /// The versions are taken from the table (see [EmojiTable::version]).
+    /// # Examples
+    /// ```
+    /// use emoji_builder::emoji::Emoji;
+    /// use emoji_builder::layout::Layout;
+    ///
+    /// let layout = Layout::from_test_data("1F600 ; fully-qualified # 😀 E1.0 grinning face\n".as_bytes()).unwrap();
+    /// let mut emojis = vec![Emoji::from(vec![0x1f600]), Emoji::from(vec![0xf0000])];
+    /// emojis[1].name = Some(String::from("party parrot"));
+    ///
+    /// let mut test_data = Vec::new();
+    /// layout.write_test_data(&emojis, None, &mut test_data).unwrap();
+    /// let test_data = String::from_utf8(test_data).unwrap();
+    /// assert!(test_data.contains("1F600 ; fully-qualified # 😀 grinning face\n"));
+    /// assert!(test_data.contains("F0000 ; custom # \u{f0000} party parrot\n"));
+    /// ```
+    pub fn write_test_data<W: Write>(&self, emojis: &[Emoji], table: Option<&EmojiTable>, mut writer: W) -> std::io::Result<()> {
+        let mut remaining: HashMap<Vec<u32>, &Emoji> = emojis.iter()
+            .map(|emoji| (without_fe0f(&emoji.sequence), emoji))
+            .collect();
+
+        writeln!(writer, "# emoji-test.txt for {} emojis", remaining.len())?;
+        for group in &self.groups {
+            let mut group_written = false;
+            for subgroup in &group.subgroups {
+                let lines = subgroup.emojis.iter()
+                    .flat_map(|emoji| std::iter::once(emoji).chain(emoji.variants.iter()))
+                    .filter_map(|emoji| {
+                        let codepoints = emoji.codepoints();
+                        remaining.remove(&without_fe0f(&codepoints))
+                            .map(|_| test_data_line(&codepoints, "fully-qualified", &emoji.name, table))
+                    })
+                    .collect_vec();
+                if lines.is_empty() {
+                    continue;
+                }
+                if !group_written {
+                    writeln!(writer, "\n{} {}", GROUP_PREFIX, group.name)?;
+                    group_written = true;
+                }
+                writeln!(writer, "\n{} {}", SUBGROUP_PREFIX, subgroup.name)?;
+                for line in lines {
+                    writeln!(writer, "{}", line)?;
+                }
+            }
+        }
+
+        if !remaining.is_empty() {
+            writeln!(writer, "\n{} {}", GROUP_PREFIX, CUSTOM_GROUP)?;
+            writeln!(writer, "\n{} {}", SUBGROUP_PREFIX, CUSTOM_SUBGROUP)?;
+            for emoji in remaining.values().sorted_by_key(|emoji| &emoji.sequence) {
+                let name = emoji.name.clone()
+                    .or_else(|| table
+                        .and_then(|table| table.get(&emoji.sequence))
+                        .and_then(|entry| entry.name().map(Name::to_string)))
+                    .unwrap_or_else(|| format!("{:#}", emoji));
+                writeln!(writer, "{}", test_data_line(&emoji.sequence, CUSTOM_STATUS, &name, table))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line like `1F600 ; fully-qualified # 😀 E1.0 grinning face`
+fn test_data_line(codepoints: &[u32], status: &str, name: &str, table: Option<&EmojiTable>) -> String {
+    let sequence = codepoints.iter().map(|codepoint| format!("{:X}", codepoint)).join(" ");
+    let emoji: String = codepoints.iter().filter_map(|codepoint| std::char::from_u32(*codepoint)).collect();
+    let version = table.and_then(|table| table.version(codepoints))
+        .map(|version| format!("E{}.{} ", version.major, version.minor))
+        .unwrap_or_default();
+    format!("{} ; {} # {} {}{}", sequence, status, emoji, version, name)
+}
+
+fn without_fe0f(sequence: &[u32]) -> Vec<u32> {
+    sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect()
+}
+
+impl LayoutSubgroup {
+    /// Adds an emoji or, if it has a skin tone and its base is already in this subgroup,
+    /// adds it as a variant of it
+    fn add(&mut self, emoji: LayoutEmoji) {
+        let base = base_sequence(&emoji.codepoints());
+        let base = if base != emoji.codepoints() {
+            self.emojis.iter_mut().find(|other| other.codepoints() == base)
+        } else {
+            None
+        };
+        match base {
+            Some(base) => base.variants.push(emoji),
+            None => self.emojis.push(emoji)
+        }
+    }
+}
+
+impl LayoutEmoji {
+    fn new(codepoints: &[u32], name: &str) -> LayoutEmoji {
+        LayoutEmoji {
+            sequence: codepoints.iter().map(|codepoint| format!("{:x}", codepoint)).join(" "),
+            emoji: codepoints.iter().filter_map(|codepoint| std::char::from_u32(*codepoint)).collect(),
+            name: name.to_string(),
+            glyph: format!("emoji_u{}.png", codepoints.iter()
+                .filter(|codepoint| **codepoint != 0xfe0f)
+                .map(|codepoint| format!("{:x}", codepoint))
+                .join("_")),
+            keywords: vec![],
+            variants: vec![],
+        }
+    }
+
+    fn codepoints(&self) -> Vec<u32> {
+        self.sequence.split(' ')
+            .filter_map(|codepoint| u32::from_str_radix(codepoint, 16).ok())
+            .collect()
+    }
+}
+
+/// Removes the skin tone modifiers from a sequence
+fn base_sequence(sequence: &[u32]) -> Vec<u32> {
+    sequence.iter()
+        .filter(|codepoint| !(0x1f3fb..=0x1f3ff).contains(*codepoint))
+        .copied()
+        .collect()
+}
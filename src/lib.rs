@@ -24,6 +24,8 @@ extern crate log;
 pub mod builder;
 /// Concrete builders (will be outsourced at some point)
 pub mod builders;
+/// Settings that are merged from the command line, environment variables and configuration files
+pub mod configs;
 /// A helper module to detect file changes based on their SHA256 hashes
 pub mod changes;
 /// Handling for single emojis
@@ -39,6 +41,38 @@ pub mod emoji_processor;
 pub mod deriving_emoji_processor;
 /// Concrete emoji processors
 pub mod emoji_processors;
+/// Checks and normalizes the `viewBox` of SVG files
+pub mod viewbox;
+/// Keyboard layouts built from the groups in `emoji-test.txt`
+pub mod layout;
+/// Converts images in other formats (e.g. PDF) to SVG files
+pub mod converters;
+/// Collects the problems of a build for a structured report
+pub mod diagnostics;
+/// The licenses of the emoji images (from the packs and from sidecar files)
+pub mod attribution;
+/// Short IDs of the emojis for file names and URLs
+pub mod short_ids;
+/// Finds the sequences with a common prefix
+pub mod prefix_index;
+/// Search keywords of the emojis
+pub mod keywords;
+/// Builds several packs into one font
+pub mod packs;
+/// Files in the image directories that are ignored (`.emojiignore`)
+pub mod ignore_files;
+/// The licenses of emoji_builder itself that are embedded into the binary
+pub mod bundled_licenses;
+/// An inventory of everything that is shipped with its license (e.g. as an SPDX document)
+pub mod licensing;
+/// Keeps a builder in memory and builds on requests from a socket
+pub mod server;
+/// A cache for the emoji tables that are downloaded from unicode.org
+#[cfg(feature = "cache")]
+pub mod online_cache;
+/// A C-compatible interface for looking up emojis and parsing file names
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,408 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The license information that ends up in the release artifacts.
+//!
+//! An [Inventory] collects everything that is shipped with its license and the checksums of its
+//! files: the crates emoji_builder depends on (from the embedded licenses directory, see
+//! [crate::bundled_licenses]), the files a builder bundles (e.g. Python scripts and templates,
+//! see [crate::builder::EmojiBuilder::bundled_files]) and the emoji images (see
+//! [crate::attribution]). It's written as an SPDX-like JSON document (`licenses --spdx FILE`).
+//! Anything whose license is unknown is listed as [NOASSERTION] instead of being left out.
+//!
+//! The license information that is embedded into the fonts comes from here as well
+//! ([font_license]).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use include_dir::Dir;
+use itertools::Itertools;
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::attribution::{Attribution, ATTRIBUTION_FILE};
+use crate::bundled_licenses::recurse_included_dir;
+use crate::emoji::Emoji;
+
+/// The value for a license or a copyright notice that isn't known
+pub const NOASSERTION: &str = "NOASSERTION";
+/// The file in the licenses directory that lists the licenses of the crates
+/// (according to `cargo-license`)
+pub const LICENSES_SUMMARY: &str = "README.txt";
+/// The directory that contains the licenses of the crates in the file names of the inventory
+const LICENSES_DIR: &str = "licenses";
+
+lazy_static! {
+    /// A line of the summary, e.g. `Apache-2.0 OR MIT (2): hex, itertools`
+    static ref SUMMARY_LINE: Regex = Regex::new(r"^(?P<license>.+) \(\d+\): (?P<crates>.+)$").unwrap();
+}
+
+/// What a [Component] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentKind {
+    /// A crate that emoji_builder depends on
+    Crate,
+    /// A file that is bundled by a builder
+    Bundled,
+    /// The emoji images
+    Content,
+}
+
+/// A file that a builder bundles and ships with its output (e.g. a Python script)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundledFile {
+    /// The path of the file in the sources of the builder
+    pub name: &'static str,
+    pub content: &'static [u8],
+    /// The SPDX identifier of its license, if it's known
+    pub license: Option<&'static str>,
+}
+
+/// A file of a [Component]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryFile {
+    pub name: String,
+    /// The SHA256 hash of the file's content in hexadecimal
+    pub sha256: String,
+    pub license: Option<String>,
+    pub copyright: Vec<String>,
+}
+
+/// Something that is shipped with its license
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub name: String,
+    pub kind: ComponentKind,
+    /// The SPDX expression of the license, if it's known
+    pub license: Option<String>,
+    pub files: Vec<InventoryFile>,
+}
+
+/// Everything that ends up in the release artifacts (see the [module documentation](self))
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    pub components: Vec<Component>,
+}
+
+impl BundledFile {
+    pub const fn new(name: &'static str, content: &'static [u8], license: Option<&'static str>) -> BundledFile {
+        BundledFile { name, content, license }
+    }
+}
+
+impl InventoryFile {
+    fn new<C: AsRef<[u8]>>(name: String, content: C, license: Option<String>, copyright: Vec<String>) -> InventoryFile {
+        InventoryFile {
+            name,
+            sha256: hex::encode(Sha256::digest(content.as_ref())),
+            license,
+            copyright,
+        }
+    }
+}
+
+impl ComponentKind {
+    fn description(&self) -> &'static str {
+        match self {
+            ComponentKind::Crate => "A crate that emoji_builder depends on",
+            ComponentKind::Bundled => "A file that is bundled by the builder",
+            ComponentKind::Content => "The emoji images",
+        }
+    }
+}
+
+impl Inventory {
+    /// Adds the crates from the embedded licenses directory. Each crate has a directory with its
+    /// license files, their licenses are taken from [LICENSES_SUMMARY]. Crates that only appear
+    /// in the summary are added without files.
+    pub fn add_crates(&mut self, licenses: &Dir) {
+        let mut crates: BTreeMap<String, Option<String>> = licenses.get_file(LICENSES_SUMMARY)
+            .and_then(|summary| summary.contents_utf8().map(parse_summary))
+            .unwrap_or_default();
+        for dir in licenses.dirs() {
+            let name = dir.path().to_string_lossy().to_string();
+            crates.entry(name).or_default();
+        }
+
+        for (name, license) in crates {
+            if license.is_none() {
+                warn!("The license of the crate {} is unknown, it's listed as {}", name, NOASSERTION);
+            }
+            let files = licenses.get_dir(&name)
+                .map(|dir| recurse_included_dir(&dir).iter()
+                    .map(|file| InventoryFile::new(
+                        format!("./{}/{}", LICENSES_DIR, file.path().to_string_lossy().replace('\\', "/")),
+                        file.contents(),
+                        license.clone(),
+                        vec![]
+                    ))
+                    .sorted_by(|a, b| a.name.cmp(&b.name))
+                    .collect())
+                .unwrap_or_default();
+            self.components.push(Component { name, kind: ComponentKind::Crate, license, files });
+        }
+    }
+
+    /// Adds the files that a builder bundles, each of them as its own component
+    pub fn add_bundled(&mut self, files: &[BundledFile]) {
+        for file in files {
+            let license = file.license.map(String::from);
+            if license.is_none() {
+                warn!("The license of the bundled file {} is unknown, it's listed as {}", file.name, NOASSERTION);
+            }
+            self.components.push(Component {
+                name: file.name.to_string(),
+                kind: ComponentKind::Bundled,
+                license: license.clone(),
+                files: vec![InventoryFile::new(format!("./{}", file.name), file.content, license, vec![])]
+            });
+        }
+    }
+
+    /// Adds the images of the emojis with their licenses as one component.
+    /// Images that can't be read are left out with a warning.
+    pub fn add_content(&mut self, name: &str, emojis: &[Emoji], attribution: &Attribution) {
+        let mut unlicensed = 0;
+        let files: Vec<InventoryFile> = emojis.iter()
+            .filter_map(|emoji| emoji.svg_path.as_ref().map(|path| (emoji, path)))
+            .sorted_by(|(_, a), (_, b)| a.cmp(b))
+            .filter_map(|(emoji, path)| match std::fs::read(path) {
+                Ok(content) => {
+                    let credit = attribution.get(&emoji.sequence);
+                    if credit.is_none() {
+                        unlicensed += 1;
+                    }
+                    Some(InventoryFile::new(
+                        relative_name(path),
+                        content,
+                        credit.map(|credit| credit.license.id.clone()),
+                        credit.map(|credit| credit.copyright.clone()).unwrap_or_default()
+                    ))
+                }
+                Err(err) => {
+                    warn!("Couldn't read {:?}, it's left out of the inventory: {}", path, err);
+                    None
+                }
+            })
+            .collect();
+
+        let license = if unlicensed > 0 {
+            warn!("{} image(s) have no license, they're listed as {}", unlicensed, NOASSERTION);
+            None
+        } else {
+            license_expression(files.iter().filter_map(|file| file.license.as_deref()))
+        };
+        self.components.push(Component {
+            name: name.to_string(),
+            kind: ComponentKind::Content,
+            license,
+            files
+        });
+    }
+
+    /// The SPDX document (in its JSON form) with the time of its creation,
+    /// e.g. `2021-06-01T12:00:00Z` (see [spdx_timestamp])
+    pub fn to_spdx(&self, name: &str, created: &str) -> Value {
+        let mut namespace = Sha256::new();
+        namespace.input(created.as_bytes());
+
+        let mut packages = Vec::new();
+        let mut files = Vec::new();
+        for (index, component) in self.components.iter().enumerate() {
+            let mut has_files = Vec::new();
+            for file in &component.files {
+                namespace.input(file.sha256.as_bytes());
+                let id = format!("SPDXRef-File-{}", files.len() + 1);
+                let copyright = if file.copyright.is_empty() {
+                    String::from(NOASSERTION)
+                } else {
+                    file.copyright.join("\n")
+                };
+                files.push(json!({
+                    "SPDXID": id,
+                    "fileName": file.name,
+                    "checksums": [{"algorithm": "SHA256", "checksumValue": file.sha256}],
+                    "licenseConcluded": file.license.as_deref().unwrap_or(NOASSERTION),
+                    "copyrightText": copyright
+                }));
+                has_files.push(id);
+            }
+            packages.push(json!({
+                "SPDXID": format!("SPDXRef-Package-{}-{}", index + 1, spdx_id(&component.name)),
+                "name": component.name,
+                "downloadLocation": NOASSERTION,
+                "filesAnalyzed": false,
+                "licenseConcluded": NOASSERTION,
+                "licenseDeclared": component.license.as_deref().unwrap_or(NOASSERTION),
+                "copyrightText": NOASSERTION,
+                "comment": component.kind.description(),
+                "hasFiles": has_files
+            }));
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.2",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", uri_segment(name), hex::encode(namespace.result())),
+            "creationInfo": {
+                "created": created,
+                "creators": [format!("Tool: emoji_builder-{}", env!("CARGO_PKG_VERSION"))]
+            },
+            "packages": packages,
+            "files": files
+        })
+    }
+
+    /// Writes the SPDX document (see [Inventory::to_spdx])
+    pub fn write_spdx<W: Write>(&self, writer: W, name: &str, created: &str) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.to_spdx(name, created))
+    }
+}
+
+/// The licenses of the crates in the summary, `N/A` is an unknown license
+fn parse_summary(summary: &str) -> BTreeMap<String, Option<String>> {
+    summary.lines()
+        .filter_map(|line| SUMMARY_LINE.captures(line.trim()))
+        .flat_map(|captures| {
+            let license = Some(captures["license"].trim())
+                .filter(|license| *license != "N/A")
+                .map(String::from);
+            captures["crates"].split(',')
+                .map(|name| (name.trim().to_string(), license.clone()))
+                .collect::<Vec<_>>()
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+/// The file name as it appears in the inventory, i.e. relative to the current directory
+fn relative_name(path: &Path) -> String {
+    let path = std::env::current_dir().ok()
+        .and_then(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path);
+    format!("./{}", path.to_string_lossy().replace('\\', "/").trim_start_matches("./"))
+}
+
+/// Replaces the characters that aren't allowed in SPDX identifiers
+fn spdx_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+        .collect()
+}
+
+/// Replaces the characters that would have to be escaped in a URI (unlike in SPDX identifiers,
+/// `_` and `~` may stay)
+fn uri_segment(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-._~".contains(c) { c } else { '-' })
+        .collect()
+}
+
+/// Combines several licenses into one SPDX expression (each of them applies to a part)
+/// # Examples
+/// ```
+/// use emoji_builder::licensing::license_expression;
+///
+/// assert_eq!(license_expression(vec!["CC-BY-4.0", "CC0-1.0", "CC-BY-4.0"]).as_deref(), Some("CC-BY-4.0 AND CC0-1.0"));
+/// assert_eq!(license_expression(vec!["MIT OR Apache-2.0", "Zlib"]).as_deref(), Some("(MIT OR Apache-2.0) AND Zlib"));
+/// assert_eq!(license_expression(Vec::<&str>::new()), None);
+/// ```
+pub fn license_expression<'a, I: IntoIterator<Item = &'a str>>(licenses: I) -> Option<String> {
+    let licenses: BTreeSet<&str> = licenses.into_iter().map(str::trim).collect();
+    match licenses.len() {
+        0 => None,
+        1 => licenses.into_iter().next().map(String::from),
+        _ => Some(licenses.into_iter()
+            .map(|license| if license.contains(' ') {
+                format!("({})", license)
+            } else {
+                license.to_string()
+            })
+            .join(" AND "))
+    }
+}
+
+/// The license description (name ID 13) and URL (name ID 14) for the font's name table.
+/// As there's only one URL, it's the one of the license that most emojis have.
+/// # Examples
+/// ```
+/// use emoji_builder::attribution::{Attribution, Credit, License};
+/// use emoji_builder::emoji::Emoji;
+/// use emoji_builder::licensing::font_license;
+///
+/// let credit = Credit { license: License::new("CC-BY-4.0", None), copyright: vec![], source: String::from("pack.yaml") };
+/// let attribution = Attribution::for_pack(&vec![Emoji::from(vec![0x1f914])], Some(&credit)).unwrap();
+///
+/// let (description, url) = font_license(&attribution).unwrap();
+/// assert_eq!(description, "The artwork is licensed under CC-BY-4.0 (https://creativecommons.org/licenses/by/4.0/). \
+///                          See ATTRIBUTION.txt for the authors of the emojis.");
+/// assert_eq!(url.as_deref(), Some("https://creativecommons.org/licenses/by/4.0/"));
+/// ```
+pub fn font_license(attribution: &Attribution) -> Option<(String, Option<String>)> {
+    let licenses = attribution.licenses();
+    let url = licenses.first()?.url();
+    let mut description = format!("The artwork is licensed under {}.", licenses.iter().join(", "));
+    if licenses.iter().any(|license| license.requires_attribution()) {
+        description.push_str(&format!(" See {} for the authors of the emojis.", ATTRIBUTION_FILE));
+    }
+    Some((description, url))
+}
+
+/// The time in the format of SPDX (UTC), e.g. `2021-06-01T12:00:00Z`
+pub fn spdx_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+    // The civil date from the days since 1970-01-01, with years starting in March
+    // (see http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[test]
+fn test_spdx_timestamp() {
+    use std::time::Duration;
+
+    assert_eq!(spdx_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    assert_eq!(spdx_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z");
+    assert_eq!(spdx_timestamp(UNIX_EPOCH + Duration::from_secs(1_000_000_000)), "2001-09-09T01:46:40Z");
+    assert_eq!(spdx_timestamp(UNIX_EPOCH + Duration::from_secs(1_609_459_199)), "2020-12-31T23:59:59Z");
+}
+
+#[test]
+fn test_parse_summary() {
+    let summary = parse_summary("The licenses:\n\
+        Apache-2.0 OR MIT (2): hex, itertools\n\
+        N/A (1): fuchsia-cprng\n");
+    assert_eq!(summary.len(), 3);
+    assert_eq!(summary["itertools"].as_deref(), Some("Apache-2.0 OR MIT"));
+    assert_eq!(summary["fuchsia-cprng"], None);
+}
@@ -27,61 +27,517 @@ extern crate log;
 #[macro_use]
 extern crate include_dir;
 
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::{App, ArgMatches, SubCommand, Arg};
-use rayon::prelude::*;
+use serde::Serialize;
 use yaml_rust::Yaml;
 
-use emoji_builder::builder::EmojiBuilder;
-use emoji_builder::builders::blobmoji::Blobmoji;
-use emoji_builder::emoji::Emoji;
-use emoji_builder::emoji_tables::EmojiTable;
+use emoji_builder::attribution::{Attribution, Credit};
+use emoji_builder::builder::{EmojiBuilder, SupportReport};
+use emoji_builder::builders::blobmoji::{Blobmoji, BuildDir};
+use emoji_builder::builders::blobmoji::{contact_sheet, font_sequences};
+use emoji_builder::bundled_licenses::recurse_included_dir;
+use emoji_builder::changes::FileHashes;
+use emoji_builder::configs::{ConfigError, Pack, SettingSource, Settings};
+use emoji_builder::converters::Converters;
+use emoji_builder::diagnostics::{Category, DeniedCategories, Diagnostic, DiagnosticsSink};
+use emoji_builder::emoji::{Emoji, EmojiError, EmojiKind, ScannedDir};
+use emoji_builder::emoji_tables::{EmojiTable, EmojiVersion, PresentationIssue, ValidationOptions};
+use emoji_builder::layout::Layout;
+use emoji_builder::licensing::{spdx_timestamp, Inventory};
+use emoji_builder::packs;
+use emoji_builder::packs::PackLayer;
+use emoji_builder::ignore_files::IGNORE_FILE;
+use emoji_builder::server::Server;
+#[cfg(feature = "fetch")]
+use emoji_builder::online_cache::OnlineCache;
+use emoji_builder::viewbox;
+use emoji_builder::viewbox::ViewBoxReport;
 use std::fs::create_dir_all;
 use std::io::{BufReader, Write};
 use std::process::exit;
 
-const LICENSES: include_dir::Dir = include_dir!("licenses");
+include!(concat!(env!("OUT_DIR"), "/licenses.rs"));
+
+/// The hashes of the emojis in the last successful build (in the build directory)
+const LAST_BUILD: &str = "last_build.csv";
+
+const LAYOUT_SCHEMA: &str = r#"The layout is a JSON object like this one:
+{
+  "groups": [{
+    "name": "People & Body",
+    "subgroups": [{
+      "name": "hand-fingers-open",
+      "emojis": [{
+        "sequence": "1f44b",
+        "emoji": "👋",
+        "name": "waving hand",
+        "glyph": "emoji_u1f44b.png",
+        "keywords": ["hand", "wave", "waving"],
+        "variants": [{
+          "sequence": "1f44b 1f3fb",
+          "emoji": "👋🏻",
+          "name": "waving hand: light skin tone",
+          "glyph": "emoji_u1f44b_1f3fb.png"
+        }]
+      }]
+    }]
+  }]
+}
+Groups, subgroups and emojis are in the order of emoji-test.txt, but the emojis
+in each subgroup are sorted by --emoji-ordering if it's given.
+Emojis with skin tones are only listed as variants of their base emoji and
+"variants" is left out if there are none.
+"keywords" are taken from --keywords and are left out if there are none."#;
 
 fn main() {
     build::<Blobmoji>();
 }
 
-fn build<Builder: EmojiBuilder>() {
+fn build<Builder: EmojiBuilder>() where Builder::Err: From<std::io::Error> {
     let args = Builder::sub_command();
     let name = args.get_name().to_string();
     let log_modules = Builder::log_modules();
     let mut args = parse_args(vec![args], vec![log_modules]);
 
+    if let Some(emoji) = &args.explain {
+        explain(&args, emoji);
+        exit(0);
+    }
+
+    if let Some(emoji) = &args.info {
+        info::<Builder>(&args, &name, emoji);
+        exit(0);
+    }
 
-    let emojis = parse_emojis(&args);
+    if let Some(path) = &args.spdx {
+        write_spdx::<Builder>(&args, path);
+        exit(0);
+    }
+
+    if let Some(font) = &args.against_font {
+        validate(&args, font);
+        exit(0);
+    }
+
+    if let Some(format) = &args.export_format {
+        export(&args, format);
+        exit(0);
+    }
+
+    if args.serve_socket.is_some() || args.serve_port.is_some() {
+        serve::<Builder>(args, &name);
+    }
+
+    // Reading the directories doesn't need the table, so it's done while the table is loading.
+    // Everything else (including rendering) has to wait for it though, as it needs the kinds
+    let (table, scanned) = rayon::join(|| load_table(&args, args.verbose), || scan_emojis(&args, None));
+    if args.verbose {
+        print_table_stats(table.as_ref());
+    }
+    let emojis = scanned.map(|scanned| finish_emojis(&args, scanned, table.as_ref())).unwrap_or_else(|(dir, err)| {
+        error!("Couldn't read {:?}: {}", dir, err);
+        args.diagnostics.report(Diagnostic::error(Category::Emoji, format!("Couldn't read the directory: {}", err)).with_path(dir));
+        write_problems(&args);
+        exit(1);
+    });
 
     create_dir_all(&args.build_path).unwrap();
-    if let Some(output_dir) = &args.output_path.parent() {
+    if let (false, Some(output_dir)) = (args.output_stdout, args.output_path.parent()) {
         create_dir_all(output_dir).unwrap();
     }
 
     // Now we are ready to start the actual build process
     let mut builder = Builder::new(
-        args.build_path,
+        args.build_path.clone(),
         args.builder_matches.remove(name.as_str()).unwrap_or(None),
     ).unwrap();
+    builder.set_diagnostics(args.diagnostics.clone());
+    if let Some(table) = &table {
+        builder.set_table(table);
+    }
+    let attribution = Attribution::for_pack(&emojis, args.license.as_ref()).unwrap_or_else(|err| {
+        error!("Couldn't collect the licenses of the emojis: {}", err);
+        args.diagnostics.report(Diagnostic::error(Category::License, err.to_string()));
+        write_problems(&args);
+        exit(1);
+    });
+    builder.set_attribution(&attribution);
+    builder.set_output_source(&args.output_source);
 
-    let output = args.output_path;
-    let prepared: HashMap<&Emoji, _> =
-        emojis.par_iter()
-        .map(|emoji| (emoji, builder.as_ref().prepare(emoji).map(|prepared| prepared.0)))
+    let output = args.output_path.clone();
+    let emojis: Vec<&Emoji> = emojis.iter().collect();
+    let (emojis, support) = SupportReport::check(builder.as_ref(), &emojis);
+    if !support.is_empty() {
+        warn!("{}", support.to_string().trim_end());
+    }
+    let prepared: HashMap<&Emoji, _> = builder.as_ref().prepare_batch(&emojis)
+        .into_iter()
+        .map(|(emoji, prepared)| (emoji, prepared.map(|prepared| prepared.0)))
         .collect();
-    let result = builder.as_mut().build(prepared, output);
+    let result = if args.output_stdout {
+        builder.as_mut().build_to_writer(prepared, &mut std::io::stdout())
+    } else {
+        builder.as_mut().build(prepared, output)
+    };
     if let Err(err) = result {
         error!("An error occured while building the emoji set: {:?}", err);
+        args.diagnostics.report(Diagnostic::error(Category::Font, format!("An error occured while building the emoji set: {:?}", err)));
+    } else {
+        record_changes(&args, &emojis, table.as_ref());
+    }
+    write_problems(&args);
+    if let Err(summary) = args.diagnostics.check_denied(&args.deny) {
+        error!("{}", summary);
+        exit(1);
+    }
+}
+
+/// Writes the problems that have been found to the file from `--problems-out` (if it's set)
+fn write_problems(args: &BuilderArguments) {
+    if !args.packs.is_empty() {
+        packs::tag_diagnostics(&args.packs, &args.diagnostics);
+    }
+    if let Some(problems_out) = &args.problems_out {
+        let result = std::fs::File::create(problems_out)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| args.diagnostics.write_json(std::io::BufWriter::new(file)));
+        if let Err(err) = result {
+            error!("Couldn't write the problems to {:?}: {}", problems_out, err);
+        }
+    }
+}
+
+/// Compares the emojis with the last successful build, writes the changelog (if it has been
+/// requested) and stores the current state for the next build
+fn record_changes(args: &BuilderArguments, emojis: &[&Emoji], table: Option<&EmojiTable>) {
+    let snapshot = match FileHashes::snapshot(emojis) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            warn!("Couldn't hash the emojis to compare them with the last build: {:?}", err);
+            return;
+        }
+    };
+    let snapshot_path = args.build_path.join(LAST_BUILD);
+    let previous = if snapshot_path.is_file() {
+        FileHashes::from_path(&snapshot_path)
+            .map_err(|err| warn!("Couldn't read the last build {:?}: {}", snapshot_path, err))
+            .ok()
+    } else {
+        None
+    };
+
+    let changes = snapshot.changes_since(previous.as_ref());
+    info!("Since the last build: {}", changes.summary());
+    if let Some(path) = &args.changelog {
+        if changes.first_build && !args.force_changelog {
+            info!("There's no earlier build, so no changelog is written (unless --force-changelog is set)");
+        } else {
+            let result = std::fs::File::create(path)
+                .and_then(|file| changes.write_markdown(std::io::BufWriter::new(file), table));
+            if let Err(err) = result {
+                error!("Couldn't write the changelog to {:?}: {}", path, err);
+            }
+        }
+    }
+
+    if let Err(err) = snapshot.write_to_path(snapshot_path) {
+        warn!("Couldn't save the state of this build: {}", err);
+    }
+}
+
+/// Keeps the builder and the emoji table in memory and builds on requests from a socket
+/// (see [emoji_builder::server])
+fn serve<Builder: EmojiBuilder>(mut args: BuilderArguments, name: &str) -> ! {
+    create_dir_all(&args.build_path).unwrap();
+    let builder = Builder::new(
+        args.build_path.clone(),
+        args.builder_matches.remove(name).unwrap_or(None),
+    ).unwrap();
+    let table = load_table(&args, false);
+
+    let args = &args;
+    let mut server = Server::new(builder, table, Box::new(move |table: Option<&EmojiTable>| parse_emojis_with(args, table)
+        .map_err(|(dir, err)| format!("Couldn't read {:?}: {}", dir, err))))
+        .with_max_version(args.max_emoji_version);
+    let result = match (&args.serve_socket, args.serve_port) {
+        #[cfg(unix)]
+        (Some(socket), _) => {
+            // A socket from an earlier run would keep us from binding to it
+            if socket.exists() {
+                std::fs::remove_file(socket).unwrap_or_else(|err| warn!("Couldn't remove {:?}: {}", socket, err));
+            }
+            std::os::unix::net::UnixListener::bind(socket).map(|listener| {
+                info!("Listening on {:?}", socket);
+                server.serve(listener.incoming())
+            })
+        }
+        #[cfg(not(unix))]
+        (Some(_), _) => Err(std::io::Error::new(std::io::ErrorKind::Other, "Unix sockets are not supported on this platform")),
+        (None, Some(port)) => std::net::TcpListener::bind(("127.0.0.1", port)).map(|listener| {
+            info!("Listening on 127.0.0.1:{}", port);
+            server.serve(listener.incoming())
+        }),
+        (None, None) => Ok(())
+    };
+    if let Err(err) = result {
+        error!("Couldn't start the server: {}", err);
+        exit(1);
+    }
+    exit(0);
+}
+
+/// Prints where the table's information about a single emoji (given by its sequence or name)
+/// comes from
+fn explain(args: &BuilderArguments, emoji: &str) {
+    let table = load_table(args, true).unwrap_or_else(EmojiTable::with_provenance);
+    let sequence = match table.get_by_name(emoji) {
+        Some((sequence, _)) => Some(sequence),
+        None => Emoji::from_sequence(emoji, None).ok()
+            .map(|emoji| emoji.sequence)
+    };
+    match sequence {
+        Some(sequence) => print!("{}", table.explain(&sequence)),
+        None => error!("{} is neither a known emoji name nor a codepoint sequence", emoji)
+    }
+}
+
+/// Everything that's known about a single emoji (printed by the `info` subcommand)
+#[derive(Serialize)]
+struct EmojiInfo {
+    sequence: String,
+    emoji: String,
+    name: Option<String>,
+    table_kinds: Option<Vec<String>>,
+    guessed_kinds: Option<Vec<String>>,
+    status: &'static str,
+    fe0f_variants: Vec<String>,
+    flag: Option<String>,
+    output_file: Option<String>,
+    cache: String,
+    svg_file: Option<PathBuf>,
+}
+
+impl Display for EmojiInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let kinds = |kinds: &Option<Vec<String>>| or_none(kinds.as_ref().map(|kinds| kinds.join(", ")));
+        writeln!(f, "Sequence:        {}", self.sequence)?;
+        writeln!(f, "Emoji:           {}", self.emoji)?;
+        writeln!(f, "Name:            {}", or_none(self.name.clone()))?;
+        writeln!(f, "Kinds (table):   {}", kinds(&self.table_kinds))?;
+        writeln!(f, "Kinds (guessed): {}", kinds(&self.guessed_kinds))?;
+        writeln!(f, "Status:          {}", self.status)?;
+        writeln!(f, "FE0F variants:   {}", or_none(Some(self.fe0f_variants.join(", ")).filter(|variants| !variants.is_empty())))?;
+        writeln!(f, "Flag:            {}", or_none(self.flag.clone()))?;
+        writeln!(f, "Output file:     {}", or_none(self.output_file.clone()))?;
+        writeln!(f, "Cache:           {}", self.cache)?;
+        writeln!(f, "SVG file:        {}", or_none(self.svg_file.as_ref().map(|path| path.display().to_string())))
+    }
+}
+
+/// Prints everything that's known about a single emoji (given by its sequence, name or file)
+fn info<Builder: EmojiBuilder>(args: &BuilderArguments, name: &str, input: &str) {
+    let without_fe0f = |sequence: &[u32]| sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect::<Vec<u32>>();
+    let hex = |sequence: &[u32]| sequence.iter()
+        .map(|codepoint| format!("{:X}", codepoint))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let table = load_table(args, true);
+    let in_flags = args.flag_path.as_ref()
+        .map(|flags| Path::new(input).parent().is_some_and(|dir| dir.ends_with(flags)))
+        .unwrap_or(false);
+    let resolved = if in_flags && Path::new(input).is_file() {
+        Emoji::from_path(PathBuf::from(input), table.as_ref(), true)
+    } else {
+        Emoji::resolve(input, table.as_ref())
+    };
+    let emoji = resolved.unwrap_or_else(|err| {
+        error!("{} is neither an emoji file, a known emoji name nor a codepoint sequence: {:?}", input, err);
+        exit(1);
+    });
+
+    // The file that would be used for the build (and thus its sequence)
+    let svg = match &emoji.svg_path {
+        Some(path) if path.is_file() => Some(emoji.clone()),
+        _ => std::iter::once((&args.svg_path, false))
+            .chain(args.flag_path.as_ref().map(|flags| (flags, true)))
+            .filter_map(|(dir, flag)| Emoji::from_dir(dir, table.as_ref(), flag)
+                .map_err(|err| warn!("Couldn't read {:?}: {}", dir, err))
+                .ok())
+            .flatten()
+            .find(|other| without_fe0f(&other.sequence) == without_fe0f(&emoji.sequence))
+    };
+    let built = svg.as_ref().unwrap_or(&emoji);
+
+    let (status, fe0f_variants) = match &table {
+        Some(table) => {
+            let status = if table.get(&emoji.sequence).is_some() {
+                "in the emoji table"
+            } else {
+                "not in the emoji table"
+            };
+            // The variants start with the same codepoint, so there's no need to look at all emojis
+            let first = &emoji.sequence[..emoji.sequence.len().min(1)];
+            let variants: Vec<&Vec<u32>> = table.sequences_with_prefix(first)
+                .filter(|other| **other != emoji.sequence)
+                .filter(|other| without_fe0f(other) == without_fe0f(&emoji.sequence))
+                .collect();
+            (status, variants.into_iter().map(|variant| hex(variant)).collect())
+        },
+        None => ("no emoji table loaded", vec![])
+    };
+
+    let matches = args.builder_matches.get(name).and_then(Option::as_ref);
+    let cache = Builder::cache_status(&args.build_path, matches, built);
+
+    let kind_names = |kinds: Option<&Vec<EmojiKind>>| kinds
+        .map(|kinds| kinds.iter().map(EmojiKind::to_string).collect());
+    let info = EmojiInfo {
+        sequence: hex(&emoji.sequence),
+        emoji: emoji.display_emoji(),
+        name: emoji.name.clone(),
+        table_kinds: kind_names(emoji.kinds.as_ref()),
+        guessed_kinds: kind_names(emoji.guess_kinds().as_ref()),
+        status,
+        fe0f_variants,
+        flag: emoji.get_flag_name(),
+        output_file: Builder::output_file_name(built),
+        cache: cache.to_string(),
+        svg_file: svg.and_then(|svg| svg.svg_path),
+    };
+
+    if args.info_json {
+        match serde_json::to_string_pretty(&info) {
+            Ok(json) => println!("{}", json),
+            Err(err) => error!("Couldn't serialize the information: {:?}", err)
+        }
+    } else {
+        print!("{}", info);
+        if let Some(table) = &table {
+            print!("\nEmoji table:\n{}", table.stats());
+        }
     }
 }
 
-fn parse_emojis(args: &BuilderArguments) -> Vec<Emoji> {
+/// Writes the licenses inventory of emoji_builder, the builder's bundled files and the emojis
+/// as an SPDX document
+fn write_spdx<Builder: EmojiBuilder>(args: &BuilderArguments, path: &Path) {
+    let mut inventory = Inventory::default();
+    if recurse_included_dir(&LICENSES).is_empty() {
+        warn!("There are no embedded licenses in this build, the dependencies are left out");
+    }
+    inventory.add_crates(&LICENSES);
+    inventory.add_bundled(&Builder::bundled_files());
+    match parse_emojis(args) {
+        Ok(emojis) => match Attribution::for_pack(&emojis, args.license.as_ref()) {
+            Ok(attribution) => inventory.add_content("emojis", &emojis, &attribution),
+            Err(err) => {
+                error!("Couldn't collect the licenses of the emojis: {}", err);
+                exit(1);
+            }
+        },
+        Err((dir, err)) => warn!("Couldn't read the emojis in {:?}, they are left out: {}", dir, err)
+    }
+
+    let created = spdx_timestamp(std::time::SystemTime::now());
+    let result = std::fs::File::create(path)
+        .map_err(serde_json::Error::io)
+        .and_then(|file| inventory.write_spdx(std::io::BufWriter::new(file), "emoji_builder", &created));
+    match result {
+        Ok(()) => info!("Wrote the licenses inventory to {:?}", path),
+        Err(err) => {
+            error!("Couldn't write the licenses inventory to {:?}: {}", path, err);
+            exit(1);
+        }
+    }
+}
+
+/// Compares the emojis (and the emoji table) with the sequences in an existing font
+fn validate(args: &BuilderArguments, font: &Path) {
+    let without_fe0f = |sequence: &[u32]| sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect::<Vec<u32>>();
+
+    let reference = font_sequences::read_sequences(font).unwrap_or_else(|err| {
+        error!("Couldn't read the font {:?}: {:?}", font, err);
+        exit(1);
+    });
+    // The sources are tracked for the summary of the table
+    let table = load_table(args, true);
+    let emojis = parse_emojis_with(args, table.as_ref()).unwrap_or_else(|(dir, err)| {
+        error!("Couldn't read {:?}: {}", dir, err);
+        exit(1);
+    });
+
+    let pack: HashSet<Vec<u32>> = emojis.iter()
+        .map(|emoji| without_fe0f(&emoji.sequence))
+        .collect();
+    let in_reference = |emoji: &Emoji| if reference.contains(&without_fe0f(&emoji.sequence)) {
+        "yes"
+    } else {
+        "no"
+    };
+
+    if let Some(table) = &table {
+        let options = ValidationOptions { ignore_fe0f: true, max_version: args.max_emoji_version };
+        let result = table.validate_with(&pack, options);
+        for missing in &result.missing {
+            println!("Missing emoji: {} (Codepoint: {:X?})\tin reference font: {}",
+                     missing, missing.sequence, in_reference(missing));
+        }
+        for additional in &result.additional {
+            println!("Additional emoji: {} (Codepoint: {:X?})\tin reference font: {}",
+                     additional, additional.sequence, in_reference(additional));
+        }
+        println!("Pack: {}", result.summary());
+        println!("Reference font: {}", table.validate_with(&reference, options).summary());
+        print!("Emoji table:\n{}", table.stats());
+    }
+
+    let to_emoji = |sequence: &Vec<u32>| Emoji::from_u32_sequence(sequence.clone(), table.as_ref()).ok();
+    for only_pack in pack.difference(&reference).filter_map(to_emoji) {
+        println!("Only in the pack: {} (Codepoint: {:X?})", only_pack, only_pack.sequence);
+    }
+    for only_reference in reference.difference(&pack).filter_map(to_emoji) {
+        println!("Only in the reference font: {} (Codepoint: {:X?})", only_reference, only_reference.sequence);
+    }
+}
+
+/// Loads the emoji table from all the sources that have been specified.
+/// If `provenance` is set, the table records where its entries come from.
+fn load_table(args: &BuilderArguments, provenance: bool) -> Option<EmojiTable> {
+    let new_table = || {
+        let mut table = if provenance {
+            EmojiTable::with_provenance()
+        } else {
+            EmojiTable::new()
+        };
+        table.set_diagnostics(args.diagnostics.clone());
+        table
+    };
+
+    // Private emoji sets only use the names (if there are any)
+    if args.private_set {
+        let table = args.private_names.as_ref().and_then(|private_names| {
+            let mut table = new_table();
+            table.expand_names_from_csv_file(private_names)
+                .map(|_| table)
+                .map_err(|err| error!("Error in parsing the names of the private emoji set: {}", err))
+                .ok()
+        });
+        return override_names(args, table);
+    }
+
     let table_paths = &args.tables_path;
 
     let table = match table_paths {
@@ -92,7 +548,10 @@ fn parse_emojis(args: &BuilderArguments) -> Vec<Emoji> {
                 .filter_map(|entry| entry.ok())
                 .map(|entry| entry.path())
                 .collect();
-            Some(EmojiTable::from_files(&table_paths))
+            let mut table = new_table();
+            Some(table_paths.iter()
+                .try_for_each(|path| table.expand_from_file(path))
+                .map(|_| table))
         }
         None => None,
     };
@@ -100,6 +559,7 @@ fn parse_emojis(args: &BuilderArguments) -> Vec<Emoji> {
         Some(Ok(table)) => Some(table),
         Some(Err(err)) => {
             error!("Error in parsing the emoji tables: {}", err);
+            args.diagnostics.report(Diagnostic::error(Category::Table, format!("Error in parsing the emoji tables: {}", err)));
             None
         },
         None => None,
@@ -108,8 +568,12 @@ fn parse_emojis(args: &BuilderArguments) -> Vec<Emoji> {
     let table = if let Some(emoji_test) = args.emoji_test.as_ref() {
         let reader = std::fs::File::open(emoji_test).map(BufReader::new);
         if let Ok(reader) = reader {
-            let mut table = table.unwrap_or_default();
-            table.expand_descriptions_from_test_data(reader)
+            let mut table = table.unwrap_or_else(new_table);
+            let source = emoji_test.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            table.expand_descriptions_from_test_data_with_source(reader, &source)
                 .map(|_| table)
                 .map_err(|err|
                     error!("Error in parsing emoji-test.txt: {}", err)
@@ -122,77 +586,250 @@ fn parse_emojis(args: &BuilderArguments) -> Vec<Emoji> {
         table
     };
 
-    #[cfg(feature = "online")]
+    let table = if let Some(emojibase) = args.emojibase.as_ref() {
+        let mut table = table.unwrap_or_else(new_table);
+        table.expand_from_emojibase_file(emojibase)
+            .unwrap_or_else(|err| error!("Error in parsing the emojibase data: {}", err));
+        Some(table)
+    } else {
+        table
+    };
+
+    let table = if !args.keywords.is_empty() {
+        let mut table = table.unwrap_or_else(new_table);
+        for keywords in &args.keywords {
+            table.expand_keywords_from_file(keywords)
+                .unwrap_or_else(|err| error!("Error in parsing the keywords {:?}: {}", keywords, err));
+        }
+        Some(table)
+    } else {
+        table
+    };
+
+    let table = if let Some(emoji_ordering) = args.emoji_ordering.as_ref() {
+        let mut table = table.unwrap_or_else(new_table);
+        table.expand_ordering_from_file(emoji_ordering)
+            .unwrap_or_else(|err| error!("Error in parsing the emoji ordering: {}", err));
+        Some(table)
+    } else {
+        table
+    };
+
+    #[cfg(feature = "fetch")]
     let table = if !args.offline {
-        let mut table = table.unwrap_or_default();
-        table.expand_all_online((13, 0)).unwrap_or_else(|e| warn!("Couldn't load online emoji tables: {:?}", e));
+        let mut table = table.unwrap_or_else(new_table);
+        let cache = match args.cache_dir.as_ref() {
+            Some(cache_dir) => OnlineCache::new(cache_dir),
+            None => OnlineCache::with_default_dir()
+        };
+        table.expand_all_online_with_cache((13, 0), &cache)
+            .unwrap_or_else(|e| warn!("Couldn't load online emoji tables: {:?}", e));
+        if !table.has_ordering() {
+            table.expand_ordering_online_with_cache(&cache)
+                .unwrap_or_else(|e| warn!("Couldn't load the online emoji ordering: {:?}", e));
+        }
         Some(table)
     } else {
         table
     };
 
 
+    let table = override_names(args, table);
+
     if table.is_some() {
         info!("Using emoji table");
     }
 
+    table
+}
 
-    let images = &args.svg_path;
+/// Applies the name overrides (see [EmojiTable::apply_name_overrides]) once the table is complete,
+/// so they take precedence over all other sources
+fn override_names(args: &BuilderArguments, table: Option<EmojiTable>) -> Option<EmojiTable> {
+    match (table, args.name_overrides.as_ref()) {
+        (Some(mut table), Some(name_overrides)) => {
+            table.apply_name_overrides_from_file(name_overrides)
+                .unwrap_or_else(|err| error!("Error in parsing the name overrides: {}", err));
+            Some(table)
+        },
+        (table, Some(_)) => {
+            warn!("There's no emoji table whose names could be overridden");
+            table
+        },
+        (table, None) => table
+    }
+}
 
-    let paths: Vec<_> = fs::read_dir(images)
-        .unwrap_or_else(|_| panic!("Couldn't find image directory: {}", images.to_string_lossy())).collect();
+/// Reads the emojis from the images and flags directories.
+/// # Errors
+/// The directory that couldn't be read together with the error
+fn parse_emojis(args: &BuilderArguments) -> Result<Vec<Emoji>, (PathBuf, std::io::Error)> {
+    let table = load_table(args, args.verbose);
+    if args.verbose {
+        print_table_stats(table.as_ref());
+    }
+    parse_emojis_with(args, table.as_ref())
+}
 
-    let flag_paths: Vec<_> = match &args.flag_path {
-        None => vec![],
-        Some(flags) => fs::read_dir(flags).unwrap().collect()
-    };
+/// Prints the summary of the emoji table (see [EmojiTable::stats]) to stderr, so it doesn't mix
+/// with the output of the subcommands
+fn print_table_stats(table: Option<&EmojiTable>) {
+    match table {
+        Some(table) => eprint!("Emoji table:\n{}", table.stats()),
+        None => eprintln!("No emoji table loaded")
+    }
+}
 
+/// Like [parse_emojis], but with an emoji table that has already been loaded
+fn parse_emojis_with(args: &BuilderArguments, table: Option<&EmojiTable>) -> Result<Vec<Emoji>, (PathBuf, std::io::Error)> {
+    let scanned = scan_emojis(args, table)?;
+    Ok(finish_emojis(args, scanned, table))
+}
 
-    let emojis = paths
-        .into_par_iter()
-        .filter_map(|path| path.ok())
-        .map(|path| path.path())
-        .filter(|path| path.is_file())
-        .map(|path| Emoji::from_path(path, table.as_ref(), false));
+/// Reads the images and flags directories (see [Emoji::scan_dir]), either the ones from the
+/// settings or the ones of each pack if several packs are built together.
+/// The table may be `None` if it's still loading.
+/// # Errors
+/// The directory that couldn't be read together with the error
+fn scan_emojis(args: &BuilderArguments, table: Option<&EmojiTable>) -> Result<Vec<Vec<ScannedDir>>, (PathBuf, std::io::Error)> {
+    let ignore = !args.no_ignore;
+    let scanned = if !args.packs.is_empty() {
+        args.packs.iter()
+            .map(|pack| pack.scan(table, ignore))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let images = &args.svg_path;
+        let mut scanned = vec![Emoji::scan_dir_with_ignore(images, table, false, ignore)
+            .map_err(|err| (images.clone(), err))?];
+        if let Some(flags) = &args.flag_path {
+            scanned.push(Emoji::scan_dir_with_ignore(flags, table, true, ignore)
+                .map_err(|err| (flags.clone(), err))?);
+        }
+        vec![scanned]
+    };
+    let ignored: usize = scanned.iter().flatten().map(|scanned| scanned.ignored).sum();
+    if args.verbose && ignored > 0 {
+        eprintln!("Ignored {} file(s) in the image directories (see {} or --no-ignore)", ignored, IGNORE_FILE);
+    }
+    Ok(scanned)
+}
+
+/// Completes the scanned directories with the table (see [ScannedDir::complete]) and layers the
+/// packs (if there are several of them),
+/// then checks, deduplicates, converts and validates the emojis
+fn finish_emojis(args: &BuilderArguments, scanned: Vec<Vec<ScannedDir>>, table: Option<&EmojiTable>) -> Vec<Emoji> {
+    let emojis: Vec<Emoji> = if args.packs.is_empty() {
+        let emojis = scanned.into_iter()
+            .flatten()
+            .flat_map(|scanned| scanned.complete(table, &args.diagnostics))
+            .collect();
+        Emoji::select_variant(emojis, args.variant.as_deref(), &args.diagnostics)
+    } else {
+        let layers = args.packs.iter()
+            .zip(scanned)
+            .map(|(pack, scanned)| (pack, pack.complete(scanned, table, args.variant.as_deref(), &args.diagnostics)))
+            .collect();
+        packs::layer_emojis(layers, &args.diagnostics)
+    };
 
-    let flags = flag_paths
-        .into_par_iter()
-        .filter_map(|path| path.ok())
-        .map(|path| path.path())
-        .filter(|path| path.is_file())
-        .map(|path| Emoji::from_path(path, table.as_ref(), true));
+    // Images that are named after an emoji might end up with another sequence than intended
+    if let Some(table) = table {
+        for inconsistency in table.check_consistency(&emojis) {
+            warn!("{}", inconsistency);
+            args.diagnostics.report(Diagnostic::warning(Category::Validation, inconsistency.to_string())
+                .with_sequence(inconsistency.sequence()));
+        }
+    }
 
+    // The same sequence might have multiple files (also in different directories).
+    // This has to happen before the conversion, which replaces the files with SVG files
+    let emojis = Emoji::deduplicate_sources(emojis, args.prefer_raster, &args.diagnostics);
 
-    let emojis = emojis.chain(flags)
-        .filter_map(|emoji| match emoji {
-            Ok(emoji) => Some(emoji),
+    // Typos in file names would only be noticed by add_glyphs.py otherwise
+    let emojis = emojis.into_iter()
+        .filter_map(|mut emoji| match args.converters.convert(&mut emoji) {
+            Ok(()) => Some(emoji),
             Err(err) => {
-                error!("{:?}", err);
+                error!("Couldn't convert the image of {} ({:?}): {}", emoji, emoji.svg_path.clone().unwrap_or_default(), err);
+                args.diagnostics.report(Diagnostic::error(Category::Emoji, format!("Couldn't convert the image: {}", err)).with_emoji(&emoji));
                 None
             }
+        })
+        .filter(|emoji| match emoji.validate_sequence_with(args.max_sequence_length) {
+            Ok(()) => true,
+            Err(err) => {
+                args.diagnostics.report(Diagnostic::error(Category::Emoji, format!("Invalid sequence: {}", err)).with_emoji(emoji));
+                error!("{:?}", EmojiError::InvalidSequence(emoji.svg_path.clone().unwrap_or_default(), err));
+                false
+            }
         });
 
-
     // remove all multi character sequences if no_sequences is set
     if args.no_sequences {
         emojis.filter(|emoji| emoji.sequence.len() <= 1).collect()
-    } else {
+    } else if args.private_set {
+        // There's nothing to validate against, but Unicode emojis are probably a mistake here
         let emojis: Vec<_> = emojis.collect();
+        emojis.iter()
+            .filter(|emoji| !emoji.is_private_use())
+            .for_each(|emoji| {
+                warn!("Not in the Private Use Area: {} (Codepoint: {:X?})", emoji, emoji.sequence);
+                args.diagnostics.report(Diagnostic::warning(Category::Validation, format!("Not in the Private Use Area: {}", emoji))
+                    .with_emoji(emoji));
+            });
+        emojis
+    } else {
+        let mut emojis: Vec<_> = emojis.collect();
         if let Some(table) = table {
+            // Other fonts would be used for emojis with a text presentation and no U+FE0F
+            for emoji in emojis.iter_mut() {
+                match table.presentation_issue(&emoji.sequence) {
+                    Some(_) if args.canonicalize_vs16 => {
+                        let path = emoji.svg_path.clone().unwrap_or_default();
+                        emoji.canonicalize(table);
+                        info!("Using {:X?} for {:?}", emoji.sequence, path);
+                    }
+                    Some(issue) => {
+                        let message = match issue {
+                            PresentationIssue::MissingVs16 { canonical } =>
+                                format!("{} needs U+FE0F to be shown as an emoji (use {:X?} instead)", emoji, canonical),
+                            PresentationIssue::RedundantVs16 { canonical } =>
+                                format!("{} has a redundant U+FE0F (use {:X?} instead)", emoji, canonical)
+                        };
+                        warn!("{}", message);
+                        args.diagnostics.report(Diagnostic::warning(Category::Validation, message).with_emoji(emoji));
+                    }
+                    None => ()
+                }
+            }
+
+            // Emojis that are newer than the targeted platform are left out
+            if let Some(max_version) = args.max_emoji_version {
+                let restriction = table.restrict_to_version(emojis, max_version, args.downgrade_sequences);
+                for ((major, minor), excluded) in &restriction.excluded {
+                    info!("Excluded {} emoji(s) from Emoji {}.{}", excluded, major, minor);
+                }
+                for (sequence, fallback) in &restriction.downgraded {
+                    info!("Using the image of {:X?} for {:X?}", fallback, sequence);
+                }
+                emojis = restriction.emojis;
+            }
+
             // Validate against the table
-            let emoji_set = emojis.iter()
-                .map(|emoji| emoji.sequence.clone())
-                .collect();
-            let (result, additional) = table.validate(&emoji_set, true);
-            if let Err(missing) = result {
-                missing.iter()
-                    .for_each(|missing| warn!("Missing emoji: {} (Codepoint: {:X?}, Emoji: {})",
-                                              missing,
-                                              missing.sequence,
-                                              missing.display_emoji()));
+            let sequences = emojis.iter().map(|emoji| &emoji.sequence);
+            let result = table.validate_with(sequences, ValidationOptions { ignore_fe0f: true, max_version: args.max_emoji_version });
+            for missing in &result.missing {
+                warn!("Missing emoji: {} (Codepoint: {:X?})", missing, missing.sequence);
+                args.diagnostics.report(Diagnostic::warning(Category::Validation, format!("Missing emoji: {}", missing))
+                    .with_sequence(&missing.sequence));
             }
-            additional.iter()
+            result.additional.iter()
                 .for_each(|additional| info!("Additional emoji: {} (Codepoint: {:X?}, Emoji: )", additional, additional.sequence));
+            if !result.is_complete() {
+                warn!("{}", result.summary());
+            }
+        } else if args.max_emoji_version.is_some() {
+            warn!("There's no emoji table with the versions of the emojis, so --max-emoji-version is ignored");
         }
         emojis
     }
@@ -204,21 +841,94 @@ struct BuilderArguments<'a> {
     tables_path: Option<PathBuf>,
     build_path: PathBuf,
     output_path: PathBuf,
+    /// The font is written to stdout (`--output -`) instead of `output_path`
+    output_stdout: bool,
+    /// Where `output` has been set (see [EmojiBuilder::set_output_source])
+    output_source: SettingSource,
     builder_matches: HashMap<String, Option<ArgMatches<'a>>>,
     no_sequences: bool,
     emoji_test: Option<PathBuf>,
-    #[cfg(feature = "online")]
-    offline: bool
+    emoji_ordering: Option<PathBuf>,
+    emojibase: Option<PathBuf>,
+    /// Files with search keywords (CLDR annotations or `sequence ; keyword, keyword`)
+    keywords: Vec<PathBuf>,
+    /// Display names that replace the ones from the tables (`sequence_or_name ; New Name`)
+    name_overrides: Option<PathBuf>,
+    /// The packs whose images are layered if several of them are built together
+    /// (instead of using `svg_path` and `flag_path`)
+    packs: Vec<PackLayer>,
+    /// Only codepoints from the Private Use Area are used, so there are no emoji tables
+    private_set: bool,
+    private_names: Option<PathBuf>,
+    /// The maximum number of codepoints in a sequence
+    max_sequence_length: usize,
+    /// Converts images in other formats to SVG before they are used
+    converters: Converters,
+    /// Use images in other formats instead of SVG files with the same sequence
+    prefer_raster: bool,
+    /// The variant of the images (e.g. `blob` for `1f600~blob.svg`) that replaces the default ones
+    variant: Option<String>,
+    /// Use all files in the image directories, even the ones that are ignored (see [IGNORE_FILE])
+    no_ignore: bool,
+    explain: Option<String>,
+    /// Where the SPDX document of the licenses inventory is written to (`licenses --spdx`)
+    spdx: Option<PathBuf>,
+    info: Option<String>,
+    info_json: bool,
+    against_font: Option<PathBuf>,
+    export_format: Option<String>,
+    export_output: Option<PathBuf>,
+    serve_socket: Option<PathBuf>,
+    serve_port: Option<u16>,
+    /// Add missing and remove redundant U+FE0Fs instead of only warning about them
+    canonicalize_vs16: bool,
+    /// Leave out the emojis that are newer than this Emoji version
+    max_emoji_version: Option<(u32, u32)>,
+    /// Keep newer ZWJ sequences with the image of an older fallback
+    downgrade_sequences: bool,
+    /// Where to write the problems as JSON
+    problems_out: Option<PathBuf>,
+    /// Problems of these categories make the build fail
+    deny: DeniedCategories,
+    /// Collects the problems if `problems_out` or `deny` is set
+    diagnostics: DiagnosticsSink,
+    /// Where to write the Markdown changelog
+    changelog: Option<PathBuf>,
+    /// Write a changelog even if there's no earlier build
+    force_changelog: bool,
+    /// The license of the emojis without a sidecar file
+    license: Option<Credit>,
+    /// Print a summary of the emoji table after it has been loaded (`-v`)
+    verbose: bool,
+    #[cfg(feature = "fetch")]
+    offline: bool,
+    #[cfg(feature = "fetch")]
+    cache_dir: Option<PathBuf>
 }
 
+/// The settings of the main command (i.e. not of the builders)
+const SETTINGS: &[&str] = &[
+    "images", "flags", "tables", "build", "output", "output_dir", "no_sequences", "emoji_test",
+    "emoji_ordering", "emojibase", "keywords", "name_overrides", "private_set", "private_names", "max_sequence_length", "convert_cmd", "prefer_raster", "variant", "no_ignore", "changelog", "force_changelog", "problems_out", "deny", "canonicalize_vs16", "max_emoji_version", "downgrade_sequences", "config", "offline", "cache_dir",
+    "license", "license_url", "copyright"
+];
+/// The settings that refer to existing files or directories
+const PATH_SETTINGS: &[&str] = &["images", "flags", "tables", "emoji_test", "emoji_ordering", "emojibase", "keywords", "name_overrides", "private_names"];
+
 fn parse_args<'a>(builder_args: Vec<App<'a, 'a>>, builder_log_modules: Vec<Vec<String>>) -> BuilderArguments<'a> {
     lazy_static! {
         static ref YAML: Yaml = load_yaml!("cli.yaml").clone();
     }
     let names: Vec<String> = builder_args.iter().map(|args| String::from(args.get_name())).collect();
+    // The builders' settings can be given as environment variables as well
+    let builder_keys: Vec<&str> = builder_args.iter()
+        .flat_map(|args| args.p.flags.iter().map(|flag| flag.b.name)
+            .chain(args.p.opts.iter().map(|opt| opt.b.name)))
+        .collect();
     let log_modules = builder_log_modules
         .into_iter()
         .flatten();
+    let builder_apps = builder_args.clone();
     // IntelliJ thinks this is an error, but it isn't.
     // As you can see above, &YAML really has the type &Yaml
     let mut app: App<'a, 'a> = App::from_yaml(&*YAML)
@@ -235,14 +945,178 @@ fn parse_args<'a>(builder_args: Vec<App<'a, 'a>>, builder_log_modules: Vec<Vec<S
                 .short("p")
                 .long("print")
             )
+            .arg(Arg::with_name("spdx")
+                .help("Writes an SPDX document with the licenses and checksums of the dependencies, \
+                       the files bundled by the builder and the emoji images instead")
+                .long("spdx")
+                .value_name("FILE.json")
+                .conflicts_with("print")
+            )
             .help("Extracts the license information for the used dependencies to the specified directory"))
+        .subcommand(SubCommand::with_name("explain")
+            .arg(Arg::with_name("emoji")
+                .help("The codepoint sequence (e.g. 1f3f3-fe0f-200d-1f308) or the name of the emoji")
+                .required(true)
+                .value_name("EMOJI")
+            )
+            .help("Shows which of the emoji tables contributed to the kinds and the name of an emoji"))
+        .subcommand(SubCommand::with_name("info")
+            .arg(Arg::with_name("emoji")
+                .help("The codepoint sequence (e.g. 1f3f3-fe0f-200d-1f308), the name or the SVG file of the emoji")
+                .required(true)
+                .value_name("EMOJI")
+            )
+            .arg(Arg::with_name("json")
+                .help("Prints the information as JSON")
+                .long("json")
+                .takes_value(false)
+            )
+            .help("Shows the sequence, name, kinds, files and build state of a single emoji"))
+        .subcommand(SubCommand::with_name("validate")
+            .arg(Arg::with_name("against_font")
+                .help("A font (e.g. NotoColorEmoji.ttf) whose cmap and ligatures are compared with the emojis")
+                .long("against-font")
+                .required(true)
+                .takes_value(true)
+                .value_name("FONT")
+            )
+            .help("Compares the emojis and the emoji table with the sequences in an existing font"))
+        .subcommand(SubCommand::with_name("check-viewbox")
+            .arg(Arg::with_name("normalize_viewbox")
+                .help("Writes copies of the SVG files with a viewBox of 0 0 SIZE SIZE to the output directory")
+                .long("normalize-viewbox")
+                .takes_value(true)
+                .value_name("SIZE")
+                .requires("normalized_dir")
+            )
+            .arg(Arg::with_name("normalized_dir")
+                .help("The directory for the normalized SVG files (the original files are never modified)")
+                .long("normalized-dir")
+                .takes_value(true)
+                .value_name("DIR")
+            )
+            .help("Reports the viewBox sizes of the emoji images and flags and the files without a (square) viewBox"))
+        .subcommand(SubCommand::with_name("contact-sheet")
+            .arg(Arg::with_name("background")
+                .help("The background colors (RRGGBB or RRGGBBAA); one sheet is drawn per color")
+                .long("background")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .value_name("COLOR")
+                .default_value("ffffff,000000")
+            )
+            .arg(Arg::with_name("columns")
+                .help("The number of emojis per row")
+                .long("columns")
+                .takes_value(true)
+                .value_name("COLUMNS")
+                .default_value("32")
+            )
+            .arg(Arg::with_name("labels")
+                .help("Writes the sequence below each emoji")
+                .long("labels")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("sheet_output")
+                .help("The directory to write the contact sheets to")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value("contact_sheets")
+            )
+            .help("Draws the rendered emojis of the build directory on one large PNG file per background color, \
+            e.g. with --background ffffff,000000,ff8800 to check their transparency"))
+        .subcommand(SubCommand::with_name("export-layout")
+            .arg(Arg::with_name("layout_output")
+                .help("The JSON file to write the layout to (stdout if not set)")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("FILE")
+            )
+            .help("Exports the groups and subgroups of the emoji-test.txt file (--emoji-test) as a keyboard layout")
+            .after_help(LAYOUT_SCHEMA))
+        .subcommand(SubCommand::with_name("export")
+            .arg(Arg::with_name("export_format")
+                .help("The format of the exported file")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["emoji-test"])
+                .default_value("emoji-test")
+            )
+            .arg(Arg::with_name("export_output")
+                .help("The file to write to (stdout if not set)")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("FILE")
+            )
+            .help("Exports an emoji-test.txt file with exactly the emojis of the set, using the groups of --emoji-test"))
+        .subcommand(SubCommand::with_name("serve")
+            .arg(Arg::with_name("socket")
+                .help("The Unix socket to listen on")
+                .long("socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .required_unless("port")
+                .conflicts_with("port")
+            )
+            .arg(Arg::with_name("port")
+                .help("The port to listen on (only on localhost)")
+                .long("port")
+                .takes_value(true)
+                .value_name("PORT")
+            )
+            .help("Keeps the builder and the emoji table in memory and builds on JSON requests (one per line), \
+            e.g. {\"action\": \"build\", \"output\": \"font.ttf\"}, {\"action\": \"render\", \"sequence\": \"1f914\"} \
+            or {\"action\": \"validate\"}. The builder uses the settings from the config file."))
+        .subcommand(SubCommand::with_name("build")
+            .arg(Arg::with_name("pack")
+                .help("The configuration of the pack (a YAML or JSON file)")
+                .long_help("The configuration of the pack (a YAML or JSON file). \
+                Several packs (e.g. base emojis, flags and seasonal extras) are built into one font \
+                with the later ones taking precedence: their settings and their images replace the ones of the earlier packs.")
+                .required(true)
+                .multiple(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("pack_sha256")
+                .help("Only build the pack if its configuration has this SHA256 hash")
+                .long_help("Only build the pack if its configuration has this SHA256 hash. \
+                Without it, the hash is checked against <pack>.sha256 (as written by sha256sum) if that file exists. \
+                With several packs, it has to be given once for each of them (in the same order).")
+                .long("pack-sha256")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("HASH")
+            )
+            .arg(Arg::with_name("builder")
+                .help("Builds the pack with a different builder than the one it declares")
+                .long("builder")
+                .takes_value(true)
+                .value_name("BUILDER")
+            )
+            // The builder's arguments on the command line override the ones from the pack
+            .subcommands(builder_args.clone())
+            .help("Builds an emoji pack with the builder and the options from its configuration, \
+            e.g. {\"images\": \"./svg\", \"builder\": {\"name\": \"blobmoji\", \"options\": {\"waveflag\": true}}}. \
+            The other arguments on the command line take precedence over the ones in the pack."))
         .subcommands(builder_args);
 
-    if cfg!(feature = "online") {
+    if cfg!(feature = "fetch") {
         app = app.arg(Arg::with_name("offline")
             .long("offline")
             .takes_value(false)
             .help("Disable the inclusion of online emoji tables")
+        ).arg(Arg::with_name("cache_dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Where to cache the online emoji tables (default: ~/.cache/emoji_builder)")
         );
     }
 
@@ -255,7 +1129,16 @@ fn parse_args<'a>(builder_args: Vec<App<'a, 'a>>, builder_log_modules: Vec<Vec<S
         .verbosity(matches.occurrences_of("verbose") as usize)
         .init().unwrap();
 
-    if let Some(matches) = matches.subcommand_matches("licenses") {
+    // The SPDX document needs the emojis, so it's written by the build (see write_spdx)
+    let spdx = matches.subcommand_matches("licenses")
+        .and_then(|matches| matches.value_of("spdx"))
+        .map(PathBuf::from);
+    if let (Some(matches), None) = (matches.subcommand_matches("licenses"), &spdx) {
+        if recurse_included_dir(&LICENSES).is_empty() {
+            error!("There are no embedded licenses in this build \
+                    (the licenses directory didn't exist when it was compiled, see EMOJI_BUILDER_LICENSES_DIR)");
+            exit(1);
+        }
         let print = matches.is_present("print");
         if !print {
             let output_dir = matches.value_of("output_dir").unwrap();
@@ -297,30 +1180,242 @@ fn parse_args<'a>(builder_args: Vec<App<'a, 'a>>, builder_log_modules: Vec<Vec<S
     }
 
 
-    let images: PathBuf = matches.value_of("images").unwrap().into();
-    let flags = matches.value_of("flags");
-    let tables = matches.value_of("tables");
-    let build: PathBuf = matches.value_of("build").unwrap().into();
+    let mut settings = Settings::new();
+    settings.load_matches(&matches, SETTINGS);
+    settings.load_env(std::env::vars());
+    let build_matches = matches.subcommand_matches("build");
+    let pack_paths: Vec<&str> = build_matches
+        .and_then(|matches| matches.values_of("pack"))
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    let pinned_hashes: Vec<&str> = build_matches
+        .and_then(|matches| matches.values_of("pack_sha256"))
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    if !pinned_hashes.is_empty() && pinned_hashes.len() != pack_paths.len() {
+        error!("--pack-sha256 has to be given once for each pack ({} hashes for {} packs)", pinned_hashes.len(), pack_paths.len());
+        exit(1);
+    }
+    let loaded_packs: Vec<Pack> = pack_paths.iter()
+        .enumerate()
+        .map(|(index, path)| Pack::from_file_verified(path, pinned_hashes.get(index).copied()).unwrap_or_else(|err| {
+            match err {
+                ConfigError::Checksum(_, expected, actual) =>
+                    error!("The pack {:?} has been changed: its SHA256 hash is {}, but {} was expected", path, actual, expected),
+                err => error!("Couldn't load the pack {:?}: {:?}", path, err)
+            }
+            exit(1);
+        }))
+        .collect();
+    // A single pack is used like a configuration file (whose images can be replaced on the
+    // command line), while the images of several packs are layered
+    let pack_layers: Vec<PackLayer> = if loaded_packs.len() > 1 {
+        pack_paths.iter()
+            .zip(&loaded_packs)
+            .map(|(path, pack)| PackLayer::new(path, pack).unwrap_or_else(|err| {
+                error!("Couldn't load the pack {:?}: {:?}", path, err);
+                exit(1);
+            }))
+            .collect()
+    } else {
+        vec![]
+    };
+    let pack = if loaded_packs.is_empty() {
+        None
+    } else {
+        Some(Pack::layered(&loaded_packs))
+    };
+    if let Some(pack) = &pack {
+        settings.merge(pack.settings.clone());
+    }
+    let config = settings.get_path("config").unwrap_or_else(|err| {
+        error!("{:?}", err);
+        exit(1);
+    });
+    if let Some(config) = config {
+        if let Err(err) = settings.load_file(&config) {
+            error!("Couldn't load the configuration file {:?}: {:?}", config, err);
+            exit(1);
+        }
+    }
+
+    let known_keys: Vec<&str> = SETTINGS.iter().copied()
+        .chain(builder_keys)
+        .collect();
+    let issues = settings.validate(&known_keys, PATH_SETTINGS);
+    if !issues.is_empty() {
+        if matches.is_present("strict_config") {
+            error!("{:?}", ConfigError::Schema(issues));
+            exit(1);
+        }
+        for issue in issues {
+            warn!("{}", issue);
+        }
+    }
 
-    let output = matches.value_of("output").unwrap();
-    let output_dir = matches.value_of("output_dir").unwrap();
-    let output_path = PathBuf::from(output_dir).join(PathBuf::from(output));
+    if matches.is_present("print_config") {
+        println!("{}", settings.dump());
+        exit(0);
+    }
 
-    let no_sequences = matches.is_present("no_sequences");
+    let get_bool = |key: &str| settings.get_bool(key).unwrap_or_else(|err| {
+        error!("{:?}", err);
+        exit(1);
+    });
+    let get_path = |key: &str| settings.get_path(key).unwrap_or_else(|err| {
+        error!("{:?}", err);
+        exit(1);
+    });
 
-    let flags = flags.map(PathBuf::from);
+    let images = get_path("images").unwrap();
+    let flags = get_path("flags");
+    let tables = get_path("tables");
+    let build = get_path("build").unwrap();
 
-    let emoji_test = matches.value_of("emoji_test").map(PathBuf::from);
+    let output = get_path("output").unwrap();
+    let output_stdout = output == Path::new("-");
+    let output_source = settings.source("output").cloned().unwrap_or(SettingSource::Default);
+    let output_dir = get_path("output_dir").unwrap();
+    let output_path = output_dir.join(output);
 
-    #[cfg(feature = "online")]
-    let offline = matches.is_present("offline");
+    let no_sequences = get_bool("no_sequences");
 
-    let tables = tables.map(PathBuf::from);
+    let emoji_test = get_path("emoji_test");
+    let emoji_ordering = get_path("emoji_ordering");
+    let emojibase = get_path("emojibase");
+    let keywords: Vec<PathBuf> = settings.get_paths("keywords").unwrap_or_else(|err| {
+        error!("{:?}", err);
+        exit(1);
+    });
+    let name_overrides = get_path("name_overrides");
 
-    let subcommands: Vec<_> = names.iter()
+    let private_set = get_bool("private_set");
+    let private_names = get_path("private_names");
+
+    let max_sequence_length = match settings.get("max_sequence_length") {
+        Some(length) => length.parse().unwrap_or_else(|_| {
+            error!("{:?}", ConfigError::InvalidValue(String::from("max_sequence_length"), length.to_string()));
+            exit(1);
+        }),
+        None => Emoji::MAX_SEQUENCE_LENGTH
+    };
+
+    let prefer_raster = get_bool("prefer_raster");
+    let variant = settings.get("variant").map(String::from);
+    let no_ignore = get_bool("no_ignore");
+    let canonicalize_vs16 = get_bool("canonicalize_vs16");
+    let max_emoji_version = settings.get("max_emoji_version").map(|version| match version.parse::<EmojiVersion>() {
+        Ok(version) => version.as_tuple(),
+        Err(err) => {
+            error!("{:?}", err);
+            exit(1);
+        }
+    });
+    let downgrade_sequences = get_bool("downgrade_sequences");
+
+    let problems_out = settings.get("problems_out").map(PathBuf::from);
+    let deny = DeniedCategories::parse(settings.get_list("deny").unwrap_or_default()).unwrap_or_else(|err| {
+        error!("{:?}", err);
+        exit(1);
+    });
+    let diagnostics = if problems_out.is_some() || !deny.is_empty() {
+        DiagnosticsSink::new()
+    } else {
+        DiagnosticsSink::default()
+    };
+
+    let changelog = settings.get("changelog").map(PathBuf::from);
+    let force_changelog = get_bool("force_changelog");
+
+    let license = Credit::from_settings(&settings);
+
+    let convert_cmds = settings.get_list("convert_cmd").unwrap_or_default();
+    let converters = Converters::from_specs(convert_cmds, build.join("converted"))
+        .unwrap_or_else(|err| {
+            error!("{}", err);
+            exit(1);
+        });
+
+    if let Some(matches) = matches.subcommand_matches("check-viewbox") {
+        check_viewbox(matches, &images, flags.as_ref());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("contact-sheet") {
+        contact_sheet(matches, &build);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("export-layout") {
+        export_layout(matches, emoji_test.as_ref(), emoji_ordering.as_ref(), &keywords);
+    }
+
+    let explain = matches.subcommand_matches("explain")
+        .and_then(|matches| matches.value_of("emoji"))
+        .map(String::from);
+
+    let info = matches.subcommand_matches("info")
+        .and_then(|matches| matches.value_of("emoji"))
+        .map(String::from);
+    let info_json = matches.subcommand_matches("info")
+        .map(|matches| matches.is_present("json"))
+        .unwrap_or(false);
+
+    let export_format = matches.subcommand_matches("export")
+        .and_then(|matches| matches.value_of("export_format"))
+        .map(String::from);
+    let export_output = matches.subcommand_matches("export")
+        .and_then(|matches| matches.value_of("export_output"))
+        .map(PathBuf::from);
+
+    let serve_socket = matches.subcommand_matches("serve")
+        .and_then(|matches| matches.value_of("socket"))
+        .map(PathBuf::from);
+    let serve_port = matches.subcommand_matches("serve")
+        .and_then(|matches| matches.value_of("port"))
+        .map(|port| port.parse().unwrap_or_else(|_| {
+            error!("{:?}", ConfigError::InvalidValue(String::from("port"), port.to_string()));
+            exit(1);
+        }));
+
+    let against_font = matches.subcommand_matches("validate")
+        .and_then(|matches| matches.value_of("against_font"))
+        .map(PathBuf::from);
+
+    let verbose = matches.occurrences_of("verbose") > 0;
+
+    #[cfg(feature = "fetch")]
+    let offline = get_bool("offline");
+    #[cfg(feature = "fetch")]
+    let cache_dir = get_path("cache_dir");
+
+    let mut subcommands: Vec<_> = names.iter()
         .map(|name| matches.subcommand_matches(name).cloned())
         .collect();
 
+    // A pack is built by the builder it declares (unless another one is chosen with --builder),
+    // whose options are parsed as if they were given on the command line
+    if let (Some(pack), Some(build_matches)) = (&pack, matches.subcommand_matches("build")) {
+        let builder = build_matches.value_of("builder")
+            .or(pack.builder.as_deref())
+            .or_else(|| names.first().map(String::as_str))
+            .unwrap_or_default();
+        let index = names.iter().position(|name| name == builder).unwrap_or_else(|| {
+            error!("Unknown builder: {} (available: {})", builder, names.join(", "));
+            exit(1);
+        });
+        // The options are meant for the declared builder only
+        let options = if pack.builder.as_deref() == Some(builder) {
+            pack.clone()
+        } else {
+            Pack::default()
+        };
+        let builder_matches = options.builder_matches(builder_apps[index].clone(), build_matches.subcommand_matches(builder))
+            .unwrap_or_else(|err| {
+                error!("Invalid options for {}: {:?}", builder, err);
+                exit(1);
+            });
+        subcommands[index] = Some(builder_matches);
+    }
+
     // We want to move the name here, but then it would not be possible to use it in
     // subcommand_matches anymore, so this is done earlier
     let builder_matches: HashMap<_, _> = names.into_iter()
@@ -333,20 +1428,212 @@ fn parse_args<'a>(builder_args: Vec<App<'a, 'a>>, builder_log_modules: Vec<Vec<S
         tables_path: tables,
         build_path: build,
         output_path,
+        output_stdout,
+        output_source,
         builder_matches,
         no_sequences,
         emoji_test,
-        #[cfg(feature = "online")]
-        offline
+        emoji_ordering,
+        emojibase,
+        keywords,
+        name_overrides,
+        packs: pack_layers,
+        private_set,
+        private_names,
+        max_sequence_length,
+        converters,
+        prefer_raster,
+        variant,
+        no_ignore,
+        explain,
+        spdx,
+        info,
+        info_json,
+        against_font,
+        export_format,
+        export_output,
+        serve_socket,
+        serve_port,
+        canonicalize_vs16,
+        max_emoji_version,
+        downgrade_sequences,
+        problems_out,
+        deny,
+        diagnostics,
+        changelog,
+        force_changelog,
+        license,
+        verbose,
+        #[cfg(feature = "fetch")]
+        offline,
+        #[cfg(feature = "fetch")]
+        cache_dir
     }
 }
 
+/// Writes a file with exactly the emojis of the set (in the given format)
+fn export(args: &BuilderArguments, format: &str) {
+    let emojis = parse_emojis(args).unwrap_or_else(|(dir, err)| {
+        error!("Couldn't read {:?}: {}", dir, err);
+        exit(1);
+    });
+    let table = load_table(args, false);
+    // The groups and the fully-qualified sequences are taken from emoji-test.txt
+    let layout = match &args.emoji_test {
+        Some(emoji_test) => std::fs::File::open(emoji_test)
+            .map(BufReader::new)
+            .and_then(Layout::from_test_data)
+            .unwrap_or_else(|err| {
+                error!("Couldn't read {:?}: {:?}", emoji_test, err);
+                exit(1);
+            }),
+        None => Layout::default()
+    };
+    info!("Exporting {} emojis as {}", emojis.len(), format);
 
-fn recurse_included_dir<'a>(dir: &'a include_dir::Dir) -> Vec<&'a include_dir::File<'a>> {
-    dir.files().iter()
-        .chain(dir.dirs().iter()
-            .map(|dir| recurse_included_dir(dir))
-            .flatten()
-        )
-        .collect()
-}
\ No newline at end of file
+    let write = |writer: &mut dyn Write| match format {
+        "emoji-test" => layout.write_test_data(&emojis, table.as_ref(), writer),
+        _ => unreachable!("Unknown export format: {}", format)
+    };
+    let result = match &args.export_output {
+        Some(output) => std::fs::File::create(output)
+            .map(std::io::BufWriter::new)
+            .and_then(|mut file| write(&mut file).and_then(|_| file.flush())),
+        None => write(&mut std::io::stdout())
+    };
+    if let Err(err) = result {
+        error!("Couldn't export the emojis: {:?}", err);
+        exit(1);
+    }
+}
+
+/// Checks (and optionally normalizes) the viewBox of all SVG files in the images and flags
+/// directory. Exits afterwards.
+fn check_viewbox(matches: &ArgMatches, images: &Path, flags: Option<&PathBuf>) -> ! {
+    let files: Vec<PathBuf> = std::iter::once(images)
+        .chain(flags.map(PathBuf::as_path))
+        .filter_map(|dir| match dir.read_dir() {
+            Ok(entries) => Some(entries),
+            Err(err) => {
+                error!("Couldn't read {:?}: {:?}", dir, err);
+                None
+            }
+        })
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|extension| extension == "svg").unwrap_or(false))
+        .collect();
+
+    let report = ViewBoxReport::check(&files);
+    print!("{}", report);
+
+    if let Some(size) = matches.value_of("normalize_viewbox") {
+        let size: f64 = match size.parse() {
+            Ok(size) if size > 0.0 => size,
+            _ => {
+                error!("Invalid viewBox size: {}", size);
+                exit(1);
+            }
+        };
+        let output_dir = PathBuf::from(matches.value_of("normalized_dir").unwrap());
+        match viewbox::normalize_files(&files, &output_dir, size) {
+            Ok(changed) => println!("Normalized {} of {} files to {:?}", changed, files.len(), output_dir),
+            Err(err) => {
+                error!("Couldn't normalize the viewBoxes: {:?}", err);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    exit(if report.missing.is_empty() && report.non_square.is_empty() && report.invalid.is_empty() {
+        0
+    } else {
+        1
+    });
+}
+
+/// Draws the PNG files of the build directory on contact sheets. Exits afterwards.
+fn contact_sheet(matches: &ArgMatches, build: &Path) -> ! {
+    let backgrounds: Vec<[u8; 4]> = matches.values_of("background").unwrap()
+        .map(|color| contact_sheet::parse_background(color)
+            .unwrap_or_else(|| {
+                error!("{:?}", ConfigError::InvalidValue(String::from("background"), color.to_string()));
+                exit(1);
+            }))
+        .collect();
+    let columns = matches.value_of("columns").unwrap();
+    let columns = match columns.parse() {
+        Ok(columns) if columns > 0 => columns,
+        _ => {
+            error!("{:?}", ConfigError::InvalidValue(String::from("columns"), columns.to_string()));
+            exit(1);
+        }
+    };
+    let output_dir = PathBuf::from(matches.value_of("sheet_output").unwrap());
+    let png_dir = BuildDir::at(build).png_dir();
+    match contact_sheet::write_contact_sheets(&png_dir, &output_dir, &backgrounds, columns, matches.is_present("labels")) {
+        Ok(sheets) => {
+            sheets.iter().for_each(|sheet| println!("Wrote {:?}", sheet));
+            exit(0);
+        }
+        Err(err) => {
+            error!("Couldn't draw the contact sheets of {:?}: {:?}", png_dir, err);
+            exit(1);
+        }
+    }
+}
+
+/// Writes the keyboard layout from the emoji-test.txt file (with the search keywords) as JSON.
+/// Exits afterwards.
+fn export_layout(matches: &ArgMatches, emoji_test: Option<&PathBuf>, emoji_ordering: Option<&PathBuf>, keywords: &[PathBuf]) -> ! {
+    let emoji_test = match emoji_test {
+        Some(emoji_test) => emoji_test,
+        None => {
+            error!("export-layout needs an emoji-test.txt file (--emoji-test)");
+            exit(1);
+        }
+    };
+    let mut layout = match std::fs::File::open(emoji_test).map(BufReader::new).and_then(Layout::from_test_data) {
+        Ok(layout) => layout,
+        Err(err) => {
+            error!("Couldn't read {:?}: {:?}", emoji_test, err);
+            exit(1);
+        }
+    };
+    if let Some(emoji_ordering) = emoji_ordering {
+        let mut table = EmojiTable::new();
+        match table.expand_ordering_from_file(emoji_ordering) {
+            Ok(_) => layout.sort_by_ordering(&table),
+            Err(err) => {
+                error!("Couldn't read {:?}: {:?}", emoji_ordering, err);
+                exit(1);
+            }
+        }
+    }
+    if !keywords.is_empty() {
+        let mut table = EmojiTable::new();
+        for path in keywords {
+            if let Err(err) = table.expand_keywords_from_file(path) {
+                error!("Couldn't read {:?}: {:?}", path, err);
+                exit(1);
+            }
+        }
+        layout.add_keywords(&table);
+    }
+    info!("Exporting {} emojis", layout.len());
+
+    let result = match matches.value_of("layout_output") {
+        Some(output) => std::fs::File::create(output)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(file, &layout)),
+        None => serde_json::to_writer_pretty(std::io::stdout(), &layout)
+            .map(|_| println!())
+    };
+    if let Err(err) = result {
+        error!("Couldn't write the layout: {:?}", err);
+        exit(1);
+    }
+    exit(0);
+}
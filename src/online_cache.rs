@@ -0,0 +1,569 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! A cache for the files that are downloaded from unicode.org.
+//!
+//! The files are kept in memory for the current process and stored on disk as
+//! `<cache dir>/<version>/<file>`, together with a `<file>.meta.json` that contains the `ETag` and
+//! `Last-Modified` headers of the response. If a file is already on disk, a conditional request is
+//! sent, so the file is only downloaded again if it has changed.
+//!
+//! Multiple processes (e.g. parallel CI jobs) can share the same cache directory:
+//! Downloads of the same file are serialized with a lock file and the files are replaced
+//! atomically.
+//! If the cache directory can't be written to, only the in-memory cache is used.
+//!
+//! The files are downloaded with an [HttpFetcher], so embedders can use their own HTTP client.
+//! With the `fetch` feature, it's implemented for [reqwest::blocking::Client].
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+#[cfg(feature = "fetch")]
+use reqwest::blocking::Client;
+#[cfg(feature = "fetch")]
+use reqwest::header::{ETAG, HeaderName, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+#[cfg(feature = "fetch")]
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::configs::ENV_PREFIX;
+use crate::emoji_tables::ExpansionError;
+
+/// How long to wait for another process to finish its download
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+/// Lock files that are older than this are assumed to be left over from a crashed process
+const STALE_LOCK: Duration = Duration::from_secs(300);
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+const META_SUFFIX: &str = ".meta.json";
+const LOCK_SUFFIX: &str = ".lock";
+
+/// The validators of a cached response that are used for conditional requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMeta {
+    /// The `ETag` header
+    pub etag: Option<String>,
+    /// The `Last-Modified` header
+    pub last_modified: Option<String>,
+}
+
+/// An error while downloading a file
+#[derive(Debug)]
+pub enum FetchError {
+    /// The server answered with an error status (e.g. 404)
+    Status(u16),
+    /// The request timed out
+    Timeout,
+    /// Any other error, e.g. if there's no connection
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Downloads the files for an [OnlineCache] (blocking)
+pub trait HttpFetcher {
+    /// Downloads the content of the URL
+    fn fetch(&self, url: &str) -> Result<Bytes, FetchError>;
+
+    /// Downloads the file, using the validators of the cached copy (if given) for a conditional
+    /// request. Returns `None` if it has not been modified.
+    ///
+    /// The default implementation always downloads the file and returns no validators, i.e. the
+    /// cached copy is never reused if the file can be downloaded.
+    fn fetch_conditional(&self, url: &str, _meta: Option<&CacheMeta>) -> Result<Option<(Bytes, CacheMeta)>, FetchError> {
+        self.fetch(url).map(|bytes| Some((bytes, CacheMeta::default())))
+    }
+}
+
+/// A cache for downloaded files in memory and (optionally) on disk
+#[derive(Debug)]
+pub struct OnlineCache {
+    dir: Option<PathBuf>,
+    memory: RwLock<HashMap<String, Bytes>>,
+}
+
+impl OnlineCache {
+    /// Creates a cache in the given directory (which is created if necessary).
+    /// If it can't be written to, a warning is logged and only the in-memory cache is used.
+    pub fn new<P: AsRef<Path>>(dir: P) -> OnlineCache {
+        let dir = dir.as_ref();
+        let writable = create_dir_all(dir).and_then(|_| tempfile::tempfile_in(dir));
+        match writable {
+            Ok(_) => OnlineCache {
+                dir: Some(dir.to_path_buf()),
+                memory: RwLock::default(),
+            },
+            Err(err) => {
+                warn!("Can't use {:?} as a cache directory, keeping the downloads in memory only: {}", dir, err);
+                OnlineCache::memory_only()
+            }
+        }
+    }
+
+    /// Creates a cache that only keeps the files in memory
+    pub fn memory_only() -> OnlineCache {
+        OnlineCache {
+            dir: None,
+            memory: RwLock::default(),
+        }
+    }
+
+    /// Creates a cache in [OnlineCache::default_dir] (or in memory if there is none)
+    pub fn with_default_dir() -> OnlineCache {
+        match Self::default_dir() {
+            Some(dir) => OnlineCache::new(dir),
+            None => OnlineCache::memory_only()
+        }
+    }
+
+    /// The cache that is shared by the whole process (in [OnlineCache::default_dir])
+    pub fn shared() -> &'static OnlineCache {
+        lazy_static! {
+            static ref SHARED: OnlineCache = OnlineCache::with_default_dir();
+        }
+        &SHARED
+    }
+
+    /// The default cache directory, i.e. (in this order):
+    /// - `$EMOJI_BUILDER_CACHE_DIR`
+    /// - `$XDG_CACHE_HOME/emoji_builder`
+    /// - `$HOME/.cache/emoji_builder`
+    /// - `%LOCALAPPDATA%\emoji_builder`
+    pub fn default_dir() -> Option<PathBuf> {
+        let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+        var(&format!("{}CACHE_DIR", ENV_PREFIX))
+            .or_else(|| var("XDG_CACHE_HOME").map(|dir| dir.join("emoji_builder")))
+            .or_else(|| var("HOME").map(|dir| dir.join(".cache").join("emoji_builder")))
+            .or_else(|| var("LOCALAPPDATA").map(|dir| dir.join("emoji_builder")))
+    }
+
+    /// The directory that is used on disk (if any)
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Returns the content of the URL, which is stored as `<version>/<file>` on disk.
+    ///
+    /// If the file can't be downloaded but is already on disk, the cached copy is used.
+    pub fn get(&self, fetcher: &dyn HttpFetcher, url: &str, version: &str, file: &str) -> Result<Bytes, ExpansionError> {
+        if let Some(bytes) = self.memory.read().unwrap().get(url) {
+            debug!("Using {} from memory", url);
+            return Ok(bytes.clone());
+        }
+        let bytes = match &self.dir {
+            Some(dir) => Self::get_with_disk(fetcher, url, &dir.join(version), file)?,
+            None => fetcher.fetch(url)?
+        };
+        self.memory.write().unwrap().insert(url.to_string(), bytes.clone());
+        Ok(bytes)
+    }
+
+    fn get_with_disk(fetcher: &dyn HttpFetcher, url: &str, dir: &Path, file: &str) -> Result<Bytes, ExpansionError> {
+        if let Err(err) = create_dir_all(dir) {
+            warn!("Couldn't create the cache directory {:?}: {}", dir, err);
+        }
+        // Without the lock, concurrent processes would (at worst) download the file twice
+        let _lock = match CacheLock::acquire(dir.join(format!("{}{}", file, LOCK_SUFFIX))) {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                warn!("Couldn't lock the cache for {}: {}", file, err);
+                None
+            }
+        };
+
+        let path = dir.join(file);
+        let meta_path = dir.join(format!("{}{}", file, META_SUFFIX));
+        let cached = Self::read_cached(&path, &meta_path);
+
+        match fetcher.fetch_conditional(url, cached.as_ref().map(|(_, meta)| meta)) {
+            Ok(Some((bytes, meta))) => {
+                if let Err(err) = Self::store(dir, &path, &meta_path, &bytes, &meta) {
+                    warn!("Couldn't store {} in the cache: {}", url, err);
+                }
+                Ok(bytes)
+            }
+            Ok(None) => {
+                debug!("{} has not been modified, using the cached copy", url);
+                // There can't be a 304 response without a cached copy
+                Ok(cached.map(|(content, _)| content).unwrap_or_default())
+            }
+            Err(err) => match cached {
+                Some((content, _)) => {
+                    warn!("Couldn't download {}, using the cached copy: {}", url, err);
+                    Ok(content)
+                }
+                None => Err(err.into())
+            }
+        }
+    }
+
+    fn read_cached(path: &Path, meta_path: &Path) -> Option<(Bytes, CacheMeta)> {
+        let content = std::fs::read(path).ok()?;
+        let meta = File::open(meta_path).ok()
+            .and_then(|meta| serde_json::from_reader(meta).ok())
+            .unwrap_or_default();
+        Some((Bytes::from(content), meta))
+    }
+
+    /// Replaces the cached file and its metadata (atomically, one after the other)
+    fn store(dir: &Path, path: &Path, meta_path: &Path, content: &[u8], meta: &CacheMeta) -> std::io::Result<()> {
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(content)?;
+        file.persist(path).map_err(|err| err.error)?;
+
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&mut file, meta)?;
+        file.persist(meta_path).map_err(|err| err.error)?;
+        Ok(())
+    }
+}
+
+/// The bundled client, which sends conditional requests with `If-None-Match` and
+/// `If-Modified-Since`
+#[cfg(feature = "fetch")]
+impl HttpFetcher for Client {
+    fn fetch(&self, url: &str) -> Result<Bytes, FetchError> {
+        match self.fetch_conditional(url, None)? {
+            Some((bytes, _)) => Ok(bytes),
+            None => unreachable!("Got 304 Not Modified for an unconditional request")
+        }
+    }
+
+    fn fetch_conditional(&self, url: &str, meta: Option<&CacheMeta>) -> Result<Option<(Bytes, CacheMeta)>, FetchError> {
+        let mut request = self.get(url);
+        if let Some(meta) = meta {
+            if let Some(etag) = &meta.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        let response = request.send()?;
+        if meta.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let header = |name: HeaderName| response.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let meta = CacheMeta {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        Ok(Some((response.bytes()?, meta)))
+    }
+}
+
+#[cfg(feature = "fetch")]
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            FetchError::Timeout
+        } else if let Some(status) = err.status() {
+            FetchError::Status(status.as_u16())
+        } else {
+            FetchError::Other(Box::new(err))
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Status(status) => write!(f, "HTTP status {}", status),
+            FetchError::Timeout => write!(f, "The request timed out"),
+            FetchError::Other(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+/// A lock file that is removed when it's dropped
+struct CacheLock(PathBuf);
+
+impl CacheLock {
+    fn acquire(path: PathBuf) -> std::io::Result<CacheLock> {
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(CacheLock(path)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| modified.elapsed().unwrap_or_default() > STALE_LOCK)
+                        .unwrap_or(false);
+                    if stale {
+                        warn!("Removing stale lock file {:?}", path);
+                        std::fs::remove_file(&path).ok();
+                    } else if start.elapsed() > LOCK_TIMEOUT {
+                        return Err(std::io::Error::new(ErrorKind::TimedOut, format!("{:?} is still locked", path)));
+                    } else {
+                        sleep(LOCK_RETRY);
+                    }
+                }
+                Err(err) => return Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+/// A minimal HTTP server that serves one file with an `ETag` and answers conditional requests.
+/// It records the `If-None-Match` header of every request.
+#[cfg(all(test, feature = "fetch"))]
+struct FixtureServer {
+    url: String,
+    content: std::sync::Arc<std::sync::Mutex<(String, String)>>,
+    requests: std::sync::Arc<std::sync::Mutex<Vec<Option<String>>>>,
+}
+
+#[cfg(all(test, feature = "fetch"))]
+impl FixtureServer {
+    fn start(etag: &str, body: &str) -> FixtureServer {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/emoji-test.txt", listener.local_addr().unwrap());
+        let content = Arc::new(Mutex::new((etag.to_string(), body.to_string())));
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let (server_content, server_requests) = (content.clone(), requests.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                // All headers need to be read before answering
+                let headers: Vec<String> = BufReader::new(&stream).lines()
+                    .map(|line| line.unwrap())
+                    .take_while(|line| !line.is_empty())
+                    .collect();
+                let if_none_match = headers.iter()
+                    .find_map(|line| {
+                        let parts: Vec<&str> = line.splitn(2, ':').collect();
+                        match parts.as_slice() {
+                            [name, value] if name.eq_ignore_ascii_case("if-none-match") => Some(value.trim().to_string()),
+                            _ => None
+                        }
+                    });
+                let (etag, body) = server_content.lock().unwrap().clone();
+                let response = if if_none_match.as_ref() == Some(&etag) {
+                    format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\nConnection: close\r\n\r\n", etag)
+                } else {
+                    format!("HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", etag, body.len(), body)
+                };
+                server_requests.lock().unwrap().push(if_none_match);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        FixtureServer {
+            url,
+            content,
+            requests,
+        }
+    }
+
+    fn requests(&self) -> Vec<Option<String>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(all(test, feature = "fetch"))]
+fn test_client() -> Client {
+    Client::builder().no_proxy().build().unwrap()
+}
+
+#[cfg(feature = "fetch")]
+#[test]
+fn test_conditional_requests() {
+    let server = FixtureServer::start("\"v1\"", "first");
+    let client = test_client();
+    let dir = tempfile::tempdir().unwrap();
+
+    let cache = OnlineCache::new(dir.path());
+    assert_eq!(cache.dir(), Some(dir.path()));
+    let bytes = cache.get(&client, &server.url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("first"));
+    assert_eq!(server.requests(), vec![None]);
+    assert_eq!(std::fs::read_to_string(dir.path().join("13.0").join("emoji-test.txt")).unwrap(), "first");
+    assert!(dir.path().join("13.0").join("emoji-test.txt.meta.json").is_file());
+    assert!(!dir.path().join("13.0").join("emoji-test.txt.lock").exists());
+
+    // The second time, it comes from memory
+    cache.get(&client, &server.url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(server.requests().len(), 1);
+
+    // Another process only sends a conditional request
+    let bytes = OnlineCache::new(dir.path()).get(&client, &server.url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("first"));
+    assert_eq!(server.requests(), vec![None, Some(String::from("\"v1\""))]);
+
+    // The file has changed
+    *server.content.lock().unwrap() = (String::from("\"v2\""), String::from("second"));
+    let bytes = OnlineCache::new(dir.path()).get(&client, &server.url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("second"));
+    assert_eq!(server.requests()[2], Some(String::from("\"v1\"")));
+    assert_eq!(std::fs::read_to_string(dir.path().join("13.0").join("emoji-test.txt")).unwrap(), "second");
+}
+
+#[cfg(feature = "fetch")]
+#[test]
+fn test_offline_with_cached_copy() {
+    let client = test_client();
+    // Nothing is listening on this port anymore
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/emoji-test.txt", listener.local_addr().unwrap())
+    };
+    let dir = tempfile::tempdir().unwrap();
+    create_dir_all(dir.path().join("13.0")).unwrap();
+    std::fs::write(dir.path().join("13.0").join("emoji-test.txt"), "cached").unwrap();
+
+    let bytes = OnlineCache::new(dir.path()).get(&client, &url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("cached"));
+    assert!(OnlineCache::memory_only().get(&client, &url, "13.0", "emoji-test.txt").is_err());
+}
+
+#[cfg(all(unix, feature = "fetch"))]
+#[test]
+fn test_read_only_cache_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // root can write anyway
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+    let server = FixtureServer::start("\"v1\"", "first");
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    let cache = OnlineCache::new(dir.path().join("cache"));
+    assert_eq!(cache.dir(), None);
+    let bytes = cache.get(&test_client(), &server.url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("first"));
+
+    std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A fetcher that serves fixed responses and records the URLs and `ETag`s of the requests
+#[cfg(test)]
+pub(crate) struct MockFetcher {
+    responses: HashMap<String, Result<(&'static str, &'static str), u16>>,
+    requests: std::sync::Mutex<Vec<(String, Option<String>)>>,
+}
+
+#[cfg(test)]
+impl MockFetcher {
+    pub(crate) fn new() -> MockFetcher {
+        MockFetcher {
+            responses: HashMap::new(),
+            requests: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Serves the body with the given `ETag`
+    pub(crate) fn serve(mut self, url: &str, etag: &'static str, body: &'static str) -> MockFetcher {
+        self.responses.insert(url.to_string(), Ok((etag, body)));
+        self
+    }
+
+    /// Answers with an error status; 0 simulates a timeout
+    pub(crate) fn fail(mut self, url: &str, status: u16) -> MockFetcher {
+        self.responses.insert(url.to_string(), Err(status));
+        self
+    }
+
+    pub(crate) fn requests(&self) -> Vec<(String, Option<String>)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl HttpFetcher for MockFetcher {
+    fn fetch(&self, url: &str) -> Result<Bytes, FetchError> {
+        self.fetch_conditional(url, None)
+            .map(|response| response.map(|(bytes, _)| bytes).unwrap_or_default())
+    }
+
+    fn fetch_conditional(&self, url: &str, meta: Option<&CacheMeta>) -> Result<Option<(Bytes, CacheMeta)>, FetchError> {
+        let etag = meta.and_then(|meta| meta.etag.clone());
+        self.requests.lock().unwrap().push((url.to_string(), etag.clone()));
+        match self.responses.get(url) {
+            Some(Ok((current, _))) if etag.as_deref() == Some(*current) => Ok(None),
+            Some(Ok((current, body))) => Ok(Some((Bytes::from(*body), CacheMeta {
+                etag: Some(current.to_string()),
+                last_modified: None,
+            }))),
+            Some(Err(0)) => Err(FetchError::Timeout),
+            Some(Err(status)) => Err(FetchError::Status(*status)),
+            None => Err(FetchError::Status(404))
+        }
+    }
+}
+
+#[test]
+fn test_mock_fetcher() {
+    let url = "https://unicode.org/Public/emoji/13.0/emoji-test.txt";
+    let dir = tempfile::tempdir().unwrap();
+    let fetcher = MockFetcher::new().serve(url, "\"v1\"", "first");
+
+    let cache = OnlineCache::new(dir.path());
+    assert_eq!(cache.get(&fetcher, url, "13.0", "emoji-test.txt").unwrap(), Bytes::from("first"));
+    // The second time, it comes from memory
+    assert_eq!(cache.get(&fetcher, url, "13.0", "emoji-test.txt").unwrap(), Bytes::from("first"));
+    assert_eq!(fetcher.requests(), vec![(url.to_string(), None)]);
+
+    // Another process only sends a conditional request and gets the cached copy
+    let bytes = OnlineCache::new(dir.path()).get(&fetcher, url, "13.0", "emoji-test.txt").unwrap();
+    assert_eq!(bytes, Bytes::from("first"));
+    assert_eq!(fetcher.requests()[1], (url.to_string(), Some(String::from("\"v1\""))));
+
+    // The cached copy is used if the server fails or times out
+    for status in &[500, 0] {
+        let failing = MockFetcher::new().fail(url, *status);
+        let bytes = OnlineCache::new(dir.path()).get(&failing, url, "13.0", "emoji-test.txt").unwrap();
+        assert_eq!(bytes, Bytes::from("first"));
+    }
+}
+
+#[test]
+fn test_mock_fetcher_errors() {
+    let url = "https://unicode.org/Public/emoji/13.0/emoji-test.txt";
+    let dir = tempfile::tempdir().unwrap();
+
+    // Without a cached copy, the errors are returned
+    match OnlineCache::new(dir.path()).get(&MockFetcher::new(), url, "13.0", "emoji-test.txt") {
+        Err(ExpansionError::Fetch(FetchError::Status(404))) => (),
+        other => panic!("Expected a 404 error, got {:?}", other)
+    }
+    match OnlineCache::memory_only().get(&MockFetcher::new().fail(url, 0), url, "13.0", "emoji-test.txt") {
+        Err(ExpansionError::Fetch(FetchError::Timeout)) => (),
+        other => panic!("Expected a timeout, got {:?}", other)
+    }
+    // Failed downloads aren't stored
+    assert!(!dir.path().join("13.0").join("emoji-test.txt").exists());
+}
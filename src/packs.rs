@@ -0,0 +1,146 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Builds several packs (e.g. the base emojis, the flags and seasonal extras) into one font.
+//!
+//! The packs are layered in the order in which they are given: an image in a later pack replaces
+//! the images of the same emoji in the earlier packs (see [layer_emojis]).
+//! Everything that's logged or reported for a pack carries its name, so it's clear which pack a
+//! problem (or a conflict) comes from.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::configs::{ConfigError, Pack};
+use crate::diagnostics::{Category, Diagnostic, DiagnosticsSink};
+use crate::emoji::{Emoji, ScannedDir};
+use crate::emoji_tables::{EmojiTable, EmojiTableKey};
+
+/// The images of one of the layered packs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackLayer {
+    /// The name of the pack, i.e. the stem of its file name (e.g. `flags` for `flags.yaml`)
+    pub name: String,
+    /// The configuration file of the pack
+    pub path: PathBuf,
+    pub images: Option<PathBuf>,
+    pub flags: Option<PathBuf>,
+    /// The variant of the images that's used (see [Emoji::select_variant]),
+    /// if the pack doesn't use the one of the build
+    pub variant: Option<String>,
+}
+
+impl PackLayer {
+    /// Takes the image directories from the pack's settings
+    /// # Errors
+    /// If one of them is outside of the pack (see [Settings::get_path](crate::configs::Settings::get_path))
+    pub fn new<P: AsRef<Path>>(path: P, pack: &Pack) -> Result<PackLayer, ConfigError> {
+        let path = path.as_ref();
+        Ok(PackLayer {
+            name: path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+            path: path.to_path_buf(),
+            images: pack.settings.get_path("images")?,
+            flags: pack.settings.get_path("flags")?,
+            variant: pack.settings.get("variant").map(String::from),
+        })
+    }
+
+    /// Reads the image directories of the pack (see [Emoji::scan_dir]).
+    /// The table may be `None` if it's still loading.
+    /// The ignored files are skipped if `ignore` is set (see [Emoji::scan_dir_with_ignore]).
+    /// # Errors
+    /// The directory that couldn't be read together with the error
+    pub fn scan(&self, table: Option<&EmojiTable>, ignore: bool) -> Result<Vec<ScannedDir>, (PathBuf, std::io::Error)> {
+        let mut scanned = vec![];
+        if let Some(images) = &self.images {
+            scanned.push(Emoji::scan_dir_with_ignore(images, table, false, ignore).map_err(|err| (images.clone(), err))?);
+        }
+        if let Some(flags) = &self.flags {
+            scanned.push(Emoji::scan_dir_with_ignore(flags, table, true, ignore).map_err(|err| (flags.clone(), err))?);
+        }
+        Ok(scanned)
+    }
+
+    /// Completes the scanned directories of the pack (see [ScannedDir::complete]) and chooses the
+    /// files of its variant (or of `variant` if it doesn't have one, see [Emoji::select_variant]).
+    /// The problems are reported with the name of the pack.
+    pub fn complete(&self, scanned: Vec<ScannedDir>, table: Option<&EmojiTable>, variant: Option<&str>, diagnostics: &DiagnosticsSink) -> Vec<Emoji> {
+        let pack_diagnostics = DiagnosticsSink::new();
+        let emojis: Vec<Emoji> = scanned.into_iter()
+            .flat_map(|scanned| scanned.complete(table, &pack_diagnostics))
+            .collect();
+        let emojis = Emoji::select_variant(emojis, self.variant.as_deref().or(variant), &pack_diagnostics);
+        for diagnostic in pack_diagnostics.diagnostics() {
+            diagnostics.report(diagnostic.with_pack(&self.name));
+        }
+        info!("[{}] {} emoji image(s)", self.name, emojis.len());
+        emojis
+    }
+
+    /// Whether the file is in one of the pack's image directories
+    pub fn contains(&self, path: &Path) -> bool {
+        self.images.iter().chain(self.flags.iter())
+            .any(|dir| path.starts_with(dir))
+    }
+}
+
+/// Layers the emojis of the packs (in the order of the packs): if multiple packs have images
+/// for the same emoji (regardless of `U+FE0F`), only the ones from the last of them are kept.
+///
+/// Every replaced image is logged and reported as a warning of the pack that replaces it.
+/// Multiple images of the same emoji within one pack are kept (see [Emoji::deduplicate_sources]).
+pub fn layer_emojis(packs: Vec<(&PackLayer, Vec<Emoji>)>, diagnostics: &DiagnosticsSink) -> Vec<Emoji> {
+    let key = |emoji: &Emoji| -> EmojiTableKey {
+        emoji.sequence.iter().copied().filter(|codepoint| *codepoint != 0xfe0f).collect()
+    };
+    // The last pack that has an image for each emoji
+    let mut winners: HashMap<EmojiTableKey, usize> = HashMap::new();
+    for (index, (_, emojis)) in packs.iter().enumerate() {
+        for emoji in emojis {
+            winners.insert(key(emoji), index);
+        }
+    }
+
+    let mut layered = vec![];
+    for (index, (pack, emojis)) in packs.iter().enumerate() {
+        for emoji in emojis {
+            let winner = winners[&key(emoji)];
+            if winner == index {
+                layered.push(emoji.clone());
+            } else {
+                let winner = packs[winner].0;
+                let message = format!("{} from {} replaces the image from {}", emoji, winner.name, pack.name);
+                info!("[{}] {}", winner.name, message);
+                diagnostics.report(Diagnostic::warning(Category::Emoji, message)
+                    .with_emoji(emoji)
+                    .with_pack(&winner.name));
+            }
+        }
+    }
+    layered
+}
+
+/// Sets the pack of the reported problems whose file is in one of the packs
+/// (unless they already have one), e.g. for problems that the builder has reported
+pub fn tag_diagnostics(packs: &[PackLayer], diagnostics: &DiagnosticsSink) {
+    diagnostics.update(|diagnostic| if diagnostic.pack.is_none() {
+        let pack = diagnostic.path.as_ref()
+            .and_then(|path| packs.iter().rev().find(|pack| pack.contains(path)));
+        if let Some(pack) = pack {
+            diagnostic.pack = Some(pack.name.clone());
+        }
+    });
+}
+
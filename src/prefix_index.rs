@@ -0,0 +1,142 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Finds the sequences that start with some codepoints (e.g. to complete a ZWJ sequence in a
+//! picker) without looking at every sequence of a table.
+//!
+//! The sequences are kept sorted, so all sequences with the same prefix are next to each other
+//! and can be found with a binary search.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::emoji_tables::EmojiTableKey;
+
+/// The sorted sequences of a table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefixIndex(Vec<EmojiTableKey>);
+
+impl PrefixIndex {
+    /// Sorts the sequences
+    pub fn build<'a, I: IntoIterator<Item = &'a EmojiTableKey>>(sequences: I) -> PrefixIndex {
+        let mut sequences: Vec<EmojiTableKey> = sequences.into_iter().cloned().collect();
+        sequences.sort_unstable();
+        sequences.dedup();
+        PrefixIndex(sequences)
+    }
+
+    /// The positions of the sequences that start with `prefix` (including `prefix` itself)
+    pub fn range(&self, prefix: &[u32]) -> Range<usize> {
+        // The first sequence that isn't smaller than the prefix
+        let start = match self.0.binary_search_by(|sequence| sequence.as_slice().cmp(prefix)) {
+            Ok(start) | Err(start) => start
+        };
+        // All sequences with the prefix come before the ones without it
+        let length = match self.0[start..].binary_search_by(|sequence| if sequence.starts_with(prefix) {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }) {
+            Ok(length) | Err(length) => length
+        };
+        start..start + length
+    }
+
+    /// The sequences that start with `prefix` (including `prefix` itself) in ascending order
+    /// # Examples
+    /// ```
+    /// use emoji_builder::prefix_index::PrefixIndex;
+    ///
+    /// let sequences = vec![vec![0x1f468], vec![0x1f468, 0x200d, 0x1f4bb], vec![0x1f469]];
+    /// let index = PrefixIndex::build(&sequences);
+    ///
+    /// assert_eq!(index.with_prefix(&[0x1f468]).len(), 2);
+    /// assert_eq!(index.with_prefix(&[]).len(), 3);
+    /// ```
+    pub fn with_prefix(&self, prefix: &[u32]) -> &[EmojiTableKey] {
+        &self.0[self.range(prefix)]
+    }
+
+    /// All sequences in ascending order
+    pub fn sequences(&self) -> &[EmojiTableKey] {
+        &self.0
+    }
+
+    /// The number of sequences
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there aren't any sequences
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The [PrefixIndex] of a table, which is only built when it's needed and has to be built again
+/// after emojis have been added (like the [ShortIdCache](crate::short_ids::ShortIdCache)).
+/// It's ignored when comparing tables.
+#[derive(Debug, Default)]
+pub(crate) struct PrefixIndexCache(Mutex<Option<Arc<PrefixIndex>>>);
+
+impl PrefixIndexCache {
+    /// Returns the index, building it with `build` if necessary
+    pub(crate) fn get_or_build<F: FnOnce() -> PrefixIndex>(&self, build: F) -> Arc<PrefixIndex> {
+        let mut cached = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        cached.get_or_insert_with(|| Arc::new(build())).clone()
+    }
+
+    /// Removes the index, e.g. because an emoji has been added
+    pub(crate) fn invalidate(&mut self) {
+        *self.0.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    }
+}
+
+impl PartialEq for PrefixIndexCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for PrefixIndexCache {}
+
+#[test]
+fn test_ranges() {
+    let sequences: Vec<EmojiTableKey> = vec![
+        vec![0x1f468],
+        vec![0x1f468, 0x200d, 0x1f4bb],
+        vec![0x1f468, 0x200d, 0x1f469, 0x200d, 0x1f466],
+        vec![0x1f468, 0x1f3fb],
+        vec![0x1f469],
+        vec![0x1f469, 0x200d, 0x1f4bb],
+    ];
+    let index = PrefixIndex::build(sequences.iter().rev());
+    assert_eq!(index.len(), 6);
+
+    // The empty prefix matches everything
+    assert_eq!(index.range(&[]), 0..6);
+    // An exact match is included
+    assert_eq!(index.with_prefix(&[0x1f468]).len(), 4);
+    assert_eq!(index.with_prefix(&[0x1f468, 0x200d]), &[
+        vec![0x1f468, 0x200d, 0x1f469, 0x200d, 0x1f466],
+        vec![0x1f468, 0x200d, 0x1f4bb],
+    ]);
+    assert_eq!(index.with_prefix(&[0x1f469, 0x200d, 0x1f4bb]), &[vec![0x1f469, 0x200d, 0x1f4bb]]);
+    // Longer than any sequence
+    assert!(index.with_prefix(&[0x1f468, 0x200d, 0x1f469, 0x200d, 0x1f466, 0x200d]).is_empty());
+    assert!(index.with_prefix(&[0x1f46a]).is_empty());
+    assert!(index.with_prefix(&[0x1f000]).is_empty());
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Converts emoji images in other formats (e.g. PDF or EPS) to SVG before they are rendered.
+//! Keeps a builder and the emoji table in memory and builds on request.
+//!
+//! Requests and events are JSON objects, one per line.
+//! The requests are
+//! - `{"action": "build", "output": "font.ttf"}` to build the whole emoji set,
+//! - `{"action": "render", "sequence": "1f914"}` to only prepare a single emoji and
+//! - `{"action": "validate"}` to compare the emojis with the emoji table.
+//!
+//! Each request is answered by any number of `progress` events followed by a `done` or an
+//! `error` event, e.g. `{"event": "done", "message": "Built 3000 emojis"}`.
+//! Connections are handled one after another, so there's never more than one build at a time
+//! and other clients wait until it's their turn.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::builder::{EmojiBuilder, SupportReport};
+use crate::configs::SettingSource;
+use crate::emoji::Emoji;
+use crate::emoji_tables::{EmojiTable, ValidationOptions};
+
+/// Reads the emojis of the set (this is done for each request, so new images are picked up)
+pub type EmojiLoader<'a> = Box<dyn Fn(Option<&EmojiTable>) -> Result<Vec<Emoji>, String> + 'a>;
+
+/// A request to the server
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Request {
+    /// Builds the emoji set to the output file
+    Build {
+        output: PathBuf
+    },
+    /// Prepares a single emoji, given by its sequence (like in the file names)
+    Render {
+        sequence: String
+    },
+    /// Validates the emojis against the emoji table
+    Validate,
+}
+
+/// What the server sends back while handling a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    /// The request is still being handled
+    Progress {
+        message: String
+    },
+    /// The request has been handled successfully
+    Done {
+        message: String
+    },
+    /// The request couldn't be handled
+    Error {
+        message: String
+    },
+}
+
+/// A builder that is kept in memory between the requests
+pub struct Server<'a, B: EmojiBuilder> {
+    builder: Box<B>,
+    table: Option<EmojiTable>,
+    load_emojis: EmojiLoader<'a>,
+    /// How the emojis are validated against the table
+    validation: ValidationOptions,
+}
+
+impl<'a, B: EmojiBuilder> Server<'a, B> {
+    /// Creates a server for an already initialized builder (which is given the table, if there is
+    /// one)
+    pub fn new(mut builder: Box<B>, table: Option<EmojiTable>, load_emojis: EmojiLoader<'a>) -> Server<'a, B> {
+        if let Some(table) = &table {
+            builder.set_table(table);
+        }
+        // Each build request names its output file
+        builder.set_output_source(&SettingSource::Cli);
+        Server {
+            builder,
+            table,
+            load_emojis,
+            validation: ValidationOptions { ignore_fe0f: true, max_version: None },
+        }
+    }
+
+    /// Only expects the emojis up to this Emoji version when validating (see `--max-emoji-version`)
+    pub fn with_max_version(mut self, max_version: Option<(u32, u32)>) -> Server<'a, B> {
+        self.validation.max_version = max_version;
+        self
+    }
+
+    /// Handles the connections one after another (e.g. from `UnixListener::incoming`).
+    /// Errors of a single connection are only logged.
+    pub fn serve<S, I>(&mut self, incoming: I)
+        where I: IntoIterator<Item=std::io::Result<S>>,
+              for<'s> &'s S: Read + Write {
+        for stream in incoming {
+            let result = stream.and_then(|stream|
+                self.serve_connection(BufReader::new(&stream), &stream)
+            );
+            if let Err(err) = result {
+                warn!("Connection failed: {}", err);
+            }
+        }
+    }
+
+    /// Handles the requests of a single connection until it's closed
+    pub fn serve_connection<R: BufRead, W: Write>(&mut self, reader: R, mut writer: W) -> std::io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Request>(&line) {
+                Ok(request) => {
+                    info!("Handling {:?}", request);
+                    let mut send = |event: Event| send_event(&mut writer, &event);
+                    let result = self.handle(&request, &mut send)?;
+                    send(result)?;
+                }
+                Err(err) => send_event(&mut writer, &Event::Error {
+                    message: format!("Invalid request: {}", err)
+                })?
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a single request and returns the final (`done` or `error`) event.
+    /// `progress` is called for the intermediate events.
+    pub fn handle<F>(&mut self, request: &Request, progress: &mut F) -> std::io::Result<Event>
+        where F: FnMut(Event) -> std::io::Result<()> {
+        let emojis = match (self.load_emojis)(self.table.as_ref()) {
+            Ok(emojis) => emojis,
+            Err(message) => return Ok(Event::Error { message })
+        };
+        match request {
+            Request::Build { output } => self.build(&emojis, output.clone(), progress),
+            Request::Render { sequence } => Ok(self.render(&emojis, sequence)),
+            Request::Validate => self.validate(&emojis, progress),
+        }
+    }
+
+    fn build<F>(&mut self, emojis: &[Emoji], output: PathBuf, progress: &mut F) -> std::io::Result<Event>
+        where F: FnMut(Event) -> std::io::Result<()> {
+        let emojis: Vec<&Emoji> = emojis.iter().collect();
+        let (emojis, support) = SupportReport::check(self.builder.as_ref(), &emojis);
+        if !support.is_empty() {
+            progress(Event::Progress { message: support.to_string().trim_end().to_string() })?;
+        }
+        progress(Event::Progress { message: format!("Preparing {} emojis", emojis.len()) })?;
+        let prepared: HashMap<&Emoji, _> = self.builder.prepare_batch(&emojis)
+            .into_iter()
+            .map(|(emoji, prepared)| (emoji, prepared.map(|prepared| prepared.0)))
+            .collect();
+        let failed = prepared.values().filter(|prepared| prepared.is_err()).count();
+        if failed > 0 {
+            progress(Event::Progress { message: format!("{} emojis couldn't be prepared", failed) })?;
+        }
+        progress(Event::Progress { message: format!("Building {:?}", output) })?;
+        Ok(match self.builder.build(prepared, output.clone()) {
+            Ok(()) => Event::Done { message: format!("Built {:?} with {} emojis", output, emojis.len() - failed) },
+            Err(err) => Event::Error { message: format!("An error occured while building the emoji set: {:?}", err) }
+        })
+    }
+
+    fn render(&self, emojis: &[Emoji], sequence: &str) -> Event {
+        let requested = match Emoji::from_sequence(sequence, self.table.as_ref()) {
+            Ok(emoji) => emoji,
+            Err(err) => return Event::Error { message: format!("Invalid sequence {}: {:?}", sequence, err) }
+        };
+        let without_fe0f = |sequence: &[u32]| sequence.iter()
+            .filter(|codepoint| **codepoint != 0xfe0f)
+            .copied()
+            .collect::<Vec<u32>>();
+        let emoji = emojis.iter()
+            .find(|emoji| without_fe0f(&emoji.sequence) == without_fe0f(&requested.sequence));
+        match emoji {
+            Some(emoji) => match self.builder.prepare(emoji) {
+                Ok(_) => Event::Done { message: format!("Rendered {}", emoji) },
+                Err(err) => Event::Error { message: format!("Couldn't render {}: {:?}", emoji, err) }
+            },
+            None => Event::Error { message: format!("There's no image for {} (Codepoint: {:X?})", requested, requested.sequence) }
+        }
+    }
+
+    fn validate<F>(&self, emojis: &[Emoji], progress: &mut F) -> std::io::Result<Event>
+        where F: FnMut(Event) -> std::io::Result<()> {
+        let table = match &self.table {
+            Some(table) => table,
+            None => return Ok(Event::Error { message: String::from("There's no emoji table to validate against") })
+        };
+        let sequences = emojis.iter().map(|emoji| &emoji.sequence);
+        let result = table.validate_with(sequences, self.validation);
+        for missing in &result.missing {
+            progress(Event::Progress { message: format!("Missing emoji: {} (Codepoint: {:X?})", missing, missing.sequence) })?;
+        }
+        Ok(Event::Done { message: result.summary() })
+    }
+}
+
+/// Writes an event as a single line and flushes it, so the client sees it immediately
+fn send_event<W: Write>(writer: &mut W, event: &Event) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, event)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
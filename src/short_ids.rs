@@ -0,0 +1,199 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Short, URL-safe identifiers for emojis (e.g. for file names and anchors), as the sequences
+//! themselves can get quite long for ZWJ sequences.
+//!
+//! An ID is the beginning of the (hex) SHA256 hash of the sequence without `U+FE0F`, so it's the
+//! same for all spellings of an emoji and doesn't change between builds.
+//! Within an [EmojiTable](crate::emoji_tables::EmojiTable), the IDs are made longer if two
+//! emojis would get the same one (see [ShortIds]).
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::emoji_tables::EmojiTableKey;
+
+/// The length of a short ID (unless that's not unique within a table)
+pub const SHORT_ID_LENGTH: usize = 8;
+
+/// The full (hex) hash of a sequence; all short IDs are prefixes of it
+fn full_id(sequence: &[u32]) -> String {
+    let normalized = sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .map(|codepoint| format!("{:x}", codepoint))
+        .join(" ");
+    let mut hasher = Sha256::new();
+    hasher.input(normalized.as_bytes());
+    hasher.result().iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The short ID of a sequence with the given length (at most 64)
+/// # Examples
+/// ```
+/// use emoji_builder::short_ids::short_id;
+///
+/// let with_fe0f = short_id(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308], 8);
+/// assert_eq!(with_fe0f.len(), 8);
+/// assert_eq!(with_fe0f, short_id(&[0x1f3f3, 0x200d, 0x1f308], 8));
+/// ```
+pub fn short_id(sequence: &[u32], length: usize) -> String {
+    let mut id = full_id(sequence);
+    id.truncate(length);
+    id
+}
+
+/// The short IDs of all emojis of a table.
+///
+/// They all have the same length, which is the shortest one (starting at [SHORT_ID_LENGTH])
+/// at which there are no collisions.
+/// Sequences that only differ in `U+FE0F` share an ID, which resolves to the longest one
+/// (i.e. usually the fully-qualified sequence).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortIds {
+    length: usize,
+    sequences: HashMap<String, EmojiTableKey>,
+}
+
+impl ShortIds {
+    /// Finds the IDs of the sequences
+    pub fn build<'a, I: IntoIterator<Item = &'a EmojiTableKey>>(sequences: I) -> ShortIds {
+        ShortIds::build_with_length(sequences, SHORT_ID_LENGTH)
+    }
+
+    /// Finds the IDs of the sequences, starting with IDs of the given length
+    pub(crate) fn build_with_length<'a, I: IntoIterator<Item = &'a EmojiTableKey>>(sequences: I, length: usize) -> ShortIds {
+        // The longest spelling of each emoji and its full ID
+        let mut full_ids: HashMap<String, &EmojiTableKey> = HashMap::new();
+        for sequence in sequences {
+            let existing = full_ids.entry(full_id(sequence)).or_insert(sequence);
+            if sequence.len() > existing.len() || (sequence.len() == existing.len() && sequence < *existing) {
+                *existing = sequence;
+            }
+        }
+
+        let mut length = length.clamp(1, 64);
+        loop {
+            let prefixes: HashSet<&str> = full_ids.keys()
+                .map(|id| &id[..length])
+                .collect();
+            let unique = prefixes.len() == full_ids.len();
+            if unique || length >= 64 {
+                break;
+            }
+            length += 1;
+        }
+        if length != SHORT_ID_LENGTH {
+            debug!("Using short IDs with {} digits to avoid collisions", length);
+        }
+
+        let sequences = full_ids.into_iter()
+            .map(|(id, sequence)| (id[..length].to_string(), sequence.clone()))
+            .collect();
+        ShortIds {
+            length,
+            sequences,
+        }
+    }
+
+    /// The length of the IDs
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The ID of a sequence (which doesn't need to be in the table)
+    pub fn id(&self, sequence: &[u32]) -> String {
+        short_id(sequence, self.length)
+    }
+
+    /// The sequence with this ID
+    pub fn resolve(&self, id: &str) -> Option<&EmojiTableKey> {
+        self.sequences.get(&id.to_lowercase())
+    }
+
+    /// The number of IDs
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Whether there aren't any IDs
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+}
+
+/// The [ShortIds] of a table, which are only built when they're needed and have to be built
+/// again after emojis have been added.
+///
+/// Like the [DiagnosticsSink](crate::diagnostics::DiagnosticsSink), it's not part of the
+/// table's data, so it's ignored when comparing tables.
+#[derive(Debug, Default)]
+pub(crate) struct ShortIdCache(Mutex<Option<Arc<ShortIds>>>);
+
+impl ShortIdCache {
+    /// Returns the IDs, building them with `build` if necessary
+    pub(crate) fn get_or_build<F: FnOnce() -> ShortIds>(&self, build: F) -> Arc<ShortIds> {
+        let mut cached = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        cached.get_or_insert_with(|| Arc::new(build())).clone()
+    }
+
+    /// Removes the IDs, e.g. because an emoji has been added
+    pub(crate) fn invalidate(&mut self) {
+        *self.0.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    }
+}
+
+impl PartialEq for ShortIdCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ShortIdCache {}
+
+#[test]
+fn test_collisions() {
+    let sequences: Vec<EmojiTableKey> = (0x1f600..0x1f650).map(|codepoint| vec![codepoint]).collect();
+    // With a single digit, 80 emojis can't get different IDs
+    let ids = ShortIds::build_with_length(&sequences, 1);
+    assert!(ids.length() > 1);
+    assert_eq!(ids.len(), sequences.len());
+    for sequence in &sequences {
+        assert_eq!(ids.resolve(&ids.id(sequence)), Some(sequence));
+    }
+    // The length is the shortest one without collisions
+    let shorter = ids.length() - 1;
+    let prefixes: HashSet<String> = sequences.iter().map(|sequence| short_id(sequence, shorter)).collect();
+    assert!(prefixes.len() < sequences.len());
+
+    let ids = ShortIds::build(&sequences);
+    assert_eq!(ids.length(), SHORT_ID_LENGTH);
+    assert_eq!(ids.resolve(&ids.id(&[0x1f914])), None);
+}
+
+#[test]
+fn test_fe0f_spellings() {
+    let sequences = vec![vec![0x2764], vec![0x2764, 0xfe0f], vec![0x1f3f3, 0x200d, 0x1f308], vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]];
+    let ids = ShortIds::build(&sequences);
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids.id(&[0x2764]), ids.id(&[0x2764, 0xfe0f]));
+    assert_eq!(ids.resolve(&ids.id(&[0x2764])), Some(&vec![0x2764, 0xfe0f]));
+    assert_eq!(ids.resolve(&ids.id(&[0x1f3f3, 0x200d, 0x1f308]).to_uppercase()), Some(&vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]));
+}
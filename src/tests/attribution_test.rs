@@ -0,0 +1,126 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+
+use crate::attribution::{Attribution, AttributionError, ATTRIBUTION_FILE, Credit, License};
+use crate::configs::Pack;
+use crate::emoji::Emoji;
+use crate::licensing::font_license;
+
+const PACKS: &str = "test_files/packs/attribution";
+
+fn load_emojis(name: &str) -> Vec<Emoji> {
+    Emoji::from_dir(Path::new(PACKS).join(name), None, false).unwrap()
+}
+
+fn load_pack(name: &str) -> Attribution {
+    let pack = Pack::from_file(Path::new(PACKS).join(format!("{}.yaml", name))).unwrap();
+    let emojis = Emoji::from_dir(pack.settings.get_path("images").unwrap().unwrap(), None, false).unwrap();
+    Attribution::for_pack(&emojis, Credit::from_settings(&pack.settings).as_ref()).unwrap()
+}
+
+#[test]
+fn test_sidecar() {
+    let credit = Credit::from_sidecar(Path::new(PACKS).join("a/emoji_uf0001.svg.license")).unwrap();
+    assert_eq!(credit.license, License::new("CC0-1.0", None));
+    assert_eq!(credit.copyright, vec![String::from("2020 Jane Doe")]);
+    assert_eq!(Credit::sidecar_path(Path::new("svg/emoji_u1f914.svg")), Path::new("svg/emoji_u1f914.svg.license"));
+
+    // The sidecar files are not parsed as emojis
+    assert_eq!(load_emojis("a").len(), 2);
+
+    let dir = tempfile::tempdir().unwrap();
+    let invalid = dir.path().join("emoji_u1f914.svg.license");
+    std::fs::write(&invalid, "SPDX-FileCopyrightText: 2021 Jane Doe\n").unwrap();
+    assert!(matches!(Credit::from_sidecar(&invalid), Err(AttributionError::InvalidSidecar(_, _))));
+}
+
+#[test]
+fn test_two_pack_merge() {
+    let mut attribution = load_pack("a");
+    attribution.merge(load_pack("b")).unwrap();
+
+    assert_eq!(attribution.get(&[0xf0000]).unwrap().license.id, "CC-BY-4.0");
+    assert_eq!(attribution.get(&[0xf0001]).unwrap().license.id, "CC0-1.0");
+    assert_eq!(attribution.get(&[0xf0002]).unwrap().license.id, "Apache-2.0");
+    assert_eq!(attribution.get(&[0xf0003]).unwrap().license.id, "Apache-2.0");
+
+    // The URL is the one of the license with the most emojis
+    let (description, url) = font_license(&attribution).unwrap();
+    assert_eq!(description, "The artwork is licensed under \
+        Apache-2.0 (https://www.apache.org/licenses/LICENSE-2.0.txt), \
+        CC-BY-4.0 (https://creativecommons.org/licenses/by/4.0/), \
+        CC0-1.0 (https://creativecommons.org/publicdomain/zero/1.0/). \
+        See ATTRIBUTION.txt for the authors of the emojis.");
+    assert_eq!(url.as_deref(), Some("https://www.apache.org/licenses/LICENSE-2.0.txt"));
+
+    // Only the emoji with CC-BY needs an attribution
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(ATTRIBUTION_FILE);
+    attribution.write(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Licenses\n\
+        ========\n\
+        Apache-2.0 (https://www.apache.org/licenses/LICENSE-2.0.txt)\n\
+        CC-BY-4.0 (https://creativecommons.org/licenses/by/4.0/)\n\
+        CC0-1.0 (https://creativecommons.org/publicdomain/zero/1.0/)\n\
+        \n\
+        Attributions\n\
+        ============\n\
+        \u{f0000} [F0000] (U+F0000): © 2021 Pack A Authors, CC-BY-4.0\n");
+
+    // Merging the same pack again doesn't change anything
+    let merged = attribution.clone();
+    attribution.merge(load_pack("a")).unwrap();
+    assert_eq!(attribution, merged);
+}
+
+#[test]
+fn test_conflicts() {
+    let mut attribution = load_pack("a");
+
+    // Another pack with the same emojis, but another license
+    let apache = Credit {
+        license: License::new("Apache-2.0", None),
+        copyright: vec![],
+        source: String::from("other pack"),
+    };
+    let other = Attribution::for_pack(&load_emojis("a"), Some(&apache)).unwrap();
+    match attribution.merge(other) {
+        Err(AttributionError::ConflictingLicenses { emoji, licenses, sources }) => {
+            assert_eq!(emoji, "\u{f0000} [F0000]");
+            assert_eq!(licenses, (String::from("CC-BY-4.0"), String::from("Apache-2.0")));
+            assert_eq!(sources.1, "other pack");
+        }
+        result => panic!("Expected conflicting licenses, got {:?}", result)
+    }
+
+    // The same license with another URL
+    let mut attribution = load_pack("b");
+    let apache = Credit {
+        license: License::new("Apache-2.0", Some("https://example.com/apache")),
+        copyright: vec![],
+        source: String::from("other pack"),
+    };
+    let other = Attribution::for_pack(&[Emoji::from(vec![0xf0010])], Some(&apache)).unwrap();
+    match attribution.merge(other) {
+        Err(AttributionError::ConflictingUrls { license, urls, .. }) => {
+            assert_eq!(license, "Apache-2.0");
+            assert_eq!(urls, (String::from("https://www.apache.org/licenses/LICENSE-2.0.txt"), String::from("https://example.com/apache")));
+        }
+        result => panic!("Expected conflicting URLs, got {:?}", result)
+    }
+}
@@ -15,3 +15,75 @@
  */
 
 
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::builder::{EmojiBuilder, PreparationResult, SupportLevel, SupportReport};
+use crate::emoji::Emoji;
+
+/// A builder that doesn't support flags and can only build emojis with a single codepoint
+/// without an animation
+struct SingleCodepointBuilder;
+
+impl EmojiBuilder for SingleCodepointBuilder {
+    type Err = ();
+    type PreparedEmoji = ();
+
+    fn new(_build_dir: PathBuf, _arguments: Option<ArgMatches>) -> Result<Box<Self>, Self::Err> {
+        Ok(Box::new(SingleCodepointBuilder))
+    }
+
+    fn supports_emoji(&self, emoji: &Emoji) -> SupportLevel {
+        if emoji.is_flag() {
+            SupportLevel::Unsupported(String::from("flag"))
+        } else if emoji.sequence.len() > 1 {
+            SupportLevel::Degraded(String::from("only the first codepoint"))
+        } else {
+            SupportLevel::Full
+        }
+    }
+
+    fn prepare(&self, _emoji: &Emoji) -> PreparationResult<Self::PreparedEmoji, Self::Err> {
+        Ok(((), None))
+    }
+
+    fn build(&mut self, _emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>, _output_file: PathBuf) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    fn sub_command<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("single")
+    }
+}
+
+#[test]
+fn test_support_report() {
+    let builder = SingleCodepointBuilder::new(PathBuf::new(), None).unwrap();
+    let thinking = Emoji::from(vec![0x1f914]);
+    let wave = Emoji::from(vec![0x1f44b, 0x1f3fb]);
+    let germany = Emoji::from_flag("DE", None).unwrap();
+    let eu = Emoji::from_flag("EU", None).unwrap();
+    let emojis = vec![&thinking, &germany, &wave, &eu];
+
+    let (supported, report) = SupportReport::check(builder.as_ref(), &emojis);
+    // Degraded emojis are still built, unsupported ones are skipped
+    assert_eq!(supported, vec![&thinking, &wave]);
+    assert_eq!(report.unsupported, vec![
+        (&germany, String::from("flag")),
+        (&eu, String::from("flag")),
+    ]);
+    assert_eq!(report.degraded, vec![(&wave, String::from("only the first codepoint"))]);
+
+    assert_eq!(report.to_string(), format!(
+        "Skipped 2 unsupported emoji(s):\n  flag: {}, {}\nBuilt 1 degraded emoji(s):\n  only the first codepoint: {}\n",
+        germany, eu, wave
+    ));
+
+    let (supported, report) = SupportReport::check(builder.as_ref(), &[&thinking]);
+    assert_eq!(supported, vec![&thinking]);
+    assert!(report.is_empty());
+    assert_eq!(report.to_string(), "");
+}
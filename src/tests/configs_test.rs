@@ -0,0 +1,345 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::PathBuf;
+
+use clap::{App, Arg};
+use sha2::{Digest, Sha256};
+
+use crate::configs::{ConfigError, Pack, Settings, SettingSource};
+
+fn app() -> App<'static, 'static> {
+    App::new("test")
+        .arg(Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .default_value("font.ttf"))
+        .arg(Arg::with_name("build")
+            .long("build")
+            .takes_value(true)
+            .default_value("./build"))
+        .arg(Arg::with_name("flags")
+            .long("flags")
+            .takes_value(true)
+            .multiple(true))
+        .arg(Arg::with_name("waveflag")
+            .long("waveflag"))
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+const CONFIG: &str = "output: config.ttf\nbuild: ./config_build\nflags:\n  - ./flags\n  - ./more_flags\nwaveflag: true\n";
+
+#[test]
+fn test_precedence() {
+    let matches = app().get_matches_from(vec!["test", "--output", "cli.ttf"]);
+    let mut settings = Settings::new();
+    settings.load_matches(&matches, &["output", "build", "flags", "waveflag"]);
+    settings.load_env(env(&[
+        ("EMOJI_BUILDER_OUTPUT", "env.ttf"),
+        ("EMOJI_BUILDER_BUILD", "./env_build"),
+    ]));
+    settings.load_yaml(CONFIG, SettingSource::ConfigFile(PathBuf::from("config.yaml"))).unwrap();
+
+    // CLI > environment > config file > default
+    assert_eq!(settings.get("output"), Some("cli.ttf"));
+    assert_eq!(settings.source("output"), Some(&SettingSource::Cli));
+    assert_eq!(settings.get("build"), Some("./env_build"));
+    assert_eq!(settings.source("build"), Some(&SettingSource::Environment(String::from("EMOJI_BUILDER_BUILD"))));
+    assert_eq!(settings.get_list("flags").unwrap(), &["./flags", "./more_flags"]);
+    assert_eq!(settings.source("flags"), Some(&SettingSource::ConfigFile(PathBuf::from("config.yaml"))));
+    assert_eq!(settings.get_bool("waveflag"), Ok(true));
+
+    // The order in which the sources are loaded doesn't matter
+    let mut reversed = Settings::new();
+    reversed.load_yaml(CONFIG, SettingSource::ConfigFile(PathBuf::from("config.yaml"))).unwrap();
+    reversed.load_env(env(&[
+        ("EMOJI_BUILDER_OUTPUT", "env.ttf"),
+        ("EMOJI_BUILDER_BUILD", "./env_build"),
+    ]));
+    reversed.load_matches(&matches, &["output", "build", "flags", "waveflag"]);
+    assert_eq!(reversed, settings);
+}
+
+#[test]
+fn test_defaults() {
+    let matches = app().get_matches_from(vec!["test"]);
+    let mut settings = Settings::new();
+    settings.load_matches(&matches, &["output", "build", "flags", "waveflag"]);
+
+    assert_eq!(settings.get("output"), Some("font.ttf"));
+    assert_eq!(settings.source("output"), Some(&SettingSource::Default));
+    assert_eq!(settings.get("flags"), None);
+    assert_eq!(settings.get_bool("waveflag"), Ok(false));
+
+    // Default values are overridden by everything else
+    settings.load_yaml(CONFIG, SettingSource::ConfigFile(PathBuf::from("config.yaml"))).unwrap();
+    assert_eq!(settings.get("output"), Some("config.ttf"));
+    assert_eq!(settings.get_path("build"), Ok(Some(PathBuf::from("./config_build"))));
+}
+
+#[test]
+fn test_cli_flags() {
+    let matches = app().get_matches_from(vec!["test", "--waveflag"]);
+    let mut settings = Settings::new();
+    settings.load_matches(&matches, &["output", "build", "flags", "waveflag"]);
+
+    assert_eq!(settings.get_bool("waveflag"), Ok(true));
+    assert_eq!(settings.source("waveflag"), Some(&SettingSource::Cli));
+}
+
+#[test]
+fn test_env_bools() {
+    let mut settings = Settings::new();
+    settings.load_env(env(&[
+        ("EMOJI_BUILDER_A", "1"),
+        ("EMOJI_BUILDER_B", "TRUE"),
+        ("EMOJI_BUILDER_C", "on"),
+        ("EMOJI_BUILDER_D", "0"),
+        ("EMOJI_BUILDER_E", "No"),
+        ("EMOJI_BUILDER_F", ""),
+        ("EMOJI_BUILDER_G", "2"),
+        // Not ours
+        ("WAVEFLAG", "true"),
+    ]));
+
+    assert_eq!(settings.get_bool("a"), Ok(true));
+    assert_eq!(settings.get_bool("b"), Ok(true));
+    assert_eq!(settings.get_bool("c"), Ok(true));
+    assert_eq!(settings.get_bool("d"), Ok(false));
+    assert_eq!(settings.get_bool("e"), Ok(false));
+    assert_eq!(settings.get_bool("f"), Ok(false));
+    assert_eq!(settings.get_bool("g"), Err(ConfigError::InvalidValue(String::from("g"), String::from("2"))));
+    assert_eq!(settings.get_bool("waveflag"), Ok(false));
+}
+
+#[test]
+fn test_env_paths_and_lists() {
+    let mut settings = Settings::new();
+    settings.load_env(env(&[
+        ("EMOJI_BUILDER_OUTPUT_DIR", "/tmp/fonts"),
+        ("EMOJI_BUILDER_FLAGS", "./flags, ./more_flags,"),
+    ]));
+
+    // Keys are case-insensitive and dashes are treated like underscores
+    assert_eq!(settings.get_path("output-dir"), Ok(Some(PathBuf::from("/tmp/fonts"))));
+    assert_eq!(settings.get_path("OUTPUT_DIR"), Ok(Some(PathBuf::from("/tmp/fonts"))));
+    assert_eq!(settings.get_list("flags").unwrap(), &["./flags", "./more_flags"]);
+}
+
+#[test]
+fn test_invalid_config() {
+    let mut settings = Settings::new();
+    let source = SettingSource::ConfigFile(PathBuf::from("config.yaml"));
+    assert!(settings.load_yaml("- not\n- a\n- mapping\n", source.clone()).is_err());
+    assert!(settings.load_yaml("nested:\n  key: value\n", source).is_err());
+    assert!(settings.load_file("test_files/does_not_exist.yaml").is_err());
+}
+
+#[test]
+fn test_dump() {
+    let mut settings = Settings::new();
+    settings.set("output", vec![String::from("font.ttf")], SettingSource::Cli);
+    settings.load_env(env(&[("EMOJI_BUILDER_FLAGS", "a,b")]));
+
+    assert_eq!(
+        settings.dump(),
+        "flags = a, b (from environment variable EMOJI_BUILDER_FLAGS)\noutput = font.ttf (from CLI)"
+    );
+}
+
+#[test]
+fn test_validate() {
+    let mut settings = Settings::new();
+    settings.load_yaml(
+        "emoji_tst: ./emoji-test.txt\nimages: test_files/svg\nflags: ./missing_flags\nbuild: ./not_checked\n",
+        SettingSource::ConfigFile(PathBuf::from("config.yaml"))
+    ).unwrap();
+    settings.load_env(env(&[("EMOJI_BUILDER_WAVEFLAGS", "true")]));
+    // The command line has already been checked by clap
+    settings.set("unknown", vec![String::from("value")], SettingSource::Cli);
+
+    let known = ["images", "flags", "build", "emoji_test", "waveflag"];
+    let issues = settings.validate(&known, &["images", "flags", "emoji_test"]);
+    let messages: Vec<String> = issues.iter().map(ToString::to_string).collect();
+    assert_eq!(messages, vec![
+        "emoji_tst (from config file config.yaml): unknown setting, did you mean `emoji_test`?",
+        "flags (from config file config.yaml): ./missing_flags does not exist",
+        "waveflags (from environment variable EMOJI_BUILDER_WAVEFLAGS): unknown setting, did you mean `waveflag`?",
+    ]);
+    assert_eq!(issues[1].suggestion, None);
+
+    // Keys that aren't similar to any known key don't get a suggestion
+    let mut settings = Settings::new();
+    settings.load_yaml("completely_different: 1", SettingSource::ConfigFile(PathBuf::from("config.yaml"))).unwrap();
+    let issues = settings.validate(&known, &[]);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].suggestion, None);
+
+    assert!(Settings::new().validate(&known, &[]).is_empty());
+}
+
+const PACK: &str = "output: pack.ttf\nbuilder:\n  name: test\n  options:\n    build: ./pack_build\n    waveflag: true\n";
+
+#[test]
+fn test_pack() {
+    let pack = Pack::from_yaml(PACK, &PathBuf::from("pack.yaml")).unwrap();
+    assert_eq!(pack.builder.as_deref(), Some("test"));
+    assert_eq!(pack.settings.get("output"), Some("pack.ttf"));
+    assert_eq!(pack.settings.get("builder"), None);
+    assert_eq!(pack.builder_options.source("waveflag"), Some(&SettingSource::PackConfig(PathBuf::from("pack.yaml"))));
+
+    // The options are parsed like arguments on the command line
+    let matches = pack.builder_matches(app(), None).unwrap();
+    assert_eq!(matches.value_of("build"), Some("./pack_build"));
+    assert_eq!(matches.value_of("output"), Some("font.ttf"));
+    assert!(matches.is_present("waveflag"));
+
+    // But actual arguments on the command line override them (their default values don't)
+    let overrides = app().get_matches_from(vec!["test", "--build", "./cli_build"]);
+    let matches = pack.builder_matches(app(), Some(&overrides)).unwrap();
+    assert_eq!(matches.value_of("build"), Some("./cli_build"));
+    assert!(matches.is_present("waveflag"));
+
+    let mut settings = Settings::new();
+    settings.set("output", vec![String::from("env.ttf")], SettingSource::Environment(String::from("EMOJI_BUILDER_OUTPUT")));
+    settings.merge(pack.settings);
+    assert_eq!(settings.get("output"), Some("env.ttf"));
+}
+
+#[test]
+fn test_invalid_pack() {
+    let pack = Pack::from_yaml("builder:\n  name: test\n  options:\n    waveflags: true\n", &PathBuf::from("pack.yaml")).unwrap();
+    match pack.builder_matches(app(), None) {
+        Err(ConfigError::Schema(issues)) => {
+            assert_eq!(issues.len(), 1);
+            assert_eq!(issues[0].suggestion.as_deref(), Some("waveflag"));
+        },
+        other => panic!("Expected an unknown option, got {:?}", other.map(|_| ()))
+    }
+
+    let pack = Pack::from_yaml("builder:\n  name: test\n  options:\n    waveflag: maybe\n", &PathBuf::from("pack.yaml")).unwrap();
+    assert_eq!(pack.builder_matches(app(), None).unwrap_err(),
+               ConfigError::InvalidValue(String::from("waveflag"), String::from("maybe")));
+
+    assert!(Pack::from_yaml("builder: [blobmoji]", &PathBuf::from("pack.yaml")).is_err());
+}
+
+#[test]
+fn test_pack_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_dir = dir.path().join("blobs");
+    std::fs::create_dir_all(pack_dir.join("svg")).unwrap();
+    std::fs::create_dir_all(pack_dir.join("flags")).unwrap();
+    std::fs::create_dir_all(dir.path().join("tables")).unwrap();
+    let path = pack_dir.join("pack.yaml");
+    let pack = Pack::from_yaml(&format!("images: ./svg\n\
+        flags: svg/../flags\n\
+        aliases: {}\n\
+        tables: ../tables\n\
+        emoji_test: /etc/emoji-test.txt\n\
+        keywords: [keywords/de.csv, svg/../../keywords.csv]\n", pack_dir.join("aliases.txt").display()), &path).unwrap();
+    // Relative paths are relative to the pack, not to the working directory
+    assert_eq!(pack.settings.get_path("images"), Ok(Some(pack_dir.join("svg"))));
+    assert_eq!(pack.settings.get_path("flags"), Ok(Some(pack_dir.join("svg/../flags"))));
+    assert_eq!(pack.settings.get_path("aliases"), Ok(Some(pack_dir.join("aliases.txt"))));
+    assert_eq!(pack.settings.get_path("tables"),
+               Err(ConfigError::PathOutsidePack(String::from("tables"), PathBuf::from("../tables"))));
+    assert_eq!(pack.settings.get_path("emoji_test"),
+               Err(ConfigError::PathOutsidePack(String::from("emoji_test"), PathBuf::from("/etc/emoji-test.txt"))));
+    assert_eq!(pack.settings.get_paths("keywords"),
+               Err(ConfigError::PathOutsidePack(String::from("keywords"), PathBuf::from("svg/../../keywords.csv"))));
+
+    // Symbolic links are followed
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(dir.path().join("tables"), pack_dir.join("tables")).unwrap();
+        std::os::unix::fs::symlink(pack_dir.join("svg"), pack_dir.join("images")).unwrap();
+        let pack = Pack::from_yaml("tables: tables/emoji-data.txt\nimages: images\n", &path).unwrap();
+        assert_eq!(pack.settings.get_path("tables"),
+                   Err(ConfigError::PathOutsidePack(String::from("tables"), PathBuf::from("tables/emoji-data.txt"))));
+        assert_eq!(pack.settings.get_path("images"), Ok(Some(pack_dir.join("images"))));
+    }
+
+    // Other configuration files may point anywhere
+    let mut settings = Settings::new();
+    settings.load_yaml("tables: ../tables", SettingSource::ConfigFile(path)).unwrap();
+    assert_eq!(settings.get_path("tables"), Ok(Some(PathBuf::from("../tables"))));
+}
+
+#[test]
+fn test_pack_checksum() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("pack.yaml");
+    std::fs::write(&path, PACK).unwrap();
+    let sha256 = hex::encode(Sha256::digest(PACK.as_bytes()));
+    let expected = Pack {
+        fingerprint: Some(sha256.clone()),
+        ..Pack::from_yaml(PACK, &path).unwrap()
+    };
+
+    // Without a checksum, there's nothing to check
+    assert_eq!(Pack::from_file_verified(&path, None).unwrap(), expected);
+    assert_eq!(Pack::from_file_verified(&path, Some(&sha256.to_uppercase())).unwrap(), expected);
+    assert_eq!(Pack::from_file_verified(&path, Some("1234")).unwrap_err(),
+               ConfigError::InvalidValue(String::from("pack_sha256"), String::from("1234")));
+
+    // The detached checksum is used unless the hash is pinned
+    let checksum_file = dir.path().join("pack.yaml.sha256");
+    std::fs::write(&checksum_file, format!("{}  pack.yaml\n", sha256)).unwrap();
+    assert_eq!(Pack::from_file_verified(&path, None).unwrap(), expected);
+
+    let tampered = PACK.replace("waveflag: true", "waveflag: false");
+    std::fs::write(&path, &tampered).unwrap();
+    let actual = hex::encode(Sha256::digest(tampered.as_bytes()));
+    assert_eq!(Pack::from_file_verified(&path, None).unwrap_err(),
+               ConfigError::Checksum(path.clone(), sha256.clone(), actual.clone()));
+    assert_eq!(Pack::from_file_verified(&path, Some(&sha256)).unwrap_err(),
+               ConfigError::Checksum(path.clone(), sha256, actual.clone()));
+    assert!(Pack::from_file_verified(&path, Some(&actual)).is_ok());
+}
+
+#[test]
+fn test_pack_cache_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("pack.yaml");
+    std::fs::write(&path, PACK).unwrap();
+    let sha256 = hex::encode(Sha256::digest(PACK.as_bytes()));
+    let pack = Pack::from_file(&path).unwrap();
+    assert_eq!(pack.fingerprint.as_deref(), Some(sha256.as_str()));
+
+    // Builders with a cache key get the fingerprint
+    let with_cache_key = || app().arg(Arg::with_name("cache_key").long("cache-key").takes_value(true));
+    let matches = pack.builder_matches(with_cache_key(), None).unwrap();
+    assert_eq!(matches.value_of("cache_key"), Some(&sha256[..16]));
+    // Unless the key is chosen explicitly
+    let overrides = with_cache_key().get_matches_from(vec!["test", "--cache-key", "main"]);
+    assert_eq!(pack.builder_matches(with_cache_key(), Some(&overrides)).unwrap().value_of("cache_key"), Some("main"));
+    // Other builders don't
+    assert!(pack.builder_matches(app(), None).is_ok());
+
+    // Layered packs have a fingerprint of their own
+    let other = Pack { fingerprint: Some(String::from("1234")), ..Pack::default() };
+    let layered = Pack::layered(&[pack.clone(), other.clone()]);
+    assert_ne!(layered.fingerprint, pack.fingerprint);
+    assert_ne!(layered.fingerprint, Pack::layered(&[other, pack.clone()]).fingerprint);
+    assert_eq!(Pack::layered(std::slice::from_ref(&pack)).fingerprint, pack.fingerprint);
+    assert_eq!(Pack::layered(&[Pack::default()]).fingerprint, None);
+}
@@ -0,0 +1,157 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::converters::{CommandConverter, ConversionError, Converter, Converters};
+use crate::emoji::Emoji;
+
+const SVG: &str = "test_files/svg/emoji_u1f93f.svg";
+
+/// Copies the file and counts how often it has been called (the description stands for the
+/// command line)
+struct CountingConverter(Arc<AtomicUsize>, &'static str);
+
+impl Converter for CountingConverter {
+    fn convert(&self, input: &Path, output: &Path) -> Result<(), ConversionError> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        std::fs::copy(input, output)?;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        String::from(self.1)
+    }
+}
+
+#[test]
+fn test_convert_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("emoji_u1f93f.pdf");
+    std::fs::copy(SVG, &source).unwrap();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut converters = Converters::new(dir.path().join("converted"));
+    converters.add(".PDF", Box::new(CountingConverter(count.clone(), "copy")));
+    assert!(converters.converts(&source));
+    assert!(!converters.converts(Path::new(SVG)));
+
+    let mut emoji = Emoji::from_path(source.clone(), None, false).unwrap();
+    converters.convert(&mut emoji).unwrap();
+    let output = emoji.svg_path.clone().unwrap();
+    assert_eq!(output.parent(), Some(dir.path().join("converted").as_path()));
+    assert_eq!(std::fs::read(&output).unwrap(), std::fs::read(SVG).unwrap());
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // The source hasn't changed
+    let mut emoji = Emoji::from_path(source.clone(), None, false).unwrap();
+    converters.convert(&mut emoji).unwrap();
+    assert_eq!(emoji.svg_path, Some(output.clone()));
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    std::fs::write(&source, b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap();
+    let mut emoji = Emoji::from_path(source, None, false).unwrap();
+    converters.convert(&mut emoji).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(std::fs::read(&output).unwrap(), b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>");
+
+    // SVG files are used as they are
+    let mut emoji = Emoji::from_path(SVG.into(), None, false).unwrap();
+    converters.convert(&mut emoji).unwrap();
+    assert_eq!(emoji.svg_path, Some(SVG.into()));
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_convert_same_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let sources = [
+        dir.path().join("a").join("emoji_u1f93f.pdf"),
+        dir.path().join("b").join("emoji_u1f93f.pdf"),
+        dir.path().join("a").join("emoji_u1f93f.eps"),
+    ];
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut converters = Converters::new(dir.path().join("converted"));
+    converters.add("pdf", Box::new(CountingConverter(count.clone(), "copy")));
+    converters.add("eps", Box::new(CountingConverter(count.clone(), "copy")));
+
+    let mut outputs = vec![];
+    for (index, source) in sources.iter().enumerate() {
+        std::fs::create_dir_all(source.parent().unwrap()).unwrap();
+        std::fs::write(source, format!("<svg xmlns=\"http://www.w3.org/2000/svg\" id=\"{}\"/>", index)).unwrap();
+        let mut emoji = Emoji::from_path(source.clone(), None, false).unwrap();
+        converters.convert(&mut emoji).unwrap();
+        outputs.push(emoji.svg_path.unwrap());
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    for (source, output) in sources.iter().zip(&outputs) {
+        assert_eq!(std::fs::read(output).unwrap(), std::fs::read(source).unwrap());
+    }
+
+    // Another command converts the files again
+    let mut converters = Converters::new(dir.path().join("converted"));
+    converters.add("pdf", Box::new(CountingConverter(count.clone(), "copy --new")));
+    let mut emoji = Emoji::from_path(sources[0].clone(), None, false).unwrap();
+    converters.convert(&mut emoji).unwrap();
+    assert_eq!(emoji.svg_path.as_ref(), Some(&outputs[0]));
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_invalid_specs() {
+    let cache = tempfile::tempdir().unwrap();
+    for spec in &["pdf2svg {in} {out}", "=pdf2svg {in} {out}", "pdf=", "pdf=  "] {
+        match Converters::from_specs(&[spec], cache.path().to_path_buf()) {
+            Err(ConversionError::InvalidSpec(invalid)) => assert_eq!(&invalid, spec),
+            _ => panic!("{} should be invalid", spec)
+        }
+    }
+    let converters = Converters::from_specs(&["pdf=pdf2svg {in} {out}", "eps = epstool {in} {out}"], cache.path().to_path_buf()).unwrap();
+    assert!(converters.converts(Path::new("emoji_u1f914.pdf")));
+    assert!(converters.converts(Path::new("emoji_u1f914.EPS")));
+    assert!(!converters.converts(Path::new("emoji_u1f914.png")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_converter() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.svg");
+
+    let copy = CommandConverter::new("cp {in} {out}").unwrap();
+    assert_eq!(copy.describe(), "cp {in} {out}");
+    copy.convert(Path::new(SVG), &output).unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), std::fs::read(SVG).unwrap());
+
+    let script = dir.path().join("fail.sh");
+    std::fs::write(&script, "echo \"broken: $1\" >&2\nexit 3\n").unwrap();
+    let failing = CommandConverter::new(&format!("sh {} {{in}} {{out}}", script.display())).unwrap();
+    match failing.convert(Path::new(SVG), &dir.path().join("missing.svg")) {
+        Err(err @ ConversionError::Failed { status: Some(3), .. }) => {
+            assert!(err.to_string().contains(&format!("broken: {}", SVG)));
+        }
+        other => panic!("Expected a failed conversion, got {:?}", other)
+    }
+
+    // Succeeding without writing the file is an error as well
+    let lazy = CommandConverter::new("true {in} {out}").unwrap();
+    match lazy.convert(Path::new(SVG), &dir.path().join("missing.svg")) {
+        Err(ConversionError::Failed { status: Some(0), .. }) => {}
+        other => panic!("Expected a missing file, got {:?}", other)
+    }
+}
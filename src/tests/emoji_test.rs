@@ -14,14 +14,15 @@
  * limitations under the License.
  */
 
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fs;
 use std::iter::FromIterator;
 use std::path::PathBuf;
 
-use crate::emoji::Emoji;
+use crate::emoji::{Emoji, EmojiKind, SequenceError};
 use crate::emoji::EmojiKind::EmojiZwjSequence;
-use crate::emoji_tables::EmojiTable;
+use crate::emoji_tables::{EmojiTable, EmojiTableEntry};
 
 const SVG_PATH: &str = "test_files/svg";
 const TABLES_PATH: &str = "test_files/tables";
@@ -143,3 +144,343 @@ fn build_emojis() -> HashSet<Emoji> {
         diya,
     ])
 }
+
+#[test]
+fn emojis_from_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("1f914.svg"), "").unwrap();
+    fs::write(dir.path().join("not an emoji.svg"), "").unwrap();
+    // Directories are skipped
+    fs::create_dir(dir.path().join("1f9a6")).unwrap();
+
+    let emojis = Emoji::from_dir(dir.path(), None, false).unwrap();
+    assert_eq!(emojis, vec![Emoji::from(vec![0x1f914])]);
+    assert_eq!(emojis[0].svg_path, Some(dir.path().join("1f914.svg")));
+
+    assert!(Emoji::from_dir(dir.path().join("missing"), None, false).is_err());
+}
+
+#[test]
+fn emojis_backfilled_from_late_table() {
+    use crate::diagnostics::DiagnosticsSink;
+
+    let dir = tempfile::tempdir().unwrap();
+    for file in &["1f600.svg", "hugging.svg", "bed.svg", "not an emoji.svg"] {
+        fs::write(dir.path().join(file), "").unwrap();
+    }
+
+    // The directory is read before the table is there
+    let scanned = Emoji::scan_dir(dir.path(), None, false).unwrap();
+    assert_eq!(scanned.emojis.len(), 2);
+    assert_eq!(scanned.failed.len(), 2);
+    assert!(scanned.emojis.iter().all(|emoji| emoji.name.is_none() && emoji.kinds.is_none()));
+
+    let mut table = EmojiTable::new();
+    table.insert(vec![0x1f600], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("grinning face"));
+    table.insert(vec![0x1f917], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("hugging face"));
+    table.insert(vec![0x1f6cf], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("bed"));
+    table.insert_lookup_name("hugging", vec![0x1f917]);
+    table.insert_lookup_name("bed", vec![0x1f6cf]);
+
+    let diagnostics = DiagnosticsSink::new();
+    let mut emojis = scanned.complete(Some(&table), &diagnostics);
+    emojis.sort_by_key(|emoji| emoji.sequence.clone());
+    let sequences: Vec<_> = emojis.iter().map(|emoji| emoji.sequence.clone()).collect();
+    // bed.svg is a valid sequence (U+0BED) as well, but names take precedence
+    assert_eq!(sequences, vec![vec![0x1f600], vec![0x1f6cf], vec![0x1f917]]);
+    assert_eq!(emojis[0].name, Some("grinning face".to_string()));
+    assert_eq!(emojis[0].kinds, Some(vec![EmojiKind::Emoji]));
+    assert_eq!(emojis[2].svg_path, Some(dir.path().join("hugging.svg")));
+    // Only the file that still can't be parsed is reported
+    let reported: Vec<_> = diagnostics.diagnostics().into_iter().filter_map(|diagnostic| diagnostic.path).collect();
+    assert_eq!(reported, vec![dir.path().join("not an emoji.svg")]);
+}
+
+#[test]
+fn emoji_from_path_with_base_name() {
+    let mut table = EmojiTable::new();
+    let technologist = vec![0x1f9d1, 0x200d, 0x1f4bb];
+    table.insert(technologist.clone(), EmojiTableEntry::new(vec![EmojiZwjSequence]).with_name("technologist"));
+    table.insert_lookup_name("technologist", technologist);
+
+    let path = PathBuf::from("woman_technologist_dark_skin_tone.svg");
+    let emoji = Emoji::from_path(path.clone(), Some(&table), false).unwrap();
+    assert_eq!(emoji.sequence, vec![0x1f469, 0x1f3ff, 0x200d, 0x1f4bb]);
+    assert_eq!(emoji.svg_path, Some(path));
+
+    // Sequences still work
+    let emoji = Emoji::from_path(PathBuf::from("1f9d1_200d_1f4bb.svg"), Some(&table), false).unwrap();
+    assert_eq!(emoji.sequence, vec![0x1f9d1, 0x200d, 0x1f4bb]);
+}
+
+#[test]
+fn emoji_resolve() {
+    let mut table = EmojiTable::new();
+    let technologist = vec![0x1f9d1, 0x200d, 0x1f4bb];
+    table.insert(technologist.clone(), EmojiTableEntry::new(vec![EmojiZwjSequence]).with_name("technologist"));
+    table.insert_lookup_name("technologist", technologist.clone());
+    // "face" would also be a valid (but unassigned) codepoint
+    table.insert(vec![0x1f642], EmojiTableEntry::new(vec![]).with_name("face"));
+    table.insert_lookup_name("face", vec![0x1f642]);
+
+    // Names come first
+    let emoji = Emoji::resolve("Technologist", Some(&table)).unwrap();
+    assert_eq!(emoji.sequence, technologist);
+    assert_eq!(emoji.kinds, Some(vec![EmojiZwjSequence]));
+    // The name is the table's one, not the query
+    assert_eq!(emoji.name, Some(String::from("technologist")));
+    assert_eq!(Emoji::resolve("face", Some(&table)).unwrap().sequence, vec![0x1f642]);
+    assert_eq!(Emoji::resolve("face", None).unwrap().sequence, vec![0xface]);
+    assert_eq!(Emoji::resolve("man technologist: medium skin tone", Some(&table)).unwrap().sequence,
+               vec![0x1f468, 0x1f3fd, 0x200d, 0x1f4bb]);
+
+    // Then sequences
+    let emoji = Emoji::resolve("1f9d1-200d-1f4bb", Some(&table)).unwrap();
+    assert_eq!(emoji.name, Some(String::from("technologist")));
+    assert_eq!(emoji.svg_path, None);
+
+    // Files are parsed by their name, even if they don't exist
+    let emoji = Emoji::resolve("svg/technologist.SVG", Some(&table)).unwrap();
+    assert_eq!(emoji.sequence, technologist);
+    assert_eq!(emoji.svg_path, Some(PathBuf::from("svg/technologist.SVG")));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("emoji_u1f9a8.png");
+    fs::write(&path, "").unwrap();
+    let emoji = Emoji::resolve(path.to_str().unwrap(), None).unwrap();
+    assert_eq!(emoji.sequence, vec![0x1f9a8]);
+    assert_eq!(emoji.svg_path, Some(path));
+
+    assert!(Emoji::resolve("not an emoji", Some(&table)).is_err());
+}
+
+#[test]
+fn emoji_validate_sequence() {
+    let emoji = |file: &str| Emoji::from_path(PathBuf::from(file), None, false).unwrap();
+    let validate = |file: &str| emoji(file).validate_sequence();
+
+    assert_eq!(validate("1f3f3_fe0f_200d_1f308.svg"), Ok(()));
+    assert_eq!(validate("1f3f3_fe0f_200d_1f30800.svg"), Err(SequenceError::InvalidCodepoint(0x1f30800)));
+    assert_eq!(validate("200d_1f308.svg"), Err(SequenceError::LeadingZwj));
+    assert_eq!(validate("1f3f3_fe0f_200d.svg"), Err(SequenceError::TrailingZwj));
+    assert_eq!(validate("1f3f3_fe0f_fe0f_200d_1f308.svg"), Err(SequenceError::RepeatedVs16(2)));
+
+    // A family with one person too many
+    let family = "1f469_200d_1f469_200d_1f467_200d_1f466_200d_1f466_200d_1f467.svg";
+    assert_eq!(validate(family), Err(SequenceError::TooLong { length: 11, max_length: 10 }));
+    assert_eq!(emoji(family).validate_sequence_with(11), Ok(()));
+}
+
+#[cfg(unix)]
+#[test]
+fn emojis_from_non_utf8_file_name() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(OsStr::from_bytes(b"1f9a8_\xff.svg"));
+    fs::write(&path, "").unwrap();
+
+    let emoji = Emoji::from_path(path.clone(), None, false).unwrap();
+    assert_eq!(emoji.sequence, vec![0x1f9a8]);
+    assert_eq!(emoji.svg_path, Some(path));
+    assert_eq!(Emoji::from_dir(dir.path(), None, false).unwrap(), vec![emoji]);
+}
+
+#[cfg(unix)]
+#[test]
+fn emojis_from_unreadable_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Permissions don't apply to root
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let unreadable = dir.path().join("unreadable");
+    fs::create_dir(&unreadable).unwrap();
+    fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let result = Emoji::from_dir(&unreadable, None, false);
+    fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn emojis_with_svg_and_png() {
+    use crate::diagnostics::DiagnosticsSink;
+
+    let images = tempfile::tempdir().unwrap();
+    let flags = tempfile::tempdir().unwrap();
+    for file in &["1f914.svg", "1f914.png", "1f600.png"] {
+        fs::write(images.path().join(file), "").unwrap();
+    }
+    // The same sequence in another directory
+    fs::write(flags.path().join("1f600.svg"), "").unwrap();
+
+    let emojis = || Emoji::from_dir(images.path(), None, false).unwrap().into_iter()
+        .chain(Emoji::from_dir(flags.path(), None, false).unwrap())
+        .collect::<Vec<_>>();
+    let files = |emojis: Vec<Emoji>| -> HashSet<PathBuf> {
+        emojis.into_iter().filter_map(|emoji| emoji.svg_path).collect()
+    };
+
+    let diagnostics = DiagnosticsSink::new();
+    let svg = Emoji::deduplicate_sources(emojis(), false, &diagnostics);
+    assert_eq!(svg.len(), 2);
+    assert_eq!(files(svg), HashSet::from_iter(vec![images.path().join("1f914.svg"), flags.path().join("1f600.svg")]));
+    // The shadowed files are reported
+    let shadowed: HashSet<PathBuf> = diagnostics.diagnostics().into_iter().filter_map(|diagnostic| diagnostic.path).collect();
+    assert_eq!(shadowed, HashSet::from_iter(vec![images.path().join("1f914.png"), images.path().join("1f600.png")]));
+
+    let raster = Emoji::deduplicate_sources(emojis(), true, &DiagnosticsSink::default());
+    assert_eq!(files(raster), HashSet::from_iter(vec![images.path().join("1f914.png"), images.path().join("1f600.png")]));
+}
+
+/// All kinds, with some of them spelled differently as `Other` kinds
+fn kinds_with_duplicates() -> Vec<EmojiKind> {
+    vec![
+        EmojiKind::Emoji,
+        EmojiKind::EmojiZwjSequence,
+        EmojiKind::EmojiSequence,
+        EmojiKind::EmojiPresentation,
+        EmojiKind::ModifierBase,
+        EmojiKind::EmojiComponent,
+        EmojiKind::EmojiKeycapSequence,
+        EmojiKind::EmojiFlagSequence,
+        EmojiKind::EmojiModifierSequence,
+        EmojiKind::Other(String::from("Extended_Pictographic")),
+        EmojiKind::Other(String::from("extended pictographic")),
+        EmojiKind::Other(String::from("Emoji_Presentation")),
+        EmojiKind::Other(String::from("vendor")),
+    ]
+}
+
+#[test]
+fn kinds_in_any_order() {
+    let kinds = kinds_with_duplicates();
+    let unique: HashSet<EmojiKind> = kinds.iter().cloned().collect();
+    assert_eq!(unique.len(), 11);
+
+    // A xorshift generator, so the orders are random, but the same in every run
+    let mut state: u32 = 0x2545_f491;
+    for _ in 0..200 {
+        let mut shuffled = kinds.clone();
+        for i in (1..shuffled.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            shuffled.swap(i, state as usize % (i + 1));
+        }
+
+        let mut inserted = vec![];
+        for kind in shuffled.iter().cloned() {
+            EmojiKind::insert_sorted(&mut inserted, kind);
+        }
+        assert!(inserted.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", inserted);
+        assert_eq!(inserted.len(), unique.len());
+        assert_eq!(inserted.iter().cloned().collect::<HashSet<_>>(), unique);
+
+        let mut repaired = shuffled;
+        EmojiKind::sort_unique(&mut repaired);
+        assert_eq!(repaired, inserted);
+    }
+}
+
+#[test]
+fn other_kinds_with_different_spellings() {
+    let upper = EmojiKind::Other(String::from("Extended_Pictographic"));
+    let lower = EmojiKind::Other(String::from("extended pictographic"));
+    assert_eq!(upper, lower);
+    assert_eq!(upper.cmp(&lower), Ordering::Equal);
+
+    let mut kinds = vec![upper];
+    assert!(!EmojiKind::insert_sorted(&mut kinds, lower));
+    assert_eq!(kinds.len(), 1);
+    // It's still shown like it has been written
+    assert_eq!(kinds[0].to_string(), "Extended_Pictographic");
+
+    // Known kinds are recognized in any spelling
+    assert_eq!(EmojiKind::Other(String::from("emoji_modifier_base")), EmojiKind::ModifierBase);
+}
+
+#[test]
+fn emojis_with_variants() {
+    use crate::diagnostics::DiagnosticsSink;
+
+    let emojis: Vec<Emoji> = vec!["1f600.svg", "1f600~blob.svg", "1f601.svg", "1f602~blob.svg", "1f603~ab.svg"]
+        .into_iter()
+        .map(|path| Emoji::from_path(PathBuf::from(path), None, false).unwrap())
+        .collect();
+    // The suffix isn't part of the sequence
+    assert_eq!(emojis[1].sequence, vec![0x1f600]);
+    assert_eq!(emojis[4].variant(), Some(String::from("ab")));
+    let paths = |emojis: Vec<Emoji>| -> Vec<PathBuf> {
+        emojis.into_iter().filter_map(|emoji| emoji.svg_path).collect()
+    };
+
+    // The variant replaces the default files, the others are used as they are
+    let diagnostics = DiagnosticsSink::new();
+    let blob = Emoji::select_variant(emojis.clone(), Some("blob"), &diagnostics);
+    assert_eq!(paths(blob), vec![PathBuf::from("1f600~blob.svg"), PathBuf::from("1f601.svg"), PathBuf::from("1f602~blob.svg")]);
+    assert!(diagnostics.diagnostics().is_empty());
+
+    // Without a variant, only the default files are used
+    let default = Emoji::select_variant(emojis.clone(), None, &diagnostics);
+    assert_eq!(paths(default), vec![PathBuf::from("1f600.svg"), PathBuf::from("1f601.svg")]);
+    assert!(diagnostics.diagnostics().is_empty());
+
+    // Unknown variants fall back to the default files as well, but they're reported
+    let unknown = Emoji::select_variant(emojis, Some("blobs"), &diagnostics);
+    assert_eq!(paths(unknown), vec![PathBuf::from("1f600.svg"), PathBuf::from("1f601.svg")]);
+    assert_eq!(diagnostics.diagnostics().len(), 1);
+    assert!(diagnostics.diagnostics()[0].message.contains("blobs"));
+}
+
+#[test]
+fn emoji_from_test_line() {
+    let woman_scientist = Emoji::from_test_line("1F469 1F3FD 200D 1F52C ; fully-qualified # 👩🏽‍🔬 E4.0 woman scientist: medium skin tone").unwrap();
+    assert_eq!(woman_scientist, crate::emoji!("👩🏽‍🔬"));
+    assert_eq!(woman_scientist.name, Some(String::from("woman scientist: medium skin tone")));
+    assert_eq!(woman_scientist.kinds, Some(vec![EmojiZwjSequence]));
+
+    // Unqualified sequences are kept as they are
+    let keycap = Emoji::from_test_line("0023 20E3 ; unqualified # #⃣ E0.6 keycap: #").unwrap();
+    assert_eq!(keycap.sequence, vec![0x23, 0x20e3]);
+    assert_eq!(keycap.kinds, Some(vec![EmojiKind::EmojiKeycapSequence, EmojiKind::EmojiSequence]));
+    assert_ne!(keycap, crate::emoji!("#️⃣"));
+
+    let red_hair = Emoji::from_test_line("1F9B0 ; component # 🦰 E11.0 red hair").unwrap();
+    assert_eq!(red_hair.kinds, Some(vec![EmojiKind::EmojiComponent]));
+
+    assert!(Emoji::from_test_line("").is_err());
+    assert!(Emoji::from_test_line("1F600 ; fully-qualified #").is_err());
+    assert!(Emoji::from_test_line("# group: Smileys & Emotion").is_err());
+}
+
+#[test]
+fn ignored_files_skipped() {
+    use crate::ignore_files::IGNORE_FILE;
+
+    let dir = tempfile::tempdir().unwrap();
+    for file in &["1f600.svg", "1f600.svg~", ".#1f600.svg", "1f917.svg", "1f917_old.svg"] {
+        fs::write(dir.path().join(file), "").unwrap();
+    }
+    fs::create_dir(dir.path().join("experiments")).unwrap();
+    fs::write(dir.path().join(IGNORE_FILE), "*_old.svg\nexperiments/\n").unwrap();
+
+    let scanned = Emoji::scan_dir(dir.path(), None, false).unwrap();
+    let mut files: Vec<_> = scanned.emojis.iter()
+        .filter_map(|emoji| emoji.svg_path.clone())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec![dir.path().join("1f600.svg"), dir.path().join("1f917.svg")]);
+    assert!(scanned.failed.is_empty());
+    // The backups, the ignore file itself and the subdirectory
+    assert_eq!(scanned.ignored, 5);
+
+    // --no-ignore
+    let scanned = Emoji::scan_dir_with_ignore(dir.path(), None, false, false).unwrap();
+    assert_eq!(scanned.ignored, 0);
+    assert_eq!(scanned.emojis.len() + scanned.failed.len(), 6);
+}
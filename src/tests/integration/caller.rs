@@ -19,10 +19,14 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use crate::builder::EmojiBuilder;
-use crate::builders::blobmoji::Blobmoji;
+use crate::builders::blobmoji::{Blobmoji, BuildDir};
+use crate::builders::blobmoji::font_sequences;
 use crate::changes::FileHashes;
+use crate::configs::Pack;
+use crate::diagnostics::DiagnosticsSink;
 use crate::emoji::Emoji;
 use crate::emoji_tables::EmojiTable;
+use crate::packs::{layer_emojis, PackLayer};
 use crate::tests::integration::builder::DummyBuilder;
 
 pub struct TestResult<T: EmojiBuilder> {
@@ -93,6 +97,15 @@ const TEST_EMOJIS: &str = "test_files/svg";
 const TEST_FLAGS: &str = "test_files/flags";
 const TEST_TABLES: &str = "test_files/tables";
 const TEST_HASHES: &str = "test_files/hashes.csv";
+const TEST_PRIVATE_EMOJIS: &str = "test_files/private/svg";
+const TEST_PRIVATE_NAMES: &str = "test_files/private/names.csv";
+const TEST_PACK: &str = "test_files/render_only.json";
+/// The base emojis, the flags and a seasonal pack that replaces one of the base emojis
+const TEST_LAYERED_PACKS: [&str; 3] = [
+    "test_files/packs/layers/base.yaml",
+    "test_files/packs/layers/flags.yaml",
+    "test_files/packs/layers/seasonal.yaml",
+];
 
 pub fn run_with_test_files<T: EmojiBuilder>() -> TestResult<T> {
     let table = parse_tables(&PathBuf::from(TEST_TABLES));
@@ -136,6 +149,138 @@ fn test_blobmoji() {
     assert!(result.output_path.exists());
 }
 
+#[test]
+fn test_blobmoji_private_set() {
+    // No emoji tables, only the names of the private emojis
+    let mut table = EmojiTable::new();
+    table.expand_names_from_csv_file(TEST_PRIVATE_NAMES).unwrap();
+    let emojis = Emoji::from_dir(TEST_PRIVATE_EMOJIS, Some(&table), false).unwrap();
+    assert_eq!(emojis.len(), 2);
+    assert!(emojis.iter().all(|emoji| emoji.is_private_use() && emoji.name.is_some()));
+
+    let (_, output_path, result) = run::<Blobmoji>(&emojis);
+    assert!(result.is_ok(),
+            "An error has occured:\n\t{:?}", result.unwrap_err());
+    let mut sequences = font_sequences::read_sequences(&output_path).unwrap();
+    // The template already contains some whitespace characters
+    sequences.retain(|sequence| sequence.iter().any(|codepoint| *codepoint > 0x20));
+    // Nothing has been mapped to other PUA codepoints or variation selectors
+    assert_eq!(sequences, vec![vec![0xf0000], vec![0xf0001]].into_iter().collect());
+}
+
+#[test]
+fn test_blobmoji_flags_subset() {
+    let table = parse_tables(&PathBuf::from(TEST_TABLES));
+    let emojis = parse_emojis(
+        &PathBuf::from(TEST_EMOJIS),
+        &PathBuf::from(TEST_FLAGS),
+        table.as_ref(),
+    );
+    let (build_path, output_path) = create_temps();
+    let matches = Blobmoji::sub_command().get_matches_from(vec!["blobmoji", "--subset", "emoji_flag_sequence"]);
+    let mut builder = *Blobmoji::new(build_path, Some(matches)).unwrap();
+    let prepared = prepare(&emojis, &builder);
+    let without_fe0f = |emoji: &Emoji| emoji.sequence.iter()
+        .filter(|codepoint| **codepoint != 0xfe0f)
+        .copied()
+        .collect::<Vec<u32>>();
+    let (flags, others): (Vec<_>, Vec<_>) = prepared.iter()
+        .filter(|(_, prepared)| prepared.is_ok())
+        .map(|(emoji, _)| *emoji)
+        .partition(|emoji| emoji.is_flag());
+    assert!(!flags.is_empty() && !others.is_empty());
+    let result = build(prepared, &mut builder, output_path.clone());
+    assert!(result.is_ok(),
+            "An error has occured:\n\t{:?}", result.unwrap_err());
+
+    // The default output gets a suffix
+    assert!(!output_path.exists());
+    let sequences = font_sequences::read_sequences(&output_path.with_file_name("output_subset")).unwrap();
+    for flag in flags {
+        assert!(sequences.contains(&without_fe0f(flag)), "Missing flag: {:?}", flag);
+    }
+    for emoji in others {
+        assert!(!sequences.contains(&without_fe0f(emoji)), "Not a flag: {:?}", emoji);
+    }
+}
+
+#[test]
+fn test_blobmoji_pack() {
+    // The pack only renders the emojis, so there's no need for Python here
+    let pack = Pack::from_file(TEST_PACK).unwrap();
+    assert_eq!(pack.builder.as_deref(), Some(Blobmoji::sub_command().get_name()));
+    let matches = pack.builder_matches(Blobmoji::sub_command(), None).unwrap();
+
+    let table = parse_tables(&pack.settings.get_path("tables").unwrap().unwrap());
+    let emojis = parse_emojis(
+        &pack.settings.get_path("images").unwrap().unwrap(),
+        &pack.settings.get_path("flags").unwrap().unwrap(),
+        table.as_ref(),
+    );
+    let (build_path, output_path) = create_temps();
+    let mut builder = *Blobmoji::new(build_path.clone(), Some(matches)).unwrap();
+    let prepared = prepare(&emojis, &builder);
+    let result = build(prepared, &mut builder, output_path.clone());
+
+    assert!(result.is_ok(),
+            "An error has occured:\n\t{:?}", result.unwrap_err());
+    assert!(BuildDir::at(&build_path).png_dir().read_dir().unwrap().next().is_some());
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn test_blobmoji_layered_packs() {
+    let packs: Vec<Pack> = TEST_LAYERED_PACKS.iter()
+        .map(|path| Pack::from_file(path).unwrap())
+        .collect();
+    let layers: Vec<PackLayer> = TEST_LAYERED_PACKS.iter()
+        .zip(&packs)
+        .map(|(path, pack)| PackLayer::new(path, pack).unwrap())
+        .collect();
+    let matches = Pack::layered(&packs).builder_matches(Blobmoji::sub_command(), None).unwrap();
+
+    let diagnostics = DiagnosticsSink::new();
+    let scanned = layers.iter()
+        .map(|layer| (layer, layer.complete(layer.scan(None, true).unwrap(), None, None, &diagnostics)))
+        .collect();
+    let emojis = layer_emojis(scanned, &diagnostics);
+    assert_eq!(emojis.len(), 3);
+
+    // The replaced image is reported with the pack that replaced it
+    let replaced = diagnostics.diagnostics();
+    assert_eq!(replaced.len(), 1);
+    assert_eq!(replaced[0].pack.as_deref(), Some("seasonal"));
+    assert_eq!(replaced[0].path, Some(PathBuf::from("test_files/packs/layers/base/emoji_u1f384.svg")));
+
+    let tree = emojis.iter().find(|emoji| emoji.sequence == vec![0x1f384]).unwrap();
+    assert_eq!(tree.svg_path, Some(PathBuf::from("test_files/packs/layers/seasonal/emoji_u1f384.svg")));
+
+    let (build_path, _) = create_temps();
+    let builder = *Blobmoji::new(build_path, Some(matches)).unwrap();
+    let prepared = prepare(&emojis, &builder);
+    assert!(prepared.values().all(Result::is_ok));
+    // The seasonal image is the one that has been rendered
+    let (png, hash) = prepared[tree].as_ref().unwrap();
+    assert!(png.exists());
+    assert_eq!(hash.as_ref().unwrap(), &FileHashes::hash(tree).unwrap());
+}
+
+#[test]
+fn test_default_prepare_batch() {
+    let emojis = parse_emojis(&PathBuf::from(TEST_EMOJIS), &PathBuf::from(TEST_FLAGS), None);
+    let emoji_refs: Vec<&Emoji> = emojis.iter().collect();
+    let (build_path, _) = create_temps();
+    let builder: DummyBuilder = create(build_path);
+
+    let single = prepare(&emojis, &builder);
+    let batch = builder.prepare_batch(&emoji_refs);
+
+    assert_eq!(batch.len(), single.len());
+    for (emoji, prepared) in batch {
+        assert_eq!(prepared.unwrap().0, *single[emoji].as_ref().unwrap());
+    }
+}
+
 fn check_hashes(actual: &Path, expected: &Path) {
     let actual = FileHashes::from_path(actual).unwrap();
     let expected = FileHashes::from_path(expected).unwrap();
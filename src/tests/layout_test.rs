@@ -0,0 +1,143 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::emoji::Emoji;
+use crate::emoji_tables::EmojiTable;
+use crate::layout::{Layout, LayoutEmoji};
+
+/// Two groups (and a `Component` group that's left out) with a modifier base in the second one
+const EMOJI_TEST: &str = "test_files/layout/emoji-test.txt";
+/// The expected layout for [EMOJI_TEST]
+const EXPECTED: &str = "test_files/layout/layout.json";
+
+#[test]
+fn test_layout_snapshot() {
+    let layout = Layout::from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+    let expected: serde_json::Value = serde_json::from_reader(File::open(EXPECTED).unwrap()).unwrap();
+
+    assert_eq!(serde_json::to_value(&layout).unwrap(), expected);
+}
+
+#[test]
+fn test_layout_variants() {
+    let layout = Layout::from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+    // The skin tone variants are not counted
+    assert_eq!(layout.len(), 5);
+    assert!(!layout.is_empty());
+
+    let waving_hand = &layout.groups[1].subgroups[0].emojis[0];
+    assert_eq!(waving_hand.name, "waving hand");
+    assert_eq!(waving_hand.variants.len(), 2);
+    assert!(waving_hand.variants.iter().all(|variant| variant.variants.is_empty()));
+}
+
+#[test]
+fn test_layout_without_groups() {
+    let test_data = "1F44B 1F3FB ; fully-qualified # 👋🏻 E1.0 waving hand: light skin tone\n\
+                     1F44B ; fully-qualified # 👋 E0.6 waving hand\n";
+    let layout = Layout::from_test_data(test_data.as_bytes()).unwrap();
+
+    assert_eq!(layout.groups.len(), 1);
+    assert_eq!(layout.groups[0].name, "");
+    // The base comes after the variant, so it can't be folded
+    assert_eq!(layout.len(), 2);
+}
+
+#[test]
+fn test_layout_ordering() {
+    let mut layout = Layout::from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+    let mut table = EmojiTable::new();
+    table.expand_ordering("1F603\n1F44B 1F3FF\n1F600\n".as_bytes()).unwrap();
+    layout.sort_by_ordering(&table);
+
+    let names = |emojis: &[LayoutEmoji]| emojis.iter().map(|emoji| emoji.name.clone()).collect::<Vec<_>>();
+    let face_smiling = &layout.groups[0].subgroups[0].emojis;
+    assert_eq!(names(face_smiling), vec!["grinning face with big eyes", "grinning face"]);
+    // Emojis without a position stay behind the others in their original order
+    let hands = &layout.groups[1].subgroups[0].emojis;
+    assert_eq!(names(hands), vec!["waving hand", "raised back of hand"]);
+    assert_eq!(names(&hands[0].variants), vec!["waving hand: dark skin tone", "waving hand: light skin tone"]);
+}
+
+#[test]
+fn test_write_test_data() {
+    let layout = Layout::from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+    let mut table = EmojiTable::new();
+    table.expand_descriptions_from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+
+    // The file names usually don't contain U+FE0F
+    let mut parrot = Emoji::from(vec![0xf0000]);
+    parrot.name = Some(String::from("party parrot"));
+    let emojis = vec![
+        Emoji::from(vec![0xf0001]),
+        Emoji::from(vec![0x1f44b, 0x1f3ff]),
+        Emoji::from(vec![0x263a]),
+        parrot,
+        Emoji::from(vec![0x1f600]),
+        Emoji::from(vec![0x1f44b]),
+    ];
+    let write = |layout: &Layout, table: &EmojiTable| {
+        let mut test_data = Vec::new();
+        layout.write_test_data(&emojis, Some(table), &mut test_data).unwrap();
+        String::from_utf8(test_data).unwrap()
+    };
+
+    let test_data = write(&layout, &table);
+    assert_eq!(test_data, "# emoji-test.txt for 6 emojis\n\
+                           \n# group: Smileys & Emotion\n\
+                           \n# subgroup: face-smiling\n\
+                           1F600 ; fully-qualified # \u{1f600} E1.0 grinning face\n\
+                           \n# subgroup: face-affection\n\
+                           263A FE0F ; fully-qualified # \u{263a}\u{fe0f} E0.6 smiling face\n\
+                           \n# group: People & Body\n\
+                           \n# subgroup: hand-fingers-open\n\
+                           1F44B ; fully-qualified # \u{1f44b} E0.6 waving hand\n\
+                           1F44B 1F3FF ; fully-qualified # \u{1f44b}\u{1f3ff} E1.0 waving hand: dark skin tone\n\
+                           \n# group: Custom\n\
+                           \n# subgroup: custom\n\
+                           F0000 ; custom # \u{f0000} party parrot\n\
+                           F0001 ; custom # \u{f0001} [F0001]\n");
+
+    // It can be read again and gives the same file
+    let mut reparsed_table = EmojiTable::new();
+    reparsed_table.expand_descriptions_from_test_data(test_data.as_bytes()).unwrap();
+    assert_eq!(reparsed_table.get_by_name("party parrot").unwrap().0, vec![0xf0000]);
+    assert_eq!(reparsed_table.get_by_name("smiling face").unwrap().0, vec![0x263a, 0xfe0f]);
+    let reparsed_layout = Layout::from_test_data(test_data.as_bytes()).unwrap();
+    assert_eq!(reparsed_layout.len(), 3);
+    assert_eq!(write(&reparsed_layout, &reparsed_table), test_data);
+}
+
+#[test]
+fn test_layout_keywords() {
+    let mut layout = Layout::from_test_data(BufReader::new(File::open(EMOJI_TEST).unwrap())).unwrap();
+    let mut table = EmojiTable::new();
+    table.expand_keywords("1F44B ; hand, wave\n1F44B 1F3FB ; hand, light skin tone\n".as_bytes()).unwrap();
+    layout.add_keywords(&table);
+
+    let hands = &layout.groups[1].subgroups[0].emojis;
+    assert_eq!(hands[0].keywords, vec!["hand", "wave"]);
+    let light_skin_tone = hands[0].variants.iter().find(|variant| variant.sequence == "1f44b 1f3fb").unwrap();
+    assert_eq!(light_skin_tone.keywords, vec!["hand", "light skin tone"]);
+
+    // Emojis without keywords are exported like before
+    let json = serde_json::to_value(&hands[1]).unwrap();
+    assert!(json.get("keywords").is_none());
+    assert_eq!(serde_json::to_value(&hands[0]).unwrap()["keywords"], serde_json::json!(["hand", "wave"]));
+}
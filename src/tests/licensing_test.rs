@@ -0,0 +1,70 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+
+use include_dir::Dir;
+use serde_json::Value;
+
+use crate::attribution::{Attribution, Credit};
+use crate::configs::Pack;
+use crate::emoji::Emoji;
+use crate::licensing::{BundledFile, Inventory, NOASSERTION};
+
+const LICENSES: Dir = include_dir::include_dir!("test_files/licensing/licenses");
+const EXPECTED: &str = "test_files/licensing/expected.spdx.json";
+const PACKS: &str = "test_files/packs/attribution";
+
+fn inventory() -> Inventory {
+    let pack = Pack::from_file(Path::new(PACKS).join("a.yaml")).unwrap();
+    let emojis = Emoji::from_dir(Path::new(PACKS).join("a"), None, false).unwrap();
+    let attribution = Attribution::for_pack(&emojis, Credit::from_settings(&pack.settings).as_ref()).unwrap();
+
+    let mut inventory = Inventory::default();
+    inventory.add_crates(&LICENSES);
+    inventory.add_bundled(&[BundledFile::new("scripts/hello.py", b"print('hello')\n", None)]);
+    inventory.add_content("emojis", &emojis, &attribution);
+    inventory
+}
+
+#[test]
+fn test_spdx_snapshot() {
+    let mut spdx = inventory().to_spdx("emoji_builder", "2021-06-01T12:00:00Z");
+    let expected: Value = serde_json::from_str(&std::fs::read_to_string(EXPECTED).unwrap()).unwrap();
+
+    // The namespace is unique for each document and the tool includes the version
+    assert!(spdx["documentNamespace"].as_str().unwrap().starts_with("https://spdx.org/spdxdocs/emoji_builder-"));
+    assert_ne!(spdx["documentNamespace"], inventory().to_spdx("emoji_builder", "2021-06-02T12:00:00Z")["documentNamespace"]);
+    assert_eq!(spdx["documentNamespace"], inventory().to_spdx("emoji_builder", "2021-06-01T12:00:00Z")["documentNamespace"]);
+    spdx["documentNamespace"] = expected["documentNamespace"].clone();
+    assert!(spdx["creationInfo"]["creators"][0].as_str().unwrap().starts_with("Tool: emoji_builder-"));
+    spdx["creationInfo"]["creators"] = expected["creationInfo"]["creators"].clone();
+
+    assert_eq!(spdx, expected);
+}
+
+#[test]
+fn test_unlicensed_content() {
+    let emojis = Emoji::from_dir(Path::new(PACKS).join("b"), None, false).unwrap();
+    let mut inventory = Inventory::default();
+    inventory.add_content("emojis", &emojis, &Attribution::default());
+
+    let spdx = inventory.to_spdx("emoji_builder", "2021-06-01T12:00:00Z");
+    assert_eq!(spdx["packages"][0]["licenseDeclared"], NOASSERTION);
+    let files = spdx["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().all(|file| file["licenseConcluded"] == NOASSERTION && file["copyrightText"] == NOASSERTION));
+}
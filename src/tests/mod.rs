@@ -17,4 +17,13 @@
 mod emoji_test;
 mod utils_test;
 mod builder_test;
-mod integration;
\ No newline at end of file
+mod tables_test;
+mod configs_test;
+mod viewbox_test;
+mod layout_test;
+mod converters_test;
+mod attribution_test;
+mod licensing_test;
+#[cfg(unix)]
+mod server_test;
+mod integration;
@@ -0,0 +1,139 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Converts emoji images in other formats (e.g. PDF or EPS) to SVG before they are rendered.
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::builder::{EmojiBuilder, PreparationResult};
+use crate::emoji::{Emoji, EmojiKind};
+use crate::emoji_tables::{EmojiTable, EmojiTableEntry};
+use crate::server::{Event, Server};
+
+/// Counts the prepared emojis and fails to build `fail.ttf`
+struct CountingBuilder(Arc<AtomicUsize>);
+
+impl EmojiBuilder for CountingBuilder {
+    type Err = String;
+    type PreparedEmoji = ();
+
+    fn new(_build_dir: PathBuf, _arguments: Option<ArgMatches>) -> Result<Box<Self>, Self::Err> {
+        Ok(Box::new(CountingBuilder(Arc::new(AtomicUsize::new(0)))))
+    }
+
+    fn prepare(&self, _emoji: &Emoji) -> PreparationResult<Self::PreparedEmoji, Self::Err> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(((), None))
+    }
+
+    fn build(&mut self, _emojis: HashMap<&Emoji, Result<Self::PreparedEmoji, Self::Err>>, output_file: PathBuf) -> Result<(), Self::Err> {
+        if output_file == Path::new("fail.ttf") {
+            Err(String::from("failed"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn sub_command<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("counting")
+    }
+}
+
+/// Serves a set of two emojis (and a table with three) on one end of a socket pair
+fn start_server(prepared: Arc<AtomicUsize>) -> (thread::JoinHandle<()>, UnixStream) {
+    let (client, stream) = UnixStream::pair().unwrap();
+    let handle = thread::spawn(move || {
+        let mut table = EmojiTable::new();
+        table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking face"));
+        table.insert(vec![0x1f44b], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("waving hand"));
+        table.insert(vec![0x2615], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("hot beverage"));
+        let emojis = Box::new(|_: Option<&EmojiTable>| Ok::<_, String>(vec![
+            Emoji::from(vec![0x1f914]),
+            Emoji::from(vec![0x2615, 0xfe0f]),
+        ]));
+        let mut server = Server::new(Box::new(CountingBuilder(prepared)), Some(table), emojis);
+        server.serve_connection(BufReader::new(&stream), &stream).unwrap();
+    });
+    (handle, client)
+}
+
+/// Sends a request and returns all events until the request has been handled
+fn request<R: BufRead>(reader: &mut R, mut writer: &UnixStream, request: &str) -> Vec<Event> {
+    writeln!(writer, "{}", request).unwrap();
+    let mut events = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let event: Event = serde_json::from_str(&line).unwrap();
+        let last = !matches!(event, Event::Progress { .. });
+        events.push(event);
+        if last {
+            return events;
+        }
+    }
+}
+
+#[test]
+fn test_server() {
+    let prepared = Arc::new(AtomicUsize::new(0));
+    let (server, client) = start_server(prepared.clone());
+    let mut reader = BufReader::new(&client);
+
+    let events = request(&mut reader, &client, r#"{"action": "build", "output": "font.ttf"}"#);
+    assert_eq!(events.first(), Some(&Event::Progress { message: String::from("Preparing 2 emojis") }));
+    assert!(matches!(events.last(), Some(Event::Done { .. })));
+    assert_eq!(prepared.load(Ordering::SeqCst), 2);
+
+    // Builder errors are sent to the client
+    let events = request(&mut reader, &client, r#"{"action": "build", "output": "fail.ttf"}"#);
+    match events.last() {
+        Some(Event::Error { message }) => assert!(message.contains("failed")),
+        other => panic!("Expected an error, got {:?}", other)
+    }
+    assert_eq!(prepared.load(Ordering::SeqCst), 4);
+
+    // U+FE0F doesn't matter
+    let events = request(&mut reader, &client, r#"{"action": "render", "sequence": "2615"}"#);
+    assert!(matches!(events.as_slice(), [Event::Done { .. }]));
+    assert_eq!(prepared.load(Ordering::SeqCst), 5);
+    let events = request(&mut reader, &client, r#"{"action": "render", "sequence": "1f44b"}"#);
+    assert!(matches!(events.as_slice(), [Event::Error { .. }]));
+
+    let events = request(&mut reader, &client, r#"{"action": "validate"}"#);
+    match events.as_slice() {
+        [Event::Progress { message: missing }, Event::Done { message: summary }] => {
+            assert!(missing.contains("[1F44B]"));
+            assert!(summary.starts_with("1 missing"));
+        }
+        other => panic!("Expected one missing emoji, got {:?}", other)
+    }
+
+    // Invalid requests don't close the connection
+    let events = request(&mut reader, &client, r#"{"action": "dance"}"#);
+    assert!(matches!(events.as_slice(), [Event::Error { .. }]));
+    let events = request(&mut reader, &client, r#"{"action": "validate"}"#);
+    assert!(matches!(events.last(), Some(Event::Done { .. })));
+
+    client.shutdown(std::net::Shutdown::Both).unwrap();
+    server.join().unwrap();
+    assert_eq!(prepared.load(Ordering::SeqCst), 5);
+}
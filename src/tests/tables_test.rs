@@ -0,0 +1,915 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use crate::diagnostics::{Category, DiagnosticsSink, Severity};
+use crate::emoji::{Emoji, EmojiKind};
+use crate::emoji_tables::{EmojiTable, EmojiTableEntry, EmojiVersion, Gender, Inconsistency, KindPolicy, MergePolicy, Name, NamePolicy, Presentation, PresentationIssue, ValidationOptions};
+use crate::emoji_tables;
+
+const EMOJI_TEST_11: &str = "test_files/emoji_test/emoji-test-11.txt";
+const VENDOR_EMOJI_TEST: &str = "test_files/emoji_test/vendor-emoji-test.txt";
+const EMOJIBASE: &str = "test_files/emojibase/data.json";
+const EMOJI_DATA: &str = "test_files/emoji_data/emoji-data.txt";
+const EMOJI_VARIATION_SEQUENCES: &str = "test_files/variation_sequences/emoji-variation-sequences.txt";
+const ANNOTATIONS: &str = "test_files/keywords/annotations.xml";
+const KEYWORDS: &str = "test_files/keywords/keywords.txt";
+
+fn load_test_data(path: &str) -> EmojiTable {
+    let mut table = EmojiTable::new();
+    let reader = BufReader::new(File::open(path).unwrap());
+    table.expand_descriptions_from_test_data(reader).unwrap();
+    table
+}
+
+#[test]
+fn test_emoji_test_11() {
+    let table = load_test_data(EMOJI_TEST_11);
+
+    assert_eq!(table.get_codepoint_by_name("grinning face"), vec![0x1f600]);
+    // non-fully-qualified sequences don't replace the fully-qualified ones
+    assert_eq!(table.get_codepoint_by_name("smiling face"), vec![0x263a, 0xfe0f]);
+    assert_eq!(table.get_codepoint_by_name("rainbow flag"), vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    assert_eq!(table.get_codepoint_by_name("keycap: #"), vec![0x23, 0xfe0f, 0x20e3]);
+
+    assert_eq!(table.get(&vec![0x263a]).unwrap().name(), Some(&"smiling face".into()));
+    assert_eq!(table.get(&vec![0x23, 0x20e3]).unwrap().name(), Some(&"keycap: #".into()));
+}
+
+#[test]
+fn test_display_names() {
+    let mut table = EmojiTable::new();
+    let test = "1F469 1F3FD 200D 1F9B3 ; fully-qualified # 👩🏽‍🦳 E11.0 woman: medium skin tone, white hair\n";
+    table.expand_descriptions_from_test_data(Cursor::new(test)).unwrap();
+    let key = vec![0x1f469, 0x1f3fd, 0x200d, 0x1f9b3];
+
+    // The name keeps its punctuation…
+    let name = table.get(&key).unwrap().name().unwrap();
+    assert_eq!(name.to_string(), "woman: medium skin tone, white hair");
+    assert_eq!(name.key(), "woman medium skin tone white hair");
+    let mut emoji = Emoji::from(key.clone());
+    emoji.set_name(&table).unwrap();
+    assert_eq!(emoji.to_string(), "👩🏽‍🦳 woman: medium skin tone, white hair");
+
+    // …but sloppy queries still find it
+    assert_eq!(table.get_codepoint_by_name("Woman_Medium-Skin-Tone White Hair"), key);
+    assert_eq!(table.get_codepoint_by_name("woman medium skin tone, white hair"), key);
+    assert!(name.matches("WOMAN: medium_skin_tone white-hair"));
+}
+
+#[test]
+fn test_variation_sequences() {
+    let mut table = EmojiTable::new();
+    let diagnostics = DiagnosticsSink::new();
+    table.set_diagnostics(diagnostics.clone());
+    table.expand_from_file(EMOJI_VARIATION_SEQUENCES).unwrap();
+
+    let expected: HashSet<u32> = vec![0x23, 0x2640, 0x2764].into_iter().collect();
+    assert_eq!(*table.emoji_variation_bases(), expected);
+    // They aren't malformed lines of an emoji data table
+    assert!(diagnostics.diagnostics().is_empty());
+
+    let mut other = EmojiTable::new();
+    other.expand("1F610 FE0F ; emoji style; # (1.1) NEUTRAL FACE\n".as_bytes()).unwrap();
+    table.merge_from(&other, MergePolicy::default());
+    assert_eq!(table.emoji_variation_bases().len(), 4);
+}
+
+#[test]
+fn test_vendor_emoji_test() {
+    let table = load_test_data(VENDOR_EMOJI_TEST);
+
+    assert_eq!(table.get_codepoint_by_name("thinking face"), vec![0x1f914]);
+    assert_eq!(table.get_codepoint_by_name("partying face"), vec![0x1f973]);
+    assert_eq!(table.get_codepoint_by_name("pleading face"), vec![0x1f97a]);
+    assert_eq!(table.get_codepoint_by_name("blob cat"), vec![0xfe4e5]);
+    assert_eq!(table.get_codepoint_by_name("blob fox"), vec![0xfe4e6]);
+    // That one doesn't have a name at all
+    assert!(table.get(&vec![0xfe4e7]).is_none());
+}
+
+#[test]
+fn test_parse_test_data_line() {
+    let parsed = EmojiTable::parse_test_data_line("1F97A ; fully-qualified # 🥺 E11.0 pleading face").unwrap();
+    assert_eq!(parsed.emoji, Some("🥺"));
+    assert_eq!(parsed.version, Some((11, 0)));
+    assert_eq!(parsed.name, "pleading face");
+
+    let parsed = EmojiTable::parse_test_data_line("FE4E5 ; fully-qualified # E0.6 blob cat").unwrap();
+    assert_eq!(parsed.emoji, None);
+    assert_eq!(parsed.version, Some((0, 6)));
+    assert_eq!(parsed.name, "blob cat");
+
+    assert!(EmojiTable::parse_test_data_line("FE4E7 ; fully-qualified #").is_none());
+    assert!(EmojiTable::parse_test_data_line("1F600 ; qualified # 😀 grinning face").is_none());
+}
+
+/// Two tables that share thinking face and rainbow flag
+fn overlapping_tables() -> (EmojiTable, EmojiTable) {
+    let first_data = "1F914 ; Emoji\n1F3F3 FE0F 200D 1F308 ; Emoji_ZWJ_Sequence\n1F600 ; Emoji\n";
+    let first_test = "1F914 ; fully-qualified # 🤔 thinking\n1F3F3 FE0F 200D 1F308 ; fully-qualified # 🏳️‍🌈 rainbow flag\n";
+    let second_data = "1F914 ; Emoji_Presentation\n1F3F3 FE0F 200D 1F308 ; Emoji_ZWJ_Sequence\n1F973 ; Emoji\n";
+    let second_test = "1F914 ; fully-qualified # 🤔 thinking face\n1F3F3 FE0F 200D 1F308 ; fully-qualified # 🏳️‍🌈 pride\n1F973 ; fully-qualified # 🥳 partying face\n";
+
+    let load = |data: &str, test: &str| {
+        let mut table = EmojiTable::new();
+        table.expand(Cursor::new(data)).unwrap();
+        table.expand_descriptions_from_test_data(Cursor::new(test)).unwrap();
+        table
+    };
+    (load(first_data, first_test), load(second_data, second_test))
+}
+
+const THINKING: [u32; 1] = [0x1f914];
+const RAINBOW: [u32; 4] = [0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+
+fn entry(table: &EmojiTable, key: &[u32]) -> (Vec<EmojiKind>, Option<String>) {
+    let entry = table.get(&key.to_vec()).unwrap();
+    (entry.kinds().to_vec(), entry.name().map(Name::to_string))
+}
+
+#[test]
+fn test_merge_kinds() {
+    let expected = vec![
+        (KindPolicy::Union, vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]),
+        (KindPolicy::PreferSelf, vec![EmojiKind::Emoji]),
+        (KindPolicy::PreferOther, vec![EmojiKind::EmojiPresentation]),
+    ];
+    for (kinds, expected_kinds) in expected {
+        let (mut first, second) = overlapping_tables();
+        first.merge_with(second, MergePolicy {
+            kinds,
+            names: NamePolicy::PreferSelf,
+        });
+        assert_eq!(entry(&first, &THINKING).0, expected_kinds, "{:?}", kinds);
+        // The same kind is never added twice
+        assert_eq!(entry(&first, &RAINBOW).0, vec![EmojiKind::EmojiZwjSequence], "{:?}", kinds);
+        // Entries that are only in one of the tables are always kept
+        assert_eq!(entry(&first, &[0x1f600]).0, vec![EmojiKind::Emoji]);
+        assert_eq!(entry(&first, &[0x1f973]).0, vec![EmojiKind::Emoji]);
+        // Both tables also contain the rainbow flag without FE0F
+        assert_eq!(first.len(), 5);
+    }
+}
+
+#[test]
+fn test_merge_repairs_kinds() {
+    // Unsorted and with a duplicate, like the kinds of tables from older versions
+    let mut old = EmojiTable::new();
+    old.insert(THINKING.to_vec(), EmojiTableEntry::new(vec![
+        EmojiKind::EmojiPresentation,
+        EmojiKind::Other(String::from("Extended_Pictographic")),
+        EmojiKind::Emoji,
+        EmojiKind::Other(String::from("extended pictographic")),
+    ]));
+    let mut other = EmojiTable::new();
+    other.insert(THINKING.to_vec(), EmojiTableEntry::new(vec![EmojiKind::ModifierBase]));
+
+    old.merge_with(other, MergePolicy::default());
+    assert_eq!(entry(&old, &THINKING).0, vec![
+        EmojiKind::Emoji,
+        EmojiKind::ModifierBase,
+        EmojiKind::EmojiPresentation,
+        EmojiKind::Other(String::from("extended pictographic")),
+    ]);
+}
+
+#[test]
+fn test_merge_names() {
+    let expected = vec![
+        (NamePolicy::PreferSelf, "thinking", "rainbow flag"),
+        (NamePolicy::PreferOther, "thinking face", "pride"),
+        (NamePolicy::PreferLonger, "thinking face", "rainbow flag"),
+    ];
+    for (names, thinking, rainbow) in expected {
+        let (mut first, second) = overlapping_tables();
+        first.merge_with(second, MergePolicy {
+            kinds: KindPolicy::Union,
+            names,
+        });
+        assert_eq!(entry(&first, &THINKING).1, Some(thinking.to_string()), "{:?}", names);
+        assert_eq!(entry(&first, &RAINBOW).1, Some(rainbow.to_string()), "{:?}", names);
+        assert_eq!(entry(&first, &[0x1f973]).1, Some("partying face".into()));
+
+        // The lookup names of both tables are available
+        assert_eq!(first.get_codepoint_by_name("thinking"), THINKING.to_vec());
+        assert_eq!(first.get_codepoint_by_name("thinking face"), THINKING.to_vec());
+        assert_eq!(first.get_codepoint_by_name("pride"), RAINBOW.to_vec());
+        assert_eq!(first.get_codepoint_by_name("partying face"), vec![0x1f973]);
+    }
+}
+
+#[test]
+fn test_add() {
+    let (first, second) = overlapping_tables();
+    let sum = first + second;
+
+    let (mut first, second) = overlapping_tables();
+    first += second;
+    assert_eq!(sum, first);
+
+    let (mut merged, second) = overlapping_tables();
+    merged.merge_with(second, MergePolicy::default());
+    assert_eq!(sum, merged);
+
+    // The right side's names win, the kinds are combined
+    assert_eq!(entry(&sum, &THINKING), (
+        vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation],
+        Some("thinking face".into())
+    ));
+    assert_eq!(entry(&sum, &RAINBOW).1, Some("pride".into()));
+}
+
+#[test]
+fn test_merge_from() {
+    let policies = [
+        MergePolicy::default(),
+        MergePolicy { kinds: KindPolicy::PreferSelf, names: NamePolicy::PreferSelf },
+    ];
+    for policy in policies.iter() {
+        let mut owned = load_test_data(EMOJI_TEST_11);
+        let mut borrowed = load_test_data(EMOJI_TEST_11);
+        let other = load_test_data(VENDOR_EMOJI_TEST);
+
+        borrowed.merge_from(&other, *policy);
+        owned.merge_with(other, *policy);
+        assert_eq!(borrowed, owned);
+    }
+
+    let (mut first, second) = overlapping_tables();
+    first.merge_from(&second, MergePolicy::default());
+    assert_eq!(first, overlapping_tables().0 + second);
+}
+
+#[test]
+fn test_validate_with() {
+    let mut table = EmojiTable::new();
+    let entries = vec![
+        (vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308], EmojiKind::EmojiZwjSequence, "rainbow flag"),
+        (vec![0x1f1e9, 0x1f1ea], EmojiKind::EmojiFlagSequence, "flag: Germany"),
+        (vec![0x1f1eb, 0x1f1f7], EmojiKind::EmojiFlagSequence, "flag: France"),
+        (vec![0x1f914], EmojiKind::Emoji, "thinking face"),
+    ];
+    for (sequence, kind, name) in entries {
+        table.insert(sequence, EmojiTableEntry::new(vec![kind]).with_name(name));
+    }
+    // Entries without a name are not validated
+    table.insert(vec![0x1f9a6], EmojiTableEntry::new(vec![EmojiKind::Emoji]));
+
+    let emojis: HashSet<Vec<u32>> = vec![
+        vec![0x1f3f3, 0x200d, 0x1f308],
+        vec![0x1f914],
+        vec![0x1f9a8],
+    ].into_iter().collect();
+
+    let result = table.validate_with(&emojis, ValidationOptions { ignore_fe0f: true, max_version: None });
+    assert!(!result.is_complete());
+    assert_eq!(result.missing.iter().map(|emoji| emoji.sequence.clone()).collect::<Vec<_>>(), vec![
+        vec![0x1f1e9, 0x1f1ea],
+        vec![0x1f1eb, 0x1f1f7],
+    ]);
+    assert_eq!(result.missing[0].name, Some(String::from("flag: Germany")));
+    assert_eq!(result.missing_by_kind.len(), 1);
+    assert_eq!(result.missing_by_kind[&EmojiKind::EmojiFlagSequence], 2);
+    assert_eq!(result.additional.len(), 1);
+    assert_eq!(result.additional[0].sequence, vec![0x1f9a8]);
+    assert_eq!(result.summary(), "2 missing (2 Emoji_Flag_Sequence), 1 additional");
+
+    // Without ignoring U+FE0F, the rainbow flag doesn't match
+    let result = table.validate_with(&emojis, ValidationOptions { ignore_fe0f: false, max_version: None });
+    assert_eq!(result.missing_by_kind[&EmojiKind::EmojiZwjSequence], 1);
+    assert_eq!(result.additional.len(), 2);
+}
+
+#[test]
+fn test_validate_without_cloning() {
+    let emojis: HashSet<Vec<u32>> = vec![
+        vec![0x1f914],
+        vec![0x1f3f3, 0x200d, 0x1f308],
+        vec![0x2615, 0xfe0f],
+    ].into_iter().collect();
+
+    let normalized = emoji_tables::normalized_keys(&emojis, true);
+    assert_eq!(normalized.len(), emojis.len());
+    for key in &normalized {
+        match key {
+            // Only the sequence with U+FE0F has been copied
+            Cow::Owned(key) => assert_eq!(key, &vec![0x2615]),
+            // The others still point into the input set
+            Cow::Borrowed(key) => assert!(emojis.iter().any(|emoji| std::ptr::eq(emoji.as_slice(), *key)))
+        }
+    }
+    assert_eq!(normalized.iter().filter(|key| matches!(key, Cow::Owned(_))).count(), 1);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_validate_shim() {
+    let mut table = EmojiTable::new();
+    table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking face"));
+    let emojis: HashSet<Vec<u32>> = vec![vec![0x1f9a8]].into_iter().collect();
+
+    let (result, additional) = table.validate(&emojis, true);
+    assert_eq!(result.unwrap_err().len(), 1);
+    assert_eq!(additional.len(), 1);
+}
+
+#[test]
+fn test_check_consistency() {
+    let pack = Path::new("test_files/packs/collisions");
+    let mut table = EmojiTable::new();
+    table.expand_names_from_csv_file(pack.join("names.csv")).unwrap();
+    let emojis: Vec<Emoji> = ["a", "b"].iter()
+        .flat_map(|dir| Emoji::from_dir(pack.join(dir), Some(&table), false).unwrap())
+        .collect();
+
+    assert_eq!(table.check_consistency(&emojis), vec![
+        // b/green_blob.svg was meant to be the second image of U+F0000
+        Inconsistency::NameCollision {
+            name: "green blob".to_string(),
+            named: vec![0xf0000],
+            resolved: vec![0xf0003],
+        },
+        Inconsistency::SequenceCollision {
+            names: vec!["yellow square".to_string()],
+            sequence: vec![0xf0001],
+            paths: vec![pack.join("a/yellow_square.svg"), pack.join("b/emoji_uf0001.svg")],
+        },
+        Inconsistency::MissingEmoji {
+            names: vec!["red circle".to_string()],
+            sequence: vec![0xf0002],
+        },
+    ]);
+
+    // Without the second pack, the name only leads to a missing image
+    let emojis = Emoji::from_dir(pack.join("a"), Some(&table), false).unwrap();
+    assert_eq!(table.check_consistency(&emojis), vec![
+        Inconsistency::MissingEmoji {
+            names: vec!["green blob".to_string()],
+            sequence: vec![0xf0003],
+        },
+        Inconsistency::MissingEmoji {
+            names: vec!["red circle".to_string()],
+            sequence: vec![0xf0002],
+        },
+    ]);
+}
+
+#[test]
+fn test_ordering() {
+    let mut table = EmojiTable::new();
+    assert!(!table.has_ordering());
+    table.expand_ordering_from_file("test_files/ordering/emoji-ordering.txt").unwrap();
+    assert!(table.has_ordering());
+
+    assert_eq!(table.ordering_index(&[0x1f600]), Some(0));
+    assert_eq!(table.ordering_index(&[0x263a, 0xfe0f]), Some(2));
+    assert_eq!(table.ordering_index(&[0x263a]), Some(2));
+    assert_eq!(table.ordering_index(&[0x1f44b]), Some(3));
+    assert_eq!(table.ordering_index(&[0x1f44b, 0x1f3ff]), Some(5));
+    assert_eq!(table.ordering_index(&[0x1f91a]), Some(6));
+    assert_eq!(table.ordering_index(&[0x23, 0xfe0f, 0x20e3]), Some(7));
+    assert_eq!(table.ordering_index(&[0x2a, 0x20e3]), Some(8));
+    assert_eq!(table.ordering_index(&[0x1f1e9, 0x1f1ea]), Some(9));
+    assert_eq!(table.ordering_index(&[0x1f914]), None);
+
+    // Another ordering is appended, existing positions are kept
+    let mut other = EmojiTable::new();
+    other.expand_ordering("1F914\n1F600\n".as_bytes()).unwrap();
+    table += other;
+    assert_eq!(table.ordering_index(&[0x1f600]), Some(0));
+    assert_eq!(table.ordering_index(&[0x1f914]), Some(10));
+}
+
+#[test]
+fn test_get_base_by_name() {
+    let mut table = EmojiTable::new();
+    let mut add = |name: &str, key: Vec<u32>| {
+        table.insert(key.clone(), EmojiTableEntry::new(vec![]).with_name(name));
+        table.insert_lookup_name(name, key);
+    };
+    add("technologist", vec![0x1f9d1, 0x200d, 0x1f4bb]);
+    add("person running", vec![0x1f3c3]);
+    add("woman dancing", vec![0x1f483]);
+    add("thumbs up", vec![0x1f44d]);
+    add("people holding hands", vec![0x1f9d1, 0x200d, 0x1f91d, 0x200d, 0x1f9d1]);
+
+    let (base, _, modifiers) = table.get_base_by_name("woman technologist: dark skin tone").unwrap();
+    assert_eq!(base, vec![0x1f9d1, 0x200d, 0x1f4bb]);
+    assert_eq!(modifiers.skin_tones, vec![0x1f3ff]);
+    assert_eq!(modifiers.gender, Some(Gender::Female));
+    assert_eq!(modifiers.apply(&base), vec![0x1f469, 0x1f3ff, 0x200d, 0x1f4bb]);
+
+    // Gender signs are appended if there's no person at the beginning
+    let (base, _, modifiers) = table.get_base_by_name("man_running_medium-light_skin_tone").unwrap();
+    assert_eq!(base, vec![0x1f3c3]);
+    assert_eq!(modifiers.apply(&base), vec![0x1f3c3, 0x1f3fc, 0x200d, 0x2642, 0xfe0f]);
+
+    // The gender is part of the name here
+    let (base, _, modifiers) = table.get_base_by_name("woman dancing: medium skin tone").unwrap();
+    assert_eq!(base, vec![0x1f483]);
+    assert_eq!(modifiers.gender, None);
+    assert_eq!(modifiers.skin_tones, vec![0x1f3fd]);
+
+    let (base, _, modifiers) = table.get_base_by_name("thumbs_up").unwrap();
+    assert_eq!(base, vec![0x1f44d]);
+    assert!(modifiers.is_empty());
+
+    // Multiple skin tones are applied to the people in order
+    let (base, _, modifiers) = table
+        .get_base_by_name("people holding hands: light skin tone, medium-dark skin tone")
+        .unwrap();
+    assert_eq!(modifiers.skin_tones, vec![0x1f3fb, 0x1f3fe]);
+    assert_eq!(
+        modifiers.apply(&base),
+        vec![0x1f9d1, 0x1f3fb, 0x200d, 0x1f91d, 0x200d, 0x1f9d1, 0x1f3fe]
+    );
+
+    assert!(table.get_base_by_name("woman juggling: dark skin tone").is_none());
+}
+
+#[test]
+fn test_emojibase() {
+    use EmojiKind::*;
+
+    let mut table = EmojiTable::new();
+    table.expand_from_emojibase_file(EMOJIBASE).unwrap();
+    let kinds = |key: &[u32]| table.get(&key.to_vec()).unwrap().kinds().iter().cloned().collect::<HashSet<_>>();
+    let name = |key: &[u32]| table.get(&key.to_vec()).unwrap().name().unwrap().to_string();
+
+    assert_eq!(name(&[0x1f600]), "grinning face");
+    assert_eq!(kinds(&[0x1f600]), vec![Emoji, EmojiPresentation].into_iter().collect());
+    // Text presentation and U+FE0F
+    assert_eq!(name(&[0x263a, 0xfe0f]), "smiling face");
+    assert_eq!(name(&[0x263a]), "smiling face");
+    assert_eq!(kinds(&[0x263a, 0xfe0f]), vec![Emoji].into_iter().collect());
+    // "annotation" and "keywords" of older versions
+    assert_eq!(name(&[0x1f914]), "thinking face");
+
+    // Skins
+    assert_eq!(kinds(&[0x1f44b]), vec![Emoji, EmojiPresentation, ModifierBase].into_iter().collect());
+    assert_eq!(name(&[0x1f44b, 0x1f3ff]), "waving hand: dark skin tone");
+    assert_eq!(kinds(&[0x1f44b, 0x1f3fb]), vec![EmojiModifierSequence].into_iter().collect());
+    assert_eq!(table.get_codepoint_by_name("woman scientist: medium skin tone"), vec![0x1f469, 0x1f3fd, 0x200d, 0x1f52c]);
+    assert_eq!(kinds(&[0x1f469, 0x1f3fd, 0x200d, 0x1f52c]), vec![EmojiZwjSequence].into_iter().collect());
+
+    assert_eq!(kinds(&[0x1f3fb]), vec![Emoji, EmojiPresentation, EmojiComponent].into_iter().collect());
+    assert!(kinds(&[0x23, 0xfe0f, 0x20e3]).contains(&EmojiKeycapSequence));
+    assert!(kinds(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]).contains(&EmojiZwjSequence));
+    assert!(kinds(&[0x1f1e9, 0x1f1ea]).contains(&EmojiFlagSequence));
+
+    // 11 records with 3 skins, the one with the broken hexcode is skipped.
+    // The ones with U+FE0F are also added without it.
+    assert_eq!(table.len(), 14 + 4);
+
+    // Tags are only used if they're unique
+    assert_eq!(table.get_codepoint_by_name("grin"), vec![0x1f600]);
+    assert_eq!(table.get_codepoint_by_name("thinking"), vec![0x1f914]);
+    assert_eq!(table.get_codepoint_by_name("pride"), vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    assert!(table.get_by_name("face").is_none());
+    assert!(table.get_by_name("smile").is_none());
+    assert!(table.get_by_name("broken").is_none());
+
+    // The order of the records
+    assert_eq!(table.ordering_index(&[0x1f600]), Some(0));
+    assert_eq!(table.ordering_index(&[0x263a]), Some(2));
+    assert_eq!(table.ordering_index(&[0x1f44b, 0x1f3ff]), Some(7));
+    assert_eq!(table.ordering_index(&[0x1f469, 0x200d, 0x1f52c]), Some(8));
+    assert_eq!(table.ordering_index(&[0x1f1e9, 0x1f1ea]), Some(13));
+}
+
+#[test]
+fn test_emojibase_merge() {
+    let mut table = EmojiTable::new();
+    table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("thinking"));
+    table.insert(vec![0x1f970], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("smiling face with hearts"));
+    table.insert_lookup_name("grin", vec![0x1f970]);
+    table.expand_from_emojibase_file(EMOJIBASE).unwrap();
+
+    // The names are replaced, the kinds are combined
+    assert_eq!(entry(&table, &[0x1f914]), (
+        vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation],
+        Some("thinking face".into())
+    ));
+    assert_eq!(entry(&table, &[0x1f970]).1, Some("smiling face with hearts".into()));
+    // Tags never replace existing lookup names
+    assert_eq!(table.get_codepoint_by_name("grin"), vec![0x1f970]);
+
+    assert!(EmojiTable::new().expand_from_emojibase("{}".as_bytes()).is_err());
+}
+
+#[test]
+fn test_versions_from_data() {
+    let mut table = EmojiTable::new();
+    table.expand_from_file(EMOJI_DATA).unwrap();
+
+    let exact = |major, minor| Some(EmojiVersion { major, minor, approximate: false });
+    let approximate = |major, minor| Some(EmojiVersion { major, minor, approximate: true });
+
+    assert_eq!(table.version(&[0x1f90c]), exact(13, 0));
+    assert_eq!(table.version(&[0x23]), exact(0, 0));
+    for codepoint in 0x1f600..=0x1f606 {
+        assert_eq!(table.version(&[codepoint]), approximate(1, 0));
+    }
+    assert_eq!(table.version(&[0x1f1e6, 0x1f1e8]), exact(2, 0));
+    // The comment has no version
+    assert!(table.get(&vec![0x1f3fb]).is_some());
+    assert_eq!(table.version(&[0x1f3fb]), None);
+
+    let until_1: HashSet<Vec<u32>> = table.keys_until_version((1, 0)).cloned().collect();
+    let expected: HashSet<Vec<u32>> = (0x1f600..=0x1f606)
+        .chain(std::iter::once(0x23))
+        .map(|codepoint| vec![codepoint])
+        .collect();
+    assert_eq!(until_1, expected);
+    assert_eq!(table.keys_until_version((13, 0)).count(), 11);
+
+    // emoji-test.txt has the exact version of each emoji
+    let test_data = "1F603 FE0F ; fully-qualified # 😃 E0.6 grinning face with big eyes\n\
+                     1F90C ; fully-qualified # 🤌 E12.0 pinched fingers\n";
+    let mut test_table = EmojiTable::new();
+    test_table.expand_descriptions_from_test_data(Cursor::new(test_data)).unwrap();
+    assert_eq!(test_table.version(&[0x1f603]), exact(0, 6));
+
+    table.merge_with(test_table, MergePolicy::default());
+    assert_eq!(table.version(&[0x1f603]), exact(0, 6));
+    assert_eq!(table.version(&[0x1f603, 0xfe0f]), exact(0, 6));
+    assert_eq!(table.version(&[0x1f604]), approximate(1, 0));
+    // An exact version isn't replaced by another one
+    assert_eq!(table.version(&[0x1f90c]), exact(13, 0));
+}
+
+#[test]
+fn test_restrict_to_version() {
+    let test_data = "1F3C3 ; fully-qualified # 🏃 E0.6 person running\n\
+                     1F3C3 200D 2640 FE0F ; fully-qualified # 🏃‍♀️ E4.0 woman running\n\
+                     1F9D1 200D 1F52C ; fully-qualified # 🧑‍🔬 E12.1 scientist\n\
+                     1F90C ; fully-qualified # 🤌 E13.0 pinched fingers\n";
+    let mut table = EmojiTable::new();
+    table.expand_descriptions_from_test_data(Cursor::new(test_data)).unwrap();
+    let emoji = |file: &str| Emoji::from_path(Path::new(file).to_path_buf(), None, false).unwrap();
+    let person_running = emoji("emoji_u1f3c3.svg");
+    let woman_running = emoji("emoji_u1f3c3_200d_2640_fe0f.svg");
+    let scientist = emoji("emoji_u1f9d1_200d_1f52c.svg");
+    let custom = emoji("emoji_uf0000.svg");
+    let emojis = vec![person_running.clone(), woman_running.clone(), scientist.clone(), emoji("emoji_u1f90c.svg"), custom.clone()];
+
+    // Emojis without a known version are kept
+    let restricted = table.restrict_to_version(emojis.clone(), (5, 0), false);
+    assert_eq!(restricted.emojis, vec![person_running.clone(), woman_running.clone(), custom.clone()]);
+    assert_eq!(restricted.excluded.into_iter().collect::<Vec<_>>(), vec![((12, 1), 1), ((13, 0), 1)]);
+    assert!(restricted.downgraded.is_empty());
+
+    // Woman running falls back to the image of person running, the scientist has no fallback
+    let downgraded = table.restrict_to_version(emojis, (3, 0), true);
+    assert_eq!(downgraded.downgraded, vec![(woman_running.sequence.clone(), person_running.sequence.clone())]);
+    assert_eq!(downgraded.emojis.len(), 3);
+    let fallback = downgraded.emojis.iter().find(|emoji| **emoji == woman_running).unwrap();
+    assert_eq!(fallback.svg_path, person_running.svg_path);
+
+    // The newer emojis aren't missing
+    for (sequence, name) in [(vec![0x1f3c3], "person running"), (vec![0x1f9d1, 0x200d, 0x1f52c], "scientist")] {
+        table.insert(sequence, EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]).with_name(name));
+    }
+    let sequences = vec![person_running.sequence];
+    assert!(table.validate_with(&sequences, ValidationOptions { ignore_fe0f: true, max_version: Some((5, 0)) }).missing.is_empty());
+    assert_eq!(table.validate_with(&sequences, ValidationOptions { ignore_fe0f: true, max_version: None }).missing, vec![scientist]);
+
+    assert_eq!("E5.0".parse::<EmojiVersion>().unwrap().as_tuple(), (5, 0));
+    assert_eq!("13".parse::<EmojiVersion>().unwrap().as_tuple(), (13, 0));
+    assert!("five".parse::<EmojiVersion>().is_err());
+}
+
+#[test]
+fn test_get_entry_with_without_fe0f() {
+    let mut table = EmojiTable::new();
+    let coffee = EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("hot beverage");
+    let rainbow = EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]).with_name("rainbow flag");
+    table.insert(vec![0x2615], coffee.clone());
+    table.insert(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308], rainbow.clone());
+
+    // The key itself
+    assert_eq!(table.get_with_without_fe0f(&[0x2615]), Some(&coffee));
+    let (key, entry) = table.get_entry_with_without_fe0f(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]).unwrap();
+    assert_eq!(key, vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]);
+    assert_eq!(*entry, rainbow);
+
+    // Only the key without U+FE0F is in the table
+    assert_eq!(table.get_with_without_fe0f(&[0x2615, 0xfe0f]), Some(&coffee));
+    let (key, entry) = table.get_entry_with_without_fe0f(&[0x2615, 0xfe0f]).unwrap();
+    assert_eq!(key, vec![0x2615]);
+    assert!(entry.add_kind(EmojiKind::EmojiPresentation));
+    assert_eq!(table.get(&vec![0x2615]).unwrap().kinds(), vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]);
+    assert!(table.get(&vec![0x2615, 0xfe0f]).is_none());
+
+    // Misses
+    assert!(table.get_with_without_fe0f(&[0x1f914]).is_none());
+    assert!(table.get_entry_with_without_fe0f(&[0x1f914, 0xfe0f]).is_none());
+    // Keys are only ever shortened, not extended
+    assert!(table.get_entry_with_without_fe0f(&[0x1f3f3, 0x200d, 0x1f308]).is_none());
+}
+
+#[test]
+fn test_malformed_line_diagnostics() {
+    let data = "1F914 ; Emoji # E3.0 [1] (🤔) thinking face\n\
+                this is not a table line\n\
+                # A comment is fine\n";
+    let diagnostics = DiagnosticsSink::new();
+    let mut table = EmojiTable::new();
+    table.set_diagnostics(diagnostics.clone());
+    table.expand(data.as_bytes()).unwrap();
+
+    assert!(table.get(&vec![0x1f914]).is_some());
+    let diagnostics = diagnostics.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].category, Category::Table);
+    assert!(diagnostics[0].message.contains("this is not a table line"));
+    // The data didn't come from a file
+    assert_eq!(diagnostics[0].path, None);
+}
+
+#[test]
+fn test_presentation() {
+    let data = "00A9 ; Emoji # E0.6 [1] (©️) copyright\n\
+                2764 ; Emoji # E0.6 [1] (❤️) red heart\n\
+                1F44D ; Emoji # E0.6 [1] (👍) thumbs up\n\
+                1F44D ; Emoji_Presentation # E0.6 [1] (👍) thumbs up\n\
+                1F525 ; Emoji # E0.6 [1] (🔥) fire\n\
+                1F525 ; Emoji_Presentation # E0.6 [1] (🔥) fire\n\
+                261D ; Emoji # E0.6 [1] (☝️) index pointing up\n\
+                1F3FB ; Emoji # E1.0 [1] (🏻) light skin tone\n\
+                1F3FB ; Emoji_Presentation # E1.0 [1] (🏻) light skin tone\n";
+    let mut table = EmojiTable::new();
+    table.expand(data.as_bytes()).unwrap();
+    assert_eq!(table.default_presentation(0x2764), Some(Presentation::Text));
+    assert_eq!(table.default_presentation(0xa9), Some(Presentation::Text));
+    assert_eq!(table.default_presentation(0x1f44d), Some(Presentation::Emoji));
+    assert_eq!(table.default_presentation(0x200d), None);
+
+    // Text presentation by default
+    assert_eq!(table.presentation_issue(&[0x2764]), Some(PresentationIssue::MissingVs16 {
+        canonical: vec![0x2764, 0xfe0f]
+    }));
+    assert_eq!(table.presentation_issue(&[0xa9]), Some(PresentationIssue::MissingVs16 {
+        canonical: vec![0xa9, 0xfe0f]
+    }));
+    assert_eq!(table.presentation_issue(&[0x2764, 0x200d, 0x1f525]), Some(PresentationIssue::MissingVs16 {
+        canonical: vec![0x2764, 0xfe0f, 0x200d, 0x1f525]
+    }));
+
+    // Fully-qualified emojis are fine
+    for sequence in &[
+        vec![0x2764, 0xfe0f],
+        vec![0xa9, 0xfe0f],
+        vec![0x1f44d],
+        vec![0x2764, 0xfe0f, 0x200d, 0x1f525],
+        // The skin tone replaces the U+FE0F
+        vec![0x261d, 0x1f3fb],
+    ] {
+        assert_eq!(table.presentation_issue(sequence), None, "{:X?}", sequence);
+    }
+
+    // Emoji presentation by default
+    assert_eq!(table.presentation_issue(&[0x1f44d, 0xfe0f]), Some(PresentationIssue::RedundantVs16 {
+        canonical: vec![0x1f44d]
+    }));
+    // Unknown codepoints keep their U+FE0F
+    assert_eq!(table.canonical_sequence(&[0x1f914, 0xfe0f]), vec![0x1f914, 0xfe0f]);
+}
+
+#[test]
+fn test_short_ids() {
+    let mut table = EmojiTable::new();
+    table.insert(vec![0x1f914], EmojiTableEntry::new(vec![EmojiKind::Emoji]));
+    let thinking = table.short_id(&[0x1f914]);
+    assert_eq!(table.resolve_short_id(&thinking), Some(vec![0x1f914]));
+
+    // Emojis that are added afterwards can be found as well
+    let rainbow_flag = vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+    table.insert(rainbow_flag.clone(), EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]));
+    let id = table.short_id(&rainbow_flag);
+    assert_eq!(table.resolve_short_id(&id), Some(rainbow_flag));
+    assert_eq!(table.short_id(&[0x1f914]), thinking);
+    assert_eq!(Emoji::from(vec![0x1f914]).short_id(), thinking);
+    assert_eq!(table.resolve_short_id("00000000"), None);
+}
+
+#[test]
+fn test_sequences_with_prefix() {
+    let table = load_test_data(EMOJI_TEST_11);
+    // The same as looking at every sequence
+    let naive = |prefix: &[u32]| -> Vec<Vec<u32>> {
+        let mut sequences: Vec<Vec<u32>> = table.as_ref().keys()
+            .filter(|sequence| sequence.starts_with(prefix))
+            .cloned()
+            .collect();
+        sequences.sort();
+        sequences
+    };
+    let with_prefix = |table: &EmojiTable, prefix: &[u32]| -> Vec<Vec<u32>> {
+        table.sequences_with_prefix(prefix).cloned().collect()
+    };
+
+    let rainbow_flag = [0x1f3f3];
+    assert_eq!(naive(&rainbow_flag).len(), 2);
+    assert_eq!(with_prefix(&table, &rainbow_flag), naive(&rainbow_flag));
+    assert_eq!(with_prefix(&table, &[0x1f3f3, 0xfe0f]), vec![vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]]);
+    assert_eq!(with_prefix(&table, &[]), naive(&[]));
+    // The sequence itself is included
+    assert_eq!(with_prefix(&table, &[0x1f600]), vec![vec![0x1f600]]);
+    // Longer than any sequence
+    assert!(with_prefix(&table, &[0x1f3f3, 0xfe0f, 0x200d, 0x1f308, 0x200d]).is_empty());
+
+    // The index is built again after an emoji has been added
+    let mut table = table;
+    let custom = vec![0x1f600, 0x200d, 0x1f4bb];
+    table.insert(custom.clone(), EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]));
+    assert_eq!(with_prefix(&table, &[0x1f600]), vec![vec![0x1f600], custom]);
+}
+
+#[test]
+fn test_keywords() {
+    let diagnostics = DiagnosticsSink::new();
+    let mut table = EmojiTable::new();
+    table.set_diagnostics(diagnostics.clone());
+    table.expand_keywords_from_file(ANNOTATIONS).unwrap();
+    table.expand_keywords_from_file(KEYWORDS).unwrap();
+
+    assert_eq!(table.keywords(&[0x1f600]), vec!["face", "grin", "grinning face"]);
+    // The keywords of both files are combined and the names for text-to-speech are left out
+    assert_eq!(table.keywords(&[0x1f914]), vec!["face", "thinking", "hmm", "pondering"]);
+    let rainbow_flag = [0x1f3f3, 0xfe0f, 0x200d, 0x1f308];
+    assert_eq!(table.keywords(&rainbow_flag), table.keywords(&[0x1f3f3, 0x200d, 0x1f308]));
+    assert!(table.keywords(&rainbow_flag).contains(&"queer"));
+    assert_eq!(table.keywords(&[0x1f197]), vec!["ok & fine", "button"]);
+    // The line without a `;`
+    assert_eq!(diagnostics.diagnostics().len(), 1);
+
+    // Every word of the term has to be the beginning of a word of a keyword
+    assert_eq!(table.search("grin"), vec![vec![0x1f600], vec![0x1f603]]);
+    assert_eq!(table.search("GRIN big"), vec![vec![0x1f603]]);
+    assert_eq!(table.search("pon"), vec![vec![0x1f914]]);
+    assert_eq!(table.search("prid"), vec![vec![0x1f3f3, 0x200d, 0x1f308]]);
+    assert!(table.search("inning").is_empty());
+    assert!(table.search(" ").is_empty());
+
+    // Names can be found as well and the results follow the ordering
+    table.insert(vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308], EmojiTableEntry::new(vec![EmojiKind::EmojiZwjSequence]).with_name("rainbow flag"));
+    table.insert(vec![0x1f92d], EmojiTableEntry::new(vec![EmojiKind::Emoji]).with_name("face with hand over mouth"));
+    table.expand_ordering("1F92D\n1F914\n".as_bytes()).unwrap();
+    assert_eq!(table.search("fla"), vec![vec![0x1f3f3, 0xfe0f, 0x200d, 0x1f308]]);
+    assert_eq!(&table.search("face")[..2], &[vec![0x1f92d], vec![0x1f914]]);
+    assert!(table.explain(&[0x1f3f3, 0xfe0f, 0x200d, 0x1f308]).contains("Keywords: bisexual, gay, lgbt"));
+
+    // Merging keeps the keywords
+    let mut other = EmojiTable::new();
+    other.merge_from(&table, MergePolicy::default());
+    assert_eq!(other.keywords(&[0x1f914]), table.keywords(&[0x1f914]));
+}
+
+#[test]
+fn test_emojibase_keywords() {
+    let mut table = EmojiTable::new();
+    table.expand_from_emojibase_file(EMOJIBASE).unwrap();
+    // Both, tags and the older keywords become keywords
+    assert_eq!(table.keywords(&[0x1f600]), vec!["face", "grin"]);
+    assert_eq!(table.keywords(&[0x1f914]), vec!["face", "thinking"]);
+    assert_eq!(table.search("thin"), vec![vec![0x1f914]]);
+}
+
+#[test]
+fn test_entry() {
+    let mut entry = EmojiTableEntry::new(vec![EmojiKind::EmojiPresentation]);
+    assert_eq!(entry.name(), None);
+    assert!(entry.add_kind(EmojiKind::Emoji));
+    assert!(!entry.add_kind(EmojiKind::EmojiPresentation));
+    assert_eq!(entry.kinds(), &[EmojiKind::Emoji, EmojiKind::EmojiPresentation]);
+
+    entry.set_name(Some(Name::from("thinking face")));
+    assert_eq!(entry, EmojiTableEntry::new(vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation]).with_name("thinking face"));
+
+    // The tuples that have been used before can still be converted
+    let tuple: (Vec<EmojiKind>, Option<Name>) = entry.clone().into();
+    assert_eq!(tuple, (vec![EmojiKind::Emoji, EmojiKind::EmojiPresentation], Some(Name::from("thinking face"))));
+    assert_eq!(EmojiTableEntry::from(tuple), entry);
+    assert_eq!(EmojiTableEntry::from((vec![], None)), EmojiTableEntry::default());
+}
+
+#[test]
+fn test_stats() {
+    use std::collections::BTreeMap;
+
+    let table = load_test_data(EMOJI_TEST_11);
+    let stats = table.stats();
+    assert_eq!(stats.entries, 8);
+    assert_eq!(stats.named, 8);
+    assert!(stats.by_kind.is_empty());
+    assert_eq!(stats.by_status, vec![
+        (String::from("fully-qualified"), 5),
+        (String::from("non-fully-qualified"), 3),
+    ].into_iter().collect::<BTreeMap<_, _>>());
+    assert_eq!(stats.max_version, None);
+    // The sources aren't tracked
+    assert!(stats.by_source.is_empty());
+    assert_eq!(table.status(&[0x263a]), Some("non-fully-qualified"));
+    assert_eq!(table.status(&[0x263a, 0xfe0f]), Some("fully-qualified"));
+
+    let mut data = EmojiTable::with_provenance();
+    data.expand_from_file("test_files/tables/emoji-data.txt").unwrap();
+    let stats = data.stats();
+    assert_eq!(stats.entries, 55);
+    assert_eq!(stats.named, 0);
+    assert_eq!(stats.by_kind, vec![
+        (EmojiKind::Emoji, 24),
+        (EmojiKind::EmojiPresentation, 21),
+        (EmojiKind::EmojiComponent, 26),
+        (EmojiKind::Other(String::from("emoji modifier")), 5),
+    ].into_iter().collect::<BTreeMap<_, _>>());
+    assert!(stats.by_status.is_empty());
+    assert_eq!(stats.by_source.get("emoji-data.txt"), Some(&55));
+
+    let mut vendor = EmojiTable::with_provenance();
+    let reader = BufReader::new(File::open(VENDOR_EMOJI_TEST).unwrap());
+    vendor.expand_descriptions_from_test_data_with_source(reader, "vendor-emoji-test.txt").unwrap();
+    let stats = vendor.stats();
+    assert_eq!((stats.entries, stats.named), (5, 5));
+    assert_eq!(stats.by_status.get("fully-qualified"), Some(&5));
+    assert_eq!(stats.max_version, Some((11, 0)));
+    assert_eq!(stats.by_source.get("vendor-emoji-test.txt"), Some(&5));
+
+    // The tables don't overlap, so the counts of the merged table add up
+    let mut merged = EmojiTable::with_provenance();
+    merged.merge_from(&data, MergePolicy::default());
+    merged.merge_with(vendor, MergePolicy::default());
+    let stats = merged.stats();
+    assert_eq!((stats.entries, stats.named), (60, 5));
+    assert_eq!(stats.by_kind, data.stats().by_kind);
+    assert_eq!(stats.by_status.get("fully-qualified"), Some(&5));
+    assert_eq!(stats.max_version, Some((11, 0)));
+    assert_eq!(stats.by_source.get("emoji-data.txt"), Some(&55));
+    assert_eq!(stats.by_source.get("vendor-emoji-test.txt"), Some(&5));
+
+    let summary = stats.to_string();
+    assert!(summary.contains("Entries:         60\n"));
+    assert!(summary.contains("Highest version: 11.0\n"));
+    assert!(summary.contains("Statuses:        fully-qualified: 5\n"));
+    assert!(summary.contains("Sources:         emoji-data.txt: 55, vendor-emoji-test.txt: 5\n"));
+    assert!(EmojiTable::new().stats().to_string().contains("Kinds:           -\n"));
+}
+
+#[test]
+fn test_name_overrides() {
+    let test_data = "1F52B ; fully-qualified # 🔫 E0.6 water pistol\n\
+                     2615 FE0F ; fully-qualified # ☕ E0.6 hot beverage\n\
+                     1F914 ; fully-qualified # 🤔 E1.0 thinking face\n";
+    let diagnostics = DiagnosticsSink::new();
+    let mut table = EmojiTable::with_provenance();
+    table.set_diagnostics(diagnostics.clone());
+    table.expand_descriptions_from_test_data(test_data.as_bytes()).unwrap();
+
+    let overrides = "# Product names\n\
+                     U+1F52B ; water gun\n\
+                     hot_beverage ; Coffee\n\
+                     1F600 ; grinning\n\
+                     thinking face\n";
+    table.apply_name_overrides(overrides.as_bytes()).unwrap();
+
+    // By sequence
+    assert_eq!(table.get(&vec![0x1f52b]).unwrap().name(), Some(&Name::from("water gun")));
+    assert_eq!(table.get_by_name("water gun").unwrap().0, vec![0x1f52b]);
+    // By name (and for the sequence without U+FE0F as well)
+    assert_eq!(table.get(&vec![0x2615, 0xfe0f]).unwrap().name(), Some(&Name::from("Coffee")));
+    assert_eq!(table.get(&vec![0x2615]).unwrap().name(), Some(&Name::from("Coffee")));
+    assert_eq!(table.get_by_name("coffee").unwrap().0, vec![0x2615, 0xfe0f]);
+    // The old names still find the emojis
+    assert_eq!(table.get_by_name("water_pistol").unwrap().0, vec![0x1f52b]);
+    assert_eq!(table.get_by_name("hot beverage").unwrap().0, vec![0x2615, 0xfe0f]);
+    assert_eq!(table.get(&vec![0x1f914]).unwrap().name(), Some(&Name::from("thinking face")));
+    assert!(table.get_provenance(&[0x1f52b]).unwrap().iter()
+        .any(|(field, _)| *field == emoji_tables::EntryField::Name(String::from("water gun"))));
+
+    // The emoji that isn't in the table and the line without a name
+    let reported: Vec<_> = diagnostics.diagnostics().into_iter().map(|diagnostic| diagnostic.message).collect();
+    assert_eq!(reported.len(), 2);
+    assert!(reported[0].contains("1F600 ; grinning"));
+    assert!(reported[1].contains("thinking face"));
+}
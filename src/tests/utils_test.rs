@@ -18,7 +18,7 @@ use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 
-use crate::changes::FileHashes;
+use crate::changes::{Changelog, FileHashes};
 use crate::emoji::Emoji;
 
 const SVG_FILE: &str = "test_files/svg/emoji_u1f9a6.svg";
@@ -57,4 +57,78 @@ fn test_hashing() {
     let correct_hashes = FileHashes::from_path(&correct_path).unwrap();
     assert_eq!(correct_hashes.len(), 1);
     assert!(correct_hashes.check(&emoji).unwrap());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_hash_bytes() {
+    let emoji = Emoji::from_path(PathBuf::from(SVG_FILE), None, false).unwrap();
+    let source = std::fs::read(SVG_FILE).unwrap();
+    assert_eq!(FileHashes::hash_bytes(&source, &[]), FileHashes::hash(&emoji).unwrap());
+    assert_eq!(FileHashes::hash_bytes(&source, b"tweak"), FileHashes::hash_with(&emoji, b"tweak").unwrap());
+
+    // CRs are ignored, just like when the file is hashed
+    let crlf: Vec<u8> = source.iter()
+        .flat_map(|byte| if *byte == b'\n' { vec![b'\r', b'\n'] } else { vec![*byte] })
+        .collect();
+    assert_ne!(crlf, source);
+    assert_eq!(FileHashes::hash_bytes(&crlf, &[]), FileHashes::hash_bytes(&source, &[]));
+
+    // The check returns the new hash
+    let mut hashes = FileHashes::new();
+    let (unchanged, hash) = hashes.check_bytes(&emoji, &source, &[]);
+    assert!(!unchanged);
+    hashes.update(&emoji, &hash);
+    assert_eq!(hashes.check_bytes(&emoji, &crlf, &[]), (true, hash));
+    assert!(hashes.check(&emoji).unwrap());
+    assert!(!hashes.check_bytes(&emoji, &source, b"tweak").0);
+}
+
+#[test]
+fn test_changelog() {
+    let dir = tempfile::tempdir().unwrap();
+    for file in &["emoji_u1f9a6.svg", "emoji_u1f93f.svg"] {
+        std::fs::copy(PathBuf::from("test_files/svg").join(file), dir.path().join(file)).unwrap();
+    }
+    let snapshot = |dir: &std::path::Path| {
+        let emojis = Emoji::from_dir(dir, None, false).unwrap();
+        FileHashes::snapshot(&emojis.iter().collect::<Vec<_>>()).unwrap()
+    };
+
+    // The first build
+    let first = snapshot(dir.path());
+    let changes = first.changes_since(None);
+    assert!(changes.first_build);
+    assert_eq!(changes.added, vec![vec![0x1f93f], vec![0x1f9a6]]);
+    let build_dir = tempfile::tempdir().unwrap();
+    let last_build = build_dir.path().join("last_build.csv");
+    first.write_to_path(last_build.clone()).unwrap();
+
+    // One emoji is modified and one is added
+    std::fs::copy("test_files/svg/emoji_u1f9c6.svg", dir.path().join("emoji_u1f93f.svg")).unwrap();
+    std::fs::copy("test_files/svg/emoji_u1fa94.svg", dir.path().join("emoji_u1fa94.svg")).unwrap();
+    let second = snapshot(dir.path());
+    let previous = FileHashes::from_path(&last_build).unwrap();
+    assert_eq!(second.changes_since(Some(&previous)), Changelog {
+        added: vec![vec![0x1fa94]],
+        changed: vec![vec![0x1f93f]],
+        removed: vec![],
+        first_build: false,
+    });
+    // And the other way round
+    assert_eq!(previous.changes_since(Some(&second)).removed, vec![vec![0x1fa94]]);
+    assert!(second.changes_since(Some(&second)).is_empty());
+
+    let mut markdown = Vec::new();
+    second.changes_since(Some(&previous)).write_markdown(&mut markdown, None).unwrap();
+    assert_eq!(String::from_utf8(markdown).unwrap(), "# Changelog\n\
+        \n\
+        1 new, 1 changed, 0 removed emojis\n\
+        \n\
+        ## New emojis (1)\n\
+        \n\
+        - \u{1fa94} [1FA94] (`1fa94`)\n\
+        \n\
+        ## Changed emojis (1)\n\
+        \n\
+        - \u{1f93f} [1F93F] (`1f93f`)\n");
+}
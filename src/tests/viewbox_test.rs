@@ -0,0 +1,118 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs;
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::viewbox;
+use crate::viewbox::{ViewBox, ViewBoxError, ViewBoxReport};
+
+/// Has a viewBox of 0 0 128 128
+const CORRECT: &str = "test_files/svg/1f9a8.svg";
+/// Only has a width and height of 128
+const MISSING: &str = "test_files/svg/emoji_u1f3f3_200d_1f308.svg";
+/// Has a viewBox of 0 0 5 3
+const NON_SQUARE: &str = "test_files/flags/DE.svg";
+
+#[test]
+fn test_report() {
+    let report = ViewBoxReport::check([CORRECT, MISSING, NON_SQUARE]);
+
+    assert_eq!(report.sizes.len(), 2);
+    assert_eq!(report.sizes["128x128"], vec![PathBuf::from(CORRECT)]);
+    assert_eq!(report.sizes["5x3"], vec![PathBuf::from(NON_SQUARE)]);
+    assert_eq!(report.missing, vec![PathBuf::from(MISSING)]);
+    assert_eq!(report.non_square, vec![PathBuf::from(NON_SQUARE)]);
+    assert!(report.invalid.is_empty());
+    assert!(!report.is_uniform(128.0));
+
+    assert!(ViewBoxReport::check([CORRECT]).is_uniform(128.0));
+    let invalid = ViewBoxReport::check(["test_files/svg/does_not_exist.svg"]);
+    assert_eq!(invalid.invalid.len(), 1);
+}
+
+#[test]
+fn test_parse() {
+    let svg = r#"<svg viewBox="-10,-10 20,40"></svg>"#;
+    assert_eq!(ViewBox::parse(svg).unwrap(), Some(ViewBox {
+        min_x: -10.0,
+        min_y: -10.0,
+        width: 20.0,
+        height: 40.0,
+    }));
+    assert!(matches!(ViewBox::parse(r#"<svg viewBox="0 0 128"></svg>"#), Err(ViewBoxError::InvalidViewBox(_))));
+    assert!(matches!(ViewBox::parse("<g></g>"), Err(ViewBoxError::NoSvgElement)));
+    // Relative sizes can't be used instead of a viewBox
+    assert!(matches!(
+        ViewBox::parse_or_size(r#"<svg width="100%" height="100%"></svg>"#),
+        Err(ViewBoxError::UnknownSize)
+    ));
+    assert_eq!(ViewBox::parse_or_size(r#"<svg width="36px" height="36"></svg>"#).unwrap(), ViewBox::square(36.0));
+}
+
+#[test]
+fn test_normalize() {
+    // Already correct files stay the same
+    let correct = fs::read_to_string(CORRECT).unwrap();
+    assert_eq!(viewbox::normalize(&correct, 128.0).unwrap(), correct);
+
+    let missing = fs::read_to_string(MISSING).unwrap();
+    let normalized = viewbox::normalize(&missing, 36.0).unwrap();
+    assert_eq!(ViewBox::parse(&normalized).unwrap(), Some(ViewBox::square(36.0)));
+    assert!(normalized.contains(r#"<g transform="translate(0 0) scale(0.28125)">"#));
+
+    // The 5x3 flag is scaled to 128x76.8 and moved down by 25.6 to be centered
+    let non_square = fs::read_to_string(NON_SQUARE).unwrap();
+    let normalized = viewbox::normalize(&non_square, 128.0).unwrap();
+    assert_eq!(ViewBox::parse(&normalized).unwrap(), Some(ViewBox::square(128.0)));
+    let transform = Regex::new(r#"<g transform="translate\(([^ ]+) ([^ ]+)\) scale\(([^)]+)\)">"#).unwrap();
+    let transform: Vec<f64> = transform.captures(&normalized).unwrap()
+        .iter()
+        .skip(1)
+        .map(|value| value.unwrap().as_str().parse().unwrap())
+        .collect();
+    assert!(transform[0].abs() < 1e-6);
+    assert!((transform[1] - 25.6).abs() < 1e-6);
+    assert!((transform[2] - 25.6).abs() < 1e-6);
+    assert!(normalized.trim_end().ends_with("</g></svg>"));
+    // Only the width and height of the svg element are replaced
+    assert_eq!(normalized.matches("<svg").count(), 1);
+    assert!(normalized.contains(r#"<svg width="128" height="128" viewBox="0 0 128 128" xmlns="http://www.w3.org/2000/svg">"#));
+}
+
+#[test]
+fn test_normalize_files() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let files = [CORRECT, MISSING, NON_SQUARE];
+    let originals: Vec<String> = files.iter().map(|file| fs::read_to_string(file).unwrap()).collect();
+
+    assert_eq!(viewbox::normalize_files(&files, output_dir.path(), 128.0).unwrap(), 2);
+    assert!(ViewBoxReport::check(fs::read_dir(output_dir.path()).unwrap()
+        .map(|entry| entry.unwrap().path()))
+        .is_uniform(128.0));
+
+    // The original files are untouched
+    for (file, original) in files.iter().zip(originals) {
+        assert_eq!(fs::read_to_string(file).unwrap(), original);
+    }
+
+    // Writing to the directory of the original files is refused
+    let original = fs::read_to_string(NON_SQUARE).unwrap();
+    assert!(viewbox::normalize_files(&[NON_SQUARE], &PathBuf::from("test_files/flags"), 128.0).is_err());
+    assert_eq!(fs::read_to_string(NON_SQUARE).unwrap(), original);
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright 2021 Constantin A. <emoji.builder@c1710.de>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Checks the `viewBox` of SVG files.
+//!
+//! Emoji SVGs are usually 128×128 units large, but contributions might use a different size or
+//! no `viewBox` at all. Even though they are scaled to the same size when rendering,
+//! this changes the width of the strokes. SVGs can also be normalized to a common size by
+//! wrapping their content in a scaling group.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+lazy_static! {
+    static ref SVG_TAG: Regex = Regex::new(r"<svg\b[^>]*>").unwrap();
+    static ref VIEW_BOX: Regex = Regex::new(r#"\sviewBox\s*=\s*["']([^"']*)["']"#).unwrap();
+    static ref WIDTH: Regex = Regex::new(r#"\swidth\s*=\s*["']([^"']*)["']"#).unwrap();
+    static ref HEIGHT: Regex = Regex::new(r#"\sheight\s*=\s*["']([^"']*)["']"#).unwrap();
+    static ref SVG_END: Regex = Regex::new(r"</svg\s*>").unwrap();
+}
+
+/// The `viewBox` of an SVG file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// An error that prevents an SVG file from being checked or normalized
+#[derive(Debug)]
+pub enum ViewBoxError {
+    /// Wrapper for [std::io::Error]
+    Io(std::io::Error),
+    /// There is no `<svg>` element (or it isn't closed)
+    NoSvgElement,
+    /// The `viewBox` attribute can't be parsed
+    InvalidViewBox(String),
+    /// There is neither a `viewBox` nor `width` and `height` in pixels
+    UnknownSize,
+}
+
+/// The result of checking the `viewBox`es of multiple SVG files
+#[derive(Debug, Default)]
+pub struct ViewBoxReport {
+    /// The files for each size of the `viewBox` (e.g. `128x128`)
+    pub sizes: BTreeMap<String, Vec<PathBuf>>,
+    /// Files without a `viewBox`
+    pub missing: Vec<PathBuf>,
+    /// Files with a `viewBox` that is not a square
+    pub non_square: Vec<PathBuf>,
+    /// Files that couldn't be checked
+    pub invalid: Vec<(PathBuf, ViewBoxError)>,
+}
+
+impl ViewBox {
+    /// A square `viewBox` at the origin
+    pub fn square(size: f64) -> ViewBox {
+        ViewBox {
+            min_x: 0.0,
+            min_y: 0.0,
+            width: size,
+            height: size,
+        }
+    }
+
+    /// Whether the `viewBox` is a square
+    pub fn is_square(&self) -> bool {
+        (self.width - self.height).abs() < f64::EPSILON
+    }
+
+    /// A short representation of the size, e.g. `128x128`
+    pub fn size(&self) -> String {
+        format!("{}x{}", self.width, self.height)
+    }
+
+    /// Reads the `viewBox` from the `<svg>` element of an SVG document.
+    /// # Returns
+    /// `Ok(None)` if there is a `<svg>` element, but it doesn't have a `viewBox`
+    /// # Examples
+    /// ```
+    /// use emoji_builder::viewbox::ViewBox;
+    ///
+    /// let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 36 36"></svg>"#;
+    /// assert_eq!(ViewBox::parse(svg).unwrap(), Some(ViewBox::square(36.0)));
+    ///
+    /// let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="128" height="128"></svg>"#;
+    /// assert_eq!(ViewBox::parse(svg).unwrap(), None);
+    /// ```
+    pub fn parse(svg: &str) -> Result<Option<ViewBox>, ViewBoxError> {
+        let tag = SVG_TAG.find(svg).ok_or(ViewBoxError::NoSvgElement)?.as_str();
+        match VIEW_BOX.captures(tag) {
+            Some(view_box) => {
+                let view_box = &view_box[1];
+                let values: Vec<f64> = view_box
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ViewBoxError::InvalidViewBox(view_box.to_string()))?;
+                if values.len() != 4 || values[2] <= 0.0 || values[3] <= 0.0 {
+                    return Err(ViewBoxError::InvalidViewBox(view_box.to_string()));
+                }
+                Ok(Some(ViewBox {
+                    min_x: values[0],
+                    min_y: values[1],
+                    width: values[2],
+                    height: values[3],
+                }))
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Reads the `viewBox` of an SVG document. If there is none, the `width` and `height` are
+    /// used instead (as long as they are absolute values), as this is what a renderer does.
+    pub fn parse_or_size(svg: &str) -> Result<ViewBox, ViewBoxError> {
+        if let Some(view_box) = Self::parse(svg)? {
+            return Ok(view_box);
+        }
+        let tag = SVG_TAG.find(svg).ok_or(ViewBoxError::NoSvgElement)?.as_str();
+        let length = |regex: &Regex| regex.captures(tag)
+            .and_then(|length| length[1].trim().trim_end_matches("px").parse::<f64>().ok())
+            .filter(|length| *length > 0.0);
+        match (length(&WIDTH), length(&HEIGHT)) {
+            (Some(width), Some(height)) => Ok(ViewBox {
+                min_x: 0.0,
+                min_y: 0.0,
+                width,
+                height,
+            }),
+            _ => Err(ViewBoxError::UnknownSize)
+        }
+    }
+}
+
+impl ViewBoxReport {
+    /// Checks the `viewBox` of each file
+    pub fn check<P: AsRef<Path>, I: IntoIterator<Item=P>>(files: I) -> ViewBoxReport {
+        let mut report = ViewBoxReport::default();
+        for file in files {
+            let path = file.as_ref().to_path_buf();
+            let view_box = fs::read_to_string(&path)
+                .map_err(ViewBoxError::Io)
+                .and_then(|svg| ViewBox::parse(&svg));
+            match view_box {
+                Ok(Some(view_box)) => {
+                    if !view_box.is_square() {
+                        report.non_square.push(path.clone());
+                    }
+                    report.sizes.entry(view_box.size()).or_default().push(path);
+                }
+                Ok(None) => report.missing.push(path),
+                Err(err) => report.invalid.push((path, err))
+            }
+        }
+        report
+    }
+
+    /// Whether all files have the given, square `viewBox` size
+    pub fn is_uniform(&self, size: f64) -> bool {
+        self.missing.is_empty()
+            && self.invalid.is_empty()
+            && self.sizes.keys().all(|other| *other == ViewBox::square(size).size())
+    }
+}
+
+impl Display for ViewBoxReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "viewBox sizes:")?;
+        for (size, files) in &self.sizes {
+            writeln!(f, "  {}: {} file(s)", size, files.len())?;
+        }
+        if !self.missing.is_empty() {
+            writeln!(f, "Without a viewBox:")?;
+            for file in &self.missing {
+                writeln!(f, "  {}", file.to_string_lossy())?;
+            }
+        }
+        if !self.non_square.is_empty() {
+            writeln!(f, "Not square:")?;
+            for file in &self.non_square {
+                writeln!(f, "  {}", file.to_string_lossy())?;
+            }
+        }
+        if !self.invalid.is_empty() {
+            writeln!(f, "Invalid:")?;
+            for (file, err) in &self.invalid {
+                writeln!(f, "  {}: {:?}", file.to_string_lossy(), err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changes the `viewBox` of an SVG document to `0 0 size size`.
+/// The content is wrapped in a group that scales it to fit into the new `viewBox` (keeping its
+/// aspect ratio and centering it). Documents that already have this `viewBox` are returned
+/// unchanged, while those that only have the right width and height get the `viewBox`, too.
+pub fn normalize(svg: &str, size: f64) -> Result<String, ViewBoxError> {
+    if ViewBox::parse(svg)? == Some(ViewBox::square(size)) {
+        return Ok(svg.to_string());
+    }
+    let view_box = ViewBox::parse_or_size(svg)?;
+
+    let tag = SVG_TAG.find(svg).ok_or(ViewBoxError::NoSvgElement)?;
+    let end = SVG_END.find_iter(svg).last().ok_or(ViewBoxError::NoSvgElement)?;
+    if end.start() < tag.end() {
+        return Err(ViewBoxError::NoSvgElement);
+    }
+
+    let scale = size / view_box.width.max(view_box.height);
+    let translate_x = (size - view_box.width * scale) / 2.0 - view_box.min_x * scale;
+    let translate_y = (size - view_box.height * scale) / 2.0 - view_box.min_y * scale;
+
+    let new_tag = WIDTH.replace(tag.as_str(), "");
+    let new_tag = HEIGHT.replace(&new_tag, "");
+    let new_tag = VIEW_BOX.replace(&new_tag, "");
+    let new_tag = new_tag.replacen(
+        "<svg",
+        &format!(r#"<svg width="{0}" height="{0}" viewBox="0 0 {0} {0}""#, size),
+        1,
+    );
+
+    Ok(format!(
+        r#"{}{}<g transform="translate({} {}) scale({})">{}</g>{}"#,
+        &svg[..tag.start()],
+        new_tag,
+        translate_x,
+        translate_y,
+        scale,
+        &svg[tag.end()..end.start()],
+        &svg[end.start()..]
+    ))
+}
+
+/// Writes normalized copies of the SVG files to `output_dir` (using the same file names).
+/// The original files are never modified, so `output_dir` must not contain any of them.
+/// # Returns
+/// The number of files that had to be changed
+pub fn normalize_files<P: AsRef<Path>>(files: &[P], output_dir: &Path, size: f64) -> Result<usize, ViewBoxError> {
+    fs::create_dir_all(output_dir).map_err(ViewBoxError::Io)?;
+    let output_dir = output_dir.canonicalize().map_err(ViewBoxError::Io)?;
+    let mut changed = 0;
+    for file in files {
+        let file = file.as_ref();
+        let parent = file.parent()
+            .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent })
+            .unwrap_or_else(|| Path::new("."));
+        if parent.canonicalize().map_err(ViewBoxError::Io)? == output_dir {
+            return Err(ViewBoxError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} would be overwritten", file.to_string_lossy()),
+            )));
+        }
+        let svg = fs::read_to_string(file).map_err(ViewBoxError::Io)?;
+        let normalized = normalize(&svg, size)?;
+        if normalized != svg {
+            changed += 1;
+        }
+        let file_name = file.file_name().ok_or_else(|| ViewBoxError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a file", file.to_string_lossy()),
+        )))?;
+        fs::write(output_dir.join(file_name), normalized).map_err(ViewBoxError::Io)?;
+    }
+    Ok(changed)
+}